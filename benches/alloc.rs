@@ -0,0 +1,83 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Allocation-heavy workloads, run with `cargo bench`.
+//! Each iteration makes a region, allocates a tuple of i32s in it, fills in every field, and frees the region,
+//! once with each `RegionStrategy`.
+
+use sabervm::header::{Op1, Outcome};
+use sabervm::{Config, Instance, Module, RegionArena, RegionStrategy};
+
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A program that counts down from `iterations`, allocating a `fields`-wide tuple each time around.
+fn alloc_loop(iterations: i32, fields: u8) -> Vec<u8> {
+    let mut bytes = vec![];
+    // no data section, three functions
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(3u32.to_le_bytes());
+    // main: ()->0, then loop and exit: (i32)->0
+    bytes.extend([0x09, 0x00, 0x0B]);
+    bytes.extend([0x02, 0x09, 0x01, 0x0B]);
+    bytes.extend([0x02, 0x09, 0x01, 0x0B]);
+    // main: lit iterations; global_func 1; call
+    Op1::Lit(iterations).write(&mut bytes);
+    Op1::GlobalFunc(1).write(&mut bytes);
+    bytes.push(0x11);
+    // loop: lit -1; add; new_rgn 4096
+    Op1::Lit(-1).write(&mut bytes);
+    bytes.push(0x1F);
+    Op1::NewRgn(4096).write(&mut bytes);
+    // share 0; i32 ... i32; tuple fields; ptr; malloc
+    bytes.extend([0x37, 0]);
+    bytes.extend((0..fields).map(|_| 0x02));
+    bytes.extend([0x03, fields, 0x1A, 0x0F]);
+    // get 2; init i, for each field
+    for i in 0..fields {
+        bytes.extend([0x0D, 2, 0x0E, i]);
+    }
+    // share 1; free_rgn; get 2; get 3; global_func 1; global_func 2; call_nz
+    bytes.extend([0x37, 1, 0x19, 0x0D, 2, 0x0D, 3]);
+    Op1::GlobalFunc(1).write(&mut bytes);
+    Op1::GlobalFunc(2).write(&mut bytes);
+    bytes.push(0x22);
+    // exit: u8_lit 0; halt
+    bytes.extend([0x27, 0, 0x15]);
+    bytes
+}
+
+fn bench(iterations: i32, fields: u8, strategy: RegionStrategy) {
+    let config = Config {
+        regions: RegionArena {
+            strategy,
+            ..RegionArena::default()
+        },
+        ..Config::default()
+    };
+    let module = Module::with_config(vec![alloc_loop(iterations, fields)], &config, &Default::default(), &[]).unwrap();
+    let mut instance = Instance::new(Arc::new(module));
+    let start = Instant::now();
+    let res = instance.run();
+    let elapsed = start.elapsed();
+    assert_eq!(res, Ok(Outcome::Halted(0)));
+    println!(
+        "{:?}: {} allocations of {} fields: {:?} ({:.1} ns each)",
+        strategy,
+        iterations,
+        fields,
+        elapsed,
+        elapsed.as_nanos() as f64 / iterations as f64
+    );
+}
+
+fn main() {
+    for strategy in [RegionStrategy::Malloc, RegionStrategy::Pooled, RegionStrategy::Adaptive] {
+        for fields in [1, 4, 16, 64] {
+            bench(1_000_000, fields, strategy);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Interpreter workloads, run with `cargo bench`: a tight arithmetic loop, a long chain of calls,
+//! and calls that keep moving the stack onto a new chunk and back.
+//! To compare the VM's dispatch strategies, run it again with `--features threaded-dispatch`.
+//! Both are written in assembly, so they read like the programs in `examples`.
+//! The chain of calls is run again after `opt::inline`, to see what inlining saves.
+
+use sabervm::header::Outcome;
+use sabervm::{asm, opt, vm, Instance, Module};
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+fn assemble(src: &str) -> Vec<u8> {
+    let lines = asm::parse(src).unwrap();
+    asm::assemble(&asm::expand(&lines, Path::new(".")).unwrap()).unwrap()
+}
+
+/// Count down from `iterations`, doing a few multiplies and a modulo on the way.
+/// There's no way to drop a value, so the result is multiplied by zero and added to the count.
+fn arith_loop(iterations: i32) -> String {
+    format!(
+        ".func
+    func 0
+    lced
+.body
+    lit {}
+    call @loop
+
+.func @loop
+    i32
+    func 1
+    lced
+.body
+    get 0
+    lit 3
+    mul
+    lit 7
+    modulo
+    lit 5
+    mul
+    lit 0
+    mul
+    add
+    lit -1
+    add
+    get 0
+    global_func @loop
+    global_func @done
+    call_nz
+
+.func @done
+    i32
+    func 1
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        iterations
+    )
+}
+
+/// Go around a ring of `funcs` functions `laps` times, each one passing the count on to the next.
+fn call_ring(funcs: u32, laps: i32) -> String {
+    let mut src = format!(
+        ".func
+    func 0
+    lced
+.body
+    lit {}
+    call @f0
+",
+        laps
+    );
+    for i in 0..funcs - 1 {
+        src += &format!(
+            "
+.func @f{}
+    i32
+    func 1
+    lced
+.body
+    call @f{}
+",
+            i,
+            i + 1
+        );
+    }
+    src += &format!(
+        "
+.func @f{}
+    i32
+    func 1
+    lced
+.body
+    lit -1
+    add
+    get 0
+    global_func @f0
+    global_func @done
+    call_nz
+
+.func @done
+    i32
+    func 1
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        funcs - 1
+    );
+    src
+}
+
+/// Count down from `laps` with the stack sitting just under the end of a chunk (see `STACK_CHUNK_SIZE` in vm.h),
+/// so every lap pushes onto a new chunk and pops back off it.
+fn chunk_boundary(laps: i32) -> String {
+    // 1022 i32s and the count leave 4 bytes at the top of a 4096-byte chunk
+    let filler = "    lit 0\n".repeat(1022);
+    format!(
+        ".func
+    func 0
+    lced
+.body
+{}    lit {}
+    call @loop
+
+.func @loop
+    i32
+    func 1
+    lced
+.body
+    lit -1
+    add
+    get 0
+    global_func @loop
+    global_func @done
+    call_nz
+
+.func @done
+    i32
+    func 1
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        filler, laps
+    )
+}
+
+fn bench(name: &str, program: Vec<u8>, ops: u64) {
+    let module = Module::new(vec![program]).unwrap();
+    let mut instance = Instance::new(Arc::new(module));
+    let start = Instant::now();
+    let res = instance.run();
+    let elapsed = start.elapsed();
+    assert_eq!(res, Ok(Outcome::Halted(0)));
+    println!(
+        "{}: {:?} ({:.1} ns per {})",
+        name,
+        elapsed,
+        elapsed.as_nanos() as f64 / ops as f64,
+        if name.starts_with("arith") { "iteration" } else { "call" }
+    );
+}
+
+fn main() {
+    println!("{} dispatch", vm::DISPATCH);
+    bench("arith loop, 10000000 iterations", assemble(&arith_loop(10_000_000)), 10_000_000);
+    for (funcs, laps) in [(10, 1_000_000), (1000, 10_000)] {
+        let name = format!("call ring of {} functions, {} laps", funcs, laps);
+        let program = assemble(&call_ring(funcs, laps));
+        let (inlined, _) = opt::inline(&program, 16).unwrap();
+        bench(&name, program, funcs as u64 * laps as u64);
+        bench(&format!("{}, inlined", name), inlined, funcs as u64 * laps as u64);
+    }
+    bench("calls across a stack chunk boundary, 1000000 laps", assemble(&chunk_boundary(1_000_000)), 1_000_000);
+}
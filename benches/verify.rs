@@ -0,0 +1,132 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Verifying large generated modules, run with `cargo bench`.
+//! Every function takes a deeply nested tuple and passes it on to the next one,
+//! so most of the time goes to building and comparing types.
+//! Then one function copies its deeply nested tuple over and over, which takes the same time however deep it is,
+//! since the verifier interns its types (see `intern`).
+//! Then one function grows the stack very deep, with the verifier's trace (as `--explain`, `analyze`,
+//! and `opt` use it) and without, since the trace keeps the state of the stacks after every op.
+
+use sabervm::header::Op1;
+use sabervm::{parse, verify, Module};
+
+use std::time::Instant;
+
+/// A program with `funcs` functions (after main), each taking a tuple nested `depth` deep.
+fn chain(funcs: u32, depth: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend((funcs + 1).to_le_bytes());
+    // main: ()->0
+    bytes.extend([0x09, 0x00, 0x0B]);
+    for _ in 0..funcs {
+        // i32, then (i32, previous) over and over
+        bytes.push(0x02);
+        for _ in 0..depth {
+            bytes.extend([0x02, 0x03, 0x02]);
+        }
+        // func 1
+        bytes.extend([0x09, 0x01, 0x0B]);
+    }
+    // main: u8_lit 0; halt
+    bytes.extend([0x27, 0, 0x15]);
+    for label in 1..funcs {
+        // get 0; global_func next; call
+        bytes.extend([0x0D, 0]);
+        Op1::GlobalFunc(label + 1).write(&mut bytes);
+        bytes.push(0x11);
+    }
+    // the last one just halts
+    bytes.extend([0x27, 0, 0x15]);
+    bytes
+}
+
+fn bench(funcs: u32, depth: usize) {
+    let bytes = chain(funcs, depth);
+    let start = Instant::now();
+    Module::new(vec![bytes]).unwrap();
+    println!("{} functions with types {} deep: {:?}", funcs, depth, start.elapsed());
+}
+
+/// A program whose function 1 takes a tuple nested `depth` deep and copies it `copies` times.
+fn copies(copies: usize, depth: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(2u32.to_le_bytes());
+    // main: ()->0
+    bytes.extend([0x09, 0x00, 0x0B]);
+    // i32, then (i32, previous) over and over
+    bytes.push(0x02);
+    for _ in 0..depth {
+        bytes.extend([0x02, 0x03, 0x02]);
+    }
+    // func 1
+    bytes.extend([0x09, 0x01, 0x0B]);
+    // main: u8_lit 0; halt
+    bytes.extend([0x27, 0, 0x15]);
+    for _ in 0..copies {
+        // get 0
+        bytes.extend([0x0D, 0]);
+    }
+    // u8_lit 0; halt
+    bytes.extend([0x27, 0, 0x15]);
+    bytes
+}
+
+fn bench_copies(n: usize, depth: usize) {
+    let bytes = copies(n, depth);
+    let start = Instant::now();
+    Module::new(vec![bytes]).unwrap();
+    println!("{} copies of a type {} deep: {:?}", n, depth, start.elapsed());
+}
+
+/// A program whose main function pushes `depth` values and then halts.
+fn deep(depth: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(1u32.to_le_bytes());
+    // main: ()->0
+    bytes.extend([0x09, 0x00, 0x0B]);
+    for i in 0..depth {
+        // lit i
+        Op1::Lit(i as i32).write(&mut bytes);
+    }
+    // u8_lit 0; halt
+    bytes.extend([0x27, 0, 0x15]);
+    bytes
+}
+
+fn bench_deep(depth: usize) {
+    let bytes = deep(depth);
+    let start = Instant::now();
+    Module::new(vec![bytes.clone()]).unwrap();
+    let verified = start.elapsed();
+    let (data_section, type_decs, forward_decs, stmts) = parse::go(&bytes).unwrap();
+    let start = Instant::now();
+    let (trace, res) = verify::explain(data_section, type_decs, forward_decs, stmts);
+    res.unwrap();
+    println!(
+        "a stack {} deep: {:?}, and {:?} with a trace of {} steps",
+        depth,
+        verified,
+        start.elapsed(),
+        trace.len()
+    );
+}
+
+fn main() {
+    for (funcs, depth) in [(1000, 1), (1000, 16), (1000, 64), (10000, 16)] {
+        bench(funcs, depth);
+    }
+    for depth in [16, 256] {
+        bench_copies(10000, depth);
+    }
+    for depth in [1000, 10000, 50000] {
+        bench_deep(depth);
+    }
+}
@@ -1,6 +1,14 @@
 fn main() {
-    cc::Build::new()
-        .file("src/vm.h")
-        .file("src/vm.c")
-        .compile("vm");
+    // cc emits rerun-if-env-changed, which turns off cargo's default of rerunning on any change.
+    println!("cargo:rerun-if-changed=src/vm.h");
+    println!("cargo:rerun-if-changed=src/vm.c");
+    let mut build = cc::Build::new();
+    build.file("src/vm.h").file("src/vm.c");
+    if std::env::var_os("CARGO_FEATURE_THREADED_DISPATCH").is_some() {
+        build.define("SVM_THREADED_DISPATCH", None);
+    }
+    if std::env::var_os("CARGO_FEATURE_PROFILE").is_some() {
+        build.define("SVM_PROFILE", None);
+    }
+    build.compile("vm");
 }
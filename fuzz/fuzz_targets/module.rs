@@ -0,0 +1,45 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Whatever bytes it's given, parsing, verifying, and linking a module should return an error, not panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sabervm::header::*;
+use sabervm::stream::Stream;
+use sabervm::vm::{Config, Module, Verification};
+use sabervm::{parse, verify};
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = parse::features(bytes);
+    let _ = parse::lint_config(bytes);
+    let _ = parse::metadata(bytes);
+    let limits = Limits::default();
+    if let Ok(Some(ranges)) = parse::body_ranges(bytes, &limits) {
+        for (i, range) in ranges.into_iter().enumerate() {
+            let _ = parse::body(bytes, range, i as u32, 4, &limits);
+        }
+    }
+    if let Ok((data_section, types, funcs, sizes)) = parse::go(bytes) {
+        let _ = parse::export_names(bytes, &funcs);
+        let _ = verify::explain(data_section, types, funcs, sizes);
+    }
+    // fed in two pieces, so a piece can end anywhere in the module
+    let mut stream = Stream::new(&Config::default());
+    let (a, b) = bytes.split_at(bytes.len() / 2);
+    if stream.feed(a).is_ok() && stream.feed(b).is_ok() {
+        let _ = stream.finish();
+    }
+    let lazy = Config {
+        verification: Verification::Lazy,
+        ..Config::default()
+    };
+    if let Ok(module) = Module::with_config(vec![bytes], &lazy, &Cancellation::default(), &[]) {
+        let _ = module.verify_all();
+    }
+    let _ = Module::new(vec![bytes.to_vec()]);
+});
@@ -0,0 +1,41 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Whatever module verifies should run without crashing the VM, however it ends.
+//! A run is interrupted after a moment, so a program that never ends doesn't stop the fuzzer.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sabervm::header::*;
+use sabervm::vm::{Instance, Module};
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fuzz_target!(|bytes: &[u8]| {
+    let Ok(module) = Module::new(vec![bytes.to_vec()]) else {
+        return;
+    };
+    let args = vec![0; module.entry_params()];
+    let mut instance = Instance::new(Arc::new(module));
+    let handle = instance.interrupt_handle();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        handle.interrupt();
+    });
+    let mut res = instance.run_with_args(&args);
+    // a program that yields forever gets interrupted while it's suspended, so it's only resumed so many times
+    for _ in 0..100 {
+        match res {
+            Ok(Outcome::Yielded(val)) => res = instance.resume(val),
+            _ => break,
+        }
+    }
+    let _ = instance.core_dump();
+    let _ = interrupter.join();
+});
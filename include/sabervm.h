@@ -0,0 +1,125 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+#ifndef SABERVM_H
+#define SABERVM_H
+
+/* Generated by cbindgen from src/capi.rs. Don't edit it by hand. */
+
+#include <stdint.h>
+#include <stddef.h>
+
+/**
+ * The run halted; the value is the status given to `halt`.
+ */
+#define SVM_HALTED 0
+
+/**
+ * The run stopped at a `yield`; the value is what was yielded. Continue it with `svm_instance_resume`.
+ */
+#define SVM_YIELDED 1
+
+/**
+ * The run trapped, or the call was invalid. `svm_last_error` says which.
+ */
+#define SVM_ERROR -1
+
+/**
+ * One run of a module.
+ */
+typedef struct SvmInstance SvmInstance;
+
+/**
+ * A verified and linked module. One module can back any number of instances.
+ */
+typedef struct SvmModule SvmModule;
+
+/**
+ * A host function: it's given the pointer it was registered with, and the i32 from `host_call`.
+ */
+typedef int32_t (*SvmHostFn)(void *data, int32_t arg);
+
+/**
+ * The message for the last failure on this thread, or null if nothing has failed.
+ * The string stays valid until the next failure on this thread.
+ */
+const char *svm_last_error(void);
+
+/**
+ * Parse and verify each program, returning 0 if they're all valid.
+ * Unlike `svm_module_new`, this doesn't link them, so imports don't have to be satisfied.
+ *
+ * # Safety
+ * `programs` and `lens` must both point to `count` elements, and each program must be `lens[i]` readable bytes.
+ */
+int32_t svm_module_verify(const uint8_t *const *programs, const size_t *lens, size_t count);
+
+/**
+ * Parse, verify, and link the programs into a module, or return null if any of them is invalid.
+ * The module has to be freed with `svm_module_free`.
+ *
+ * # Safety
+ * `programs` and `lens` must both point to `count` elements, and each program must be `lens[i]` readable bytes.
+ */
+SvmModule *svm_module_new(const uint8_t *const *programs, const size_t *lens, size_t count);
+
+/**
+ * # Safety
+ * `module` must be null or from `svm_module_new`, and not already freed.
+ * Instances made from it stay valid.
+ */
+void svm_module_free(SvmModule *module);
+
+/**
+ * A new instance of the module. It has to be freed with `svm_instance_free`.
+ *
+ * # Safety
+ * `module` must be from `svm_module_new`, and not freed.
+ */
+SvmInstance *svm_instance_new(const SvmModule *module);
+
+/**
+ * # Safety
+ * `instance` must be null or from `svm_instance_new`, and not already freed.
+ */
+void svm_instance_free(SvmInstance *instance);
+
+/**
+ * Provide the function that `host_call index` runs. `data` is passed to it on every call.
+ *
+ * # Safety
+ * `instance` must be from `svm_instance_new`, and not freed.
+ * `f` gets `data` on whichever thread runs the instance.
+ */
+void svm_instance_register_host_fn(SvmInstance *instance, uint32_t index, SvmHostFn f, void *data);
+
+/**
+ * Run the module from its entry point, returning `SVM_HALTED`, `SVM_YIELDED`, or `SVM_ERROR`.
+ * If it halts or yields, the status or yielded value is written to `value`, unless that's null.
+ *
+ * # Safety
+ * `instance` must be from `svm_instance_new`, and not freed. `value` must be null or writable.
+ */
+int32_t svm_instance_run(SvmInstance *instance, int32_t *value);
+
+/**
+ * Like `svm_instance_run`, but passing the `n` values at `args` to the entry function.
+ * This is an error if the entry function doesn't take exactly `n` `i32`s.
+ *
+ * # Safety
+ * `instance` must be from `svm_instance_new`, and not freed. `args` must point to `n` values, unless `n` is 0.
+ * `value` must be null or writable.
+ */
+int32_t svm_instance_run_with_args(SvmInstance *instance, const int32_t *args, size_t n, int32_t *value);
+
+/**
+ * Continue a run that stopped at a `yield`, with `val` as the result of the `yield`.
+ * This returns like `svm_instance_run`, and is an error if the instance isn't stopped at a `yield`.
+ *
+ * # Safety
+ * `instance` must be from `svm_instance_new`, and not freed. `value` must be null or writable.
+ */
+int32_t svm_instance_resume(SvmInstance *instance, int32_t val, int32_t *value);
+
+#endif /* SABERVM_H */
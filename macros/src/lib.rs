@@ -0,0 +1,125 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! `#[svm_host_fn]`, which binds a Rust function as a SaberVM host function.
+//!
+//! On `fn name_len(name: String, n: i32) -> i32`, it keeps the function and adds
+//! `const NAME_LEN: sabervm::host::HostBinding`, which reads the arguments with `sabervm::guest::FromStack`
+//! and gives back the result with `sabervm::guest::IntoSvm`. `Instance::register_binding` checks it against every
+//! `host_call` in the module before the program runs, so a program that pushes the wrong things is rejected up front.
+//!
+//! This only needs to see the function's signature, so it reads the tokens itself instead of depending on a parser.
+
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+
+#[proc_macro_attribute]
+pub fn svm_host_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let binding = match attr.is_empty() {
+        true => binding(item.clone()),
+        false => Err("#[svm_host_fn] doesn't take any arguments".to_string()),
+    };
+    let extra = match binding {
+        Ok(binding) => binding,
+        Err(msg) => format!("::core::compile_error!({:?});", msg),
+    };
+    let mut out = item;
+    out.extend(extra.parse::<TokenStream>().unwrap());
+    out
+}
+
+/// The `HostBinding` const for the function `item`, as source code.
+fn binding(item: TokenStream) -> Result<String, String> {
+    let mut tokens = item.into_iter().peekable();
+    let mut vis = String::new();
+    // attributes and visibility come before `fn`
+    loop {
+        match tokens.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '#' => {
+                tokens.next();
+            }
+            Some(TokenTree::Ident(i)) if i.to_string() == "fn" => break,
+            Some(TokenTree::Ident(i)) if i.to_string() == "pub" => {
+                vis = "pub".to_string();
+                if let Some(TokenTree::Group(g)) = tokens.peek() {
+                    if g.delimiter() == Delimiter::Parenthesis {
+                        vis += &g.to_string();
+                        tokens.next();
+                    }
+                }
+            }
+            Some(TokenTree::Ident(i)) => return Err(format!("host functions can't be `{}`", i)),
+            _ => return Err("#[svm_host_fn] only goes on functions".to_string()),
+        }
+    }
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(i)) => i.to_string(),
+        _ => return Err("expected the function's name".to_string()),
+    };
+    let params = match tokens.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => g.stream(),
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => return Err("host functions can't be generic".to_string()),
+        _ => return Err("expected the function's parameters".to_string()),
+    };
+    let types = param_types(params)?;
+    let tuple = "(".to_string() + &types.iter().map(|t| t.clone() + ",").collect::<String>() + ")";
+    let args = (0..types.len()).map(|i| format!("a{},", i)).collect::<String>();
+    let from_stack = format!("<{} as ::sabervm::guest::FromStack>", tuple);
+    Ok(format!(
+        "#[allow(dead_code)]
+        {vis} const {upper}: ::sabervm::host::HostBinding = ::sabervm::host::HostBinding {{
+            name: {name:?},
+            accepts: {from_stack}::accepts,
+            signature: {from_stack}::signature,
+            call: |view| {{
+                let ({args}) = {from_stack}::from_stack(view)?;
+                ::core::option::Option::Some(::sabervm::guest::IntoSvm::into_svm({name}({args})))
+            }},
+        }};",
+        upper = name.to_uppercase(),
+    ))
+}
+
+/// The type of each parameter in `params`, as source code.
+fn param_types(params: TokenStream) -> Result<Vec<String>, String> {
+    let mut types = vec![];
+    let mut current: Option<Vec<TokenTree>> = None;
+    // commas inside `<>` don't end a type, and the `>` of a `->` doesn't close one
+    let mut depth = 0;
+    let mut after_minus = false;
+    for token in params {
+        match (&token, &mut current) {
+            (TokenTree::Punct(p), None) if p.as_char() == ':' => current = Some(vec![]),
+            (TokenTree::Ident(i), None) if i.to_string() == "self" => {
+                return Err("host functions can't take `self`".to_string())
+            }
+            (_, None) => {}
+            (TokenTree::Punct(p), Some(t)) if p.as_char() == ',' && depth == 0 => {
+                types.push(TokenStream::from_iter(t.drain(..)).to_string());
+                current = None;
+            }
+            (TokenTree::Punct(p), Some(t)) => {
+                match p.as_char() {
+                    '<' => depth += 1,
+                    '>' if !after_minus => depth -= 1,
+                    _ => {}
+                }
+                after_minus = p.as_char() == '-' && p.spacing() == Spacing::Joint;
+                t.push(token);
+            }
+            (_, Some(t)) => {
+                after_minus = false;
+                t.push(token);
+            }
+        }
+    }
+    if let Some(t) = current {
+        types.push(TokenStream::from_iter(t).to_string());
+    }
+    if types.len() > 4 {
+        return Err("host functions can take at most 4 arguments".to_string());
+    }
+    Ok(types)
+}
@@ -0,0 +1,277 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Analyses built on the verifier's trace (see `verify::explain`), for people writing compilers that target SaberVM.
+
+use crate::header::*;
+use crate::pretty::Pretty;
+use crate::stack::Stack;
+
+use std::fmt::Write;
+
+/// Where one region is made, used, and freed, within one function body.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionTimeline {
+    pub function: Label,
+    pub region: Region,
+    /// Where the function makes the region, or `None` if it's passed in.
+    pub created: Option<Pos>,
+    /// The ops that took a value in the region (a handle, pointer, array, big integer, or buffer) off the stack.
+    pub uses: Vec<(Pos, Op1)>,
+    /// Where the function frees the region, or `None` if it's still live at the end.
+    pub freed: Option<Pos>,
+}
+
+/// Every region variable mentioned in `t`.
+fn regions_in(t: &Type, out: &mut Vec<RgnId>) {
+    match t {
+        Type::I32 | Type::U8 | Type::Var(_, _) | Type::Named(_, _) => {}
+        Type::Handle(r) | Type::BigInt(r) | Type::Buffer(r) | Type::Resource(_, r) => out.push(r.id),
+        Type::Ptr(t, r) | Type::Array(t, r) | Type::Map(t, r) => {
+            out.push(r.id);
+            regions_in(t, out);
+        }
+        Type::Tuple(ts) => ts.iter().for_each(|field| regions_in(&field.t, out)),
+        Type::Func(ts) => ts.iter().for_each(|t| regions_in(t, out)),
+        Type::Forall(_, _, t) | Type::Exists(_, _, t) | Type::ForallRegion(_, t, _) | Type::ExistsRegion(_, t) => {
+            regions_in(t, out)
+        }
+    }
+}
+
+/// The timeline of every region in every function body of the trace, in the order they appear.
+/// The data section is left out, since it's always there.
+pub fn region_timelines(trace: &[Explained]) -> Vec<RegionTimeline> {
+    let mut timelines: Vec<RegionTimeline> = vec![];
+    let mut current = None;
+    for step in trace.iter().filter(|step| !step.forward_dec) {
+        if current != Some(step.label) {
+            current = Some(step.label);
+            for r in &step.before.rgn_vars {
+                if r.id != RgnId::DataSection {
+                    timelines.push(RegionTimeline {
+                        function: step.label,
+                        region: *r,
+                        created: None,
+                        uses: vec![],
+                        freed: None,
+                    });
+                }
+            }
+        }
+        // the values the op took off the stack are the ones past where the stacks before and after stop agreeing
+        let before = &step.before.stack_type;
+        let after = &step.after.stack_type;
+        let (_, popped, _) = changed(before, after);
+        let mut used = vec![];
+        for t in popped {
+            regions_in(t, &mut used);
+        }
+        // freeing is shown on its own
+        if step.op == Op1::FreeRgn {
+            used.clear();
+        }
+        for id in used {
+            if let Some(i) = find(&timelines, step.label, id) {
+                if timelines[i].uses.last().map(|(pos, _)| *pos) != Some(step.pos) {
+                    timelines[i].uses.push((step.pos, step.op));
+                }
+            }
+        }
+        let live = |rgn_vars: &[Region], r: &Region| rgn_vars.iter().any(|r2| r2.id == r.id);
+        for r in &step.after.rgn_vars {
+            if !live(&step.before.rgn_vars, r) && r.id != RgnId::DataSection {
+                timelines.push(RegionTimeline {
+                    function: step.label,
+                    region: *r,
+                    created: Some(step.pos),
+                    uses: vec![],
+                    freed: None,
+                });
+            }
+        }
+        for r in &step.before.rgn_vars {
+            if !live(&step.after.rgn_vars, r) {
+                if let Some(i) = find(&timelines, step.label, r.id) {
+                    timelines[i].freed = Some(step.pos);
+                }
+            }
+        }
+    }
+    timelines
+}
+
+/// The latest timeline of region `id` in function `label`, which is always at the end of the list.
+fn find(timelines: &[RegionTimeline], label: Label, id: RgnId) -> Option<usize> {
+    let start = timelines.iter().rposition(|tl| tl.function != label).map_or(0, |i| i + 1);
+    timelines[start..].iter().rposition(|tl| tl.region.id == id).map(|i| start + i)
+}
+
+/// The timelines as text, one region at a time.
+pub fn timelines_text(timelines: &[RegionTimeline]) -> String {
+    let mut out = String::new();
+    let mut current = None;
+    for tl in timelines {
+        if current != Some(tl.function) {
+            current = Some(tl.function);
+            let _ = writeln!(out, "function {}:", tl.function);
+        }
+        let unique = if tl.region.unique { " (unique)" } else { "" };
+        let _ = writeln!(out, "  region {}{}", tl.region.pretty(), unique);
+        match tl.created {
+            Some(pos) => {
+                let _ = writeln!(out, "    {:>6}  created", pos);
+            }
+            None => out += "     start  passed in\n",
+        }
+        for (pos, op) in &tl.uses {
+            let _ = writeln!(out, "    {:>6}  used by {}", pos, op.pretty());
+        }
+        match tl.freed {
+            Some(pos) => {
+                let _ = writeln!(out, "    {:>6}  freed", pos);
+            }
+            None => out += "       end  still live\n",
+        }
+    }
+    out
+}
+
+/// The timelines as a Graphviz graph, with a cluster for each function and a chain of events for each region.
+pub fn timelines_dot(timelines: &[RegionTimeline]) -> String {
+    let mut out = String::from("digraph regions {\n    node [shape=box, fontname=monospace];\n");
+    let mut current = None;
+    for (i, tl) in timelines.iter().enumerate() {
+        if current != Some(tl.function) {
+            if current.is_some() {
+                out += "    }\n";
+            }
+            current = Some(tl.function);
+            let _ = writeln!(out, "    subgraph cluster_f{} {{\n        label=\"function {}\";", tl.function, tl.function);
+        }
+        let mut events = vec![match tl.created {
+            Some(pos) => format!("{}: new {}", pos, tl.region.pretty()),
+            None => format!("{} passed in", tl.region.pretty()),
+        }];
+        events.extend(tl.uses.iter().map(|(pos, op)| format!("{}: {}", pos, op.pretty())));
+        events.push(match tl.freed {
+            Some(pos) => format!("{}: free {}", pos, tl.region.pretty()),
+            None => format!("{} still live", tl.region.pretty()),
+        });
+        for (j, event) in events.iter().enumerate() {
+            let _ = writeln!(out, "        r{}_{} [label=\"{}\"];", i, j, event);
+            if j > 0 {
+                let _ = writeln!(out, "        r{}_{} -> r{}_{};", i, j - 1, i, j);
+            }
+        }
+    }
+    if current.is_some() {
+        out += "    }\n";
+    }
+    out += "}\n";
+    out
+}
+
+/// An allocation in a region the function was given, which never leaves the function.
+/// It could be allocated in a region of its own instead, made and freed in the function,
+/// so it doesn't live as long as whatever the function was given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalAllocation {
+    pub function: Label,
+    /// Where the `malloc` is.
+    pub pos: Pos,
+    pub region: Region,
+    /// What was allocated.
+    pub typ: Type,
+}
+
+/// How many arguments a function of type `t` takes, looking through its quantifiers.
+/// How many values at the bottom of the stack an op left alone, then the ones above those before and after it.
+fn changed<'a>(before: &'a Stack<Type>, after: &'a Stack<Type>) -> (usize, Vec<&'a Type>, Vec<&'a Type>) {
+    let (shared, popped, pushed) = before.diverge(after);
+    // popping a value and pushing one just like it counts as leaving it alone
+    let same = popped.iter().zip(&pushed).take_while(|(t1, t2)| t1 == t2).count();
+    (shared + same, popped[same..].to_vec(), pushed[same..].to_vec())
+}
+
+fn arity(t: &Type) -> usize {
+    match t {
+        Type::Func(ts) => ts.len(),
+        Type::Forall(_, _, t) | Type::ForallRegion(_, t, _) | Type::Exists(_, _, t) => arity(t),
+        _ => 0,
+    }
+}
+
+/// Find the allocations that could move to shorter-lived regions, by following each `malloc`'s pointer through the function.
+/// A pointer escapes if it, or anything it was stored in, is passed to a call.
+/// This is conservative: any value made from an allocation (by `init`, `pack`, and so on) is treated as holding it.
+pub fn local_allocations(trace: &[Explained]) -> Vec<LocalAllocation> {
+    let mut out = vec![];
+    // for each value on the runtime stack, the mallocs (by position) it might hold
+    let mut held: Vec<Vec<Pos>> = vec![];
+    let mut allocs: Vec<(LocalAllocation, bool)> = vec![];
+    let mut passed_in: Vec<RgnId> = vec![];
+    let mut current = None;
+    let finish = |allocs: &mut Vec<(LocalAllocation, bool)>, out: &mut Vec<LocalAllocation>| {
+        out.extend(allocs.drain(..).filter(|(_, escaped)| !escaped).map(|(a, _)| a));
+    };
+    for step in trace.iter().filter(|step| !step.forward_dec) {
+        if current != Some(step.label) {
+            finish(&mut allocs, &mut out);
+            current = Some(step.label);
+            held = vec![vec![]; step.before.stack_type.len()];
+            passed_in = step.before.rgn_vars.iter().map(|r| r.id).filter(|id| *id != RgnId::DataSection).collect();
+        }
+        let before = &step.before.stack_type;
+        let after = &step.after.stack_type;
+        let mut escape = |held: &[Vec<Pos>]| {
+            for pos in held.iter().flatten() {
+                if let Some((_, escaped)) = allocs.iter_mut().find(|(a, _)| a.pos == *pos) {
+                    *escaped = true;
+                }
+            }
+        };
+        match step.op {
+            Op1::Call | Op1::CallNZ => {
+                let funcs = if step.op == Op1::Call { 1 } else { 2 };
+                let args = before.last().map_or(0, arity);
+                let end = held.len().saturating_sub(funcs + usize::from(step.op == Op1::CallNZ));
+                escape(&held[end.saturating_sub(args)..end]);
+                continue;
+            }
+            Op1::Get(n) | Op1::Share(n) => {
+                let copied = held.len().checked_sub(n as usize + 1).map_or(vec![], |i| held[i].clone());
+                held.push(copied);
+                continue;
+            }
+            _ => {}
+        }
+        let (kept, _, pushed) = changed(before, after);
+        let popped = held.split_off(kept.min(held.len()));
+        let mut from = popped.into_iter().flatten().collect::<Vec<_>>();
+        from.sort();
+        from.dedup();
+        for t in pushed {
+            match (step.op, t) {
+                (Op1::Malloc, Type::Ptr(typ, r)) => {
+                    if passed_in.contains(&r.id) {
+                        let alloc = LocalAllocation {
+                            function: step.label,
+                            pos: step.pos,
+                            region: *r,
+                            typ: (**typ).clone(),
+                        };
+                        allocs.push((alloc, false));
+                    }
+                    held.push(vec![step.pos]);
+                }
+                _ => held.push(from.clone()),
+            }
+        }
+    }
+    finish(&mut allocs, &mut out);
+    out
+}
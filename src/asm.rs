@@ -0,0 +1,870 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The text assembly format, `.svmasm`, for writing SaberVM programs by hand.
+//!
+//! Each line is a directive, an op, or nothing, and anything after a `;` is a comment:
+//!
+//! ```text
+//! .data "hello\n"   ; bytes appended to the data section
+//!
+//! .func             ; a function; the ops up to `.body` are its forward declaration
+//!     func 0
+//!     lced
+//! .body             ; the ops after this, up to the next `.func`, are its body
+//!     u8_lit 7
+//!     halt
+//! ```
+//!
+//! Ops are written with the mnemonics from `sabervm opcodes`.
+//! Number immediates can be decimal, `0x` hex, or `0b` binary, and import/export names are strings of at most 16 bytes.
+//! Imported functions have no `.body`. `.features n` at the top writes the feature header with bits `n`.
+//! `.lint level name`, like `.lint allow unreachable-function`, adds a line to the program's lint config (see `lint`).
+//! `.meta key "value"`, like `.meta producer "mycc"`, says what made the program, with a key of `name`, `producer`, or `version`
+//! (see `Metadata`), so `sabervm info` can tell where a module came from.
+//! `.bigint n`, like `.bigint -123456789012345678901234567890`, appends a big integer literal to the data section,
+//! for `big_data` (see `bigint`).
+//! `.checksum` adds a checksum of the program to the header, so a damaged copy is reported as damaged (see `checksum`).
+//! `.sized_bodies` writes the size of each function body into the header, so a body can be read without the ones before it
+//! (see `Feature::SizedBodies`).
+//!
+//! `.type` starts a type declaration (see `Feature::TypeDecls`), which runs up to the next `.type` or `.func`,
+//! and looks like a forward declaration that starts with the type's size, as in `size 4`, `i32`, `lced`.
+//! Types are numbered in the order of their `.type`s, for `named`, `fold`, and `unfold`.
+//!
+//! Functions are numbered in the order of their `.func`s, but they can be named instead, with `.func @name`.
+//! Then `global_func @name` pushes that function, and `call @name` is short for `global_func @name` then `call`.
+//! A name can be used before the function it names.
+//! `.export_name "fib"` anywhere in a function gives it a name the host can call it by, with `Instance::call`
+//! (see `Feature::ExportNames`). That name is for the host, so it's separate from `@name`, which is only for the assembler.
+//!
+//! `.include "file.svmasm"` pastes in another file, found relative to the one including it.
+//! Macros are defined between `.macro name param...` and `.endm`, with each parameter written `%param` in the body,
+//! and used like ops: `name arg...`. A macro can be used anywhere after it's defined, including in files that include it.
+//!
+//! A comment `;; expect: n` says the program should halt with status `n`,
+//! and `;; expect-error: Name` says it should fail to parse or verify, or trap, with the `Error` or `Trap` called `Name`.
+//! A `;; verify: lazy` comment runs it with `Verification::Lazy`,
+//! and `;; call: name arg...` runs the function exported as `name` instead of the entry function (see `Instance::call`).
+//! `sabervm test` checks these.
+
+use crate::bigint;
+use crate::checksum;
+use crate::header::*;
+use crate::lint;
+use crate::parse;
+use crate::vm::Verification;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One line of an assembly file, kept whole (with its comment) so the formatter can write it back out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// The 1-based line number in the source, for errors.
+    pub line: usize,
+    pub item: Option<Item>,
+    /// Everything after the `;`, if there is one.
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Features(u32),
+    /// A line of lint config, `level lint`.
+    Lint(String),
+    /// A line of metadata, `key value`.
+    Meta(String, String),
+    Checksum,
+    SizedBodies,
+    Data(Vec<u8>),
+    /// A big integer literal appended to the data section, in decimal.
+    BigInt(String),
+    /// The start of a type declaration.
+    Type,
+    /// The start of a function, with its name if it has one.
+    Func(Option<String>),
+    /// The name the host can call the current function by.
+    ExportName(String),
+    Body,
+    Op(Op1),
+    /// `global_func @name`.
+    GlobalFuncLabel(String),
+    /// `call @name`.
+    CallLabel(String),
+    Include(String),
+    /// `.macro name params...`.
+    MacroStart(String, Vec<String>),
+    /// A line of a macro's body, which is only read once the macro is used.
+    MacroLine(String),
+    MacroEnd,
+    /// A use of a macro, with its arguments as they were written.
+    MacroCall(String, Vec<String>),
+}
+
+enum Token {
+    Word(String),
+    Str(Vec<u8>),
+}
+
+/// Split a line into tokens and its comment, and say where the comment (if any) starts.
+fn tokenize(src: &str, line: usize) -> Result<(Vec<Token>, Option<String>, usize), Error> {
+    let mut tokens = vec![];
+    let mut chars = src.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ';' {
+            return Ok((tokens, Some(src[i + 1..].trim_end().to_string()), i));
+        } else if c == '"' {
+            chars.next();
+            let mut bytes = vec![];
+            loop {
+                match chars.next() {
+                    None => return Err(Error::AsmBadString(line)),
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => bytes.push(b'\n'),
+                        Some((_, 't')) => bytes.push(b'\t'),
+                        Some((_, 'r')) => bytes.push(b'\r'),
+                        Some((_, '0')) => bytes.push(0),
+                        Some((_, '\\')) => bytes.push(b'\\'),
+                        Some((_, '"')) => bytes.push(b'"'),
+                        Some((j, 'x')) => {
+                            let hex = src.get(j + 1..j + 3).ok_or(Error::AsmBadString(line))?;
+                            let b = u8::from_str_radix(hex, 16).map_err(|_| Error::AsmBadString(line))?;
+                            bytes.push(b);
+                            chars.next();
+                            chars.next();
+                        }
+                        _ => return Err(Error::AsmBadString(line)),
+                    },
+                    Some((_, c)) => {
+                        let mut buf = [0; 4];
+                        bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+                    }
+                }
+            }
+            tokens.push(Token::Str(bytes));
+        } else {
+            let mut word = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() || c == ';' || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok((tokens, None, src.len()))
+}
+
+/// Read a number written in decimal, `0x` hex, or `0b` binary, with optional `_` separators.
+fn parse_int(word: &str) -> Option<i64> {
+    let (neg, digits) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+    let digits = digits.replace('_', "");
+    let n = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse::<i64>().ok()?
+    };
+    Some(if neg { -n } else { n })
+}
+
+/// A function name, without its `@`.
+fn label(word: &str) -> Option<String> {
+    let name = word.strip_prefix('@')?;
+    let ok = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    ok.then(|| name.to_string())
+}
+
+fn parse_op(tokens: &[Token], line: usize) -> Result<Item, Error> {
+    let Some(Token::Word(mnemonic)) = tokens.first() else {
+        return Err(Error::AsmBadImmediate(line, String::new()));
+    };
+    match (mnemonic.as_str(), &tokens[1..]) {
+        ("global_func", [Token::Word(word)]) if word.starts_with('@') => {
+            let name = label(word).ok_or_else(|| Error::AsmBadImmediate(line, mnemonic.clone()))?;
+            return Ok(Item::GlobalFuncLabel(name));
+        }
+        ("call", [Token::Word(word)]) if word.starts_with('@') => {
+            let name = label(word).ok_or_else(|| Error::AsmBadImmediate(line, mnemonic.clone()))?;
+            return Ok(Item::CallLabel(name));
+        }
+        _ => {}
+    }
+    let Some(info) = op_by_mnemonic(mnemonic) else {
+        // it might be a macro from a file that hasn't been included yet, so this is checked once macros are expanded
+        let args = tokens[1..]
+            .iter()
+            .map(|token| match token {
+                Token::Word(word) => word.clone(),
+                Token::Str(bytes) => string_lit(bytes),
+            })
+            .collect();
+        return Ok(Item::MacroCall(mnemonic.clone(), args));
+    };
+    let args = &tokens[1..];
+    let expected = match info.imm {
+        ImmKind::None => 0,
+        _ => 1,
+    };
+    if args.len() != expected {
+        return Err(Error::AsmWrongImmediateCount(line, info.mnemonic.to_string(), expected, args.len()));
+    }
+    let bad = || Error::AsmBadImmediate(line, info.mnemonic.to_string());
+    let int = |lo: i64, hi: i64| match &args[0] {
+        Token::Word(word) => parse_int(word).filter(|n| lo <= *n && *n <= hi).ok_or_else(bad),
+        Token::Str(_) => Err(bad()),
+    };
+    let imm = match info.imm {
+        ImmKind::None => Imm::None,
+        ImmKind::U8 => Imm::U8(int(0, u8::MAX.into())? as u8),
+        ImmKind::U32 => Imm::U32(int(0, u32::MAX.into())? as u32),
+        ImmKind::I32 => Imm::I32(int(i32::MIN.into(), i32::MAX.into())? as i32),
+        ImmKind::Name => match &args[0] {
+            Token::Str(name) if name.len() <= 16 => {
+                let mut bytes = [0; 16];
+                bytes[..name.len()].copy_from_slice(name);
+                let a = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+                let b = u64::from_le_bytes(bytes[8..].try_into().unwrap());
+                Imm::Name(a, b)
+            }
+            _ => return Err(bad()),
+        },
+    };
+    Ok(Item::Op(Op1::from_parts(info.byte, imm)))
+}
+
+/// Read an assembly file into its lines.
+pub fn parse(src: &str) -> Result<Vec<Line>, Error> {
+    let mut lines = vec![];
+    let mut in_macro = false;
+    for (i, text) in src.lines().enumerate() {
+        let line = parse_line(text, i + 1, in_macro)?;
+        match line.item {
+            Some(Item::MacroStart(_, _)) => in_macro = true,
+            Some(Item::MacroEnd) => in_macro = false,
+            _ => {}
+        }
+        lines.push(line);
+    }
+    if in_macro {
+        return Err(Error::AsmBadMacro(src.lines().count()));
+    }
+    Ok(lines)
+}
+
+fn parse_line(text: &str, line: usize, in_macro: bool) -> Result<Line, Error> {
+    let (tokens, comment, code_end) = tokenize(text, line)?;
+    let item = match tokens.first() {
+        None => None,
+        Some(Token::Word(word)) if word == ".endm" && tokens.len() == 1 => Some(Item::MacroEnd),
+        Some(_) if in_macro => Some(Item::MacroLine(text[..code_end].trim().to_string())),
+        Some(Token::Word(word)) if word.starts_with('.') => Some(match (word.as_str(), &tokens[1..]) {
+            (".func", []) => Item::Func(None),
+            (".func", [Token::Word(word)]) => match label(word) {
+                Some(name) => Item::Func(Some(name)),
+                None => return Err(Error::AsmUnknownDirective(line, word.clone())),
+            },
+            (".body", []) => Item::Body,
+            (".type", []) => Item::Type,
+            (".data", args) => {
+                let mut bytes = vec![];
+                for arg in args {
+                    match arg {
+                        Token::Str(s) => bytes.extend(s),
+                        Token::Word(word) => match parse_int(word) {
+                            Some(n) if (0..=255).contains(&n) => bytes.push(n as u8),
+                            _ => return Err(Error::AsmBadImmediate(line, ".data".to_string())),
+                        },
+                    }
+                }
+                Item::Data(bytes)
+            }
+            (".bigint", [Token::Word(word)]) => match bigint::from_decimal(word) {
+                Some(bytes) => Item::BigInt(bigint::to_decimal(&bytes).unwrap()),
+                None => return Err(Error::AsmBadImmediate(line, ".bigint".to_string())),
+            },
+            (".features", [Token::Word(word)]) => match parse_int(word) {
+                Some(n) if (0..=u32::MAX.into()).contains(&n) => Item::Features(n as u32),
+                _ => return Err(Error::AsmBadImmediate(line, ".features".to_string())),
+            },
+            (".lint", [Token::Word(level), Token::Word(name)]) => {
+                let config = format!("{} {}", level, name);
+                if lint::LintLevels::default().apply_config(&config).is_err() {
+                    return Err(Error::AsmBadImmediate(line, ".lint".to_string()));
+                }
+                Item::Lint(config)
+            }
+            (".meta", [Token::Word(key), Token::Str(value)]) => match String::from_utf8(value.clone()) {
+                Ok(value) if Metadata::default().set(key, &value).is_ok() => Item::Meta(key.clone(), value),
+                _ => return Err(Error::AsmBadImmediate(line, ".meta".to_string())),
+            },
+            (".export_name", [Token::Str(name)]) => match String::from_utf8(name.clone()) {
+                Ok(name) => Item::ExportName(name),
+                Err(_) => return Err(Error::AsmBadString(line)),
+            },
+            (".checksum", []) => Item::Checksum,
+            (".sized_bodies", []) => Item::SizedBodies,
+            (".include", [Token::Str(path)]) => match String::from_utf8(path.clone()) {
+                Ok(path) => Item::Include(path),
+                Err(_) => return Err(Error::AsmBadString(line)),
+            },
+            (".macro", [Token::Word(name), params @ ..]) => {
+                let mut names = vec![];
+                for param in params {
+                    match param {
+                        Token::Word(param) if !param.is_empty() && param.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                            names.push(param.clone())
+                        }
+                        _ => return Err(Error::AsmBadMacro(line)),
+                    }
+                }
+                if op_by_mnemonic(name).is_some() {
+                    return Err(Error::AsmBadMacro(line));
+                }
+                Item::MacroStart(name.clone(), names)
+            }
+            _ => return Err(Error::AsmUnknownDirective(line, word.clone())),
+        }),
+        Some(_) => Some(parse_op(&tokens, line)?),
+    };
+    Ok(Line { line, item, comment })
+}
+
+/// What a test program says should happen when it runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    Halt(u8),
+    /// The name of an `Error` or `Trap` variant, like `TypeError` or `OutOfBounds`.
+    Error(String),
+}
+
+/// The `;; expect` comments in a file, in order.
+pub fn expectations(lines: &[Line]) -> Result<Vec<Expectation>, Error> {
+    let mut out = vec![];
+    for line in lines {
+        let Some(comment) = line.comment.as_deref().and_then(|c| c.strip_prefix(';')) else {
+            continue;
+        };
+        if let Some(status) = comment.trim().strip_prefix("expect:") {
+            match parse_int(status.trim()) {
+                Some(n) if (0..=255).contains(&n) => out.push(Expectation::Halt(n as u8)),
+                _ => return Err(Error::AsmBadImmediate(line.line, "expect".to_string())),
+            }
+        } else if let Some(name) = comment.trim().strip_prefix("expect-error:") {
+            out.push(Expectation::Error(name.trim().to_string()));
+        }
+    }
+    Ok(out)
+}
+
+/// How a test program says to verify it: lazily with a `;; verify: lazy` comment (see `Verification::Lazy`), or else eagerly.
+pub fn verification(lines: &[Line]) -> Verification {
+    let lazy = lines.iter().any(|line| {
+        let comment = line.comment.as_deref().and_then(|c| c.strip_prefix(';'));
+        comment.and_then(|c| c.trim().strip_prefix("verify:")).is_some_and(|how| how.trim() == "lazy")
+    });
+    if lazy {
+        Verification::Lazy
+    } else {
+        Verification::Eager
+    }
+}
+
+/// The export a test program says to start at instead of the entry function, and its arguments,
+/// from a `;; call: name arg...` comment (see `Instance::call`).
+pub fn call(lines: &[Line]) -> Option<(String, Vec<i32>)> {
+    lines.iter().find_map(|line| {
+        let comment = line.comment.as_deref()?.strip_prefix(';')?;
+        let mut words = comment.trim().strip_prefix("call:")?.split_whitespace();
+        let name = words.next()?.to_string();
+        let args = words.map(|word| parse_int(word).and_then(|n| i32::try_from(n).ok())).collect::<Option<_>>()?;
+        Some((name, args))
+    })
+}
+
+/// How deeply macros can nest, so a macro that uses itself is an error instead of a hang.
+const MAX_MACRO_DEPTH: usize = 64;
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// Make every `add`, `sub`, `mul`, `div`, and `modulo` its `_trap` version, so the program traps on overflow instead of wrapping,
+/// as `sabervm asm --trap-overflow` does. This goes after `expand`, so it reaches ops from includes and macros too.
+pub fn trap_overflow(lines: &mut [Line]) {
+    for line in lines {
+        line.item = match line.item.take() {
+            Some(Item::Op(Op1::Add)) => Some(Item::Op(Op1::AddTrap)),
+            Some(Item::Op(Op1::Sub)) => Some(Item::Op(Op1::SubTrap)),
+            Some(Item::Op(Op1::Mul)) => Some(Item::Op(Op1::MulTrap)),
+            Some(Item::Op(Op1::Div)) => Some(Item::Op(Op1::DivTrap)),
+            Some(Item::Op(Op1::Modulo)) => Some(Item::Op(Op1::ModuloTrap)),
+            item => item,
+        };
+    }
+}
+
+/// Paste in every `.include` and every macro use, leaving lines `assemble` can read.
+/// `dir` is where the file the lines came from is, for finding includes.
+pub fn expand(lines: &[Line], dir: &Path) -> Result<Vec<Line>, Error> {
+    let mut out = vec![];
+    expand_into(lines, dir, &mut HashMap::new(), &mut vec![], 0, &mut out)?;
+    Ok(out)
+}
+
+fn expand_into(
+    lines: &[Line],
+    dir: &Path,
+    macros: &mut HashMap<String, Macro>,
+    including: &mut Vec<PathBuf>,
+    depth: usize,
+    out: &mut Vec<Line>,
+) -> Result<(), Error> {
+    let mut defining: Option<(String, Macro)> = None;
+    for line in lines {
+        match &line.item {
+            Some(Item::MacroStart(name, params)) => {
+                defining = Some((name.clone(), Macro { params: params.clone(), body: vec![] }));
+            }
+            Some(Item::MacroLine(text)) => match &mut defining {
+                Some((_, m)) => m.body.push(text.clone()),
+                None => return Err(Error::AsmBadMacro(line.line)),
+            },
+            Some(Item::MacroEnd) => match defining.take() {
+                Some((name, m)) => {
+                    macros.insert(name, m);
+                }
+                None => return Err(Error::AsmBadMacro(line.line)),
+            },
+            Some(Item::Include(path)) => {
+                let file = dir.join(path);
+                let (Ok(src), Ok(canonical)) = (fs::read_to_string(&file), file.canonicalize()) else {
+                    return Err(Error::AsmIncludeNotFound(line.line, path.clone()));
+                };
+                if including.contains(&canonical) {
+                    return Err(Error::AsmIncludeCycle(line.line, path.clone()));
+                }
+                let in_file = |e| Error::AsmInInclude(path.clone(), Box::new(e));
+                let lines = parse(&src).map_err(in_file)?;
+                let dir = file.parent().unwrap_or(dir);
+                including.push(canonical);
+                expand_into(&lines, dir, macros, including, depth, out).map_err(in_file)?;
+                including.pop();
+            }
+            Some(Item::MacroCall(name, args)) => {
+                let Some(m) = macros.get(name) else {
+                    return Err(Error::AsmUnknownMnemonic(line.line, name.clone()));
+                };
+                if m.params.len() != args.len() {
+                    return Err(Error::AsmWrongImmediateCount(line.line, name.clone(), m.params.len(), args.len()));
+                }
+                if depth == MAX_MACRO_DEPTH {
+                    return Err(Error::AsmMacroTooDeep(line.line, name.clone()));
+                }
+                // longer parameters first, so `%ab` isn't read as `%a` followed by `b`
+                let mut substs = m.params.iter().zip(args).collect::<Vec<_>>();
+                substs.sort_by_key(|(param, _)| std::cmp::Reverse(param.len()));
+                let mut lines = vec![];
+                for text in &m.body {
+                    let mut text = text.clone();
+                    for (param, arg) in &substs {
+                        text = text.replace(&format!("%{}", param), arg);
+                    }
+                    // errors inside a macro are reported where it's used
+                    lines.push(parse_line(&text, line.line, false)?);
+                }
+                expand_into(&lines, dir, macros, including, depth + 1, out)?;
+            }
+            _ => out.push(line.clone()),
+        }
+    }
+    match defining {
+        Some(_) => Err(Error::AsmBadMacro(lines.last().map_or(0, |line| line.line))),
+        None => Ok(()),
+    }
+}
+
+/// Turn assembly into the bytecode format.
+/// Includes and macros have to be `expand`ed first.
+pub fn assemble(lines: &[Line]) -> Result<ByteStream, Error> {
+    let mut features = None;
+    let mut lint_config = vec![];
+    let mut metadata = Metadata::default();
+    let mut has_metadata = false;
+    let mut export_names: Vec<(Label, &str)> = vec![];
+    let mut checksum = false;
+    let mut sized_bodies = false;
+    let mut data_section: Vec<u8> = vec![];
+    let mut types: Vec<Vec<Op1>> = vec![];
+    // whether the ops are going into the last `.type` rather than the last `.func`
+    let mut in_type = false;
+    let mut decls: Vec<Vec<Op1>> = vec![];
+    let mut bodies: Vec<Option<Vec<Op1>>> = vec![];
+    // functions are numbered by their `.func`s, named or not
+    let mut labels = HashMap::new();
+    let mut n: Label = 0;
+    for Line { line, item, .. } in lines {
+        if let Some(Item::Func(name)) = item {
+            if let Some(name) = name {
+                if labels.insert(name.clone(), n).is_some() {
+                    return Err(Error::AsmDuplicateLabel(*line, name.clone()));
+                }
+            }
+            n += 1;
+        }
+    }
+    let resolve = |line: usize, name: &String| match labels.get(name) {
+        Some(l) => Ok(*l),
+        None => Err(Error::AsmUnknownLabel(line, name.clone())),
+    };
+    for Line { line, item, .. } in lines {
+        let ops = match item {
+            Some(Item::Op(op)) => vec![*op],
+            Some(Item::GlobalFuncLabel(name)) => vec![Op1::GlobalFunc(resolve(*line, name)?)],
+            Some(Item::CallLabel(name)) => vec![Op1::GlobalFunc(resolve(*line, name)?), Op1::Call],
+            _ => vec![],
+        };
+        match item {
+            None | Some(Item::Op(_) | Item::GlobalFuncLabel(_) | Item::CallLabel(_)) => {}
+            Some(Item::Include(path)) => return Err(Error::AsmIncludeNotFound(*line, path.clone())),
+            Some(Item::MacroCall(name, _)) => return Err(Error::AsmUnknownMnemonic(*line, name.clone())),
+            Some(Item::MacroStart(_, _) | Item::MacroLine(_) | Item::MacroEnd) => return Err(Error::AsmBadMacro(*line)),
+            Some(Item::Features(bits)) => features = Some(*bits),
+            Some(Item::Lint(config)) => lint_config.push(config.as_str()),
+            Some(Item::Meta(key, value)) => {
+                metadata.set(key, value).map_err(|_| Error::AsmBadImmediate(*line, ".meta".to_string()))?;
+                has_metadata = true;
+            }
+            Some(Item::Checksum) => checksum = true,
+            Some(Item::SizedBodies) => sized_bodies = true,
+            Some(Item::Data(bytes)) => data_section.extend(bytes),
+            Some(Item::BigInt(text)) => data_section.extend(bigint::from_decimal(text).unwrap()),
+            Some(Item::Type) => {
+                types.push(vec![]);
+                in_type = true;
+            }
+            Some(Item::Func(_)) => {
+                decls.push(vec![]);
+                bodies.push(None);
+                in_type = false;
+            }
+            Some(Item::ExportName(name)) => match decls.len() {
+                0 => return Err(Error::AsmOutsideFunction(*line)),
+                _ if in_type => return Err(Error::AsmOutsideFunction(*line)),
+                n => export_names.push((n as Label - 1, name.as_str())),
+            },
+            Some(Item::Body) => match bodies.last_mut() {
+                None => return Err(Error::AsmOutsideFunction(*line)),
+                Some(_) if in_type => return Err(Error::AsmOutsideFunction(*line)),
+                Some(Some(_)) => return Err(Error::AsmDuplicateBody(*line)),
+                Some(body) => *body = Some(vec![]),
+            },
+        }
+        if ops.is_empty() {
+            continue;
+        }
+        if in_type {
+            types.last_mut().unwrap().extend(ops);
+            continue;
+        }
+        match (decls.last_mut(), bodies.last_mut()) {
+            (Some(_), Some(Some(body))) => body.extend(ops),
+            (Some(decl), Some(None)) => decl.extend(ops),
+            _ => return Err(Error::AsmOutsideFunction(*line)),
+        }
+    }
+    let mut header = vec![];
+    if !lint_config.is_empty() {
+        features = Some(features.unwrap_or(0) | Feature::LintConfig.bit());
+    }
+    if has_metadata {
+        features = Some(features.unwrap_or(0) | Feature::Metadata.bit());
+    }
+    if !export_names.is_empty() {
+        features = Some(features.unwrap_or(0) | Feature::ExportNames.bit());
+    }
+    if checksum {
+        features = Some(features.unwrap_or(0) | Feature::Checksum.bit());
+    }
+    if !types.is_empty() {
+        features = Some(features.unwrap_or(0) | Feature::TypeDecls.bit());
+    }
+    if sized_bodies {
+        features = Some(features.unwrap_or(0) | Feature::SizedBodies.bit());
+    }
+    if let Some(bits) = features {
+        header.extend(FEATURE_HEADER_MAGIC);
+        header.extend(bits.to_le_bytes());
+    }
+    if !lint_config.is_empty() {
+        let config = lint_config.join("\n");
+        header.extend((config.len() as u32).to_le_bytes());
+        header.extend(config.as_bytes());
+    }
+    if has_metadata {
+        let text = metadata.text();
+        header.extend((text.len() as u32).to_le_bytes());
+        header.extend(text.as_bytes());
+    }
+    if !export_names.is_empty() {
+        let mut section = vec![];
+        for (label, name) in export_names {
+            section.extend(label.to_le_bytes());
+            section.extend((name.len() as u32).to_le_bytes());
+            section.extend(name.as_bytes());
+        }
+        header.extend((section.len() as u32).to_le_bytes());
+        header.extend(section);
+    }
+    let mut out = vec![];
+    out.extend((data_section.len() as u32).to_le_bytes());
+    out.extend(data_section);
+    out.extend((decls.len() as u32).to_le_bytes());
+    if !types.is_empty() {
+        out.extend((types.len() as u32).to_le_bytes());
+    }
+    let encoded_bodies = bodies.iter().flatten().map(|body| encode(body)).collect::<Vec<_>>();
+    if sized_bodies {
+        out.extend((encoded_bodies.len() as u32).to_le_bytes());
+        for body in &encoded_bodies {
+            out.extend((body.len() as u32).to_le_bytes());
+        }
+    }
+    for ops in types.iter().chain(&decls) {
+        out.extend(encode(ops));
+    }
+    out.extend(encoded_bodies.concat());
+    if checksum {
+        header.extend(checksum::crc32(&out).to_le_bytes());
+    }
+    header.extend(out);
+    Ok(header)
+}
+
+/// Ops in the bytecode format.
+fn encode(ops: &[Op1]) -> Vec<u8> {
+    let mut out = vec![];
+    for op in ops {
+        op.write(&mut out);
+    }
+    out
+}
+
+/// Write bytes as an assembly string literal.
+pub(crate) fn string_lit(bytes: &[u8]) -> String {
+    let mut out = "\"".to_string();
+    for b in bytes {
+        match b {
+            b'\n' => out += "\\n",
+            b'\t' => out += "\\t",
+            b'\r' => out += "\\r",
+            0 => out += "\\0",
+            b'\\' => out += "\\\\",
+            b'"' => out += "\\\"",
+            0x20..=0x7E => out.push(*b as char),
+            _ => out += &format!("\\x{:02x}", b),
+        }
+    }
+    out + "\""
+}
+
+/// An op the way the formatter writes it: immediates in decimal, and names as strings without their zero padding.
+pub(crate) fn op_str(op: &Op1) -> String {
+    let mnemonic = op.info().mnemonic.to_string();
+    match op.imm() {
+        Imm::None => mnemonic,
+        Imm::U8(n) => format!("{} {}", mnemonic, n),
+        Imm::U32(n) => format!("{} {}", mnemonic, n),
+        Imm::I32(n) => format!("{} {}", mnemonic, n),
+        Imm::Name(a, b) => {
+            let bytes = [a.to_le_bytes(), b.to_le_bytes()].concat();
+            let len = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+            format!("{} {}", mnemonic, string_lit(&bytes[..len]))
+        }
+    }
+}
+
+fn item_str(item: &Item) -> String {
+    match item {
+        Item::Features(bits) => format!(".features {:#x}", bits),
+        Item::Lint(config) => format!(".lint {}", config),
+        Item::Meta(key, value) => format!(".meta {} {}", key, string_lit(value.as_bytes())),
+        Item::Checksum => ".checksum".to_string(),
+        Item::SizedBodies => ".sized_bodies".to_string(),
+        Item::Data(bytes) => format!(".data {}", string_lit(bytes)),
+        Item::BigInt(text) => format!(".bigint {}", text),
+        Item::Func(None) => ".func".to_string(),
+        Item::Func(Some(name)) => format!(".func @{}", name),
+        Item::ExportName(name) => format!(".export_name {}", string_lit(name.as_bytes())),
+        Item::Body => ".body".to_string(),
+        Item::Type => ".type".to_string(),
+        Item::Op(op) => format!("    {}", op_str(op)),
+        Item::GlobalFuncLabel(name) => format!("    global_func @{}", name),
+        Item::CallLabel(name) => format!("    call @{}", name),
+        Item::Include(path) => format!(".include {}", string_lit(path.as_bytes())),
+        Item::MacroStart(name, params) => {
+            let words = [".macro", name].into_iter().chain(params.iter().map(String::as_str));
+            words.collect::<Vec<_>>().join(" ")
+        }
+        Item::MacroLine(text) => format!("    {}", text),
+        Item::MacroEnd => ".endm".to_string(),
+        Item::MacroCall(name, args) => {
+            let words = [name.as_str()].into_iter().chain(args.iter().map(String::as_str));
+            format!("    {}", words.collect::<Vec<_>>().join(" "))
+        }
+    }
+}
+
+/// Write assembly back out in the one canonical layout:
+/// directives unindented, ops indented four spaces, one blank line before each `.type` and `.func`, no runs of blank lines,
+/// and immediates in decimal. Comments are kept, indented like the line after them,
+/// and comments right before a `.func` stay with it.
+pub fn format(lines: &[Line]) -> String {
+    let is_comment = |line: &Line| line.item.is_none() && line.comment.is_some();
+    let mut starts_func = vec![false; lines.len()];
+    for (i, line) in lines.iter().enumerate() {
+        if matches!(line.item, Some(Item::Type | Item::Func(_))) {
+            let mut j = i;
+            while j > 0 && is_comment(&lines[j - 1]) {
+                j -= 1;
+            }
+            starts_func[j] = true;
+        }
+    }
+    let mut out = String::new();
+    let mut blank = false;
+    for (i, line) in lines.iter().enumerate() {
+        if line.item.is_none() && line.comment.is_none() {
+            blank = true;
+            continue;
+        }
+        if (blank || starts_func[i]) && !out.is_empty() {
+            out += "\n";
+        }
+        blank = false;
+        match (&line.item, &line.comment) {
+            (Some(item), None) => out += &item_str(item),
+            (Some(item), Some(comment)) => out += &format!("{}  ;{}", item_str(item), comment),
+            (None, Some(comment)) => {
+                let next = lines[i..].iter().find_map(|line| line.item.as_ref());
+                let indent = match next {
+                    Some(
+                        Item::Op(_)
+                        | Item::GlobalFuncLabel(_)
+                        | Item::CallLabel(_)
+                        | Item::MacroLine(_)
+                        | Item::MacroCall(_, _),
+                    ) => "    ",
+                    _ => "",
+                };
+                out += &format!("{};{}", indent, comment);
+            }
+            (None, None) => unreachable!(),
+        }
+        out += "\n";
+    }
+    out
+}
+
+/// Turn a program in the bytecode format into assembly, in the same layout `format` uses.
+pub fn disassemble(bytes: &ByteStream) -> Result<String, Error> {
+    let (data_section, type_decs, forward_decs, stmts) = parse::go(bytes)?;
+    let mut lines = vec![];
+    let mut push = |item| {
+        lines.push(Line {
+            line: 0,
+            item: Some(item),
+            comment: None,
+        })
+    };
+    if bytes.starts_with(&FEATURE_HEADER_MAGIC) {
+        // `.lint`, `.meta`, `.export_name`, `.checksum`, `.sized_bodies`, and `.type` lines set their bits themselves
+        let bits = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        let set_by_lines = [
+            Feature::LintConfig,
+            Feature::Metadata,
+            Feature::ExportNames,
+            Feature::Checksum,
+            Feature::SizedBodies,
+            Feature::TypeDecls,
+        ];
+        let rest = set_by_lines.iter().fold(bits, |bits, feature| bits & !feature.bit());
+        if rest != 0 {
+            push(Item::Features(rest));
+        }
+        if bits & Feature::Checksum.bit() != 0 {
+            push(Item::Checksum);
+        }
+        if bits & Feature::SizedBodies.bit() != 0 {
+            push(Item::SizedBodies);
+        }
+    }
+    if let Some(config) = parse::lint_config(bytes)? {
+        let lines = config.lines().map(|line| line.split('#').next().unwrap().trim());
+        for line in lines.filter(|line| !line.is_empty()) {
+            push(Item::Lint(line.split_whitespace().collect::<Vec<_>>().join(" ")));
+        }
+    }
+    if let Some(metadata) = parse::metadata(bytes)? {
+        for key in Metadata::KEYS {
+            if let Some(value) = metadata.get(key) {
+                push(Item::Meta(key.to_string(), value.to_string()));
+            }
+        }
+    }
+    if !data_section.is_empty() {
+        push(Item::Data(data_section));
+    }
+    for TypeDec::Type(_, vis, ops) in type_decs {
+        push(Item::Type);
+        for op in ops {
+            push(Item::Op(op));
+        }
+        push(Item::Op(match vis {
+            Visibility::Local => Op1::Lced,
+            Visibility::Export(a, b) => Op1::Export(a, b),
+            Visibility::Import(a, b) => Op1::Import(a, b),
+        }));
+    }
+    let export_names = parse::export_names(bytes, &forward_decs)?;
+    let mut stmts = stmts.into_iter();
+    for (label, ForwardDec::Func(_, vis, ops)) in forward_decs.into_iter().enumerate() {
+        push(Item::Func(None));
+        for (name, _) in export_names.iter().filter(|(_, l)| *l == label as Label) {
+            push(Item::ExportName(name.clone()));
+        }
+        for op in ops {
+            push(Item::Op(op));
+        }
+        match vis {
+            Visibility::Local => push(Item::Op(Op1::Lced)),
+            Visibility::Export(a, b) => push(Item::Op(Op1::Export(a, b))),
+            Visibility::Import(a, b) => {
+                push(Item::Op(Op1::Import(a, b)));
+                continue;
+            }
+        }
+        if let Some(Stmt1::Func(_, _, ops)) = stmts.next() {
+            push(Item::Body);
+            for op in ops {
+                push(Item::Op(op));
+            }
+        }
+    }
+    Ok(format(&lines))
+}
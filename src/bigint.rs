@@ -0,0 +1,105 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The big integers of `Feature::BigInts`, for frontends of languages whose integers don't overflow.
+//!
+//! A big integer is laid out the same way wherever it is: a little-endian u32 that's 1 if it's negative and 0 if not,
+//! a little-endian u32 count of limbs, and then the limbs of its magnitude, little-endian u32s, least significant first.
+//! The most significant limb is never 0, so 0 has no limbs, and it's never negative.
+//! In a region, the `big_` ops make one like this as an object; the VM's arithmetic is in vm.c.
+//! A literal is one in the data section, which `big_data` uses in place, so the data section is the constant pool.
+//! The verifier checks that each literal is laid out right (`literal_len`), and `.bigint n` in assembly writes one.
+//! A result is allocated in the region it's given the handle of, with room for the biggest result its operands could have,
+//! so big integers count against a region's size like anything else, and a region too small for one traps with `RegionFull`.
+
+/// The sign and the count of limbs before the limbs.
+pub const HEADER: usize = 8;
+
+/// The bytes of the big integer written in decimal, with an optional `-`, laid out as above.
+pub fn from_decimal(text: &str) -> Option<Vec<u8>> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut limbs: Vec<u32> = vec![];
+    for digit in digits.bytes() {
+        // limbs = limbs * 10 + digit
+        let mut carry = u64::from(digit - b'0');
+        for limb in limbs.iter_mut() {
+            let x = u64::from(*limb) * 10 + carry;
+            *limb = x as u32;
+            carry = x >> 32;
+        }
+        if carry != 0 {
+            limbs.push(carry as u32);
+        }
+    }
+    let negative = negative && !limbs.is_empty();
+    let mut bytes = Vec::with_capacity(HEADER + 4 * limbs.len());
+    bytes.extend(u32::from(negative).to_le_bytes());
+    bytes.extend((limbs.len() as u32).to_le_bytes());
+    for limb in limbs {
+        bytes.extend(limb.to_le_bytes());
+    }
+    Some(bytes)
+}
+
+/// How many bytes the big integer at the start of `bytes` takes up, or `None` if it isn't laid out right:
+/// if `bytes` stops before it does, its sign isn't 0 or 1, its top limb is 0, or it's a negative 0.
+pub fn literal_len(bytes: &[u8]) -> Option<usize> {
+    let (negative, limbs) = split(bytes)?;
+    let top = limbs.chunks_exact(4).last();
+    match (negative, top) {
+        (_, Some([0, 0, 0, 0])) | (true, None) => None,
+        _ => Some(HEADER + limbs.len()),
+    }
+}
+
+/// The sign of the big integer at the start of `bytes`, and the bytes of its limbs.
+fn split(bytes: &[u8]) -> Option<(bool, &[u8])> {
+    let sign = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?);
+    let len = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?) as usize;
+    let limbs = bytes.get(HEADER..HEADER.checked_add(len.checked_mul(4)?)?)?;
+    match sign {
+        0 | 1 => Some((sign == 1, limbs)),
+        _ => None,
+    }
+}
+
+/// The big integer at the start of `bytes` in decimal, or `None` if it isn't laid out right.
+pub fn to_decimal(bytes: &[u8]) -> Option<String> {
+    literal_len(bytes)?;
+    let (negative, limbs) = split(bytes)?;
+    let mut limbs = limbs.chunks_exact(4).map(|limb| u32::from_le_bytes([limb[0], limb[1], limb[2], limb[3]])).collect::<Vec<_>>();
+    // nine digits at a time, from the bottom up
+    let mut chunks = vec![];
+    while !limbs.is_empty() {
+        let mut rem = 0u64;
+        for limb in limbs.iter_mut().rev() {
+            let x = (rem << 32) | u64::from(*limb);
+            *limb = (x / 1_000_000_000) as u32;
+            rem = x % 1_000_000_000;
+        }
+        chunks.push(rem);
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+    }
+    let mut text = if negative { "-".to_string() } else { String::new() };
+    match chunks.split_last() {
+        Some((top, rest)) => {
+            text += &top.to_string();
+            for chunk in rest.iter().rev() {
+                text += &format!("{:09}", chunk);
+            }
+        }
+        None => text += "0",
+    }
+    Some(text)
+}
@@ -0,0 +1,75 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! An on-disk record of function bodies that have already verified,
+//! so checking a big program again after a small edit only looks at what changed.
+//! This is for development: anyone who can write to the cache directory can make a function skip verification.
+
+use crate::header::*;
+use crate::metrics::{self, Counter};
+use crate::verify::{check_entry, Signatures};
+
+use std::fs;
+use std::path::Path;
+
+/// How much work a cached check did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub verified: usize,
+    pub cached: usize,
+}
+
+/// FNV-1a, which unlike std's hasher is guaranteed to give the same answer on every build.
+fn hash(bytes: &[u8]) -> u128 {
+    let mut h: u128 = 0x6c62272e07bb014262b821756295c58d;
+    for b in bytes {
+        h ^= *b as u128;
+        h = h.wrapping_mul(0x0000000001000000000000000000013B);
+    }
+    h
+}
+
+/// Verify a parsed program like `verify::go`, skipping function bodies that verified before.
+/// A body is only skipped if it, the data section, every type and forward declaration, and this version of SaberVM are all unchanged,
+/// since any of those could change whether it verifies.
+pub fn check(
+    data_section: &[u8],
+    type_decs: &[TypeDec],
+    types_instrs: &[ForwardDec],
+    unverified_stmts: &[Stmt1],
+    dir: &Path,
+) -> Result<CacheStats, Error> {
+    let sigs = Signatures::new(type_decs, types_instrs, &Limits::default(), None)?;
+    if let Some(Stmt1::Func(l, _, _)) = unverified_stmts.first() {
+        if let Some(t) = sigs.signature(*l) {
+            check_entry(t)?;
+        }
+    }
+    let context = format!("{} {:?} {:?} {:?}", env!("CARGO_PKG_VERSION"), data_section, type_decs, types_instrs);
+    let _ = fs::create_dir_all(dir);
+    let mut stats = CacheStats::default();
+    for stmt in unverified_stmts {
+        let key = hash(format!("{} {:?}", context, stmt).as_bytes());
+        let entry = dir.join(format!("{:032x}", key));
+        if entry.exists() {
+            stats.cached += 1;
+            metrics::count(Counter::CacheHits, 1);
+            metrics::count(Counter::FunctionsVerified, 1);
+            continue;
+        }
+        metrics::count(Counter::CacheMisses, 1);
+        let res = sigs.check_body(data_section, stmt, &Cancellation::default(), None);
+        if res.is_err() {
+            metrics::count(Counter::VerifyErrors, 1);
+        }
+        res?;
+        stats.verified += 1;
+        metrics::count(Counter::FunctionsVerified, 1);
+        // failing to write the cache only makes the next check slower
+        let _ = fs::write(entry, []);
+    }
+    Ok(stats)
+}
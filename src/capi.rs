@@ -0,0 +1,220 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The C API, for embedding SaberVM in hosts that aren't written in Rust.
+//! The declarations are in `include/sabervm.h`, which cbindgen generates from this file.
+//!
+//! Functions that can fail return null or a negative status,
+//! and then `svm_last_error` says why, until the next failure on the same thread.
+
+use crate::error_msgs;
+use crate::header::{ByteStream, Outcome};
+use crate::vm::{Instance, Module};
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CString};
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+/// A verified and linked module. One module can back any number of instances.
+pub struct SvmModule(Arc<Module>);
+
+/// One run of a module.
+pub struct SvmInstance(Instance);
+
+/// The run halted; the value is the status given to `halt`.
+pub const SVM_HALTED: i32 = 0;
+/// The run stopped at a `yield`; the value is what was yielded. Continue it with `svm_instance_resume`.
+pub const SVM_YIELDED: i32 = 1;
+/// The run trapped, or the call was invalid. `svm_last_error` says which.
+pub const SVM_ERROR: i32 = -1;
+
+/// A host function: it's given the pointer it was registered with, and the i32 from `host_call`.
+pub type SvmHostFn = extern "C" fn(data: *mut c_void, arg: i32) -> i32;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(msg: String) {
+    // messages are built by SaberVM, and never have a nul byte in them
+    let msg = CString::new(msg.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// The message for the last failure on this thread, or null if nothing has failed.
+/// The string stays valid until the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn svm_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |msg| msg.as_ptr()))
+}
+
+/// # Safety
+/// `programs` and `lens` must both point to `count` elements, and each program must be `lens[i]` readable bytes.
+unsafe fn read_programs(programs: *const *const u8, lens: *const usize, count: usize) -> Vec<ByteStream> {
+    if count == 0 {
+        return vec![];
+    }
+    let programs = slice::from_raw_parts(programs, count);
+    let lens = slice::from_raw_parts(lens, count);
+    programs.iter().zip(lens).map(|(&p, &len)| slice::from_raw_parts(p, len).to_vec()).collect()
+}
+
+/// Parse and verify each program, returning 0 if they're all valid.
+/// Unlike `svm_module_new`, this doesn't link them, so imports don't have to be satisfied.
+///
+/// # Safety
+/// `programs` and `lens` must both point to `count` elements, and each program must be `lens[i]` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn svm_module_verify(programs: *const *const u8, lens: *const usize, count: usize) -> i32 {
+    for prog in read_programs(programs, lens, count) {
+        let res = crate::parse::go(&prog).and_then(|(data_section, type_decs, types_instrs, stmts)| {
+            crate::verify::go(data_section, type_decs, types_instrs, stmts)
+        });
+        if let Err(e) = res {
+            set_error(error_msgs::msg(e));
+            return SVM_ERROR;
+        }
+    }
+    0
+}
+
+/// Parse, verify, and link the programs into a module, or return null if any of them is invalid.
+/// The module has to be freed with `svm_module_free`.
+///
+/// # Safety
+/// `programs` and `lens` must both point to `count` elements, and each program must be `lens[i]` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn svm_module_new(
+    programs: *const *const u8,
+    lens: *const usize,
+    count: usize,
+) -> *mut SvmModule {
+    match Module::new(read_programs(programs, lens, count)) {
+        Ok(module) => Box::into_raw(Box::new(SvmModule(Arc::new(module)))),
+        Err(e) => {
+            set_error(error_msgs::msg(e));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `module` must be null or from `svm_module_new`, and not already freed.
+/// Instances made from it stay valid.
+#[no_mangle]
+pub unsafe extern "C" fn svm_module_free(module: *mut SvmModule) {
+    if !module.is_null() {
+        drop(Box::from_raw(module));
+    }
+}
+
+/// A new instance of the module. It has to be freed with `svm_instance_free`.
+///
+/// # Safety
+/// `module` must be from `svm_module_new`, and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn svm_instance_new(module: *const SvmModule) -> *mut SvmInstance {
+    let module = &*module;
+    Box::into_raw(Box::new(SvmInstance(Instance::new(module.0.clone()))))
+}
+
+/// # Safety
+/// `instance` must be null or from `svm_instance_new`, and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn svm_instance_free(instance: *mut SvmInstance) {
+    if !instance.is_null() {
+        drop(Box::from_raw(instance));
+    }
+}
+
+struct HostData(*mut c_void);
+
+// It's up to the host to make `data` safe to use from whichever thread runs the instance.
+unsafe impl Send for HostData {}
+
+/// Provide the function that `host_call index` runs. `data` is passed to it on every call.
+///
+/// # Safety
+/// `instance` must be from `svm_instance_new`, and not freed.
+/// `f` gets `data` on whichever thread runs the instance.
+#[no_mangle]
+pub unsafe extern "C" fn svm_instance_register_host_fn(
+    instance: *mut SvmInstance,
+    index: u32,
+    f: SvmHostFn,
+    data: *mut c_void,
+) {
+    let data = HostData(data);
+    (*instance).0.register_host_fn(index, move |arg| {
+        let data = &data;
+        f(data.0, arg)
+    });
+}
+
+fn outcome(res: Result<Outcome, crate::header::Trap>, value: *mut i32) -> i32 {
+    let (status, val) = match res {
+        Ok(Outcome::Halted(status)) => (SVM_HALTED, status.into()),
+        Ok(Outcome::Yielded(val)) => (SVM_YIELDED, val),
+        Err(trap) => {
+            set_error(error_msgs::trap_msg(trap));
+            return SVM_ERROR;
+        }
+    };
+    if !value.is_null() {
+        unsafe { *value = val };
+    }
+    status
+}
+
+/// Run the module from its entry point, returning `SVM_HALTED`, `SVM_YIELDED`, or `SVM_ERROR`.
+/// If it halts or yields, the status or yielded value is written to `value`, unless that's null.
+///
+/// # Safety
+/// `instance` must be from `svm_instance_new`, and not freed. `value` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn svm_instance_run(instance: *mut SvmInstance, value: *mut i32) -> i32 {
+    svm_instance_run_with_args(instance, ptr::null(), 0, value)
+}
+
+/// Like `svm_instance_run`, but passing the `n` values at `args` to the entry function.
+/// This is an error if the entry function doesn't take exactly `n` `i32`s.
+///
+/// # Safety
+/// `instance` must be from `svm_instance_new`, and not freed. `args` must point to `n` values, unless `n` is 0.
+/// `value` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn svm_instance_run_with_args(
+    instance: *mut SvmInstance,
+    args: *const i32,
+    n: usize,
+    value: *mut i32,
+) -> i32 {
+    let instance = &mut (*instance).0;
+    let params = instance.module().entry_params();
+    if params != n {
+        set_error(format!("the entry function takes {} arguments, but was given {}", params, n));
+        return SVM_ERROR;
+    }
+    let args = if n == 0 { &[] } else { slice::from_raw_parts(args, n) };
+    outcome(instance.run_with_args(args), value)
+}
+
+/// Continue a run that stopped at a `yield`, with `val` as the result of the `yield`.
+/// This returns like `svm_instance_run`, and is an error if the instance isn't stopped at a `yield`.
+///
+/// # Safety
+/// `instance` must be from `svm_instance_new`, and not freed. `value` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn svm_instance_resume(instance: *mut SvmInstance, val: i32, value: *mut i32) -> i32 {
+    let instance = &mut (*instance).0;
+    if !instance.is_suspended() {
+        set_error("resumed an instance that isn't stopped at a yield".into());
+        return SVM_ERROR;
+    }
+    outcome(instance.resume(val), value)
+}
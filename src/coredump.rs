@@ -0,0 +1,161 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Snapshots of the VM at a trap, so a crash in a long run can be looked at later.
+//!
+//! A core dump file is, with every number little-endian:
+//!
+//! - the 8 bytes `SVMCORE\0`
+//! - the format version, a u32, currently 1
+//! - the trap: a u8 kind (0 interrupted, 1 unknown host function, 2 async host function, 3 uninitialized read, 4 out of bounds)
+//!   and a u32 argument (the host function for kinds 1 and 2, otherwise 0)
+//! - the position in the linked code where the run stopped, a u32, or 0 if it stopped between tasks
+//! - the number of recent calls recorded, a u32, then the code position of each call as a u32, newest first
+//! - the number of tasks waiting in the scheduler, a u32, then the code position of each as a u32
+//! - the number of bytes on the stack, a u32, then the stack itself, bottom first
+//!
+//! The stack is raw bytes laid out as the verifier sized them (see `Type::size`).
+//! The VM doesn't know the types of what's on it, so pointers into regions are kept as they were but the heap isn't walked.
+
+use crate::header::Trap;
+use crate::vm::{Location, Module};
+
+const MAGIC: &[u8; 8] = b"SVMCORE\0";
+const VERSION: u32 = 1;
+
+/// What an `Instance` looked like when it trapped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreDump {
+    pub trap: Trap,
+    /// Where in the linked code the run stopped, or 0 between tasks.
+    pub pc: u32,
+    /// Where the last few calls before the trap were made from, newest first.
+    /// CPS calls never return, so this is the closest thing to a call stack.
+    pub calls: Vec<u32>,
+    /// The code positions of the tasks still waiting to run.
+    pub tasks: Vec<u32>,
+    /// The stack, bottom first.
+    pub stack: Vec<u8>,
+}
+
+impl CoreDump {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (kind, arg): (u8, u32) = match self.trap {
+            Trap::Interrupted => (0, 0),
+            Trap::UnknownHostFunction(f) => (1, f),
+            Trap::AsyncHostFunction(f) => (2, f),
+            Trap::UninitializedRead => (3, 0),
+            Trap::OutOfBounds => (4, 0),
+            Trap::CallbackHalted(status) => (5, status.into()),
+            Trap::ReentrantHostCall(f) => (6, f),
+            Trap::HostSignature(f) => (7, f),
+            Trap::UseAfterFree => (8, 0),
+            Trap::DoubleFree => (9, 0),
+            Trap::Overflow => (10, 0),
+            Trap::DivideByZero => (11, 0),
+            Trap::Unverified(label) => (12, label),
+            Trap::RegionFull => (13, 0),
+            Trap::StdinBusy => (14, 0),
+            Trap::NotSuspended => (15, 0),
+            Trap::ArgCount(n) => (16, n),
+            Trap::ForeignGuestFn => (17, 0),
+        };
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        out.push(kind);
+        out.extend(arg.to_le_bytes());
+        out.extend(self.pc.to_le_bytes());
+        out.extend((self.calls.len() as u32).to_le_bytes());
+        for call in &self.calls {
+            out.extend(call.to_le_bytes());
+        }
+        out.extend((self.tasks.len() as u32).to_le_bytes());
+        for task in &self.tasks {
+            out.extend(task.to_le_bytes());
+        }
+        out.extend((self.stack.len() as u32).to_le_bytes());
+        out.extend(&self.stack);
+        out
+    }
+
+    /// Read a core dump written by `to_bytes`, or `None` if the bytes aren't one this version understands.
+    pub fn from_bytes(bytes: &[u8]) -> Option<CoreDump> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(8)? != MAGIC || r.u32()? != VERSION {
+            return None;
+        }
+        let kind = r.take(1)?[0];
+        let arg = r.u32()?;
+        let trap = match kind {
+            0 => Trap::Interrupted,
+            1 => Trap::UnknownHostFunction(arg),
+            2 => Trap::AsyncHostFunction(arg),
+            3 => Trap::UninitializedRead,
+            4 => Trap::OutOfBounds,
+            5 => Trap::CallbackHalted(arg.try_into().ok()?),
+            6 => Trap::ReentrantHostCall(arg),
+            7 => Trap::HostSignature(arg),
+            8 => Trap::UseAfterFree,
+            9 => Trap::DoubleFree,
+            10 => Trap::Overflow,
+            11 => Trap::DivideByZero,
+            12 => Trap::Unverified(arg),
+            13 => Trap::RegionFull,
+            14 => Trap::StdinBusy,
+            15 => Trap::NotSuspended,
+            16 => Trap::ArgCount(arg),
+            17 => Trap::ForeignGuestFn,
+            _ => return None,
+        };
+        let pc = r.u32()?;
+        let calls = r.u32s()?;
+        let tasks = r.u32s()?;
+        let len = r.u32()? as usize;
+        let stack = r.take(len)?.to_vec();
+        if r.pos != bytes.len() {
+            return None;
+        }
+        Some(CoreDump {
+            trap,
+            pc,
+            calls,
+            tasks,
+            stack,
+        })
+    }
+
+    /// The function the trap was in, then the functions the recent calls were made from, newest first.
+    /// `module` has to be the one that trapped, or the locations won't mean anything.
+    pub fn backtrace(&self, module: &Module) -> Vec<Location> {
+        std::iter::once(self.pc)
+            .chain(self.calls.iter().copied())
+            .filter_map(|pc| module.locate(pc))
+            .collect()
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let out = self.bytes.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(out)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A u32 count, then that many u32s.
+    fn u32s(&mut self) -> Option<Vec<u32>> {
+        let n = self.u32()?;
+        (0..n).map(|_| self.u32()).collect()
+    }
+}
@@ -0,0 +1,382 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Comparing two programs function by function, for seeing what a change to a compiler did to its output.
+//!
+//! Exported and imported functions are matched up by name, so they still line up when functions are added before them,
+//! and the rest are matched up by label. Each pair is compared as disassembly, the same text `sabervm disasm` writes.
+//!
+//! `equivalent` is stricter about meaning and looser about text: it checks that two programs do the same thing
+//! even if their functions are numbered differently or their types are built by different ops.
+
+use crate::asm;
+use crate::header::*;
+use crate::parse;
+use crate::pretty::Pretty;
+use crate::verify;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// How a function is matched with its counterpart in the other program.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FuncKey {
+    Export(Vec<u8>),
+    Import(Vec<u8>),
+    Label(Label),
+}
+
+impl FuncKey {
+    fn describe(&self, label: Label) -> String {
+        match self {
+            FuncKey::Export(name) => format!("function {} (export {})", label, asm::string_lit(name)),
+            FuncKey::Import(name) => format!("function {} (import {})", label, asm::string_lit(name)),
+            FuncKey::Label(_) => format!("function {}", label),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Added,
+    Removed,
+    Changed,
+    Same,
+}
+
+/// One line of a function's disassembly, and which program it's in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffLine {
+    Both(String),
+    Old(String),
+    New(String),
+}
+
+/// One function, compared with its counterpart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuncDiff {
+    pub key: FuncKey,
+    /// The function's label in the old program, or in the new one if it was added.
+    pub label: Label,
+    pub status: Status,
+    /// Every line of both versions, in order; only changed functions have `Old` or `New` lines.
+    pub lines: Vec<DiffLine>,
+}
+
+/// What changed between two programs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleDiff {
+    /// Whether the data sections are different.
+    pub data_changed: bool,
+    /// The functions of the old program, in label order, and then the ones only the new program has.
+    pub funcs: Vec<FuncDiff>,
+}
+
+/// The key and disassembly of every function in a program.
+fn functions(forward_decs: Vec<ForwardDec>, stmts: &[Stmt1]) -> Vec<(FuncKey, Label, Vec<String>)> {
+    let mut out = vec![];
+    for ForwardDec::Func(label, vis, ops) in forward_decs {
+        let name = |a: u64, b: u64| {
+            let bytes = [a.to_le_bytes(), b.to_le_bytes()].concat();
+            let len = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+            bytes[..len].to_vec()
+        };
+        let (key, last) = match vis {
+            Visibility::Local => (FuncKey::Label(label), Op1::Lced),
+            Visibility::Export(a, b) => (FuncKey::Export(name(a, b)), Op1::Export(a, b)),
+            Visibility::Import(a, b) => (FuncKey::Import(name(a, b)), Op1::Import(a, b)),
+        };
+        let mut lines: Vec<String> = ops.iter().chain([&last]).map(asm::op_str).collect();
+        if let Some(Stmt1::Func(_, _, body)) = stmts.iter().find(|Stmt1::Func(l, _, _)| *l == label) {
+            lines.push(".body".to_string());
+            lines.extend(body.iter().map(asm::op_str));
+        }
+        out.push((key, label, lines));
+    }
+    out
+}
+
+/// The most cells the line-matching table can have before a function is shown as wholly replaced instead.
+const MAX_TABLE: usize = 1 << 22;
+
+/// Line up `old` and `new` along their longest common subsequence of lines.
+fn diff_lines(old: &[String], new: &[String]) -> Vec<DiffLine> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    let mut out: Vec<DiffLine> = old[..prefix].iter().cloned().map(DiffLine::Both).collect();
+    if (a.len() + 1) * (b.len() + 1) > MAX_TABLE {
+        out.extend(a.iter().cloned().map(DiffLine::Old));
+        out.extend(b.iter().cloned().map(DiffLine::New));
+    } else {
+        // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                out.push(DiffLine::Both(a[i].clone()));
+                i += 1;
+                j += 1;
+            } else if i < a.len() && (j == b.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                out.push(DiffLine::Old(a[i].clone()));
+                i += 1;
+            } else {
+                out.push(DiffLine::New(b[j].clone()));
+                j += 1;
+            }
+        }
+    }
+    out.extend(old[old.len() - suffix..].iter().cloned().map(DiffLine::Both));
+    out
+}
+
+/// Compare two programs in the bytecode format.
+pub fn diff(old: &ByteStream, new: &ByteStream) -> Result<ModuleDiff, Error> {
+    let (old_data, _, old_decs, old_stmts) = parse::go(old)?;
+    let (new_data, _, new_decs, new_stmts) = parse::go(new)?;
+    let mut new_funcs = functions(new_decs, &new_stmts);
+    let mut funcs = vec![];
+    for (key, label, lines) in functions(old_decs, &old_stmts) {
+        match new_funcs.iter().position(|(k, _, _)| *k == key) {
+            Some(i) => {
+                let (_, _, new_lines) = new_funcs.remove(i);
+                let status = if lines == new_lines { Status::Same } else { Status::Changed };
+                let lines = diff_lines(&lines, &new_lines);
+                funcs.push(FuncDiff { key, label, status, lines });
+            }
+            None => {
+                let lines = lines.into_iter().map(DiffLine::Old).collect();
+                funcs.push(FuncDiff { key, label, status: Status::Removed, lines });
+            }
+        }
+    }
+    for (key, label, lines) in new_funcs {
+        let lines = lines.into_iter().map(DiffLine::New).collect();
+        funcs.push(FuncDiff { key, label, status: Status::Added, lines });
+    }
+    Ok(ModuleDiff { data_changed: old_data != new_data, funcs })
+}
+
+/// How many unchanged lines to show around each change.
+const CONTEXT: usize = 2;
+
+impl ModuleDiff {
+    /// How many functions have each status: added, removed, changed, and the same.
+    pub fn counts(&self) -> (usize, usize, usize, usize) {
+        let count = |status| self.funcs.iter().filter(|f| f.status == status).count();
+        (count(Status::Added), count(Status::Removed), count(Status::Changed), count(Status::Same))
+    }
+
+    /// Whether the programs are the same, function for function.
+    pub fn is_empty(&self) -> bool {
+        !self.data_changed && self.funcs.iter().all(|f| f.status == Status::Same)
+    }
+
+    /// The diff as text: each function that isn't the same, with its changed lines marked `-` and `+`
+    /// and a few unchanged ones around them, and then a summary.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        if self.data_changed {
+            out += "data section: changed\n";
+        }
+        for f in self.funcs.iter().filter(|f| f.status != Status::Same) {
+            let status = match f.status {
+                Status::Added => "added",
+                Status::Removed => "removed",
+                _ => "changed",
+            };
+            let _ = writeln!(out, "{}: {}", f.key.describe(f.label), status);
+            let changed = |line: &DiffLine| !matches!(line, DiffLine::Both(_));
+            let mut skipped = false;
+            for (i, line) in f.lines.iter().enumerate() {
+                let lo = i.saturating_sub(CONTEXT);
+                let near = f.lines[lo..(i + CONTEXT + 1).min(f.lines.len())].iter().any(changed);
+                match line {
+                    DiffLine::Both(_) if !near => {
+                        skipped = true;
+                        continue;
+                    }
+                    _ if skipped => {
+                        out += "    ...\n";
+                        skipped = false;
+                    }
+                    _ => {}
+                }
+                let _ = match line {
+                    DiffLine::Both(text) => writeln!(out, "      {}", text),
+                    DiffLine::Old(text) => writeln!(out, "    - {}", text),
+                    DiffLine::New(text) => writeln!(out, "    + {}", text),
+                };
+            }
+            if skipped {
+                out += "    ...\n";
+            }
+        }
+        let (added, removed, changed, same) = self.counts();
+        let _ = writeln!(out, "{} added, {} removed, {} changed, {} unchanged", added, removed, changed, same);
+        out
+    }
+}
+
+/// Why two programs aren't equivalent. Labels are the old program's, then the new one's.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inequivalence {
+    DataSection,
+    /// The types declared at this index differ.
+    NamedType(u32),
+    /// An export or import that only one of the programs has.
+    Unmatched(Visibility),
+    /// Two functions that line up have different types.
+    Signature(Label, Label, Type, Type),
+    /// Two functions that line up differ at this instruction (or one is an import, and the other isn't).
+    Body(Label, Label, usize),
+    /// A function in one program lines up with two different functions in the other.
+    Renumbering(Label, Label),
+}
+
+impl Inequivalence {
+    pub fn describe(&self) -> String {
+        match self {
+            Inequivalence::DataSection => "the data sections are different".to_string(),
+            Inequivalence::NamedType(k) => format!("the types declared at index {} are different", k),
+            Inequivalence::Unmatched(vis) => {
+                format!("only one of the programs has {}", vis.pretty().trim_end_matches('\0'))
+            }
+            Inequivalence::Signature(old, new, t1, t2) => format!(
+                "function {} has type {} but function {}, which it lines up with, has type {}",
+                old,
+                t1.pretty(),
+                new,
+                t2.pretty()
+            ),
+            Inequivalence::Body(old, new, i) => {
+                format!("function {} and function {}, which it lines up with, differ at instruction {}", old, new, i)
+            }
+            Inequivalence::Renumbering(old, new) => {
+                format!("function {} and function {} line up with different functions in the other program", old, new)
+            }
+        }
+    }
+}
+
+/// A program's functions, as `equivalent` compares them.
+struct Program {
+    data_section: Vec<u8>,
+    named: Vec<NamedType>,
+    sigs: HashMap<Label, (Visibility, Type)>,
+    bodies: HashMap<Label, Vec<Op2>>,
+    entry: Option<Label>,
+}
+
+fn program(bytes: &ByteStream) -> Result<Program, Error> {
+    let (data_section, type_decs, forward_decs, stmts) = parse::go(bytes)?;
+    let named = verify::named_types(&type_decs, forward_decs.len(), &Limits::default())?;
+    let sigs = verify::signatures(&type_decs, &forward_decs)?;
+    let ir = verify::go(data_section, type_decs, forward_decs, stmts)?;
+    Ok(Program {
+        named,
+        entry: ir.funcs.first().map(|Stmt2::Func(label, _, _, _)| *label),
+        sigs: sigs.into_iter().map(|(label, vis, t)| (label, (vis, t))).collect(),
+        bodies: ir.funcs.into_iter().map(|Stmt2::Func(label, _, ops, _)| (label, ops)).collect(),
+        data_section: ir.data_section,
+    })
+}
+
+/// Check that two programs mean the same thing, so a compiler change that only moved things around can be told apart
+/// from one that changed what its output does.
+///
+/// Both programs are verified, and then compared after verification, where the ops that only build types are gone,
+/// so functions can build their types in a different order, or with different ops, as long as the types come out the same.
+/// Functions can be numbered differently too: starting from the entry points, exports, and imports, which are matched by name,
+/// each `global_func` in one program has to line up with the same function every time in the other.
+/// Functions that can't be reached from those aren't compared, since they can never run.
+/// Type declarations aren't renumbered, so they have to come in the same order; `Ok(None)` means the programs are equivalent.
+pub fn equivalent(old: &ByteStream, new: &ByteStream) -> Result<Option<Inequivalence>, Error> {
+    let (a, b) = (program(old)?, program(new)?);
+    if a.data_section != b.data_section {
+        return Ok(Some(Inequivalence::DataSection));
+    }
+    if a.named.len() != b.named.len() {
+        let k = a.named.len().min(b.named.len());
+        return Ok(Some(Inequivalence::NamedType(k as u32)));
+    }
+    for (k, (t1, t2)) in a.named.iter().zip(&b.named).enumerate() {
+        let same = match (&t1.definition, &t2.definition) {
+            (Some(d1), Some(d2)) => verify::type_eq(d1, d2),
+            (None, None) => true,
+            _ => false,
+        };
+        if !same || t1.size != t2.size || t1.visibility != t2.visibility {
+            return Ok(Some(Inequivalence::NamedType(k as u32)));
+        }
+    }
+    // the functions that have to line up before any bodies are compared
+    let mut todo = vec![];
+    if let (Some(e1), Some(e2)) = (a.entry, b.entry) {
+        todo.push((e1, e2));
+    }
+    let named = |p: &Program| {
+        let visible = p.sigs.iter().filter(|(_, (vis, _))| *vis != Visibility::Local);
+        let mut names = visible.map(|(l, (vis, _))| (*vis, *l)).collect::<Vec<_>>();
+        names.sort_by_key(|(_, l)| *l);
+        names
+    };
+    let names2 = named(&b);
+    for (vis, l1) in named(&a) {
+        match names2.iter().find(|(vis2, _)| *vis2 == vis) {
+            Some((_, l2)) => todo.push((l1, *l2)),
+            None => return Ok(Some(Inequivalence::Unmatched(vis))),
+        }
+    }
+    let names1 = named(&a);
+    if let Some((vis, _)) = names2.iter().find(|(vis, _)| !names1.iter().any(|(vis1, _)| vis1 == vis)) {
+        return Ok(Some(Inequivalence::Unmatched(*vis)));
+    }
+    let mut forward: HashMap<Label, Label> = HashMap::new();
+    let mut backward: HashMap<Label, Label> = HashMap::new();
+    while let Some((l1, l2)) = todo.pop() {
+        match (forward.get(&l1), backward.get(&l2)) {
+            (Some(m2), _) if *m2 != l2 => return Ok(Some(Inequivalence::Renumbering(l1, *m2))),
+            (_, Some(m1)) if *m1 != l1 => return Ok(Some(Inequivalence::Renumbering(*m1, l2))),
+            (Some(_), _) => continue,
+            _ => {}
+        }
+        forward.insert(l1, l2);
+        backward.insert(l2, l1);
+        let (Some((_, t1)), Some((_, t2))) = (a.sigs.get(&l1), b.sigs.get(&l2)) else {
+            return Ok(Some(Inequivalence::Body(l1, l2, 0)));
+        };
+        if !verify::type_eq(t1, t2) {
+            return Ok(Some(Inequivalence::Signature(l1, l2, t1.clone(), t2.clone())));
+        }
+        let (ops1, ops2) = match (a.bodies.get(&l1), b.bodies.get(&l2)) {
+            (Some(ops1), Some(ops2)) => (ops1, ops2),
+            (None, None) => continue,
+            _ => return Ok(Some(Inequivalence::Body(l1, l2, 0))),
+        };
+        for (i, pair) in ops1.iter().zip(ops2).enumerate() {
+            match pair {
+                (Op2::GlobalFunc(f1), Op2::GlobalFunc(f2)) => todo.push((*f1, *f2)),
+                (op1, op2) if op1 == op2 => {}
+                _ => return Ok(Some(Inequivalence::Body(l1, l2, i))),
+            }
+        }
+        if ops1.len() != ops2.len() {
+            return Ok(Some(Inequivalence::Body(l1, l2, ops1.len().min(ops2.len()))));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,3662 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A stable code for every `Error`, `Trap`, and `CallError`, like `E0404`, and a longer explanation of each,
+//! which `sabervm explain E0404` prints. Messages end with their code, so it's easy to go from one to the other.
+//!
+//! The codes are grouped by the hundred: `E00xx` for the binary format, `E01xx` for assembly,
+//! `E02xx` for rules the host sets, `E03xx` for declarations, `E04xx` for type errors in function bodies,
+//! `E05xx` for region errors, `E06xx` for traps, `E07xx` for the host calling an export,
+//! and `E08xx` for reloading a module.
+//! A code is never reused for something else, even if its error goes away.
+//!
+//! Most explanations come with a small assembly program that fails with the code and a fixed version that doesn't.
+//! `sabervm explain --check` runs all of them, so they can't drift from what SaberVM really does.
+
+use crate::header::*;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ErrorCode(pub u16);
+
+impl ErrorCode {
+    /// Read a code written like `E0404` (or `e0404`).
+    pub fn parse(s: &str) -> Option<ErrorCode> {
+        let digits = s.strip_prefix('E').or_else(|| s.strip_prefix('e'))?;
+        if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok().map(ErrorCode)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "E{:04}", self.0)
+    }
+}
+
+pub fn code(e: &Error) -> ErrorCode {
+    ErrorCode(match e {
+        Error::SyntaxErrorParamNeeded(_, _) => 1,
+        Error::SyntaxErrorUnknownOp(_, _) => 2,
+        Error::SyntaxErrorLabelOutOfRange(_, _, _) => 3,
+        Error::SyntaxErrorFieldCount(_, _, _) => 4,
+        Error::SyntaxErrorStrayField(_) => 5,
+        Error::UnexpectedEOF => 6,
+        Error::UnsupportedFeature(_) => 7,
+        Error::UnknownFeatureBits(_) => 8,
+        Error::ChecksumMismatch(_, _) => 9,
+        Error::BodyCountMismatch(_, _) => 10,
+        Error::BodySizeMismatch(_, _) => 11,
+        Error::OpsAfterSizedBodies(_) => 12,
+        Error::UnknownLint(_) => 13,
+        Error::BadLintConfig(_) => 14,
+        Error::BadMetadata(_) => 15,
+        Error::BadExportNames => 16,
+        Error::DuplicateExportName(_) => 17,
+        Error::ExportNameNotFunction(_, _) => 18,
+        Error::BadSourceMap(_) => 19,
+        Error::FeatureNotEnabled(_, _, _) => 20,
+        Error::AsmUnknownMnemonic(_, _) => 101,
+        Error::AsmUnknownDirective(_, _) => 102,
+        Error::AsmBadImmediate(_, _) => 103,
+        Error::AsmWrongImmediateCount(_, _, _, _) => 104,
+        Error::AsmBadString(_) => 105,
+        Error::AsmOutsideFunction(_) => 106,
+        Error::AsmDuplicateBody(_) => 107,
+        Error::AsmDuplicateLabel(_, _) => 108,
+        Error::AsmUnknownLabel(_, _) => 109,
+        Error::AsmBadMacro(_) => 110,
+        Error::AsmIncludeNotFound(_, _) => 111,
+        Error::AsmIncludeCycle(_, _) => 112,
+        Error::AsmMacroTooDeep(_, _) => 113,
+        // the error is in the included file, so it's that error's code
+        Error::AsmInInclude(_, e) => return code(e),
+        Error::LimitExceeded(_, _, _) => 201,
+        Error::OpcodeNotAllowed(_, _) => 202,
+        Error::RejectedByPass(_, _) => 203,
+        Error::VerificationCancelled => 204,
+        Error::VerificationTimedOut => 205,
+        Error::HostSignatureMismatch(_, _, _, _) => 206,
+        Error::ForwardDeclNotType(_) => 301,
+        Error::ForwardDeclRuntimeOp(_) => 302,
+        Error::ForwardDeclBadStack(_) => 303,
+        Error::TypeDeclNeedsSize(_) => 304,
+        Error::TypeDeclBadStack(_, _) => 305,
+        Error::TypeDeclSizeMismatch(_, _, _) => 306,
+        Error::TypeErrorEntryParam(_) => 307,
+        Error::TypeErrorNonEmptyQuantificationStack(_) => 308,
+        Error::UnknownGlobalFunc(_, _, _) => 309,
+        Error::UnknownImport(_, _) => 310,
+        Error::NoEntryFunction => 311,
+        Error::TypeErrorEmptyStack(_, _) => 401,
+        Error::TypeErrorEmptyCTStack(_, _) => 402,
+        Error::TypeErrorEmptyQuantificationStack(_, _) => 403,
+        Error::TypeError(_, _, _, _) => 404,
+        Error::KindError(_, _, _, _) => 405,
+        Error::KindErrorBadApp(_, _, _) => 406,
+        Error::SizeError(_, _, _, _) => 407,
+        Error::TypeErrorSpecificTypeVarExpected(_, _, _, _) => 408,
+        Error::TypeErrorTypeVarExpected(_, _, _, _) => 409,
+        Error::TypeErrorCTGetOutOfRange(_, _, _) => 410,
+        Error::TypeErrorGetOutOfRange(_, _, _) => 411,
+        Error::TypeErrorInitOutOfRange(_, _, _) => 412,
+        Error::TypeErrorProjOutOfRange(_, _, _) => 413,
+        Error::TypeErrorExistentialExpected(_, _, _) => 414,
+        Error::TypeErrorTupleExpected(_, _, _) => 415,
+        Error::TypeErrorFunctionExpected(_, _, _) => 416,
+        Error::TypeErrorRegionHandleExpected(_, _, _) => 417,
+        Error::TypeErrorPtrExpected(_, _, _) => 418,
+        Error::TypeErrorForallExpected(_, _, _) => 419,
+        Error::TypeErrorForallRegionExpected(_, _, _) => 420,
+        Error::TypeErrorArrayExpected(_, _, _) => 421,
+        Error::TypeErrorNamedTypeExpected(_, _, _) => 422,
+        Error::TypeErrorMallocNonTuple(_, _, _) => 423,
+        Error::TypeErrorInitTypeMismatch(_, _, _) => 424,
+        Error::TypeErrorDoubleInit(_, _, _) => 425,
+        Error::TypeErrorBadField(_, _, _, _) => 426,
+        Error::TypeErrorUninitializedRead(_, _, _) => 427,
+        Error::TypeErrorNotEnoughRuntimeArgs(_, _, _) => 428,
+        Error::TypeErrorCallArgMismatch(_, _, _, _, _) => 429,
+        Error::TypeErrorCallArgUninitialized(_, _, _, _) => 430,
+        Error::TypeErrorNotEnoughCTArgs(_, _, _) => 431,
+        Error::TooBigForStack(_, _, _) => 432,
+        Error::UnknownNamedType(_, _, _, _) => 433,
+        Error::TypeErrorAbstractType(_, _, _) => 434,
+        Error::DataSectionLoadOutOfBounds(_, _, _, _) => 435,
+        Error::InvalidDataSectionType(_, _, _) => 436,
+        Error::CannotMutateDataSection(_, _) => 437,
+        Error::UnknownChannel(_, _, _) => 438,
+        Error::DeclarationOpInBody(_, _) => 439,
+        Error::BadBigIntLiteral(_, _, _) => 440,
+        Error::TypeErrorBigIntExpected(_, _, _) => 441,
+        Error::TypeErrorBufferExpected(_, _, _) => 442,
+        Error::TypeErrorMapExpected(_, _, _) => 443,
+        Error::TypeErrorResourceExpected(_, _, _) => 444,
+        Error::RegionError(_, _, _, _) => 501,
+        Error::UniquenessError(_, _, _) => 502,
+        Error::RegionAccessError(_, _, _) => 503,
+        Error::ReadOnlyRegionError(_, _, _) => 504,
+        Error::TypeErrorCallRegionNotLive(_, _, _) => 505,
+        Error::TypeErrorCallRegionNotUnique(_, _, _) => 506,
+        Error::TypeErrorOwnsRegion(_, _, _) => 507,
+        Error::RegionPackageWithoutHandle(_, _, _) => 508,
+        Error::TypeErrorHandleCopy(_, _, _) => 509,
+    })
+}
+
+pub fn trap_code(t: &Trap) -> ErrorCode {
+    ErrorCode(match t {
+        Trap::Interrupted => 601,
+        Trap::UnknownHostFunction(_) => 602,
+        Trap::AsyncHostFunction(_) => 603,
+        Trap::UninitializedRead => 604,
+        Trap::OutOfBounds => 605,
+        Trap::CallbackHalted(_) => 606,
+        Trap::ReentrantHostCall(_) => 607,
+        Trap::HostSignature(_) => 608,
+        Trap::UseAfterFree => 609,
+        Trap::DoubleFree => 610,
+        Trap::Overflow => 611,
+        Trap::DivideByZero => 612,
+        Trap::Unverified(_) => 613,
+        Trap::RegionFull => 614,
+        Trap::StdinBusy => 615,
+        Trap::NotSuspended => 616,
+        Trap::ArgCount(_) => 617,
+        Trap::ForeignGuestFn => 618,
+    })
+}
+
+pub fn call_code(e: &CallError) -> ErrorCode {
+    match e {
+        CallError::UnknownExport(_) => ErrorCode(701),
+        CallError::NotCallable(_, _) => ErrorCode(702),
+        CallError::ArgMismatch(_, _, _) => ErrorCode(703),
+        CallError::Trap(trap) => trap_code(trap),
+    }
+}
+
+pub fn reload_code(e: &ReloadError) -> ErrorCode {
+    match e {
+        ReloadError::MissingExport(_) => ErrorCode(801),
+        ReloadError::ExportChanged(_, _, _) => ErrorCode(802),
+        ReloadError::EntryChanged(_, _) => ErrorCode(803),
+        ReloadError::Binding(e) => code(e),
+    }
+}
+
+/// What `sabervm explain` says about a code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Explanation {
+    pub code: ErrorCode,
+    /// The name of the `Error`, `Trap`, or `CallError` variant, which is also what `;; expect-error:` takes.
+    pub name: &'static str,
+    pub description: &'static str,
+    pub example: Option<Example>,
+}
+
+/// A test program (in assembly, with the same header comments as the examples) that fails with a code,
+/// and the same program fixed, which runs to a `halt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Example {
+    pub failing: &'static str,
+    pub fixed: &'static str,
+}
+
+pub fn explain(code: ErrorCode) -> Option<&'static Explanation> {
+    EXPLANATIONS.iter().find(|explanation| explanation.code == code)
+}
+
+const fn explanation(code: u16, name: &'static str, description: &'static str) -> Explanation {
+    Explanation {
+        code: ErrorCode(code),
+        name,
+        description,
+        example: None,
+    }
+}
+
+const fn example(
+    code: u16,
+    name: &'static str,
+    description: &'static str,
+    failing: &'static str,
+    fixed: &'static str,
+) -> Explanation {
+    Explanation {
+        code: ErrorCode(code),
+        name,
+        description,
+        example: Some(Example { failing, fixed }),
+    }
+}
+
+/// Every code, in order.
+pub const EXPLANATIONS: &[Explanation] = &[
+    explanation(
+        1,
+        "SyntaxErrorParamNeeded",
+        "The program ends in the middle of an op: the op takes an immediate, like the i32 after `lit`, \
+but the file ends before all of the immediate's bytes. The program was most likely cut short on its way here. \
+A program assembled with `.checksum` reports this as E0009 instead, which says so.",
+    ),
+    explanation(
+        2,
+        "SyntaxErrorUnknownOp",
+        "A byte where an op should start isn't any op's byte. `sabervm opcodes` lists them all. \
+Either the program was damaged, or it was made for a newer SaberVM with ops this one doesn't have, \
+or the compiler that wrote it counted an earlier op's immediate wrong, so everything after it is out of step.",
+    ),
+    example(
+        3,
+        "SyntaxErrorLabelOutOfRange",
+        "`global_func n` names function n, but the program doesn't have that many functions. \
+Functions are numbered from 0 in the order they're declared.",
+        "\
+.func
+    func 0
+    lced
+.body
+    global_func 1
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    global_func 0
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        4,
+        "SyntaxErrorFieldCount",
+        "`tuple_fields n` has to be followed by exactly n `field` ops, one describing each component of the tuple, \
+but fewer follow it. Use plain `tuple n` for a tuple whose components are all the default.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    u8
+    tuple_fields 2
+    field 1
+    malloc
+    u8_lit 3
+    init 1
+    proj 1
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    u8
+    tuple_fields 2
+    field 1
+    field 0
+    malloc
+    u8_lit 3
+    init 1
+    proj 1
+    halt
+",
+    ),
+    example(
+        5,
+        "SyntaxErrorStrayField",
+        "A `field` op describes a component of the tuple the `tuple_fields` just before it makes, \
+so it can't come anywhere else. A plain `tuple` doesn't take field descriptors.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    tuple 1
+    field 1
+    malloc
+    u8_lit 3
+    init 0
+    proj 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    tuple_fields 1
+    field 1
+    malloc
+    u8_lit 3
+    init 0
+    proj 0
+    halt
+",
+    ),
+    explanation(
+        6,
+        "UnexpectedEOF",
+        "The program ends before it's complete: it's missing part of its header, its data section, or a function it said it has. \
+The program was most likely cut short, or the compiler that wrote it got one of the lengths or counts in it wrong.",
+    ),
+    example(
+        7,
+        "UnsupportedFeature",
+        "The program's feature header says it needs a part of the instruction set that this build of SaberVM doesn't have. \
+The feature might not be implemented yet, like floats, threads, and exceptions, or might need a newer SaberVM.",
+        "\
+.features 1
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        8,
+        "UnknownFeatureBits",
+        "The program's feature header has bits set that don't stand for any feature this version of SaberVM knows about, \
+so it was probably made for a newer one. `Feature` in header.rs lists the bits.",
+        "\
+.features 0x10000
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    explanation(
+        9,
+        "ChecksumMismatch",
+        "The program's header has a checksum of the rest of the program (`.checksum` in assembly), \
+and the program doesn't match it anymore, so it was cut short or damaged after it was written. \
+Rebuild it, or copy it again from wherever it came from.",
+    ),
+    explanation(
+        10,
+        "BodyCountMismatch",
+        "A program with sized bodies (`.sized_bodies`) lists the size of each function body in its header, \
+and the list has a different number of entries than there are functions with bodies. \
+This is a bug in whatever wrote the program.",
+    ),
+    explanation(
+        11,
+        "BodySizeMismatch",
+        "A program with sized bodies (`.sized_bodies`) lists the size of each function body in its header, \
+and a body doesn't end where its size says it should, at the end of an op. \
+This is a bug in whatever wrote the program, or the program was damaged.",
+    ),
+    explanation(
+        12,
+        "OpsAfterSizedBodies",
+        "A program with sized bodies (`.sized_bodies`) has to end with its last function body, \
+so the bodies can be found from the end of the file, but there are ops after it. \
+Without sized bodies, those ops would only be a warning (the `trailing-ops` lint), since they can never run.",
+    ),
+    explanation(
+        13,
+        "UnknownLint",
+        "A lint config names a lint or a lint level that doesn't exist. \
+The levels are `allow`, `warn`, and `deny`, and `warnings` stands for every lint. The lints are listed in lint.rs. \
+The assembler checks `.lint` lines itself, so this comes from other compilers' programs, or from `check --allow` and the like.",
+    ),
+    explanation(
+        14,
+        "BadLintConfig",
+        "A line of a lint config isn't a level followed by a lint, like `deny unreachable-function`. \
+Each line sets one lint, and `#` starts a comment.",
+    ),
+    explanation(
+        15,
+        "BadMetadata",
+        "A line of the program's metadata isn't a key and a value, or its key isn't one of `name`, `producer`, and `version`. \
+The assembler checks `.meta` lines itself, so this comes from other compilers' programs.",
+    ),
+    explanation(
+        16,
+        "BadExportNames",
+        "The export names in the program's header run past the end of their section, or one of them isn't UTF-8. \
+This is a bug in whatever wrote the program, or the program was damaged.",
+    ),
+    example(
+        17,
+        "DuplicateExportName",
+        "Two functions in the module are exported under the same name, so the host can't tell which one it's calling. \
+This is checked across all the programs in a module, not just within one.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func
+.export_name \"f\"
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+
+.func
+.export_name \"f\"
+    u8
+    func 1
+    lced
+.body
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func
+.export_name \"f\"
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+
+.func
+.export_name \"g\"
+    u8
+    func 1
+    lced
+.body
+    halt
+",
+    ),
+    explanation(
+        18,
+        "ExportNameNotFunction",
+        "An export name is given to a label that isn't a function with a body in the same program, \
+like an imported function, so there's nowhere for a call to start. Give the name to the function that has the body.",
+    ),
+    explanation(
+        19,
+        "BadSourceMap",
+        "A line of the source map next to a program (`prog.svm.map`, read by `sabervm check --format sarif`) \
+isn't a marker number, a file, a line, and maybe a column, like `3 main.sbr 12 5`. `#` starts a comment.",
+    ),
+    example(
+        20,
+        "FeatureNotEnabled",
+        "The op is from an optional part of the instruction set, like the `big_` ops of big integers, \
+which a program has to ask for in its feature header. `.features 0x200` asks for big integers.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    lit 7
+    big_from_i32
+    big_to_i32
+    i32_to_u8
+    halt
+",
+        "\
+.features 0x200
+
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    lit 7
+    big_from_i32
+    big_to_i32
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        101,
+        "AsmUnknownMnemonic",
+        "A line of assembly starts with a word that isn't an op's mnemonic or a macro defined before that line. \
+`sabervm opcodes` lists the mnemonics.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit_u8 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        102,
+        "AsmUnknownDirective",
+        "A line of assembly starts with a `.` but isn't one of the directives, or doesn't have the arguments the directive takes. \
+The directives are listed at the top of asm.rs.",
+        "\
+.function
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        103,
+        "AsmBadImmediate",
+        "An op's immediate isn't a number that fits in the immediate's type, like 300 for a u8, \
+or a string of at most 16 bytes for `import` and `export`. Numbers can be decimal, `0x` hex, or `0b` binary.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 300
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 30
+    halt
+",
+    ),
+    example(
+        104,
+        "AsmWrongImmediateCount",
+        "An op is written with a different number of immediates than it takes. \
+Every op takes either none or one; `sabervm opcodes` shows which.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        105,
+        "AsmBadString",
+        "A string in assembly isn't closed, or has an escape other than `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\\"`, or `\\xNN`.",
+        "\
+.data \"hi
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.data \"hi\"
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        106,
+        "AsmOutsideFunction",
+        "An op, `.body`, or `.export_name` comes before the first `.func`, so it isn't in any function.",
+        "\
+    u8_lit 0
+
+.func
+    func 0
+    lced
+.body
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        107,
+        "AsmDuplicateBody",
+        "A function has two `.body` lines. Everything from `.func` to `.body` is the function's forward declaration, \
+and everything after `.body` up to the next `.func` is its body.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+.body
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        108,
+        "AsmDuplicateLabel",
+        "Two functions are given the same `@name`, so `global_func @name` and `call @name` couldn't tell them apart.",
+        "\
+.func @main
+    func 0
+    lced
+.body
+    call @main
+
+.func @main
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func @main
+    func 0
+    lced
+.body
+    call @done
+
+.func @done
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        109,
+        "AsmUnknownLabel",
+        "`global_func @name` or `call @name` names a function that no `.func @name` in the file (or its includes) defines.",
+        "\
+.func @main
+    func 0
+    lced
+.body
+    call @dnoe
+
+.func @done
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func @main
+    func 0
+    lced
+.body
+    call @done
+
+.func @done
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        110,
+        "AsmBadMacro",
+        "A `.macro` doesn't have a name, or has no `.endm` after it, or there's an `.endm` that doesn't end a macro.",
+        "\
+.macro zero
+    u8_lit 0
+
+.func
+    func 0
+    lced
+.body
+    zero
+    halt
+",
+        "\
+.macro zero
+    u8_lit 0
+.endm
+
+.func
+    func 0
+    lced
+.body
+    zero
+    halt
+",
+    ),
+    explanation(
+        111,
+        "AsmIncludeNotFound",
+        "An `.include` names a file that can't be read. The path is relative to the file with the `.include` in it, \
+not to where the assembler was run.",
+    ),
+    explanation(
+        112,
+        "AsmIncludeCycle",
+        "An `.include` names a file that's already being included, further up, so including it would never end. \
+Move what both files need into a third file that each of them includes.",
+    ),
+    example(
+        113,
+        "AsmMacroTooDeep",
+        "Using a macro expands into more macros too many times over, which almost always means the macro uses itself.",
+        "\
+.macro zero
+    zero
+.endm
+
+.func
+    func 0
+    lced
+.body
+    zero
+    halt
+",
+        "\
+.macro zero
+    u8_lit 0
+.endm
+
+.func
+    func 0
+    lced
+.body
+    zero
+    halt
+",
+    ),
+    explanation(
+        201,
+        "LimitExceeded",
+        "The program is bigger than one of the limits the host set in its `Limits`, like the most functions a module can have \
+or the longest a function body can be. The message says which limit. The host sets these so that a hostile program \
+can't make verification take too long or use too much memory, so either split the program up or ask for higher limits.",
+    ),
+    explanation(
+        202,
+        "OpcodeNotAllowed",
+        "The program uses an op that the host turned off with `Config::opcodes`, like a sandbox that doesn't allow `read` and `write`. \
+The op can't be used with this host.",
+    ),
+    explanation(
+        203,
+        "RejectedByPass",
+        "One of the host's own checks (a `VerifyPass`) rejected the program after it verified. \
+The message has the pass's name and its reason, like `max-allocation` rejecting an allocation it thinks is too big.",
+    ),
+    explanation(
+        204,
+        "VerificationCancelled",
+        "The host cancelled verification with its `Cancellation` before it finished. The program itself might be fine.",
+    ),
+    explanation(
+        205,
+        "VerificationTimedOut",
+        "Verification took longer than the deadline the host gave its `Cancellation`. The program itself might be fine, \
+but verifying it takes too long for this host.",
+    ),
+    explanation(
+        206,
+        "HostSignatureMismatch",
+        "A host function registered with Rust argument types (`Instance::register_binding`) takes different arguments \
+than what a `host_call` of its index has on the stack, types the verifier worked out before the program ran. \
+Either the program calls the wrong host function or pushes the wrong things before calling it.",
+    ),
+    example(
+        301,
+        "ForwardDeclNotType",
+        "A function's forward declaration has to make the function's type: a `func` type, maybe under `all` and `rgn` quantifiers. \
+This one makes some other type instead.",
+        "\
+.func
+    i32
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        302,
+        "ForwardDeclRuntimeOp",
+        "A function's forward declaration can only use the ops that build types, the ones that work on the compile-time stack. \
+This one uses an op that does something at runtime, which belongs in the function's body.",
+        "\
+.func
+    lit 0
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        303,
+        "ForwardDeclBadStack",
+        "A function's forward declaration has to leave exactly one thing on the compile-time stack, the function's type, \
+but this one leaves something else, or more than one thing. Often a `func n` takes fewer parameters than were pushed.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 1
+    lit 2
+    call @add
+
+.func @add
+    i32
+    i32
+    func 1
+    lced
+.body
+    add
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 1
+    lit 2
+    call @add
+
+.func @add
+    i32
+    i32
+    func 2
+    lced
+.body
+    add
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        304,
+        "TypeDeclNeedsSize",
+        "A type declaration has to start with `size n`, the size of the type in bytes, \
+so other declarations can use the type before its definition is checked.",
+        "\
+.type
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+        "\
+.type
+    size 4
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        305,
+        "TypeDeclBadStack",
+        "A type declaration has to leave its size and then its definition on the compile-time stack, \
+or only its size for an abstract or imported type, but this one leaves something else.",
+        "\
+.type
+    size 4
+    i32
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+        "\
+.type
+    size 4
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        306,
+        "TypeDeclSizeMismatch",
+        "A type declaration's `size` doesn't match the size of its definition. An i32 is 4 bytes, a u8 is 1, \
+a pointer or an array is 8, a handle is 8, a function is 8, and a tuple is the sum of its components.",
+        "\
+.type
+    size 8
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+        "\
+.type
+    size 4
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        307,
+        "TypeErrorEntryParam",
+        "The entry function, the first one in the program, can only take i32s, \
+since those are all the host can pass it when the program starts (see `Instance::run_with_args`).",
+        "\
+;; call: main 7
+
+.func
+.export_name \"main\"
+    u8
+    func 1
+    lced
+.body
+    halt
+",
+        "\
+;; call: main 7
+
+.func
+.export_name \"main\"
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        308,
+        "TypeErrorNonEmptyQuantificationStack",
+        "A function body starts a quantifier with `all`, `some`, `rgn`, or `some_rgn`, but never finishes it with `end`.",
+        "\
+.func
+    func 0
+    lced
+.body
+    size 4
+    all
+    ctget 0
+    func 1
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    size 4
+    all
+    ctget 0
+    func 1
+    end
+    u8_lit 0
+    halt
+",
+    ),
+    explanation(
+        309,
+        "UnknownGlobalFunc",
+        "`global_func n` names a function whose type the verifier doesn't have, because it hasn't seen \
+the function's forward declaration. This can only happen when bodies are checked apart from their declarations, \
+and is a bug in whatever did that.",
+    ),
+    example(
+        310,
+        "UnknownImport",
+        "A function is declared with `import`, but no program in the module exports a function by that name, \
+so there's nothing to link it to. Every program in a module can import what any of them exports, itself included, \
+so the fix is usually to add the program that exports it, or to spell the name the way the export does.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 7
+    halt
+
+.func
+    func 0
+    import \"helper\"
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 7
+    halt
+
+.func
+    func 0
+    export \"helper\"
+.body
+    u8_lit 7
+    halt
+
+.func
+    func 0
+    import \"helper\"
+",
+    ),
+    example(
+        311,
+        "NoEntryFunction",
+        "A module starts running at the first function body of its first program, and that program doesn't have any, \
+like one that only imports. Put a program with a body first.",
+        "\
+.func
+    func 0
+    import \"main\"
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        401,
+        "TypeErrorEmptyStack",
+        "An op needs a value from the stack, but there's nothing on the stack there. \
+Every path into the op has to have pushed everything the op uses.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 1
+    add
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 1
+    lit 2
+    add
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        402,
+        "TypeErrorEmptyCTStack",
+        "An op needs something from the compile-time stack, like a type or a region, but there's nothing there. \
+A `tuple n` or `func n` needs n types, and `ptr`, `arr`, and `handle` need a region under their type.",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 2
+    malloc
+    lit 7
+    init 1
+    proj 1
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    i32
+    tuple 2
+    malloc
+    lit 7
+    init 1
+    proj 1
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        403,
+        "TypeErrorEmptyQuantificationStack",
+        "An `end` doesn't finish any quantifier, since there's no `all`, `some`, `rgn`, or `some_rgn` before it that's still open. \
+A type without quantifiers doesn't need an `end`.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    i32
+    func 1
+    end
+    lced
+.body
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        404,
+        "TypeError",
+        "An op needs a value of one type, but finds another on the stack. The message says which type it wanted. \
+For example, `halt` takes a u8, so an i32 has to be converted with `i32_to_u8` first.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 7
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 7
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        405,
+        "KindError",
+        "An op needs one kind of thing from the compile-time stack, like a type, a region, or a size, but finds another there. \
+For example, `handle` makes the type of a region's handle, so it needs a region, not a type.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    rgn
+    i32
+    handle
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        406,
+        "KindErrorBadApp",
+        "`app` instantiates a polymorphic function on the stack with what's on top of the compile-time stack, \
+so that has to be a type or a region. It can't be a size.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    global_func @id
+    size 4
+    app
+    call
+
+; forall a: 4. (a) -> 0
+.func @id
+    size 4
+    all
+    ctget 0
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    global_func @id
+    i32
+    app
+    call
+
+; forall a: 4. (a) -> 0
+.func @id
+    size 4
+    all
+    ctget 0
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        407,
+        "SizeError",
+        "A type variable stands for a type of a certain size, given when it's bound, like `size 4` and `all`, \
+so only a type of that size can instantiate it. An i32 is 4 bytes and a u8 is 1.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 5
+    global_func @id
+    u8
+    app
+    call
+
+; forall a: 4. (a) -> 0
+.func @id
+    size 4
+    all
+    ctget 0
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    global_func @id
+    i32
+    app
+    call
+
+; forall a: 4. (a) -> 0
+.func @id
+    size 4
+    all
+    ctget 0
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        408,
+        "TypeErrorSpecificTypeVarExpected",
+        "An `end` finishes the innermost open quantifier, so under the type it's quantifying, \
+the compile-time stack has to have that quantifier's variable. Here it has a different variable, \
+usually because an op like `func n` took the variable that should have stayed.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    size 4
+    all
+    size 4
+    all
+    ctget 1
+    func 2
+    end
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    size 4
+    all
+    size 4
+    all
+    ctget 1
+    ctget 1
+    func 2
+    end
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        409,
+        "TypeErrorTypeVarExpected",
+        "An `end` finishes the innermost open quantifier, so under the type it's quantifying, \
+the compile-time stack has to have that quantifier's variable. Here it has some other type, which was left there.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    size 4
+    all
+    i32
+    ctget 1
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    size 4
+    all
+    i32
+    ctget 1
+    func 2
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        410,
+        "TypeErrorCTGetOutOfRange",
+        "`ctget i` copies the thing i from the top of the compile-time stack, counting from 0, but the stack isn't that deep. \
+The message shows what's on it.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    rgn
+    ctget 1
+    handle
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        411,
+        "TypeErrorGetOutOfRange",
+        "`get i` and `share i` copy the value i from the top of the stack, counting from 0, but the stack isn't that deep.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 1
+    get 1
+    add
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 1
+    get 0
+    add
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        412,
+        "TypeErrorInitOutOfRange",
+        "`init i` sets component i of a tuple, counting from 0, but the tuple doesn't have that many components.",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    lit 7
+    init 1
+    proj 0
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    lit 7
+    init 0
+    proj 0
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        413,
+        "TypeErrorProjOutOfRange",
+        "`proj i` reads component i of a tuple, counting from 0, but the tuple doesn't have that many components.",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    lit 7
+    init 0
+    proj 1
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    lit 7
+    init 0
+    proj 0
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        414,
+        "TypeErrorExistentialExpected",
+        "The op works on existential types, made with `some` (or `some_rgn`) and `end`, but found another type. \
+`pack` needs one on the compile-time stack, under the type it hides, and `unpack` needs a value of one on the stack.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    i32
+    i32
+    pack
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    size 4
+    some
+    ctget 0
+    end
+    i32
+    pack
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        415,
+        "TypeErrorTupleExpected",
+        "The op works on tuples, or pointers to them, like `init` and `proj`, but found another type.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 1
+    lit 2
+    init 0
+    proj 0
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    lit 2
+    init 0
+    proj 0
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        416,
+        "TypeErrorFunctionExpected",
+        "`call` and `call_nz` call the function on top of the stack, with its arguments under it, \
+but the top of the stack isn't a function, or it's a polymorphic function that needs `app` or compile-time arguments first.",
+        "\
+.func
+    func 0
+    lced
+.body
+    global_func @f
+    lit 5
+    call
+
+.func @f
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    global_func @f
+    call
+
+.func @f
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        417,
+        "TypeErrorRegionHandleExpected",
+        "The op needs a region's handle on the stack, the value `new_rgn` pushes, but found another type. \
+`free_rgn` takes the handle of the region to free, and `malloc` takes the handle of the region to allocate in.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    lit 7
+    free_rgn
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 7
+    new_rgn 64
+    free_rgn
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        418,
+        "TypeErrorPtrExpected",
+        "`deref` reads the value behind a pointer, but the top of the stack isn't a pointer. \
+`malloc` only makes a pointer when its type is a `ptr` type in a region; otherwise it makes a tuple on the stack.",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    lit 7
+    init 0
+    deref
+    proj 0
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    i32
+    tuple 1
+    ptr
+    malloc
+    lit 7
+    init 0
+    deref
+    proj 0
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        419,
+        "TypeErrorForallExpected",
+        "`app` with a type instantiates a function that's polymorphic over a type (made with `all`), \
+but the top of the stack is something else, like a function that isn't polymorphic.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    global_func @f
+    i32
+    app
+    call
+
+.func @f
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    global_func @f
+    call
+
+.func @f
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        420,
+        "TypeErrorForallRegionExpected",
+        "`app` with a region instantiates a function that's polymorphic over a region (made with `rgn`), \
+but the top of the stack is something else. Here `new_rgn` pushed its handle on top of the function.",
+        "\
+.func
+    func 0
+    lced
+.body
+    global_func @f
+    new_rgn 64
+    app
+    call
+
+; forall r. (handle r) -> 0
+.func @f
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    global_func @f
+    app
+    call
+
+; forall r. (handle r) -> 0
+.func @f
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        421,
+        "TypeErrorArrayExpected",
+        "The op works on arrays, like `arr_proj`, `arr_mut`, and `copy_n`, but found another type. \
+`data` with an i32 type makes a pointer into the data section; it takes an `arr` type to make an array.",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    i32
+    data 0
+    lit 0
+    arr_proj
+    halt
+",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    data_sec
+    u8
+    arr
+    data 0
+    lit 0
+    arr_proj
+    halt
+",
+    ),
+    example(
+        422,
+        "TypeErrorNamedTypeExpected",
+        "`unfold` gives the definition of a named type's value, so it needs a value of a named type, one made with `fold`.",
+        "\
+.type
+    size 4
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    unfold
+    i32_to_u8
+    halt
+",
+        "\
+.type
+    size 4
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        423,
+        "TypeErrorMallocNonTuple",
+        "`malloc` with a `ptr` type allocates a tuple in the pointer's region, so the type behind the pointer has to be a tuple. \
+A single value goes in a tuple with one component.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    i32
+    ptr
+    malloc
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    i32
+    tuple 1
+    ptr
+    malloc
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        424,
+        "TypeErrorInitTypeMismatch",
+        "`init i` sets component i of a tuple to the value on top of the stack, which has to have the component's type.",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    u8_lit 7
+    init 0
+    proj 0
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    lit 7
+    init 0
+    proj 0
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        425,
+        "TypeErrorDoubleInit",
+        "A component of a tuple can only be initialized once, unless it's marked mutable with `tuple_fields` and `field 1`.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    tuple 1
+    malloc
+    u8_lit 1
+    init 0
+    u8_lit 7
+    init 0
+    proj 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    tuple_fields 1
+    field 1
+    malloc
+    u8_lit 1
+    init 0
+    u8_lit 7
+    init 0
+    proj 0
+    halt
+",
+    ),
+    example(
+        426,
+        "TypeErrorBadField",
+        "A `field` descriptor has bits set that don't mean anything yet. Bit 0 marks the component mutable, \
+bits 1 and 2 are its representation, where only 0 (stored flat in the tuple) is defined so far, and the rest are reserved.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    tuple_fields 1
+    field 2
+    malloc
+    u8_lit 7
+    init 0
+    proj 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    tuple_fields 1
+    field 0
+    malloc
+    u8_lit 7
+    init 0
+    proj 0
+    halt
+",
+    ),
+    example(
+        427,
+        "TypeErrorUninitializedRead",
+        "`proj i` reads component i of a tuple, but on some path to it the component hasn't been initialized with `init`.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    tuple 1
+    malloc
+    proj 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8
+    tuple 1
+    malloc
+    u8_lit 7
+    init 0
+    proj 0
+    halt
+",
+    ),
+    example(
+        428,
+        "TypeErrorNotEnoughRuntimeArgs",
+        "A call needs the function's arguments under the function on the stack, but there are fewer values there than it takes.",
+        "\
+.func
+    func 0
+    lced
+.body
+    call @f
+
+.func @f
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    call @f
+
+.func @f
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        429,
+        "TypeErrorCallArgMismatch",
+        "An argument to a call has a different type than the function takes there. \
+Arguments are counted from the top of the stack, so argument 0 is the one just under the function.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 5
+    call @f
+
+.func @f
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    call @f
+
+.func @f
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        430,
+        "TypeErrorCallArgUninitialized",
+        "An argument to a call is a tuple of the right type, but not every component of it has been initialized, \
+and the function expects them all to be.",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    call @f
+
+.func @f
+    i32
+    tuple 1
+    func 1
+    lced
+.body
+    proj 0
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    malloc
+    lit 5
+    init 0
+    call @f
+
+.func @f
+    i32
+    tuple 1
+    func 1
+    lced
+.body
+    proj 0
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        431,
+        "TypeErrorNotEnoughCTArgs",
+        "A call to a polymorphic function takes a type or region for each of the function's quantifiers \
+from the compile-time stack, but the compile-time stack ran out.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    call @id
+
+; forall a: 4. (a) -> 0
+.func @id
+    size 4
+    all
+    ctget 0
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 5
+    i32
+    call @id
+
+; forall a: 4. (a) -> 0
+.func @id
+    size 4
+    all
+    ctget 0
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        432,
+        "TooBigForStack",
+        "A value can be at most 4096 bytes to go on the stack, with `malloc` of a tuple type or with `deref`. \
+Anything bigger has to go in a region and be used through a pointer.",
+        "\
+.macro double
+    ctget 0
+    tuple 2
+.endm
+
+.func
+    func 0
+    lced
+.body
+    i32
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    malloc
+    u8_lit 0
+    halt
+",
+        "\
+.macro double
+    ctget 0
+    tuple 2
+.endm
+
+.func
+    func 0
+    lced
+.body
+    new_rgn 16384
+    i32
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    double
+    ptr
+    malloc
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        433,
+        "UnknownNamedType",
+        "`named k`, `fold k`, or `unfold` uses named type k, but the program doesn't declare that many types. \
+Types are numbered from 0 in the order of their declarations.",
+        "\
+.type
+    size 4
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 1
+    unfold
+    i32_to_u8
+    halt
+",
+        "\
+.type
+    size 4
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        434,
+        "TypeErrorAbstractType",
+        "`fold` and `unfold` go between a named type and its definition, but this named type has none in the program: \
+it's abstract, or imported from another program, which is the only place its values can be made or looked into.",
+        "\
+.type
+    size 4
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+        "\
+.type
+    size 4
+    i32
+    lced
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    fold 0
+    unfold
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        435,
+        "DataSectionLoadOutOfBounds",
+        "`data n` with a type points at the bytes from n on in the data section, \
+but the data section ends before the end of the type.",
+        "\
+.data \"ab\"
+
+.func
+    func 0
+    lced
+.body
+    i32
+    data 0
+    deref
+    i32_to_u8
+    halt
+",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    i32
+    data 0
+    deref
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        436,
+        "InvalidDataSectionType",
+        "`data` can only point at i32s, arrays in the data section, and tuples of those. A single u8 has to be read as an array.",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    u8
+    data 0
+    deref
+    halt
+",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    data_sec
+    u8
+    arr
+    data 0
+    lit 0
+    arr_proj
+    halt
+",
+    ),
+    example(
+        437,
+        "CannotMutateDataSection",
+        "The data section is read-only, so `arr_mut` and `copy_n` can't write to an array in it. \
+Copy the data into an array in a region first.",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    data_sec
+    u8
+    arr
+    data 0
+    u8_lit 66
+    lit 0
+    arr_mut
+    lit 0
+    arr_proj
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    share 0
+    lit 4
+    u8
+    arr
+    malloc
+    u8_lit 66
+    lit 0
+    arr_mut
+    lit 0
+    arr_proj
+    halt
+",
+    ),
+    explanation(
+        438,
+        "UnknownChannel",
+        "`read` and `write` take the channel to use as their immediate, and only channel 0, the console, exists so far.",
+    ),
+    example(
+        439,
+        "DeclarationOpInBody",
+        "`lced`, `export`, and `import` end a forward declaration, so they can't be in a function body, which only ends \
+at its first `call`, `call_nz`, or `halt`.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 7
+    lced
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 7
+    halt
+",
+    ),
+    example(
+        440,
+        "BadBigIntLiteral",
+        "`big_data` uses the big integer literal at its offset in the data section, so the bytes there have to be one: \
+a sign of 0 or 1, a count of limbs, and that many limbs, with no zero limb on top. `.bigint` writes one.",
+        "\
+.features 0x200
+.data 2 0 0 0 0 0 0 0
+
+.func
+    func 0
+    lced
+.body
+    big_data 0
+    big_to_i32
+    i32_to_u8
+    halt
+",
+        "\
+.features 0x200
+.bigint 7
+
+.func
+    func 0
+    lced
+.body
+    big_data 0
+    big_to_i32
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        441,
+        "TypeErrorBigIntExpected",
+        "The op works on big integers, like `big_add` and `big_to_i32`, but found another type. \
+`big_from_i32` makes a big integer out of an i32.",
+        "\
+.features 0x200
+
+.func
+    func 0
+    lced
+.body
+    lit 7
+    big_to_i32
+    i32_to_u8
+    halt
+",
+        "\
+.features 0x200
+
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    lit 7
+    big_from_i32
+    big_to_i32
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        442,
+        "TypeErrorBufferExpected",
+        "The op works on buffers, like `buf_len` and `buf_get_u8`, but found another type. \
+A `u8` array isn't a buffer, even though they're laid out the same; `buf_new` makes one.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    lit 4
+    u8
+    arr
+    malloc
+    buf_len
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    lit 4
+    buf_new
+    buf_len
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        443,
+        "TypeErrorMapExpected",
+        "The op works on maps, like `map_len` and `map_get`, but found another type. \
+`malloc` of a `map` type makes an empty one, in the region of the handle it's given.",
+        "\
+.features 0x400
+
+.func
+    func 0
+    lced
+.body
+    new_rgn 1024
+    lit 8
+    buf_new
+    map_len
+    i32_to_u8
+    halt
+",
+        "\
+.features 0x400
+
+.func
+    func 0
+    lced
+.body
+    new_rgn 1024
+    i32
+    map
+    malloc
+    map_len
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        444,
+        "TypeErrorResourceExpected",
+        "`host_res` makes a resource of the type on top of the compile-time stack, which has to be a resource type, \
+made with `res` and the kind of resource. The host function it calls has to make that kind (see `Instance::register_resource_fn`). \
+Tests have host function 128, which makes resources of kind 1, and 129, which reads their ids.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    i32
+    lit 5
+    host_res 128
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    res 1
+    lit 5
+    host_res 128
+    lit 0
+    host_call 129
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        501,
+        "RegionError",
+        "Two regions that have to be the same aren't. Here `malloc` allocates a pointer type in one region, \
+but the handle on top of the stack belongs to another.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    new_rgn 64
+    ctget 1
+    i32
+    tuple 1
+    ptr
+    malloc
+    lit 3
+    init 0
+    proj 0
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    new_rgn 64
+    ctget 1
+    i32
+    tuple 1
+    ptr
+    share 1
+    malloc
+    lit 3
+    init 0
+    proj 0
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        502,
+        "UniquenessError",
+        "Freeing a region (or packing it away) needs the region to be unique, so nothing else can still be using it. \
+A function only gets to free a region it's given if the region variable is marked `unique`.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    call @consume
+
+; forall r. (handle r) -> 0
+.func @consume
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    free_rgn
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    call @consume
+
+; forall unique r. (handle r) -> 0
+.func @consume
+    unique
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    free_rgn
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        503,
+        "RegionAccessError",
+        "The op uses a region that isn't live there: it was freed, or packed away, or the function isn't quantified over it.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    share 0
+    free_rgn
+    i32
+    tuple 1
+    ptr
+    malloc
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    share 0
+    i32
+    tuple 1
+    ptr
+    malloc
+    share 1
+    free_rgn
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        504,
+        "ReadOnlyRegionError",
+        "The data section is read-only, so it can't be allocated in or freed, and there's no handle to it. \
+Reading a component through a pointer into it with `proj` isn't allowed either; `deref` the pointer first.",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    data 0
+    proj 0
+    i32_to_u8
+    halt
+",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    i32
+    tuple 1
+    data 0
+    deref
+    proj 0
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        505,
+        "TypeErrorCallRegionNotLive",
+        "A function that's polymorphic over a region gets to use whichever region it's called with, \
+so that region has to be live at the call, not already freed.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    share 0
+    free_rgn
+    call @f
+
+; forall r. (handle r) -> 0
+.func @f
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    call @f
+
+; forall r. (handle r) -> 0
+.func @f
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        506,
+        "TypeErrorCallRegionNotUnique",
+        "A function that's polymorphic over a `unique` region can free it, so the region it's called with has to be unique \
+where it's called. A region a function was given without `unique` might still be used by its caller.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    call @pass
+
+; forall r. (handle r) -> 0
+.func @pass
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    call @consume
+
+; forall unique r. (handle r) -> 0
+.func @consume
+    unique
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    free_rgn
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    call @pass
+
+; forall unique r. (handle r) -> 0
+.func @pass
+    unique
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    call @consume
+
+; forall unique r. (handle r) -> 0
+.func @consume
+    unique
+    rgn
+    ctget 0
+    handle
+    func 1
+    end
+    lced
+.body
+    free_rgn
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        507,
+        "TypeErrorOwnsRegion",
+        "A region package (see `some_rgn`) owns its region, so it can only be moved. Copying it with `get`, putting it \
+behind a pointer, or using it as a type argument would give the region two owners, which could both free it.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    rgn
+    some_rgn
+    ctget 0
+    handle
+    end
+    ptr
+    func 1
+    end
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    some_rgn
+    ctget 0
+    handle
+    end
+    func 1
+    lced
+.body
+    unpack
+    free_rgn
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        508,
+        "RegionPackageWithoutHandle",
+        "A region package (see `some_rgn`) owns its region, so whoever opens it has to be able to free it, \
+which means the package has to hold the region's handle.",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    some_rgn
+    i32
+    end
+    func 1
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func @f
+    some_rgn
+    ctget 0
+    handle
+    end
+    func 1
+    lced
+.body
+    unpack
+    free_rgn
+    u8_lit 0
+    halt
+",
+    ),
+    example(
+        509,
+        "TypeErrorHandleCopy",
+        "A region's handle can only be moved, so that every copy of it is spelled out: `get` won't copy a value holding one, \
+and it can't be put in memory or used as a type argument. Use `share` instead of `get` when the copy is meant to be there.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    get 0
+    free_rgn
+    u8_lit 0
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    share 0
+    free_rgn
+    u8_lit 0
+    halt
+",
+    ),
+    explanation(
+        601,
+        "Interrupted",
+        "The host stopped the program while it was running, with `Instance::interrupt`, usually because it ran too long.",
+    ),
+    example(
+        602,
+        "UnknownHostFunction",
+        "The program did a `host_call` of a host function that the host doesn't provide. \
+The standard ones, from 0x100 up, are only there if the host allows them (see `host::STD_PROFILE`).",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 100
+    host_call 9999
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 100
+    host_call 258
+    i32_to_u8
+    halt
+",
+    ),
+    explanation(
+        603,
+        "AsyncHostFunction",
+        "The program called a host function that's async, but was started with `run`, which can't wait for it. \
+Start it with `run_async` instead.",
+    ),
+    explanation(
+        604,
+        "UninitializedRead",
+        "In paranoid mode (`--paranoid`), the program read memory it never initialized. \
+The verifier should have made that impossible, so this is a bug in SaberVM. Please report it, with the program.",
+    ),
+    example(
+        605,
+        "OutOfBounds",
+        "An array index, or the length given to `copy_n`, was outside the array. \
+Array lengths are only known at runtime, so this is checked then.",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    data_sec
+    u8
+    arr
+    data 0
+    lit 4
+    arr_proj
+    halt
+",
+        "\
+.data \"abcd\"
+
+.func
+    func 0
+    lced
+.body
+    data_sec
+    u8
+    arr
+    data 0
+    lit 3
+    arr_proj
+    halt
+",
+    ),
+    explanation(
+        606,
+        "CallbackHalted",
+        "A host function called back into a function of the program (with `Guest::call`), and that function halted, \
+instead of giving the host a result with `yield`. That stops the whole run once the host function returns.",
+    ),
+    explanation(
+        607,
+        "ReentrantHostCall",
+        "A host function called back into the program, and the program called the same host function again \
+while the first call was still running, which host functions don't support.",
+    ),
+    explanation(
+        608,
+        "HostSignature",
+        "The values on the stack at a `host_call` didn't match the arguments of the Rust host function behind it \
+(see `Instance::register_native_host_fn`). Registering it with `register_binding` instead catches this before the program runs. \
+It's also a `host_res` of a host function that doesn't make that kind of resource, or a `host_call` of one that makes resources, \
+so a program can't make a resource the host didn't.",
+    ),
+    explanation(
+        609,
+        "UseAfterFree",
+        "In checked mode (`--checked`), the program used a region, or a pointer into one, after freeing it. \
+The verifier should have made that impossible, so this is a bug in SaberVM. Please report it, with the program.",
+    ),
+    explanation(
+        610,
+        "DoubleFree",
+        "In checked mode (`--checked`), the program freed a region that was already freed. \
+The verifier should have made that impossible, so this is a bug in SaberVM. Please report it, with the program.",
+    ),
+    example(
+        611,
+        "Overflow",
+        "The result of a `_trap` arithmetic op, like `add_trap`, didn't fit in its type. \
+The plain ops wrap instead, the `_sat` ones clamp, and the `_checked` ones say whether it fit.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 2147483647
+    lit 1
+    add_trap
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 2147483647
+    lit 1
+    add_sat
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        612,
+        "DivideByZero",
+        "A `div` or `modulo` (or their `_trap` versions) divided by zero. Check the divisor first, with `call_nz`.",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 10
+    lit 0
+    div
+    i32_to_u8
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    lit 10
+    lit 2
+    div
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        613,
+        "Unverified",
+        "With lazy verification, the program called a function whose body doesn't verify. \
+`Module::verify_error` has the verifier's error for it, which has its own code.",
+        "\
+;; verify: lazy
+
+.sized_bodies
+
+.func
+    func 0
+    lced
+.body
+    lit 1
+    call @inc
+
+.func @inc
+    i32
+    func 1
+    lced
+.body
+    add
+    i32_to_u8
+    halt
+",
+        "\
+;; verify: lazy
+
+.sized_bodies
+
+.func
+    func 0
+    lced
+.body
+    lit 1
+    call @inc
+
+.func @inc
+    i32
+    func 1
+    lced
+.body
+    lit 1
+    add
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        614,
+        "RegionFull",
+        "A `malloc` needed more room than was left in its region. A region's size is given to `new_rgn`, \
+and every object in it takes 16 bytes more than its type's size, for the header that says what's there, \
+or 24 more for an array, which also has its length. Array lengths are only known at runtime, so this is checked then.",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 64
+    share 0
+    lit 64
+    u8
+    arr
+    malloc
+    lit 0
+    arr_proj
+    halt
+",
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 128
+    share 0
+    lit 64
+    u8
+    arr
+    malloc
+    lit 0
+    arr_proj
+    halt
+",
+    ),
+    explanation(
+        615,
+        "StdinBusy",
+        "The program did a `read` of stdin while another instance was already reading it. \
+The signal that says input has come is shared by the whole process, so only one instance at a time can read stdin, \
+and it keeps it until it's dropped.",
+    ),
+    explanation(
+        616,
+        "NotSuspended",
+        "The host called `Instance::resume` on an instance that wasn't stopped at a `yield`, \
+because it halted, trapped, or never ran. Nothing ran, and `Instance::is_suspended` says whether an instance can be resumed.",
+    ),
+    explanation(
+        617,
+        "ArgCount",
+        "The host ran the entry function with `Instance::run_with_args`, or called back a function with `Guest::call`, \
+with a different number of arguments than it takes, so nothing ran. `Module::entry_params` says how many the entry function takes.",
+    ),
+    explanation(
+        618,
+        "ForeignGuestFn",
+        "A host function called back a `GuestFn` with `Guest::call` that it read from a different module's program, \
+or that it kept from before the instance was given a new module with `Instance::reload`. \
+A `GuestFn` is a place in its module's code, which means nothing in another, so nothing ran. \
+Read the function from the stack again in the call it's needed in.",
+    ),
+    example(
+        701,
+        "UnknownExport",
+        "The host called a function by a name that nothing in the module is exported as. \
+Names are given with `.export_name` in assembly, and `sabervm info` lists a module's.",
+        "\
+;; call: triple 14
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func
+.export_name \"tripel\"
+    i32
+    func 1
+    lced
+.body
+    lit 3
+    mul
+    i32_to_u8
+    halt
+",
+        "\
+;; call: triple 14
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func
+.export_name \"triple\"
+    i32
+    func 1
+    lced
+.body
+    lit 3
+    mul
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        702,
+        "NotCallable",
+        "The host can only call exports that take i32s and u8s, since those are all it can pass, \
+but this one takes something else, or is polymorphic.",
+        "\
+;; call: first 14
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func
+.export_name \"first\"
+    i32
+    tuple 1
+    func 1
+    lced
+.body
+    proj 0
+    i32_to_u8
+    halt
+",
+        "\
+;; call: first 14
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func
+.export_name \"first\"
+    i32
+    func 1
+    lced
+.body
+    i32_to_u8
+    halt
+",
+    ),
+    example(
+        703,
+        "ArgMismatch",
+        "The host called an export with arguments that don't fit its parameters: too many, too few, or of the wrong types.",
+        "\
+;; call: triple
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func
+.export_name \"triple\"
+    i32
+    func 1
+    lced
+.body
+    lit 3
+    mul
+    i32_to_u8
+    halt
+",
+        "\
+;; call: triple 14
+
+.func
+    func 0
+    lced
+.body
+    u8_lit 0
+    halt
+
+.func
+.export_name \"triple\"
+    i32
+    func 1
+    lced
+.body
+    lit 3
+    mul
+    i32_to_u8
+    halt
+",
+    ),
+    explanation(
+        801,
+        "MissingExport",
+        "A module reloaded into an instance with `Instance::reload` has to export everything the instance's old module did, \
+since the host might still call any of them. The new module can add exports, but not take them away.",
+    ),
+    explanation(
+        802,
+        "ExportChanged",
+        "A module reloaded into an instance with `Instance::reload` has to give each export the old module had the same type, \
+since the host calls them with arguments for that type. Give the new version a new name instead, and keep the old one around.",
+    ),
+    explanation(
+        803,
+        "EntryChanged",
+        "A module reloaded into an instance with `Instance::reload` has to have an entry function that takes as many arguments as the old one's, \
+since the host runs it with arguments for the old one.",
+    ),
+];
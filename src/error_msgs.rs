@@ -4,10 +4,18 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use crate::error_codes;
 use crate::header::*;
 use crate::pretty::Pretty;
 
+/// The message for `e`, ending with its code, like `[E0404]`, which `sabervm explain` says more about.
 pub fn msg(e: Error) -> String {
+    let code = error_codes::code(&e);
+    format!("{} [{}]", bare_msg(e), code)
+}
+
+/// The message for `e` without its code, for formats that give the code on its own.
+pub fn bare_msg(e: Error) -> String {
     match e {
         Error::SyntaxErrorParamNeeded(pos, op) => {
             format!("Syntax Error: Parameter needed for opcode {:?} at pos {}", op, pos)
@@ -15,8 +23,116 @@ pub fn msg(e: Error) -> String {
         Error::SyntaxErrorUnknownOp(pos, op) => {
             format!("Syntax Error: Unknown opcode {:?} at pos {}", op, pos)
         },
-        Error::TypeErrorMainHasArgs => {
-            format!("Type Error: Main function cannot have arguments")
+        Error::SyntaxErrorFieldCount(pos, expected, got) => {
+            format!("Syntax Error: tuple_fields {} needs {} field descriptors but only {} follow it, at pos {}", expected, expected, got, pos)
+        },
+        Error::SyntaxErrorStrayField(pos) => {
+            format!("Syntax Error: field at pos {} doesn't follow a tuple_fields", pos)
+        },
+        Error::LimitExceeded(limit, max, n) => {
+            format!("Limit Exceeded: {} is limited to {} but this program needs at least {}", limit.pretty(), max, n)
+        },
+        Error::AsmUnknownMnemonic(line, mnemonic) => {
+            format!("Assembly Error: unknown op {} on line {}", mnemonic, line)
+        },
+        Error::AsmUnknownDirective(line, directive) => {
+            format!("Assembly Error: unknown or malformed directive {} on line {}", directive, line)
+        },
+        Error::AsmBadImmediate(line, what) => {
+            format!("Assembly Error: bad immediate for {} on line {}", what, line)
+        },
+        Error::AsmWrongImmediateCount(line, mnemonic, expected, got) => {
+            format!("Assembly Error: {} takes {} immediates but got {} on line {}", mnemonic, expected, got, line)
+        },
+        Error::AsmBadString(line) => {
+            format!("Assembly Error: bad string literal on line {}", line)
+        },
+        Error::AsmOutsideFunction(line) => {
+            format!("Assembly Error: line {} needs to be inside a .func", line)
+        },
+        Error::AsmDuplicateBody(line) => {
+            format!("Assembly Error: a second .body for the same function on line {}", line)
+        },
+        Error::AsmDuplicateLabel(line, name) => {
+            format!("Assembly Error: there's already a function named @{}, on line {}", name, line)
+        },
+        Error::AsmUnknownLabel(line, name) => {
+            format!("Assembly Error: no function is named @{}, on line {}", name, line)
+        },
+        Error::AsmBadMacro(line) => {
+            format!("Assembly Error: malformed or unterminated macro on line {}", line)
+        },
+        Error::AsmIncludeNotFound(line, path) => {
+            format!("Assembly Error: couldn't read {}, included on line {}", path, line)
+        },
+        Error::AsmInInclude(path, e) => {
+            format!("In {}: {}", path, bare_msg(*e))
+        },
+        Error::AsmIncludeCycle(line, path) => {
+            format!("Assembly Error: {}, included on line {}, is already being included", path, line)
+        },
+        Error::AsmMacroTooDeep(line, name) => {
+            format!("Assembly Error: macro {} on line {} expands too deeply (does it use itself?)", name, line)
+        },
+        Error::UnsupportedFeature(feature) => {
+            format!("Unsupported Feature: this program requires {}, which this build of SaberVM doesn't support", feature.pretty())
+        },
+        Error::FeatureNotEnabled(pos, op, feature) => {
+            format!("Feature Not Enabled: {} at pos {} needs {}, which the program's feature header doesn't ask for", op.pretty(), pos, feature.pretty())
+        },
+        Error::UnknownFeatureBits(bits) => {
+            format!("Unknown Feature: this program requires features this version of SaberVM doesn't know about (bits {:#x})", bits)
+        },
+        Error::ChecksumMismatch(expected, actual) => {
+            format!("Corrupted Program: the header's checksum is {:#010x} but the program's is {:#010x}, so the file was cut short or damaged after it was written", expected, actual)
+        },
+        Error::BodyCountMismatch(listed, bodies) => {
+            format!("Syntax Error: the table of body sizes lists {} bodies, but {} functions have bodies", listed, bodies)
+        },
+        Error::BodySizeMismatch(label, size) => {
+            format!("Syntax Error: the body of function {} isn't the {} bytes the table of body sizes says it is", label, size)
+        },
+        Error::OpsAfterSizedBodies(n) => {
+            format!("Syntax Error: {} ops come after the last function body, but a program with sized bodies has to end with its last body", n)
+        },
+        Error::UnknownLint(name) => {
+            format!("Lint Error: {} isn't a lint or a lint level (allow, warn, or deny)", name)
+        },
+        Error::BadLintConfig(line) => {
+            format!("Lint Error: lint config lines look like `deny unreachable-function`, but this one is `{}`", line)
+        },
+        Error::BadMetadata(line) => {
+            format!("Bad Metadata: metadata lines look like `producer mycc 1.2`, with a key of name, producer, or version, but this one is `{}`", line)
+        },
+        Error::BadExportNames => {
+            "Bad Export Names: the export names run past the end of their section, or one of them isn't UTF-8".to_string()
+        },
+        Error::DuplicateExportName(name) => {
+            format!("Bad Export Names: more than one function is exported as {}", name)
+        },
+        Error::ExportNameNotFunction(name, label) => {
+            format!("Bad Export Names: {} names function {}, but the program has no body for it", name, label)
+        },
+        Error::BadSourceMap(line) => {
+            format!("Bad Source Map: source map lines look like `3 main.sbr 12 5`, with a marker, a file, a line, and maybe a column, but this one is `{}`", line)
+        },
+        Error::OpcodeNotAllowed(label, op) => {
+            format!("Disallowed Op: function {} uses {}, which this host has turned off", label, op.pretty())
+        },
+        Error::RejectedByPass(pass, reason) => {
+            format!("Rejected: the {} pass doesn't allow this program: {}", pass, reason)
+        },
+        Error::VerificationCancelled => {
+            "Verification was cancelled".to_string()
+        },
+        Error::VerificationTimedOut => {
+            "Verification ran out of time".to_string()
+        },
+        Error::SyntaxErrorLabelOutOfRange(pos, label, n) => {
+            format!("Syntax Error: global_func {} at pos {} but only {} functions are declared", label, pos, n)
+        },
+        Error::TypeErrorEntryParam(t) => {
+            format!("Type Error: The entry function can only take i32 arguments, but it takes a {}", t.pretty())
         },
         Error::TypeErrorNonEmptyQuantificationStack(label) => {
             format!("Type Error: Non-empty quantification stack at label {}", label)
@@ -54,8 +170,8 @@ pub fn msg(e: Error) -> String {
         Error::TypeErrorTypeVarExpected(pos, op, id, t) => {
             format!("Type Error: Expected type variable a{} at pos {} for opcode {} but found {}", id.1, pos, op.pretty(), t.pretty())
         },
-        Error::TypeErrorCTGetOutOfRange(pos, i, max) => {
-            format!("Type Error: ct_get out of range at pos {}: the compile-time stack depth is {} but got {}", pos, max, i)
+        Error::TypeErrorCTGetOutOfRange(pos, i, ctvals) => {
+            format!("Type Error: ct_get out of range at pos {}: the compile-time stack depth is {} but got {}. The compile-time stack is: {}", pos, ctvals.len(), i, ct_stack_str(&ctvals))
         },
         Error::TypeErrorGetOutOfRange(pos, i, max) => {
             format!("Type Error: get out of range at pos {}: the stack depth is {} but got {}", pos, max, i)
@@ -69,6 +185,15 @@ pub fn msg(e: Error) -> String {
         Error::TypeErrorExistentialExpected(pos, op, t) => {
             format!("Type Error: Expected existential type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
         },
+        Error::TypeErrorOwnsRegion(pos, op, t) => {
+            format!("Region Error: {} at pos {} for opcode {} owns a region, so it can only be moved, not copied, put in memory, or used as a type argument", t.pretty(), pos, op.pretty())
+        },
+        Error::RegionPackageWithoutHandle(pos, op, t) => {
+            format!("Region Error: the region package {} at pos {} for opcode {} doesn't hold its region's handle, so the region could never be freed", t.pretty(), pos, op.pretty())
+        },
+        Error::TypeErrorHandleCopy(pos, op, t) => {
+            format!("Region Error: {} at pos {} for opcode {} holds a region handle, so it can only be moved: it can't be put in memory or used as a type argument, and get can't copy it (share can, if the copy is meant to be there)", t.pretty(), pos, op.pretty())
+        },
         Error::TypeErrorInitTypeMismatch(pos, t1, t2) => {
             format!("Type Error: Expected type {} at pos {} for init but found {}", t1.pretty(), pos, t2.pretty())
         },
@@ -84,8 +209,20 @@ pub fn msg(e: Error) -> String {
         Error::TypeErrorNotEnoughRuntimeArgs(pos, s1, s2) => {
             format!("Type Error: Not enough runtime arguments at pos {}: expected {} but got {}", pos, s1, s2)
         },
-        Error::TypeErrorCallArgTypesMismatch(pos, ts1, ts2) => {
-            format!("Type Error: Call argument types mismatch at pos {}: expected {} but got {}", pos, ts1.iter().map(|t| t.pretty()).collect::<Vec<_>>().join(", "), ts2.iter().map(|t| t.pretty()).collect::<Vec<_>>().join(", "))
+        Error::TypeErrorCallArgMismatch(pos, op, i, t1, t2) => {
+            format!("Type Error: Argument {} of the callee at pos {} for opcode {} should be {} but found {}", i, pos, op.pretty(), t1.pretty(), t2.pretty())
+        },
+        Error::TypeErrorCallArgUninitialized(pos, op, i, t) => {
+            format!("Type Error: Argument {} of the callee at pos {} for opcode {} has the right type but isn't fully initialized: {}", i, pos, op.pretty(), t.pretty())
+        },
+        Error::TypeErrorNotEnoughCTArgs(pos, op, kind) => {
+            format!("Type Error: The callee at pos {} for opcode {} is quantified over a {} but the compile-time stack is empty", pos, op.pretty(), kind.pretty())
+        },
+        Error::TypeErrorCallRegionNotLive(pos, op, r) => {
+            format!("Region Error: The callee at pos {} for opcode {} needs region {} but it has already been freed", pos, op.pretty(), r.pretty())
+        },
+        Error::TypeErrorCallRegionNotUnique(pos, op, r) => {
+            format!("Region Error: The callee at pos {} for opcode {} needs a unique region, since it can free it, but region {} isn't unique here", pos, op.pretty(), r.pretty())
         },
         Error::TypeErrorMallocNonTuple(pos, op, t) => {
             format!("Type Error: Expected tuple type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
@@ -105,6 +242,10 @@ pub fn msg(e: Error) -> String {
         Error::TypeErrorDoubleInit(pos, op, n) => {
             format!("Type Error: Double init at pos {} for opcode {}: component {} has already been initialized", pos, op.pretty(), n)
         },
+        Error::TypeErrorBadField(pos, op, n, descriptor) => {
+            let problem = if descriptor & FIELD_REPR != 0 { "an unknown representation" } else { "reserved bits set" };
+            format!("Type Error: Bad field at pos {} for opcode {}: the descriptor {:#04x} of component {} has {}", pos, op.pretty(), descriptor, n, problem)
+        },
         Error::TypeErrorUninitializedRead(pos, op, n) => {
             format!("Type Error: Uninitialized read at pos {} for opcode {}: component {} has not been initialized", pos, op.pretty(), n)
         },
@@ -120,21 +261,63 @@ pub fn msg(e: Error) -> String {
         Error::ForwardDeclBadStack(ctvals) => {
             format!("Forward declaration of bad stack: {}", ctvals.iter().map(|ctval| ctval.kind().pretty()).collect::<Vec<_>>().join(", "))
         },
+        Error::TypeDeclNeedsSize(k) => {
+            format!("Type declaration {} doesn't start with its size", k)
+        },
+        Error::TypeDeclBadStack(k, ctvals) => {
+            format!("Type declaration {} of bad stack: {} (expected its size, then its definition, which abstract and imported types leave out)", k, ctvals.iter().map(|ctval| ctval.kind().pretty()).collect::<Vec<_>>().join(", "))
+        },
+        Error::TypeDeclSizeMismatch(k, declared, actual) => {
+            format!("Size Error: Type declaration {} says it's {} bytes but its definition is {}", k, declared, actual)
+        },
+        Error::UnknownNamedType(pos, op, k, n) => {
+            format!("Type Error: Unknown named type at pos {} for opcode {}: {} isn't less than the {} declared types", pos, op.pretty(), k, n)
+        },
+        Error::TypeErrorAbstractType(pos, op, k) => {
+            format!("Type Error: Abstract type at pos {} for opcode {}: named type {} has no definition in this program", pos, op.pretty(), k)
+        },
+        Error::TypeErrorNamedTypeExpected(pos, op, t) => {
+            format!("Type Error: Expected named type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
+        },
         Error::UnknownGlobalFunc(pos, op, label) => {
             format!("Unknown global function at pos {}, opcode {}: {}", pos, op.pretty(), label)
         },
+        Error::UnknownImport(label, name) => {
+            format!("Unknown import: function {} imports {:?}, but no program in the module exports it", label, name)
+        },
+        Error::NoEntryFunction => {
+            "No entry function: the first program has no function bodies".to_string()
+        },
+        Error::DeclarationOpInBody(pos, op) => {
+            format!("Declaration opcode in a function body at pos {}: {}", pos, op.pretty())
+        },
         Error::UnexpectedEOF => {
             "Unexpected end of file".to_string()
         },
         Error::TypeErrorArrayExpected(pos, op, t) => {
             format!("Type Error: Expected array type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
         },
+        Error::TypeErrorBigIntExpected(pos, op, t) => {
+            format!("Type Error: Expected big integer type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
+        },
+        Error::TypeErrorBufferExpected(pos, op, t) => {
+            format!("Type Error: Expected buffer type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
+        },
+        Error::TypeErrorMapExpected(pos, op, t) => {
+            format!("Type Error: Expected map type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
+        },
+        Error::TypeErrorResourceExpected(pos, op, t) => {
+            format!("Type Error: Expected resource type at pos {} for opcode {} but found {}", pos, op.pretty(), t.pretty())
+        },
         Error::ReadOnlyRegionError(pos, op, r) => {
             format!("Region Error: region is read-only at pos {} for opcode {}: {}", pos, op.pretty(), r.pretty())
         },
         Error::DataSectionLoadOutOfBounds(pos, op, loc, max) => {
             format!("Data section load out of bounds at pos {} for opcode {}: loading from {} but the data section ends at {}", pos, op.pretty(), loc, max)
         },
+        Error::BadBigIntLiteral(pos, op, loc) => {
+            format!("Data section type error at pos {} for opcode {}: the bytes at {} aren't a big integer literal", pos, op.pretty(), loc)
+        },
         Error::InvalidDataSectionType(pos, op, t) => {
             format!("Data section type error at pos {} for opcode {}: invalid data section type {}", pos, op.pretty(), t.pretty())
         },
@@ -144,5 +327,149 @@ pub fn msg(e: Error) -> String {
         Error::UnknownChannel(pos, op, c) => {
             format!("Unknown channel {} at pos {} for opcode {}", c, pos, op.pretty())
         }
+        Error::HostSignatureMismatch(f, label, signature, stack) => {
+            let stack = stack.iter().map(|t| t.pretty()).collect::<Vec<String>>().join(", ");
+            format!("Host function {} takes {}, but function {} calls it with [{}] under the argument", f, signature, label, stack)
+        }
+    }
+}
+
+pub fn trap_msg(t: Trap) -> String {
+    let code = error_codes::trap_code(&t);
+    format!("{} [{}]", bare_trap_msg(t), code)
+}
+
+pub fn bare_trap_msg(t: Trap) -> String {
+    match t {
+        Trap::Interrupted => {
+            "Runtime Error! The program was interrupted by its host.".to_string()
+        }
+        Trap::UnknownHostFunction(f) => {
+            format!("Runtime Error! The program called host function {} but the host doesn't provide it.", f)
+        }
+        Trap::AsyncHostFunction(f) => {
+            format!("Runtime Error! Host function {} is async, so the program must be run with `run_async`.", f)
+        }
+        Trap::UninitializedRead => {
+            "Runtime Error! The program read uninitialized memory. The verifier should have caught this, so please report it as a SaberVM bug.".to_string()
+        }
+        Trap::OutOfBounds => {
+            "Runtime Error! Array index out of bounds.".to_string()
+        }
+        Trap::CallbackHalted(status) => {
+            format!("Runtime Error! A function the host called back halted with status {} instead of yielding a result.", status)
+        }
+        Trap::ReentrantHostCall(f) => {
+            format!("Runtime Error! Host function {} was called again from one of its own callbacks.", f)
+        }
+        Trap::HostSignature(f) => {
+            format!("Runtime Error! The values on the stack don't match the arguments of host function {}.", f)
+        }
+        Trap::UseAfterFree => {
+            "Runtime Error! The program used a region after freeing it. The verifier should have caught this, so please report it as a SaberVM bug.".to_string()
+        }
+        Trap::DoubleFree => {
+            "Runtime Error! The program freed a region twice. The verifier should have caught this, so please report it as a SaberVM bug.".to_string()
+        }
+        Trap::Overflow => {
+            "Runtime Error! Integer overflow.".to_string()
+        }
+        Trap::DivideByZero => {
+            "Runtime Error! Division by zero.".to_string()
+        }
+        Trap::Unverified(label) => {
+            format!("Runtime Error! The program called function {}, which doesn't verify. It was only checked when it was first called.", label)
+        }
+        Trap::RegionFull => {
+            "Runtime Error! Allocation too big for region.".to_string()
+        }
+        Trap::StdinBusy => {
+            "Runtime Error! Another instance is already reading stdin.".to_string()
+        }
+        Trap::NotSuspended => {
+            "Runtime Error! The instance was resumed, but it isn't stopped at a yield.".to_string()
+        }
+        Trap::ArgCount(n) => {
+            format!("Runtime Error! The function takes {} arguments, but was given a different number.", n)
+        }
+        Trap::ForeignGuestFn => {
+            "Runtime Error! A host function called back a function from a different module.".to_string()
+        }
+    }
+}
+
+pub fn call_msg(e: CallError) -> String {
+    let code = error_codes::call_code(&e);
+    format!("{} [{}]", bare_call_msg(e), code)
+}
+
+pub fn bare_call_msg(e: CallError) -> String {
+    match e {
+        CallError::UnknownExport(name) => {
+            format!("Call Error! Nothing in the module is exported as {}.", name)
+        }
+        CallError::NotCallable(name, t) => {
+            format!("Call Error! {} has type {}, but the host can only call functions that take i32s and u8s.", name, t.pretty())
+        }
+        CallError::ArgMismatch(name, t, args) => {
+            // in the order they're given, which is the reverse of how the type lists them
+            let params = match t {
+                Type::Func(ts) => ts.iter().rev().map(|t| t.pretty()).collect::<Vec<_>>().join(", "),
+                t => t.pretty(),
+            };
+            format!("Call Error! {} takes ({}), but was given {}.", name, params, args)
+        }
+        CallError::Trap(trap) => bare_trap_msg(trap),
+    }
+}
+
+pub fn reload_msg(e: ReloadError) -> String {
+    let code = error_codes::reload_code(&e);
+    format!("{} [{}]", bare_reload_msg(e), code)
+}
+
+pub fn bare_reload_msg(e: ReloadError) -> String {
+    match e {
+        ReloadError::MissingExport(name) => {
+            format!("Reload Error! The old module exports {}, but the new one doesn't.", name)
+        }
+        ReloadError::ExportChanged(name, old, new) => {
+            format!("Reload Error! {} has type {} in the old module but {} in the new one.", name, old.pretty(), new.pretty())
+        }
+        ReloadError::EntryChanged(old, new) => {
+            format!("Reload Error! The entry function takes {} arguments in the old module but {} in the new one.", old, new)
+        }
+        ReloadError::Binding(e) => format!("Reload Error! {}", bare_msg(e)),
+    }
+}
+
+pub fn warning_msg(w: Warning) -> String {
+    match w {
+        Warning::TrailingOps(n) => {
+            format!("Warning: {} ops come after the last function body, so they never run.", n)
+        }
+        Warning::UnusedRegionBinding(label) => {
+            format!("Warning: Function {} is quantified over a region its type never uses.", label)
+        }
+        Warning::UnusedTypeBinding(label) => {
+            format!("Warning: Function {} is quantified over a type its type never uses.", label)
+        }
+        Warning::UnreachableFunction(label) => {
+            format!("Warning: Function {} is never referred to by the entry point, an export, or anything they reach, so it never runs.", label)
+        }
+    }
+}
+
+/// The compile-time stack from the top down, indexed the way `ct_get` counts.
+pub fn ct_stack_str(ctvals: &[CTStackVal]) -> String {
+    if ctvals.is_empty() {
+        return "(empty)".to_string();
     }
-}
\ No newline at end of file
+    ctvals
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, ctval)| format!("{}: {}", i, ctval.pretty()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
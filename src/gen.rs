@@ -0,0 +1,174 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Generated stress modules, for benchmarking and limit testing of the parser, verifier, and VM.
+//!
+//! The functions are split into chains of `depth` functions, where each one calls the next.
+//! Every chain runs `laps` times before moving on to the next one, so each function runs `laps` times in all.
+//! Every function passes a pair of i32s along, the number of laps and the laps left in its chain.
+//! Before that, it makes `regions` regions, each with a tuple of `tuple` i32 fields in it,
+//! and then does arithmetic until its body is about `size` bytes long.
+
+use std::fmt::Write;
+
+/// What a generated module looks like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shape {
+    pub functions: u32,
+    /// About how many bytes of bytecode each function body should be.
+    pub size: usize,
+    /// How many functions call each other in a row before going back around.
+    pub depth: u32,
+    pub laps: i32,
+    /// How many i32 fields each allocation has. Zero makes the regions but allocates nothing in them.
+    pub tuple: u8,
+    /// How many regions each function makes and frees, at most `MAX_REGIONS`.
+    pub regions: u32,
+}
+
+/// Each region leaves its handle and tuple on the stack, and `get` can only reach 255 values down.
+pub const MAX_REGIONS: u32 = 100;
+
+impl Default for Shape {
+    fn default() -> Self {
+        Shape { functions: 100, size: 64, depth: 10, laps: 10, tuple: 4, regions: 1 }
+    }
+}
+
+/// The bytes one step of arithmetic takes: `lit; add; lit; mul; lit; modulo`.
+const ARITH_STEP: usize = 18;
+
+/// The assembly for a module with the given shape, ready for `asm::parse`.
+/// The module halts with status 0.
+pub fn generate(shape: &Shape) -> String {
+    let functions = shape.functions.max(1);
+    let depth = shape.depth.clamp(1, functions);
+    let chains = functions.div_ceil(depth);
+    let mut src = String::new();
+    let _ = writeln!(src, "; generated by `sabervm gen`: {:?}", shape);
+    src += ".meta producer \"sabervm gen\"\n";
+    let _ = writeln!(src, ".meta version \"{}\"", env!("CARGO_PKG_VERSION"));
+    src += "
+.func @main
+    func 0
+    lced
+.body
+";
+    let _ = writeln!(src, "    lit {}", shape.laps.max(1));
+    src += "    lit 0
+    call @reset0
+";
+    for chain in 0..chains {
+        let first = chain * depth;
+        let last = (first + depth).min(functions) - 1;
+        // the chain is entered with no laps done, so it starts its count over
+        let _ = write!(
+            src,
+            "
+.func @reset{}
+    i32
+    i32
+    func 2
+    lced
+.body
+    add
+    get 0
+    call @f{}
+",
+            chain, first
+        );
+        for f in first..=last {
+            let _ = write!(
+                src,
+                "
+.func @f{}
+    i32
+    i32
+    func 2
+    lced
+.body
+",
+                f
+            );
+            // how much is on the stack above the two i32s being passed along
+            let height = body(&mut src, shape);
+            let _ = writeln!(src, "    get {}", height + 1);
+            let _ = writeln!(src, "    get {}", height + 1);
+            if f == last {
+                let _ = write!(
+                    src,
+                    "    lit -1
+    add
+    get 0
+    global_func @f{}
+    global_func @reset{}
+    call_nz
+",
+                    first,
+                    chain + 1
+                );
+            } else {
+                let _ = writeln!(src, "    call @f{}", f + 1);
+            }
+        }
+    }
+    let _ = write!(
+        src,
+        "
+.func @reset{}
+    i32
+    i32
+    func 2
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        chains
+    );
+    src
+}
+
+/// Write the region churn and arithmetic of a function body, returning how many values it leaves on the stack.
+fn body(src: &mut String, shape: &Shape) -> usize {
+    let mut height = 0;
+    let mut size = 0;
+    for _ in 0..shape.regions.min(MAX_REGIONS) {
+        src.push_str("    new_rgn 4096\n");
+        size += 5;
+        height += 1;
+        if shape.tuple > 0 {
+            src.push_str("    share 0\n");
+            for _ in 0..shape.tuple {
+                src.push_str("    i32\n");
+            }
+            let _ = writeln!(src, "    tuple {}", shape.tuple);
+            src.push_str("    ptr\n    malloc\n");
+            size += 2 + shape.tuple as usize + 4;
+            height += 1;
+            for field in 0..shape.tuple {
+                // the laps left, from under the region and the tuple
+                let _ = writeln!(src, "    get {}", height);
+                let _ = writeln!(src, "    init {}", field);
+                size += 4;
+            }
+            src.push_str("    share 1\n    free_rgn\n");
+        } else {
+            src.push_str("    share 0\n    free_rgn\n");
+        }
+        size += 3;
+    }
+    src.push_str("    lit 0\n");
+    size += 5;
+    height += 1;
+    let mut step = 0;
+    while size + ARITH_STEP <= shape.size {
+        let _ = write!(src, "    lit {}\n    add\n    lit 3\n    mul\n    lit 1000\n    modulo\n", step);
+        size += ARITH_STEP;
+        step += 1;
+    }
+    height
+}
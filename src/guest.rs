@@ -0,0 +1,733 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Typed views of a program's values, for host functions registered with `Instance::register_host_fn_with_view`.
+//!
+//! The verifier records the type of everything on the stack at each `host_call` (see `HostSite`),
+//! so a host function can read a tuple's fields, follow pointers, and go through arrays without knowing how they're laid out.
+//! A `GuestView` only lasts as long as the host call, and the program can't run (or free anything) until the host function returns,
+//! so nothing a view hands out can be freed underneath it.
+//! Pointers are only followed into regions the verifier knew were live, and only if the object's generation still matches.
+//! Values whose type is a variable or a named type can't be looked into, since their layout isn't known here.
+//!
+//! `FromSvm` reads Rust values out of a view, like a `String` from a `u8` array or a `Resource` from one the host made,
+//! and `IntoSvm` turns a Rust result back into the `i32` a host call gives the program.
+//! `FromStack` uses them to read a host function's Rust arguments for `Instance::register_native_host_fn`.
+//! Going the other way, `IntoArgs` writes the Rust arguments of `Instance::call`, checked against the export's type first.
+
+use crate::bigint;
+use crate::header::*;
+
+use std::slice;
+
+/// `METADATA_OFFSET` from vm.c: an object's generation and size come just before it.
+const METADATA_OFFSET: usize = 16;
+
+/// The type of a host call's argument.
+static I32: Type = Type::I32;
+
+/// The program's stack at a host call, under the argument.
+pub struct GuestView<'a> {
+    stack: Vec<u8>,
+    arg: [u8; 4],
+    site: Option<&'a HostSite>,
+    /// The module's data section.
+    data_section: &'a [u8],
+    /// The module's id, for the functions read from the stack.
+    module: u64,
+}
+
+impl<'a> GuestView<'a> {
+    /// The view of a copy of the stack, as the verifier described it at `site`.
+    /// The stack mustn't change, and the live regions mustn't be freed, while the view is around.
+    pub(crate) fn new(stack: Vec<u8>, arg: i32, site: Option<&'a HostSite>, data_section: &'a [u8], module: u64) -> GuestView<'a> {
+        GuestView {
+            stack,
+            arg: arg.to_ne_bytes(),
+            site,
+            data_section,
+            module,
+        }
+    }
+
+    /// How many values are on the stack.
+    pub fn depth(&self) -> usize {
+        self.site.map_or(0, |site| site.stack.len())
+    }
+
+    /// The value `n` down from the top of the stack, so 0 is the one that was just under the argument.
+    pub fn get(&self, n: usize) -> Option<Value<'_>> {
+        let types = &self.site?.stack;
+        let i = types.len().checked_sub(n + 1)?;
+        let above = types[i + 1..].iter().map(Type::size).sum::<usize>();
+        let end = self.stack.len().checked_sub(above)?;
+        let start = end.checked_sub(types[i].size())?;
+        Some(Value {
+            view: self,
+            t: &types[i],
+            bytes: &self.stack[start..end],
+        })
+    }
+
+    /// The host call's argument.
+    pub fn arg(&self) -> Value<'_> {
+        Value {
+            view: self,
+            t: &I32,
+            bytes: &self.arg,
+        }
+    }
+
+    fn is_live(&self, r: &Region) -> bool {
+        self.site.is_some_and(|site| site.regions.contains(&r.id))
+    }
+
+    /// The bytes of the object `ptr` points to, if it's `size` bytes long and hasn't been freed.
+    /// Heap objects are checked against their generation, and data section objects against the data section's bounds.
+    fn object(&self, ptr: &[u8], size: usize) -> Option<&[u8]> {
+        let generation = i64::from_ne_bytes(ptr[0..8].try_into().unwrap());
+        let reference = usize::from_ne_bytes(ptr[8..16].try_into().unwrap()) as *const u8;
+        if generation < 0 {
+            let offset = (reference as usize).checked_sub(self.data_section.as_ptr() as usize)?;
+            return self.data_section.get(offset..offset.checked_add(size)?);
+        }
+        // the region is live, so its metadata and the object are still there to read
+        let object_generation = unsafe { reference.sub(METADATA_OFFSET).cast::<i64>().read_unaligned() };
+        let object_size = unsafe { reference.sub(METADATA_OFFSET - 8).cast::<u64>().read_unaligned() };
+        if object_generation != generation || (object_size as usize) < size {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts(reference, size) })
+    }
+
+    /// The elements of the array `ptr` points to, as bytes.
+    fn array(&self, ptr: &[u8]) -> Option<&[u8]> {
+        let generation = i64::from_ne_bytes(ptr[0..8].try_into().unwrap());
+        if generation < 0 {
+            // data section arrays have no length, and go on to the end of the data section
+            let reference = usize::from_ne_bytes(ptr[8..16].try_into().unwrap());
+            let offset = reference.checked_sub(self.data_section.as_ptr() as usize)?;
+            return self.data_section.get(offset..);
+        }
+        let len = usize::from_ne_bytes(self.object(ptr, 8)?.try_into().unwrap());
+        Some(&self.object(ptr, 8 + len)?[8..])
+    }
+
+    /// Copy `bytes` into the elements of the heap array `ptr` points to, from `offset`, if they fit and it hasn't been freed.
+    fn write_array(&self, ptr: &[u8], offset: usize, bytes: &[u8]) -> Option<()> {
+        if i64::from_ne_bytes(ptr[0..8].try_into().unwrap()) < 0 {
+            // the data section can't be written to
+            return None;
+        }
+        let len = self.array(ptr)?.len();
+        if offset.checked_add(bytes.len())? > len {
+            return None;
+        }
+        let reference = usize::from_ne_bytes(ptr[8..16].try_into().unwrap()) as *mut u8;
+        // the program can't run until the host call returns, so nothing else is using the array
+        unsafe { reference.add(8 + offset).copy_from_nonoverlapping(bytes.as_ptr(), bytes.len()) };
+        Some(())
+    }
+}
+
+/// One value in a `GuestView`, with its type.
+#[derive(Clone, Copy)]
+pub struct Value<'a> {
+    view: &'a GuestView<'a>,
+    t: &'a Type,
+    bytes: &'a [u8],
+}
+
+impl<'a> Value<'a> {
+    pub fn typ(&self) -> &'a Type {
+        self.t
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        match self.t {
+            Type::I32 => Some(i32::from_ne_bytes(self.bytes.try_into().unwrap())),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> Option<u8> {
+        match self.t {
+            Type::U8 => Some(self.bytes[0]),
+            _ => None,
+        }
+    }
+
+    /// Component `i` of a tuple, or `None` if it's not a tuple or the component doesn't hold a value yet.
+    pub fn field(&self, i: usize) -> Option<Value<'a>> {
+        let Type::Tuple(fields) = self.t else {
+            return None;
+        };
+        let field = fields.get(i).filter(|field| field.init)?;
+        let offset = fields[..i].iter().map(|field| field.t.size()).sum::<usize>();
+        Some(Value {
+            view: self.view,
+            t: &field.t,
+            bytes: &self.bytes[offset..offset + field.t.size()],
+        })
+    }
+
+    /// What a pointer points to, or `None` if it's not a pointer or its region or object has been freed.
+    pub fn deref(&self) -> Option<Value<'a>> {
+        let Type::Ptr(t, r) = self.t else {
+            return None;
+        };
+        if !self.view.is_live(r) {
+            return None;
+        }
+        Some(Value {
+            view: self.view,
+            t,
+            bytes: self.view.object(self.bytes, t.size())?,
+        })
+    }
+
+    /// The elements of an array, or `None` if it's not an array or its region or object has been freed.
+    pub fn elements(&self) -> Option<impl Iterator<Item = Value<'a>> + 'a> {
+        let Type::Array(t, r) = self.t else {
+            return None;
+        };
+        if !self.view.is_live(r) || t.size() == 0 {
+            return None;
+        }
+        let view = self.view;
+        let t: &Type = t;
+        let bytes = view.array(self.bytes)?;
+        Some(bytes.chunks_exact(t.size()).map(move |bytes| Value { view, t, bytes }))
+    }
+
+    /// How many elements an array has.
+    pub fn array_len(&self) -> Option<usize> {
+        self.elements().map(Iterator::count)
+    }
+
+    /// A function the host can call back with `Guest::call`, if this is a function that only takes `i32`s.
+    pub fn as_func(&self) -> Option<GuestFn> {
+        match self.t {
+            Type::Func(ts) if ts.iter().all(|t| *t == Type::I32) => Some(GuestFn {
+                module: self.view.module,
+                pc: u32::from_ne_bytes(self.bytes.try_into().unwrap()),
+                params: ts.len(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// A big integer in decimal (see `bigint`), or `None` if it's not one or its region or object has been freed.
+    pub fn as_bigint(&self) -> Option<String> {
+        let Type::BigInt(r) = self.t else {
+            return None;
+        };
+        if !self.view.is_live(r) {
+            return None;
+        }
+        let header = self.view.object(self.bytes, bigint::HEADER)?;
+        let len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        bigint::to_decimal(self.view.object(self.bytes, bigint::HEADER + 4 * len)?)
+    }
+
+    /// The host's id for a resource of kind `kind`, or `None` if it's not one or its region or object has been freed.
+    /// The kind is checked against the one `host_res` wrote when it made the resource, as well as against the type.
+    pub fn as_resource(&self, kind: u32) -> Option<i32> {
+        match self.t {
+            Type::Resource(k, r) if *k == kind && self.view.is_live(r) => {
+                let object = self.view.object(self.bytes, 8)?;
+                if u32::from_ne_bytes(object[0..4].try_into().unwrap()) != kind {
+                    return None;
+                }
+                Some(i32::from_ne_bytes(object[4..8].try_into().unwrap()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The bytes of a `u8` array, like a string, or of a buffer, which is laid out the same way.
+    pub fn bytes(&self) -> Option<&'a [u8]> {
+        match self.t {
+            Type::Array(t, r) if **t == Type::U8 && self.view.is_live(r) => self.view.array(self.bytes),
+            Type::Buffer(r) if self.view.is_live(r) => self.view.array(self.bytes),
+            _ => None,
+        }
+    }
+
+    /// Write `bytes` into a buffer from `offset`, or give `None` if it's not a buffer, they don't fit,
+    /// or its region or object has been freed. Buffers are the only values a host function can change,
+    /// since they're the only ones with no types inside to break.
+    pub fn write_bytes(&self, offset: usize, bytes: &[u8]) -> Option<()> {
+        match self.t {
+            Type::Buffer(r) if self.view.is_live(r) => self.view.write_array(self.bytes, offset, bytes),
+            _ => None,
+        }
+    }
+}
+
+/// A function in the program, which the host can call back with `Guest::call`.
+/// It's only a place in its module's code, so it can only be called back into an instance of that module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuestFn {
+    /// The id of the module it's from.
+    pub(crate) module: u64,
+    pub(crate) pc: u32,
+    pub(crate) params: usize,
+}
+
+impl GuestFn {
+    /// How many `i32`s the function takes.
+    pub fn params(&self) -> usize {
+        self.params
+    }
+}
+
+/// A Rust value that can be read from a program's value.
+/// Pointers are followed, so a pointer to a tuple can be read as a Rust tuple, for example.
+pub trait FromSvm: Sized {
+    /// The value, or `None` if it isn't of a type that reads as `Self`, or it's in memory that's been freed.
+    fn from_svm(value: Value) -> Option<Self>;
+
+    /// Whether values of type `t` can be read as `Self`, which is checked before a program runs by `Instance::register_binding`.
+    /// A value that passes can still fail to read if it points into memory that's been freed.
+    fn matches(t: &Type) -> bool;
+
+    /// The type a program should have for `Self`, for error messages.
+    fn name() -> String;
+}
+
+/// What `value` points to, through any number of pointers.
+fn follow(mut value: Value) -> Option<Value> {
+    while let Type::Ptr(_, _) = value.typ() {
+        value = value.deref()?;
+    }
+    Some(value)
+}
+
+/// The type a pointer of type `t` points to, through any number of pointers.
+fn pointee(mut t: &Type) -> &Type {
+    while let Type::Ptr(t2, _) = t {
+        t = t2;
+    }
+    t
+}
+
+impl FromSvm for i32 {
+    fn from_svm(value: Value) -> Option<i32> {
+        follow(value)?.as_i32()
+    }
+
+    fn matches(t: &Type) -> bool {
+        *pointee(t) == Type::I32
+    }
+
+    fn name() -> String {
+        "i32".to_string()
+    }
+}
+
+impl FromSvm for u8 {
+    fn from_svm(value: Value) -> Option<u8> {
+        follow(value)?.as_u8()
+    }
+
+    fn matches(t: &Type) -> bool {
+        *pointee(t) == Type::U8
+    }
+
+    fn name() -> String {
+        "u8".to_string()
+    }
+}
+
+/// An `i32` or a `u8`, which is true if it isn't 0.
+impl FromSvm for bool {
+    fn from_svm(value: Value) -> Option<bool> {
+        let value = follow(value)?;
+        value.as_i32().or(value.as_u8().map(i32::from)).map(|n| n != 0)
+    }
+
+    fn matches(t: &Type) -> bool {
+        matches!(pointee(t), Type::I32 | Type::U8)
+    }
+
+    fn name() -> String {
+        "i32 or u8".to_string()
+    }
+}
+
+/// A tuple of two `i32`s, the low half and then the high half, like the times from `host::CLOCK_WALL`.
+impl FromSvm for i64 {
+    fn from_svm(value: Value) -> Option<i64> {
+        let (low, high) = <(i32, i32)>::from_svm(value)?;
+        Some(i64::from(high) << 32 | i64::from(low as u32))
+    }
+
+    fn matches(t: &Type) -> bool {
+        <(i32, i32)>::matches(t)
+    }
+
+    fn name() -> String {
+        <(i32, i32)>::name()
+    }
+}
+
+/// A `u8` array or a buffer, if it's UTF-8.
+impl FromSvm for String {
+    fn from_svm(value: Value) -> Option<String> {
+        String::from_utf8(follow(value)?.bytes()?.to_vec()).ok()
+    }
+
+    fn matches(t: &Type) -> bool {
+        Bytes::matches(t)
+    }
+
+    fn name() -> String {
+        "u8[]".to_string()
+    }
+}
+
+/// The bytes of a buffer or a `u8` array, for host functions that take binary data rather than text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bytes(pub Vec<u8>);
+
+impl FromSvm for Bytes {
+    fn from_svm(value: Value) -> Option<Bytes> {
+        Some(Bytes(follow(value)?.bytes()?.to_vec()))
+    }
+
+    fn matches(t: &Type) -> bool {
+        matches!(pointee(t), Type::Buffer(_)) || matches!(pointee(t), Type::Array(t, _) if **t == Type::U8)
+    }
+
+    fn name() -> String {
+        "buf".to_string()
+    }
+}
+
+/// An array, read element by element.
+impl<T: FromSvm> FromSvm for Vec<T> {
+    fn from_svm(value: Value) -> Option<Vec<T>> {
+        follow(value)?.elements()?.map(T::from_svm).collect()
+    }
+
+    fn matches(t: &Type) -> bool {
+        matches!(pointee(t), Type::Array(t, _) if T::matches(t))
+    }
+
+    fn name() -> String {
+        T::name() + "[]"
+    }
+}
+
+/// A resource of kind `KIND`, as the id its host function gave it (see `Instance::register_resource_fn`),
+/// so a host function can take a file without being handed a socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resource<const KIND: u32>(pub i32);
+
+impl<const KIND: u32> FromSvm for Resource<KIND> {
+    fn from_svm(value: Value) -> Option<Resource<KIND>> {
+        follow(value)?.as_resource(KIND).map(Resource)
+    }
+
+    fn matches(t: &Type) -> bool {
+        matches!(pointee(t), Type::Resource(kind, _) if *kind == KIND)
+    }
+
+    fn name() -> String {
+        format!("res {}", KIND)
+    }
+}
+
+impl FromSvm for GuestFn {
+    fn from_svm(value: Value) -> Option<GuestFn> {
+        value.as_func()
+    }
+
+    fn matches(t: &Type) -> bool {
+        matches!(t, Type::Func(ts) if ts.iter().all(|t| *t == Type::I32))
+    }
+
+    fn name() -> String {
+        "a function of i32s".to_string()
+    }
+}
+
+/// A tuple with exactly as many components as the Rust tuple, each of which holds a value.
+macro_rules! tuple_from_svm {
+    ($n:expr; $($t:ident $i:tt),+) => {
+        impl<$($t: FromSvm),+> FromSvm for ($($t,)+) {
+            fn from_svm(value: Value) -> Option<($($t,)+)> {
+                let value = follow(value)?;
+                match value.typ() {
+                    Type::Tuple(fields) if fields.len() == $n => Some(($($t::from_svm(value.field($i)?)?,)+)),
+                    _ => None,
+                }
+            }
+
+            fn matches(t: &Type) -> bool {
+                match pointee(t) {
+                    Type::Tuple(fields) if fields.len() == $n => {
+                        fields.iter().all(|field| field.init) $(&& $t::matches(&fields[$i].t))+
+                    }
+                    _ => false,
+                }
+            }
+
+            fn name() -> String {
+                "(".to_string() + &[$($t::name()),+].join(", ") + ")"
+            }
+        }
+    };
+}
+
+tuple_from_svm!(1; A 0);
+tuple_from_svm!(2; A 0, B 1);
+tuple_from_svm!(3; A 0, B 1, C 2);
+tuple_from_svm!(4; A 0, B 1, C 2, D 3);
+
+/// A Rust value a host function can give back to the program, as the `i32` result of the `host_call`.
+/// Only values that fit in an `i32` can go back, since a host function can't allocate in the program's regions.
+pub trait IntoSvm {
+    fn into_svm(self) -> i32;
+}
+
+impl IntoSvm for i32 {
+    fn into_svm(self) -> i32 {
+        self
+    }
+}
+
+impl IntoSvm for u8 {
+    fn into_svm(self) -> i32 {
+        self.into()
+    }
+}
+
+/// 1 for true and 0 for false.
+impl IntoSvm for bool {
+    fn into_svm(self) -> i32 {
+        self.into()
+    }
+}
+
+/// The same bits, so the program sees numbers past `i32::MAX` as negative.
+impl IntoSvm for u32 {
+    fn into_svm(self) -> i32 {
+        self as i32
+    }
+}
+
+/// 0, for host functions that are only called for what they do.
+impl IntoSvm for () {
+    fn into_svm(self) -> i32 {
+        0
+    }
+}
+
+/// A host function's Rust arguments, as a tuple: the last is the `host_call`'s argument,
+/// and the ones before it are the values under it, in the order they were pushed.
+/// So `(String, i32)` is a string with the argument pushed on top of it.
+pub trait FromStack: Sized {
+    fn from_stack(view: &GuestView) -> Option<Self>;
+
+    /// Whether a `host_call` with `stack` under its argument (bottom first, as in a `HostSite`) can call the function.
+    fn accepts(stack: &[Type]) -> bool;
+
+    /// The arguments as a program should push them, for error messages.
+    fn signature() -> String;
+}
+
+/// No arguments, not even the `host_call`'s.
+impl FromStack for () {
+    fn from_stack(_view: &GuestView) -> Option<()> {
+        Some(())
+    }
+
+    fn accepts(_stack: &[Type]) -> bool {
+        true
+    }
+
+    fn signature() -> String {
+        "()".to_string()
+    }
+}
+
+macro_rules! tuple_from_stack {
+    ($n:expr; $($t:ident $i:tt),+) => {
+        impl<$($t: FromSvm),+> FromStack for ($($t,)+) {
+            fn from_stack(view: &GuestView) -> Option<($($t,)+)> {
+                // the last one is the argument, and the one before it is 0 down from the top of the stack
+                let get = |i: usize| match ($n - 1 - i).checked_sub(1) {
+                    Some(n) => view.get(n),
+                    None => Some(view.arg()),
+                };
+                Some(($($t::from_svm(get($i)?)?,)+))
+            }
+
+            fn accepts(stack: &[Type]) -> bool {
+                let Some(under) = stack.len().checked_sub($n - 1) else {
+                    return false;
+                };
+                let t = |i: usize| if i + 1 == $n { &I32 } else { &stack[under + i] };
+                true $(&& $t::matches(t($i)))+
+            }
+
+            fn signature() -> String {
+                "(".to_string() + &[$($t::name()),+].join(", ") + ")"
+            }
+        }
+    };
+}
+
+tuple_from_stack!(1; A 0);
+tuple_from_stack!(2; A 0, B 1);
+tuple_from_stack!(3; A 0, B 1, C 2);
+tuple_from_stack!(4; A 0, B 1, C 2, D 3);
+
+/// A Rust value the host can pass to one of the program's functions, for `Instance::call`.
+pub trait IntoArg {
+    /// Whether the value can be passed for a parameter of type `t`.
+    fn fits(t: &Type) -> bool;
+
+    /// The type a parameter should have for `Self`, for error messages.
+    fn arg_name() -> String;
+
+    /// Lay the value out the way a parameter of type `t` is on the stack, which `fits` has said it can be.
+    fn write(self, t: &Type, out: &mut Vec<u8>);
+}
+
+impl IntoArg for i32 {
+    fn fits(t: &Type) -> bool {
+        *t == Type::I32
+    }
+
+    fn arg_name() -> String {
+        "i32".to_string()
+    }
+
+    fn write(self, _t: &Type, out: &mut Vec<u8>) {
+        out.extend(self.to_ne_bytes());
+    }
+}
+
+/// A `u8`, or an `i32` from 0 to 255.
+impl IntoArg for u8 {
+    fn fits(t: &Type) -> bool {
+        matches!(t, Type::I32 | Type::U8)
+    }
+
+    fn arg_name() -> String {
+        "u8".to_string()
+    }
+
+    fn write(self, t: &Type, out: &mut Vec<u8>) {
+        match t {
+            Type::U8 => out.push(self),
+            _ => i32::from(self).write(t, out),
+        }
+    }
+}
+
+/// 1 for true and 0 for false, as an `i32` or a `u8`.
+impl IntoArg for bool {
+    fn fits(t: &Type) -> bool {
+        u8::fits(t)
+    }
+
+    fn arg_name() -> String {
+        "bool".to_string()
+    }
+
+    fn write(self, t: &Type, out: &mut Vec<u8>) {
+        u8::from(self).write(t, out);
+    }
+}
+
+/// The same bits, so the program sees numbers past `i32::MAX` as negative.
+impl IntoArg for u32 {
+    fn fits(t: &Type) -> bool {
+        i32::fits(t)
+    }
+
+    fn arg_name() -> String {
+        "u32".to_string()
+    }
+
+    fn write(self, t: &Type, out: &mut Vec<u8>) {
+        (self as i32).write(t, out);
+    }
+}
+
+/// All of the Rust arguments to `Instance::call`: a tuple of `IntoArg`s, one for each parameter,
+/// or a slice of `i32`s, for when how many there are is only known at runtime.
+pub trait IntoArgs {
+    /// Whether the arguments can be passed to a function with `params`, which go from the bottom of the stack up.
+    fn fits(&self, params: &[Type]) -> bool;
+
+    /// The arguments' types, for error messages.
+    fn signature(&self) -> String;
+
+    /// The arguments as they go on the stack, the last one on top, which `fits` has said they can.
+    fn into_args(self, params: &[Type]) -> Vec<u8>;
+}
+
+impl IntoArgs for &[i32] {
+    fn fits(&self, params: &[Type]) -> bool {
+        params.len() == self.len() && params.iter().all(i32::fits)
+    }
+
+    fn signature(&self) -> String {
+        "(".to_string() + &vec!["i32"; self.len()].join(", ") + ")"
+    }
+
+    fn into_args(self, params: &[Type]) -> Vec<u8> {
+        let mut out = vec![];
+        for (arg, t) in self.iter().zip(params) {
+            arg.write(t, &mut out);
+        }
+        out
+    }
+}
+
+impl IntoArgs for () {
+    fn fits(&self, params: &[Type]) -> bool {
+        params.is_empty()
+    }
+
+    fn signature(&self) -> String {
+        "()".to_string()
+    }
+
+    fn into_args(self, _params: &[Type]) -> Vec<u8> {
+        vec![]
+    }
+}
+
+macro_rules! tuple_into_args {
+    ($n:expr; $($t:ident $i:tt),+) => {
+        impl<$($t: IntoArg),+> IntoArgs for ($($t,)+) {
+            fn fits(&self, params: &[Type]) -> bool {
+                params.len() == $n $(&& $t::fits(&params[$i]))+
+            }
+
+            fn signature(&self) -> String {
+                "(".to_string() + &[$($t::arg_name()),+].join(", ") + ")"
+            }
+
+            fn into_args(self, params: &[Type]) -> Vec<u8> {
+                let mut out = vec![];
+                $(self.$i.write(&params[$i], &mut out);)+
+                out
+            }
+        }
+    };
+}
+
+tuple_into_args!(1; A 0);
+tuple_into_args!(2; A 0, B 1);
+tuple_into_args!(3; A 0, B 1, C 2);
+tuple_into_args!(4; A 0, B 1, C 2, D 3);
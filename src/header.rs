@@ -4,7 +4,12 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use crate::stack::Stack;
+
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// The input type for SaberVM.
 pub type ByteStream = Vec<u8>;
@@ -19,6 +24,8 @@ pub struct Id(pub Pos, pub u32);
 
 /// The type of unverified ops.
 /// This includes all the static analysis ops, which disappear after verification.
+/// Arities and indices (into a stack or a tuple) are one byte, so a tuple has at most 255 components.
+/// Function labels, sizes, and data-section offsets are four bytes.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Op1 {
     Unique,
@@ -67,18 +74,541 @@ pub enum Op1 {
     I32ToU8,
     Read(u8),
     Write(u8),
+    Yield,
+    HostCall(u32),
+    TupleFields(u8),
+    Field(u8),
+    Named(u32),
+    Fold(u32),
+    Unfold,
+    SomeRgn,
+    Share(u8),
+    AddTrap,
+    MulTrap,
+    DivTrap,
+    ModuloTrap,
+    Sub,
+    SubTrap,
+    AddSat,
+    SubSat,
+    MulSat,
+    AddChecked,
+    SubChecked,
+    MulChecked,
+    Nop,
+    Marker(u32),
+    BigInt,
+    BigData(u32),
+    BigFromI32,
+    BigAdd,
+    BigSub,
+    BigMul,
+    BigDiv,
+    BigModulo,
+    BigCmp,
+    BigToI32,
+    Buf,
+    BufNew,
+    BufLen,
+    BufGetU8,
+    BufSetU8,
+    BufGetU32,
+    BufSetU32,
+    Map,
+    MapGet,
+    MapSet,
+    MapHas,
+    MapRemove,
+    MapLen,
+    Res(u32),
+    HostRes(u32),
 }
 
-/// The type of unverified ops.
-/// This includes all the static analysis ops, which disappear after verification.
-#[derive(Clone, Copy, Debug)]
+/// How the immediate after an op's byte is encoded in the bytecode format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImmKind {
+    None,
+    U8,
+    U32,
+    I32,
+    /// A 16-byte name, read as two u64s.
+    Name,
+}
+
+impl ImmKind {
+    /// The number of bytes the immediate takes up.
+    pub fn width(self) -> usize {
+        match self {
+            ImmKind::None => 0,
+            ImmKind::U8 => 1,
+            ImmKind::U32 | ImmKind::I32 => 4,
+            ImmKind::Name => 16,
+        }
+    }
+}
+
+/// A decoded immediate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Imm {
+    None,
+    U8(u8),
+    U32(u32),
+    I32(i32),
+    Name(u64, u64),
+}
+
+/// One op in the bytecode format.
+#[derive(Debug)]
+pub struct OpInfo {
+    pub byte: u8,
+    pub mnemonic: &'static str,
+    pub imm: ImmKind,
+}
+
+/// Every op in the bytecode format, in byte order.
+/// The lexer, the pretty-printer, and `sabervm opcodes` all go by this table, so a new op only needs adding here and to `Op1`.
+pub const OPCODES: &[OpInfo] = &[
+    OpInfo { byte: 0x00, mnemonic: "unique", imm: ImmKind::None },
+    OpInfo { byte: 0x01, mnemonic: "handle", imm: ImmKind::None },
+    OpInfo { byte: 0x02, mnemonic: "i32", imm: ImmKind::None },
+    OpInfo { byte: 0x03, mnemonic: "tuple", imm: ImmKind::U8 },
+    OpInfo { byte: 0x04, mnemonic: "some", imm: ImmKind::None },
+    OpInfo { byte: 0x05, mnemonic: "all", imm: ImmKind::None },
+    OpInfo { byte: 0x06, mnemonic: "rgn", imm: ImmKind::None },
+    OpInfo { byte: 0x07, mnemonic: "end", imm: ImmKind::None },
+    OpInfo { byte: 0x08, mnemonic: "app", imm: ImmKind::None },
+    OpInfo { byte: 0x09, mnemonic: "func", imm: ImmKind::U8 },
+    OpInfo { byte: 0x0A, mnemonic: "ctget", imm: ImmKind::U8 },
+    OpInfo { byte: 0x0B, mnemonic: "lced", imm: ImmKind::None },
+    OpInfo { byte: 0x0C, mnemonic: "unpack", imm: ImmKind::None },
+    OpInfo { byte: 0x0D, mnemonic: "get", imm: ImmKind::U8 },
+    OpInfo { byte: 0x0E, mnemonic: "init", imm: ImmKind::U8 },
+    OpInfo { byte: 0x0F, mnemonic: "malloc", imm: ImmKind::None },
+    OpInfo { byte: 0x10, mnemonic: "proj", imm: ImmKind::U8 },
+    OpInfo { byte: 0x11, mnemonic: "call", imm: ImmKind::None },
+    OpInfo { byte: 0x13, mnemonic: "lit", imm: ImmKind::I32 },
+    OpInfo { byte: 0x14, mnemonic: "global_func", imm: ImmKind::U32 },
+    OpInfo { byte: 0x15, mnemonic: "halt", imm: ImmKind::None },
+    OpInfo { byte: 0x16, mnemonic: "pack", imm: ImmKind::None },
+    OpInfo { byte: 0x17, mnemonic: "size", imm: ImmKind::U32 },
+    OpInfo { byte: 0x18, mnemonic: "new_rgn", imm: ImmKind::U32 },
+    OpInfo { byte: 0x19, mnemonic: "free_rgn", imm: ImmKind::None },
+    OpInfo { byte: 0x1A, mnemonic: "ptr", imm: ImmKind::None },
+    OpInfo { byte: 0x1B, mnemonic: "deref", imm: ImmKind::None },
+    OpInfo { byte: 0x1C, mnemonic: "arr", imm: ImmKind::None },
+    OpInfo { byte: 0x1D, mnemonic: "arr_mut", imm: ImmKind::None },
+    OpInfo { byte: 0x1E, mnemonic: "arr_proj", imm: ImmKind::None },
+    OpInfo { byte: 0x1F, mnemonic: "add", imm: ImmKind::None },
+    OpInfo { byte: 0x20, mnemonic: "mul", imm: ImmKind::None },
+    OpInfo { byte: 0x21, mnemonic: "div", imm: ImmKind::None },
+    OpInfo { byte: 0x22, mnemonic: "call_nz", imm: ImmKind::None },
+    OpInfo { byte: 0x23, mnemonic: "data", imm: ImmKind::U32 },
+    OpInfo { byte: 0x24, mnemonic: "data_sec", imm: ImmKind::None },
+    OpInfo { byte: 0x25, mnemonic: "u8", imm: ImmKind::None },
+    OpInfo { byte: 0x26, mnemonic: "copy_n", imm: ImmKind::None },
+    OpInfo { byte: 0x27, mnemonic: "u8_lit", imm: ImmKind::U8 },
+    OpInfo { byte: 0x28, mnemonic: "u8_to_i32", imm: ImmKind::None },
+    OpInfo { byte: 0x29, mnemonic: "import", imm: ImmKind::Name },
+    OpInfo { byte: 0x2A, mnemonic: "export", imm: ImmKind::Name },
+    OpInfo { byte: 0x2B, mnemonic: "modulo", imm: ImmKind::None },
+    OpInfo { byte: 0x2C, mnemonic: "i32_to_u8", imm: ImmKind::None },
+    OpInfo { byte: 0x2D, mnemonic: "read", imm: ImmKind::U8 },
+    OpInfo { byte: 0x2E, mnemonic: "write", imm: ImmKind::U8 },
+    OpInfo { byte: 0x2F, mnemonic: "yield", imm: ImmKind::None },
+    OpInfo { byte: 0x30, mnemonic: "host_call", imm: ImmKind::U32 },
+    OpInfo { byte: 0x31, mnemonic: "tuple_fields", imm: ImmKind::U8 },
+    OpInfo { byte: 0x32, mnemonic: "field", imm: ImmKind::U8 },
+    OpInfo { byte: 0x33, mnemonic: "named", imm: ImmKind::U32 },
+    OpInfo { byte: 0x34, mnemonic: "fold", imm: ImmKind::U32 },
+    OpInfo { byte: 0x35, mnemonic: "unfold", imm: ImmKind::None },
+    OpInfo { byte: 0x36, mnemonic: "some_rgn", imm: ImmKind::None },
+    OpInfo { byte: 0x37, mnemonic: "share", imm: ImmKind::U8 },
+    OpInfo { byte: 0x38, mnemonic: "add_trap", imm: ImmKind::None },
+    OpInfo { byte: 0x39, mnemonic: "mul_trap", imm: ImmKind::None },
+    OpInfo { byte: 0x3A, mnemonic: "div_trap", imm: ImmKind::None },
+    OpInfo { byte: 0x3B, mnemonic: "modulo_trap", imm: ImmKind::None },
+    OpInfo { byte: 0x3C, mnemonic: "sub", imm: ImmKind::None },
+    OpInfo { byte: 0x3D, mnemonic: "sub_trap", imm: ImmKind::None },
+    OpInfo { byte: 0x3E, mnemonic: "add_sat", imm: ImmKind::None },
+    OpInfo { byte: 0x3F, mnemonic: "sub_sat", imm: ImmKind::None },
+    OpInfo { byte: 0x40, mnemonic: "mul_sat", imm: ImmKind::None },
+    OpInfo { byte: 0x41, mnemonic: "add_checked", imm: ImmKind::None },
+    OpInfo { byte: 0x42, mnemonic: "sub_checked", imm: ImmKind::None },
+    OpInfo { byte: 0x43, mnemonic: "mul_checked", imm: ImmKind::None },
+    OpInfo { byte: 0x44, mnemonic: "nop", imm: ImmKind::None },
+    OpInfo { byte: 0x45, mnemonic: "marker", imm: ImmKind::U32 },
+    OpInfo { byte: 0x46, mnemonic: "bigint", imm: ImmKind::None },
+    OpInfo { byte: 0x47, mnemonic: "big_data", imm: ImmKind::U32 },
+    OpInfo { byte: 0x48, mnemonic: "big_from_i32", imm: ImmKind::None },
+    OpInfo { byte: 0x49, mnemonic: "big_add", imm: ImmKind::None },
+    OpInfo { byte: 0x4A, mnemonic: "big_sub", imm: ImmKind::None },
+    OpInfo { byte: 0x4B, mnemonic: "big_mul", imm: ImmKind::None },
+    OpInfo { byte: 0x4C, mnemonic: "big_div", imm: ImmKind::None },
+    OpInfo { byte: 0x4D, mnemonic: "big_modulo", imm: ImmKind::None },
+    OpInfo { byte: 0x4E, mnemonic: "big_cmp", imm: ImmKind::None },
+    OpInfo { byte: 0x4F, mnemonic: "big_to_i32", imm: ImmKind::None },
+    OpInfo { byte: 0x50, mnemonic: "buf", imm: ImmKind::None },
+    OpInfo { byte: 0x51, mnemonic: "buf_new", imm: ImmKind::None },
+    OpInfo { byte: 0x52, mnemonic: "buf_len", imm: ImmKind::None },
+    OpInfo { byte: 0x53, mnemonic: "buf_get_u8", imm: ImmKind::None },
+    OpInfo { byte: 0x54, mnemonic: "buf_set_u8", imm: ImmKind::None },
+    OpInfo { byte: 0x55, mnemonic: "buf_get_u32", imm: ImmKind::None },
+    OpInfo { byte: 0x56, mnemonic: "buf_set_u32", imm: ImmKind::None },
+    OpInfo { byte: 0x57, mnemonic: "map", imm: ImmKind::None },
+    OpInfo { byte: 0x58, mnemonic: "map_get", imm: ImmKind::None },
+    OpInfo { byte: 0x59, mnemonic: "map_set", imm: ImmKind::None },
+    OpInfo { byte: 0x5A, mnemonic: "map_has", imm: ImmKind::None },
+    OpInfo { byte: 0x5B, mnemonic: "map_remove", imm: ImmKind::None },
+    OpInfo { byte: 0x5C, mnemonic: "map_len", imm: ImmKind::None },
+    OpInfo { byte: 0x5D, mnemonic: "res", imm: ImmKind::U32 },
+    OpInfo { byte: 0x5E, mnemonic: "host_res", imm: ImmKind::U32 },
+];
+
+/// Look up an op by its byte.
+pub fn op_info(byte: u8) -> Option<&'static OpInfo> {
+    OPCODES.iter().find(|info| info.byte == byte)
+}
+
+/// Look up an op by its mnemonic, like `free_rgn`.
+pub fn op_by_mnemonic(mnemonic: &str) -> Option<&'static OpInfo> {
+    OPCODES.iter().find(|info| info.mnemonic == mnemonic)
+}
+
+/// A set of ops, by byte, for turning parts of the instruction set off (see `vm::Config`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeSet([u64; 4]);
+
+impl OpcodeSet {
+    pub fn all() -> Self {
+        OpcodeSet([u64::MAX; 4])
+    }
+
+    pub fn none() -> Self {
+        OpcodeSet([0; 4])
+    }
+
+    pub fn contains(&self, byte: u8) -> bool {
+        self.0[byte as usize / 64] & (1 << (byte % 64)) != 0
+    }
+
+    /// The set with the op `byte` in it, as in `OpcodeSet::none().allow(0x13)`.
+    pub fn allow(mut self, byte: u8) -> Self {
+        self.0[byte as usize / 64] |= 1 << (byte % 64);
+        self
+    }
+
+    /// The set without the op `byte`, as in `OpcodeSet::all().deny(0x19)`.
+    pub fn deny(mut self, byte: u8) -> Self {
+        self.0[byte as usize / 64] &= !(1 << (byte % 64));
+        self
+    }
+}
+
+impl Default for OpcodeSet {
+    fn default() -> Self {
+        OpcodeSet::all()
+    }
+}
+
+impl Op1 {
+    /// Build an op from its byte and the immediate the opcode table says it has.
+    pub fn from_parts(byte: u8, imm: Imm) -> Op1 {
+        match (byte, imm) {
+            (0x00, Imm::None) => Op1::Unique,
+            (0x01, Imm::None) => Op1::Handle,
+            (0x02, Imm::None) => Op1::I32,
+            (0x03, Imm::U8(n)) => Op1::Tuple(n),
+            (0x04, Imm::None) => Op1::Some,
+            (0x05, Imm::None) => Op1::All,
+            (0x06, Imm::None) => Op1::Rgn,
+            (0x07, Imm::None) => Op1::End,
+            (0x08, Imm::None) => Op1::App,
+            (0x09, Imm::U8(n)) => Op1::Func(n),
+            (0x0A, Imm::U8(n)) => Op1::CTGet(n),
+            (0x0B, Imm::None) => Op1::Lced,
+            (0x0C, Imm::None) => Op1::Unpack,
+            (0x0D, Imm::U8(n)) => Op1::Get(n),
+            (0x0E, Imm::U8(n)) => Op1::Init(n),
+            (0x0F, Imm::None) => Op1::Malloc,
+            (0x10, Imm::U8(n)) => Op1::Proj(n),
+            (0x11, Imm::None) => Op1::Call,
+            (0x13, Imm::I32(n)) => Op1::Lit(n),
+            (0x14, Imm::U32(n)) => Op1::GlobalFunc(n),
+            (0x15, Imm::None) => Op1::Halt,
+            (0x16, Imm::None) => Op1::Pack,
+            (0x17, Imm::U32(n)) => Op1::Size(n),
+            (0x18, Imm::U32(n)) => Op1::NewRgn(n),
+            (0x19, Imm::None) => Op1::FreeRgn,
+            (0x1A, Imm::None) => Op1::Ptr,
+            (0x1B, Imm::None) => Op1::Deref,
+            (0x1C, Imm::None) => Op1::Arr,
+            (0x1D, Imm::None) => Op1::ArrMut,
+            (0x1E, Imm::None) => Op1::ArrProj,
+            (0x1F, Imm::None) => Op1::Add,
+            (0x20, Imm::None) => Op1::Mul,
+            (0x21, Imm::None) => Op1::Div,
+            (0x22, Imm::None) => Op1::CallNZ,
+            (0x23, Imm::U32(n)) => Op1::Data(n),
+            (0x24, Imm::None) => Op1::DataSec,
+            (0x25, Imm::None) => Op1::U8,
+            (0x26, Imm::None) => Op1::CopyN,
+            (0x27, Imm::U8(n)) => Op1::U8Lit(n),
+            (0x28, Imm::None) => Op1::U8ToI32,
+            (0x29, Imm::Name(a, b)) => Op1::Import(a, b),
+            (0x2A, Imm::Name(a, b)) => Op1::Export(a, b),
+            (0x2B, Imm::None) => Op1::Modulo,
+            (0x2C, Imm::None) => Op1::I32ToU8,
+            (0x2D, Imm::U8(n)) => Op1::Read(n),
+            (0x2E, Imm::U8(n)) => Op1::Write(n),
+            (0x2F, Imm::None) => Op1::Yield,
+            (0x30, Imm::U32(n)) => Op1::HostCall(n),
+            (0x31, Imm::U8(n)) => Op1::TupleFields(n),
+            (0x32, Imm::U8(d)) => Op1::Field(d),
+            (0x33, Imm::U32(k)) => Op1::Named(k),
+            (0x34, Imm::U32(k)) => Op1::Fold(k),
+            (0x35, Imm::None) => Op1::Unfold,
+            (0x36, Imm::None) => Op1::SomeRgn,
+            (0x37, Imm::U8(n)) => Op1::Share(n),
+            (0x38, Imm::None) => Op1::AddTrap,
+            (0x39, Imm::None) => Op1::MulTrap,
+            (0x3A, Imm::None) => Op1::DivTrap,
+            (0x3B, Imm::None) => Op1::ModuloTrap,
+            (0x3C, Imm::None) => Op1::Sub,
+            (0x3D, Imm::None) => Op1::SubTrap,
+            (0x3E, Imm::None) => Op1::AddSat,
+            (0x3F, Imm::None) => Op1::SubSat,
+            (0x40, Imm::None) => Op1::MulSat,
+            (0x41, Imm::None) => Op1::AddChecked,
+            (0x42, Imm::None) => Op1::SubChecked,
+            (0x43, Imm::None) => Op1::MulChecked,
+            (0x44, Imm::None) => Op1::Nop,
+            (0x45, Imm::U32(n)) => Op1::Marker(n),
+            (0x46, Imm::None) => Op1::BigInt,
+            (0x47, Imm::U32(n)) => Op1::BigData(n),
+            (0x48, Imm::None) => Op1::BigFromI32,
+            (0x49, Imm::None) => Op1::BigAdd,
+            (0x4A, Imm::None) => Op1::BigSub,
+            (0x4B, Imm::None) => Op1::BigMul,
+            (0x4C, Imm::None) => Op1::BigDiv,
+            (0x4D, Imm::None) => Op1::BigModulo,
+            (0x4E, Imm::None) => Op1::BigCmp,
+            (0x4F, Imm::None) => Op1::BigToI32,
+            (0x50, Imm::None) => Op1::Buf,
+            (0x51, Imm::None) => Op1::BufNew,
+            (0x52, Imm::None) => Op1::BufLen,
+            (0x53, Imm::None) => Op1::BufGetU8,
+            (0x54, Imm::None) => Op1::BufSetU8,
+            (0x55, Imm::None) => Op1::BufGetU32,
+            (0x56, Imm::None) => Op1::BufSetU32,
+            (0x57, Imm::None) => Op1::Map,
+            (0x58, Imm::None) => Op1::MapGet,
+            (0x59, Imm::None) => Op1::MapSet,
+            (0x5A, Imm::None) => Op1::MapHas,
+            (0x5B, Imm::None) => Op1::MapRemove,
+            (0x5C, Imm::None) => Op1::MapLen,
+            (0x5D, Imm::U32(n)) => Op1::Res(n),
+            (0x5E, Imm::U32(n)) => Op1::HostRes(n),
+            (byte, imm) => unreachable!("the opcode table disagrees with Op1 about {:#04x} with {:?}", byte, imm),
+        }
+    }
+
+    /// The op's byte in the bytecode format.
+    pub fn byte(&self) -> u8 {
+        match self {
+            Op1::Unique => 0x00,
+            Op1::Handle => 0x01,
+            Op1::I32 => 0x02,
+            Op1::Tuple(_) => 0x03,
+            Op1::Some => 0x04,
+            Op1::All => 0x05,
+            Op1::Rgn => 0x06,
+            Op1::End => 0x07,
+            Op1::App => 0x08,
+            Op1::Func(_) => 0x09,
+            Op1::CTGet(_) => 0x0A,
+            Op1::Lced => 0x0B,
+            Op1::Unpack => 0x0C,
+            Op1::Get(_) => 0x0D,
+            Op1::Init(_) => 0x0E,
+            Op1::Malloc => 0x0F,
+            Op1::Proj(_) => 0x10,
+            Op1::Call => 0x11,
+            Op1::Lit(_) => 0x13,
+            Op1::GlobalFunc(_) => 0x14,
+            Op1::Halt => 0x15,
+            Op1::Pack => 0x16,
+            Op1::Size(_) => 0x17,
+            Op1::NewRgn(_) => 0x18,
+            Op1::FreeRgn => 0x19,
+            Op1::Ptr => 0x1A,
+            Op1::Deref => 0x1B,
+            Op1::Arr => 0x1C,
+            Op1::ArrMut => 0x1D,
+            Op1::ArrProj => 0x1E,
+            Op1::Add => 0x1F,
+            Op1::Mul => 0x20,
+            Op1::Div => 0x21,
+            Op1::CallNZ => 0x22,
+            Op1::Data(_) => 0x23,
+            Op1::DataSec => 0x24,
+            Op1::U8 => 0x25,
+            Op1::CopyN => 0x26,
+            Op1::U8Lit(_) => 0x27,
+            Op1::U8ToI32 => 0x28,
+            Op1::Import(_, _) => 0x29,
+            Op1::Export(_, _) => 0x2A,
+            Op1::Modulo => 0x2B,
+            Op1::I32ToU8 => 0x2C,
+            Op1::Read(_) => 0x2D,
+            Op1::Write(_) => 0x2E,
+            Op1::Yield => 0x2F,
+            Op1::HostCall(_) => 0x30,
+            Op1::TupleFields(_) => 0x31,
+            Op1::Field(_) => 0x32,
+            Op1::Named(_) => 0x33,
+            Op1::Fold(_) => 0x34,
+            Op1::Unfold => 0x35,
+            Op1::SomeRgn => 0x36,
+            Op1::Share(_) => 0x37,
+            Op1::AddTrap => 0x38,
+            Op1::MulTrap => 0x39,
+            Op1::DivTrap => 0x3A,
+            Op1::ModuloTrap => 0x3B,
+            Op1::Sub => 0x3C,
+            Op1::SubTrap => 0x3D,
+            Op1::AddSat => 0x3E,
+            Op1::SubSat => 0x3F,
+            Op1::MulSat => 0x40,
+            Op1::AddChecked => 0x41,
+            Op1::SubChecked => 0x42,
+            Op1::MulChecked => 0x43,
+            Op1::Nop => 0x44,
+            Op1::Marker(_) => 0x45,
+            Op1::BigInt => 0x46,
+            Op1::BigData(_) => 0x47,
+            Op1::BigFromI32 => 0x48,
+            Op1::BigAdd => 0x49,
+            Op1::BigSub => 0x4A,
+            Op1::BigMul => 0x4B,
+            Op1::BigDiv => 0x4C,
+            Op1::BigModulo => 0x4D,
+            Op1::BigCmp => 0x4E,
+            Op1::BigToI32 => 0x4F,
+            Op1::Buf => 0x50,
+            Op1::BufNew => 0x51,
+            Op1::BufLen => 0x52,
+            Op1::BufGetU8 => 0x53,
+            Op1::BufSetU8 => 0x54,
+            Op1::BufGetU32 => 0x55,
+            Op1::BufSetU32 => 0x56,
+            Op1::Map => 0x57,
+            Op1::MapGet => 0x58,
+            Op1::MapSet => 0x59,
+            Op1::MapHas => 0x5A,
+            Op1::MapRemove => 0x5B,
+            Op1::MapLen => 0x5C,
+            Op1::Res(_) => 0x5D,
+            Op1::HostRes(_) => 0x5E,
+        }
+    }
+
+    /// The optional part of the instruction set the op is from, which the program's feature header has to ask for.
+    pub fn feature(&self) -> Option<Feature> {
+        match self {
+            Op1::BigInt
+            | Op1::BigData(_)
+            | Op1::BigFromI32
+            | Op1::BigAdd
+            | Op1::BigSub
+            | Op1::BigMul
+            | Op1::BigDiv
+            | Op1::BigModulo
+            | Op1::BigCmp
+            | Op1::BigToI32 => Some(Feature::BigInts),
+            Op1::Map | Op1::MapGet | Op1::MapSet | Op1::MapHas | Op1::MapRemove | Op1::MapLen => Some(Feature::Maps),
+            _ => None,
+        }
+    }
+
+    /// The op's entry in the opcode table.
+    pub fn info(&self) -> &'static OpInfo {
+        op_info(self.byte()).expect("every Op1 is in the opcode table")
+    }
+
+    /// Append the op in the bytecode format: its byte, then its immediate.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.push(self.byte());
+        self.imm().write(out);
+    }
+
+    /// The op's immediate.
+    pub fn imm(&self) -> Imm {
+        match self {
+            Op1::Tuple(n) => Imm::U8(*n),
+            Op1::Func(n) => Imm::U8(*n),
+            Op1::CTGet(n) => Imm::U8(*n),
+            Op1::Get(n) | Op1::Share(n) => Imm::U8(*n),
+            Op1::Init(n) => Imm::U8(*n),
+            Op1::Proj(n) => Imm::U8(*n),
+            Op1::Lit(n) => Imm::I32(*n),
+            Op1::GlobalFunc(n) => Imm::U32(*n),
+            Op1::Size(n) => Imm::U32(*n),
+            Op1::NewRgn(n) => Imm::U32(*n),
+            Op1::Data(n) => Imm::U32(*n),
+            Op1::U8Lit(n) => Imm::U8(*n),
+            Op1::Import(a, b) => Imm::Name(*a, *b),
+            Op1::Export(a, b) => Imm::Name(*a, *b),
+            Op1::Read(n) => Imm::U8(*n),
+            Op1::Write(n) => Imm::U8(*n),
+            Op1::HostCall(n) => Imm::U32(*n),
+            Op1::TupleFields(n) => Imm::U8(*n),
+            Op1::Field(d) => Imm::U8(*d),
+            Op1::Named(k) => Imm::U32(*k),
+            Op1::Fold(k) => Imm::U32(*k),
+            Op1::Marker(n) => Imm::U32(*n),
+            Op1::BigData(n) => Imm::U32(*n),
+            Op1::Res(n) => Imm::U32(*n),
+            Op1::HostRes(n) => Imm::U32(*n),
+            _ => Imm::None,
+        }
+    }
+}
+
+/// What an arithmetic op does when the result doesn't fit in its type.
+/// `add`, `sub`, `mul`, `div`, and `modulo` wrap, and their `_trap` versions trap.
+/// Dividing by zero is a `Trap::DivideByZero` either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Overflow {
+    /// Wrap around, in two's complement for i32s, so `i32::MIN / -1` is `i32::MIN` and `i32::MIN % -1` is 0.
+    Wrap,
+    /// Stop the run with `Trap::Overflow`.
+    Trap,
+}
+
+/// Which arithmetic a saturating or checked op does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+/// The type of verified ops.
+/// The static analysis ops are gone, and the verifier has worked out every byte offset and size,
+/// so the VM never needs to know about types or field indices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Op2 {
+    /// The value's offset in bytes down from the top of the stack, and its size.
     Get(usize, usize),
+    /// The field's offset in the tuple, the field's size, and the size of the whole tuple.
     Init(usize, usize, usize),
+    /// The field's offset in the pointed-to tuple, and the field's size.
     InitIP(usize, usize),
     Malloc(usize),
     Alloca(usize),
+    /// The field's offset in the tuple, the field's size, and the size of the whole tuple.
     Proj(usize, usize, usize),
+    /// The field's offset in the pointed-to tuple, and the field's size.
     ProjIP(usize, usize),
     Call,
     // Print,
@@ -91,26 +621,66 @@ pub enum Op2 {
     NewArr(usize),
     ArrMut(usize),
     ArrProj(usize),
-    AddI32,
-    MulI32,
-    DivI32,
+    AddI32(Overflow),
+    MulI32(Overflow),
+    DivI32(Overflow),
     CallNZ,
     Data(usize),
     DataIndex(usize),
     CopyN(usize),
     U8Lit(u8),
-    AddU8,
-    MulU8,
+    AddU8(Overflow),
+    MulU8(Overflow),
+    /// Unsigned division can't overflow, so there's only one kind of u8 `div` and `modulo`.
     DivU8,
     U8ToI32,
-    ModuloI32,
+    ModuloI32(Overflow),
     ModuloU8,
     I32ToU8,
     Read(u8),
     Write(u8),
+    Yield,
+    HostCall(u32),
+    SubI32(Overflow),
+    SubU8(Overflow),
+    /// Clamp the result to the type's smallest or largest value instead of overflowing.
+    SatI32(ArithOp),
+    SatU8(ArithOp),
+    /// Push the wrapped result, then an i32 that's 1 if it fit and 0 if it overflowed.
+    CheckedI32(ArithOp),
+    CheckedU8(ArithOp),
+    Nop,
+    /// A number the compiler chose, like a statement's, for debuggers and coverage tools.
+    /// The VM skips it, but it stays in linked code, so `Module::marker` can find it.
+    Marker(u32),
+    /// The offset of the literal in the data section.
+    BigData(usize),
+    BigFromI32,
+    BigAdd,
+    BigSub,
+    BigMul,
+    BigDiv,
+    BigModulo,
+    BigCmp,
+    BigToI32,
+    BufNew,
+    BufLen,
+    BufGetU8,
+    BufSetU8,
+    BufGetU32,
+    BufSetU32,
+    /// The size of the values, like the other map ops, since a map's entries are laid out next to each other.
+    NewMap(usize),
+    MapGet(usize),
+    MapSet(usize),
+    MapHas(usize),
+    MapRemove(usize),
+    MapLen,
+    /// The host function and the kind of resource it makes.
+    HostRes(u32, u32),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Visibility {
     Local,
     Export(u64, u64),
@@ -122,6 +692,23 @@ pub enum ForwardDec {
     Func(Pos, Visibility, Vec<Op1>),
 }
 
+/// A declaration from the type section (see `Feature::TypeDecls`): the type's index, its visibility, and the ops that build it.
+#[derive(Debug)]
+pub enum TypeDec {
+    Type(u32, Visibility, Vec<Op1>),
+}
+
+/// A named type, after its declaration has been checked.
+/// Named types are nominal: a `Type::Named` is only ever the same as itself, never its definition,
+/// and programs go between the two with `fold` and `unfold`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamedType {
+    pub visibility: Visibility,
+    pub size: usize,
+    /// What the name stands for, or `None` if the type is abstract, as imported types always are.
+    pub definition: Option<Type>,
+}
+
 /// Statements produced by the parsing pass.
 /// Next they would go through the verification pass.
 #[derive(Debug)]
@@ -130,9 +717,24 @@ pub enum Stmt1 {
 }
 
 /// Statements produced by the verification pass.
+/// A function's host sites are in the order of its `host_call`s and `host_res`s.
 #[derive(Debug)]
 pub enum Stmt2 {
-    Func(Pos, Type, Vec<Op2>),
+    Func(Pos, Type, Vec<Op2>, Vec<HostSite>),
+}
+
+/// What the verifier knew at a `host_call` (or a `host_res`), once it took the argument off the stack,
+/// so host functions can look at the rest of the stack safely (see `guest::GuestView`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostSite {
+    /// The index of the `host_call` in the function's ops.
+    pub op: usize,
+    /// The host function it calls.
+    pub host_fn: u32,
+    /// The types of the values on the stack, from the bottom up.
+    pub stack: Vec<Type>,
+    /// The regions that were live, which are the only ones a host function can look into.
+    pub regions: Vec<RgnId>,
 }
 
 pub struct IRProgram {
@@ -148,43 +750,151 @@ pub enum RgnId {
     DataSection,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Region {
     pub unique: bool,
     pub id: RgnId,
 }
 
+/// The bit of a `field` descriptor that makes the component mutable.
+pub const FIELD_MUTABLE: u8 = 0b001;
+/// The bits of a `field` descriptor that give the component's representation.
+/// Only 0, where the component is stored flat inside the tuple, is defined so far. The bits above these are reserved.
+pub const FIELD_REPR: u8 = 0b110;
+
+/// One component of a tuple type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    /// Whether the component holds a value yet. Allocated tuples start out with none, and `init` fills them in.
+    pub init: bool,
+    /// Whether `init` can overwrite the component once it holds a value (see `FIELD_MUTABLE`).
+    pub mutable: bool,
+    pub t: Type,
+}
+
+impl Field {
+    /// An initialized, immutable component, which is what `tuple` makes.
+    pub fn new(t: Type) -> Field {
+        Field {
+            init: true,
+            mutable: false,
+            t,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Type {
     I32,
     U8,
     Handle(Region),
-    Tuple(Vec<(bool, Type)>),
+    Tuple(Vec<Field>),
     Ptr(Box<Type>, Region),
     Var(Id, usize),
     Func(Vec<Type>),
     Forall(Id, usize, Box<Type>),
     ForallRegion(Region, Box<Type>, Vec<Region>),
     Exists(Id, usize, Box<Type>),
+    /// A value that owns a region, like a data structure with the region it lives in and that region's handle.
+    /// The region is hidden, and each `unpack` gives a fresh unique one, so a value of this type can only be moved.
+    ExistsRegion(Region, Box<Type>),
     Array(Box<Type>, Region),
+    /// The type declared at some index in the type section, and its size.
+    Named(u32, usize),
+    /// A big integer in a region, which can't be changed once it's made (see `bigint`).
+    BigInt(Region),
+    /// A buffer of raw bytes in a region, which can be read and written a `u8` or a little-endian `u32` at a time.
+    /// It's laid out like a `u8` array, so `read` and `write` take either.
+    Buffer(Region),
+    /// A hash map in a region from `i32` keys to values of a type, which is changed in place like an array (see `Feature::Maps`).
+    Map(Box<Type>, Region),
+    /// A resource of the host's, like a file or a socket, of the kind the host gave this number.
+    /// Only a `host_res` of a host function that makes that kind can make one, so programs can't forge them
+    /// or pass one kind off as another. It lives in a region, so it can't be used once the region is freed.
+    Resource(u32, Region),
 }
 
 impl Type {
+    /// The number of bytes a value of this type takes up in the VM, which decides allocation sizes and field offsets.
+    /// Tuples are laid out flat, with no padding, so these must match the C structs in `vm.h`.
     pub fn size(&self) -> usize {
         match self {
             Self::I32 => 4,
             Self::U8 => 1,
             Self::Handle(_r) => 8,
-            Self::Tuple(ts) => ts.iter().map(|(_, t)| t.size()).sum(),
+            Self::Tuple(ts) => ts.iter().map(|field| field.t.size()).sum(),
             Self::Ptr(_t, _r) => 16,
             Self::Var(_id, s) => *s,
             Self::Func(_param_ts) => 4,
             Self::Forall(_id, _size, t) => t.size(),
             Self::ForallRegion(_r, t, _captured_rgns) => t.size(),
             Self::Exists(_id, _size, t) => t.size(),
+            Self::ExistsRegion(_r, t) => t.size(),
             Self::Array(_t, _r) => 16,
+            Self::Named(_k, s) => *s,
+            Self::BigInt(_r) => 16,
+            Self::Buffer(_r) => 16,
+            Self::Map(_t, _r) => 16,
+            Self::Resource(_kind, _r) => 16,
         }
     }
+
+    /// How deeply other types nest in this one: 0 for `i32`, 1 for `(i32)` or `(i32)@r`, and so on.
+    /// Named types count as 0, since their definitions are only looked at when they're folded or unfolded.
+    /// This works through an explicit worklist instead of recursing, so it's safe on a type of any depth.
+    pub fn depth<'a>(&'a self) -> usize {
+        if self.is_leaf() {
+            return 0;
+        }
+        // the verifier asks this about every type it makes, so leaves aren't put on the worklist,
+        // and the first child that isn't a leaf is walked into directly rather than pushed and popped
+        let mut deepest = 0;
+        let mut todo = vec![];
+        let mut next = Some((self, 0));
+        while let Some((t, depth)) = next.take().or_else(|| todo.pop()) {
+            deepest = deepest.max(depth);
+            let mut visit = |child: &'a Type| match child {
+                _ if child.is_leaf() => deepest = deepest.max(depth + 1),
+                _ if next.is_none() => next = Some((child, depth + 1)),
+                _ => todo.push((child, depth + 1)),
+            };
+            match t {
+                Self::Tuple(fields) => fields.iter().for_each(|field| visit(&field.t)),
+                Self::Func(param_ts) => param_ts.iter().for_each(visit),
+                Self::Ptr(t, _)
+                | Self::Array(t, _)
+                | Self::Map(t, _)
+                | Self::Forall(_, _, t)
+                | Self::ForallRegion(_, t, _)
+                | Self::Exists(_, _, t)
+                | Self::ExistsRegion(_, t) => visit(t),
+                Self::I32
+                | Self::U8
+                | Self::Handle(_)
+                | Self::Var(_, _)
+                | Self::Named(_, _)
+                | Self::BigInt(_)
+                | Self::Buffer(_)
+                | Self::Resource(_, _) => {}
+            }
+        }
+        deepest
+    }
+
+    /// Whether no other types nest in this one.
+    fn is_leaf(&self) -> bool {
+        matches!(
+            self,
+            Self::I32
+                | Self::U8
+                | Self::Handle(_)
+                | Self::Var(_, _)
+                | Self::Named(_, _)
+                | Self::BigInt(_)
+                | Self::Buffer(_)
+                | Self::Resource(_, _)
+        )
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -212,19 +922,334 @@ impl CTStackVal {
     }
 }
 
+/// What the verifier knows at some point in a function.
+/// The stacks are persistent, so each state in a trace shares what it can with the ones before it.
+#[derive(Clone, Debug, Default)]
+pub struct VerifierState {
+    pub compile_time_stack: Stack<CTStackVal>,
+    /// The types of the values on the runtime stack.
+    pub stack_type: Stack<Type>,
+    /// The regions that are live, and so can be used, here.
+    pub rgn_vars: Vec<Region>,
+}
+
+/// How the verifier's state changed over one instruction, as shown by `--explain`.
+#[derive(Clone, Debug)]
+pub struct Explained {
+    pub label: Label,
+    /// Whether this is from the function's forward declaration rather than its body.
+    pub forward_dec: bool,
+    pub pos: Pos,
+    pub op: Op1,
+    pub before: VerifierState,
+    pub after: VerifierState,
+}
+
 #[derive(Debug)]
 pub enum Quantification {
     Region(Region),
     Forall(Id, usize),
     Exist(Id, usize),
+    ExistRegion(Region),
+}
+
+/// Bounds on what the parser accepts, so pathological input can't make verification take forever or eat all memory.
+/// The defaults are far beyond what real programs need.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The size in bytes of one program file.
+    pub module_size: usize,
+    /// The number of declared functions in one program.
+    pub functions: usize,
+    /// The number of declared types in one program.
+    pub types: usize,
+    /// The number of ops in one forward declaration or function body.
+    pub body_len: usize,
+    /// How deeply `all`, `some`, and `rgn` quantifiers can nest.
+    pub quantifier_depth: usize,
+    /// How deeply the verifier lets types nest, counting each tuple, pointer, array, function, and quantifier
+    /// one level deeper than the types in it (see `Type::depth`).
+    /// The checker recurses over types, so this keeps adversarially nested ones from overflowing the host's stack.
+    pub type_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            module_size: 64 << 20,
+            functions: 1 << 16,
+            types: 1 << 16,
+            body_len: 1 << 20,
+            quantifier_depth: 256,
+            type_depth: 1024,
+        }
+    }
+}
+
+/// A way for embedders to give up on verifying untrusted code:
+/// at a deadline, when a flag is set from another thread, or both.
+/// The default never cancels.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    deadline: Option<Instant>,
+    flag: Option<Arc<AtomicBool>>,
+}
+
+impl Cancellation {
+    /// Cancel once `deadline` has passed.
+    pub fn at(deadline: Instant) -> Self {
+        Cancellation {
+            deadline: Some(deadline),
+            flag: None,
+        }
+    }
+
+    /// Cancel once `budget` has passed, starting now.
+    pub fn after(budget: Duration) -> Self {
+        Cancellation::at(Instant::now() + budget)
+    }
+
+    /// Also cancel as soon as `flag` is set.
+    pub fn with_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.flag = Some(flag);
+        self
+    }
+
+    pub fn check(&self) -> Result<(), Error> {
+        if self.flag.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+            return Err(Error::VerificationCancelled);
+        }
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(Error::VerificationTimedOut);
+        }
+        Ok(())
+    }
+}
+
+/// Which of the `Limits` was exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    ModuleSize,
+    Functions,
+    Types,
+    BodyLen,
+    QuantifierDepth,
+    TypeDepth,
+}
+
+/// The first four bytes of a program with a feature header.
+/// Read as the data section length of a program without one, this would be over a gigabyte,
+/// so older programs (which start with the data section length) can't be mistaken for newer ones.
+pub const FEATURE_HEADER_MAGIC: [u8; 4] = *b"\0SVM";
+
+/// Parts of the instruction set that a program can say it needs, by setting their bit in the feature header.
+/// A program with the header is `FEATURE_HEADER_MAGIC`, a little-endian u32 of feature bits, and then the usual program
+/// (after the lint config, the metadata, and the checksum, if their bits are set).
+/// Programs without the header need no features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    Floats,
+    Threads,
+    Exceptions,
+    /// Not part of the instruction set: the feature bits are followed by a little-endian u32 length
+    /// and that many bytes of lint config text (see `lint::LintLevels::apply_config`).
+    LintConfig,
+    /// Not part of the instruction set: the feature bits (and the lint config, if there is one) are followed by
+    /// the little-endian CRC-32 of the rest of the program, which is checked before anything else is read.
+    Checksum,
+    /// Not part of the instruction set: the number of functions is followed by a little-endian u32 number of named types,
+    /// and the ops start with that many type declarations, before the forward declarations (see `TypeDec`).
+    TypeDecls,
+    /// Not part of the instruction set: after the lint config, if there is one, comes a little-endian u32 length
+    /// and that many bytes of metadata text, saying what made the program (see `Metadata`).
+    Metadata,
+    /// Not part of the instruction set: after the number of functions (and of named types, if there is one)
+    /// comes a little-endian u32 number of function bodies, then each body's length in bytes as a little-endian u32.
+    /// The bodies take up the end of the program, so any one of them can be found and parsed without reading the others
+    /// (see `parse::body_ranges`). Without this, a body is found by parsing every op before it.
+    SizedBodies,
+    /// Not part of the instruction set: after the metadata, if there is any, comes a little-endian u32 length
+    /// and that many bytes of export names, each a little-endian u32 label, a little-endian u32 length, and that many bytes
+    /// of UTF-8 name, so a host can call a function by name (see `Instance::call`).
+    ExportNames,
+    /// Big integers, of any size, in regions: the `bigint` type and the `big_` ops (see `bigint`).
+    BigInts,
+    /// Hash maps from `i32` keys, in regions: the `map` type and the `map_` ops, with `malloc` making an empty one.
+    Maps,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 11] = [
+        Feature::Floats,
+        Feature::Threads,
+        Feature::Exceptions,
+        Feature::LintConfig,
+        Feature::Checksum,
+        Feature::TypeDecls,
+        Feature::Metadata,
+        Feature::SizedBodies,
+        Feature::ExportNames,
+        Feature::BigInts,
+        Feature::Maps,
+    ];
+
+    pub fn bit(self) -> u32 {
+        match self {
+            Feature::Floats => 1 << 0,
+            Feature::Threads => 1 << 1,
+            Feature::Exceptions => 1 << 2,
+            Feature::LintConfig => 1 << 3,
+            Feature::Checksum => 1 << 4,
+            Feature::TypeDecls => 1 << 5,
+            Feature::Metadata => 1 << 6,
+            Feature::SizedBodies => 1 << 7,
+            Feature::ExportNames => 1 << 8,
+            Feature::BigInts => 1 << 9,
+            Feature::Maps => 1 << 10,
+        }
+    }
+
+    /// Whether this build of SaberVM can run programs that need the feature.
+    pub fn supported(self) -> bool {
+        match self {
+            // none of these are implemented yet
+            Feature::Floats | Feature::Threads | Feature::Exceptions => false,
+            Feature::LintConfig
+            | Feature::Checksum
+            | Feature::TypeDecls
+            | Feature::Metadata
+            | Feature::SizedBodies
+            | Feature::ExportNames
+            | Feature::BigInts
+            | Feature::Maps => true,
+        }
+    }
+}
+
+/// What made a program, from its feature header (see `Feature::Metadata`), for telling where a bad module came from.
+/// The text is lines of `key value`, where the key is `name`, `producer`, or `version` and the value is the rest of the line.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The module's name.
+    pub name: Option<String>,
+    /// The compiler or tool that wrote the module, like `sabervm asm`.
+    pub producer: Option<String>,
+    /// The producer's version.
+    pub version: Option<String>,
+}
+
+impl Metadata {
+    pub const KEYS: [&'static str; 3] = ["name", "producer", "version"];
+
+    pub fn parse(text: &str) -> Result<Metadata, Error> {
+        let mut metadata = Metadata::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            match metadata.field_mut(key) {
+                Some(field) if !value.is_empty() => *field = Some(value.to_string()),
+                _ => return Err(Error::BadMetadata(line.to_string())),
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// The value for `key`, if it's one of `KEYS` and set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match key {
+            "name" => self.name.as_deref(),
+            "producer" => self.producer.as_deref(),
+            "version" => self.version.as_deref(),
+            _ => None,
+        }
+    }
+
+    fn field_mut(&mut self, key: &str) -> Option<&mut Option<String>> {
+        match key {
+            "name" => Some(&mut self.name),
+            "producer" => Some(&mut self.producer),
+            "version" => Some(&mut self.version),
+            _ => None,
+        }
+    }
+
+    /// Set `key` to `value`, or say why it can't be.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let line = format!("{} {}", key, value);
+        match self.field_mut(key) {
+            Some(field) if !value.trim().is_empty() && !value.contains('\n') => {
+                *field = Some(value.trim().to_string());
+                Ok(())
+            }
+            _ => Err(Error::BadMetadata(line)),
+        }
+    }
+
+    /// The metadata as it's written in the header, one `key value` line per field that's set.
+    pub fn text(&self) -> String {
+        let lines = Metadata::KEYS.iter().filter_map(|key| self.get(key).map(|value| format!("{} {}", key, value)));
+        lines.collect::<Vec<_>>().join("\n")
+    }
 }
 
 /// The type for user-facing errors (as opposed to internal SaberVM errors, which are panics).
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
     SyntaxErrorParamNeeded(Pos, u8),
     SyntaxErrorUnknownOp(Pos, u8),
-    TypeErrorMainHasArgs,
+    SyntaxErrorLabelOutOfRange(Pos, Label, usize),
+    /// How many `field`s the `tuple_fields` said would follow, and how many did.
+    SyntaxErrorFieldCount(Pos, u8, u8),
+    SyntaxErrorStrayField(Pos),
+    /// The limit, what it's set to, and the amount the input wanted. Big inputs only report the first limit they hit.
+    LimitExceeded(Limit, usize, usize),
+    UnsupportedFeature(Feature),
+    /// An op from an optional part of the instruction set that the program's feature header doesn't ask for.
+    FeatureNotEnabled(Pos, Op1, Feature),
+    /// The checksum in the feature header, and the checksum of the program's bytes.
+    ChecksumMismatch(u32, u32),
+    /// How many bodies the table of body sizes lists (see `Feature::SizedBodies`), and how many functions have bodies.
+    BodyCountMismatch(usize, usize),
+    /// The function whose body isn't the size the table says, and that size.
+    BodySizeMismatch(Label, u32),
+    /// How many ops come after the last function body when the bodies are sized, which they can't be, since they're found from the end.
+    OpsAfterSizedBodies(usize),
+    AsmUnknownMnemonic(usize, String),
+    AsmUnknownDirective(usize, String),
+    AsmBadImmediate(usize, String),
+    AsmWrongImmediateCount(usize, String, usize, usize),
+    AsmBadString(usize),
+    AsmOutsideFunction(usize),
+    AsmDuplicateBody(usize),
+    AsmDuplicateLabel(usize, String),
+    AsmUnknownLabel(usize, String),
+    AsmBadMacro(usize),
+    AsmIncludeNotFound(usize, String),
+    AsmInInclude(String, Box<Error>),
+    AsmIncludeCycle(usize, String),
+    AsmMacroTooDeep(usize, String),
+    UnknownFeatureBits(u32),
+    /// The name that isn't a lint or a lint level.
+    UnknownLint(String),
+    /// The line of lint config that doesn't say `level lint`.
+    BadLintConfig(String),
+    BadMetadata(String),
+    /// The export names run past the end of their section, or one isn't UTF-8.
+    BadExportNames,
+    /// A name that's given to more than one function in a module.
+    DuplicateExportName(String),
+    /// An export name, and the label it names, which isn't a function with a body.
+    ExportNameNotFunction(String, Label),
+    /// The line of a source map that doesn't say `marker file line [column]`.
+    BadSourceMap(String),
+    /// The function that uses an op the host turned off, and the op.
+    OpcodeNotAllowed(Label, Op1),
+    /// The name of the `VerifyPass` that rejected the program, and why.
+    RejectedByPass(String, String),
+    VerificationCancelled,
+    VerificationTimedOut,
+    /// The entry function takes something other than an `i32`.
+    TypeErrorEntryParam(Type),
     TypeErrorNonEmptyQuantificationStack(Label),
     TypeErrorEmptyQuantificationStack(Pos, Op1),
     TypeErrorEmptyCTStack(Pos, Op1),
@@ -237,34 +1262,205 @@ pub enum Error {
     RegionAccessError(Pos, Op1, Region),
     TypeErrorSpecificTypeVarExpected(Pos, Op1, Id, Id),
     TypeErrorTypeVarExpected(Pos, Op1, Id, Type),
-    TypeErrorCTGetOutOfRange(Pos, u8, usize),
+    TypeErrorCTGetOutOfRange(Pos, u8, Vec<CTStackVal>),
     TypeErrorGetOutOfRange(Pos, u8, usize),
     TypeErrorInitOutOfRange(Pos, u8, usize),
     TypeErrorProjOutOfRange(Pos, u8, usize),
     TypeErrorExistentialExpected(Pos, Op1, Type),
+    /// A type that owns a region (see `Type::ExistsRegion`) where it would be copied, put in memory, or used as a type argument.
+    TypeErrorOwnsRegion(Pos, Op1, Type),
+    /// A region package whose type doesn't hold its region's handle, so the region could never be freed.
+    RegionPackageWithoutHandle(Pos, Op1, Type),
+    /// A type that holds a region handle where it would be copied without a `share`: by a `get`, in memory, or as a type argument.
+    TypeErrorHandleCopy(Pos, Op1, Type),
     TypeErrorInitTypeMismatch(Pos, Type, Type),
     TypeErrorTupleExpected(Pos, Op1, Type),
     TypeErrorFunctionExpected(Pos, Op1, Type),
     TypeErrorRegionHandleExpected(Pos, Op1, Type),
     TypeErrorNotEnoughRuntimeArgs(Pos, usize, usize),
-    TypeErrorCallArgTypesMismatch(Pos, Vec<Type>, Vec<Type>),
+    TypeErrorCallArgMismatch(Pos, Op1, usize, Type, Type),
+    TypeErrorCallArgUninitialized(Pos, Op1, usize, Type),
+    TypeErrorNotEnoughCTArgs(Pos, Op1, Kind),
+    TypeErrorCallRegionNotLive(Pos, Op1, Region),
+    /// A region that isn't unique, given to a function that could free it.
+    TypeErrorCallRegionNotUnique(Pos, Op1, Region),
     TypeErrorMallocNonTuple(Pos, Op1, Type),
     TypeErrorPtrExpected(Pos, Op1, Type),
     TypeErrorForallExpected(Pos, Op1, Type),
     TypeErrorForallRegionExpected(Pos, Op1, Type),
     KindErrorBadApp(Pos, Op1, CTStackVal),
     TypeErrorDoubleInit(Pos, Op1, u8),
+    /// The index of the field in its tuple, and its descriptor.
+    TypeErrorBadField(Pos, Op1, u8, u8),
     TypeErrorUninitializedRead(Pos, Op1, u8),
     TooBigForStack(Pos, Op1, Type),
     ForwardDeclNotType(Type),
     ForwardDeclRuntimeOp(Op1),
     ForwardDeclBadStack(Vec<CTStackVal>),
+    /// The index of a type declaration that doesn't start with its size.
+    TypeDeclNeedsSize(u32),
+    TypeDeclBadStack(u32, Vec<CTStackVal>),
+    /// The index of the type declaration, the size it declares, and the size of its definition.
+    TypeDeclSizeMismatch(u32, usize, usize),
+    /// The index that isn't in the type section, and how many types there are.
+    UnknownNamedType(Pos, Op1, u32, usize),
+    TypeErrorAbstractType(Pos, Op1, u32),
+    TypeErrorNamedTypeExpected(Pos, Op1, Type),
     UnknownGlobalFunc(Pos, Op1, Label),
+    /// A function that's imported under a name no program in the module exports, and the name.
+    UnknownImport(Label, String),
+    /// The first program of the module has no function bodies, so there's no entry function to start at.
+    NoEntryFunction,
+    /// An op that can only end a declaration, like `lced`, in a function body.
+    DeclarationOpInBody(Pos, Op1),
     UnexpectedEOF,
     TypeErrorArrayExpected(Pos, Op1, Type),
+    TypeErrorBigIntExpected(Pos, Op1, Type),
+    TypeErrorBufferExpected(Pos, Op1, Type),
+    TypeErrorMapExpected(Pos, Op1, Type),
+    TypeErrorResourceExpected(Pos, Op1, Type),
     ReadOnlyRegionError(Pos, Op1, RgnId),
     DataSectionLoadOutOfBounds(Pos, Op1, usize, usize),
+    /// A `big_data` whose literal isn't laid out the way `bigint` says, and where in the data section it is.
+    BadBigIntLiteral(Pos, Op1, usize),
     InvalidDataSectionType(Pos, Op1, Type),
     CannotMutateDataSection(Pos, Op1),
-    UnknownChannel(Pos, Op1, u8)
+    UnknownChannel(Pos, Op1, u8),
+    /// The index of a host function, a function that calls it, the arguments the host function takes, and what was on the stack.
+    HostSignatureMismatch(u32, Label, String, Vec<Type>),
+}
+
+/// The ways a run can stop other than `halt`.
+/// These come from the VM at runtime, as opposed to `Error`s which come from parsing and verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    Interrupted,
+    UnknownHostFunction(u32),
+    AsyncHostFunction(u32),
+    /// Only in paranoid mode: the program read memory it never initialized, which means the verifier let something through.
+    UninitializedRead,
+    /// An array index or copy length was outside the array, or a new array's length was negative.
+    OutOfBounds,
+    /// A function a host function called back into halted, with this status, instead of giving a result with `yield`.
+    CallbackHalted(u8),
+    /// A callback called the host function that called it, which is still running.
+    ReentrantHostCall(u32),
+    /// The values on the stack at a call to this host function weren't what its Rust arguments needed.
+    HostSignature(u32),
+    /// Only in checked mode: the program used a region, or a pointer into one, after freeing it, which means the verifier let something through.
+    UseAfterFree,
+    /// Only in checked mode: the program freed a region that was already freed, which means the verifier let something through.
+    DoubleFree,
+    /// The result of a `_trap` arithmetic op didn't fit in its type (see `Overflow`).
+    Overflow,
+    /// A `div` or `modulo` by zero.
+    DivideByZero,
+    /// Only with `Verification::Lazy`: the program called this function, and its body doesn't verify.
+    /// `Module::verify_error` says why.
+    Unverified(Label),
+    /// A `malloc` needed more room than was left in its region.
+    RegionFull,
+    /// A `read` of stdin while another instance was reading it, which only one instance at a time can.
+    StdinBusy,
+    /// The host resumed an instance that wasn't stopped at a `yield`, so nothing ran.
+    NotSuspended,
+    /// The host gave the entry function, or a function it called back, a different number of arguments than this,
+    /// which is how many it takes, so nothing ran.
+    ArgCount(u32),
+    /// A host function called back a `GuestFn` from another module, or from the one an instance had before a `reload`, so nothing ran.
+    ForeignGuestFn,
+}
+
+/// Why `Instance::call` couldn't call an export, or the trap the call stopped with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallError {
+    /// No program in the module exports a function by this name (see `Feature::ExportNames`).
+    UnknownExport(String),
+    /// The export, and its type, which takes something other than `i32`s and `u8`s, so the host can't call it.
+    NotCallable(String, Type),
+    /// The export, its type, and the types of the arguments it was given, which don't fit it (see `guest::IntoArgs`).
+    ArgMismatch(String, Type, String),
+    Trap(Trap),
+}
+
+/// Why `Instance::reload` couldn't swap in a new version of its module (see `Module::check_reload`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReloadError {
+    /// The old module exports a function by this name, and the new one doesn't.
+    MissingExport(String),
+    /// An export, its type in the old module, and its different type in the new one.
+    ExportChanged(String, Type, Type),
+    /// How many arguments the entry function takes in the old module, and in the new one.
+    EntryChanged(usize, usize),
+    /// The new module calls a host function provided with `Instance::register_binding` with the wrong arguments:
+    /// the `Error::HostSignatureMismatch`.
+    Binding(Error),
+}
+
+/// Things about a valid program that are probably mistakes, from `lint::warnings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    /// How many ops come after the last function body, where nothing can run them.
+    TrailingOps(usize),
+    /// A function is quantified over a region that its type never mentions.
+    UnusedRegionBinding(Label),
+    /// A function is quantified over a type that its type never mentions.
+    UnusedTypeBinding(Label),
+    /// No function reachable from the entry point or an export refers to this function.
+    UnreachableFunction(Label),
+}
+
+/// How a run stopped, when it wasn't a trap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every task finished; this is the status given to `halt`.
+    Halted(u8),
+    /// The program ran `yield`. The run can be continued with `Instance::resume`.
+    Yielded(i32),
+}
+
+/// Read the next `N` bytes as the immediate of the op `byte` at `pos`.
+/// Every multi-byte number in the bytecode format is little-endian no matter the host,
+/// so the result goes to `from_le_bytes`; `Imm::read` does that for each kind of immediate.
+pub fn read_imm<'a, const N: usize>(
+    bytes: &mut impl Iterator<Item = &'a u8>,
+    pos: Pos,
+    byte: u8,
+) -> Result<[u8; N], Error> {
+    let mut imm = [0u8; N];
+    for b in imm.iter_mut() {
+        *b = *bytes.next().ok_or(Error::SyntaxErrorParamNeeded(pos, byte))?;
+    }
+    Ok(imm)
+}
+
+impl Imm {
+    /// Read an immediate of the given kind for the op `byte` at `pos`. The inverse of `Imm::write`.
+    pub fn read<'a>(kind: ImmKind, bytes: &mut impl Iterator<Item = &'a u8>, pos: Pos, byte: u8) -> Result<Imm, Error> {
+        Ok(match kind {
+            ImmKind::None => Imm::None,
+            ImmKind::U8 => Imm::U8(u8::from_le_bytes(read_imm(bytes, pos, byte)?)),
+            ImmKind::U32 => Imm::U32(u32::from_le_bytes(read_imm(bytes, pos, byte)?)),
+            ImmKind::I32 => Imm::I32(i32::from_le_bytes(read_imm(bytes, pos, byte)?)),
+            ImmKind::Name => {
+                let a = u64::from_le_bytes(read_imm(bytes, pos, byte)?);
+                let b = u64::from_le_bytes(read_imm(bytes, pos, byte)?);
+                Imm::Name(a, b)
+            }
+        })
+    }
+
+    /// Append the immediate in the bytecode format. The inverse of `Imm::read`.
+    pub fn write(self, out: &mut Vec<u8>) {
+        match self {
+            Imm::None => {}
+            Imm::U8(n) => out.extend(n.to_le_bytes()),
+            Imm::U32(n) => out.extend(n.to_le_bytes()),
+            Imm::I32(n) => out.extend(n.to_le_bytes()),
+            Imm::Name(a, b) => {
+                out.extend(a.to_le_bytes());
+                out.extend(b.to_le_bytes());
+            }
+        }
+    }
 }
@@ -0,0 +1,484 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Functions the host provides to programs, called with `host_call`.
+//!
+//! Any index can be given to `Instance::register_host_fn`, but the ones from 0x100 up are the standard profile, `svm_std`,
+//! so compilers targeting SaberVM can count on them instead of each inventing their own (see `STD_PROFILE`).
+//! Printing and reading aren't host functions, since the `write` and `read` ops already do them on channel 0.
+//! `Instance::allow_std` provides the whole profile, and `sabervm run` always does.
+
+use crate::guest::GuestView;
+use crate::header::Type;
+use crate::vm::Guest;
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::env;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::str;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// A function the host provides to programs, called with `host_call`.
+/// Like `yield`, a host function takes one i32 and gives one back.
+pub type HostFn = Box<dyn FnMut(i32) -> i32 + Send>;
+
+/// A host function that also looks at the program's stack, through a `GuestView`.
+pub type ViewingHostFn = Box<dyn FnMut(&GuestView, i32) -> i32 + Send>;
+
+/// A host function with Rust arguments, read from a `GuestView`, which gives `None` if they can't be read.
+pub type NativeHostFn = Box<dyn FnMut(&GuestView) -> Option<i32> + Send>;
+
+/// A host function with Rust arguments, and what's needed to check that programs call it right.
+/// `#[svm_host_fn]` writes one of these for a Rust function, and `Instance::register_binding` provides it to programs.
+#[derive(Clone, Copy)]
+pub struct HostBinding {
+    /// The Rust function's name.
+    pub name: &'static str,
+    /// Whether a `host_call` with these types under its argument can call the function (see `FromStack::accepts`).
+    pub accepts: fn(&[Type]) -> bool,
+    /// The arguments as a program should push them, like `(u8[], i32)`.
+    pub signature: fn() -> String,
+    /// Read the arguments from the view and call the function, or give `None` if they can't be read.
+    pub call: fn(&GuestView) -> Option<i32>,
+}
+
+/// A host function that can call back into the program, through a `Guest`.
+pub type CallingHostFn = Box<dyn FnMut(&mut Guest, i32) -> i32 + Send>;
+
+/// The future returned by an async host function.
+#[cfg(feature = "async")]
+pub type HostFuture = Pin<Box<dyn Future<Output = i32> + Send>>;
+
+/// A host function that finishes later.
+/// The instance stays suspended at the `host_call` until the future completes.
+#[cfg(feature = "async")]
+pub type AsyncHostFn = Box<dyn FnMut(i32) -> HostFuture + Send>;
+
+/// What the host does with one of its resources once the program is done with it, like closing a file, given the resource's id.
+pub type Finalizer = Box<dyn FnMut(i32) + Send>;
+
+pub(crate) enum Host {
+    Sync(HostFn),
+    Viewing(ViewingHostFn),
+    Native(NativeHostFn),
+    Calling(CallingHostFn),
+    /// A function for `host_res`, which gives its id for a new resource of this kind.
+    Minting(u32, NativeHostFn),
+    #[cfg(feature = "async")]
+    Async(AsyncHostFn),
+}
+
+/// A resource a `host_res` made that hasn't been finalized yet.
+struct OpenResource {
+    kind: u32,
+    id: i32,
+    /// The address of the region it's in, only to match it with the `free_rgn` that frees it.
+    region: u64,
+}
+
+/// The host functions an instance can call, by the index given to `host_call`,
+/// and the resources they've made that the program hasn't let go of yet.
+#[derive(Default)]
+pub(crate) struct HostFns {
+    fns: HashMap<u32, Host>,
+    /// The ones provided with `Instance::register_binding`, so they can be checked again against a reloaded module.
+    bindings: HashMap<u32, HostBinding>,
+    /// The host functions that are running callbacks, which are taken out of `fns` until they're done.
+    busy: Vec<u32>,
+    finalizers: HashMap<u32, Finalizer>,
+    /// Oldest first, so they're finalized from the end.
+    open: Vec<OpenResource>,
+}
+
+impl HostFns {
+    pub(crate) fn insert(&mut self, index: u32, f: Host) {
+        self.bindings.remove(&index);
+        self.fns.insert(index, f);
+    }
+
+    pub(crate) fn bind(&mut self, index: u32, binding: HostBinding) {
+        let call = binding.call;
+        self.insert(index, Host::Native(Box::new(call)));
+        self.bindings.insert(index, binding);
+    }
+
+    pub(crate) fn bindings(&self) -> impl Iterator<Item = (u32, &HostBinding)> {
+        self.bindings.iter().map(|(index, binding)| (*index, binding))
+    }
+
+    pub(crate) fn get_mut(&mut self, index: u32) -> Option<&mut Host> {
+        self.fns.get_mut(&index)
+    }
+
+    /// Take out a host function while it runs, so its callbacks can use the others.
+    pub(crate) fn take(&mut self, index: u32) -> Option<Host> {
+        let f = self.fns.remove(&index)?;
+        self.busy.push(index);
+        Some(f)
+    }
+
+    pub(crate) fn put_back(&mut self, index: u32, f: Host) {
+        self.busy.retain(|i| *i != index);
+        self.fns.insert(index, f);
+    }
+
+    pub(crate) fn is_busy(&self, index: u32) -> bool {
+        self.busy.contains(&index)
+    }
+
+    pub(crate) fn set_finalizer(&mut self, kind: u32, f: Finalizer) {
+        self.finalizers.insert(kind, f);
+    }
+
+    /// Remember a resource a `host_res` made in `region`, to finalize once it's freed or the run ends.
+    pub(crate) fn opened(&mut self, kind: u32, id: i32, region: u64) {
+        self.open.push(OpenResource { kind, id, region });
+    }
+
+    /// Finalize the resources in a region a `free_rgn` just freed, newest first.
+    pub(crate) fn finalize_region(&mut self, region: u64) {
+        self.finalize(|res| res.region == region);
+    }
+
+    /// Finalize every resource that's still open, newest first, when the run that made them is over.
+    pub(crate) fn finalize_all(&mut self) {
+        self.finalize(|_| true);
+    }
+
+    fn finalize(&mut self, pick: impl Fn(&OpenResource) -> bool) {
+        for i in (0..self.open.len()).rev() {
+            if !pick(&self.open[i]) {
+                continue;
+            }
+            let res = self.open.remove(i);
+            // a kind without a finalizer has nothing to close
+            if let Some(f) = self.finalizers.get_mut(&res.kind) {
+                f(res.id);
+            }
+        }
+    }
+}
+
+/// The `host_call` index of the host function that gives the length in bytes of an environment variable,
+/// given the variable's index in an `EnvAccess`, or -1 if it isn't set.
+pub const ENV_LEN: u32 = 0x100;
+
+/// The `host_call` index of the host function that gives one byte of an environment variable.
+/// Its argument is the variable's index times 65536 plus the byte's offset, and it gives -1 past the end.
+pub const ENV_BYTE: u32 = 0x101;
+
+/// The `host_call` index of the host function that gives a random number from 0 up to (not including) its argument,
+/// or any i32 if its argument isn't positive. See `Instance::allow_random`.
+pub const RANDOM: u32 = 0x102;
+
+/// The `host_call` index of the host function that reads the monotonic clock, in microseconds since the instance's clock was set.
+/// The time is 64 bits, so an argument of 0 reads the clock and gives the low half, and then 1 gives the high half of the same reading.
+pub const CLOCK_MONOTONIC: u32 = 0x103;
+
+/// The `host_call` index of the host function that reads the wall clock, in microseconds since the Unix epoch.
+/// It gives the time in halves, like `CLOCK_MONOTONIC`.
+pub const CLOCK_WALL: u32 = 0x104;
+
+/// The environment variables a program is allowed to read, with `Instance::allow_env`.
+/// Programs name a variable by its index in this list rather than by its name,
+/// so they can't read anything the host didn't list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvAccess {
+    names: Vec<String>,
+}
+
+impl EnvAccess {
+    pub fn new() -> EnvAccess {
+        EnvAccess::default()
+    }
+
+    /// Let programs read `name`, as the variable after all the ones allowed before it.
+    pub fn allow(mut self, name: &str) -> EnvAccess {
+        self.names.push(name.to_string());
+        self
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The allowed variables' values right now. A variable that isn't set, or isn't unicode, is `None`.
+    pub(crate) fn snapshot(&self) -> Strings {
+        Strings(self.names.iter().map(|name| env::var(name).ok().map(String::into_bytes)).collect())
+    }
+}
+
+/// Strings that programs read a byte at a time, by index, like environment variables and arguments.
+pub(crate) struct Strings(pub(crate) Vec<Option<Vec<u8>>>);
+
+impl Strings {
+    fn get(&self, index: i32) -> Option<&[u8]> {
+        self.0.get(usize::try_from(index).ok()?)?.as_deref()
+    }
+
+    /// What `ENV_LEN` and `ARG_LEN` give.
+    pub(crate) fn len_of(&self, index: i32) -> i32 {
+        self.get(index).map_or(-1, |bytes| bytes.len().try_into().unwrap_or(-1))
+    }
+
+    /// What `ENV_BYTE` and `ARG_BYTE` give.
+    pub(crate) fn byte(&self, arg: i32) -> i32 {
+        let byte = self.get(arg >> 16).and_then(|bytes| bytes.get((arg & 0xFFFF) as usize));
+        byte.map_or(-1, |b| (*b).into())
+    }
+}
+
+/// The random numbers behind `RANDOM`: a SplitMix64 generator, which is the same on every platform,
+/// so a run with the same seed gets the same numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn seeded(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// A seed that's different every time, for runs that don't need to be reproduced.
+    pub fn random_seed() -> u64 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos()));
+        hasher.finish()
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// What `RANDOM` gives.
+    pub(crate) fn below(&mut self, bound: i32) -> i32 {
+        let x = self.next_u64() >> 32;
+        match u64::try_from(bound) {
+            Ok(bound) if bound > 0 => ((x * bound) >> 32) as i32,
+            _ => x as u32 as i32,
+        }
+    }
+}
+
+/// The `host_call` index of the host function that gives how many string arguments the program was given.
+/// Its argument is ignored. See `Instance::allow_args`.
+pub const ARG_COUNT: u32 = 0x105;
+
+/// The `host_call` index of the host function that gives the length in bytes of a string argument, given its index, or -1 if there isn't one.
+pub const ARG_LEN: u32 = 0x106;
+
+/// The `host_call` index of the host function that gives one byte of a string argument.
+/// Its argument is the argument's index times 65536 plus the byte's offset, and it gives -1 past the end.
+pub const ARG_BYTE: u32 = 0x107;
+
+/// The `host_call` index of the host function that writes its argument in decimal into a buffer.
+/// Under the argument go the buffer and then the offset to write at, and it gives how many bytes it wrote,
+/// or -1 if they don't fit. See `Instance::allow_text`.
+pub const FMT_I32: u32 = 0x108;
+
+/// The `host_call` index of the host function that writes its argument's bits in lowercase hexadecimal, like `FMT_I32`.
+pub const FMT_HEX: u32 = 0x109;
+
+/// The `host_call` index of the host function that gives how many bytes of a buffer (or `u8` array), from the offset in its argument,
+/// are an `i32` in decimal: maybe a `-`, and then digits. It gives 0 if there isn't one there, or it doesn't fit in an `i32`.
+pub const SCAN_I32: u32 = 0x10A;
+
+/// The `host_call` index of the host function that gives the value of the decimal `SCAN_I32` finds, or 0 if it finds none.
+pub const PARSE_I32: u32 = 0x10B;
+
+/// The `host_call` index of the host function that gives how many bytes of a buffer (or `u8` array), from the offset in its argument,
+/// are valid UTF-8, so the bytes are all UTF-8 if it's the rest of the length.
+pub const UTF8_VALID: u32 = 0x10C;
+
+/// The host functions of `svm_std`, with the names compilers should know them by.
+pub const STD_PROFILE: [(u32, &str); 13] = [
+    (ENV_LEN, "env_len"),
+    (ENV_BYTE, "env_byte"),
+    (RANDOM, "random"),
+    (CLOCK_MONOTONIC, "clock_monotonic"),
+    (CLOCK_WALL, "clock_wall"),
+    (ARG_COUNT, "arg_count"),
+    (ARG_LEN, "arg_len"),
+    (ARG_BYTE, "arg_byte"),
+    (FMT_I32, "fmt_i32"),
+    (FMT_HEX, "fmt_hex"),
+    (SCAN_I32, "scan_i32"),
+    (PARSE_I32, "parse_i32"),
+    (UTF8_VALID, "utf8_valid"),
+];
+
+/// Everything `Instance::allow_std` needs to provide `svm_std`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StdProfile {
+    pub env: EnvAccess,
+    pub args: Vec<String>,
+    pub seed: u64,
+    pub clock: Clock,
+}
+
+impl StdProfile {
+    /// No environment variables or arguments, a fresh seed, and the host's clock.
+    pub fn new() -> StdProfile {
+        StdProfile {
+            env: EnvAccess::new(),
+            args: vec![],
+            seed: Rng::random_seed(),
+            clock: Clock::Real,
+        }
+    }
+}
+
+impl Default for StdProfile {
+    fn default() -> StdProfile {
+        StdProfile::new()
+    }
+}
+
+/// What time programs see through `CLOCK_MONOTONIC` and `CLOCK_WALL`, with `Instance::allow_clock`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clock {
+    /// The host's clocks.
+    Real,
+    /// Time stands still at this many microseconds since the Unix epoch, and the monotonic clock stays at 0.
+    Fixed(u64),
+    /// Time passes this many times as fast as the host's, starting from the host's wall clock.
+    Scaled(f64),
+}
+
+impl Clock {
+    /// The clock written as `real`, `fixed=MICROS`, or `scaled=FACTOR`.
+    pub fn parse(spec: &str) -> Option<Clock> {
+        match spec.split_once('=') {
+            None if spec == "real" => Some(Clock::Real),
+            Some(("fixed", micros)) => micros.parse().ok().map(Clock::Fixed),
+            Some(("scaled", factor)) => factor.parse().ok().filter(|f: &f64| *f >= 0.0).map(Clock::Scaled),
+            _ => None,
+        }
+    }
+}
+
+/// A `Clock` that's been started, for the host functions.
+#[derive(Clone)]
+pub(crate) struct Timer {
+    clock: Clock,
+    start: Instant,
+    wall_start: u64,
+    /// The last reading, for its high half.
+    reading: u64,
+}
+
+impl Timer {
+    pub(crate) fn new(clock: Clock) -> Timer {
+        Timer {
+            clock,
+            start: Instant::now(),
+            wall_start: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64),
+            reading: 0,
+        }
+    }
+
+    fn monotonic(&self) -> u64 {
+        let elapsed = self.start.elapsed().as_micros() as u64;
+        match self.clock {
+            Clock::Real => elapsed,
+            Clock::Fixed(_) => 0,
+            Clock::Scaled(factor) => (elapsed as f64 * factor) as u64,
+        }
+    }
+
+    fn wall(&self) -> u64 {
+        match self.clock {
+            Clock::Real => SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64),
+            Clock::Fixed(micros) => micros,
+            Clock::Scaled(_) => self.wall_start + self.monotonic(),
+        }
+    }
+
+    /// What `CLOCK_MONOTONIC` (or `CLOCK_WALL`, if `wall`) gives for `part`.
+    pub(crate) fn read(&mut self, wall: bool, part: i32) -> i32 {
+        if part == 0 {
+            self.reading = if wall { self.wall() } else { self.monotonic() };
+            self.reading as u32 as i32
+        } else {
+            (self.reading >> 32) as u32 as i32
+        }
+    }
+}
+
+/// What `FMT_I32` and `FMT_HEX` give, writing `text` into the buffer under the offset under the argument.
+/// A stack without a buffer and an offset there is `None`, which traps with `Trap::HostSignature`.
+fn write_text(view: &GuestView, text: &str) -> Option<i32> {
+    let buf = view.get(1)?;
+    let offset = view.get(0)?.as_i32()?;
+    let Type::Buffer(_) = buf.typ() else {
+        return None;
+    };
+    let len = buf.bytes()?.len();
+    match usize::try_from(offset) {
+        Ok(offset) if offset.checked_add(text.len()).is_some_and(|end| end <= len) => {
+            buf.write_bytes(offset, text.as_bytes())?;
+            Some(text.len() as i32)
+        }
+        _ => Some(-1),
+    }
+}
+
+/// What `FMT_I32` gives.
+pub(crate) fn fmt_i32(view: &GuestView) -> Option<i32> {
+    write_text(view, &view.arg().as_i32()?.to_string())
+}
+
+/// What `FMT_HEX` gives.
+pub(crate) fn fmt_hex(view: &GuestView) -> Option<i32> {
+    write_text(view, &format!("{:x}", view.arg().as_i32()?))
+}
+
+/// The bytes under the argument from the offset in the argument, which are empty if the offset is past the end.
+fn text_from<'a>(view: &'a GuestView) -> Option<&'a [u8]> {
+    let bytes = view.get(0)?.bytes()?;
+    let offset = usize::try_from(view.arg().as_i32()?).unwrap_or(usize::MAX);
+    Some(bytes.get(offset..).unwrap_or_default())
+}
+
+/// The decimal `i32` at the start of `text`, and how many bytes it is.
+fn scan_i32(text: &[u8]) -> Option<(i32, usize)> {
+    let sign = usize::from(text.first() == Some(&b'-'));
+    let digits = text[sign..].iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+    let len = sign + digits;
+    // the bytes are ASCII, so they're a str
+    let n = str::from_utf8(&text[..len]).ok()?.parse().ok()?;
+    Some((n, len))
+}
+
+/// What `SCAN_I32` gives.
+pub(crate) fn scan(view: &GuestView) -> Option<i32> {
+    Some(scan_i32(text_from(view)?).map_or(0, |(_, len)| len as i32))
+}
+
+/// What `PARSE_I32` gives.
+pub(crate) fn parse_i32(view: &GuestView) -> Option<i32> {
+    Some(scan_i32(text_from(view)?).map_or(0, |(n, _)| n))
+}
+
+/// What `UTF8_VALID` gives.
+pub(crate) fn utf8_valid(view: &GuestView) -> Option<i32> {
+    let text = text_from(view)?;
+    let valid = str::from_utf8(text).map_or_else(|e| e.valid_up_to(), |text| text.len());
+    Some(valid.try_into().unwrap_or(i32::MAX))
+}
@@ -0,0 +1,406 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The instructions the C VM runs, fully decoded.
+//!
+//! `Op1` is what the parser reads and `Op2` is what the verifier checked, still in terms of a single program:
+//! its calls name labels and its data offsets start at its own data section.
+//! An `Instr` is one `Op2` after linking, with every immediate at the width the VM reads it
+//! and every label and data offset resolved to a position in the module's code,
+//! so the VM's loop only ever has to copy fixed-width values out of the code.
+
+use crate::header::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    /// The value's offset in bytes down from the top of the stack, and its size.
+    Get(u64, u64),
+    /// The field's offset in the tuple, the field's size, and the size of the whole tuple.
+    Init(u64, u64, u64),
+    /// The field's offset in the pointed-to tuple, and the field's size.
+    InitIP(u64, u64),
+    Malloc(u64),
+    Alloca(u64),
+    /// The field's offset in the tuple, the field's size, and the size of the whole tuple.
+    Proj(u64, u64, u64),
+    /// The field's offset in the pointed-to tuple, and the field's size.
+    ProjIP(u64, u64),
+    Call,
+    Lit(i32),
+    /// Where the function starts in the module's code.
+    GlobalFunc(u32),
+    Halt,
+    NewRgn(u64),
+    FreeRgn,
+    Deref(u64),
+    NewArr(u64),
+    ArrMut(u64),
+    ArrProj(u64),
+    AddI32(Overflow),
+    MulI32(Overflow),
+    DivI32(Overflow),
+    CallNZ,
+    /// The offset into all the data sections together.
+    Data(u64),
+    DataIndex(u64),
+    CopyN(u64),
+    U8Lit(u8),
+    AddU8(Overflow),
+    MulU8(Overflow),
+    DivU8,
+    U8ToI32,
+    ModuloI32(Overflow),
+    ModuloU8,
+    I32ToU8,
+    Read(u8),
+    Write(u8),
+    Yield,
+    HostCall(u32),
+    SubI32(Overflow),
+    SubU8(Overflow),
+    SatI32(ArithOp),
+    SatU8(ArithOp),
+    CheckedI32(ArithOp),
+    CheckedU8(ArithOp),
+    Nop,
+    Marker(u32),
+    /// The offset of the literal into all the data sections together.
+    BigData(u64),
+    BigFromI32,
+    BigAdd,
+    BigSub,
+    BigMul,
+    BigDiv,
+    BigModulo,
+    BigCmp,
+    BigToI32,
+    BufNew,
+    BufLen,
+    BufGetU8,
+    BufSetU8,
+    BufGetU32,
+    BufSetU32,
+    NewMap(u64),
+    MapGet(u64),
+    MapSet(u64),
+    MapHas(u64),
+    MapRemove(u64),
+    MapLen,
+    /// The host function, and then the kind of resource it makes.
+    HostRes(u32, u32),
+}
+
+impl Instr {
+    /// Decode a verified op, given where each function it can name starts,
+    /// and where its program's data section starts among all of them.
+    /// This is `None` if it names a function `func_pos` doesn't know where to find.
+    pub fn link(op: &Op2, func_pos: &mut dyn FnMut(Label) -> Option<u32>, data_start: u64) -> Option<Instr> {
+        let w = |n: usize| n as u64;
+        Some(match *op {
+            Op2::Get(offset, size) => Instr::Get(w(offset), w(size)),
+            Op2::Init(offset, size, tpl_size) => Instr::Init(w(offset), w(size), w(tpl_size)),
+            Op2::InitIP(offset, size) => Instr::InitIP(w(offset), w(size)),
+            Op2::Malloc(size) => Instr::Malloc(w(size)),
+            Op2::Alloca(size) => Instr::Alloca(w(size)),
+            Op2::Proj(offset, size, tpl_size) => Instr::Proj(w(offset), w(size), w(tpl_size)),
+            Op2::ProjIP(offset, size) => Instr::ProjIP(w(offset), w(size)),
+            Op2::Call => Instr::Call,
+            Op2::Lit(lit) => Instr::Lit(lit),
+            Op2::GlobalFunc(label) => Instr::GlobalFunc(func_pos(label)?),
+            Op2::Halt => Instr::Halt,
+            Op2::NewRgn(size) => Instr::NewRgn(w(size)),
+            Op2::FreeRgn => Instr::FreeRgn,
+            Op2::Deref(size) => Instr::Deref(w(size)),
+            Op2::NewArr(size) => Instr::NewArr(w(size)),
+            Op2::ArrMut(size) => Instr::ArrMut(w(size)),
+            Op2::ArrProj(size) => Instr::ArrProj(w(size)),
+            Op2::AddI32(overflow) => Instr::AddI32(overflow),
+            Op2::MulI32(overflow) => Instr::MulI32(overflow),
+            Op2::DivI32(overflow) => Instr::DivI32(overflow),
+            Op2::CallNZ => Instr::CallNZ,
+            Op2::Data(offset) => Instr::Data(data_start + w(offset)),
+            Op2::DataIndex(size) => Instr::DataIndex(w(size)),
+            Op2::CopyN(size) => Instr::CopyN(w(size)),
+            Op2::U8Lit(n) => Instr::U8Lit(n),
+            Op2::AddU8(overflow) => Instr::AddU8(overflow),
+            Op2::MulU8(overflow) => Instr::MulU8(overflow),
+            Op2::DivU8 => Instr::DivU8,
+            Op2::U8ToI32 => Instr::U8ToI32,
+            Op2::ModuloI32(overflow) => Instr::ModuloI32(overflow),
+            Op2::ModuloU8 => Instr::ModuloU8,
+            Op2::I32ToU8 => Instr::I32ToU8,
+            Op2::Read(c) => Instr::Read(c),
+            Op2::Write(c) => Instr::Write(c),
+            Op2::Yield => Instr::Yield,
+            Op2::HostCall(f) => Instr::HostCall(f),
+            Op2::SubI32(overflow) => Instr::SubI32(overflow),
+            Op2::SubU8(overflow) => Instr::SubU8(overflow),
+            Op2::SatI32(arith) => Instr::SatI32(arith),
+            Op2::SatU8(arith) => Instr::SatU8(arith),
+            Op2::CheckedI32(arith) => Instr::CheckedI32(arith),
+            Op2::CheckedU8(arith) => Instr::CheckedU8(arith),
+            Op2::Nop => Instr::Nop,
+            Op2::Marker(n) => Instr::Marker(n),
+            Op2::BigData(offset) => Instr::BigData(data_start + w(offset)),
+            Op2::BigFromI32 => Instr::BigFromI32,
+            Op2::BigAdd => Instr::BigAdd,
+            Op2::BigSub => Instr::BigSub,
+            Op2::BigMul => Instr::BigMul,
+            Op2::BigDiv => Instr::BigDiv,
+            Op2::BigModulo => Instr::BigModulo,
+            Op2::BigCmp => Instr::BigCmp,
+            Op2::BigToI32 => Instr::BigToI32,
+            Op2::BufNew => Instr::BufNew,
+            Op2::BufLen => Instr::BufLen,
+            Op2::BufGetU8 => Instr::BufGetU8,
+            Op2::BufSetU8 => Instr::BufSetU8,
+            Op2::BufGetU32 => Instr::BufGetU32,
+            Op2::BufSetU32 => Instr::BufSetU32,
+            Op2::NewMap(size) => Instr::NewMap(w(size)),
+            Op2::MapGet(size) => Instr::MapGet(w(size)),
+            Op2::MapSet(size) => Instr::MapSet(w(size)),
+            Op2::MapHas(size) => Instr::MapHas(w(size)),
+            Op2::MapRemove(size) => Instr::MapRemove(w(size)),
+            Op2::MapLen => Instr::MapLen,
+            Op2::HostRes(f, kind) => Instr::HostRes(f, kind),
+        })
+    }
+
+    /// The byte the VM switches on, from the `switch` in vm.c.
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Instr::Get(_, _) => 0,
+            Instr::Init(_, _, _) => 1,
+            Instr::InitIP(_, _) => 2,
+            Instr::Malloc(_) => 3,
+            Instr::Alloca(_) => 4,
+            Instr::Proj(_, _, _) => 5,
+            Instr::ProjIP(_, _) => 6,
+            Instr::Call => 7,
+            // 8 was print
+            Instr::Lit(_) => 9,
+            Instr::GlobalFunc(_) => 10,
+            Instr::Halt => 11,
+            Instr::NewRgn(_) => 12,
+            Instr::FreeRgn => 13,
+            Instr::Deref(_) => 14,
+            Instr::NewArr(_) => 15,
+            Instr::ArrMut(_) => 16,
+            Instr::ArrProj(_) => 17,
+            Instr::AddI32(Overflow::Wrap) => 18,
+            Instr::MulI32(Overflow::Wrap) => 19,
+            Instr::DivI32(Overflow::Wrap) => 20,
+            Instr::CallNZ => 21,
+            Instr::Data(_) => 22,
+            Instr::DataIndex(_) => 23,
+            Instr::CopyN(_) => 24,
+            Instr::U8Lit(_) => 25,
+            Instr::AddU8(Overflow::Wrap) => 26,
+            Instr::MulU8(Overflow::Wrap) => 27,
+            Instr::DivU8 => 28,
+            Instr::U8ToI32 => 29,
+            Instr::ModuloI32(Overflow::Wrap) => 30,
+            Instr::ModuloU8 => 31,
+            Instr::I32ToU8 => 32,
+            Instr::Read(_) => 33,
+            Instr::Write(_) => 34,
+            Instr::Yield => 35,
+            Instr::HostCall(_) => 36,
+            Instr::AddI32(Overflow::Trap) => 37,
+            Instr::MulI32(Overflow::Trap) => 38,
+            Instr::DivI32(Overflow::Trap) => 39,
+            Instr::ModuloI32(Overflow::Trap) => 40,
+            Instr::AddU8(Overflow::Trap) => 41,
+            Instr::MulU8(Overflow::Trap) => 42,
+            Instr::SubI32(Overflow::Wrap) => 43,
+            Instr::SubI32(Overflow::Trap) => 44,
+            Instr::SubU8(Overflow::Wrap) => 45,
+            Instr::SubU8(Overflow::Trap) => 46,
+            Instr::SatI32(ArithOp::Add) => 47,
+            Instr::SatI32(ArithOp::Sub) => 48,
+            Instr::SatI32(ArithOp::Mul) => 49,
+            Instr::SatU8(ArithOp::Add) => 50,
+            Instr::SatU8(ArithOp::Sub) => 51,
+            Instr::SatU8(ArithOp::Mul) => 52,
+            Instr::CheckedI32(ArithOp::Add) => 53,
+            Instr::CheckedI32(ArithOp::Sub) => 54,
+            Instr::CheckedI32(ArithOp::Mul) => 55,
+            Instr::CheckedU8(ArithOp::Add) => 56,
+            Instr::CheckedU8(ArithOp::Sub) => 57,
+            Instr::CheckedU8(ArithOp::Mul) => 58,
+            Instr::Nop => 59,
+            Instr::Marker(_) => 60,
+            Instr::BigData(_) => 62,
+            Instr::BigFromI32 => 63,
+            Instr::BigAdd => 64,
+            Instr::BigSub => 65,
+            Instr::BigMul => 66,
+            Instr::BigDiv => 67,
+            Instr::BigModulo => 68,
+            Instr::BigCmp => 69,
+            Instr::BigToI32 => 70,
+            Instr::BufNew => 71,
+            Instr::BufLen => 72,
+            Instr::BufGetU8 => 73,
+            Instr::BufSetU8 => 74,
+            Instr::BufGetU32 => 75,
+            Instr::BufSetU32 => 76,
+            Instr::NewMap(_) => 77,
+            Instr::MapGet(_) => 78,
+            Instr::MapSet(_) => 79,
+            Instr::MapHas(_) => 80,
+            Instr::MapRemove(_) => 81,
+            Instr::MapLen => 82,
+            Instr::HostRes(_, _) => 83,
+        }
+    }
+
+    /// A name for each byte `opcode` gives, for profiles. 61 is the stub at the start of a lazily verified function.
+    pub fn opcode_name(opcode: u8) -> &'static str {
+        match opcode {
+            0 => "get",
+            1 => "init",
+            2 => "init_ip",
+            3 => "malloc",
+            4 => "alloca",
+            5 => "proj",
+            6 => "proj_ip",
+            7 => "call",
+            9 => "lit",
+            10 => "global_func",
+            11 => "halt",
+            12 => "new_rgn",
+            13 => "free_rgn",
+            14 => "deref",
+            15 => "new_arr",
+            16 => "arr_mut",
+            17 => "arr_proj",
+            18 => "add_i32",
+            19 => "mul_i32",
+            20 => "div_i32",
+            21 => "call_nz",
+            22 => "data",
+            23 => "data_index",
+            24 => "copy_n",
+            25 => "u8_lit",
+            26 => "add_u8",
+            27 => "mul_u8",
+            28 => "div_u8",
+            29 => "u8_to_i32",
+            30 => "mod_i32",
+            31 => "mod_u8",
+            32 => "i32_to_u8",
+            33 => "read",
+            34 => "write",
+            35 => "yield",
+            36 => "host_call",
+            37 => "add_i32_trap",
+            38 => "mul_i32_trap",
+            39 => "div_i32_trap",
+            40 => "mod_i32_trap",
+            41 => "add_u8_trap",
+            42 => "mul_u8_trap",
+            43 => "sub_i32",
+            44 => "sub_i32_trap",
+            45 => "sub_u8",
+            46 => "sub_u8_trap",
+            47 => "add_i32_sat",
+            48 => "sub_i32_sat",
+            49 => "mul_i32_sat",
+            50 => "add_u8_sat",
+            51 => "sub_u8_sat",
+            52 => "mul_u8_sat",
+            53 => "add_i32_checked",
+            54 => "sub_i32_checked",
+            55 => "mul_i32_checked",
+            56 => "add_u8_checked",
+            57 => "sub_u8_checked",
+            58 => "mul_u8_checked",
+            59 => "nop",
+            60 => "marker",
+            61 => "lazy_stub",
+            62 => "big_data",
+            63 => "big_from_i32",
+            64 => "big_add",
+            65 => "big_sub",
+            66 => "big_mul",
+            67 => "big_div",
+            68 => "big_mod",
+            69 => "big_cmp",
+            70 => "big_to_i32",
+            71 => "buf_new",
+            72 => "buf_len",
+            73 => "buf_get_u8",
+            74 => "buf_set_u8",
+            75 => "buf_get_u32",
+            76 => "buf_set_u32",
+            77 => "new_map",
+            78 => "map_get",
+            79 => "map_set",
+            80 => "map_has",
+            81 => "map_remove",
+            82 => "map_len",
+            83 => "host_res",
+            _ => "unknown",
+        }
+    }
+
+    /// The immediates, in the order the VM reads them.
+    fn immediates(&self) -> Vec<u64> {
+        match *self {
+            Instr::Get(a, b) | Instr::InitIP(a, b) | Instr::ProjIP(a, b) => vec![a, b],
+            Instr::Init(a, b, c) | Instr::Proj(a, b, c) => vec![a, b, c],
+            Instr::Malloc(a)
+            | Instr::Alloca(a)
+            | Instr::NewRgn(a)
+            | Instr::Deref(a)
+            | Instr::NewArr(a)
+            | Instr::ArrMut(a)
+            | Instr::ArrProj(a)
+            | Instr::Data(a)
+            | Instr::DataIndex(a)
+            | Instr::CopyN(a)
+            | Instr::BigData(a)
+            | Instr::NewMap(a)
+            | Instr::MapGet(a)
+            | Instr::MapSet(a)
+            | Instr::MapHas(a)
+            | Instr::MapRemove(a) => vec![a],
+            _ => vec![],
+        }
+    }
+
+    /// How many bytes the instruction takes up in linked code.
+    pub fn size(&self) -> usize {
+        let small = match self {
+            Instr::Lit(_) | Instr::GlobalFunc(_) | Instr::HostCall(_) | Instr::Marker(_) => 4,
+            Instr::U8Lit(_) | Instr::Read(_) | Instr::Write(_) => 1,
+            Instr::HostRes(_, _) => 8,
+            _ => 0,
+        };
+        1 + 8 * self.immediates().len() + small
+    }
+
+    // Linked code never leaves this process, and the C side reads immediates with `memcpy`,
+    // so unlike the bytecode format it uses the host's byte order.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.opcode());
+        for imm in self.immediates() {
+            out.extend(imm.to_ne_bytes());
+        }
+        match *self {
+            Instr::Lit(lit) => out.extend(lit.to_ne_bytes()),
+            Instr::GlobalFunc(pos) => out.extend(pos.to_ne_bytes()),
+            Instr::HostCall(f) | Instr::Marker(f) => out.extend(f.to_ne_bytes()),
+            Instr::U8Lit(n) | Instr::Read(n) | Instr::Write(n) => out.push(n),
+            Instr::HostRes(f, kind) => {
+                out.extend(f.to_ne_bytes());
+                out.extend(kind.to_ne_bytes());
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,537 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Hash-consed types, which the verifier keeps the types of the runtime stack in.
+//!
+//! A `Type` is a tree, so when the verifier kept those on its stack, copying a value copied its whole type,
+//! and checking two types were the same walked both of them, renaming bound variables as it went.
+//! A function that passed a deep type along paid for the type's size at every op.
+//! A `Types` arena keeps each distinct type once, with its parts as `TypeId`s into the same arena,
+//! so a type is copied in constant time, and what the verifier asks about the types it makes
+//! (their size, their depth, whether they own a region or hold a handle) is worked out once, when they're interned.
+//!
+//! Every type also has a canonical form, where a bound variable is named by how many binders out its binder is,
+//! and which leaves out what typechecking ignores: which regions a function has captured, and which region variables are unique.
+//! Types that typecheck as the same have the same canonical form, so `Types::eq` is comparing two numbers.
+
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use crate::header::*;
+use std::collections::HashMap;
+
+/// A type in a `Types` arena.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TypeId(u32);
+
+/// One component of an interned tuple type, like a `Field`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Component {
+    pub init: bool,
+    pub mutable: bool,
+    pub t: TypeId,
+}
+
+/// A `Type` one level deep, with the types in it interned.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Node {
+    I32,
+    U8,
+    Handle(Region),
+    Tuple(Vec<Component>),
+    Ptr(TypeId, Region),
+    Var(Id, usize),
+    Func(Vec<TypeId>),
+    Forall(Id, usize, TypeId),
+    ForallRegion(Region, TypeId, Vec<Region>),
+    Exists(Id, usize, TypeId),
+    ExistsRegion(Region, TypeId),
+    Array(TypeId, Region),
+    Named(u32, usize),
+    BigInt(Region),
+    Buffer(Region),
+    Map(TypeId, Region),
+    Resource(u32, Region),
+}
+
+/// The position canonical forms name bound variables with: `Id(BOUND, n)` is bound `n` binders out.
+/// A variable is named after the function or type declaration that made it, and no program has this many.
+const BOUND: Pos = Pos::MAX;
+
+/// The binder of a canonical form, and a region variable bound in one.
+fn bound_region(n: u32) -> Region {
+    Region {
+        unique: false,
+        id: RgnId::Var(Id(BOUND, n)),
+    }
+}
+
+fn is_bound(id: Id) -> bool {
+    id.0 == BOUND
+}
+
+/// Whether `r` is a variable that could be substituted or bound, which the data section can't be.
+fn region_is_open(r: &Region) -> bool {
+    matches!(r.id, RgnId::Var(id) if !is_bound(id))
+}
+
+/// A variable being bound, to make a canonical form.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Binder {
+    Type(Id),
+    Region(RgnId),
+}
+
+struct Entry {
+    node: Node,
+    canon: TypeId,
+    size: usize,
+    depth: usize,
+    /// Whether a variable is named in it, so a substitution could change it.
+    open: bool,
+    owns_region: bool,
+    holds_any_handle: bool,
+}
+
+/// The types an arena made, for other arenas to build on (see `Types::on`).
+#[derive(Default)]
+pub struct TypeArena {
+    entries: Vec<Entry>,
+    ids: HashMap<Node, TypeId>,
+}
+
+/// An arena of types, maybe on top of a finished one that it reads the types of but adds none to.
+/// The verifier interns every function's signature once, and each body is checked in an arena on top of those.
+pub struct Types<'a> {
+    base: Option<&'a TypeArena>,
+    own: TypeArena,
+}
+
+impl Types<'static> {
+    pub fn new() -> Self {
+        Types {
+            base: None,
+            own: TypeArena::default(),
+        }
+    }
+}
+
+impl Default for Types<'static> {
+    fn default() -> Self {
+        Types::new()
+    }
+}
+
+impl<'a> Types<'a> {
+    /// An arena that starts with every type in `base`.
+    pub fn on(base: &'a TypeArena) -> Self {
+        Types {
+            base: Some(base),
+            own: TypeArena::default(),
+        }
+    }
+
+    /// The types made, for building other arenas on. The arena can't be on another one.
+    pub fn finish(self) -> TypeArena {
+        debug_assert!(self.base.is_none());
+        self.own
+    }
+
+    fn base_len(&self) -> usize {
+        self.base.map_or(0, |base| base.entries.len())
+    }
+
+    fn entry(&self, t: TypeId) -> &Entry {
+        let i = t.0 as usize;
+        match self.base {
+            Some(base) if i < base.entries.len() => &base.entries[i],
+            _ => &self.own.entries[i - self.base_len()],
+        }
+    }
+
+    pub fn node(&self, t: TypeId) -> &Node {
+        &self.entry(t).node
+    }
+
+    /// The number of bytes a value of the type takes up, like `Type::size`.
+    pub fn size(&self, t: TypeId) -> usize {
+        self.entry(t).size
+    }
+
+    /// How deeply other types nest in the type, like `Type::depth`.
+    pub fn depth(&self, t: TypeId) -> usize {
+        self.entry(t).depth
+    }
+
+    /// Whether a value of the type owns a region, so copying it would give two owners.
+    /// Function types don't own anything, even if they take a region package.
+    pub fn owns_region(&self, t: TypeId) -> bool {
+        self.entry(t).owns_region
+    }
+
+    /// Whether a value of the type holds any region handle itself, rather than behind a pointer.
+    pub fn holds_any_handle(&self, t: TypeId) -> bool {
+        self.entry(t).holds_any_handle
+    }
+
+    /// Whether two types are the same, for typechecking purposes: whether they have the same canonical form.
+    pub fn eq(&self, t1: TypeId, t2: TypeId) -> bool {
+        self.entry(t1).canon == self.entry(t2).canon
+    }
+
+    fn find(&self, node: &Node) -> Option<TypeId> {
+        self.base
+            .and_then(|base| base.ids.get(node))
+            .or_else(|| self.own.ids.get(node))
+            .copied()
+    }
+
+    /// The type `node` describes, which is only made if it hasn't been already.
+    pub fn mk(&mut self, node: Node) -> TypeId {
+        if let Some(t) = self.find(&node) {
+            return t;
+        }
+        let canonical = self.canonical(&node);
+        // a canonical form is its own canonical form, so this only goes one deep
+        let canon = (canonical != node).then(|| self.mk(canonical));
+        let t = TypeId((self.base_len() + self.own.entries.len()) as u32);
+        let entry = self.describe(node.clone(), canon.unwrap_or(t));
+        self.own.entries.push(entry);
+        self.own.ids.insert(node, t);
+        t
+    }
+
+    /// Work out what's cached about a type from what's cached about its parts.
+    fn describe(&self, node: Node, canon: TypeId) -> Entry {
+        let (size, open, owns_region, holds_any_handle) = match &node {
+            Node::I32 => (4, false, false, false),
+            Node::U8 => (1, false, false, false),
+            Node::Handle(r) => (8, region_is_open(r), false, true),
+            Node::Tuple(fields) => (
+                fields.iter().map(|field| self.size(field.t)).sum(),
+                fields.iter().any(|field| self.entry(field.t).open),
+                fields.iter().any(|field| self.owns_region(field.t)),
+                fields.iter().any(|field| self.holds_any_handle(field.t)),
+            ),
+            Node::Ptr(t, r) | Node::Array(t, r) | Node::Map(t, r) => {
+                (16, self.entry(*t).open || region_is_open(r), false, false)
+            }
+            Node::Var(id, s) => (*s, !is_bound(*id), false, false),
+            Node::Func(param_ts) => (
+                4,
+                param_ts.iter().any(|t| self.entry(*t).open),
+                false,
+                false,
+            ),
+            Node::Forall(id, _, t) => (
+                self.size(*t),
+                !is_bound(*id) || self.entry(*t).open,
+                false,
+                false,
+            ),
+            Node::ForallRegion(r, t, captured_rgns) => (
+                self.size(*t),
+                region_is_open(r)
+                    || self.entry(*t).open
+                    || captured_rgns.iter().any(region_is_open),
+                false,
+                false,
+            ),
+            Node::Exists(id, _, t) => (
+                self.size(*t),
+                !is_bound(*id) || self.entry(*t).open,
+                self.owns_region(*t),
+                self.holds_any_handle(*t),
+            ),
+            Node::ExistsRegion(r, t) => (
+                self.size(*t),
+                region_is_open(r) || self.entry(*t).open,
+                true,
+                self.holds_any_handle(*t),
+            ),
+            Node::Named(_, s) => (*s, false, false, false),
+            Node::BigInt(r) | Node::Buffer(r) | Node::Resource(_, r) => {
+                (16, region_is_open(r), false, false)
+            }
+        };
+        let depth = self
+            .children(&node)
+            .map(|t| self.depth(t) + 1)
+            .max()
+            .unwrap_or(0);
+        Entry {
+            node,
+            canon,
+            size,
+            depth,
+            open,
+            owns_region,
+            holds_any_handle,
+        }
+    }
+
+    /// The types that nest directly in one.
+    fn children<'n>(&self, node: &'n Node) -> Box<dyn Iterator<Item = TypeId> + 'n> {
+        match node {
+            Node::Tuple(fields) => Box::new(fields.iter().map(|field| field.t)),
+            Node::Func(param_ts) => Box::new(param_ts.iter().copied()),
+            Node::Ptr(t, _)
+            | Node::Array(t, _)
+            | Node::Map(t, _)
+            | Node::Forall(_, _, t)
+            | Node::ForallRegion(_, t, _)
+            | Node::Exists(_, _, t)
+            | Node::ExistsRegion(_, t) => Box::new(std::iter::once(*t)),
+            _ => Box::new(std::iter::empty()),
+        }
+    }
+
+    /// The canonical form of `node`, given that its parts have been interned (so they have canonical forms already).
+    fn canonical(&mut self, node: &Node) -> Node {
+        let canon = |types: &Self, t: TypeId| types.entry(t).canon;
+        match node {
+            Node::Tuple(fields) => Node::Tuple(
+                fields
+                    .iter()
+                    .map(|field| Component {
+                        t: canon(self, field.t),
+                        ..*field
+                    })
+                    .collect(),
+            ),
+            Node::Ptr(t, r) => Node::Ptr(canon(self, *t), *r),
+            Node::Array(t, r) => Node::Array(canon(self, *t), *r),
+            Node::Map(t, r) => Node::Map(canon(self, *t), *r),
+            Node::Func(param_ts) => Node::Func(param_ts.iter().map(|t| canon(self, *t)).collect()),
+            Node::Forall(id, s, t) if !is_bound(*id) => {
+                let body = self.close(canon(self, *t), Binder::Type(*id));
+                Node::Forall(Id(BOUND, 0), *s, body)
+            }
+            Node::Exists(id, s, t) if !is_bound(*id) => {
+                let body = self.close(canon(self, *t), Binder::Type(*id));
+                Node::Exists(Id(BOUND, 0), *s, body)
+            }
+            Node::ForallRegion(r, t, _) if region_is_open(r) => {
+                let body = self.close(canon(self, *t), Binder::Region(r.id));
+                Node::ForallRegion(bound_region(0), body, vec![])
+            }
+            Node::ExistsRegion(r, t) if region_is_open(r) => {
+                let body = self.close(canon(self, *t), Binder::Region(r.id));
+                Node::ExistsRegion(bound_region(0), body)
+            }
+            node => node.clone(),
+        }
+    }
+
+    /// Name the variable `var` in the canonical form `t` by how many binders out it's bound, counting from just outside `t`.
+    fn close(&mut self, t: TypeId, var: Binder) -> TypeId {
+        self.close_at(t, var, 0, &mut HashMap::new())
+    }
+
+    fn close_at(
+        &mut self,
+        t: TypeId,
+        var: Binder,
+        depth: u32,
+        done: &mut HashMap<(TypeId, u32), TypeId>,
+    ) -> TypeId {
+        if !self.entry(t).open {
+            return t;
+        }
+        if let Some(closed) = done.get(&(t, depth)) {
+            return *closed;
+        }
+        let r = |r: Region| match var {
+            Binder::Region(id) if r.id == id => bound_region(depth),
+            _ => r,
+        };
+        let node = match self.node(t).clone() {
+            Node::Var(id, s) if var == Binder::Type(id) => Node::Var(Id(BOUND, depth), s),
+            Node::Handle(r2) => Node::Handle(r(r2)),
+            Node::BigInt(r2) => Node::BigInt(r(r2)),
+            Node::Buffer(r2) => Node::Buffer(r(r2)),
+            Node::Resource(kind, r2) => Node::Resource(kind, r(r2)),
+            Node::Tuple(fields) => Node::Tuple(
+                fields
+                    .into_iter()
+                    .map(|field| Component {
+                        t: self.close_at(field.t, var, depth, done),
+                        ..field
+                    })
+                    .collect(),
+            ),
+            Node::Ptr(t2, r2) => Node::Ptr(self.close_at(t2, var, depth, done), r(r2)),
+            Node::Array(t2, r2) => Node::Array(self.close_at(t2, var, depth, done), r(r2)),
+            Node::Map(t2, r2) => Node::Map(self.close_at(t2, var, depth, done), r(r2)),
+            Node::Func(param_ts) => Node::Func(
+                param_ts
+                    .into_iter()
+                    .map(|t2| self.close_at(t2, var, depth, done))
+                    .collect(),
+            ),
+            Node::Forall(id, s, t2) => Node::Forall(id, s, self.close_at(t2, var, depth + 1, done)),
+            Node::Exists(id, s, t2) => Node::Exists(id, s, self.close_at(t2, var, depth + 1, done)),
+            Node::ForallRegion(r2, t2, captured_rgns) => {
+                Node::ForallRegion(r2, self.close_at(t2, var, depth + 1, done), captured_rgns)
+            }
+            Node::ExistsRegion(r2, t2) => {
+                Node::ExistsRegion(r2, self.close_at(t2, var, depth + 1, done))
+            }
+            node => node,
+        };
+        let closed = self.mk(node);
+        done.insert((t, depth), closed);
+        closed
+    }
+
+    /// Intern a type and everything in it.
+    pub fn intern(&mut self, t: &Type) -> TypeId {
+        let node = match t {
+            Type::I32 => Node::I32,
+            Type::U8 => Node::U8,
+            Type::Handle(r) => Node::Handle(*r),
+            Type::Tuple(fields) => Node::Tuple(
+                fields
+                    .iter()
+                    .map(|field| Component {
+                        init: field.init,
+                        mutable: field.mutable,
+                        t: self.intern(&field.t),
+                    })
+                    .collect(),
+            ),
+            Type::Ptr(t, r) => Node::Ptr(self.intern(t), *r),
+            Type::Var(id, s) => Node::Var(*id, *s),
+            Type::Func(param_ts) => Node::Func(param_ts.iter().map(|t| self.intern(t)).collect()),
+            Type::Forall(id, s, t) => Node::Forall(*id, *s, self.intern(t)),
+            Type::ForallRegion(r, t, captured_rgns) => {
+                Node::ForallRegion(*r, self.intern(t), captured_rgns.clone())
+            }
+            Type::Exists(id, s, t) => Node::Exists(*id, *s, self.intern(t)),
+            Type::ExistsRegion(r, t) => Node::ExistsRegion(*r, self.intern(t)),
+            Type::Array(t, r) => Node::Array(self.intern(t), *r),
+            Type::Named(k, s) => Node::Named(*k, *s),
+            Type::BigInt(r) => Node::BigInt(*r),
+            Type::Buffer(r) => Node::Buffer(*r),
+            Type::Map(t, r) => Node::Map(self.intern(t), *r),
+            Type::Resource(kind, r) => Node::Resource(*kind, *r),
+        };
+        self.mk(node)
+    }
+
+    /// The type as a tree again, for error messages and everything outside the verifier.
+    pub fn resolve(&self, t: TypeId) -> Type {
+        match self.node(t) {
+            Node::I32 => Type::I32,
+            Node::U8 => Type::U8,
+            Node::Handle(r) => Type::Handle(*r),
+            Node::Tuple(fields) => Type::Tuple(
+                fields
+                    .iter()
+                    .map(|field| Field {
+                        init: field.init,
+                        mutable: field.mutable,
+                        t: self.resolve(field.t),
+                    })
+                    .collect(),
+            ),
+            Node::Ptr(t, r) => Type::Ptr(Box::new(self.resolve(*t)), *r),
+            Node::Var(id, s) => Type::Var(*id, *s),
+            Node::Func(param_ts) => Type::Func(param_ts.iter().map(|t| self.resolve(*t)).collect()),
+            Node::Forall(id, s, t) => Type::Forall(*id, *s, Box::new(self.resolve(*t))),
+            Node::ForallRegion(r, t, captured_rgns) => {
+                Type::ForallRegion(*r, Box::new(self.resolve(*t)), captured_rgns.clone())
+            }
+            Node::Exists(id, s, t) => Type::Exists(*id, *s, Box::new(self.resolve(*t))),
+            Node::ExistsRegion(r, t) => Type::ExistsRegion(*r, Box::new(self.resolve(*t))),
+            Node::Array(t, r) => Type::Array(Box::new(self.resolve(*t)), *r),
+            Node::Named(k, s) => Type::Named(*k, *s),
+            Node::BigInt(r) => Type::BigInt(*r),
+            Node::Buffer(r) => Type::Buffer(*r),
+            Node::Map(t, r) => Type::Map(Box::new(self.resolve(*t)), *r),
+            Node::Resource(kind, r) => Type::Resource(*kind, *r),
+        }
+    }
+
+    /// Substitute types for type variables and regions for region variables in `t`.
+    /// Binders are left alone, and every function type gets gone through has the unique regions substituted in added
+    /// to the ones it's captured, so it can't be given them again.
+    pub fn substitute(
+        &mut self,
+        t: TypeId,
+        tsubs: &HashMap<Id, TypeId>,
+        rsubs: &HashMap<RgnId, Region>,
+    ) -> TypeId {
+        self.substitute_in(t, tsubs, rsubs, &mut HashMap::new())
+    }
+
+    fn substitute_in(
+        &mut self,
+        t: TypeId,
+        tsubs: &HashMap<Id, TypeId>,
+        rsubs: &HashMap<RgnId, Region>,
+        done: &mut HashMap<TypeId, TypeId>,
+    ) -> TypeId {
+        if !self.entry(t).open {
+            return t;
+        }
+        if let Some(subbed) = done.get(&t) {
+            return *subbed;
+        }
+        let r = |r: Region| rsubs.get(&r.id).copied().unwrap_or(r);
+        let node = match self.node(t).clone() {
+            Node::Var(id, s) => match tsubs.get(&id) {
+                Some(subbed) => {
+                    done.insert(t, *subbed);
+                    return *subbed;
+                }
+                None => Node::Var(id, s),
+            },
+            Node::Handle(r2) => Node::Handle(r(r2)),
+            Node::BigInt(r2) => Node::BigInt(r(r2)),
+            Node::Buffer(r2) => Node::Buffer(r(r2)),
+            Node::Resource(kind, r2) => Node::Resource(kind, r(r2)),
+            Node::Tuple(fields) => Node::Tuple(
+                fields
+                    .into_iter()
+                    .map(|field| Component {
+                        t: self.substitute_in(field.t, tsubs, rsubs, done),
+                        ..field
+                    })
+                    .collect(),
+            ),
+            Node::Ptr(t2, r2) => Node::Ptr(self.substitute_in(t2, tsubs, rsubs, done), r(r2)),
+            Node::Array(t2, r2) => Node::Array(self.substitute_in(t2, tsubs, rsubs, done), r(r2)),
+            Node::Map(t2, r2) => Node::Map(self.substitute_in(t2, tsubs, rsubs, done), r(r2)),
+            Node::Func(param_ts) => Node::Func(
+                param_ts
+                    .into_iter()
+                    .map(|t2| self.substitute_in(t2, tsubs, rsubs, done))
+                    .collect(),
+            ),
+            Node::Forall(id, s, t2) => {
+                Node::Forall(id, s, self.substitute_in(t2, tsubs, rsubs, done))
+            }
+            Node::Exists(id, s, t2) => {
+                Node::Exists(id, s, self.substitute_in(t2, tsubs, rsubs, done))
+            }
+            Node::ExistsRegion(r2, t2) => {
+                Node::ExistsRegion(r2, self.substitute_in(t2, tsubs, rsubs, done))
+            }
+            Node::ForallRegion(r2, t2, mut captured_rgns) => {
+                captured_rgns.extend(rsubs.values().filter(|r| r.unique));
+                Node::ForallRegion(
+                    r2,
+                    self.substitute_in(t2, tsubs, rsubs, done),
+                    captured_rgns,
+                )
+            }
+            node => node,
+        };
+        let subbed = self.mk(node);
+        done.insert(t, subbed);
+        subbed
+    }
+}
@@ -0,0 +1,121 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The verified IR, for tools that want to analyze or optimize programs without verifying them again.
+//!
+//! `ir::verify` parses and verifies a program into an `IRProgram`, the same thing `Module::link` takes.
+//! Its functions can be walked with `IRProgram::functions` and `Function::instrs`,
+//! or all at once with a `Visitor`. Each instruction comes with its offset in the function's linked code,
+//! which is the same offset a `Location` has, so results can be matched up with traps and backtraces.
+//!
+//! An optimizer can rewrite `funcs` and link the result, but nothing checks the rewrite,
+//! so it's on the optimizer to keep the program safe.
+
+use crate::header::*;
+use crate::parse;
+use crate::verify;
+use crate::vm;
+
+/// Parse and verify one program.
+pub fn verify(bytes: &ByteStream) -> Result<IRProgram, Error> {
+    let (data_section, type_decs, forward_decs, stmts) = parse::go(bytes)?;
+    verify::go(data_section, type_decs, forward_decs, stmts)
+}
+
+/// One verified function.
+#[derive(Clone, Copy, Debug)]
+pub struct Function<'a> {
+    pub label: Label,
+    /// The function's elaborated type, as the verifier checked its body against it.
+    pub signature: &'a Type,
+    /// `Local` or `Export`; imports have no body, so they're never a `Function`.
+    pub visibility: Visibility,
+    pub ops: &'a [Op2],
+}
+
+/// One verified instruction, with how many bytes of linked code into its function it is.
+#[derive(Clone, Copy, Debug)]
+pub struct Instr<'a> {
+    pub offset: u32,
+    pub op: &'a Op2,
+}
+
+impl Op2 {
+    /// How many bytes the op takes up in linked code.
+    pub fn linked_len(&self) -> usize {
+        vm::op_len(self)
+    }
+}
+
+impl<'a> Function<'a> {
+    /// The instructions of the body, in order.
+    pub fn instrs(&self) -> impl Iterator<Item = Instr<'a>> + 'a {
+        self.ops.iter().scan(0, |offset, op| {
+            let instr = Instr { offset: *offset, op };
+            *offset += op.linked_len() as u32;
+            Some(instr)
+        })
+    }
+
+    /// How many bytes the body takes up in linked code.
+    pub fn linked_len(&self) -> usize {
+        self.ops.iter().map(Op2::linked_len).sum()
+    }
+}
+
+impl IRProgram {
+    /// The verified functions, in the order they're defined. The first is the entry point.
+    pub fn functions(&self) -> impl Iterator<Item = Function<'_>> {
+        self.funcs.iter().map(|Stmt2::Func(label, signature, ops, _)| {
+            let visibility = match self.exports.iter().find(|(_, l)| **l == *label) {
+                Some(((a, b), _)) => Visibility::Export(*a, *b),
+                None => Visibility::Local,
+            };
+            Function {
+                label: *label,
+                signature,
+                visibility,
+                ops,
+            }
+        })
+    }
+
+    /// The function with label `label`, if it has a body in this program.
+    pub fn function(&self, label: Label) -> Option<Function<'_>> {
+        self.functions().find(|f| f.label == label)
+    }
+}
+
+/// Something that looks at every part of a verified program, for `walk`.
+/// Each method does nothing by default, so a visitor only needs the ones it cares about.
+pub trait Visitor {
+    /// An imported function, with the number of the program and function it comes from.
+    fn visit_import(&mut self, _label: Label, _from: (u64, u64)) {}
+
+    /// A function, before its instructions.
+    fn visit_function(&mut self, _function: &Function) {}
+
+    fn visit_instr(&mut self, _function: &Function, _instr: Instr) {}
+
+    /// A function, after its instructions.
+    fn leave_function(&mut self, _function: &Function) {}
+}
+
+/// Show `visitor` the imports, in label order, then each function and its instructions.
+pub fn walk(program: &IRProgram, visitor: &mut impl Visitor) {
+    let mut imports = program.imports.iter().collect::<Vec<_>>();
+    imports.sort();
+    for (label, from) in imports {
+        visitor.visit_import(*label, *from);
+    }
+    for function in program.functions() {
+        visitor.visit_function(&function);
+        for instr in function.instrs() {
+            visitor.visit_instr(&function, instr);
+        }
+        visitor.leave_function(&function);
+    }
+}
@@ -0,0 +1,61 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+#![allow(
+    clippy::enum_variant_names,
+    clippy::needless_range_loop,
+    clippy::type_complexity,
+    clippy::result_large_err
+)]
+
+pub mod analyze;
+pub mod asm;
+pub mod bigint;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod checksum;
+pub mod coredump;
+pub mod diff;
+pub mod error_codes;
+pub mod gen;
+pub mod guest;
+pub mod header;
+pub mod pretty;
+#[cfg(feature = "profile")]
+pub mod profile;
+pub mod error_msgs;
+pub mod host;
+pub mod instr;
+pub mod intern;
+pub mod ir;
+pub mod lint;
+pub mod log;
+pub mod metrics;
+pub mod minimize;
+#[cfg(all(feature = "mmap", unix, target_pointer_width = "64"))]
+pub mod mmap;
+pub mod opt;
+pub mod parse;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod safepoint;
+pub mod sarif;
+pub mod stack;
+pub mod stream;
+pub mod testing;
+pub mod verify;
+pub mod vm;
+
+pub use coredump::CoreDump;
+#[cfg(feature = "macros")]
+pub use sabervm_macros::svm_host_fn;
+// so the bindings `#[svm_host_fn]` writes for `testing`, which name `::sabervm`, work in this crate too
+#[cfg(feature = "macros")]
+extern crate self as sabervm;
+pub use safepoint::{Paused, Safepoints};
+pub use vm::{Config, Guest, Instance, InterruptHandle, Location, Module, RegionArena, RegionStats, RegionStrategy, Verification};
@@ -0,0 +1,195 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Warnings about programs that verify but probably don't do what was meant.
+//! The verifier only says whether a program is safe, so these are checked separately, after it.
+//!
+//! Each kind of warning is a `Lint`, which can be allowed, left as a warning, or denied (made an error).
+//! The levels start at `warn`, then a program's own lint config (see `Feature::LintConfig`) can change them,
+//! and then the host can override that, like `sabervm check --deny unreachable-function` does.
+//! A lint config is lines of `level lint`, where `lint` can also be `warnings` for every lint, and `#` starts a comment.
+
+use crate::header::*;
+use crate::verify;
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    TrailingOps,
+    UnusedRegionBinding,
+    UnusedTypeBinding,
+    UnreachableFunction,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 4] = [
+        Lint::TrailingOps,
+        Lint::UnusedRegionBinding,
+        Lint::UnusedTypeBinding,
+        Lint::UnreachableFunction,
+    ];
+
+    /// The name used in lint configs and on the command line, like `unreachable-function`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::TrailingOps => "trailing-ops",
+            Lint::UnusedRegionBinding => "unused-region-binding",
+            Lint::UnusedTypeBinding => "unused-type-binding",
+            Lint::UnreachableFunction => "unreachable-function",
+        }
+    }
+}
+
+impl Warning {
+    pub fn lint(self) -> Lint {
+        match self {
+            Warning::TrailingOps(_) => Lint::TrailingOps,
+            Warning::UnusedRegionBinding(_) => Lint::UnusedRegionBinding,
+            Warning::UnusedTypeBinding(_) => Lint::UnusedTypeBinding,
+            Warning::UnreachableFunction(_) => Lint::UnreachableFunction,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LintLevel {
+    Allow,
+    #[default]
+    Warn,
+    Deny,
+}
+
+impl LintLevel {
+    pub fn parse(name: &str) -> Option<LintLevel> {
+        match name {
+            "allow" => Some(LintLevel::Allow),
+            "warn" => Some(LintLevel::Warn),
+            "deny" => Some(LintLevel::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// How seriously to take each lint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LintLevels {
+    levels: [LintLevel; Lint::ALL.len()],
+}
+
+impl LintLevels {
+    pub fn get(&self, lint: Lint) -> LintLevel {
+        self.levels[lint as usize]
+    }
+
+    /// Set the level of the lint called `name`, or of every lint if it's `warnings`.
+    pub fn set(&mut self, name: &str, level: LintLevel) -> Result<(), Error> {
+        if name == "warnings" {
+            self.levels = [level; Lint::ALL.len()];
+            return Ok(());
+        }
+        match Lint::ALL.into_iter().find(|lint| lint.name() == name) {
+            Some(lint) => {
+                self.levels[lint as usize] = level;
+                Ok(())
+            }
+            None => Err(Error::UnknownLint(name.to_string())),
+        }
+    }
+
+    /// Change the levels as a lint config says, one `level lint` line at a time.
+    pub fn apply_config(&mut self, config: &str) -> Result<(), Error> {
+        for line in config.lines() {
+            let code = line.split('#').next().unwrap().trim();
+            if code.is_empty() {
+                continue;
+            }
+            match code.split_whitespace().collect::<Vec<_>>()[..] {
+                [level, name] => match LintLevel::parse(level) {
+                    Some(level) => self.set(name, level)?,
+                    None => return Err(Error::UnknownLint(level.to_string())),
+                },
+                _ => return Err(Error::BadLintConfig(line.to_string())),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A variable bound by a quantifier.
+enum Bound {
+    Type(Id),
+    Region(RgnId),
+}
+
+/// Whether `bound` shows up anywhere in `t`.
+fn mentions(t: &Type, bound: &Bound) -> bool {
+    let in_region = |r: &Region| matches!(bound, Bound::Region(id) if r.id == *id);
+    match t {
+        Type::I32 | Type::U8 | Type::Named(_, _) => false,
+        Type::Var(id, _) => matches!(bound, Bound::Type(id2) if id == id2),
+        Type::Handle(r) | Type::BigInt(r) | Type::Buffer(r) | Type::Resource(_, r) => in_region(r),
+        Type::Ptr(t, r) | Type::Array(t, r) | Type::Map(t, r) => in_region(r) || mentions(t, bound),
+        Type::Tuple(ts) => ts.iter().any(|field| mentions(&field.t, bound)),
+        Type::Func(ts) => ts.iter().any(|t| mentions(t, bound)),
+        Type::Forall(_, _, t) | Type::Exists(_, _, t) | Type::ExistsRegion(_, t) => mentions(t, bound),
+        Type::ForallRegion(_, t, captured) => captured.iter().any(in_region) || mentions(t, bound),
+    }
+}
+
+/// The first unused quantifier in `t`, as a warning about function `label`.
+fn unused_binding(label: Label, t: &Type) -> Option<Warning> {
+    match t {
+        Type::Forall(id, _, body) if !mentions(body, &Bound::Type(*id)) => Some(Warning::UnusedTypeBinding(label)),
+        Type::ForallRegion(r, body, _) if !mentions(body, &Bound::Region(r.id)) => {
+            Some(Warning::UnusedRegionBinding(label))
+        }
+        Type::Forall(_, _, body) | Type::ForallRegion(_, body, _) => unused_binding(label, body),
+        _ => None,
+    }
+}
+
+/// The warnings for a parsed program, given how many ops came after its last function body (see `parse::go_with_trailing`).
+/// This assumes the program verifies, and errors only if its declarations don't.
+pub fn warnings(
+    type_decs: &[TypeDec],
+    forward_decs: &[ForwardDec],
+    stmts: &[Stmt1],
+    trailing: usize,
+) -> Result<Vec<Warning>, Error> {
+    let mut warnings = vec![];
+    if trailing > 0 {
+        warnings.push(Warning::TrailingOps(trailing));
+    }
+    let sigs = verify::signatures(type_decs, forward_decs)?;
+    warnings.extend(sigs.iter().filter_map(|(label, _, t)| unused_binding(*label, t)));
+    // everything the entry point or an export can get to with `global_func`
+    let bodies: HashMap<Label, &Vec<Op1>> = stmts.iter().map(|Stmt1::Func(l, _, ops)| (*l, ops)).collect();
+    let mut reachable = HashSet::new();
+    let mut todo = vec![];
+    if let Some(Stmt1::Func(main, _, _)) = stmts.first() {
+        todo.push(*main);
+    }
+    todo.extend(sigs.iter().filter(|(_, vis, _)| matches!(vis, Visibility::Export(_, _))).map(|(l, _, _)| *l));
+    while let Some(label) = todo.pop() {
+        if !reachable.insert(label) {
+            continue;
+        }
+        if let Some(ops) = bodies.get(&label) {
+            todo.extend(ops.iter().filter_map(|op| match op {
+                Op1::GlobalFunc(target) => Some(*target),
+                _ => None,
+            }));
+        }
+    }
+    // imports have no body to be dead
+    for Stmt1::Func(label, _, _) in stmts {
+        if !reachable.contains(label) {
+            warnings.push(Warning::UnreachableFunction(*label));
+        }
+    }
+    Ok(warnings)
+}
@@ -0,0 +1,203 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Structured logging for the parser, verifier, and VM.
+//!
+//! Every event has a level, the module it came from, and the spans it happened in,
+//! like `parse` or `verify > function(3)`. Nothing is logged unless a level is turned on,
+//! either by `RUST_LOG` (as in `RUST_LOG=debug` or `RUST_LOG=sabervm=trace`) or with `set_max_level`.
+//! Events go to stderr, unless an embedder sends them to its own telemetry with `set_logger`.
+
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Once, OnceLock};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// The level called `name`, like `debug`, in any case.
+    pub fn parse(name: &str) -> Option<Level> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// One thing that happened, as handed to a `Logger`.
+pub struct Record<'a> {
+    pub level: Level,
+    /// The module the event came from, like `sabervm::verify`.
+    pub target: &'static str,
+    /// The spans the event happened in, outermost first.
+    pub spans: &'a [Span],
+    pub message: fmt::Arguments<'a>,
+}
+
+/// A phase of work that events happen in, like verifying one function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub name: &'static str,
+    /// What the span is about, like the label of the function, or empty.
+    pub fields: String,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.fields.as_str() {
+            "" => write!(f, "{}", self.name),
+            fields => write!(f, "{}({})", self.name, fields),
+        }
+    }
+}
+
+/// Somewhere for events to go.
+pub trait Logger: Send + Sync {
+    fn log(&self, record: &Record);
+}
+
+/// The default logger, which writes each event as a line on stderr.
+struct Stderr;
+
+impl Logger for Stderr {
+    fn log(&self, record: &Record) {
+        let spans = record.spans.iter().map(Span::to_string).collect::<Vec<_>>();
+        match spans.is_empty() {
+            true => eprintln!("{:5} {}: {}", record.level.name(), record.target, record.message),
+            false => eprintln!(
+                "{:5} {} [{}]: {}",
+                record.level.name(),
+                record.target,
+                spans.join(" > "),
+                record.message
+            ),
+        }
+    }
+}
+
+/// The most detailed level that's logged, or 0 for nothing.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(0);
+static FROM_ENV: Once = Once::new();
+static LOGGER: OnceLock<Box<dyn Logger>> = OnceLock::new();
+
+thread_local! {
+    static SPANS: RefCell<Vec<Span>> = const { RefCell::new(vec![]) };
+}
+
+/// Log events up to `level`, or nothing if it's `None`. This overrides `RUST_LOG`.
+pub fn set_max_level(level: Option<Level>) {
+    // reading RUST_LOG later mustn't undo this
+    FROM_ENV.call_once(|| {});
+    MAX_LEVEL.store(level.map_or(0, |l| l as u8), Ordering::Relaxed);
+}
+
+/// Send events to `logger` instead of stderr.
+/// This can only be done once, and it returns false if a logger was already set.
+pub fn set_logger(logger: Box<dyn Logger>) -> bool {
+    LOGGER.set(logger).is_ok()
+}
+
+/// Read the level from `RUST_LOG`: the last of its comma-separated directives that's a bare level or `sabervm=level`.
+fn init_from_env() {
+    FROM_ENV.call_once(|| {
+        let Ok(directives) = env::var("RUST_LOG") else {
+            return;
+        };
+        let mut level = None;
+        for directive in directives.split(',') {
+            match directive.trim().split_once('=') {
+                None => level = Level::parse(directive.trim()).or(level),
+                Some((target, l)) if target == "sabervm" || target.starts_with("sabervm::") => {
+                    level = Level::parse(l).or(level)
+                }
+                Some(_) => {}
+            }
+        }
+        MAX_LEVEL.store(level.map_or(0, |l| l as u8), Ordering::Relaxed);
+    });
+}
+
+pub fn enabled(level: Level) -> bool {
+    init_from_env();
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Log an event, if `level` is on. Use the `event!` macro instead, which doesn't format anything when it's off.
+pub fn emit(level: Level, target: &'static str, message: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    SPANS.with(|spans| {
+        let record = Record {
+            level,
+            target,
+            spans: &spans.borrow(),
+            message,
+        };
+        match LOGGER.get() {
+            Some(logger) => logger.log(&record),
+            None => Stderr.log(&record),
+        }
+    });
+}
+
+/// Enter a span, which lasts until the guard is dropped. Leaving it logs how long it took, at `level`.
+/// If `level` is off, this does nothing, and `fields` isn't even called.
+pub fn span(level: Level, target: &'static str, name: &'static str, fields: impl FnOnce() -> String) -> SpanGuard {
+    if !enabled(level) {
+        return SpanGuard(None);
+    }
+    let fields = fields();
+    SPANS.with(|spans| spans.borrow_mut().push(Span { name, fields }));
+    SpanGuard(Some((level, target, Instant::now())))
+}
+
+#[must_use]
+pub struct SpanGuard(Option<(Level, &'static str, Instant)>);
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        if let Some((level, target, start)) = self.0 {
+            emit(level, target, format_args!("done in {:?}", start.elapsed()));
+            SPANS.with(|spans| spans.borrow_mut().pop());
+        }
+    }
+}
+
+/// `event!(Level::Debug, "verified {} functions", n)` logs from the module it's written in.
+macro_rules! event {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::emit($level, module_path!(), format_args!($($arg)+))
+        }
+    };
+}
+
+pub(crate) use event;
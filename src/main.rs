@@ -4,37 +4,1459 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-mod header;
-mod pretty;
-mod error_msgs;
-mod parse;
-mod verify;
-mod vm;
+#![allow(clippy::result_large_err)]
 
-use std::fs;
+use sabervm::header::Outcome;
+use sabervm::host::{Clock, StdProfile};
+use sabervm::pretty::Pretty;
+use sabervm::error_codes::{self, ErrorCode};
+use sabervm::{analyze, asm, diff, error_msgs, gen, header, lint, log, metrics, minimize, opt, parse, sarif, testing, verify};
+use sabervm::{Config, CoreDump, Instance, Location, Module, RegionArena, RegionStrategy, Verification};
+
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+fn main() {
+    let mut args = env::args().collect::<Vec<_>>();
+    // `--log-level` can go anywhere, and overrides RUST_LOG
+    if let Some(i) = args.iter().position(|arg| arg == "--log-level") {
+        let level = args.get(i + 1).map(String::as_str);
+        match level.map(|level| (level, log::Level::parse(level))) {
+            Some((_, Some(level))) => log::set_max_level(Some(level)),
+            Some(("off", None)) => log::set_max_level(None),
+            _ => {
+                println!("--log-level needs one of off, error, warn, info, debug, or trace");
+                exit(1);
+            }
+        }
+        args.drain(i..i + 2);
+    }
+    match args.get(1).map(String::as_str) {
+        Some("run") => run(&args[2..]),
+        Some("check") => check(&args[2..]),
+        Some("signatures") => signatures(&args[2..]),
+        Some("opcodes") => opcodes(),
+        Some("asm") => assemble(&args[2..]),
+        Some("gen") => generate(&args[2..]),
+        Some("disasm") => disassemble(&args[2..]),
+        Some("fmt") => format(&args[2..]),
+        Some("test") => test(&args[2..]),
+        Some("inspect-core") => inspect_core(&args[2..]),
+        Some("analyze") => analyze(&args[2..]),
+        Some("info") => info(&args[2..]),
+        Some("diff") => diff_modules(&args[2..]),
+        Some("equiv") => equiv(&args[2..]),
+        Some("opt") => optimize(&args[2..]),
+        Some("minimize") => minimize_crash(&args[2..]),
+        Some("explain") => explain_code(&args[2..]),
+        // like rustc's, `--explain E0404` explains a code, and otherwise it walks through how the programs verify
+        Some("--explain") if args.get(2).and_then(|arg| ErrorCode::parse(arg)).is_some() => explain_code(&args[2..]),
+        Some("--explain") => explain(&args[2..]),
+        _ => run(&args[1..]),
+    }
+}
+
+fn read_files(filenames: &[String]) -> Vec<header::ByteStream> {
+    filenames.iter().map(|filename| fs::read(filename).unwrap()).collect()
+}
+
+/// Parse, verify, link, and run the given programs together.
+/// `--paranoid` double-checks the verifier by trapping on reads of uninitialized memory.
+/// `--checked` double-checks it by trapping on double frees and uses of freed regions.
+/// `--lazy` verifies each function body of a program with sized bodies when it's first called (see `Verification::Lazy`).
+/// Otherwise, a program with sized bodies starts running once its declarations are checked, while another thread verifies
+/// all of its bodies; a body that doesn't verify stops the run, and its error is printed instead of however the run ended
+/// (with the position counted from the start of the body, as with `--lazy`).
+/// `--verify-first` verifies everything before running anything instead.
+/// `--call NAME` starts at the function exported as NAME instead of the entry function (see `Instance::call`).
+/// `--core FILE` writes a core dump to FILE if the program traps.
+/// `--allow-env NAME` lets the programs read the environment variable NAME (see `host::EnvAccess`).
+/// `--clock real`, `--clock fixed=MICROS`, or `--clock scaled=FACTOR` says what time programs see (see `host::Clock`).
+/// `--seed N` makes the random numbers from `host::RANDOM` the same as in any other run with the same seed.
+/// `--regions malloc`, `--regions pooled`, or `--regions adaptive` says how regions get their memory (see `RegionStrategy`).
+/// `--profile` (with the `profile` feature) prints how many times each opcode ran and about how long it took (see `profile.rs`),
+/// and what the regions cost.
+/// Anything after `--` is given to the programs as string arguments (see `host::ARG_LEN`),
+/// and if the entry function (or the one given to `--call`) takes arguments, they're passed to it too,
+/// so there have to be that many `i32`s.
+/// The programs get all of `svm_std` (see `host::STD_PROFILE`).
+fn run(args: &[String]) {
+    let mut paranoid = false;
+    let mut checked = false;
+    let mut verification = Verification::Eager;
+    let mut verify_first = false;
+    let mut regions = RegionArena::default();
+    let mut core_file = None;
+    let mut call = None;
+    #[cfg(feature = "profile")]
+    let mut profiling = false;
+    let mut profile = StdProfile::new();
+    let mut filenames = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--paranoid" => paranoid = true,
+            "--checked" => checked = true,
+            "--lazy" => verification = Verification::Lazy,
+            "--verify-first" => verify_first = true,
+            #[cfg(feature = "profile")]
+            "--profile" => profiling = true,
+            #[cfg(not(feature = "profile"))]
+            "--profile" => {
+                println!("--profile needs SaberVM to be built with the `profile` feature");
+                exit(1);
+            }
+            "--" => profile.args.extend(args.by_ref().cloned()),
+            "--call" => match args.next() {
+                Some(name) => call = Some(name.clone()),
+                None => {
+                    println!("--call needs the name of an exported function");
+                    exit(1);
+                }
+            },
+            "--core" => match args.next() {
+                Some(file) => core_file = Some(file),
+                None => {
+                    println!("--core needs a file to write to");
+                    exit(1);
+                }
+            },
+            "--regions" => match args.next().map(String::as_str) {
+                Some("malloc") => regions.strategy = RegionStrategy::Malloc,
+                Some("pooled") => regions.strategy = RegionStrategy::Pooled,
+                Some("adaptive") => regions.strategy = RegionStrategy::Adaptive,
+                _ => {
+                    println!("--regions needs malloc, pooled, or adaptive");
+                    exit(1);
+                }
+            },
+            "--clock" => match args.next().and_then(|spec| Clock::parse(spec)) {
+                Some(c) => profile.clock = c,
+                None => {
+                    println!("--clock needs real, fixed=MICROS, or scaled=FACTOR");
+                    exit(1);
+                }
+            },
+            "--seed" => match args.next().and_then(|n| n.parse::<u64>().ok()) {
+                Some(n) => profile.seed = n,
+                None => {
+                    println!("--seed needs a number");
+                    exit(1);
+                }
+            },
+            "--allow-env" => match args.next() {
+                Some(name) => profile.env = profile.env.allow(name),
+                None => {
+                    println!("--allow-env needs the name of a variable");
+                    exit(1);
+                }
+            },
+            _ => filenames.push(arg.clone()),
+        }
+    }
+    // bodies the run reaches before the background thread does are verified by the run itself
+    let pipelined = verification == Verification::Eager && !verify_first;
+    let config = Config {
+        verification: if pipelined { Verification::Lazy } else { verification },
+        regions,
+        ..Config::default()
+    };
+    match Module::with_config(read_files(&filenames), &config, &header::Cancellation::default(), &[]) {
+        Ok(module) => {
+            let mut entry_args = vec![];
+            // an export's arguments are checked by `Instance::call`
+            if call.is_some() || module.entry_params() > 0 {
+                if call.is_none() && module.entry_params() != profile.args.len() {
+                    println!(
+                        "The entry function takes {} arguments, but {} were given after --",
+                        module.entry_params(),
+                        profile.args.len()
+                    );
+                    exit(1);
+                }
+                for arg in &profile.args {
+                    match arg.parse::<i32>() {
+                        Ok(n) => entry_args.push(n),
+                        Err(_) => {
+                            println!("{} takes i32s, but got {}", call.as_deref().unwrap_or("The entry function"), arg);
+                            exit(1);
+                        }
+                    }
+                }
+            }
+            let module = Arc::new(module);
+            let mut instance = Instance::new(module.clone());
+            let background = pipelined.then(|| {
+                let interrupt = instance.interrupt_handle();
+                thread::spawn(move || {
+                    let res = module.verify_all();
+                    if res.is_err() {
+                        interrupt.interrupt();
+                    }
+                    res
+                })
+            });
+            instance.set_paranoid(paranoid);
+            instance.set_checked(checked);
+            instance.allow_std(&profile);
+            let mut res = match &call {
+                None => instance.run_with_args(&entry_args),
+                Some(name) => match instance.call(name, &entry_args[..]) {
+                    Ok(outcome) => Ok(outcome),
+                    Err(header::CallError::Trap(trap)) => Err(trap),
+                    Err(e) => {
+                        println!("{}", error_msgs::call_msg(e));
+                        exit(1);
+                    }
+                },
+            };
+            // the command line has no host to talk to, so every yield just gets its own value back
+            while let Ok(Outcome::Yielded(val)) = res {
+                res = instance.resume(val);
+            }
+            // an eager build wouldn't have run anything, so a body that doesn't verify beats however the run stopped
+            if let Some(Err(e)) = background.map(|t| t.join().unwrap()) {
+                println!("{}", error_msgs::msg(e));
+                return;
+            }
+            #[cfg(feature = "profile")]
+            if profiling {
+                print!("{}", instance.profile().table());
+                let stats = instance.region_stats();
+                println!(
+                    "regions: {} made, {} small, {} pooled, at most {} bytes of small ones live, {} bytes of chunks",
+                    stats.made, stats.small, stats.pooled, stats.small_peak, stats.chunk_bytes
+                );
+            }
+            match res {
+                Ok(Outcome::Halted(0)) => {}
+                Ok(Outcome::Halted(status)) => exit(status.into()),
+                Ok(Outcome::Yielded(_)) => unreachable!(),
+                Err(trap) => {
+                    println!("{}", error_msgs::trap_msg(trap));
+                    let dump = instance.core_dump().unwrap();
+                    if let Some(e) = instance.module().locate(dump.pc).and_then(|loc| instance.module().verify_error(loc)) {
+                        println!("{}", error_msgs::msg(e.clone()));
+                    }
+                    print_backtrace(instance.module(), &dump.backtrace(instance.module()));
+                    if let Some(file) = core_file {
+                        match fs::write(file, dump.to_bytes()) {
+                            Ok(()) => println!("Wrote a core dump to {}.", file),
+                            Err(e) => println!("Couldn't write a core dump to {}: {}", file, e),
+                        }
+                    }
+                    exit(1);
+                }
+            }
+        }
+        Err(e) => println!("{}", error_msgs::msg(e)),
+    }
+}
+
+/// Parse and verify the given programs without linking or running them.
+/// Given a directory, this checks every `.svm` file in it, and in its subdirectories too with `--recursive`,
+/// going on past the ones that fail, then prints a table of each file's result, time, and size,
+/// so a compiler's CI can check everything it built at once. It exits with 1 if any file failed.
+/// `--cache-dir DIR` (with the `cache` feature) skips functions that verified in an earlier check.
+/// `--watch` keeps checking the programs, and every `.svm` file in any directory given, each time one changes.
+/// `--metrics` prints the verifier's metrics afterwards, in the Prometheus text format.
+/// Warnings about valid programs are printed too. `--allow LINT`, `--warn LINT`, and `--deny LINT` override the
+/// program's own lint config, where `LINT` can be `warnings` for all of them, and `--deny-warnings` is `--deny warnings`.
+/// `--format sarif` prints every error and warning as one SARIF log instead, for code-scanning UIs (see `sarif.rs`).
+fn check(args: &[String]) {
+    let mut watching = false;
+    let mut recursive = false;
+    let mut as_sarif = false;
+    let mut lints = vec![];
+    let mut cache_dir = None;
+    let mut totals = None;
+    let mut paths = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => watching = true,
+            "--recursive" => recursive = true,
+            "--format" => match args.next().map(String::as_str) {
+                Some("sarif") => as_sarif = true,
+                Some("text") => as_sarif = false,
+                _ => {
+                    println!("--format needs to be text or sarif");
+                    exit(1);
+                }
+            },
+            "--deny-warnings" => lints.push(("warnings".to_string(), lint::LintLevel::Deny)),
+            "--allow" | "--warn" | "--deny" => {
+                let level = lint::LintLevel::parse(&arg[2..]).unwrap();
+                let Some(name) = args.next() else {
+                    println!("{} needs a lint name, or `warnings` for all of them", arg);
+                    exit(1);
+                };
+                // catch typos before checking anything
+                if let Err(e) = lint::LintLevels::default().set(name, level) {
+                    println!("{}", error_msgs::msg(e));
+                    exit(1);
+                }
+                lints.push((name.clone(), level));
+            }
+            "--metrics" => {
+                let t = Arc::new(metrics::Totals::default());
+                metrics::set_metrics(Box::new(t.clone()));
+                totals = Some(t);
+            }
+            "--cache-dir" => match args.next() {
+                Some(dir) => cache_dir = Some(dir),
+                None => {
+                    println!("--cache-dir needs a directory");
+                    exit(1);
+                }
+            },
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if as_sarif && (watching || totals.is_some()) {
+        println!("--format sarif prints only the SARIF log, so it can't go with --watch or --metrics");
+        exit(1);
+    }
+    if watching {
+        watch(&paths, cache_dir, &lints, recursive);
+    }
+    // a single file stops at the first failure, like a compiler, but a directory is checked all the way through,
+    // and so is everything for a SARIF log, which should have every problem
+    let batch = paths.iter().any(|path| Path::new(path).is_dir());
+    let mut failed = false;
+    let mut rows = vec![];
+    let mut diagnostics = vec![];
+    for file in files_in(&paths, "svm", recursive) {
+        let filename = file.display().to_string();
+        let start = Instant::now();
+        let (ok, size) = match fs::read(&file) {
+            Ok(bytes) if as_sarif => {
+                let found = sarif_diagnostics(&filename, &bytes, check_one(&bytes, cache_dir, &lints));
+                let ok = !found.iter().any(|d| d.level == lint::LintLevel::Deny);
+                diagnostics.extend(found);
+                (ok, bytes.len())
+            }
+            Ok(bytes) => match check_one(&bytes, cache_dir, &lints) {
+                Ok(warnings) => (print_warnings(&filename, &warnings), bytes.len()),
+                Err(e) => {
+                    println!("{}: {}", filename, error_msgs::msg(e));
+                    (false, bytes.len())
+                }
+            },
+            Err(e) if as_sarif => {
+                diagnostics.push(sarif::Diagnostic {
+                    rule: "UnreadableFile".to_string(),
+                    rule_name: None,
+                    level: lint::LintLevel::Deny,
+                    message: e.to_string(),
+                    file: filename.clone(),
+                    function: None,
+                    source: None,
+                });
+                (false, 0)
+            }
+            Err(e) => {
+                println!("{}: {}", filename, e);
+                (false, 0)
+            }
+        };
+        rows.push((filename, ok, start.elapsed(), size));
+        if !ok {
+            failed = true;
+            if !batch && !as_sarif {
+                break;
+            }
+        }
+    }
+    if as_sarif {
+        println!("{}", sarif::log(&diagnostics));
+    } else if batch {
+        print_summary(&rows);
+    }
+    if let Some(totals) = totals {
+        print_metrics(&totals);
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+/// The SARIF results for checking `bytes` from `filename`, using the source map next to it if there is one.
+fn sarif_diagnostics(
+    filename: &str,
+    bytes: &[u8],
+    checked: Result<Vec<(header::Warning, lint::LintLevel)>, header::Error>,
+) -> Vec<sarif::Diagnostic> {
+    let mut diagnostics = vec![];
+    let map_file = filename.to_string() + ".map";
+    let source_map = match fs::read_to_string(&map_file).map(|text| sarif::SourceMap::parse(&text)) {
+        Ok(Ok(source_map)) => Some(source_map),
+        Ok(Err(e)) => {
+            diagnostics.push(sarif::error(&map_file, &[], e, None));
+            None
+        }
+        Err(_) => None,
+    };
+    match checked {
+        Ok(warnings) => diagnostics.extend(
+            warnings.into_iter().map(|(w, level)| sarif::warning(filename, bytes, w, level, source_map.as_ref())),
+        ),
+        Err(e) => diagnostics.push(sarif::error(filename, bytes, e, source_map.as_ref())),
+    }
+    diagnostics
+}
+
+/// Print the warnings that weren't allowed, returning false if any were denied.
+fn print_warnings(filename: &str, warnings: &[(header::Warning, lint::LintLevel)]) -> bool {
+    for (w, level) in warnings {
+        let denied = if *level == lint::LintLevel::Deny { ", denied" } else { "" };
+        println!("{}: {} [{}{}]", filename, error_msgs::warning_msg(*w), w.lint().name(), denied);
+    }
+    !warnings.iter().any(|(_, level)| *level == lint::LintLevel::Deny)
+}
+
+/// The table `check` prints after a directory: one row per file, with whether it passed, how long it took, and its size.
+fn print_summary(rows: &[(String, bool, Duration, usize)]) {
+    println!("{:<6} {:>10} {:>10}  file", "result", "time", "bytes");
+    for (filename, ok, time, size) in rows {
+        let result = if *ok { "ok" } else { "FAIL" };
+        let ms = format!("{:.2}ms", time.as_secs_f64() * 1000.0);
+        println!("{:<6} {:>10} {:>10}  {}", result, ms, size, filename);
+    }
+    let failed = rows.iter().filter(|(_, ok, _, _)| !ok).count();
+    let total = rows.iter().map(|(_, _, time, _)| *time).sum::<Duration>();
+    println!("{} checked, {} failed, in {:.2}ms", rows.len(), failed, total.as_secs_f64() * 1000.0);
+}
 
-fn go(bytes: Vec<header::ByteStream>) -> Result<(), header::Error> {
-    let mut ir_programs = vec![];
-    for prog in bytes {
-        let (data_section, types_instrs, unverified_stmts) = parse::go(&prog)?;
-        // println!("{}", unverified_stmts.iter().map(|f|f.pretty() + "\n").collect::<String>());
-        let ir_program = verify::go(data_section, types_instrs, unverified_stmts)?;
-        ir_programs.push(ir_program);
+fn print_metrics(totals: &metrics::Totals) {
+    for counter in metrics::Counter::ALL {
+        println!("# TYPE {} counter", counter.name());
+        println!("{} {}", counter.name(), totals.get(counter));
     }
-    let status = vm::go(ir_programs);
-    if status != 0 {
-        exit(status.into());
+    println!("# TYPE sabervm_phase_seconds_total counter");
+    for phase in metrics::Phase::ALL {
+        let secs = totals.total_time(phase).as_secs_f64();
+        println!("sabervm_phase_seconds_total{{phase=\"{}\"}} {}", phase.name(), secs);
+    }
+}
+
+/// Check every watched file whenever its modification time changes, forever.
+/// This polls rather than asking the OS for change events, so it works the same everywhere.
+fn watch(paths: &[String], cache_dir: Option<&String>, lints: &[(String, lint::LintLevel)], recursive: bool) -> ! {
+    let mut seen: HashMap<PathBuf, SystemTime> = HashMap::new();
+    loop {
+        for file in files_in(paths, "svm", recursive) {
+            let Ok(modified) = fs::metadata(&file).and_then(|m| m.modified()) else {
+                continue;
+            };
+            if seen.get(&file) == Some(&modified) {
+                continue;
+            }
+            seen.insert(file.clone(), modified);
+            // the file may be half-written or already gone; the next change will get another look
+            let Ok(bytes) = fs::read(&file) else {
+                continue;
+            };
+            match check_one(&bytes, cache_dir, lints) {
+                Ok(warnings) => {
+                    if print_warnings(&file.display().to_string(), &warnings) {
+                        println!("{}: ok", file.display());
+                    }
+                }
+                Err(e) => println!("{}: {}", file.display(), error_msgs::msg(e)),
+            }
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// The given files, plus the files with the extension `ext` inside the given directories,
+/// and inside their subdirectories too if `recursive` is set.
+fn files_in(paths: &[String], ext: &str, recursive: bool) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for path in paths {
+        let path = PathBuf::from(path);
+        match fs::read_dir(&path) {
+            Ok(_) => files.extend(files_in_dir(&path, ext, recursive)),
+            Err(_) if path.is_file() => files.push(path),
+            // an option the subcommand doesn't know ends up here too, like `--help`
+            Err(_) => {
+                println!("{}: no such file", path.display());
+                exit(1);
+            }
+        }
+    }
+    files
+}
+
+/// The files with the extension `ext` in `dir`, in order, each subdirectory's after the files next to it.
+fn files_in_dir(dir: &Path, ext: &str, recursive: bool) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut entries = entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect::<Vec<_>>();
+    entries.sort();
+    let (dirs, files): (Vec<_>, Vec<_>) = entries.into_iter().partition(|path| path.is_dir());
+    let mut out = files.into_iter().filter(|file| file.extension().is_some_and(|e| e == ext)).collect::<Vec<_>>();
+    if recursive {
+        out.extend(dirs.iter().flat_map(|dir| files_in_dir(dir, ext, recursive)));
+    }
+    out
+}
+
+/// Parse and verify one program, returning the warnings that aren't allowed, with their levels.
+/// The program's lint config decides the levels, and then `lints` overrides them.
+fn check_one(
+    bytes: &header::ByteStream,
+    cache_dir: Option<&String>,
+    lints: &[(String, lint::LintLevel)],
+) -> Result<Vec<(header::Warning, lint::LintLevel)>, header::Error> {
+    let (data_section, type_decs, types_instrs, unverified_stmts, trailing) =
+        parse::go_with_trailing(bytes, &header::Limits::default())?;
+    let warnings = lint::warnings(&type_decs, &types_instrs, &unverified_stmts, trailing);
+    verify_one(data_section, type_decs, types_instrs, unverified_stmts, cache_dir)?;
+    let mut levels = lint::LintLevels::default();
+    if let Some(config) = parse::lint_config(bytes)? {
+        levels.apply_config(&config)?;
+    }
+    for (name, level) in lints {
+        levels.set(name, *level)?;
+    }
+    let leveled = warnings?.into_iter().map(|w| (w, levels.get(w.lint())));
+    Ok(leveled.filter(|(_, level)| *level != lint::LintLevel::Allow).collect())
+}
+
+#[cfg(feature = "cache")]
+fn verify_one(
+    data_section: Vec<u8>,
+    type_decs: Vec<header::TypeDec>,
+    types_instrs: Vec<header::ForwardDec>,
+    unverified_stmts: Vec<header::Stmt1>,
+    cache_dir: Option<&String>,
+) -> Result<(), header::Error> {
+    match cache_dir {
+        Some(dir) => {
+            let stats = sabervm::cache::check(&data_section, &type_decs, &types_instrs, &unverified_stmts, dir.as_ref())?;
+            println!("verified {} functions, {} unchanged since the last check", stats.verified, stats.cached);
+        }
+        None => {
+            verify::go(data_section, type_decs, types_instrs, unverified_stmts)?;
+        }
     }
     Ok(())
 }
 
-fn main() {
-    let args = env::args().collect::<Vec<_>>();
-    let bytes: Vec<header::ByteStream> = args.iter().skip(1).map(|filename| fs::read(filename).unwrap()).collect();
-    let res = go(bytes);
-    if let Err(e) = res {
-        println!("{}", error_msgs::msg(e));
+#[cfg(not(feature = "cache"))]
+fn verify_one(
+    data_section: Vec<u8>,
+    type_decs: Vec<header::TypeDec>,
+    types_instrs: Vec<header::ForwardDec>,
+    unverified_stmts: Vec<header::Stmt1>,
+    cache_dir: Option<&String>,
+) -> Result<(), header::Error> {
+    if cache_dir.is_some() {
+        println!("--cache-dir needs SaberVM to be built with the `cache` feature");
+        exit(1);
+    }
+    verify::go(data_section, type_decs, types_instrs, unverified_stmts)?;
+    Ok(())
+}
+
+/// Print what's in a core dump written by `run --core`.
+/// Giving the programs that were running after the dump also prints a backtrace.
+fn inspect_core(args: &[String]) {
+    let Some((filename, programs)) = args.split_first() else {
+        println!("inspect-core needs a core dump file");
+        exit(1);
+    };
+    let bytes = fs::read(filename).unwrap();
+    let Some(dump) = CoreDump::from_bytes(&bytes) else {
+        println!("{} isn't a core dump this version of SaberVM can read.", filename);
+        exit(1);
+    };
+    println!("{}", error_msgs::trap_msg(dump.trap));
+    match dump.pc {
+        0 => println!("stopped between tasks"),
+        pc => println!("stopped at {}", pc),
+    }
+    if !programs.is_empty() {
+        match Module::new(read_files(programs)) {
+            Ok(module) => print_backtrace(&module, &dump.backtrace(&module)),
+            Err(e) => println!("{}", error_msgs::msg(e)),
+        }
+    }
+    let tasks = dump.tasks.iter().map(u32::to_string).collect::<Vec<_>>();
+    match tasks.is_empty() {
+        true => println!("waiting tasks: (none)"),
+        false => println!("waiting tasks: {}", tasks.join(", ")),
+    }
+    println!("stack ({} bytes, bottom first):", dump.stack.len());
+    for (i, row) in dump.stack.chunks(16).enumerate() {
+        let hex = row.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>();
+        println!("  {:06x}  {}", i * 16, hex.join(" "));
+    }
+}
+
+/// The function a trap happened in, then the ones the most recent calls came from,
+/// each with the last `marker` before it, if there is one.
+fn print_backtrace(module: &Module, backtrace: &[Location]) {
+    for (i, loc) in backtrace.iter().enumerate() {
+        let how = if i == 0 { "in" } else { "called from" };
+        let marker = match module.marker(*loc) {
+            Some(n) => format!(", after marker {}", n),
+            None => String::new(),
+        };
+        println!(
+            "  {} function {} of program {}, {} bytes in{}",
+            how, loc.function, loc.program, loc.offset, marker
+        );
+    }
+}
+
+/// Assemble a `.svmasm` file into a program, as in `asm in.svmasm out.svm`.
+/// With `--trap-overflow`, arithmetic traps on overflow instead of wrapping (see `asm::trap_overflow`).
+fn assemble(args: &[String]) {
+    let trap_overflow = args.iter().any(|arg| arg == "--trap-overflow");
+    let args = args.iter().filter(|arg| *arg != "--trap-overflow").collect::<Vec<_>>();
+    let [input, output] = args[..] else {
+        println!("asm needs an assembly file and a file to write the program to");
+        exit(1);
+    };
+    let src = fs::read_to_string(input).unwrap();
+    let dir = Path::new(input).parent().unwrap_or(Path::new("."));
+    let res = match asm::parse(&src).and_then(|lines| asm::expand(&lines, dir)) {
+        Ok(mut lines) => {
+            if trap_overflow {
+                asm::trap_overflow(&mut lines);
+            }
+            asm::assemble(&lines)
+        }
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok(bytes) => fs::write(output, bytes).unwrap(),
+        Err(e) => {
+            println!("{}: {}", input, error_msgs::msg(e));
+            exit(1);
+        }
+    }
+}
+
+/// Write a generated stress module, as in `gen --functions 10000 --size 1k out.svm`.
+/// The module is written as assembly if the file name ends in `.svmasm`.
+fn generate(args: &[String]) {
+    let mut shape = gen::Shape::default();
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let field = match arg.as_str() {
+            "--functions" | "--size" | "--depth" | "--laps" | "--tuple" | "--regions" => arg,
+            _ => {
+                output = Some(arg);
+                continue;
+            }
+        };
+        let Some(n) = args.next().and_then(|n| parse_size(n)) else {
+            println!("{} needs a number, like 100 or 1k", field);
+            exit(1);
+        };
+        let ok = match field.as_str() {
+            "--functions" => u32::try_from(n).map(|n| shape.functions = n).is_ok(),
+            "--size" => {
+                shape.size = n;
+                true
+            }
+            "--depth" => u32::try_from(n).map(|n| shape.depth = n).is_ok(),
+            "--laps" => i32::try_from(n).map(|n| shape.laps = n).is_ok(),
+            "--tuple" => u8::try_from(n).map(|n| shape.tuple = n).is_ok(),
+            _ => n <= gen::MAX_REGIONS as usize && {
+                shape.regions = n as u32;
+                true
+            },
+        };
+        if !ok {
+            println!("{} {} is too big", field, n);
+            exit(1);
+        }
+    }
+    let Some(output) = output else {
+        println!("gen needs a file to write the module to");
+        exit(1);
+    };
+    let src = gen::generate(&shape);
+    if output.ends_with(".svmasm") {
+        fs::write(output, src).unwrap();
+        return;
+    }
+    match asm::parse(&src).and_then(|lines| asm::assemble(&lines)) {
+        Ok(bytes) => fs::write(output, bytes).unwrap(),
+        Err(e) => {
+            println!("{}", error_msgs::msg(e));
+            exit(1);
+        }
+    }
+}
+
+/// A number with an optional `k` or `m` suffix, for thousands or millions.
+fn parse_size(s: &str) -> Option<usize> {
+    let (digits, scale) = match s.strip_suffix(['k', 'K']) {
+        Some(digits) => (digits, 1000),
+        None => match s.strip_suffix(['m', 'M']) {
+            Some(digits) => (digits, 1_000_000),
+            None => (s, 1),
+        },
+    };
+    digits.parse::<usize>().ok()?.checked_mul(scale)
+}
+
+/// Print each program as assembly.
+fn disassemble(filenames: &[String]) {
+    for (filename, bytes) in filenames.iter().zip(read_files(filenames)) {
+        match asm::disassemble(&bytes) {
+            Ok(src) => print!("{}", src),
+            Err(e) => {
+                println!("{}: {}", filename, error_msgs::msg(e));
+                exit(1);
+            }
+        }
+    }
+}
+
+/// Rewrite each assembly file, and all the `.svmasm` files in any directories given, in the canonical layout.
+/// With `--check`, nothing is rewritten; the files that aren't formatted are listed and the exit status is 1.
+fn format(args: &[String]) {
+    let check = args.iter().any(|arg| arg == "--check");
+    let mut unformatted = false;
+    let paths = args.iter().filter(|arg| *arg != "--check").cloned().collect::<Vec<_>>();
+    for file in files_in(&paths, "svmasm", false) {
+        let filename = file.display();
+        let src = fs::read_to_string(&file).unwrap();
+        let formatted = match asm::parse(&src) {
+            Ok(lines) => asm::format(&lines),
+            Err(e) => {
+                println!("{}: {}", filename, error_msgs::msg(e));
+                exit(1);
+            }
+        };
+        if formatted == src {
+            continue;
+        }
+        if check {
+            println!("{} isn't formatted", filename);
+            unformatted = true;
+        } else {
+            fs::write(&file, formatted).unwrap();
+        }
+    }
+    if unformatted {
+        exit(1);
+    }
+}
+
+/// Check that each `.svmasm` file with an `;; expect` comment, in the given files and directories, does what it says.
+/// Each one also has a snapshot next to it (`.snap` instead of `.svmasm`) of its disassembly and the message it ends with,
+/// so changes to either show up in review. `--bless` writes the snapshots instead of checking them.
+/// `--traps` runs the programs of `testing::trap_matrix` instead, checking that each traps as it should in every mode,
+/// `--threads` runs `testing::stress_cases`, each on many threads at once, and `--api` runs `testing::api_cases`.
+fn test(args: &[String]) {
+    if args.iter().any(|arg| arg == "--traps") {
+        return test_traps();
+    }
+    if args.iter().any(|arg| arg == "--threads") {
+        return test_threads();
+    }
+    if args.iter().any(|arg| arg == "--api") {
+        return test_api();
+    }
+    let bless = args.iter().any(|arg| arg == "--bless");
+    let optimized = args.iter().any(|arg| arg == "--opt");
+    let paths = args.iter().filter(|arg| *arg != "--bless" && *arg != "--opt").cloned().collect::<Vec<_>>();
+    let mut passed = 0;
+    let mut failed = 0;
+    for file in files_in(&paths, "svmasm", false) {
+        let src = fs::read_to_string(&file).unwrap();
+        let expected = match asm::parse(&src).and_then(|lines| asm::expectations(&lines)) {
+            Ok(expected) => expected,
+            Err(e) => {
+                println!("FAIL {}: {}", file.display(), error_msgs::msg(e));
+                failed += 1;
+                continue;
+            }
+        };
+        let expected = match &expected[..] {
+            [] => continue,
+            [expected] => expected,
+            _ => {
+                println!("FAIL {}: more than one expect comment", file.display());
+                failed += 1;
+                continue;
+            }
+        };
+        let dir = file.parent().unwrap_or(Path::new("."));
+        let (got, snapshot) = test_run(&src, dir, false);
+        if got != *expected {
+            println!(
+                "FAIL {}: expected {} but got {}",
+                file.display(),
+                expectation_str(expected),
+                expectation_str(&got)
+            );
+            failed += 1;
+            continue;
+        }
+        if optimized {
+            let (got, _) = test_run(&src, dir, true);
+            if got != *expected {
+                println!(
+                    "FAIL {}: expected {} but the optimized program got {}",
+                    file.display(),
+                    expectation_str(expected),
+                    expectation_str(&got)
+                );
+                failed += 1;
+                continue;
+            }
+        }
+        let snap_file = file.with_extension("snap");
+        if bless {
+            fs::write(&snap_file, snapshot).unwrap();
+        } else {
+            match fs::read_to_string(&snap_file) {
+                Ok(old) if old == snapshot => {}
+                Ok(old) => {
+                    println!("FAIL {}: the snapshot changed", file.display());
+                    print_changed_lines(&old, &snapshot);
+                    failed += 1;
+                    continue;
+                }
+                Err(_) => {
+                    println!("FAIL {}: no snapshot; run with --bless to write one", file.display());
+                    failed += 1;
+                    continue;
+                }
+            }
+        }
+        println!("ok {}", file.display());
+        passed += 1;
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        exit(1);
+    }
+}
+
+fn test_traps() {
+    let mut passed = 0;
+    let mut failed = 0;
+    for case in testing::trap_matrix() {
+        match testing::expect_trap(&case.bytes(), case.trap) {
+            Ok(()) => {
+                println!("ok {}", case.name);
+                passed += 1;
+            }
+            Err((mode, ended)) => {
+                println!("FAIL {}: expected {:?} but in {:?} mode it {}", case.name, case.trap, mode, ended);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        exit(1);
+    }
+}
+
+fn test_threads() {
+    let mut passed = 0;
+    let mut failed = 0;
+    for case in testing::stress_cases() {
+        match testing::expect_concurrent(&case, 16, 8) {
+            Ok(()) => {
+                println!("ok {}", case.name);
+                passed += 1;
+            }
+            Err((mode, ended)) => {
+                println!("FAIL {}: a run in {:?} mode {}", case.name, mode, ended);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        exit(1);
+    }
+}
+
+fn test_api() {
+    let mut passed = 0;
+    let mut failed = 0;
+    for case in testing::api_cases() {
+        match testing::expect_api(&case) {
+            Ok(()) => {
+                println!("ok {}", case.name);
+                passed += 1;
+            }
+            Err(msg) => {
+                println!("FAIL {}: {}", case.name, msg);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        exit(1);
+    }
+}
+
+/// Assemble and run a test program, returning how it ended and its snapshot.
+/// If `optimized`, it's run after the passes of `sabervm opt`, unless they can't take it because it doesn't verify.
+fn test_run(src: &str, dir: &Path, optimized: bool) -> (asm::Expectation, String) {
+    let bytes = asm::parse(src)
+        .and_then(|lines| asm::expand(&lines, dir))
+        .and_then(|lines| asm::assemble(&lines));
+    let bytes = match bytes {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let name = variant_name(&e);
+            return (asm::Expectation::Error(name), format!("assembly error:\n{}\n", error_msgs::msg(e)));
+        }
+    };
+    let bytes = match optimized.then(|| optimize_bytes(&bytes, 16)) {
+        Some(Ok((optimized_bytes, _, _))) => optimized_bytes,
+        _ => bytes,
+    };
+    let disassembly = match asm::disassemble(&bytes) {
+        Ok(src) => src,
+        Err(e) => error_msgs::msg(e) + "\n",
+    };
+    let snapshot = |message: String| format!("disassembly:\n{}\nmessage:\n{}\n", disassembly, message);
+    let lines = asm::parse(src).unwrap_or_default();
+    let config = Config {
+        verification: asm::verification(&lines),
+        ..Config::default()
+    };
+    let module = match Module::with_config(vec![bytes], &config, &header::Cancellation::default(), &[]) {
+        Ok(module) => module,
+        Err(e) => {
+            let name = variant_name(&e);
+            return (asm::Expectation::Error(name), snapshot(error_msgs::msg(e)));
+        }
+    };
+    let mut instance = Instance::new(Arc::new(module));
+    // tests get svm_std too, but with nothing that changes between runs, so their snapshots don't either
+    instance.allow_std(&StdProfile {
+        seed: 0,
+        clock: Clock::Fixed(0),
+        ..StdProfile::new()
+    });
+    let resources = testing::provide_test_resources(&mut instance);
+    let mut res = match asm::call(&lines) {
+        None => instance.run(),
+        Some((name, args)) => match instance.call(&name, &args[..]) {
+            Ok(outcome) => Ok(outcome),
+            Err(header::CallError::Trap(trap)) => Err(trap),
+            Err(e) => return (asm::Expectation::Error(variant_name(&e)), snapshot(error_msgs::call_msg(e))),
+        },
+    };
+    while let Ok(Outcome::Yielded(val)) = res {
+        res = instance.resume(val);
+    }
+    // when the host finalized the program's resources is part of how it ended, so it's in the snapshot too
+    let finalized = match resources.finalized() {
+        ids if ids.is_empty() => String::new(),
+        ids => format!("\nfinalized resources: {:?}", ids),
+    };
+    match res {
+        Ok(Outcome::Halted(status)) => (
+            asm::Expectation::Halt(status),
+            snapshot(format!("halted with status {}{}", status, finalized)),
+        ),
+        Ok(Outcome::Yielded(_)) => unreachable!(),
+        Err(trap) => (
+            asm::Expectation::Error(variant_name(&trap)),
+            snapshot(error_msgs::trap_msg(trap) + &finalized),
+        ),
+    }
+}
+
+/// A rough diff: the lines only in the old snapshot, then the lines only in the new one.
+fn print_changed_lines(old: &str, new: &str) {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+    for line in &old_lines {
+        if !new_lines.contains(line) {
+            println!("  - {}", line);
+        }
+    }
+    for line in &new_lines {
+        if !old_lines.contains(line) {
+            println!("  + {}", line);
+        }
+    }
+}
+
+/// The name of an enum variant, without its fields.
+fn variant_name(x: &impl std::fmt::Debug) -> String {
+    let debug = format!("{:?}", x);
+    debug.split('(').next().unwrap().to_string()
+}
+
+fn expectation_str(expectation: &asm::Expectation) -> String {
+    match expectation {
+        asm::Expectation::Halt(status) => format!("halt {}", status),
+        asm::Expectation::Error(name) => name.clone(),
+    }
+}
+
+/// Print what the verifier worked out about each program.
+/// `--regions` prints where each region is made, used, and freed, as text, or as a Graphviz graph with `--dot`.
+/// `--escapes` suggests allocations in regions a function was given that could go in shorter-lived regions instead.
+/// If a program doesn't verify, the analysis goes up to where the verifier stopped, followed by the error.
+fn analyze(args: &[String]) {
+    let regions = args.iter().any(|arg| arg == "--regions");
+    let escapes = args.iter().any(|arg| arg == "--escapes");
+    let dot = args.iter().any(|arg| arg == "--dot");
+    let filenames = args.iter().filter(|arg| !arg.starts_with("--")).cloned().collect::<Vec<_>>();
+    if !regions && !escapes {
+        println!("analyze needs to be told what to analyze, with --regions or --escapes");
+        exit(1);
+    }
+    for (filename, bytes) in filenames.iter().zip(read_files(&filenames)) {
+        let (trace, res) = match parse::go(&bytes) {
+            Ok((data_section, type_decs, types_instrs, unverified_stmts)) => {
+                verify::explain(data_section, type_decs, types_instrs, unverified_stmts)
+            }
+            Err(e) => (vec![], Err(e)),
+        };
+        let timelines = analyze::region_timelines(&trace);
+        if dot {
+            print!("{}", analyze::timelines_dot(&timelines));
+        } else {
+            println!("{}:", filename);
+        }
+        if regions && !dot {
+            print!("{}", analyze::timelines_text(&timelines));
+        }
+        if escapes {
+            let prefix = if dot { "// " } else { "" };
+            for a in analyze::local_allocations(&trace) {
+                println!(
+                    "{}function {}, {}: the {} allocated in {} never leaves the function, so it could go in a region made and freed there",
+                    prefix,
+                    a.function,
+                    a.pos,
+                    a.typ.pretty(),
+                    a.region.pretty()
+                );
+            }
+        }
+        if let Err(e) = res {
+            // in a graph, this has to be a comment to keep the output valid
+            let prefix = if dot { "// " } else { "" };
+            println!("{}{}", prefix, error_msgs::msg(e));
+        }
+    }
+}
+
+/// Print the opcode table: each op's byte, mnemonic, and immediate.
+fn opcodes() {
+    for info in header::OPCODES {
+        match info.imm {
+            header::ImmKind::None => println!("0x{:02X} {}", info.byte, info.mnemonic),
+            imm => println!("0x{:02X} {} <{}>", info.byte, info.mnemonic, imm.pretty()),
+        }
+    }
+}
+
+/// Print the type the verifier elaborated from each function's forward declaration.
+fn signatures(filenames: &[String]) {
+    for (filename, bytes) in filenames.iter().zip(read_files(filenames)) {
+        let res = match parse::go(&bytes) {
+            Ok((_, type_decs, types_instrs, _)) => verify::signatures(&type_decs, &types_instrs),
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(sigs) => {
+                if filenames.len() > 1 {
+                    println!("{}:", filename);
+                }
+                for (label, vis, t) in sigs {
+                    match vis {
+                        header::Visibility::Local => println!("function {}: {}", label, t.pretty()),
+                        vis => println!("function {} ({}): {}", label, vis.pretty(), t.pretty()),
+                    }
+                }
+            }
+            Err(e) => {
+                println!("{}", error_msgs::msg(e));
+                exit(1);
+            }
+        }
+    }
+}
+
+/// Compare two programs function by function, printing what changed and a summary.
+/// The exit status is 1 if they're different, like `diff`'s.
+fn diff_modules(args: &[String]) {
+    let [old, new] = args else {
+        println!("diff needs the old program and the new one");
+        exit(1);
+    };
+    let files = read_files(args);
+    match diff::diff(&files[0], &files[1]) {
+        Ok(d) => {
+            print!("{}", d.text());
+            if !d.is_empty() {
+                exit(1);
+            }
+        }
+        Err(e) => {
+            println!("{} or {}: {}", old, new, error_msgs::msg(e));
+            exit(2);
+        }
+    }
+}
+
+/// Check that two programs mean the same thing, up to how their functions are numbered and how their types are built.
+/// The exit status is 0 if they do, 1 if they don't, and 2 if either doesn't verify, so test suites can tell these apart.
+fn equiv(args: &[String]) {
+    let [old, new] = args else {
+        println!("equiv needs the two programs to compare");
+        exit(2);
+    };
+    let files = read_files(args);
+    match diff::equivalent(&files[0], &files[1]) {
+        Ok(None) => println!("{} and {} are equivalent", old, new),
+        Ok(Some(why)) => {
+            println!("{} and {} aren't equivalent: {}", old, new, why.describe());
+            exit(1);
+        }
+        Err(e) => {
+            println!("{} or {}: {}", old, new, error_msgs::msg(e));
+            exit(2);
+        }
+    }
+}
+
+/// Optimize a program and write the result to another file, checking that it still verifies (see `opt.rs`).
+/// `--inline-threshold N` inlines calls to functions with at most N ops in their bodies, 16 by default, and 0 turns it off.
+fn optimize(args: &[String]) {
+    let mut threshold = 16;
+    let mut filenames = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--inline-threshold" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) => threshold = n,
+                None => {
+                    println!("--inline-threshold needs a number of ops");
+                    exit(1);
+                }
+            },
+            _ => filenames.push(arg.clone()),
+        }
+    }
+    let [input, output] = &filenames[..] else {
+        println!("opt needs a program and a file to write the optimized program to");
+        exit(1);
+    };
+    let bytes = fs::read(input).unwrap();
+    match optimize_bytes(&bytes, threshold) {
+        Ok((optimized, inlined, eliminated)) => {
+            fs::write(output, &optimized).unwrap();
+            print!("inlined {} calls into {} functions", inlined.sites, inlined.functions);
+            if inlined.rejected > 0 {
+                print!(" ({} more were left alone, since they didn't verify once inlined)", inlined.rejected);
+            }
+            println!();
+            print!(
+                "removed {} dead ops and shortened {} get chains in {} functions",
+                eliminated.ops, eliminated.gets, eliminated.functions
+            );
+            if eliminated.rejected > 0 {
+                print!(" ({} more were left alone, since they didn't verify afterwards)", eliminated.rejected);
+            }
+            println!(", {} bytes to {}", bytes.len(), optimized.len());
+        }
+        Err(e) => {
+            println!("{}: {}", input, error_msgs::msg(e));
+            exit(1);
+        }
+    }
+}
+
+/// Run every pass of `sabervm opt` over a program, inlining first, since that leaves values for the next to remove.
+fn optimize_bytes(
+    bytes: &header::ByteStream,
+    threshold: usize,
+) -> Result<(header::ByteStream, opt::Inlined, opt::Eliminated), header::Error> {
+    let (bytes, inlined) = opt::inline(bytes, threshold)?;
+    let (bytes, eliminated) = opt::eliminate_dead(&bytes)?;
+    Ok((bytes, inlined, eliminated))
+}
+
+/// Shrink a program that SaberVM panics on or rejects, like one a fuzzer found, into a smaller one that fails the same way
+/// (see `minimize.rs`): a panic at the same place in SaberVM's source, or an error with the same code.
+/// The smaller program is written next to the original with `.min` on the end, or to the file given to `-o`.
+fn minimize_crash(args: &[String]) {
+    let mut output = None;
+    let mut filenames = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(filename) => output = Some(filename.clone()),
+                None => {
+                    println!("-o needs a file to write the smaller program to");
+                    exit(1);
+                }
+            },
+            _ => filenames.push(arg.clone()),
+        }
+    }
+    let [input] = &filenames[..] else {
+        println!("minimize needs a program that fails");
+        exit(1);
+    };
+    let bytes = fs::read(input).unwrap();
+    let Some(failure) = minimize::failure(&bytes) else {
+        println!("{} parses, verifies, and links, so there's no failure to keep", input);
+        exit(1);
+    };
+    println!("{} {}", input, failure);
+    let (minimized, stats) =
+        minimize::minimize(&bytes, |bytes| minimize::failure(bytes).is_some_and(|f| f.same_as(&failure)));
+    let output = output.unwrap_or_else(|| format!("{}.min", input));
+    fs::write(&output, &minimized).unwrap();
+    println!(
+        "wrote {} ({} bytes, down from {}) after {} tries, taking out {} functions, {} named types, and {} more ops",
+        output,
+        minimized.len(),
+        bytes.len(),
+        stats.tries,
+        stats.functions,
+        stats.types,
+        stats.ops
+    );
+}
+
+/// Summarize each of the given programs on one screen: its header, how big each part is,
+/// where it starts, what it imports and exports, and what made it.
+fn info(filenames: &[String]) {
+    for (filename, bytes) in filenames.iter().zip(read_files(filenames)) {
+        if let Err(e) = print_info(filename, &bytes) {
+            println!("  {}", error_msgs::msg(e));
+            exit(1);
+        }
+    }
+}
+
+fn print_info(filename: &str, bytes: &[u8]) -> Result<(), header::Error> {
+    println!("{}: {} bytes", filename, bytes.len());
+    let (bits, header_len) = parse::features(bytes)?;
+    if header_len == 0 {
+        println!("  header     none");
+    } else {
+        let names = header::Feature::ALL.iter().filter(|f| bits & f.bit() != 0).map(|f| f.pretty()).collect::<Vec<_>>();
+        let names = if names.is_empty() { "no features".to_string() } else { names.join(", ") };
+        println!("  header     {} bytes, bits {:#x}: {}", header_len, bits, names);
+    }
+    let (data_section, type_decs, forward_decs, stmts, trailing) = parse::go_with_trailing(bytes, &header::Limits::default())?;
+    let op_bytes = |ops: &[header::Op1]| ops.iter().map(|op| 1 + op.info().imm.width()).sum::<usize>();
+    // every declaration ends in `lced`, `export`, or `import`, which the parser takes off
+    let vis_bytes = |vis: &header::Visibility| match vis {
+        header::Visibility::Local => 1,
+        _ => 17,
+    };
+    println!("  data       {} bytes", data_section.len());
+    if !type_decs.is_empty() {
+        let size = type_decs.iter().map(|header::TypeDec::Type(_, vis, ops)| op_bytes(ops) + vis_bytes(vis)).sum::<usize>();
+        println!("  types      {} declared in {} bytes", type_decs.len(), size);
+    }
+    let decls = forward_decs.iter().map(|header::ForwardDec::Func(_, vis, ops)| op_bytes(ops) + vis_bytes(vis)).sum::<usize>();
+    let bodies = stmts.iter().map(|header::Stmt1::Func(_, _, ops)| op_bytes(ops)).sum::<usize>();
+    println!("  functions  {} declared in {} bytes, {} bodies in {} bytes", forward_decs.len(), decls, stmts.len(), bodies);
+    if trailing > 0 {
+        println!("  trailing   {} ops after the last body", trailing);
+    }
+    match stmts.first() {
+        Some(header::Stmt1::Func(label, _, _)) => println!("  entry      function {}", label),
+        None => println!("  entry      none"),
+    }
+    let sigs = verify::signatures(&type_decs, &forward_decs)?;
+    for (label, vis, t) in &sigs {
+        if *vis != header::Visibility::Local {
+            let vis = vis.pretty().trim_end_matches('\0').to_string();
+            println!("  {:<10} function {}: {}", vis, label, t.pretty());
+        }
+    }
+    // what `Instance::call` checks its arguments against
+    for (name, label) in parse::export_names(bytes, &forward_decs)? {
+        let (_, _, t) = &sigs[label as usize];
+        println!("  named      function {} as {}: {}", label, name, t.pretty());
+    }
+    if let Some(metadata) = parse::metadata(bytes)? {
+        for key in header::Metadata::KEYS {
+            if let Some(value) = metadata.get(key) {
+                println!("  {:<10} {}", key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Verify the given programs, printing how the verifier's state changes over every instruction.
+/// `--function n` narrows this to the function with label n.
+/// Nothing is run; this is for seeing how the verifier reads a program.
+fn explain(args: &[String]) {
+    let (only, filenames) = match args {
+        [flag, n, rest @ ..] if flag == "--function" => match n.parse::<u32>() {
+            Ok(n) => (Some(n), rest),
+            Err(_) => {
+                println!("--function needs a function label, but got {}", n);
+                exit(1);
+            }
+        },
+        _ => (None, args),
+    };
+    for (filename, bytes) in filenames.iter().zip(read_files(filenames)) {
+        println!("{}:", filename);
+        let (trace, res) = match parse::go(&bytes) {
+            Ok((data_section, type_decs, types_instrs, unverified_stmts)) => {
+                verify::explain(data_section, type_decs, types_instrs, unverified_stmts)
+            }
+            Err(e) => (vec![], Err(e)),
+        };
+        let mut last_func = None;
+        for step in trace {
+            if only.is_some_and(|l| l != step.label) {
+                continue;
+            }
+            if last_func != Some((step.label, step.forward_dec)) {
+                let part = if step.forward_dec { "declaration" } else { "body" };
+                println!("function {} {}:", step.label, part);
+                last_func = Some((step.label, step.forward_dec));
+            }
+            println!("  {} {}", step.pos, step.op.pretty());
+            println!(
+                "    compile-time stack: {} => {}",
+                error_msgs::ct_stack_str(&step.before.compile_time_stack.to_vec()),
+                error_msgs::ct_stack_str(&step.after.compile_time_stack.to_vec())
+            );
+            if !step.forward_dec {
+                println!(
+                    "    stack: {} => {}",
+                    list_str(&step.before.stack_type.to_vec()),
+                    list_str(&step.after.stack_type.to_vec())
+                );
+                println!(
+                    "    live regions: {} => {}",
+                    list_str(&step.before.rgn_vars),
+                    list_str(&step.after.rgn_vars)
+                );
+            }
+        }
+        if let Err(e) = res {
+            println!("{}", error_msgs::msg(e));
+            exit(1);
+        }
+    }
+}
+
+/// Print what an error code, like `E0404`, means, with a small program that fails with it and the same program fixed.
+/// With no code, this lists every code and the error it's for.
+/// `--check` runs each of those programs, so CI can make sure they still fail and pass the way they say.
+fn explain_code(args: &[String]) {
+    match args {
+        [] => {
+            for explanation in error_codes::EXPLANATIONS {
+                println!("{}: {}", explanation.code, explanation.name);
+            }
+        }
+        [flag] if flag == "--check" => check_explanations(),
+        [code] => match ErrorCode::parse(code).and_then(error_codes::explain) {
+            Some(explanation) => print_explanation(explanation),
+            None => {
+                println!("{} isn't an error code; `explain` with no code lists them all", code);
+                exit(1);
+            }
+        },
+        _ => {
+            println!("explain takes one error code, like E0404, or --check");
+            exit(1);
+        }
+    }
+}
+
+fn print_explanation(explanation: &error_codes::Explanation) {
+    println!("{}: {}", explanation.code, explanation.name);
+    println!();
+    println!("{}", explanation.description);
+    if let Some(example) = explanation.example {
+        println!();
+        println!("This fails with {}:", explanation.code);
+        println!();
+        print_indented(example.failing);
+        println!("This doesn't:");
+        println!();
+        print_indented(example.fixed);
+    }
+}
+
+fn print_indented(src: &str) {
+    for line in src.lines() {
+        if line.is_empty() {
+            println!();
+        } else {
+            println!("    {}", line);
+        }
+    }
+    println!();
+}
+
+/// Run every explanation's programs: the failing one has to fail with the explanation's error, and the fixed one has to halt.
+fn check_explanations() {
+    let mut passed = 0;
+    let mut failed = 0;
+    for explanation in error_codes::EXPLANATIONS {
+        let Some(example) = explanation.example else {
+            continue;
+        };
+        let (failing, _) = test_run(example.failing, Path::new("."), false);
+        let (fixed, _) = test_run(example.fixed, Path::new("."), false);
+        let expected = asm::Expectation::Error(explanation.name.to_string());
+        if failing != expected {
+            println!(
+                "FAIL {}: the failing example should fail with {} but got {}",
+                explanation.code,
+                explanation.name,
+                expectation_str(&failing)
+            );
+            failed += 1;
+        } else if !matches!(fixed, asm::Expectation::Halt(_)) {
+            println!("FAIL {}: the fixed example should halt but got {}", explanation.code, expectation_str(&fixed));
+            failed += 1;
+        } else {
+            println!("ok {}", explanation.code);
+            passed += 1;
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        exit(1);
+    }
+}
+
+/// Things pretty-printed from the bottom of a stack to the top.
+fn list_str<T: Pretty>(xs: &[T]) -> String {
+    if xs.is_empty() {
+        return "(empty)".to_string();
     }
+    xs.iter().map(|x| x.pretty()).collect::<Vec<_>>().join(", ")
 }
@@ -0,0 +1,141 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Counters and timings from parsing, verification, and linking, for monitoring a host that loads many programs.
+//! Nothing is recorded until an embedder installs a `Metrics` with `set_metrics`,
+//! which can pass the numbers on to Prometheus, logs, or anything else. `Totals` just adds them up.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Counter {
+    /// The bytes of every program parsed, including ones that failed.
+    BytesParsed,
+    /// Forward declarations in programs that parsed.
+    FunctionsParsed,
+    /// Function bodies that verified, or were found in the cache of `cache::check`.
+    FunctionsVerified,
+    /// Programs that failed to verify.
+    VerifyErrors,
+    /// Comparisons of two types.
+    TypeChecks,
+    /// Function bodies `cache::check` skipped because they verified before.
+    CacheHits,
+    /// Function bodies `cache::check` had to verify.
+    CacheMisses,
+}
+
+impl Counter {
+    pub const ALL: [Counter; 7] = [
+        Counter::BytesParsed,
+        Counter::FunctionsParsed,
+        Counter::FunctionsVerified,
+        Counter::VerifyErrors,
+        Counter::TypeChecks,
+        Counter::CacheHits,
+        Counter::CacheMisses,
+    ];
+
+    /// A name for the counter in the style of Prometheus, like `sabervm_bytes_parsed_total`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Counter::BytesParsed => "sabervm_bytes_parsed_total",
+            Counter::FunctionsParsed => "sabervm_functions_parsed_total",
+            Counter::FunctionsVerified => "sabervm_functions_verified_total",
+            Counter::VerifyErrors => "sabervm_verify_errors_total",
+            Counter::TypeChecks => "sabervm_type_checks_total",
+            Counter::CacheHits => "sabervm_cache_hits_total",
+            Counter::CacheMisses => "sabervm_cache_misses_total",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Parse,
+    Verify,
+    Link,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 3] = [Phase::Parse, Phase::Verify, Phase::Link];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Verify => "verify",
+            Phase::Link => "link",
+        }
+    }
+}
+
+/// Somewhere for metrics to go. This is called from whichever thread is doing the work.
+pub trait Metrics: Send + Sync {
+    fn count(&self, counter: Counter, n: u64);
+
+    /// How long one run of a phase took, for one program (or one module, for linking).
+    fn time(&self, _phase: Phase, _elapsed: Duration) {}
+}
+
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+    fn count(&self, counter: Counter, n: u64) {
+        (**self).count(counter, n)
+    }
+
+    fn time(&self, phase: Phase, elapsed: Duration) {
+        (**self).time(phase, elapsed)
+    }
+}
+
+/// Metrics that are just added up, to be read with `get` and `total_time`.
+/// Install an `Arc<Totals>` to keep reading it afterwards.
+#[derive(Default)]
+pub struct Totals {
+    counts: [AtomicU64; Counter::ALL.len()],
+    nanos: [AtomicU64; Phase::ALL.len()],
+}
+
+impl Totals {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counts[counter as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn total_time(&self, phase: Phase) -> Duration {
+        Duration::from_nanos(self.nanos[phase as usize].load(Ordering::Relaxed))
+    }
+}
+
+impl Metrics for Totals {
+    fn count(&self, counter: Counter, n: u64) {
+        self.counts[counter as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn time(&self, phase: Phase, elapsed: Duration) {
+        self.nanos[phase as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+static METRICS: OnceLock<Box<dyn Metrics>> = OnceLock::new();
+
+/// Send metrics to `metrics` from now on.
+/// This can only be done once, and it returns false if metrics were already set.
+pub fn set_metrics(metrics: Box<dyn Metrics>) -> bool {
+    METRICS.set(metrics).is_ok()
+}
+
+pub(crate) fn count(counter: Counter, n: u64) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.count(counter, n);
+    }
+}
+
+pub(crate) fn time(phase: Phase, elapsed: Duration) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.time(phase, elapsed);
+    }
+}
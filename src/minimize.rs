@@ -0,0 +1,437 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Shrinking a program that makes SaberVM fail, for `sabervm minimize`.
+//!
+//! The programs a fuzzer finds crashes with are big and mostly noise. `minimize` keeps trying smaller versions of one,
+//! keeping each that still fails the same way, until there's nothing left it can take out.
+//! It splits the program up the way the parser does, so it first tries taking out whole functions and named types
+//! (with the references to the ones after them renumbered), then runs of ops, and then bytes of the data section.
+//! Only once none of those can go does it try taking out runs of raw bytes, which is also all it can do
+//! with a program too broken to find the parts of.
+
+use crate::checksum;
+use crate::error_codes;
+use crate::error_msgs;
+use crate::header::*;
+use crate::parse;
+use crate::vm::Module;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+
+/// How a program fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// SaberVM panicked, at this place in its source (like `src/verify.rs:120:5`), with this message.
+    Panic(String, String),
+    /// SaberVM rejected the program with this error.
+    Error(Error),
+}
+
+impl Failure {
+    /// Whether `other` is this failure again, as far as `minimize` cares.
+    /// Two panics are the same if they're at the same place, and two errors if they have the same code
+    /// (and are about the same limit, since those all share one), but the messages can differ,
+    /// since they say where in the program things went wrong, which moves as it shrinks.
+    pub fn same_as(&self, other: &Failure) -> bool {
+        match (self, other) {
+            (Failure::Panic(a, _), Failure::Panic(b, _)) => a == b,
+            (Failure::Error(Error::LimitExceeded(a, _, _)), Failure::Error(Error::LimitExceeded(b, _, _))) => a == b,
+            (Failure::Error(a), Failure::Error(b)) => error_codes::code(a) == error_codes::code(b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Panic(location, msg) => write!(f, "panics at {}: {}", location, msg),
+            Failure::Error(e) => write!(f, "{}", error_msgs::msg(e.clone())),
+        }
+    }
+}
+
+thread_local! {
+    static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// How SaberVM fails to parse, verify, or link the program, or `None` if it doesn't.
+/// This swaps in its own panic hook while it runs, so that panics aren't printed and their place is kept,
+/// so it shouldn't be used while other threads might panic.
+/// A stack overflow aborts the process instead of panicking, so it can't be caught here.
+pub fn failure(bytes: &[u8]) -> Option<Failure> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|info| {
+        let location = info.location().map(|location| location.to_string());
+        PANIC_LOCATION.with(|cell| *cell.borrow_mut() = location);
+    }));
+    let res = panic::catch_unwind(AssertUnwindSafe(|| Module::new(vec![bytes.to_vec()])));
+    panic::set_hook(hook);
+    match res {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(Failure::Error(e)),
+        Err(payload) => {
+            let msg = match payload.downcast_ref::<&str>() {
+                Some(msg) => msg.to_string(),
+                None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+            };
+            let location = PANIC_LOCATION.with(|cell| cell.borrow_mut().take()).unwrap_or_default();
+            Some(Failure::Panic(location, msg))
+        }
+    }
+}
+
+/// What `minimize` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Minimized {
+    /// How many smaller programs were tried.
+    pub tries: usize,
+    /// How many functions were taken out, declarations and bodies both.
+    pub functions: usize,
+    /// How many named types were taken out.
+    pub types: usize,
+    /// How many ops were taken out of the declarations and bodies that were left.
+    pub ops: usize,
+}
+
+/// Shrink `bytes` as far as it'll go while `fails` says it still fails the way it should,
+/// which is usually that `failure` gives something `same_as` the failure of the original.
+pub fn minimize(bytes: &[u8], fails: impl FnMut(&[u8]) -> bool) -> (ByteStream, Minimized) {
+    let mut minimizer = Minimizer {
+        best: bytes.to_vec(),
+        fails,
+        minimized: Minimized::default(),
+    };
+    loop {
+        let len = minimizer.best.len();
+        if let Some(shape) = Shape::new(&minimizer.best) {
+            minimizer.shrink_shape(shape);
+        }
+        // this is slow on a big program, so it waits until the program's parts can't be shrunk any more
+        if minimizer.best.len() == len {
+            let mut bytes = minimizer.best.clone();
+            minimizer.shrink_runs(&mut bytes, Vec::len, |bytes, range| cut(bytes, range), Clone::clone);
+        }
+        if minimizer.best.len() == len {
+            return (minimizer.best, minimizer.minimized);
+        }
+    }
+}
+
+struct Minimizer<F> {
+    /// The smallest program so far that fails.
+    best: ByteStream,
+    fails: F,
+    minimized: Minimized,
+}
+
+impl<F: FnMut(&[u8]) -> bool> Minimizer<F> {
+    /// Keep `bytes` if it's smaller than the best so far and still fails.
+    fn keep(&mut self, bytes: ByteStream) -> bool {
+        if bytes.len() >= self.best.len() {
+            return false;
+        }
+        self.minimized.tries += 1;
+        if !(self.fails)(&bytes) {
+            return false;
+        }
+        self.best = bytes;
+        true
+    }
+
+    fn shrink_shape(&mut self, mut shape: Shape) {
+        // from the last one back, so those still to try keep their labels
+        for i in (0..shape.decs.len()).rev() {
+            let smaller = shape.without_function(i);
+            if self.keep(smaller.bytes()) {
+                shape = smaller;
+                self.minimized.functions += 1;
+            }
+        }
+        for i in (0..shape.types.len()).rev() {
+            let smaller = shape.without_type(i);
+            if self.keep(smaller.bytes()) {
+                shape = smaller;
+                self.minimized.types += 1;
+            }
+        }
+        for place in shape.places() {
+            self.minimized.ops += self.shrink_runs(
+                &mut shape,
+                |shape| shape.ops(place).len(),
+                |shape, range| shape.without_ops(place, range),
+                Shape::bytes,
+            );
+        }
+        self.shrink_runs(
+            &mut shape,
+            |shape| shape.data_section.len(),
+            |shape, range| Shape { data_section: cut(&shape.data_section, range), ..shape.clone() },
+            Shape::bytes,
+        );
+        self.shrink_runs(
+            &mut shape,
+            |shape| shape.rest.len(),
+            |shape, range| Shape { rest: cut(&shape.rest, range), ..shape.clone() },
+            Shape::bytes,
+        );
+    }
+
+    /// Take runs of things out of `state` for as long as the program still fails, trying the run of half of them,
+    /// then of a quarter, and so on down to each thing on its own. This returns how many were taken out.
+    fn shrink_runs<T>(
+        &mut self,
+        state: &mut T,
+        len: impl Fn(&T) -> usize,
+        without: impl Fn(&T, Range<usize>) -> T,
+        bytes: impl Fn(&T) -> ByteStream,
+    ) -> usize {
+        let mut removed = 0;
+        let mut run = len(state).div_ceil(2);
+        while run > 0 {
+            // from the end back, so that the runs still to try don't move when one is taken out
+            let mut end = len(state);
+            while end > 0 {
+                let start = end.saturating_sub(run);
+                let smaller = without(state, start..end);
+                if self.keep(bytes(&smaller)) {
+                    *state = smaller;
+                    removed += end - start;
+                }
+                end = start;
+            }
+            run /= 2;
+        }
+        removed
+    }
+}
+
+fn cut<T: Clone>(xs: &[T], range: Range<usize>) -> Vec<T> {
+    let mut xs = xs.to_vec();
+    xs.drain(range);
+    xs
+}
+
+/// Where a list of ops is in a `Shape`.
+#[derive(Clone, Copy)]
+enum Place {
+    Type(usize),
+    Dec(usize),
+    Body(usize),
+    Trailing,
+}
+
+/// A program split up into the parts the parser sees, each op kept as its bytes.
+/// Putting the parts back together gives the program back as it was, however little of it makes sense.
+#[derive(Clone)]
+struct Shape {
+    /// The feature header, as it was.
+    header: Vec<u8>,
+    /// Whether the header has a checksum that's right, and so should be kept right as the program shrinks.
+    checksum: bool,
+    data_section: Vec<u8>,
+    /// The number of functions.
+    n: u32,
+    /// The number of named types, if the header says there's a count of them.
+    m: Option<u32>,
+    body_sizes: Option<Vec<u32>>,
+    types: Vec<Vec<Vec<u8>>>,
+    decs: Vec<Vec<Vec<u8>>>,
+    bodies: Vec<Vec<Vec<u8>>>,
+    /// The ops after the last body.
+    trailing: Vec<Vec<u8>>,
+    /// Whatever's after the last op that can be lexed.
+    rest: Vec<u8>,
+}
+
+impl Shape {
+    /// Split `bytes` up, or `None` if it stops before its first op, or its prelude is too broken to read.
+    fn new(bytes: &[u8]) -> Option<Shape> {
+        let prelude = parse::prelude(bytes, &Limits::default()).ok()??;
+        let header_len = prelude.header.len;
+        let m = prelude.header.type_decls.then_some(prelude.m);
+        let mut shape = Shape {
+            header: bytes[..header_len].to_vec(),
+            checksum: prelude.header.checksum == Some(checksum::crc32(&bytes[header_len..])),
+            data_section: prelude.data_section,
+            n: prelude.n,
+            m,
+            body_sizes: prelude.body_sizes,
+            types: vec![],
+            decs: vec![],
+            bodies: vec![],
+            trailing: vec![],
+            rest: vec![],
+        };
+        let mut rest = &bytes[prelude.len..];
+        let mut ops = vec![];
+        while !rest.is_empty() {
+            let Ok(Some((op, len))) = parse::lex_op(rest, 0) else {
+                break;
+            };
+            ops.push(rest[..len].to_vec());
+            rest = &rest[len..];
+            match shape.place_of_next() {
+                Place::Type(_) | Place::Dec(_) if matches!(op, Op1::Lced | Op1::Export(_, _) | Op1::Import(_, _)) => {
+                    shape.push(std::mem::take(&mut ops));
+                }
+                Place::Body(_) if matches!(op, Op1::Call | Op1::CallNZ | Op1::Halt) => shape.push(std::mem::take(&mut ops)),
+                Place::Trailing => shape.push(std::mem::take(&mut ops)),
+                _ => {}
+            }
+        }
+        // a declaration or body that the program ends in the middle of
+        if !ops.is_empty() {
+            shape.push(ops);
+        }
+        shape.rest = rest.to_vec();
+        Some(shape)
+    }
+
+    /// Where the parser would put the next declaration or body.
+    fn place_of_next(&self) -> Place {
+        if self.types.len() < self.m.unwrap_or(0) as usize {
+            Place::Type(self.types.len())
+        } else if self.decs.len() < self.n as usize {
+            Place::Dec(self.decs.len())
+        } else if self.bodies.len() < self.decs.iter().filter(|dec| !imported(dec)).count() {
+            Place::Body(self.bodies.len())
+        } else {
+            Place::Trailing
+        }
+    }
+
+    fn push(&mut self, ops: Vec<Vec<u8>>) {
+        match self.place_of_next() {
+            Place::Type(_) => self.types.push(ops),
+            Place::Dec(_) => self.decs.push(ops),
+            Place::Body(_) => self.bodies.push(ops),
+            Place::Trailing => self.trailing.extend(ops),
+        }
+    }
+
+    fn places(&self) -> Vec<Place> {
+        let types = (0..self.types.len()).map(Place::Type);
+        let decs = (0..self.decs.len()).map(Place::Dec);
+        let bodies = (0..self.bodies.len()).map(Place::Body);
+        types.chain(decs).chain(bodies).chain([Place::Trailing]).collect()
+    }
+
+    fn ops(&self, place: Place) -> &Vec<Vec<u8>> {
+        match place {
+            Place::Type(i) => &self.types[i],
+            Place::Dec(i) => &self.decs[i],
+            Place::Body(i) => &self.bodies[i],
+            Place::Trailing => &self.trailing,
+        }
+    }
+
+    fn without_ops(&self, place: Place, range: Range<usize>) -> Shape {
+        let mut shape = self.clone();
+        let ops = match place {
+            Place::Type(i) => &mut shape.types[i],
+            Place::Dec(i) => &mut shape.decs[i],
+            Place::Body(i) => &mut shape.bodies[i],
+            Place::Trailing => &mut shape.trailing,
+        };
+        let removed = ops.drain(range).map(|op| op.len() as u32).sum::<u32>();
+        // keeping the body's size right, if it was
+        if let (Place::Body(i), Some(sizes)) = (place, &mut shape.body_sizes) {
+            if let Some(size) = sizes.get_mut(i) {
+                *size = size.saturating_sub(removed);
+            }
+        }
+        shape
+    }
+
+    /// Take out function `i`'s declaration and body, and renumber the functions after it.
+    fn without_function(&self, i: usize) -> Shape {
+        let mut shape = self.clone();
+        let dec = shape.decs.remove(i);
+        if !imported(&dec) {
+            let body = self.decs[..i].iter().filter(|dec| !imported(dec)).count();
+            if body < shape.bodies.len() {
+                shape.bodies.remove(body);
+            }
+            if let Some(sizes) = &mut shape.body_sizes {
+                if body < sizes.len() {
+                    sizes.remove(body);
+                }
+            }
+        }
+        shape.n = shape.n.saturating_sub(1);
+        shape.renumber(i as u32, |op| match op {
+            Op1::GlobalFunc(label) => Some(label),
+            _ => None,
+        });
+        shape
+    }
+
+    /// Take out named type `i`'s declaration, and renumber the named types after it.
+    fn without_type(&self, i: usize) -> Shape {
+        let mut shape = self.clone();
+        shape.types.remove(i);
+        shape.m = shape.m.map(|m| m.saturating_sub(1));
+        shape.renumber(i as u32, |op| match op {
+            Op1::Named(k) | Op1::Fold(k) => Some(k),
+            _ => None,
+        });
+        shape
+    }
+
+    /// Lower by one each four-byte immediate after `removed` of the ops that `numbered` finds one in.
+    fn renumber(&mut self, removed: u32, numbered: impl Fn(Op1) -> Option<u32>) {
+        let lists = self.types.iter_mut().chain(&mut self.decs).chain(&mut self.bodies);
+        for op in lists.flatten().chain(&mut self.trailing) {
+            if let Some(k) = lex(op).and_then(&numbered).filter(|k| *k > removed) {
+                op.truncate(1);
+                Imm::U32(k - 1).write(op);
+            }
+        }
+    }
+
+    /// Put the parts back together into a program.
+    fn bytes(&self) -> ByteStream {
+        let mut rest = vec![];
+        rest.extend((self.data_section.len() as u32).to_le_bytes());
+        rest.extend(&self.data_section);
+        rest.extend(self.n.to_le_bytes());
+        if let Some(m) = self.m {
+            rest.extend(m.to_le_bytes());
+        }
+        if let Some(sizes) = &self.body_sizes {
+            rest.extend((sizes.len() as u32).to_le_bytes());
+            for size in sizes {
+                rest.extend(size.to_le_bytes());
+            }
+        }
+        let lists = self.types.iter().chain(&self.decs).chain(&self.bodies);
+        for op in lists.flatten().chain(&self.trailing) {
+            rest.extend(op);
+        }
+        rest.extend(&self.rest);
+        let mut bytes = self.header.clone();
+        if self.checksum {
+            let len = bytes.len();
+            bytes[len - 4..].copy_from_slice(&checksum::crc32(&rest).to_le_bytes());
+        }
+        bytes.extend(rest);
+        bytes
+    }
+}
+
+fn lex(op: &[u8]) -> Option<Op1> {
+    parse::lex_op(op, 0).ok().flatten().map(|(op, _)| op)
+}
+
+/// Whether a function's declaration says it's imported, so it has no body in the program.
+fn imported(dec: &[Vec<u8>]) -> bool {
+    matches!(dec.last().and_then(|op| lex(op)), Some(Op1::Import(_, _)))
+}
@@ -0,0 +1,94 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Loading programs straight from memory-mapped files, so a very large module never has to be read into a `Vec` first.
+//! The parser reads each byte once, in order, so the OS only pages in the file as it's lexed.
+//!
+//! ```ignore
+//! // safe as long as nothing changes big.svm while the module is being built
+//! let prog = unsafe { Mapped::open("big.svm")? };
+//! let module = Module::with_config(vec![prog], &Config::default(), &Cancellation::default(), &[])?;
+//! ```
+
+use std::ffi::{c_int, c_void};
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+const PROT_READ: c_int = 1;
+const MAP_PRIVATE: c_int = 2;
+
+// `offset` is an `off_t`, which is an `i64` on the 64-bit targets this module is built for
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// A file mapped read-only into memory, which derefs to its bytes.
+pub struct Mapped {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// The mapping is read-only, so sharing it is like sharing a `&[u8]`.
+unsafe impl Send for Mapped {}
+unsafe impl Sync for Mapped {}
+
+impl Mapped {
+    /// Map the file at `path`.
+    ///
+    /// # Safety
+    /// The file mustn't be written to or truncated, by this process or any other, while the `Mapped` is around.
+    /// The mapping is private, but the OS still shows it changes to the file that weren't made through it,
+    /// so the bytes could change under a `&[u8]` of them, and reading past a truncated end raises `SIGBUS`.
+    /// Map files the host controls, or read untrusted ones with `fs::read` instead.
+    pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Mapped> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // mmap can't map nothing
+            return Ok(Mapped {
+                ptr: ptr::null_mut(),
+                len,
+            });
+        }
+        // the mapping outlives the file descriptor, so `file` can be closed once this returns
+        let ptr = unsafe { mmap(ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapped { ptr, len })
+    }
+}
+
+impl Deref for Mapped {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.len {
+            0 => &[],
+            len => unsafe { slice::from_raw_parts(self.ptr as *const u8, len) },
+        }
+    }
+}
+
+impl AsRef<[u8]> for Mapped {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { munmap(self.ptr, self.len) };
+        }
+    }
+}
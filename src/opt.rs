@@ -0,0 +1,385 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Optimizations that rewrite a program into a faster one, for `sabervm opt`.
+//!
+//! A pass takes a program that verifies and rewrites the bodies of its functions.
+//! Every body it changes is verified again against the function's signature, and kept as it was if it doesn't verify,
+//! then the whole program it writes is verified once more. So a pass can fail to speed a program up,
+//! but it can't make one that the VM would refuse, or that does something the original couldn't have.
+//!
+//! Passes work on the ops of the bytecode format rather than on the verified IR, so there's something to verify again,
+//! and they write the program back out through its assembly, so everything in its header comes through as it was.
+
+use crate::asm::{self, Item, Line};
+use crate::header::*;
+use crate::ir;
+use crate::parse;
+use crate::stack::Stack;
+use crate::verify::Signatures;
+
+use std::collections::{HashMap, HashSet};
+
+/// What `inline` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Inlined {
+    /// How many calls were replaced with the body of the function they called.
+    pub sites: usize,
+    /// How many functions had calls inlined into them.
+    pub functions: usize,
+    /// How many functions would have had calls inlined, but didn't verify afterwards, so were left alone.
+    pub rejected: usize,
+}
+
+/// Inline calls to small functions, those with at most `threshold` ops in their bodies.
+///
+/// Calls in SaberVM never return, so a `call` is always the last op of a body, and the callee takes over the stack as it is:
+/// the arguments on top, in the same places its body expects them. So the call `global_func f; call`
+/// can be replaced by `f`'s body, without moving anything. The body that's pasted in might end with another call
+/// to inline, and so on, but never one to a function that's already been pasted in on the way there, so recursion
+/// is never unrolled, and no function grows by more than `threshold` ops altogether.
+/// Only calls to functions without type or region parameters are inlined, since the others need arguments at the call
+/// that the pasted-in body would use differently. `call_nz` chooses between two functions, so those calls stay too.
+pub fn inline(bytes: &ByteStream, threshold: usize) -> Result<(ByteStream, Inlined), Error> {
+    ir::verify(bytes)?;
+    let (data_section, type_decs, forward_decs, stmts) = parse::go(bytes)?;
+    let sigs = Signatures::new(&type_decs, &forward_decs, &Limits::default(), None)?;
+    let bodies = stmts
+        .iter()
+        .map(|Stmt1::Func(label, _, ops)| (*label, ops.as_slice()))
+        .collect::<HashMap<_, _>>();
+    let small = |label: &Label| {
+        let monomorphic = matches!(sigs.signature(*label), Some(Type::Func(_)));
+        monomorphic && bodies.get(label).is_some_and(|ops| ops.len() <= threshold)
+    };
+    let mut inlined = Inlined::default();
+    let mut rewritten = HashMap::new();
+    for Stmt1::Func(label, pos, ops) in &stmts {
+        let mut new_ops = ops.clone();
+        let mut pasted = HashSet::from([*label]);
+        let mut sites = 0;
+        while let [.., Op1::GlobalFunc(callee), Op1::Call] = new_ops[..] {
+            let body = bodies.get(&callee).copied().unwrap_or_default();
+            let grown = new_ops.len() - 2 + body.len();
+            if !small(&callee) || pasted.contains(&callee) || grown > ops.len() + threshold {
+                break;
+            }
+            new_ops.truncate(new_ops.len() - 2);
+            new_ops.extend(body);
+            pasted.insert(callee);
+            sites += 1;
+        }
+        if sites == 0 {
+            continue;
+        }
+        let stmt = Stmt1::Func(*label, *pos, new_ops);
+        if sigs.check_body(&data_section, &stmt, &Cancellation::default(), None).is_err() {
+            inlined.rejected += 1;
+            continue;
+        }
+        let Stmt1::Func(_, _, new_ops) = stmt;
+        rewritten.insert(*label, new_ops);
+        inlined.sites += sites;
+        inlined.functions += 1;
+    }
+    let out = rewrite(bytes, &rewritten)?;
+    ir::verify(&out)?;
+    Ok((out, inlined))
+}
+
+/// What `eliminate_dead` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Eliminated {
+    /// How many ops were removed because nothing used what they pushed.
+    pub ops: usize,
+    /// How many `get`s of a copy were made to get the original instead.
+    pub gets: usize,
+    /// How many functions were changed.
+    pub functions: usize,
+    /// How many functions would have been changed, but didn't verify afterwards, so were left alone.
+    pub rejected: usize,
+}
+
+/// Remove the ops that push values nothing uses, and make `get`s of copies get the original.
+///
+/// Naive frontends push values they end up not needing, like a `get` of every variable before each statement,
+/// and copy copies, like `get 0; get 0`. This follows each value in a body from the op that pushes it
+/// to the ops that use it, with the stack's types from the verifier's trace saying how many values each op pushes.
+/// An op is removed if it can't trap or touch memory, every value it pushes is unused or only used by other removed ops,
+/// and every value it pops was pushed by another removed op, so the stack below is left as it was.
+/// Calls never return, so whatever is left on the stack under a call's arguments is unused.
+/// A `get` or `share` of a value that was itself copied with `get` or `share` copies the original instead,
+/// if it's still on the stack, which can leave the copy unused.
+/// The remaining `get`s and `share`s are renumbered for the values removed above what they copy.
+/// A body with an op this doesn't know the stack effect of is left alone.
+pub fn eliminate_dead(bytes: &ByteStream) -> Result<(ByteStream, Eliminated), Error> {
+    ir::verify(bytes)?;
+    let (data_section, type_decs, forward_decs, stmts) = parse::go(bytes)?;
+    let sigs = Signatures::new(&type_decs, &forward_decs, &Limits::default(), None)?;
+    let mut eliminated = Eliminated::default();
+    let mut rewritten = HashMap::new();
+    for stmt in &stmts {
+        let Stmt1::Func(label, pos, ops) = stmt;
+        let mut trace = vec![];
+        sigs.check_body(&data_section, stmt, &Cancellation::default(), Some(&mut trace))?;
+        trace.retain(|e| !e.forward_dec);
+        let Some((new_ops, removed, gets)) = dead_ops(ops, &trace) else {
+            continue;
+        };
+        if removed == 0 && gets == 0 {
+            continue;
+        }
+        let stmt = Stmt1::Func(*label, *pos, new_ops);
+        if sigs.check_body(&data_section, &stmt, &Cancellation::default(), None).is_err() {
+            eliminated.rejected += 1;
+            continue;
+        }
+        let Stmt1::Func(_, _, new_ops) = stmt;
+        rewritten.insert(*label, new_ops);
+        eliminated.ops += removed;
+        eliminated.gets += gets;
+        eliminated.functions += 1;
+    }
+    let out = rewrite(bytes, &rewritten)?;
+    ir::verify(&out)?;
+    Ok((out, eliminated))
+}
+
+/// How many values `op` pops off the runtime stack, given the stack's types before and after it,
+/// or `None` if it isn't one `eliminate_dead` knows.
+fn pops(op: Op1, before: &Stack<Type>, after: &Stack<Type>) -> Option<usize> {
+    match op {
+        // these only change the compile-time stack
+        Op1::Unique
+        | Op1::Handle
+        | Op1::I32
+        | Op1::U8
+        | Op1::Tuple(_)
+        | Op1::TupleFields(_)
+        | Op1::Field(_)
+        | Op1::Some
+        | Op1::All
+        | Op1::Rgn
+        | Op1::SomeRgn
+        | Op1::End
+        | Op1::Func(_)
+        | Op1::CTGet(_)
+        | Op1::Size(_)
+        | Op1::Ptr
+        | Op1::Arr
+        | Op1::Named(_)
+        | Op1::DataSec
+        | Op1::BigInt
+        | Op1::Buf
+        | Op1::Map
+        | Op1::Nop
+        | Op1::Marker(_) => Some(0),
+        Op1::Lit(_) | Op1::U8Lit(_) | Op1::GlobalFunc(_) | Op1::Get(_) | Op1::Share(_) | Op1::NewRgn(_) | Op1::BigData(_) => Some(0),
+        Op1::App
+        | Op1::Unpack
+        | Op1::Pack
+        | Op1::Fold(_)
+        | Op1::Unfold
+        | Op1::Malloc
+        | Op1::Proj(_)
+        | Op1::Deref
+        | Op1::U8ToI32
+        | Op1::I32ToU8
+        | Op1::BigToI32
+        | Op1::BufLen
+        | Op1::MapLen => Some(1),
+        Op1::Init(_)
+        | Op1::Add
+        | Op1::AddTrap
+        | Op1::AddSat
+        | Op1::Sub
+        | Op1::SubTrap
+        | Op1::SubSat
+        | Op1::Mul
+        | Op1::MulTrap
+        | Op1::MulSat
+        | Op1::Div
+        | Op1::DivTrap
+        | Op1::Modulo
+        | Op1::ModuloTrap
+        | Op1::BigFromI32
+        | Op1::BigCmp
+        | Op1::BufNew
+        | Op1::BufGetU8
+        | Op1::BufGetU32
+        | Op1::MapGet
+        | Op1::MapHas
+        | Op1::MapRemove => Some(2),
+        Op1::BigAdd
+        | Op1::BigSub
+        | Op1::BigMul
+        | Op1::BigDiv
+        | Op1::BigModulo
+        | Op1::BufSetU8
+        | Op1::BufSetU32
+        | Op1::MapSet => Some(3),
+        // these push nothing
+        Op1::Call | Op1::CallNZ | Op1::Halt | Op1::FreeRgn => before.len().checked_sub(after.len()),
+        _ => None,
+    }
+}
+
+/// Whether `op` only computes what it pushes from what it pops, so it can go if nothing uses that.
+fn pure(op: Op1) -> bool {
+    matches!(
+        op,
+        Op1::Lit(_)
+            | Op1::U8Lit(_)
+            | Op1::GlobalFunc(_)
+            | Op1::Get(_)
+            | Op1::Share(_)
+            | Op1::Add
+            | Op1::AddSat
+            | Op1::Sub
+            | Op1::SubSat
+            | Op1::Mul
+            | Op1::MulSat
+            | Op1::U8ToI32
+            | Op1::I32ToU8
+            | Op1::BigData(_)
+            | Op1::BigCmp
+            | Op1::BufLen
+            | Op1::MapHas
+            | Op1::MapLen
+    )
+}
+
+/// The body `ops` without its dead ops, how many were removed, and how many `get`s were made to copy an original,
+/// given the verifier's trace of the body. `None` if the body has an op `pops` doesn't know.
+fn dead_ops(ops: &[Op1], trace: &[Explained]) -> Option<(Vec<Op1>, usize, usize)> {
+    if trace.len() != ops.len() {
+        return None;
+    }
+    // The values are numbered in the order they're pushed, starting with the parameters.
+    // `producer[v]` is the op that pushed value `v`, and `original[v]` is what it's a copy of, if it is one.
+    let params = trace.first()?.before.stack_type.len();
+    let mut producer: Vec<Option<usize>> = vec![None; params];
+    let mut original: Vec<Option<usize>> = vec![None; params];
+    let mut stack: Vec<usize> = (0..params).collect();
+    let mut inputs = vec![vec![]; ops.len()];
+    let mut outputs = vec![vec![]; ops.len()];
+    // what each `get` and `share` copies, once it's made to copy the original
+    let mut copies = HashMap::new();
+    let mut shortened = HashSet::new();
+    for (i, (op, e)) in ops.iter().zip(trace).enumerate() {
+        let (before, after) = (&e.before.stack_type, &e.after.stack_type);
+        let popped = pops(*op, before, after)?;
+        let pushed = (after.len() + popped).checked_sub(before.len())?;
+        if before.len() != stack.len() || popped > stack.len() {
+            return None;
+        }
+        if let Op1::Get(n) | Op1::Share(n) = op {
+            let copied = stack[stack.len() - 1 - usize::from(*n)];
+            let target = match original[copied] {
+                Some(o) if stack.iter().rev().take(256).any(|&v| v == o) => {
+                    shortened.insert(i);
+                    o
+                }
+                _ => copied,
+            };
+            copies.insert(i, target);
+        }
+        inputs[i] = stack.split_off(stack.len() - popped);
+        for _ in 0..pushed {
+            let v = producer.len();
+            producer.push(Some(i));
+            original.push(copies.get(&i).copied());
+            outputs[i].push(v);
+            stack.push(v);
+        }
+    }
+    // who uses each value
+    let mut users = vec![vec![]; producer.len()];
+    for (i, vs) in inputs.iter().enumerate() {
+        for &v in vs {
+            users[v].push(i);
+        }
+    }
+    for (&i, &v) in &copies {
+        users[v].push(i);
+    }
+    // start with every pure op removed, and keep any that something kept needs, until nothing changes
+    let mut removed = ops.iter().map(|op| pure(*op)).collect::<Vec<_>>();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..ops.len() {
+            if !removed[i] {
+                continue;
+            }
+            let needed = outputs[i].iter().any(|&v| users[v].iter().any(|&u| !removed[u]));
+            let keeps_input = inputs[i].iter().any(|&v| producer[v].is_none_or(|p| !removed[p]));
+            if needed || keeps_input {
+                removed[i] = false;
+                changed = true;
+            }
+        }
+    }
+    // write out the rest, with the indices of `get` and `share` counted on the stack without the removed values
+    let mut stack: Vec<usize> = (0..params).collect();
+    let mut new_ops = vec![];
+    for (i, op) in ops.iter().enumerate() {
+        if removed[i] {
+            continue;
+        }
+        let op = match (op, copies.get(&i)) {
+            (Op1::Get(_) | Op1::Share(_), Some(target)) => {
+                let depth = stack.iter().rev().position(|v| v == target)?;
+                let n = u8::try_from(depth).ok()?;
+                if let Op1::Get(_) = op { Op1::Get(n) } else { Op1::Share(n) }
+            }
+            _ => *op,
+        };
+        stack.truncate(stack.len().checked_sub(inputs[i].len())?);
+        stack.extend(&outputs[i]);
+        new_ops.push(op);
+    }
+    let gets = shortened.iter().filter(|&&i| !removed[i]).count();
+    let removed = removed.iter().filter(|&&r| r).count();
+    Some((new_ops, removed, gets))
+}
+
+/// The program with the given functions' bodies swapped for new ones,
+/// written out by way of its assembly so the header, the data section, and the declarations stay as they were.
+fn rewrite(bytes: &ByteStream, bodies: &HashMap<Label, Vec<Op1>>) -> Result<ByteStream, Error> {
+    let line = |item| Line {
+        line: 0,
+        item: Some(item),
+        comment: None,
+    };
+    let mut lines = vec![];
+    let mut label = None;
+    let mut next_label: Label = 0;
+    // whether the ops are the old body of a function that has a new one
+    let mut replaced = false;
+    for l in asm::parse(&asm::disassemble(bytes)?)? {
+        match &l.item {
+            Some(Item::Func(_)) => {
+                label = Some(next_label);
+                next_label += 1;
+                replaced = false;
+            }
+            Some(Item::Type) => replaced = false,
+            Some(Item::Body) => {
+                if let Some(ops) = label.and_then(|label| bodies.get(&label)) {
+                    lines.push(l);
+                    lines.extend(ops.iter().map(|op| line(Item::Op(*op))));
+                    replaced = true;
+                    continue;
+                }
+            }
+            Some(Item::Op(_)) if replaced => continue,
+            _ => {}
+        }
+        lines.push(l);
+    }
+    asm::assemble(&lines)
+}
@@ -4,20 +4,222 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use crate::checksum;
 use crate::header::*;
+use crate::log::{self, event, Level};
+use crate::metrics::{self, Counter, Phase};
+
+use std::ops::Range;
+use std::time::Instant;
 
 /// Output of the lexer, input of the parser.
 /// A sequence of (possibly parameterized) opcodes.
 type LexedOpcodes = Vec<Op1>;
 
-/// Lex bytes into (possibly parameterized) intructions.
-fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
-    let mut bytes_iter = bytes.iter();
+/// What a feature header says besides its bits.
+pub(crate) struct FeatureHeader {
+    /// How many bytes the header takes up, which is 0 if there isn't one.
+    pub(crate) len: usize,
+    /// The feature bits, which are 0 if there isn't a header.
+    pub(crate) bits: u32,
+    /// Where the lint config is in the program, if it has one.
+    lint_config: Option<Range<usize>>,
+    /// Where the metadata is in the program, if it has any.
+    metadata: Option<Range<usize>>,
+    /// Where the export names are in the program, if it has any.
+    export_names: Option<Range<usize>>,
+    pub(crate) checksum: Option<u32>,
+    /// Whether the function count is followed by a count of named types.
+    pub(crate) type_decls: bool,
+    /// Whether the counts are followed by a table of body sizes.
+    sized_bodies: bool,
+}
+
+/// The little-endian u32 at `i` in `bytes`, if `bytes` goes that far.
+fn u32_at(bytes: &[u8], i: usize) -> Option<u32> {
+    let b = bytes.get(i..i.checked_add(4)?)?;
+    b.try_into().ok().map(u32::from_le_bytes)
+}
+
+/// Read the feature header, if there is one, checking its bits against what this build supports.
+/// This returns `None` if `bytes` stops partway through it.
+fn feature_header(bytes: &[u8]) -> Result<Option<FeatureHeader>, Error> {
+    let u32_at = |i: usize| u32_at(bytes, i);
+    if !bytes.starts_with(&FEATURE_HEADER_MAGIC) {
+        return Ok(Some(FeatureHeader {
+            len: 0,
+            bits: 0,
+            lint_config: None,
+            metadata: None,
+            export_names: None,
+            checksum: None,
+            type_decls: false,
+            sized_bodies: false,
+        }));
+    }
+    let Some(bits) = u32_at(4) else {
+        return Ok(None);
+    };
+    for feature in Feature::ALL {
+        if bits & feature.bit() != 0 && !feature.supported() {
+            return Err(Error::UnsupportedFeature(feature));
+        }
+    }
+    let unknown = Feature::ALL.iter().fold(bits, |bits, feature| bits & !feature.bit());
+    if unknown != 0 {
+        return Err(Error::UnknownFeatureBits(unknown));
+    }
+    let mut len = 8;
+    let mut lint_config = None;
+    if bits & Feature::LintConfig.bit() != 0 {
+        let Some(config_len) = u32_at(8) else {
+            return Ok(None);
+        };
+        len = 12 + config_len as usize;
+        lint_config = Some(12..len);
+    }
+    let mut metadata = None;
+    if bits & Feature::Metadata.bit() != 0 {
+        let Some(metadata_len) = u32_at(len) else {
+            return Ok(None);
+        };
+        metadata = Some(len + 4..len + 4 + metadata_len as usize);
+        len += 4 + metadata_len as usize;
+    }
+    let mut export_names = None;
+    if bits & Feature::ExportNames.bit() != 0 {
+        let Some(names_len) = u32_at(len) else {
+            return Ok(None);
+        };
+        export_names = Some(len + 4..len + 4 + names_len as usize);
+        len += 4 + names_len as usize;
+    }
+    let mut checksum = None;
+    if bits & Feature::Checksum.bit() != 0 {
+        let Some(sum) = u32_at(len) else {
+            return Ok(None);
+        };
+        checksum = Some(sum);
+        len += 4;
+    }
+    if bytes.len() < len {
+        return Ok(None);
+    }
+    Ok(Some(FeatureHeader {
+        len,
+        bits,
+        lint_config,
+        metadata,
+        export_names,
+        checksum,
+        type_decls: bits & Feature::TypeDecls.bit() != 0,
+        sized_bodies: bits & Feature::SizedBodies.bit() != 0,
+    }))
+}
+
+/// Check the feature header, if there is one, against what this build supports, and return it.
+fn check_features(bytes: &[u8]) -> Result<FeatureHeader, Error> {
+    feature_header(bytes)?.ok_or(Error::UnexpectedEOF)
+}
+
+/// Check the program's checksum, if it has one.
+fn check_checksum(header: &FeatureHeader, actual: u32) -> Result<(), Error> {
+    match header.checksum {
+        Some(expected) if expected != actual => Err(Error::ChecksumMismatch(expected, actual)),
+        _ => Ok(()),
+    }
+}
+
+/// The feature bits in the program's header, and how many bytes the header takes up (both 0 if it doesn't have one).
+pub fn features(bytes: &[u8]) -> Result<(u32, usize), Error> {
+    let header = check_features(bytes)?;
+    match u32_at(bytes, 4) {
+        Some(bits) if header.len > 0 => Ok((bits, header.len)),
+        _ => Ok((0, 0)),
+    }
+}
+
+/// The lint config in the program's feature header, if it has one.
+/// The parser skips it, so a program with a bad config still runs; only `lint` reads it.
+pub fn lint_config(bytes: &[u8]) -> Result<Option<String>, Error> {
+    let Some(range) = check_features(bytes)?.lint_config else {
+        return Ok(None);
+    };
+    match String::from_utf8(bytes[range.clone()].to_vec()) {
+        Ok(config) => Ok(Some(config)),
+        Err(_) => Err(Error::BadLintConfig(String::from_utf8_lossy(&bytes[range]).into_owned())),
+    }
+}
+
+/// The metadata in the program's feature header, if it has any.
+/// Like the lint config, the parser skips it, so only tools that ask for it see it.
+pub fn metadata(bytes: &[u8]) -> Result<Option<Metadata>, Error> {
+    let Some(range) = check_features(bytes)?.metadata else {
+        return Ok(None);
+    };
+    match std::str::from_utf8(&bytes[range.clone()]) {
+        Ok(text) => Metadata::parse(text).map(Some),
+        Err(_) => Err(Error::BadMetadata(String::from_utf8_lossy(&bytes[range]).into_owned())),
+    }
+}
+
+/// The little-endian u32 at the start of the export names section, moving past it.
+fn take_u32(section: &mut &[u8]) -> Result<u32, Error> {
+    let n = u32_at(section, 0).ok_or(Error::BadExportNames)?;
+    *section = &section[4..];
+    Ok(n)
+}
+
+/// The first `n` bytes of the export names section, moving past them.
+fn take_bytes<'a>(section: &mut &'a [u8], n: usize) -> Result<&'a [u8], Error> {
+    let (taken, rest) = section.split_at_checked(n).ok_or(Error::BadExportNames)?;
+    *section = rest;
+    Ok(taken)
+}
+
+/// The names the program gives its functions in the feature header, if it has any (see `Feature::ExportNames`),
+/// each checked against `forward_decs` to make sure it names a function with a body.
+pub fn export_names(bytes: &[u8], forward_decs: &[ForwardDec]) -> Result<Vec<(String, Label)>, Error> {
+    let Some(range) = check_features(bytes)?.export_names else {
+        return Ok(vec![]);
+    };
+    let mut section = &bytes[range];
+    let mut names: Vec<(String, Label)> = vec![];
+    while !section.is_empty() {
+        let label = take_u32(&mut section)?;
+        let name_len = take_u32(&mut section)?;
+        let name = std::str::from_utf8(take_bytes(&mut section, name_len as usize)?).map_err(|_| Error::BadExportNames)?;
+        if names.iter().any(|(n, _)| n == name) {
+            return Err(Error::DuplicateExportName(name.to_string()));
+        }
+        match forward_decs.get(label as usize) {
+            Some(ForwardDec::Func(_, Visibility::Import(_, _), _)) | None => {
+                return Err(Error::ExportNameNotFunction(name.to_string(), label))
+            }
+            Some(_) => names.push((name.to_string(), label)),
+        }
+    }
+    Ok(names)
+}
+
+/// Lex bytes into (possibly parameterized) intructions, also returning the number of functions and of named types,
+/// and the table of body sizes, if there is one.
+fn lex(bytes: &[u8], limits: &Limits) -> Result<(Vec<u8>, LexedOpcodes, u32, u32, Option<Vec<u32>>), Error> {
+    if bytes.len() > limits.module_size {
+        return Err(Error::LimitExceeded(Limit::ModuleSize, limits.module_size, bytes.len()));
+    }
+    let header = check_features(bytes)?;
+    if header.checksum.is_some() {
+        check_checksum(&header, checksum::crc32(&bytes[header.len..]))?;
+    }
+    let mut bytes_iter = bytes[header.len..].iter();
     let mut lexed_opcodes = vec![];
     let mut data_section_len_vec: [u8; 4] = [0, 0, 0, 0];
     for i in 0..4 {
         let Some(a) = bytes_iter.next() else {
-            dbg!("a");
+            event!(Level::Debug, "the program ends before the data section's length");
             return Err(Error::UnexpectedEOF);
         };
         data_section_len_vec[i] = *a;
@@ -29,7 +231,7 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
     for _ in 0..data_section_len {
         data_section.push(
             *(bytes_iter.next().ok_or_else(|| {
-                dbg!(data_section_len);
+                event!(Level::Debug, "the program ends inside its {}-byte data section", data_section_len);
                 Error::UnexpectedEOF
             })?),
         );
@@ -38,7 +240,7 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
     for i in 0..4 {
         match bytes_iter.next() {
             None => {
-                dbg!("c");
+                event!(Level::Debug, "the program ends before the number of functions");
                 return Err(Error::UnexpectedEOF);
             }
             Some(b) => {
@@ -48,235 +250,547 @@ fn lex(bytes: &ByteStream) -> Result<(Vec<u8>, LexedOpcodes, u32), Error> {
     }
     let mut pos = 8 + data_section_len_u32;
     let n = u32::from_le_bytes(a);
-    loop {
-        match bytes_iter.next() {
-            None => break,
-            Some(byte) => lexed_opcodes.push(match byte {
-                0x00 => Op1::Unique,
-                0x01 => Op1::Handle,
-                0x02 => Op1::I32,
-                0x03 => match bytes_iter.next() {
-                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
-                    Some(n) => Op1::Tuple(*n),
-                },
-                0x04 => Op1::Some,
-                0x05 => Op1::All,
-                0x06 => Op1::Rgn,
-                0x07 => Op1::End,
-                0x08 => Op1::App,
-                0x09 => match bytes_iter.next() {
-                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
-                    Some(n) => Op1::Func(*n),
-                },
-                0x0A => match bytes_iter.next() {
-                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
-                    Some(n) => Op1::CTGet(*n),
-                },
-                0x0B => Op1::Lced,
-                0x0C => Op1::Unpack,
-                0x0D => match bytes_iter.next() {
-                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
-                    Some(n) => Op1::Get(*n),
-                },
-                0x0E => match bytes_iter.next() {
-                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
-                    Some(n) => Op1::Init(*n),
-                },
-                0x0F => Op1::Malloc,
-                0x10 => match bytes_iter.next() {
-                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
-                    Some(n) => Op1::Proj(*n),
-                },
-                0x11 => Op1::Call,
-                // 0x12 => Op1::Print,
-                0x13 => {
-                    let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
-                    }
-                    Op1::Lit(i32::from_le_bytes(n))
-                }
-                0x14 => {
-                    let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
-                    }
-                    Op1::GlobalFunc(u32::from_le_bytes(n))
-                }
-                0x15 => Op1::Halt,
-                0x16 => Op1::Pack,
-                0x17 => {
-                    let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
-                    }
-                    Op1::Size(u32::from_le_bytes(n))
-                }
-                0x18 => {
-                    let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
-                    }
-                    Op1::NewRgn(u32::from_le_bytes(n))
-                }
-                0x19 => Op1::FreeRgn,
-                0x1A => Op1::Ptr,
-                0x1B => Op1::Deref,
-                0x1C => Op1::Arr,
-                0x1D => Op1::ArrMut,
-                0x1E => Op1::ArrProj,
-                0x1F => Op1::Add,
-                0x20 => Op1::Mul,
-                0x21 => Op1::Div,
-                0x22 => Op1::CallNZ,
-                0x23 => {
-                    let mut n = [0u8, 0, 0, 0];
-                    for i in 0..4 {
-                        n[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
-                    }
-                    Op1::Data(u32::from_le_bytes(n))
-                }
-                0x24 => Op1::DataSec,
-                0x25 => Op1::U8,
-                0x26 => Op1::CopyN,
-                0x27 => match bytes_iter.next() {
-                    Some(n) => Op1::U8Lit(*n),
-                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
-                }
-                0x28 => Op1::U8ToI32,
-                0x29 => {
-                    let mut a: [u8; 8] = [0,0,0,0,0,0,0,0];
-                    let mut b: [u8; 8] = [0,0,0,0,0,0,0,0];
-                    for i in 0..8 {
-                        a[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
-                    }
-                    for i in 0..8 {
-                        b[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
-                    }
-                    Op1::Import(
-                        u64::from_le_bytes(a),
-                        u64::from_le_bytes(b),
-                    )
-                }
-                0x2A => {
-                    let mut a: [u8; 8] = [0,0,0,0,0,0,0,0];
-                    let mut b: [u8; 8] = [0,0,0,0,0,0,0,0];
-                    for i in 0..8 {
-                        a[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
-                    }
-                    for i in 0..8 {
-                        b[i] = *bytes_iter.next().ok_or(Error::SyntaxErrorParamNeeded(pos, *byte))?;
-                    }
-                    Op1::Export(
-                        u64::from_le_bytes(a),
-                        u64::from_le_bytes(b),
-                    )
-                }
-                0x2B => Op1::Modulo,
-                0x2C => Op1::I32ToU8,
-                0x2D => match bytes_iter.next() {
-                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
-                    Some(n) => Op1::Read(*n),
-                },
-                0x2E => match bytes_iter.next() {
-                    None => return Err(Error::SyntaxErrorParamNeeded(pos, *byte)),
-                    Some(n) => Op1::Write(*n),
-                },
-                op => return Err(Error::SyntaxErrorUnknownOp(pos, *op)),
-            }),
+    if n as usize > limits.functions {
+        return Err(Error::LimitExceeded(Limit::Functions, limits.functions, n as usize));
+    }
+    let mut m = 0;
+    if header.type_decls {
+        for i in 0..4 {
+            let Some(b) = bytes_iter.next() else {
+                event!(Level::Debug, "the program ends before the number of named types");
+                return Err(Error::UnexpectedEOF);
+            };
+            a[i] = *b;
+        }
+        pos += 4;
+        m = u32::from_le_bytes(a);
+        if m as usize > limits.types {
+            return Err(Error::LimitExceeded(Limit::Types, limits.types, m as usize));
+        }
+    }
+    let mut body_sizes = None;
+    if header.sized_bodies {
+        let mut read_u32 = |what: &str| {
+            for i in 0..4 {
+                let Some(b) = bytes_iter.next() else {
+                    event!(Level::Debug, "the program ends before {}", what);
+                    return Err(Error::UnexpectedEOF);
+                };
+                a[i] = *b;
+            }
+            Ok(u32::from_le_bytes(a))
+        };
+        let count = read_u32("the number of function bodies")?;
+        if count as usize > limits.functions {
+            return Err(Error::LimitExceeded(Limit::Functions, limits.functions, count as usize));
         }
+        let sizes = (0..count).map(|_| read_u32("the table of body sizes ends")).collect::<Result<Vec<_>, _>>()?;
+        pos += 4 + 4 * count;
+        body_sizes = Some(sizes);
+    }
+    let mut rest = bytes_iter.as_slice();
+    while !rest.is_empty() {
+        let Some((op, len)) = lex_op(rest, pos)? else {
+            return Err(Error::SyntaxErrorParamNeeded(pos, rest[0]));
+        };
+        lexed_opcodes.push(op);
+        rest = &rest[len..];
         pos += 1;
     }
-    Ok((data_section, lexed_opcodes, n))
+    Ok((data_section, lexed_opcodes, n, m, body_sizes))
+}
+
+/// Lex the op at the start of `bytes`, returning it and how many bytes it takes up,
+/// or `None` if `bytes` is empty or stops in the middle of its immediate.
+pub(crate) fn lex_op(bytes: &[u8], pos: Pos) -> Result<Option<(Op1, usize)>, Error> {
+    let Some(&byte) = bytes.first() else {
+        return Ok(None);
+    };
+    let Some(info) = op_info(byte) else {
+        return Err(Error::SyntaxErrorUnknownOp(pos, byte));
+    };
+    let width = info.imm.width();
+    let Some(imm_bytes) = bytes.get(1..1 + width) else {
+        return Ok(None);
+    };
+    let imm = Imm::read(info.imm, &mut imm_bytes.iter(), pos, byte)?;
+    Ok(Some((Op1::from_parts(byte, imm), 1 + width)))
+}
+
+/// The start of a program, up to its first op.
+pub(crate) struct Prelude {
+    pub(crate) header: FeatureHeader,
+    /// How many bytes the prelude takes up.
+    pub(crate) len: usize,
+    pub(crate) data_section: Vec<u8>,
+    /// The number of functions.
+    pub(crate) n: u32,
+    /// The number of named types.
+    pub(crate) m: u32,
+    /// The size of each function body in bytes, if the program says.
+    pub(crate) body_sizes: Option<Vec<u32>>,
+}
+
+/// The prelude at the start of `bytes`, or `None` if `bytes` stops before it does.
+/// The checksum is left for the caller, since it covers the whole rest of the program.
+pub(crate) fn prelude(bytes: &[u8], limits: &Limits) -> Result<Option<Prelude>, Error> {
+    let u32_at = |i: usize| u32_at(bytes, i);
+    // too short to tell if there's a feature header
+    if bytes.len() < FEATURE_HEADER_MAGIC.len() {
+        return Ok(None);
+    }
+    let Some(header) = feature_header(bytes)? else {
+        return Ok(None);
+    };
+    let Some(data_section_len) = u32_at(header.len) else {
+        return Ok(None);
+    };
+    let data_end = header.len + 4 + data_section_len as usize;
+    let Some(n) = u32_at(data_end) else {
+        return Ok(None);
+    };
+    if n as usize > limits.functions {
+        return Err(Error::LimitExceeded(Limit::Functions, limits.functions, n as usize));
+    }
+    let mut len = data_end + 4;
+    let mut m = 0;
+    if header.type_decls {
+        let Some(count) = u32_at(len) else {
+            return Ok(None);
+        };
+        if count as usize > limits.types {
+            return Err(Error::LimitExceeded(Limit::Types, limits.types, count as usize));
+        }
+        len += 4;
+        m = count;
+    }
+    let mut body_sizes = None;
+    if header.sized_bodies {
+        let Some(count) = u32_at(len) else {
+            return Ok(None);
+        };
+        if count as usize > limits.functions {
+            return Err(Error::LimitExceeded(Limit::Functions, limits.functions, count as usize));
+        }
+        let table = len + 4;
+        len = table + 4 * count as usize;
+        let Some(sizes) = (0..count as usize).map(|i| u32_at(table + 4 * i)).collect() else {
+            return Ok(None);
+        };
+        body_sizes = Some(sizes);
+    }
+    Ok(Some(Prelude {
+        data_section: bytes[header.len + 4..data_end].to_vec(),
+        header,
+        len,
+        n,
+        m,
+        body_sizes,
+    }))
+}
+
+/// Keeps one forward declaration or function body within the `Limits`.
+struct BodyLimits {
+    limits: Limits,
+    len: usize,
+    depth: usize,
+}
+
+impl BodyLimits {
+    fn new(limits: Limits) -> Self {
+        BodyLimits { limits, len: 0, depth: 0 }
+    }
+
+    fn check(&mut self, op: &Op1) -> Result<(), Error> {
+        self.len += 1;
+        if self.len > self.limits.body_len {
+            return Err(Error::LimitExceeded(Limit::BodyLen, self.limits.body_len, self.len));
+        }
+        match op {
+            Op1::All | Op1::Some | Op1::Rgn => self.depth += 1,
+            Op1::End => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        if self.depth > self.limits.quantifier_depth {
+            return Err(Error::LimitExceeded(Limit::QuantifierDepth, self.limits.quantifier_depth, self.depth));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that every `tuple_fields n` is followed by exactly `n` `field`s, and that no `field` is anywhere else.
+#[derive(Default)]
+struct Fields {
+    /// After a `tuple_fields n`, `n` and how many of its `field`s are still to come.
+    left: Option<(u8, u8)>,
 }
 
-fn parse_forward_decs(
-    tokens: &LexedOpcodes,
+impl Fields {
+    fn check(&mut self, op: &Op1, pos: Pos) -> Result<(), Error> {
+        match (op, self.left) {
+            (Op1::Field(_), Some((n, left))) => self.left = (left > 1).then_some((n, left - 1)),
+            (Op1::Field(_), None) => return Err(Error::SyntaxErrorStrayField(pos)),
+            (_, Some((n, left))) => return Err(Error::SyntaxErrorFieldCount(pos, n, n - left)),
+            (Op1::TupleFields(n), None) if *n > 0 => self.left = Some((*n, *n)),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `op` isn't from an optional part of the instruction set that the feature bits `features` don't ask for.
+fn check_feature(op: &Op1, features: u32, pos: Pos) -> Result<(), Error> {
+    match op.feature() {
+        Some(feature) if features & feature.bit() == 0 => Err(Error::FeatureNotEnabled(pos, *op, feature)),
+        _ => Ok(()),
+    }
+}
+
+/// How many bytes `op` takes up in the bytecode format.
+fn op_size(op: &Op1) -> u32 {
+    1 + op.info().imm.width() as u32
+}
+
+/// The parser, which takes lexed ops one at a time, so a program can be parsed while it's still arriving (see `stream`).
+/// First come the `m` type declarations and then the `n` forward declarations, each ending at `lced`, `export`, or `import`,
+/// then a body for each function that isn't an import, each ending at its first `call`, `call_nz`, or `halt`.
+pub(crate) struct Parser {
+    limits: Limits,
     n: u32,
-) -> Result<(Vec<ForwardDec>, std::slice::Iter<'_, Op1>, u32), Error> {
-    let mut forward_decs = vec![];
-    let mut tokens_iter = tokens.iter();
-    let mut current_stmt_opcodes = vec![];
-    let mut pos = 0;
-    for i in 0..n {
-        loop {
-            match tokens_iter.next() {
-                None => {
-                    return Err(Error::UnexpectedEOF)
-                }
-                Some(Op1::Lced) => {
-                    forward_decs.push(ForwardDec::Func(i, Visibility::Local, current_stmt_opcodes));
-                    break;
-                }
-                Some(Op1::Export(a, b)) => {
-                    // exported function means the implementation is in this file,
-                    // but other files can refer to it using the 128-bit (non-namespaced) UID that is a and b.
-                    // The type has just been forward-declared,
-                    // so other files can know it before all of this file is processed.
-                    forward_decs.push(ForwardDec::Func(i, Visibility::Export(*a, *b), current_stmt_opcodes));
-                    break;
-                }
-                Some(Op1::Import(a, b)) => {
-                    // imported function means the implementation is in another file,
-                    // which exports it using the 128-bit (non-namespaced) UID that is a and b
-                    // so this won't be one of the implementations in this file.
-                    // However, we now know its type, and we can refer to it with global_func
-                    // as if it were at this spot in the list of functions in this file
-                    forward_decs.push(ForwardDec::Func(i, Visibility::Import(*a, *b), current_stmt_opcodes));
-                    break;
+    m: u32,
+    /// The program's feature bits, which say which optional ops it can use.
+    features: u32,
+    type_decs: Vec<TypeDec>,
+    forward_decs: Vec<ForwardDec>,
+    /// Which forward declaration the body being parsed is for, once they're all in.
+    body: usize,
+    current_stmt_opcodes: Vec<Op1>,
+    body_limits: BodyLimits,
+    pos: u32,
+    trailing: usize,
+    fields: Fields,
+    /// The table of body sizes, if there is one (see `Feature::SizedBodies`), and how many bytes of the current body are in.
+    body_sizes: Option<Vec<u32>>,
+    body_bytes: u32,
+    /// How many bodies have been parsed.
+    bodies_done: usize,
+}
+
+impl Parser {
+    pub(crate) fn new(n: u32, m: u32, body_sizes: Option<Vec<u32>>, features: u32, limits: &Limits) -> Self {
+        Parser {
+            limits: *limits,
+            n,
+            m,
+            features,
+            type_decs: vec![],
+            forward_decs: vec![],
+            body: 0,
+            current_stmt_opcodes: vec![],
+            body_limits: BodyLimits::new(*limits),
+            pos: 0,
+            trailing: 0,
+            fields: Fields::default(),
+            body_sizes,
+            body_bytes: 0,
+            bodies_done: 0,
+        }
+    }
+
+    /// The type declarations, once they've all been parsed.
+    pub(crate) fn type_decs(&self) -> Option<&[TypeDec]> {
+        (self.type_decs.len() == self.m as usize).then_some(&self.type_decs)
+    }
+
+    /// The forward declarations, once they've all been parsed.
+    pub(crate) fn forward_decs(&self) -> Option<&[ForwardDec]> {
+        let done = self.type_decs().is_some() && self.forward_decs.len() == self.n as usize;
+        done.then_some(&self.forward_decs)
+    }
+
+    /// Move on to the next forward declaration that has a body, since imports don't.
+    fn next_body(&mut self) {
+        while let Some(ForwardDec::Func(_, Visibility::Import(_, _), _)) = self.forward_decs.get(self.body) {
+            self.body += 1;
+        }
+        self.body_limits = BodyLimits::new(self.limits);
+        self.body_bytes = 0;
+    }
+
+    /// Check that the table of body sizes, if there is one, has a size for each function with a body.
+    fn check_body_count(&self) -> Result<(), Error> {
+        let Some(sizes) = &self.body_sizes else {
+            return Ok(());
+        };
+        let with_bodies = self.forward_decs.iter().filter(|dec| !matches!(dec, ForwardDec::Func(_, Visibility::Import(_, _), _)));
+        match with_bodies.count() {
+            count if count != sizes.len() => Err(Error::BodyCountMismatch(sizes.len(), count)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count `op` toward the current body's size, if the bodies are sized, checking it's still within it.
+    fn check_body_size(&mut self, label: Label, op: &Op1) -> Result<(), Error> {
+        let Some(sizes) = &self.body_sizes else {
+            return Ok(());
+        };
+        let size = sizes[self.bodies_done];
+        self.body_bytes += op_size(op);
+        let ends = matches!(op, Op1::Call | Op1::CallNZ | Op1::Halt);
+        if self.body_bytes > size || ends && self.body_bytes != size {
+            return Err(Error::BodySizeMismatch(label, size));
+        }
+        Ok(())
+    }
+
+    /// Parse the next op, returning the function body it finishes, if it does.
+    pub(crate) fn push(&mut self, op: Op1) -> Result<Option<Stmt1>, Error> {
+        check_feature(&op, self.features, self.pos)?;
+        if self.forward_decs().is_none() {
+            self.fields.check(&op, self.pos)?;
+            let visibility = match op {
+                Op1::Lced => Visibility::Local,
+                // exported function means the implementation is in this file,
+                // but other files can refer to it using the 128-bit (non-namespaced) UID that is a and b.
+                // The type has just been forward-declared,
+                // so other files can know it before all of this file is processed.
+                Op1::Export(a, b) => Visibility::Export(a, b),
+                // imported function means the implementation is in another file,
+                // which exports it using the 128-bit (non-namespaced) UID that is a and b
+                // so this won't be one of the implementations in this file.
+                // However, we now know its type, and we can refer to it with global_func
+                // as if it were at this spot in the list of functions in this file
+                Op1::Import(a, b) => Visibility::Import(a, b),
+                op => {
+                    self.body_limits.check(&op)?;
+                    self.current_stmt_opcodes.push(op);
+                    self.pos += 1;
+                    return Ok(None);
                 }
-                Some(op) => current_stmt_opcodes.push(*op),
+            };
+            let ops = std::mem::take(&mut self.current_stmt_opcodes);
+            if self.type_decs().is_none() {
+                let k = self.type_decs.len() as u32;
+                self.type_decs.push(TypeDec::Type(k, visibility, ops));
+            } else {
+                let i = self.forward_decs.len() as u32;
+                self.forward_decs.push(ForwardDec::Func(i, visibility, ops));
+            }
+            self.body_limits = BodyLimits::new(self.limits);
+            if self.forward_decs().is_some() {
+                self.check_body_count()?;
+                self.next_body();
+            }
+            return Ok(None);
+        }
+        let Some(ForwardDec::Func(i, _, _)) = self.forward_decs.get(self.body) else {
+            self.trailing += 1;
+            return Ok(None);
+        };
+        let i = *i;
+        self.fields.check(&op, self.pos)?;
+        self.body_limits.check(&op)?;
+        self.check_body_size(i, &op)?;
+        match op {
+            Op1::Call | Op1::CallNZ | Op1::Halt => {
+                self.current_stmt_opcodes.push(op);
+                let stmt = Stmt1::Func(i, self.pos, std::mem::take(&mut self.current_stmt_opcodes));
+                self.body += 1;
+                self.bodies_done += 1;
+                self.next_body();
+                Ok(Some(stmt))
+            }
+            // labels are just indices into the forward declarations, so they can be checked right away
+            Op1::GlobalFunc(label) if label as usize >= self.forward_decs.len() => {
+                Err(Error::SyntaxErrorLabelOutOfRange(self.pos, label, self.forward_decs.len()))
+            }
+            op => {
+                self.current_stmt_opcodes.push(op);
+                self.pos += 1;
+                Ok(None)
             }
-            pos += 1;
         }
-        current_stmt_opcodes = vec![];
     }
-    Ok((forward_decs, tokens_iter, pos))
-}
 
-fn parse(mut tokens_iter: std::slice::Iter<'_, Op1>, forward_decs: &Vec<ForwardDec>, mut pos: u32) -> Result<Vec<Stmt1>, Error> {
-    let mut parsed_stmts = vec![];
-    let mut current_stmt_opcodes = vec![];
-    for decl in forward_decs {
-        match decl {
-            ForwardDec::Func(i, Visibility::Local | Visibility::Export(_, _), _) => {
-                loop {
-                    match tokens_iter.next() {
-                        None => break,
-                        Some(Op1::Call) => {
-                            current_stmt_opcodes.push(Op1::Call);
-                            break;
-                        }
-                        Some(Op1::CallNZ) => {
-                            current_stmt_opcodes.push(Op1::CallNZ);
-                            break;
-                        }
-                        Some(Op1::Halt) => {
-                            current_stmt_opcodes.push(Op1::Halt);
-                            break;
-                        }
-                        Some(op) => current_stmt_opcodes.push(*op),
-                    }
-                    pos += 1;
-                }
-                parsed_stmts.push(Stmt1::Func(*i, pos, current_stmt_opcodes));
-                current_stmt_opcodes = vec![];
+    /// Finish at the end of the program, returning the type and forward declarations, the bodies the end cut short
+    /// (which are left for the verifier to reject), and how many ops came after the last body.
+    pub(crate) fn finish(mut self) -> Result<(Vec<TypeDec>, Vec<ForwardDec>, Vec<Stmt1>, usize), Error> {
+        if self.forward_decs().is_none() {
+            return Err(Error::UnexpectedEOF);
+        }
+        if let Some((n, left)) = self.fields.left {
+            return Err(Error::SyntaxErrorFieldCount(self.pos, n, n - left));
+        }
+        // with no declarations, the parser never saw the last one come in
+        self.check_body_count()?;
+        if let Some(sizes) = &self.body_sizes {
+            if let Some(ForwardDec::Func(i, _, _)) = self.forward_decs.get(self.body) {
+                return Err(Error::BodySizeMismatch(*i, sizes[self.bodies_done]));
             }
-            ForwardDec::Func(_, Visibility::Import(_, _), _) => {}
+            if self.trailing > 0 {
+                return Err(Error::OpsAfterSizedBodies(self.trailing));
+            }
+        }
+        let mut stmts = vec![];
+        while let Some(ForwardDec::Func(i, _, _)) = self.forward_decs.get(self.body) {
+            event!(Level::Debug, "the program ends in the body of function {}", i);
+            stmts.push(Stmt1::Func(*i, self.pos, std::mem::take(&mut self.current_stmt_opcodes)));
+            self.body += 1;
+            self.next_body();
         }
+        Ok((self.type_decs, self.forward_decs, stmts, self.trailing))
+    }
+}
+
+/// Where each function body is in a program with `Feature::SizedBodies`, as byte ranges in the order of the bodies,
+/// or `None` if its bodies aren't sized. This only reads the prelude, so it's quick however big the program is.
+pub fn body_ranges(bytes: &[u8], limits: &Limits) -> Result<Option<Vec<Range<usize>>>, Error> {
+    let Some(prelude) = prelude(bytes, limits)? else {
+        return Err(Error::UnexpectedEOF);
+    };
+    let Some(sizes) = &prelude.body_sizes else {
+        return Ok(None);
+    };
+    ranges(bytes.len(), prelude.len, sizes).map(Some)
+}
+
+/// The byte ranges of bodies of these sizes, which end the program, as long as they come after the prelude.
+fn ranges(bytes_len: usize, prelude_len: usize, sizes: &[u32]) -> Result<Vec<Range<usize>>, Error> {
+    let total = sizes.iter().map(|size| *size as usize).sum::<usize>();
+    let Some(mut start) = bytes_len.checked_sub(total).filter(|start| *start >= prelude_len) else {
+        return Err(Error::UnexpectedEOF);
+    };
+    let mut ranges = Vec::with_capacity(sizes.len());
+    for size in sizes {
+        ranges.push(start..start + *size as usize);
+        start += *size as usize;
+    }
+    Ok(ranges)
+}
+
+/// The declarations of a program with `Feature::SizedBodies`, and the label and byte range of each body,
+/// without reading any of the bodies, so they can be parsed one at a time later with `body`.
+/// This is `None` if the bodies aren't sized, or if the declarations don't end where the bodies start,
+/// in which case the whole program has to be parsed to say what's wrong with it.
+pub fn declarations(
+    bytes: &[u8],
+    limits: &Limits,
+) -> Result<Option<(Vec<u8>, Vec<TypeDec>, Vec<ForwardDec>, Vec<(Label, Range<usize>)>)>, Error> {
+    if bytes.len() > limits.module_size {
+        return Err(Error::LimitExceeded(Limit::ModuleSize, limits.module_size, bytes.len()));
+    }
+    let header = check_features(bytes)?;
+    if header.checksum.is_some() {
+        check_checksum(&header, checksum::crc32(&bytes[header.len..]))?;
     }
-    if current_stmt_opcodes.len() > 0 {
-        dbg!(current_stmt_opcodes);
+    let Some(prelude) = prelude(bytes, limits)? else {
         return Err(Error::UnexpectedEOF);
+    };
+    let Some(sizes) = &prelude.body_sizes else {
+        return Ok(None);
+    };
+    let ranges = ranges(bytes.len(), prelude.len, sizes)?;
+    let decls_end = ranges.first().map_or(bytes.len(), |range| range.start);
+    let mut parser = Parser::new(prelude.n, prelude.m, None, header.bits, limits);
+    let mut rest = &bytes[prelude.len..decls_end];
+    let mut pos = (prelude.len - header.len) as Pos;
+    while !rest.is_empty() {
+        let Some((op, len)) = lex_op(rest, pos)?.filter(|_| parser.forward_decs().is_none()) else {
+            return Ok(None);
+        };
+        parser.push(op)?;
+        rest = &rest[len..];
+        pos += 1;
+    }
+    if parser.forward_decs().is_none() {
+        return Ok(None);
+    }
+    parser.body_sizes = prelude.body_sizes.clone();
+    parser.check_body_count()?;
+    let with_bodies = parser.forward_decs.iter().filter_map(|dec| match dec {
+        ForwardDec::Func(_, Visibility::Import(_, _), _) => None,
+        ForwardDec::Func(label, _, _) => Some(*label),
+    });
+    let bodies = with_bodies.zip(ranges).collect();
+    metrics::count(Counter::FunctionsParsed, parser.forward_decs.len() as u64);
+    Ok(Some((prelude.data_section, parser.type_decs, parser.forward_decs, bodies)))
+}
+
+/// Parse just the body of function `label`, which takes up `range` of the program (see `body_ranges`),
+/// without reading anything around it. `n` is how many functions the program declares.
+/// The positions in its errors count from the start of the body, since the ops before it aren't read.
+pub fn body(bytes: &[u8], range: Range<usize>, label: Label, n: u32, limits: &Limits) -> Result<Stmt1, Error> {
+    let size = range.len() as u32;
+    let features = check_features(bytes)?.bits;
+    let Some(mut rest) = bytes.get(range) else {
+        return Err(Error::UnexpectedEOF);
+    };
+    let mut body_limits = BodyLimits::new(*limits);
+    let mut fields = Fields::default();
+    let mut ops = vec![];
+    let mut pos = 0;
+    while !rest.is_empty() {
+        let Some((op, len)) = lex_op(rest, pos)? else {
+            return Err(Error::SyntaxErrorParamNeeded(pos, rest[0]));
+        };
+        rest = &rest[len..];
+        check_feature(&op, features, pos)?;
+        fields.check(&op, pos)?;
+        body_limits.check(&op)?;
+        match op {
+            Op1::Call | Op1::CallNZ | Op1::Halt if !rest.is_empty() => return Err(Error::BodySizeMismatch(label, size)),
+            Op1::GlobalFunc(target) if target >= n => {
+                return Err(Error::SyntaxErrorLabelOutOfRange(pos, target, n as usize));
+            }
+            _ => {}
+        }
+        ops.push(op);
+        pos += 1;
+    }
+    match ops.last() {
+        Some(Op1::Call | Op1::CallNZ | Op1::Halt) => Ok(Stmt1::Func(label, 0, ops)),
+        _ => Err(Error::BodySizeMismatch(label, size)),
     }
-    Ok(parsed_stmts)
 }
 
 /// Lex a stream of bytes, maybe return an error, otherwise parse.
-pub fn go(istream: &ByteStream) -> Result<(Vec<u8>, Vec<ForwardDec>, Vec<Stmt1>), Error> {
-    // this is two-pass currently (lex and parse); it would be straightforward to fuse these passes.
-    let (data_section, tokens, n) = lex(istream)?;
-    let (forward_decs, rest, pos) = parse_forward_decs(&tokens, n)?;
-    let stmts = parse(rest, &forward_decs, pos)?;
-    Ok((data_section, forward_decs, stmts))
+pub fn go(istream: &[u8]) -> Result<(Vec<u8>, Vec<TypeDec>, Vec<ForwardDec>, Vec<Stmt1>), Error> {
+    go_with_limits(istream, &Limits::default())
+}
+
+/// Like `go`, but with custom bounds on the input.
+pub fn go_with_limits(
+    istream: &[u8],
+    limits: &Limits,
+) -> Result<(Vec<u8>, Vec<TypeDec>, Vec<ForwardDec>, Vec<Stmt1>), Error> {
+    let (data_section, type_decs, forward_decs, stmts, _trailing) = go_with_trailing(istream, limits)?;
+    Ok((data_section, type_decs, forward_decs, stmts))
+}
+
+/// Like `go_with_limits`, but also saying how many ops come after the last function body.
+/// Those are never run, so they're allowed, but they're worth a warning.
+pub fn go_with_trailing(
+    istream: &[u8],
+    limits: &Limits,
+) -> Result<(Vec<u8>, Vec<TypeDec>, Vec<ForwardDec>, Vec<Stmt1>, usize), Error> {
+    let _span = log::span(Level::Debug, module_path!(), "parse", || format!("{} bytes", istream.len()));
+    let start = Instant::now();
+    metrics::count(Counter::BytesParsed, istream.len() as u64);
+    let res = (|| {
+        // this is two-pass currently (lex and parse); it would be straightforward to fuse these passes.
+        let (data_section, tokens, n, m, body_sizes) = lex(istream, limits)?;
+        event!(Level::Trace, "lexed {} ops and a {}-byte data section", tokens.len(), data_section.len());
+        let mut parser = Parser::new(n, m, body_sizes, check_features(istream)?.bits, limits);
+        let mut stmts = vec![];
+        for op in tokens {
+            stmts.extend(parser.push(op)?);
+        }
+        let (type_decs, forward_decs, cut_short, trailing) = parser.finish()?;
+        stmts.extend(cut_short);
+        event!(Level::Debug, "parsed {} functions, {} with bodies", forward_decs.len(), stmts.len());
+        metrics::count(Counter::FunctionsParsed, forward_decs.len() as u64);
+        Ok((data_section, type_decs, forward_decs, stmts, trailing))
+    })();
+    metrics::time(Phase::Parse, start.elapsed());
+    res
 }
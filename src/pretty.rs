@@ -11,61 +11,63 @@ pub trait Pretty {
 }
 
 impl Pretty for Op1 {
+    fn pretty(&self) -> String {
+        let mnemonic = self.info().mnemonic.to_string();
+        match self.imm() {
+            Imm::None => mnemonic,
+            Imm::U8(n) => mnemonic + " " + &n.to_string(),
+            Imm::U32(n) => mnemonic + " " + &n.to_string(),
+            Imm::I32(n) => mnemonic + " " + &n.to_string(),
+            Imm::Name(a, b) => mnemonic + " " + &int_pair_to_str(&a, &b),
+        }
+    }
+}
+
+impl Pretty for Limit {
+    fn pretty(&self) -> String {
+        match self {
+            Limit::ModuleSize => "the size of a program in bytes".to_string(),
+            Limit::Functions => "the number of functions".to_string(),
+            Limit::Types => "the number of named types".to_string(),
+            Limit::BodyLen => "the number of ops in a function".to_string(),
+            Limit::QuantifierDepth => "the nesting depth of quantifiers".to_string(),
+            Limit::TypeDepth => "the nesting depth of types".to_string(),
+        }
+    }
+}
+
+impl Pretty for Feature {
     fn pretty(&self) -> String {
         match self {
-            Op1::Unique => "unique".to_string(),
-            Op1::Handle => "handle".to_string(),
-            Op1::I32 => "i32".to_string(),
-            Op1::Tuple(n) => "tuple ".to_string() + &n.to_string(),
-            Op1::Some => "some".to_string(),
-            Op1::All => "all".to_string(),
-            Op1::Rgn => "rgn".to_string(),
-            Op1::End => "end".to_string(),
-            Op1::App => "app".to_string(),
-            Op1::Func(n) => "func ".to_string() + &n.to_string(),
-            Op1::CTGet(n) => "ctget ".to_string() + &n.to_string(),
-            Op1::Lced => "lced".to_string(),
-            Op1::Unpack => "unpack".to_string(),
-            Op1::Get(n) => "get ".to_string() + &n.to_string(),
-            Op1::Init(n) => "init ".to_string() + &n.to_string(),
-            Op1::Malloc => "malloc".to_string(),
-            Op1::Proj(n) => "proj ".to_string() + &n.to_string(),
-            Op1::Call => "call".to_string(),
-            // Op1::Print => "print".to_string(),
-            Op1::Lit(i) => "lit ".to_string() + &i.to_string(),
-            Op1::GlobalFunc(n) => "global_func ".to_string() + &n.to_string(),
-            Op1::Halt => "halt".to_string(),
-            Op1::Pack => "pack".to_string(),
-            Op1::Size(n) => "size ".to_string() + &n.to_string(),
-            Op1::NewRgn(n) => "new_rgn ".to_string() + &n.to_string(),
-            Op1::FreeRgn => "free_rgn".to_string(),
-            Op1::Ptr => "ptr".to_string(),
-            Op1::Deref => "deref".to_string(),
-            Op1::Arr => "arr".to_string(),
-            Op1::ArrMut => "arr_mut".to_string(),
-            Op1::ArrProj => "arr_proj".to_string(),
-            Op1::Add => "add".to_string(),
-            Op1::Mul => "mul".to_string(),
-            Op1::Div => "div".to_string(),
-            Op1::CallNZ => "call_nz".to_string(),
-            Op1::Data(n) => "data ".to_string() + &n.to_string(),
-            Op1::DataSec => "data_sec".to_string(),
-            Op1::U8 => "u8".to_string(),
-            Op1::CopyN => "copy_n".to_string(),
-            Op1::U8Lit(n) => "u8_lit ".to_string() + &n.to_string(),
-            Op1::U8ToI32 => "u8_to_i32".to_string(),
-            Op1::Import(a, b) => "import ".to_string() + &int_pair_to_str(a, b),
-            Op1::Export(a, b) => "export ".to_string() + &int_pair_to_str(a, b),
-            Op1::Modulo => "modulo".to_string(),
-            Op1::I32ToU8 => "i32_to_u8".to_string(),
-            Op1::Read(c) => "read ".to_string() + &c.to_string(),
-            Op1::Write(c) => "write ".to_string() + &c.to_string(),
+            Feature::Floats => "floats".to_string(),
+            Feature::Threads => "threads".to_string(),
+            Feature::Exceptions => "exceptions".to_string(),
+            Feature::LintConfig => "a lint config".to_string(),
+            Feature::Checksum => "a checksum".to_string(),
+            Feature::TypeDecls => "type declarations".to_string(),
+            Feature::Metadata => "metadata".to_string(),
+            Feature::SizedBodies => "sized function bodies".to_string(),
+            Feature::ExportNames => "export names".to_string(),
+            Feature::BigInts => "big integers".to_string(),
+            Feature::Maps => "maps".to_string(),
+        }
+    }
+}
+
+impl Pretty for ImmKind {
+    fn pretty(&self) -> String {
+        match self {
+            ImmKind::None => "none".to_string(),
+            ImmKind::U8 => "u8".to_string(),
+            ImmKind::U32 => "u32".to_string(),
+            ImmKind::I32 => "i32".to_string(),
+            ImmKind::Name => "16-byte name".to_string(),
         }
     }
 }
 
 fn int_pair_to_str(a: &u64, b: &u64) -> String {
-    return std::str::from_utf8(&a.to_le_bytes()).unwrap().to_owned() + &std::str::from_utf8(&b.to_le_bytes()).unwrap();
+    String::from_utf8_lossy(&[a.to_le_bytes(), b.to_le_bytes()].concat()).into_owned()
 }
 
 impl Pretty for Op2 {
@@ -89,23 +91,55 @@ impl Pretty for Op2 {
             Op2::NewArr(s) => "new_arr ".to_string() + &s.to_string(),
             Op2::ArrMut(s) => "arr_mut ".to_string() + &s.to_string(),
             Op2::ArrProj(s) => "arr_proj ".to_string() + &s.to_string(),
-            Op2::AddI32 => "add_i32".to_string(),
-            Op2::MulI32 => "mul_i32".to_string(),
-            Op2::DivI32 => "div_i32".to_string(),
+            Op2::AddI32(overflow) => "add_i32".to_string() + overflow_suffix(overflow),
+            Op2::MulI32(overflow) => "mul_i32".to_string() + overflow_suffix(overflow),
+            Op2::DivI32(overflow) => "div_i32".to_string() + overflow_suffix(overflow),
             Op2::CallNZ => "call_nz".to_string(),
             Op2::Data(s) => "data ".to_string() + &s.to_string(),
             Op2::DataIndex(s) => "data_index ".to_string() + &s.to_string(),
             Op2::CopyN(s) => "copy_n ".to_string() + &s.to_string(),
             Op2::U8Lit(n) => "u8_lit ".to_string() + &n.to_string(),
-            Op2::AddU8 => "add_u8".to_string(),
-            Op2::MulU8 => "mul_u8".to_string(),
+            Op2::AddU8(overflow) => "add_u8".to_string() + overflow_suffix(overflow),
+            Op2::MulU8(overflow) => "mul_u8".to_string() + overflow_suffix(overflow),
             Op2::DivU8 => "div_u8".to_string(),
             Op2::U8ToI32 => "u8_to_i32".to_string(),
-            Op2::ModuloI32 => "modulo_i32".to_string(),
+            Op2::ModuloI32(overflow) => "modulo_i32".to_string() + overflow_suffix(overflow),
             Op2::ModuloU8 => "modulo_u8".to_string(),
             Op2::I32ToU8 => "i32_to_u8".to_string(),
             Op2::Read(c) => "read ".to_string() + &c.to_string(),
             Op2::Write(c) => "write ".to_string() + &c.to_string(),
+            Op2::Yield => "yield".to_string(),
+            Op2::HostCall(n) => "host_call ".to_string() + &n.to_string(),
+            Op2::Nop => "nop".to_string(),
+            Op2::Marker(n) => "marker ".to_string() + &n.to_string(),
+            Op2::SubI32(overflow) => "sub_i32".to_string() + overflow_suffix(overflow),
+            Op2::SubU8(overflow) => "sub_u8".to_string() + overflow_suffix(overflow),
+            Op2::SatI32(arith) => arith_name(arith).to_string() + "_sat_i32",
+            Op2::SatU8(arith) => arith_name(arith).to_string() + "_sat_u8",
+            Op2::CheckedI32(arith) => arith_name(arith).to_string() + "_checked_i32",
+            Op2::CheckedU8(arith) => arith_name(arith).to_string() + "_checked_u8",
+            Op2::BigData(offset) => "big_data ".to_string() + &offset.to_string(),
+            Op2::BigFromI32 => "big_from_i32".to_string(),
+            Op2::BigAdd => "big_add".to_string(),
+            Op2::BigSub => "big_sub".to_string(),
+            Op2::BigMul => "big_mul".to_string(),
+            Op2::BigDiv => "big_div".to_string(),
+            Op2::BigModulo => "big_modulo".to_string(),
+            Op2::BigCmp => "big_cmp".to_string(),
+            Op2::BigToI32 => "big_to_i32".to_string(),
+            Op2::BufNew => "buf_new".to_string(),
+            Op2::BufLen => "buf_len".to_string(),
+            Op2::BufGetU8 => "buf_get_u8".to_string(),
+            Op2::BufSetU8 => "buf_set_u8".to_string(),
+            Op2::BufGetU32 => "buf_get_u32".to_string(),
+            Op2::BufSetU32 => "buf_set_u32".to_string(),
+            Op2::NewMap(s) => "new_map ".to_string() + &s.to_string(),
+            Op2::MapGet(s) => "map_get ".to_string() + &s.to_string(),
+            Op2::MapSet(s) => "map_set ".to_string() + &s.to_string(),
+            Op2::MapHas(s) => "map_has ".to_string() + &s.to_string(),
+            Op2::MapRemove(s) => "map_remove ".to_string() + &s.to_string(),
+            Op2::MapLen => "map_len".to_string(),
+            Op2::HostRes(f, kind) => "host_res ".to_string() + &f.to_string() + " " + &kind.to_string(),
         }
     }
 }
@@ -121,7 +155,17 @@ impl Pretty for Stmt1 {
 impl Pretty for Stmt2 {
     fn pretty(&self) -> String {
         match self {
-            Stmt2::Func(pos, t, ops) => "fn foo".to_string() + &pos.to_string() + ": " + &t.pretty() + " = " + &ops.iter().map(|op|op.pretty()).collect::<Vec<String>>().join("; "),
+            Stmt2::Func(pos, t, ops, _) => "fn foo".to_string() + &pos.to_string() + ": " + &t.pretty() + " = " + &ops.iter().map(|op|op.pretty()).collect::<Vec<String>>().join("; "),
+        }
+    }
+}
+
+impl Pretty for Visibility {
+    fn pretty(&self) -> String {
+        match self {
+            Visibility::Local => "local".to_string(),
+            Visibility::Export(a, b) => "export ".to_string() + &int_pair_to_str(a, b),
+            Visibility::Import(a, b) => "import ".to_string() + &int_pair_to_str(a, b),
         }
     }
 }
@@ -137,9 +181,15 @@ impl Pretty for RgnId {
 
 impl Pretty for Region {
     fn pretty(&self) -> String {
-        match self {
-            Region { id, .. } => id.pretty()
-        }
+        self.id.pretty()
+    }
+}
+
+impl Pretty for Field {
+    fn pretty(&self) -> String {
+        let mutable = if self.mutable { "mut " } else { "" };
+        let init = if self.init { "" } else { "?" };
+        mutable.to_string() + init + &self.t.pretty()
     }
 }
 
@@ -149,24 +199,44 @@ impl Pretty for Type {
             Type::I32 => "i32".to_string(),
             Type::U8 => "u8".to_string(),
             Type::Handle(r) => "handle(".to_string() + &r.pretty() + ")",
-            Type::Tuple(ts) => "(".to_string() + &ts.iter().map(|(_, t)| t.pretty()).collect::<Vec<String>>().join(", ") + ")",
+            // components that haven't been initialized yet are marked with a `?`, and mutable ones with `mut`
+            Type::Tuple(ts) => "(".to_string() + &ts.iter().map(Field::pretty).collect::<Vec<String>>().join(", ") + ")",
             Type::Ptr(t, r) => t.pretty() + "@" + &r.pretty(),
             Type::Var(id, _) => "a".to_string() + &id.1.to_string(),
             Type::Func(ts) => "(".to_string() + &ts.iter().map(|t| t.pretty()).collect::<Vec<String>>().join(", ") + ")->0",
             Type::Forall(id, size, t) => "forall a".to_string() + &id.1.to_string() + ": " + &size.to_string() + "byte. " + &t.pretty(),
             Type::ForallRegion(r, t, _) => "forall ".to_string() + &r.pretty() + ": Rgn" + own_suffix(r) + ". " + &t.pretty(),
             Type::Exists(id, size, t) => "exists a".to_string() + &id.1.to_string() + ": " + &size.to_string() + "byte. " + &t.pretty(),
+            Type::ExistsRegion(r, t) => "exists ".to_string() + &r.pretty() + ": Rgn" + own_suffix(r) + ". " + &t.pretty(),
             Type::Array(t, r) => t.pretty() + "[]@" + &r.pretty(),
+            Type::Named(k, _) => "T".to_string() + &k.to_string(),
+            Type::BigInt(r) => "bigint@".to_string() + &r.pretty(),
+            Type::Buffer(r) => "buf@".to_string() + &r.pretty(),
+            Type::Map(t, r) => "map(".to_string() + &t.pretty() + ")@" + &r.pretty(),
+            Type::Resource(kind, r) => "res ".to_string() + &kind.to_string() + "@" + &r.pretty(),
         }
     }
 }
 
-fn own_suffix(r: &Region) -> &str {
-    match r {
-        Region { unique, .. } => if *unique { "!" } else { "" },
+fn overflow_suffix(overflow: &Overflow) -> &str {
+    match overflow {
+        Overflow::Wrap => "",
+        Overflow::Trap => "_trap",
     }
 }
 
+fn arith_name(arith: &ArithOp) -> &str {
+    match arith {
+        ArithOp::Add => "add",
+        ArithOp::Sub => "sub",
+        ArithOp::Mul => "mul",
+    }
+}
+
+fn own_suffix(r: &Region) -> &str {
+    if r.unique { "!" } else { "" }
+}
+
 impl Pretty for Kind {
     fn pretty(&self) -> String {
         match self {
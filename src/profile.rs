@@ -0,0 +1,108 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Per-opcode counts and timings from the VM's loop, with the `profile` feature, so work on dispatch can go where the time goes.
+//!
+//! Every instruction an instance runs is counted. Timing every one would take longer than most instructions do,
+//! so the loop reads the CPU's cycle counter (the TSC on x86) around roughly one instruction in every `PROFILE_SAMPLE_PERIOD`
+//! (see vm.h), and each opcode's total time is estimated from its samples: the average sample times the count.
+//! Instructions that leave the loop, like `halt` and `yield`, are counted but never timed.
+//! The opcodes are the bytes of linked code (see `Instr::opcode`), so `add` and `add_trap` show up separately.
+
+use crate::instr::Instr;
+
+use std::time::Duration;
+
+/// How much a profile says one opcode ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpProfile {
+    pub opcode: u8,
+    /// How many times it ran.
+    pub count: u64,
+    /// How many of those were timed.
+    pub samples: u64,
+    /// About how long it took altogether, or zero if it was never timed.
+    pub time: Duration,
+}
+
+impl OpProfile {
+    pub fn name(&self) -> &'static str {
+        Instr::opcode_name(self.opcode)
+    }
+
+    /// About how long it took each time, or `None` if it was never timed.
+    pub fn average(&self) -> Option<Duration> {
+        (self.samples > 0).then(|| self.time.div_f64(self.count as f64))
+    }
+}
+
+/// Every opcode an instance ran since it was made or its profile was reset, from `Instance::profile`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// The opcodes that ran, the ones that took the most time first.
+    pub ops: Vec<OpProfile>,
+}
+
+impl Profile {
+    /// A profile from the raw numbers the VM keeps, where a tick of its clock is `tick` long.
+    pub(crate) fn new(counts: &[u64], ticks: &[u64], samples: &[u64], tick: f64) -> Profile {
+        let mut ops = (0..=255u8)
+            .filter(|&op| counts[op as usize] > 0)
+            .map(|op| {
+                let (count, samples) = (counts[op as usize], samples[op as usize]);
+                let seconds = match samples {
+                    0 => 0.0,
+                    _ => ticks[op as usize] as f64 / samples as f64 * count as f64 * tick,
+                };
+                OpProfile {
+                    opcode: op,
+                    count,
+                    samples,
+                    time: Duration::from_secs_f64(seconds),
+                }
+            })
+            .collect::<Vec<_>>();
+        ops.sort_by(|a, b| b.time.cmp(&a.time).then(b.count.cmp(&a.count)));
+        Profile { ops }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.ops.iter().map(|op| op.count).sum()
+    }
+
+    pub fn time(&self) -> Duration {
+        self.ops.iter().map(|op| op.time).sum()
+    }
+
+    /// The profile as a table, one opcode per row, with its share of the instructions run and of the time.
+    pub fn table(&self) -> String {
+        let (count, time) = (self.count().max(1) as f64, self.time().as_secs_f64().max(f64::MIN_POSITIVE));
+        let mut out = format!(
+            "{:<16} {:>12} {:>7} {:>12} {:>7} {:>9}\n",
+            "opcode", "count", "count%", "time", "time%", "per op"
+        );
+        for op in &self.ops {
+            let (op_time, time_share, per_op) = match op.average() {
+                Some(average) => (
+                    format!("{:.3?}", op.time),
+                    format!("{:.1}", op.time.as_secs_f64() / time * 100.0),
+                    format!("{}ns", average.as_nanos()),
+                ),
+                None => ("-".to_string(), "-".to_string(), "-".to_string()),
+            };
+            out += &format!(
+                "{:<16} {:>12} {:>7.1} {:>12} {:>7} {:>9}\n",
+                op.name(),
+                op.count,
+                op.count as f64 / count * 100.0,
+                op_time,
+                time_share,
+                per_op
+            );
+        }
+        out + &format!("{:<16} {:>12} {:>7} {:>12}\n", "total", self.count(), "", format!("{:.3?}", self.time()))
+    }
+}
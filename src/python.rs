@@ -0,0 +1,203 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The `sabervm` Python module, for scripting experiments against the VM without writing Rust.
+//! Build it with `maturin develop --features python,pyo3/extension-module`, then:
+//!
+//! ```python
+//! import sabervm
+//! module = sabervm.Module([open("prog.svm", "rb").read()])
+//! instance = module.instance()
+//! instance.register_host_fn(0, lambda x: x * 2)
+//! print(instance.run())
+//! ```
+//!
+//! Programs that don't parse or verify, and runs that trap, raise `sabervm.Error` with SaberVM's message for it.
+//! The GIL is released while programs are verified and run, and taken again for each call to a Python host function,
+//! which can't use the instance it's called from.
+
+use crate::error_msgs;
+use crate::header::{ByteStream, Outcome, Trap};
+use crate::vm::{Instance, Module};
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+create_exception!(
+    sabervm,
+    Error,
+    PyException,
+    "A program that didn't parse or verify, or a run that trapped, with SaberVM's message for it."
+);
+
+/// Parse and verify each program without linking them, raising `Error` if one is invalid.
+/// Unlike `Module`, imports don't have to be satisfied.
+#[pyfunction]
+fn verify(py: Python<'_>, programs: Vec<ByteStream>) -> PyResult<()> {
+    py.detach(|| {
+        for prog in programs {
+            crate::parse::go(&prog).and_then(|(data_section, type_decs, types_instrs, stmts)| {
+                crate::verify::go(data_section, type_decs, types_instrs, stmts)
+            })?;
+        }
+        Ok(())
+    })
+    .map_err(|e| Error::new_err(error_msgs::msg(e)))
+}
+
+/// Every task finished, with the status given to `halt`.
+#[pyclass(eq, frozen, module = "sabervm")]
+#[derive(PartialEq)]
+struct Halted {
+    #[pyo3(get)]
+    status: u8,
+}
+
+#[pymethods]
+impl Halted {
+    #[new]
+    fn new(status: u8) -> Halted {
+        Halted { status }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Halted({})", self.status)
+    }
+}
+
+/// The program ran `yield`; continue it with `Instance.resume`.
+#[pyclass(eq, frozen, module = "sabervm")]
+#[derive(PartialEq)]
+struct Yielded {
+    #[pyo3(get)]
+    value: i32,
+}
+
+#[pymethods]
+impl Yielded {
+    #[new]
+    fn new(value: i32) -> Yielded {
+        Yielded { value }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Yielded({})", self.value)
+    }
+}
+
+/// Programs (each a `bytes`) parsed, verified, and linked together. One module can back any number of instances.
+#[pyclass(name = "Module", frozen, module = "sabervm")]
+struct SvmModule(Arc<Module>);
+
+#[pymethods]
+impl SvmModule {
+    #[new]
+    fn new(py: Python<'_>, programs: Vec<ByteStream>) -> PyResult<SvmModule> {
+        match py.detach(|| Module::new(programs)) {
+            Ok(module) => Ok(SvmModule(Arc::new(module))),
+            Err(e) => Err(Error::new_err(error_msgs::msg(e))),
+        }
+    }
+
+    fn instance(&self) -> SvmInstance {
+        SvmInstance::new(self)
+    }
+
+    /// How many `i32`s `Instance.run` has to be given.
+    #[getter]
+    fn entry_params(&self) -> usize {
+        self.0.entry_params()
+    }
+}
+
+/// One run of a `Module`.
+#[pyclass(name = "Instance", frozen, module = "sabervm")]
+struct SvmInstance {
+    instance: Mutex<Instance>,
+    /// The exception a host function raised, which ends the run and is raised from `run` or `resume` in place of its trap.
+    raised: Arc<Mutex<Option<PyErr>>>,
+}
+
+#[pymethods]
+impl SvmInstance {
+    #[new]
+    fn new(module: &SvmModule) -> SvmInstance {
+        SvmInstance {
+            instance: Mutex::new(Instance::new(module.0.clone())),
+            raised: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Provide the function, from int to int, that `host_call index` runs.
+    /// If it raises, or doesn't return an int that fits in an `i32`, the run stops and that's raised instead.
+    fn register_host_fn(&self, index: u32, f: Py<PyAny>) -> PyResult<()> {
+        let raised = self.raised.clone();
+        self.lock()?.register_fallible_host_fn(index, move |arg| {
+            Python::attach(|py| match f.call1(py, (arg,)).and_then(|val| val.extract::<i32>(py)) {
+                Ok(val) => Some(val),
+                Err(e) => {
+                    *raised.lock().unwrap() = Some(e);
+                    None
+                }
+            })
+        });
+        Ok(())
+    }
+
+    /// Run from the entry point with the given ints as its arguments, returning `Halted` or `Yielded`, or raising `Error` if it traps.
+    /// This starts over even if the last run stopped at a `yield`.
+    #[pyo3(signature = (*args))]
+    fn run(&self, py: Python<'_>, args: Vec<i32>) -> PyResult<Py<PyAny>> {
+        let instance = &mut *self.lock()?;
+        let res = py.detach(|| instance.run_with_args(&args));
+        self.outcome(py, res)
+    }
+
+    /// Continue a run that stopped at a `yield`, with `val` as the result of the `yield`.
+    fn resume(&self, py: Python<'_>, val: i32) -> PyResult<Py<PyAny>> {
+        let instance = &mut *self.lock()?;
+        let res = py.detach(|| instance.resume(val));
+        self.outcome(py, res)
+    }
+
+    /// Whether the last run stopped at a `yield`, so it can be resumed.
+    #[getter]
+    fn is_suspended(&self) -> PyResult<bool> {
+        Ok(self.lock()?.is_suspended())
+    }
+}
+
+impl SvmInstance {
+    /// The instance, unless it's running, as it is when a host function tries to use it.
+    fn lock(&self) -> PyResult<MutexGuard<'_, Instance>> {
+        self.instance.try_lock().map_err(|_| Error::new_err("the instance is already running"))
+    }
+
+    fn outcome(&self, py: Python<'_>, res: Result<Outcome, Trap>) -> PyResult<Py<PyAny>> {
+        if let Some(e) = self.raised.lock().unwrap().take() {
+            return Err(e);
+        }
+        match res {
+            Ok(Outcome::Halted(status)) => Ok(Py::new(py, Halted { status })?.into_any()),
+            Ok(Outcome::Yielded(value)) => Ok(Py::new(py, Yielded { value })?.into_any()),
+            Err(trap) => Err(Error::new_err(error_msgs::trap_msg(trap))),
+        }
+    }
+}
+
+#[pymodule]
+fn sabervm(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("Error", m.py().get_type::<Error>())?;
+    m.add_function(wrap_pyfunction!(verify, m)?)?;
+    m.add_class::<Halted>()?;
+    m.add_class::<Yielded>()?;
+    m.add_class::<SvmModule>()?;
+    m.add_class::<SvmInstance>()?;
+    Ok(())
+}
@@ -0,0 +1,119 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Pausing every instance at once, for an embedder that needs them all to stand still for a moment:
+//! to run its own garbage collector over the values it has handed out, to take a snapshot, or to swap in new code.
+//!
+//! Instances join a `Safepoints` with `Instance::set_safepoints`, and `Safepoints::pause` waits until none of them
+//! is running any bytecode, then keeps it that way until the `Paused` it returns is dropped.
+//! The dispatch loop only looks at the pause flag at its safepoints (calls, and between tasks), the same places it checks
+//! for an interrupt, so this costs one more relaxed load per call.
+//!
+//! While paused, each instance that joined is in one of these states:
+//! - not running at all, or stopped at a `yield`;
+//! - stopped at a safepoint: at a `call` or `call_nz` that hasn't jumped yet, with the callee and its arguments
+//!   still on the stack, or between two tasks of the scheduler;
+//! - inside a host function, or verifying a function body lazily (see `Verification::Lazy`);
+//! - about to start a run, resume, or call back into the program, and waiting for the pause to end first.
+//!
+//! So nothing an instance owns changes while paused: not its stack, its regions, what's in them, or its scheduler's tasks,
+//! and every pointer it holds stays valid. No region is made or freed.
+//! Host functions can still be running, since they're the embedder's own code, but they can't get back into the program:
+//! `Guest::call` waits for the pause to end. The one thing in a module that can still change is the code of a function
+//! that's being verified lazily, which is filled in once it verifies.
+//!
+//! A pause doesn't wait for a host function to return, so a host function can pause everything itself.
+//! But a thread that already holds a `Paused` mustn't run, resume, or call back into an instance that joined, or pause again,
+//! since any of those would wait for the pause that thread is holding.
+//! An interrupt that comes in while an instance is paused is delivered at its next safepoint after the pause ends.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A group of instances that can be paused together. Clones share the same group.
+#[derive(Clone, Default)]
+pub struct Safepoints {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    // what the dispatch loop reads, kept in step with `state.paused`
+    flag: AtomicBool,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    paused: bool,
+    // how many instances in the group are running bytecode right now
+    running: usize,
+}
+
+impl Safepoints {
+    pub fn new() -> Safepoints {
+        Safepoints::default()
+    }
+
+    /// Wait until no instance in the group is running bytecode, and keep them from running any until the result is dropped.
+    /// If another thread has them paused already, this waits for that pause to end first.
+    pub fn pause(&self) -> Paused<'_> {
+        let mut state = self.shared.state.lock().unwrap();
+        while state.paused {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        state.paused = true;
+        self.shared.flag.store(true, Ordering::Relaxed);
+        while state.running > 0 {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        Paused { safepoints: self }
+    }
+
+    /// Whether the group is paused, or about to be.
+    pub fn is_paused(&self) -> bool {
+        self.shared.state.lock().unwrap().paused
+    }
+
+    /// The flag an instance in the group polls at its safepoints.
+    pub(crate) fn flag(&self) -> *const AtomicBool {
+        &self.shared.flag
+    }
+
+    /// Start running bytecode on one instance in the group, waiting out a pause first.
+    pub(crate) fn enter(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        while state.paused {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        state.running += 1;
+    }
+
+    /// Stop running bytecode on one instance in the group, at a safepoint or because its run stopped.
+    pub(crate) fn leave(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.running -= 1;
+        if state.running == 0 {
+            self.shared.changed.notify_all();
+        }
+    }
+}
+
+/// Every instance in a group, held still at a safepoint. Dropping this lets them go on.
+pub struct Paused<'a> {
+    safepoints: &'a Safepoints,
+}
+
+impl Drop for Paused<'_> {
+    fn drop(&mut self) {
+        let shared = &self.safepoints.shared;
+        let mut state = shared.state.lock().unwrap();
+        state.paused = false;
+        shared.flag.store(false, Ordering::Relaxed);
+        shared.changed.notify_all();
+    }
+}
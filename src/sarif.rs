@@ -0,0 +1,227 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! SARIF, the JSON format code-scanning UIs read, for `sabervm check --format sarif`.
+//!
+//! Each error and warning is a result whose rule is its error code, like `E0401`, named after its `Error` variant,
+//! or the name of its lint, like `unreachable-function`, so the same problem has the same rule from one build to the next.
+//! Results point at the `.svm` file and the function the problem is in.
+//! A compiler that tags its code with `marker n` can also write a source map next to the program, `prog.svm.map`,
+//! with lines of `marker file line [column]` (and `#` comments), and then results point at the source line
+//! of the last marker before the op that failed, which is what code-scanning UIs show.
+
+use crate::error_codes;
+use crate::error_msgs;
+use crate::header::*;
+use crate::lint::LintLevel;
+use crate::parse;
+use crate::verify;
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+/// A position in the source a compiler made a program from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+/// Where in its source each of a program's markers came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    markers: HashMap<u32, SourceLocation>,
+}
+
+impl SourceMap {
+    /// Read a source map, one `marker file line [column]` line at a time.
+    pub fn parse(text: &str) -> Result<SourceMap, Error> {
+        let mut markers = HashMap::new();
+        for line in text.lines() {
+            let code = line.split('#').next().unwrap().trim();
+            if code.is_empty() {
+                continue;
+            }
+            let bad = || Error::BadSourceMap(line.to_string());
+            let (marker, loc) = match code.split_whitespace().collect::<Vec<_>>()[..] {
+                [marker, file, line] => (marker, (file, line, None)),
+                [marker, file, line, column] => (marker, (file, line, Some(column))),
+                _ => return Err(bad()),
+            };
+            let (file, line, column) = loc;
+            let loc = SourceLocation {
+                file: file.to_string(),
+                line: line.parse().map_err(|_| bad())?,
+                column: column.map(str::parse).transpose().map_err(|_| bad())?,
+            };
+            markers.insert(marker.parse().map_err(|_| bad())?, loc);
+        }
+        Ok(SourceMap { markers })
+    }
+
+    pub fn get(&self, marker: u32) -> Option<&SourceLocation> {
+        self.markers.get(&marker)
+    }
+}
+
+/// One SARIF result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub rule: String,
+    /// The rule's name, if its id is a code rather than a name.
+    pub rule_name: Option<String>,
+    /// `Deny` for errors and denied warnings, and `Warn` for the rest.
+    pub level: LintLevel,
+    pub message: String,
+    /// The program the problem is in, as it was given on the command line.
+    pub file: String,
+    pub function: Option<Label>,
+    /// Where the problem is in the compiler's source, if the program has a source map that says.
+    pub source: Option<SourceLocation>,
+}
+
+/// The name of an `Error`'s variant, without its fields.
+pub fn variant_name(e: &impl Debug) -> String {
+    let debug = format!("{:?}", e);
+    debug.split('(').next().unwrap().to_string()
+}
+
+/// The result for program `bytes` from `file` failing to parse or verify with `e`.
+pub fn error(file: &str, bytes: &[u8], e: Error, source_map: Option<&SourceMap>) -> Diagnostic {
+    let mut function = None;
+    let mut source = None;
+    if let Ok((data_section, type_decs, forward_decs, stmts)) = parse::go(bytes) {
+        if let Some(site) = verify::error_site(&data_section, &type_decs, &forward_decs, &stmts) {
+            function = Some(site.label);
+            let body = stmts.iter().find(|Stmt1::Func(label, _, _)| *label == site.label);
+            if let (Some(Stmt1::Func(_, _, ops)), false, Some(op)) = (body, site.forward_dec, site.op) {
+                source = last_marker(&ops[..=op]).and_then(|n| source_map?.get(n).cloned());
+            }
+        }
+    }
+    Diagnostic {
+        rule: error_codes::code(&e).to_string(),
+        rule_name: Some(variant_name(&e)),
+        level: LintLevel::Deny,
+        message: error_msgs::bare_msg(e),
+        file: file.to_string(),
+        function,
+        source,
+    }
+}
+
+/// The result for a warning about program `bytes` from `file`, at the level the lints gave it.
+/// A warning about a function points at the function's first marker, if it has one.
+pub fn warning(file: &str, bytes: &[u8], w: Warning, level: LintLevel, source_map: Option<&SourceMap>) -> Diagnostic {
+    let function = match w {
+        Warning::TrailingOps(_) => None,
+        Warning::UnusedRegionBinding(label) | Warning::UnusedTypeBinding(label) | Warning::UnreachableFunction(label) => {
+            Some(label)
+        }
+    };
+    let source = function.and_then(|label| {
+        let (_, _, _, stmts) = parse::go(bytes).ok()?;
+        let Stmt1::Func(_, _, ops) = stmts.into_iter().find(|Stmt1::Func(l, _, _)| *l == label)?;
+        let first = ops.iter().find_map(|op| match op {
+            Op1::Marker(n) => Some(*n),
+            _ => None,
+        });
+        source_map?.get(first?).cloned()
+    });
+    Diagnostic {
+        rule: w.lint().name().to_string(),
+        rule_name: None,
+        level,
+        message: error_msgs::warning_msg(w),
+        file: file.to_string(),
+        function,
+        source,
+    }
+}
+
+/// The number of the last `marker` in `ops`, if there is one.
+fn last_marker(ops: &[Op1]) -> Option<u32> {
+    ops.iter().rev().find_map(|op| match op {
+        Op1::Marker(n) => Some(*n),
+        _ => None,
+    })
+}
+
+/// A SARIF 2.1.0 log with one run of `sabervm check` and `diagnostics` as its results.
+pub fn log(diagnostics: &[Diagnostic]) -> String {
+    let mut rules = diagnostics.iter().map(|d| (d.rule.as_str(), d.rule_name.as_deref())).collect::<Vec<_>>();
+    rules.sort();
+    rules.dedup();
+    let rules = rules
+        .iter()
+        .map(|(id, name)| match name {
+            Some(name) => format!("{{\"id\":{},\"name\":{}}}", json_str(id), json_str(name)),
+            None => format!("{{\"id\":{}}}", json_str(id)),
+        })
+        .collect::<Vec<_>>();
+    let results = diagnostics.iter().map(result).collect::<Vec<_>>();
+    format!(
+        "{{\"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\"version\":\"2.1.0\",\"runs\":[{{\
+        \"tool\":{{\"driver\":{{\"name\":\"sabervm\",\"version\":{},\"rules\":[{}]}}}},\"results\":[{}]}}]}}",
+        json_str(env!("CARGO_PKG_VERSION")),
+        rules.join(","),
+        results.join(",")
+    )
+}
+
+fn result(d: &Diagnostic) -> String {
+    let level = match d.level {
+        LintLevel::Deny => "error",
+        LintLevel::Warn => "warning",
+        LintLevel::Allow => "none",
+    };
+    let program = format!("{{\"uri\":{}}}", json_str(&d.file));
+    // a result with a source line points there, and keeps the program as a related location
+    let (location, related) = match &d.source {
+        Some(loc) => {
+            let column = loc.column.map_or(String::new(), |column| format!(",\"startColumn\":{}", column));
+            let region = format!(",\"region\":{{\"startLine\":{}{}}}", loc.line, column);
+            let related = format!(",\"relatedLocations\":[{}]", location(&program, "", d.function));
+            (location(&format!("{{\"uri\":{}}}", json_str(&loc.file)), &region, d.function), related)
+        }
+        None => (location(&program, "", d.function), String::new()),
+    };
+    format!(
+        "{{\"ruleId\":{},\"level\":\"{}\",\"message\":{{\"text\":{}}},\"locations\":[{}]{}}}",
+        json_str(&d.rule),
+        level,
+        json_str(&d.message),
+        location,
+        related
+    )
+}
+
+/// A SARIF location in `artifact`, with `region` spliced in after it, inside `function` if it's in one.
+fn location(artifact: &str, region: &str, function: Option<Label>) -> String {
+    let logical = match function {
+        Some(label) => format!(",\"logicalLocations\":[{{\"name\":\"function {}\",\"kind\":\"function\"}}]", label),
+        None => String::new(),
+    };
+    format!("{{\"physicalLocation\":{{\"artifactLocation\":{}{}}}{}}}", artifact, region, logical)
+}
+
+/// `s` as a JSON string.
+fn json_str(s: &str) -> String {
+    let mut out = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out + "\""
+}
@@ -0,0 +1,236 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A persistent stack, which the verifier keeps its stacks in.
+//!
+//! The verifier's trace (see `verify::explain`) has the state of the stacks before and after every op.
+//! With `Vec`s, each of those was a copy of the whole stack, so tracing a function that kept n values on the stack
+//! took time and memory in the square of n. A `Stack` is a linked list of shared nodes instead,
+//! so a copy of one is just another pointer to its top, and pushing onto a copy doesn't touch the original.
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::rc::Rc;
+
+struct Node<T> {
+    val: T,
+    next: Option<Rc<Node<T>>>,
+}
+
+impl<T: Clone> Clone for Node<T> {
+    fn clone(&self) -> Self {
+        Node {
+            val: self.val.clone(),
+            next: self.next.clone(),
+        }
+    }
+}
+
+/// A stack that can be copied in constant time, with the usual operations on its top.
+pub struct Stack<T> {
+    top: Option<Rc<Node<T>>>,
+    len: usize,
+    /// The fewest values the stack has had since `mark`.
+    low: usize,
+}
+
+impl<T> Stack<T> {
+    pub fn new() -> Self {
+        Stack { top: None, len: 0, low: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, val: T) {
+        let next = self.top.take();
+        self.top = Some(Rc::new(Node { val, next }));
+        self.len += 1;
+    }
+
+    /// Take values off the top until there are `len` left, without copying them like `pop` would.
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            let Some(node) = self.top.take() else { break };
+            self.top = node.next.clone();
+            self.len -= 1;
+        }
+        self.low = self.low.min(self.len);
+    }
+
+    /// Start keeping track of which values are new, for `new_since_mark`.
+    pub fn mark(&mut self) {
+        self.low = self.len;
+    }
+
+    /// The values pushed since `mark` that are still on the stack, or that were changed with `last_mut`, from the top down.
+    pub fn new_since_mark(&self) -> impl Iterator<Item = &T> {
+        self.iter().take(self.len - self.low)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.top.as_ref().map(|node| &node.val)
+    }
+
+    /// The values from the top down.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            node: self.top.as_deref(),
+        }
+    }
+
+    /// The value `i` from the top, so 0 is the top, like the operand of `get`.
+    pub fn nth_from_top(&self, i: usize) -> Option<&T> {
+        self.iter().nth(i)
+    }
+
+    /// The values from the bottom up, like a `Vec` of them.
+    pub fn to_vec(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        let mut vals = self.iter().cloned().collect::<Vec<_>>();
+        vals.reverse();
+        vals
+    }
+
+    /// How many values at the bottom this and `other` share because one was made from the other,
+    /// then the values above those in each of them, from the bottom up.
+    /// For a stack before and after an op, that's the values the op left alone, the ones it popped, and the ones it pushed,
+    /// found in time for the number popped and pushed rather than for the size of the stack.
+    pub fn diverge<'a>(&'a self, other: &'a Stack<T>) -> (usize, Vec<&'a T>, Vec<&'a T>) {
+        let (mut a, mut b) = (self.top.as_ref(), other.top.as_ref());
+        let (mut a_len, mut b_len) = (self.len, other.len);
+        let (mut a_vals, mut b_vals) = (vec![], vec![]);
+        loop {
+            match (a, b) {
+                (Some(x), _) if a_len > b_len => {
+                    a_vals.push(&x.val);
+                    (a, a_len) = (x.next.as_ref(), a_len - 1);
+                }
+                (_, Some(y)) if b_len > a_len => {
+                    b_vals.push(&y.val);
+                    (b, b_len) = (y.next.as_ref(), b_len - 1);
+                }
+                (Some(x), Some(y)) if !Rc::ptr_eq(x, y) => {
+                    a_vals.push(&x.val);
+                    b_vals.push(&y.val);
+                    (a, a_len) = (x.next.as_ref(), a_len - 1);
+                    (b, b_len) = (y.next.as_ref(), b_len - 1);
+                }
+                _ => break,
+            }
+        }
+        a_vals.reverse();
+        b_vals.reverse();
+        (a_len, a_vals, b_vals)
+    }
+}
+
+impl<T: Clone> Stack<T> {
+    /// Take the top value off, copying it if another stack still shares it.
+    pub fn pop(&mut self) -> Option<T> {
+        let node = self.top.take()?;
+        self.len -= 1;
+        self.low = self.low.min(self.len);
+        match Rc::try_unwrap(node) {
+            Ok(node) => {
+                self.top = node.next;
+                Some(node.val)
+            }
+            Err(node) => {
+                self.top = node.next.clone();
+                Some(node.val.clone())
+            }
+        }
+    }
+
+    /// The top value, to change, which is first copied if another stack still shares it.
+    pub fn last_mut(&mut self) -> Option<&mut T> {
+        self.low = self.low.min(self.len.saturating_sub(1));
+        self.top.as_mut().map(|node| &mut Rc::make_mut(node).val)
+    }
+}
+
+impl<T> Clone for Stack<T> {
+    fn clone(&self) -> Self {
+        Stack {
+            top: self.top.clone(),
+            len: self.len,
+            low: self.low,
+        }
+    }
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Stack::new()
+    }
+}
+
+impl<T> Drop for Stack<T> {
+    // dropping the nodes one by one, since the default would recurse once per node and could overflow on a deep stack
+    fn drop(&mut self) {
+        let mut next = self.top.take();
+        while let Some(node) = next {
+            match Rc::try_unwrap(node) {
+                Ok(mut node) => next = node.next.take(),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Stack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut vals = self.iter().collect::<Vec<_>>();
+        vals.reverse();
+        f.debug_list().entries(vals).finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for Stack<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+/// A stack with the values in the order they'd be pushed, so the last is on top.
+impl<T> FromIterator<T> for Stack<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut stack = Stack::new();
+        for val in iter {
+            stack.push(val);
+        }
+        stack
+    }
+}
+
+impl<T> From<Vec<T>> for Stack<T> {
+    fn from(vals: Vec<T>) -> Self {
+        vals.into_iter().collect()
+    }
+}
+
+/// The values of a `Stack`, from the top down.
+pub struct Iter<'a, T> {
+    node: Option<&'a Node<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.node?;
+        self.node = node.next.as_deref();
+        Some(&node.val)
+    }
+}
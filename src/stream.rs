@@ -0,0 +1,216 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Verifying a program while it's still arriving, like over a network, so a plugin host doesn't have to wait
+//! for the last byte before it starts. Once the forward declarations are in, each function body is verified
+//! as soon as it's complete, so there's little left to do by the time `finish` is called.
+//!
+//! ```ignore
+//! let mut stream = Stream::new(&Config::default());
+//! while let Some(chunk) = download.next_chunk()? {
+//!     stream.feed(&chunk)?;
+//! }
+//! let module = Module::link(vec![stream.finish()?])?;
+//! ```
+//!
+//! The result is the same as parsing and verifying the whole program at once, except that a program with
+//! more than one problem can be rejected for a different one, since problems are found in the order they arrive.
+//! A program with a checksum is only checked against it by `finish`, which says if it was damaged
+//! even if `feed` already rejected it, as long as the rest of it was fed anyway.
+
+use crate::checksum::Crc32;
+use crate::header::*;
+use crate::metrics::{self, Counter};
+use crate::parse::{self, Parser};
+use crate::verify::{self, Signatures};
+use crate::vm::Config;
+
+use std::slice;
+
+/// A program that's partly arrived.
+pub struct Stream {
+    config: Config,
+    cancel: Cancellation,
+    /// The bytes that haven't been lexed yet, because what they start isn't complete.
+    buf: Vec<u8>,
+    len: usize,
+    state: State,
+    /// Why the program was rejected, once it has been.
+    failed: Option<Error>,
+    /// The checksum from the feature header, and the checksum of what's arrived since, if there is one.
+    checksum: Option<(u32, Crc32)>,
+}
+
+// there's only ever one State per program, so its size doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum State {
+    /// Before the data section and the number of functions are in.
+    Prelude,
+    Ops {
+        data_section: Vec<u8>,
+        parser: Parser,
+        /// The lexer's position, for its errors.
+        pos: Pos,
+        /// Worked out as soon as the last forward declaration is in.
+        sigs: Option<Signatures>,
+        verified: Vec<Stmt2>,
+    },
+}
+
+impl Stream {
+    pub fn new(config: &Config) -> Stream {
+        Stream::cancellable(config, Cancellation::default())
+    }
+
+    /// Like `new`, but verification gives up with an error if `cancel` says so.
+    pub fn cancellable(config: &Config, cancel: Cancellation) -> Stream {
+        Stream {
+            config: *config,
+            cancel,
+            buf: vec![],
+            len: 0,
+            state: State::Prelude,
+            failed: None,
+            checksum: None,
+        }
+    }
+
+    /// Take the next bytes of the program, verifying any function bodies they finish.
+    /// Once this returns an error the program is rejected, and every later call returns the same error.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if let Some(e) = &self.failed {
+            // keep the checksum going, so `finish` can still tell a damaged program from a malformed one
+            if let Some((_, crc)) = &mut self.checksum {
+                crc.update(bytes);
+            }
+            return Err(e.clone());
+        }
+        let res = self.advance(bytes);
+        if let Err(e) = &res {
+            self.failed = Some(e.clone());
+        }
+        res
+    }
+
+    fn advance(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        metrics::count(Counter::BytesParsed, bytes.len() as u64);
+        self.len += bytes.len();
+        let limits = &self.config.limits;
+        if self.len > limits.module_size {
+            return Err(Error::LimitExceeded(Limit::ModuleSize, limits.module_size, self.len));
+        }
+        if let Some((_, crc)) = &mut self.checksum {
+            crc.update(bytes);
+        }
+        self.buf.extend_from_slice(bytes);
+        if let State::Prelude = self.state {
+            let Some(prelude) = parse::prelude(&self.buf, limits)? else {
+                return Ok(());
+            };
+            if let Some(expected) = prelude.header.checksum {
+                let mut crc = Crc32::new();
+                crc.update(&self.buf[prelude.header.len..]);
+                self.checksum = Some((expected, crc));
+            }
+            self.buf.drain(..prelude.len);
+            self.state = State::Ops {
+                pos: (prelude.len - prelude.header.len) as u32,
+                data_section: prelude.data_section,
+                parser: Parser::new(prelude.n, prelude.m, prelude.body_sizes, prelude.header.bits, limits),
+                sigs: None,
+                verified: vec![],
+            };
+        }
+        let State::Ops {
+            data_section,
+            parser,
+            pos,
+            sigs,
+            verified,
+        } = &mut self.state
+        else {
+            unreachable!("the prelude was just parsed")
+        };
+        let mut start = 0;
+        loop {
+            sigs_when_ready(parser, sigs, &self.config)?;
+            if start == self.buf.len() {
+                break;
+            }
+            let Some((op, len)) = parse::lex_op(&self.buf[start..], *pos)? else {
+                break;
+            };
+            start += len;
+            *pos += 1;
+            if let Some(stmt) = parser.push(op)? {
+                let sigs = sigs.as_ref().expect("bodies come after the forward declarations");
+                verified.push(check_body(sigs, data_section, &stmt, &self.config, &self.cancel)?);
+            }
+        }
+        self.buf.drain(..start);
+        Ok(())
+    }
+
+    /// Say that the whole program has arrived, and verify whatever's left of it.
+    pub fn finish(self) -> Result<IRProgram, Error> {
+        if let Some((expected, crc)) = self.checksum {
+            let actual = crc.finish();
+            if actual != expected {
+                return Err(Error::ChecksumMismatch(expected, actual));
+            }
+        }
+        if let Some(e) = self.failed {
+            return Err(e);
+        }
+        let State::Ops {
+            data_section,
+            parser,
+            pos,
+            sigs,
+            mut verified,
+        } = self.state
+        else {
+            // the program ends before its code does, which the whole-program parser has an error for
+            return Err(parse::go_with_limits(&self.buf, &self.config.limits).err().unwrap_or(Error::UnexpectedEOF));
+        };
+        if let Some(&byte) = self.buf.first() {
+            return Err(Error::SyntaxErrorParamNeeded(pos, byte));
+        }
+        let (_, _, cut_short, _trailing) = parser.finish()?;
+        let sigs = sigs.expect("the parser only finishes once the forward declarations are in");
+        for stmt in &cut_short {
+            verified.push(check_body(&sigs, &data_section, stmt, &self.config, &self.cancel)?);
+        }
+        metrics::count(Counter::FunctionsVerified, verified.len() as u64);
+        sigs.program(data_section, verified)
+    }
+}
+
+/// Work out the signatures once the forward declarations are all in, if that hasn't been done yet.
+fn sigs_when_ready(parser: &Parser, sigs: &mut Option<Signatures>, config: &Config) -> Result<(), Error> {
+    if sigs.is_some() {
+        return Ok(());
+    }
+    if let (Some(type_decs), Some(forward_decs)) = (parser.type_decs(), parser.forward_decs()) {
+        metrics::count(Counter::FunctionsParsed, forward_decs.len() as u64);
+        verify::check_opcodes(forward_decs, &[], &config.allowed_opcodes)?;
+        let res = Signatures::new(type_decs, forward_decs, &config.limits, None);
+        *sigs = Some(res.inspect_err(|_| metrics::count(Counter::VerifyErrors, 1))?);
+    }
+    Ok(())
+}
+
+fn check_body(
+    sigs: &Signatures,
+    data_section: &[u8],
+    stmt: &Stmt1,
+    config: &Config,
+    cancel: &Cancellation,
+) -> Result<Stmt2, Error> {
+    verify::check_opcodes(&[], slice::from_ref(stmt), &config.allowed_opcodes)?;
+    sigs.check_body(data_section, stmt, cancel, None)
+        .inspect_err(|_| metrics::count(Counter::VerifyErrors, 1))
+}
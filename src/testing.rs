@@ -0,0 +1,1082 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Helpers for testing how programs end, like `expect_trap(&bytes, Trap::OutOfBounds)`.
+//!
+//! A run of a program is caught whatever it does, so a test of a trap fails, instead of crashing, if SaberVM panics.
+//! It's run in every `Mode` too, since checked and paranoid mode have their own ways of allocating,
+//! and a trap has to come out the same each way.
+//!
+//! `trap_matrix` is a program for every op that can trap and every way it can, which `sabervm test --traps` runs.
+//! `stress_cases` are programs that `sabervm test --threads` runs on many threads at once, from one shared `Module`.
+//! `api_cases` are sequences of calls a host makes on instances, which `sabervm test --api` runs.
+//!
+//! Every run gets the host functions of `provide_test_resources`, so tests can make and use host resources,
+//! and a run that doesn't finalize each one it made exactly once ends with `Ended::Leaked`, whatever else it did.
+
+use crate::asm;
+use crate::guest::{GuestFn, Resource};
+use crate::header::*;
+use crate::safepoint::Safepoints;
+use crate::vm::{Instance, Module};
+
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How a run of a program ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ended {
+    /// The program didn't parse, verify, or link.
+    Rejected(Error),
+    Halted(u8),
+    Trapped(Trap),
+    /// SaberVM panicked, with this message, which is always a bug in SaberVM.
+    Panicked(String),
+    /// The run ended without finalizing each resource it made (by id) exactly once, which is also always a bug in SaberVM.
+    Leaked { made: Vec<i32>, finalized: Vec<i32> },
+    /// The run ticked this many times (see `Ticker`) while its instance was paused, more than the once it could have already
+    /// been about to, which is also always a bug in SaberVM.
+    RanWhilePaused(usize),
+}
+
+impl fmt::Display for Ended {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ended::Rejected(e) => write!(f, "was rejected with {:?}", e),
+            Ended::Halted(status) => write!(f, "halted with status {}", status),
+            Ended::Trapped(trap) => write!(f, "trapped with {:?}", trap),
+            Ended::Panicked(msg) => write!(f, "panicked: {}", msg),
+            Ended::Leaked { made, finalized } => write!(f, "made resources {:?} but finalized {:?}", made, finalized),
+            Ended::RanWhilePaused(ticks) => write!(f, "ticked {} times while paused", ticks),
+        }
+    }
+}
+
+/// The ways an instance can run a program (see `Instance::set_checked` and `Instance::set_paranoid`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Plain,
+    Checked,
+    Paranoid,
+    CheckedParanoid,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 4] = [Mode::Plain, Mode::Checked, Mode::Paranoid, Mode::CheckedParanoid];
+}
+
+/// The kind of resource `TEST_OPEN` makes.
+pub const TEST_RESOURCE: u32 = 1;
+
+/// The index of the host function that makes a `TEST_RESOURCE`, whose id is its argument, for `host_res`.
+pub const TEST_OPEN: u32 = 0x80;
+
+/// The index of the host function that gives the id of the `TEST_RESOURCE` under its argument, plus the argument.
+pub const TEST_ID: u32 = 0x81;
+
+/// The index of the host function that gives the id of the `TEST_RESOURCE` finalized at the index of its argument, oldest first,
+/// or -1 if there haven't been that many, so programs can check when and in what order theirs are finalized.
+pub const TEST_FINALIZED: u32 = 0x82;
+
+/// The index of the host function that a program run by a `StressCase` with `paused` calls on each step, with a function under
+/// its argument. It calls the function back with the argument, and gives back what that yields.
+pub const TEST_TICK: u32 = 0x83;
+
+/// The ids of the `TEST_RESOURCE`s an instance has made and finalized, in order.
+#[derive(Clone, Default)]
+pub struct TestResources(Arc<Mutex<ResourceLog>>);
+
+#[derive(Default)]
+struct ResourceLog {
+    made: Vec<i32>,
+    finalized: Vec<i32>,
+}
+
+impl TestResources {
+    pub fn made(&self) -> Vec<i32> {
+        self.0.lock().unwrap().made.clone()
+    }
+
+    pub fn finalized(&self) -> Vec<i32> {
+        self.0.lock().unwrap().finalized.clone()
+    }
+
+    /// Whether every resource made so far was finalized exactly once.
+    pub fn all_finalized(&self) -> bool {
+        let log = self.0.lock().unwrap();
+        let (mut made, mut finalized) = (log.made.clone(), log.finalized.clone());
+        made.sort();
+        finalized.sort();
+        made == finalized
+    }
+}
+
+/// Provide `TEST_OPEN`, `TEST_ID`, and `TEST_FINALIZED`, and a finalizer for `TEST_RESOURCE`,
+/// a resource kind that doesn't stand for anything, to test `host_res` and finalizers with.
+pub fn provide_test_resources(instance: &mut Instance) -> TestResources {
+    let resources = TestResources::default();
+    let log = resources.0.clone();
+    instance.register_resource_fn(TEST_OPEN, TEST_RESOURCE, move |(id,): (i32,)| {
+        log.lock().unwrap().made.push(id);
+        id
+    });
+    instance.register_native_host_fn(TEST_ID, |(res, n): (Resource<TEST_RESOURCE>, i32)| res.0.wrapping_add(n));
+    let log = resources.0.clone();
+    instance.register_host_fn(TEST_FINALIZED, move |i| {
+        let log = log.lock().unwrap();
+        usize::try_from(i).ok().and_then(|i| log.finalized.get(i)).copied().unwrap_or(-1)
+    });
+    let log = resources.0.clone();
+    instance.register_finalizer(TEST_RESOURCE, move |id| log.lock().unwrap().finalized.push(id));
+    resources
+}
+
+/// Build a module of just this program and run it in `mode`, resuming it with the same value whenever it yields.
+/// The only host functions it gets are `provide_test_resources`'s. A program that never ends never returns here either.
+pub fn run(module_bytes: &[u8], mode: Mode) -> Ended {
+    caught(|| match Module::new(vec![module_bytes.to_vec()]) {
+        Ok(module) => run_module(Arc::new(module), mode, None, None),
+        Err(e) => Ended::Rejected(e),
+    })
+}
+
+/// Run `f`, with a panic caught as `Ended::Panicked`.
+fn caught(f: impl FnOnce() -> Ended) -> Ended {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| Ended::Panicked(panic_msg(payload)))
+}
+
+fn panic_msg(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(msg) => msg.to_string(),
+        None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+    }
+}
+
+/// Run a new instance of the module, like `run`, interrupting it if it's still going after `timeout`.
+/// If it's given a `Ticker`, the instance joins its group and gets its `TEST_TICK`.
+fn run_module(module: Arc<Module>, mode: Mode, timeout: Option<Duration>, ticker: Option<&Ticker>) -> Ended {
+    let mut instance = Instance::new(module);
+    let resources = provide_test_resources(&mut instance);
+    if let Some(ticker) = ticker {
+        ticker.provide(&mut instance);
+    }
+    instance.set_checked(matches!(mode, Mode::Checked | Mode::CheckedParanoid));
+    instance.set_paranoid(matches!(mode, Mode::Paranoid | Mode::CheckedParanoid));
+    // the timer stops waiting as soon as the run is over and `done` is dropped
+    let (done, timer) = mpsc::channel::<()>();
+    let timer = timeout.map(|timeout| {
+        let interrupt = instance.interrupt_handle();
+        thread::spawn(move || {
+            if timer.recv_timeout(timeout) == Err(mpsc::RecvTimeoutError::Timeout) {
+                interrupt.interrupt();
+            }
+        })
+    });
+    let mut res = instance.run();
+    while let Ok(Outcome::Yielded(val)) = res {
+        res = instance.resume(val);
+    }
+    drop(done);
+    if let Some(timer) = timer {
+        timer.join().unwrap();
+    }
+    if !resources.all_finalized() {
+        return Ended::Leaked {
+            made: resources.made(),
+            finalized: resources.finalized(),
+        };
+    }
+    match res {
+        Ok(Outcome::Halted(status)) => Ended::Halted(status),
+        Ok(Outcome::Yielded(_)) => unreachable!(),
+        Err(trap) => Ended::Trapped(trap),
+    }
+}
+
+/// Check that the program traps with `trap` in every `Mode`, or say how it ended instead in the first one it didn't.
+pub fn expect_trap(module_bytes: &[u8], trap: Trap) -> Result<(), (Mode, Ended)> {
+    for mode in Mode::ALL {
+        match run(module_bytes, mode) {
+            Ended::Trapped(t) if t == trap => {}
+            ended => return Err((mode, ended)),
+        }
+    }
+    Ok(())
+}
+
+/// A program that should trap, for `trap_matrix`.
+#[derive(Clone, Debug)]
+pub struct TrapCase {
+    /// The op that should trap, and what's wrong with its operands, like `arr_proj u8[4] at -1`.
+    pub name: String,
+    /// The assembly of the program, whose entry function ends up at the op.
+    pub src: String,
+    pub trap: Trap,
+}
+
+impl TrapCase {
+    /// Assemble the program, which is written so that it always assembles.
+    pub fn bytes(&self) -> ByteStream {
+        assemble(&self.src)
+    }
+}
+
+fn assemble(src: &str) -> ByteStream {
+    let lines = asm::parse(src).and_then(|lines| asm::expand(&lines, Path::new(".")));
+    lines.and_then(|lines| asm::assemble(&lines)).unwrap()
+}
+
+/// An entry function with this body, followed by a `halt`, so the body should leave a `u8` on top.
+fn program(data: &str, body: &str) -> String {
+    let data = match data {
+        "" => String::new(),
+        data => format!(".data \"{}\"\n\n", data),
+    };
+    format!("{}.func\n    func 0\n    lced\n.body\n{}    halt\n", data, body)
+}
+
+/// Every op that can trap, with every kind of operand it should trap on.
+pub fn trap_matrix() -> Vec<TrapCase> {
+    let mut cases = vec![];
+    let mut case = |name: String, data: &str, body: String, trap: Trap| {
+        cases.push(TrapCase {
+            name,
+            src: program(data, &body),
+            trap,
+        })
+    };
+    let (min, max) = (i32::MIN, i32::MAX);
+    let i32_ops = [
+        ("add_trap", max, 1, Trap::Overflow),
+        ("add_trap", min, -1, Trap::Overflow),
+        ("sub_trap", min, 1, Trap::Overflow),
+        ("sub_trap", max, -1, Trap::Overflow),
+        ("mul_trap", max, 2, Trap::Overflow),
+        ("mul_trap", min, -1, Trap::Overflow),
+        ("mul_trap", min, 2, Trap::Overflow),
+        ("div_trap", min, -1, Trap::Overflow),
+        ("modulo_trap", min, -1, Trap::Overflow),
+        ("div", 7, 0, Trap::DivideByZero),
+        ("div", min, 0, Trap::DivideByZero),
+        ("div_trap", 7, 0, Trap::DivideByZero),
+        ("modulo", 7, 0, Trap::DivideByZero),
+        ("modulo_trap", 7, 0, Trap::DivideByZero),
+    ];
+    for (op, a, b, trap) in i32_ops {
+        let body = format!("    lit {}\n    lit {}\n    {}\n    i32_to_u8\n", a, b, op);
+        case(format!("{} {} {}", op, a, b), "", body, trap);
+    }
+    let u8_ops = [
+        ("add_trap", 255, 1, Trap::Overflow),
+        ("sub_trap", 0, 1, Trap::Overflow),
+        ("mul_trap", 16, 16, Trap::Overflow),
+        ("div", 7, 0, Trap::DivideByZero),
+        ("div_trap", 7, 0, Trap::DivideByZero),
+        ("modulo", 7, 0, Trap::DivideByZero),
+        ("modulo_trap", 7, 0, Trap::DivideByZero),
+    ];
+    for (op, a, b, trap) in u8_ops {
+        let body = format!("    u8_lit {}\n    u8_lit {}\n    {}\n", a, b, op);
+        case(format!("{} {}u8 {}u8", op, a, b), "", body, trap);
+    }
+    // arrays of 4 of each type in a region, so 4 bytes or 16
+    let arrays = [("u8", "    u8_lit 1\n", ""), ("i32", "    lit 1\n", "    i32_to_u8\n")];
+    let indices = [4, 5, -1, -4, min, max, 1 << 30];
+    for (t, val, to_u8) in arrays {
+        let array = format!("    new_rgn 1024\n    share 0\n    lit 4\n    {}\n    arr\n    malloc\n", t);
+        for i in indices {
+            let body = format!("{}    lit {}\n    arr_proj\n{}", array, i, to_u8);
+            case(format!("arr_proj {}[4] at {}", t, i), "", body, Trap::OutOfBounds);
+            let body = format!("{}{}    lit {}\n    arr_mut\n    lit 0\n    arr_proj\n{}", array, val, i, to_u8);
+            case(format!("arr_mut {}[4] at {}", t, i), "", body, Trap::OutOfBounds);
+        }
+        for len in [-1, min] {
+            let body = format!("    new_rgn 1024\n    share 0\n    lit {}\n    {}\n    arr\n    malloc\n    lit 0\n    arr_proj\n{}", len, t, to_u8);
+            case(format!("malloc {}[{}]", t, len), "", body, Trap::OutOfBounds);
+        }
+        // more than the region has room for
+        let body = format!("    new_rgn 64\n    share 0\n    lit 64\n    {}\n    arr\n    malloc\n    lit 0\n    arr_proj\n{}", t, to_u8);
+        case(format!("malloc {}[64] in 64 bytes", t), "", body, Trap::RegionFull);
+        // copying more than 4 into 4 of them, whether or not there are more than 4 to copy
+        for (src_len, n) in [(4, 5), (8, 5), (4, -1), (4, min)] {
+            let body = format!(
+                "    new_rgn 1024\n    ctget 0\n{}    share 1\n    lit {}\n    {}\n    arr\n    malloc\n    lit {}\n    copy_n\n    lit 0\n    arr_proj\n{}",
+                &array["    new_rgn 1024\n".len()..],
+                src_len,
+                t,
+                n,
+                to_u8
+            );
+            case(format!("copy_n {} of {}[{}] into {}[4]", n, t, src_len, t), "", body, Trap::OutOfBounds);
+        }
+    }
+    // the data section is 4 bytes, and `data 2` is an array of its last 2
+    for (offset, i) in [(0, 4), (0, -1), (0, min), (2, 2), (2, -1), (2, max)] {
+        let body = format!("    data_sec\n    u8\n    arr\n    data {}\n    lit {}\n    arr_proj\n", offset, i);
+        case(format!("arr_proj data {} at {}", offset, i), "abcd", body, Trap::OutOfBounds);
+    }
+    for n in [5, -1, min] {
+        let body = format!(
+            "    new_rgn 1024\n    share 0\n    lit 4\n    u8\n    arr\n    malloc\n    data_sec\n    u8\n    arr\n    data 0\n    lit {}\n    copy_n\n    lit 0\n    arr_proj\n",
+            n
+        );
+        case(format!("copy_n {} of data into u8[4]", n), "abcd", body, Trap::OutOfBounds);
+    }
+    // 8 bytes of tuple, and 16 of the header every object has
+    let body = "    new_rgn 16\n    share 0\n    i32\n    i32\n    tuple 2\n    ptr\n    malloc\n    u8_lit 0\n".to_string();
+    case("malloc (i32, i32) in 16 bytes".to_string(), "", body, Trap::RegionFull);
+    // a buffer of 4 bytes, so one u32
+    let buffer = "    new_rgn 1024\n    share 0\n    lit 4\n    buf_new\n";
+    for i in [4, 5, -1, min, max] {
+        let body = format!("{}    lit {}\n    buf_get_u8\n", buffer, i);
+        case(format!("buf_get_u8 buf[4] at {}", i), "", body, Trap::OutOfBounds);
+        let body = format!("{}    u8_lit 1\n    lit {}\n    buf_set_u8\n    buf_len\n    i32_to_u8\n", buffer, i);
+        case(format!("buf_set_u8 buf[4] at {}", i), "", body, Trap::OutOfBounds);
+    }
+    for i in [1, 4, -1, min, max] {
+        let body = format!("{}    lit {}\n    buf_get_u32\n    i32_to_u8\n", buffer, i);
+        case(format!("buf_get_u32 buf[4] at {}", i), "", body, Trap::OutOfBounds);
+        let body = format!("{}    lit 1\n    lit {}\n    buf_set_u32\n    buf_len\n    i32_to_u8\n", buffer, i);
+        case(format!("buf_set_u32 buf[4] at {}", i), "", body, Trap::OutOfBounds);
+    }
+    let body = "    new_rgn 1024\n    lit -1\n    buf_new\n    buf_len\n    i32_to_u8\n".to_string();
+    case("buf_new -1".to_string(), "", body, Trap::OutOfBounds);
+    let body = "    new_rgn 64\n    lit 64\n    buf_new\n    buf_len\n    i32_to_u8\n".to_string();
+    case("buf_new 64 in 64 bytes".to_string(), "", body, Trap::RegionFull);
+    case("host_call 9999".to_string(), "", "    lit 0\n    host_call 9999\n    i32_to_u8\n".to_string(), Trap::UnknownHostFunction(9999));
+    // making a resource of the test kind, or of kind 2, which no host function makes, and then reading its id
+    for (rgn, kind, f, trap) in [
+        (64, TEST_RESOURCE, TEST_ID, Trap::HostSignature(TEST_ID)),
+        (64, 2, TEST_OPEN, Trap::HostSignature(TEST_OPEN)),
+        (64, TEST_RESOURCE, 9999, Trap::UnknownHostFunction(9999)),
+        (8, TEST_RESOURCE, TEST_OPEN, Trap::RegionFull),
+    ] {
+        let body = format!(
+            "    new_rgn {}\n    res {}\n    lit 7\n    host_res {}\n    lit 0\n    host_call {}\n    i32_to_u8\n",
+            rgn, kind, f, TEST_ID
+        );
+        case(format!("host_res {} of res {} in {} bytes", f, kind, rgn), "", body, trap);
+    }
+    let body = format!("    lit 7\n    host_call {}\n    i32_to_u8\n", TEST_OPEN);
+    case(format!("host_call {}, which makes resources", TEST_OPEN), "", body, Trap::HostSignature(TEST_OPEN));
+    let body = format!("    lit 7\n    lit 0\n    host_call {}\n    i32_to_u8\n", TEST_ID);
+    case(format!("host_call {} of an i32", TEST_ID), "", body, Trap::HostSignature(TEST_ID));
+    // trapping after one resource's region was freed and with another's still live, which `run` checks both get finalized
+    let body = format!(
+        "    new_rgn 64\n    share 0\n    res {0}\n    lit 1\n    host_res {1}\n    share 1\n    free_rgn\n    new_rgn 64\n    res {0}\n    lit 2\n    host_res {1}\n    lit 1\n    lit 0\n    div\n    i32_to_u8\n",
+        TEST_RESOURCE, TEST_OPEN
+    );
+    case("div by 0 with resources made".to_string(), "", body, Trap::DivideByZero);
+    // big integer literals of 2^31 at 0, 0 at 12, and -2^31 - 1 at 20
+    let bigs = ".features 0x200\n.bigint 2147483648\n.bigint 0\n.bigint -2147483649\n\n";
+    let big_cases = [
+        ("big_div 2^31 by 0", "    new_rgn 1024\n    big_data 0\n    big_data 12\n    big_div\n", Trap::DivideByZero),
+        ("big_modulo 2^31 by 0", "    new_rgn 1024\n    big_data 0\n    big_data 12\n    big_modulo\n", Trap::DivideByZero),
+        ("big_to_i32 2^31", "    big_data 0\n", Trap::Overflow),
+        ("big_to_i32 -2^31 - 1", "    big_data 20\n", Trap::Overflow),
+        ("big_mul 2^31 2^31 in 16 bytes", "    new_rgn 16\n    big_data 0\n    big_data 0\n    big_mul\n", Trap::RegionFull),
+        ("big_from_i32 in 16 bytes", "    new_rgn 16\n    lit 7\n    big_from_i32\n", Trap::RegionFull),
+    ];
+    for (name, body, trap) in big_cases {
+        cases.push(TrapCase {
+            name: name.to_string(),
+            src: bigs.to_string() + &program("", &format!("{}    big_to_i32\n    i32_to_u8\n", body)),
+            trap,
+        });
+    }
+    // a map of key 1 to 10, and an empty one in a region too small to hold a table
+    let map = "    new_rgn 1024\n    i32\n    map\n    malloc\n    lit 10\n    lit 1\n    map_set\n";
+    let map_cases = [
+        ("map_get of a missing key".to_string(), format!("{}    lit 2\n    map_get\n", map), Trap::OutOfBounds),
+        ("map_get of a removed key".to_string(), format!("{}    lit 1\n    map_remove\n    lit 1\n    map_get\n", map), Trap::OutOfBounds),
+        ("malloc map in 64 bytes".to_string(), "    new_rgn 64\n    i32\n    map\n    malloc\n    map_len\n".to_string(), Trap::RegionFull),
+    ];
+    // 6 keys fit in the first table, and the seventh needs a bigger one
+    let mut grow = "    new_rgn 256\n    i32\n    map\n    malloc\n".to_string();
+    for key in 0..7 {
+        grow += &format!("    lit 0\n    lit {}\n    map_set\n", key);
+    }
+    let map_cases = map_cases.into_iter().chain([("map_set growing in 256 bytes".to_string(), grow + "    map_len\n", Trap::RegionFull)]);
+    for (name, body, trap) in map_cases {
+        cases.push(TrapCase {
+            name,
+            src: ".features 0x400\n\n".to_string() + &program("", &format!("{}    i32_to_u8\n", body)),
+            trap,
+        });
+    }
+    cases
+}
+
+/// A program for `expect_concurrent`, with the ways a run of it can end while others run at once,
+/// and the way it ends on its own.
+#[derive(Clone, Debug)]
+pub struct StressCase {
+    pub name: String,
+    pub src: String,
+    pub ends: Vec<Ended>,
+    pub alone: Ended,
+    /// How long a run can go before it's interrupted, for programs that wait on stdin, which may never come.
+    pub timeout: Option<Duration>,
+    /// Whether every instance joins one `Safepoints`, which another thread pauses over and over while they run,
+    /// checking each time that none of them gets past the `TEST_TICK` it might have been in when the pause came.
+    pub paused: bool,
+}
+
+/// A worker's `TEST_TICK`, which ticks once when it's called and once when its callback is done,
+/// in the group of instances a `StressCase` with `paused` pauses.
+struct Ticker {
+    safepoints: Safepoints,
+    ticks: Arc<AtomicUsize>,
+    /// The mode of the worker's run, by its index in `Mode::ALL`, to say which one ran while paused.
+    mode: AtomicUsize,
+}
+
+impl Ticker {
+    fn new(safepoints: &Safepoints) -> Ticker {
+        Ticker {
+            safepoints: safepoints.clone(),
+            ticks: Arc::new(AtomicUsize::new(0)),
+            mode: AtomicUsize::new(0),
+        }
+    }
+
+    fn provide(&self, instance: &mut Instance) {
+        instance.set_safepoints(&self.safepoints);
+        let ticks = self.ticks.clone();
+        instance.register_host_fn_with_callbacks(TEST_TICK, move |guest, arg| {
+            ticks.fetch_add(1, Ordering::Relaxed);
+            let Some(f) = guest.view().get(0).and_then(|f| f.as_func()) else {
+                return 0;
+            };
+            let res = guest.call(f, &[arg]).unwrap_or(0);
+            ticks.fetch_add(1, Ordering::Relaxed);
+            res
+        });
+    }
+}
+
+impl StressCase {
+    /// Assemble the program, which is written so that it always assembles.
+    pub fn bytes(&self) -> ByteStream {
+        assemble(&self.src)
+    }
+}
+
+/// Run the program on `workers` threads at once, `runs` times on each, every run a new instance of one shared `Module`
+/// in the next `Mode`, and then once more on its own, after the others are dropped, to check they gave back what they took,
+/// like stdin. Say how the first run that didn't end as it should did, and in which mode.
+pub fn expect_concurrent(case: &StressCase, workers: usize, runs: usize) -> Result<(), (Mode, Ended)> {
+    let module = match Module::new(vec![case.bytes()]) {
+        Ok(module) => Arc::new(module),
+        Err(e) => return Err((Mode::Plain, Ended::Rejected(e))),
+    };
+    let safepoints = Safepoints::new();
+    let tickers = (0..workers).map(|_| Ticker::new(&safepoints)).collect::<Vec<_>>();
+    let finished = AtomicBool::new(false);
+    let results = thread::scope(|scope| {
+        let pauser = case.paused.then(|| scope.spawn(|| pause_until(&safepoints, &tickers, &finished)));
+        let workers = (0..workers)
+            .map(|worker| {
+                let module = module.clone();
+                let ticker = case.paused.then(|| &tickers[worker]);
+                scope.spawn(move || {
+                    for i in 0..runs {
+                        let mode = (worker + i) % Mode::ALL.len();
+                        if let Some(ticker) = ticker {
+                            ticker.mode.store(mode, Ordering::Relaxed);
+                        }
+                        let mode = Mode::ALL[mode];
+                        match caught(|| run_module(module.clone(), mode, case.timeout, ticker)) {
+                            ended if case.ends.contains(&ended) => {}
+                            ended => return Err((mode, ended)),
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut results = workers.into_iter().map(|worker| worker.join().unwrap()).collect::<Vec<_>>();
+        finished.store(true, Ordering::Relaxed);
+        results.extend(pauser.map(|pauser| pauser.join().unwrap()));
+        results
+    });
+    results.into_iter().collect::<Result<(), _>>()?;
+    let ticker = case.paused.then(|| Ticker::new(&safepoints));
+    match caught(|| run_module(module, Mode::Plain, case.timeout, ticker.as_ref())) {
+        ended if ended == case.alone => Ok(()),
+        ended => Err((Mode::Plain, ended)),
+    }
+}
+
+/// Pause the instances of `tickers` over and over until `finished`, checking that each pause holds them still:
+/// an instance can start the `TEST_TICK` it was about to make when the pause came, but it can't finish its callback,
+/// or make another, so it can't tick more than once.
+fn pause_until(safepoints: &Safepoints, tickers: &[Ticker], finished: &AtomicBool) -> Result<(), (Mode, Ended)> {
+    let ticks = || tickers.iter().map(|ticker| ticker.ticks.load(Ordering::Relaxed)).collect::<Vec<_>>();
+    while !finished.load(Ordering::Relaxed) {
+        let paused = safepoints.pause();
+        let before = ticks();
+        thread::sleep(Duration::from_millis(2));
+        let after = ticks();
+        drop(paused);
+        for ((ticker, before), after) in tickers.iter().zip(before).zip(after) {
+            if after - before > 1 {
+                let mode = Mode::ALL[ticker.mode.load(Ordering::Relaxed)];
+                return Err((mode, Ended::RanWhilePaused(after - before)));
+            }
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+/// Programs that share what an instance can't keep to itself: the allocator, host functions and their resources, and stdin.
+pub fn stress_cases() -> Vec<StressCase> {
+    // allocating and freeing a region on each step of a countdown from 1000
+    let countdown = "\
+.func @main
+    func 0
+    lced
+.body
+    lit 1000
+    call @loop
+
+.func @loop
+    i32
+    func 1
+    lced
+.body
+    lit -1
+    add
+    new_rgn 4096
+    share 0
+    i32
+    i32
+    tuple 2
+    ptr
+    malloc
+    get 2
+    init 0
+    get 2
+    init 1
+    share 1
+    free_rgn
+    get 2
+    get 3
+    global_func @loop
+    global_func @done
+    call_nz
+
+.func @done
+    i32
+    func 1
+    lced
+.body
+    u8_lit 0
+    halt
+";
+    // the resources of ids 1, 2, and 3, and halting with the ids of the first two finalized, which are 3 and 2
+    let resources = format!(
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 128
+    share 0
+    res {0}
+    lit 1
+    host_res {1}
+    new_rgn 128
+    ctget 0
+    share 0
+    res {0}
+    lit 2
+    host_res {1}
+    share 1
+    res {0}
+    lit 3
+    host_res {1}
+    share 2
+    free_rgn
+    lit 0
+    host_call {2}
+    lit 10
+    mul
+    lit 1
+    host_call {2}
+    add
+    i32_to_u8
+    halt
+",
+        TEST_RESOURCE, TEST_OPEN, TEST_FINALIZED
+    );
+    // reading stdin with a handler that halts with 7, in a package of the handler and a pointer it doesn't use,
+    // exists a: 16. ((u8[]@r, a) -> 0, a), and otherwise ending the first task to wait for input
+    let read = "\
+.func @main
+    func 0
+    lced
+.body
+    new_rgn 1024
+    size 16
+    some
+    ctget 0
+    ctget 1
+    ctget 3
+    u8
+    arr
+    func 2
+    tuple 2
+    end
+    share 0
+    ctget 1
+    i32
+    tuple 1
+    ptr
+    malloc
+    lit 0
+    init 0
+    ctget 1
+    i32
+    tuple 1
+    ptr
+    ctget 2
+    i32
+    tuple 1
+    ptr
+    ctget 3
+    u8
+    arr
+    func 2
+    tuple 2
+    malloc
+    ctget 1
+    global_func @on_input
+    app
+    init 0
+    get 1
+    init 1
+    ctget 1
+    i32
+    tuple 1
+    ptr
+    pack
+    share 2
+    read 0
+    u8_lit 0
+    halt
+
+.func @on_input
+    rgn
+    ctget 0
+    i32
+    tuple 1
+    ptr
+    ctget 1
+    u8
+    arr
+    func 2
+    end
+    lced
+.body
+    u8_lit 7
+    halt
+";
+    // a countdown from 2000 that takes each step in a callback from `TEST_TICK`
+    let ticks = format!(
+        "\
+.func @main
+    func 0
+    lced
+.body
+    lit 2000
+    call @loop
+
+.func @loop
+    i32
+    func 1
+    lced
+.body
+    global_func @step
+    get 1
+    host_call {0}
+    get 0
+    global_func @loop
+    global_func @done
+    call_nz
+
+.func @step
+    i32
+    func 1
+    lced
+.body
+    lit -1
+    add
+    yield
+    i32_to_u8
+    halt
+
+.func @done
+    i32
+    func 1
+    lced
+.body
+    u8_lit 0
+    halt
+",
+        TEST_TICK
+    );
+    vec![
+        StressCase {
+            name: "new_rgn and free_rgn in a loop".to_string(),
+            src: countdown.to_string(),
+            ends: vec![Ended::Halted(0)],
+            alone: Ended::Halted(0),
+            timeout: None,
+            paused: false,
+        },
+        StressCase {
+            name: "host_res and finalizers".to_string(),
+            src: resources,
+            ends: vec![Ended::Halted(32)],
+            alone: Ended::Halted(32),
+            timeout: None,
+            paused: false,
+        },
+        // only one instance at a time can read stdin, so the rest trap, and the one that has it waits until it's interrupted
+        // (or halts, if there was input)
+        StressCase {
+            name: "read 0".to_string(),
+            src: read.to_string(),
+            ends: vec![
+                Ended::Trapped(Trap::Interrupted),
+                Ended::Trapped(Trap::StdinBusy),
+                Ended::Halted(7),
+            ],
+            alone: Ended::Trapped(Trap::Interrupted),
+            timeout: Some(Duration::from_millis(50)),
+            paused: false,
+        },
+        // every instance is paused over and over, in the program and in callbacks
+        StressCase {
+            name: "pausing instances in callbacks".to_string(),
+            src: ticks,
+            ends: vec![Ended::Halted(0)],
+            alone: Ended::Halted(0),
+            timeout: None,
+            paused: true,
+        },
+    ]
+}
+
+/// A sequence of calls a host makes on instances, checking what each one gives back, for the ways of driving the VM
+/// that a program run on its own doesn't cover, like reloading between a `yield` and its `resume`.
+#[derive(Clone, Debug)]
+pub struct ApiCase {
+    pub name: String,
+    /// Make the calls, saying which one gave back something it shouldn't have.
+    pub check: fn() -> Result<(), String>,
+}
+
+/// Run the case, with a panic caught as a failure like any other.
+pub fn expect_api(case: &ApiCase) -> Result<(), String> {
+    panic::catch_unwind(case.check).unwrap_or_else(|payload| Err(format!("panicked: {}", panic_msg(payload))))
+}
+
+/// A module of just this program, which is written so that it always assembles and verifies.
+fn module_of(src: &str) -> Arc<Module> {
+    Arc::new(Module::new(vec![assemble(src)]).unwrap())
+}
+
+/// Check that one of a case's calls gave back what it should have.
+fn expect<T: PartialEq + fmt::Debug>(call: &str, got: T, want: T) -> Result<(), String> {
+    if got == want {
+        Ok(())
+    } else {
+        Err(format!("{} gave {:?} instead of {:?}", call, got, want))
+    }
+}
+
+/// An entry function that yields 5 and halts with what it's resumed with, and an export `triple`,
+/// with the entry function halting with `halt` instead in `reloaded`.
+fn yielding(halt: Option<u8>) -> String {
+    let body = match halt {
+        None => "    lit 5\n    yield\n    i32_to_u8\n".to_string(),
+        Some(status) => format!("    u8_lit {}\n", status),
+    };
+    format!(
+        "\
+.func @main
+    func 0
+    lced
+.body
+{}    halt
+
+.func @triple
+.export_name \"triple\"
+    i32
+    func 1
+    lced
+.body
+    lit 3
+    mul
+    i32_to_u8
+    halt
+",
+        body
+    )
+}
+
+/// A `call` that can't start leaves a run stopped at a `yield` as it was, on the module it started on, even after a `reload`.
+fn bad_call_after_reload() -> Result<(), String> {
+    let mut instance = Instance::new(module_of(&yielding(None)));
+    expect("run", instance.run(), Ok(Outcome::Yielded(5)))?;
+    expect("reload", instance.reload(module_of(&yielding(Some(9)))), Ok(()))?;
+    let unknown = CallError::UnknownExport("nope".to_string());
+    expect("call of an unknown export", instance.call("nope", ()), Err(unknown))?;
+    let mismatch = CallError::ArgMismatch("triple".to_string(), Type::Func(vec![Type::I32]), "()".to_string());
+    expect("call with no arguments", instance.call("triple", ()), Err(mismatch))?;
+    expect("is_suspended", instance.is_suspended(), true)?;
+    expect("resume", instance.resume(7), Ok(Outcome::Halted(7)))?;
+    expect("run on the new module", instance.run(), Ok(Outcome::Halted(9)))?;
+    expect("call on the new module", instance.call("triple", (4,)), Ok(Outcome::Halted(12)))
+}
+
+/// A `call` that can't start doesn't finalize the resources of a run stopped at a `yield`, which can still use them.
+fn bad_call_keeps_resources() -> Result<(), String> {
+    let src = format!(
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 128
+    share 0
+    res {0}
+    lit 1
+    host_res {1}
+    lit 5
+    yield
+    host_call {2}
+    i32_to_u8
+    halt
+",
+        TEST_RESOURCE, TEST_OPEN, TEST_ID
+    );
+    let mut instance = Instance::new(module_of(&src));
+    let resources = provide_test_resources(&mut instance);
+    expect("run", instance.run(), Ok(Outcome::Yielded(5)))?;
+    let unknown = CallError::UnknownExport("nope".to_string());
+    expect("call of an unknown export", instance.call("nope", ()), Err(unknown))?;
+    expect("finalized after the call", resources.finalized(), vec![])?;
+    expect("resume", instance.resume(7), Ok(Outcome::Halted(8)))?;
+    expect("finalized after the resume", resources.finalized(), vec![1])
+}
+
+/// An entry function that calls host function 0 on 20 with `@double` and `@reenter` under it, and halts with what it gives.
+/// `@double` doubles one more than its argument, getting that from host function 1, and `@reenter` calls host function 0.
+const CALLBACKS: &str = "\
+.func @main
+    func 0
+    lced
+.body
+    global_func @double
+    global_func @reenter
+    lit 20
+    host_call 0
+    i32_to_u8
+    halt
+
+.func @double
+    i32
+    func 1
+    lced
+.body
+    host_call 1
+    lit 2
+    mul
+    yield
+    i32_to_u8
+    halt
+
+.func @reenter
+    i32
+    func 1
+    lced
+.body
+    host_call 0
+    yield
+    i32_to_u8
+    halt
+";
+
+/// Which function `provide_callbacks`' host function calls back.
+#[derive(Clone, Copy)]
+enum Callback {
+    Double,
+    Reenter,
+    /// The `@double` it was given the last time it called that one, possibly from another module.
+    Kept,
+}
+
+/// What `provide_callbacks`' host function calls back next, and what it kept from the calls so far.
+struct Callbacks {
+    next: Callback,
+    kept: Option<GuestFn>,
+    /// What the last callback gave back.
+    last: Option<Result<i32, Trap>>,
+}
+
+impl Callbacks {
+    fn new() -> Arc<Mutex<Callbacks>> {
+        Arc::new(Mutex::new(Callbacks {
+            next: Callback::Double,
+            kept: None,
+            last: None,
+        }))
+    }
+}
+
+fn last(callbacks: &Mutex<Callbacks>) -> Option<Result<i32, Trap>> {
+    callbacks.lock().unwrap().last
+}
+
+/// Provide host function 0 for `CALLBACKS`, which calls back the function `callbacks` says with its argument,
+/// giving back what that yields, or 0 if it didn't, and host function 1, which adds one to its argument.
+fn provide_callbacks(instance: &mut Instance, callbacks: Arc<Mutex<Callbacks>>) {
+    instance.register_host_fn_with_callbacks(0, move |guest, arg| {
+        let view = guest.view();
+        let (double, reenter) = (view.get(1).and_then(|f| f.as_func()), view.get(0).and_then(|f| f.as_func()));
+        let f = {
+            let mut callbacks = callbacks.lock().unwrap();
+            match callbacks.next {
+                Callback::Double => {
+                    callbacks.kept = double;
+                    double
+                }
+                Callback::Reenter => reenter,
+                Callback::Kept => callbacks.kept,
+            }
+        };
+        let res = guest.call(f.unwrap(), &[arg]);
+        callbacks.lock().unwrap().last = Some(res);
+        res.unwrap_or(0)
+    });
+    instance.register_host_fn(1, |arg| arg + 1);
+}
+
+/// A host function calls back into the program, which makes a host call of its own, and then calls the host function
+/// that's calling it, which stops the run.
+fn callbacks() -> Result<(), String> {
+    let callbacks = Callbacks::new();
+    let mut instance = Instance::new(module_of(CALLBACKS));
+    provide_callbacks(&mut instance, callbacks.clone());
+    expect("run calling back @double", instance.run(), Ok(Outcome::Halted(42)))?;
+    expect("@double", last(&callbacks), Some(Ok(42)))?;
+    callbacks.lock().unwrap().next = Callback::Reenter;
+    expect("run calling back @reenter", instance.run(), Err(Trap::ReentrantHostCall(0)))?;
+    expect("@reenter", last(&callbacks), Some(Err(Trap::ReentrantHostCall(0))))
+}
+
+/// A `GuestFn` kept from one run can be called back in a later run of the same module,
+/// but not in a run of another module, even one built from the same program, or after a `reload`.
+fn foreign_callbacks() -> Result<(), String> {
+    let callbacks = Callbacks::new();
+    let mut instance = Instance::new(module_of(CALLBACKS));
+    provide_callbacks(&mut instance, callbacks.clone());
+    expect("run calling back @double", instance.run(), Ok(Outcome::Halted(42)))?;
+    callbacks.lock().unwrap().next = Callback::Kept;
+    expect("run calling back the kept @double", instance.run(), Ok(Outcome::Halted(42)))?;
+    let mut other = Instance::new(module_of(CALLBACKS));
+    provide_callbacks(&mut other, callbacks.clone());
+    expect("run of another module", other.run(), Ok(Outcome::Halted(0)))?;
+    expect("the kept @double in another module", last(&callbacks), Some(Err(Trap::ForeignGuestFn)))?;
+    expect("reload", instance.reload(module_of(CALLBACKS)), Ok(()))?;
+    expect("run after the reload", instance.run(), Ok(Outcome::Halted(0)))?;
+    expect("the kept @double after the reload", last(&callbacks), Some(Err(Trap::ForeignGuestFn)))
+}
+
+/// A resource's id times `scale`, plus the argument, bound with `#[svm_host_fn]`.
+#[cfg(feature = "macros")]
+#[crate::svm_host_fn]
+pub(crate) fn scaled_id(res: Resource<TEST_RESOURCE>, scale: i32, n: i32) -> i32 {
+    res.0 * scale + n
+}
+
+/// The length of a string plus the argument, bound with `#[svm_host_fn]`, which no `host_call` in `bindings` can call.
+#[cfg(feature = "macros")]
+#[crate::svm_host_fn]
+fn plus_len(s: String, n: i32) -> i32 {
+    s.len() as i32 + n
+}
+
+/// Bindings from `#[svm_host_fn]` are checked against the module's host calls when they're registered,
+/// and one that takes other arguments than a host call pushes is rejected before anything runs.
+#[cfg(feature = "macros")]
+fn bindings() -> Result<(), String> {
+    let src = format!(
+        "\
+.func
+    func 0
+    lced
+.body
+    new_rgn 256
+    ctget 0
+    share 0
+    res {0}
+    lit 40
+    host_res {1}
+    lit 3
+    lit 2
+    host_call 0
+    i32_to_u8
+    halt
+",
+        TEST_RESOURCE, TEST_OPEN
+    );
+    let mut instance = Instance::new(module_of(&src));
+    provide_test_resources(&mut instance);
+    match instance.register_binding(0, PLUS_LEN) {
+        Err(Error::HostSignatureMismatch(0, _, signature, _)) => expect("the signature", signature.as_str(), "(u8[], i32)")?,
+        res => return Err(format!("registering plus_len gave {:?} instead of a HostSignatureMismatch", res)),
+    }
+    expect("registering scaled_id", instance.register_binding(0, SCALED_ID), Ok(()))?;
+    expect("run", instance.run(), Ok(Outcome::Halted(122)))
+}
+
+pub fn api_cases() -> Vec<ApiCase> {
+    vec![
+        ApiCase {
+            name: "a call that can't start, after a reload at a yield".to_string(),
+            check: bad_call_after_reload,
+        },
+        ApiCase {
+            name: "a call that can't start, with resources open at a yield".to_string(),
+            check: bad_call_keeps_resources,
+        },
+        ApiCase {
+            name: "callbacks, with a host call in one and a reentrant host call in another".to_string(),
+            check: callbacks,
+        },
+        ApiCase {
+            name: "a kept callback in another module, and after a reload".to_string(),
+            check: foreign_callbacks,
+        },
+        #[cfg(feature = "macros")]
+        ApiCase {
+            name: "#[svm_host_fn] bindings".to_string(),
+            check: bindings,
+        },
+    ]
+}
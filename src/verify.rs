@@ -4,73 +4,533 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use crate::bigint;
 use crate::header::RgnId::DataSection;
 use crate::header::*;
-use crate::pretty::Pretty;
+use crate::intern::{Component, Node, TypeArena, TypeId, Types};
+use crate::log::{self, event, Level};
+use crate::metrics::{self, Counter, Phase};
+use crate::stack::Stack;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::time::Instant;
+
+thread_local! {
+    /// How many times two types have been compared on this thread, for `Counter::TypeChecks`.
+    static TYPE_CHECKS: Cell<u64> = const { Cell::new(0) };
+}
 
 pub fn go(
     data_section: Vec<u8>,
+    type_decs: Vec<TypeDec>,
+    types_instrs: Vec<ForwardDec>,
+    unverified_stmts: Vec<Stmt1>,
+) -> Result<IRProgram, Error> {
+    let limits = Limits::default();
+    check(data_section, type_decs, types_instrs, unverified_stmts, &limits, &Cancellation::default(), None)
+}
+
+/// Like `go`, but giving up with an error if `cancel` says so.
+pub fn go_cancellable(
+    data_section: Vec<u8>,
+    type_decs: Vec<TypeDec>,
+    types_instrs: Vec<ForwardDec>,
+    unverified_stmts: Vec<Stmt1>,
+    cancel: &Cancellation,
+) -> Result<IRProgram, Error> {
+    go_with_limits(data_section, type_decs, types_instrs, unverified_stmts, &Limits::default(), cancel)
+}
+
+/// Like `go_cancellable`, but with types only nesting as deep as `limits.type_depth` (the other limits are the parser's).
+pub fn go_with_limits(
+    data_section: Vec<u8>,
+    type_decs: Vec<TypeDec>,
     types_instrs: Vec<ForwardDec>,
     unverified_stmts: Vec<Stmt1>,
+    limits: &Limits,
+    cancel: &Cancellation,
 ) -> Result<IRProgram, Error> {
-    let mut types = HashMap::new();
+    check(data_section, type_decs, types_instrs, unverified_stmts, limits, cancel, None)
+}
+
+/// Verify a program like `go`, also recording the verifier's state after every instruction.
+/// The record is returned even if verification fails, since that's when it's most useful.
+pub fn explain(
+    data_section: Vec<u8>,
+    type_decs: Vec<TypeDec>,
+    types_instrs: Vec<ForwardDec>,
+    unverified_stmts: Vec<Stmt1>,
+) -> (Vec<Explained>, Result<IRProgram, Error>) {
+    let mut trace = vec![];
+    let res = check(
+        data_section,
+        type_decs,
+        types_instrs,
+        unverified_stmts,
+        &Limits::default(),
+        &Cancellation::default(),
+        Some(&mut trace),
+    );
+    (trace, res)
+}
+
+/// Where in a program verification failed (see `error_site`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorSite {
+    pub label: Label,
+    /// Whether it's in the function's forward declaration rather than its body.
+    pub forward_dec: bool,
+    /// How many ops into the declaration or body the op that failed is,
+    /// or `None` if it failed after them, like a declaration that leaves more than a type.
+    pub op: Option<usize>,
+}
+
+/// Find which function, and which op in it, makes a program fail to verify, by checking it again one function at a time,
+/// in the same order `go` does. This is `None` if it verifies, or fails outside of any function, like in a type declaration.
+pub fn error_site(
+    data_section: &[u8],
+    type_decs: &[TypeDec],
+    types_instrs: &[ForwardDec],
+    unverified_stmts: &[Stmt1],
+) -> Option<ErrorSite> {
+    let limits = Limits::default();
+    let named = named_types(type_decs, types_instrs.len(), &limits).ok()?;
     let mut fresh_id = 0;
-    let mut imports = HashMap::new();
-    let mut exports = HashMap::new();
     for stmt in types_instrs {
-        match type_pass(&stmt, fresh_id) {
-            Ok((l, vis, t, new_fresh_id)) => {
-                types.insert(l, t);
-                match vis {
-                    Visibility::Import(a, b) => {
-                        imports.insert(l, (a, b));
-                    }
-                    Visibility::Export(a, b) => {
-                        exports.insert((a, b), l);
+        let ForwardDec::Func(label, _, ops) = stmt;
+        let mut trace = vec![];
+        match type_pass(stmt, &named, fresh_id, &limits, Some(&mut trace)) {
+            Ok((_, _, _, new_fresh_id)) => fresh_id = new_fresh_id,
+            Err(_) => {
+                return Some(ErrorSite {
+                    label: *label,
+                    forward_dec: true,
+                    op: (trace.len() < ops.len()).then_some(trace.len()),
+                })
+            }
+        }
+    }
+    let sigs = Signatures::new(type_decs, types_instrs, &limits, None).ok()?;
+    for stmt in unverified_stmts {
+        let Stmt1::Func(label, _, ops) = stmt;
+        let mut trace = vec![];
+        if sigs.check_body(data_section, stmt, &Cancellation::default(), Some(&mut trace)).is_err() {
+            return Some(ErrorSite {
+                label: *label,
+                forward_dec: false,
+                op: (trace.len() < ops.len()).then_some(trace.len()),
+            });
+        }
+    }
+    None
+}
+
+/// Reject the program if any forward declaration or function body uses an op that isn't in `allowed`.
+/// Ops after the last function body are never run, so they're left alone.
+pub fn check_opcodes(forward_decs: &[ForwardDec], stmts: &[Stmt1], allowed: &OpcodeSet) -> Result<(), Error> {
+    let decs = forward_decs.iter().map(|ForwardDec::Func(label, _, ops)| (label, ops));
+    let bodies = stmts.iter().map(|Stmt1::Func(label, _, ops)| (label, ops));
+    for (label, ops) in decs.chain(bodies) {
+        if let Some(op) = ops.iter().find(|op| !allowed.contains(op.byte())) {
+            return Err(Error::OpcodeNotAllowed(*label, *op));
+        }
+    }
+    Ok(())
+}
+
+/// An extra check an embedder runs on programs after they type check, to reject ones it doesn't allow,
+/// like ones that allocate too much at once. See `Module::with_passes`.
+pub trait VerifyPass: Send + Sync {
+    /// A short name for the pass, for error messages.
+    fn name(&self) -> &str;
+
+    /// Look at a verified program (see `ir` for ways to walk it), and say why it's rejected, if it is.
+    fn check(&self, program: &IRProgram) -> Result<(), String>;
+}
+
+/// Run each pass on the program in order, stopping at the first that rejects it.
+pub fn run_passes(program: &IRProgram, passes: &[&dyn VerifyPass]) -> Result<(), Error> {
+    for pass in passes {
+        event!(Level::Trace, "running pass {}", pass.name());
+        if let Err(reason) = pass.check(program) {
+            return Err(Error::RejectedByPass(pass.name().to_string(), reason));
+        }
+    }
+    Ok(())
+}
+
+/// A pass that rejects any `malloc` or `alloca` of more than this many bytes.
+pub struct MaxAllocation(pub usize);
+
+impl VerifyPass for MaxAllocation {
+    fn name(&self) -> &str {
+        "max-allocation"
+    }
+
+    fn check(&self, program: &IRProgram) -> Result<(), String> {
+        for function in program.functions() {
+            for instr in function.instrs() {
+                if let Op2::Malloc(size) | Op2::Alloca(size) = instr.op {
+                    if *size > self.0 {
+                        return Err(format!(
+                            "function {} allocates {} bytes at offset {}, but the most allowed is {}",
+                            function.label, size, instr.offset, self.0
+                        ));
                     }
-                    Visibility::Local => {}
                 }
-                fresh_id = new_fresh_id;
             }
-            Err(e) => return Err(e),
         }
+        Ok(())
+    }
+}
+
+fn check(
+    data_section: Vec<u8>,
+    type_decs: Vec<TypeDec>,
+    types_instrs: Vec<ForwardDec>,
+    unverified_stmts: Vec<Stmt1>,
+    limits: &Limits,
+    cancel: &Cancellation,
+    trace: Option<&mut Vec<Explained>>,
+) -> Result<IRProgram, Error> {
+    let start = Instant::now();
+    let type_checks = TYPE_CHECKS.with(Cell::get);
+    let res = check_program(data_section, type_decs, types_instrs, unverified_stmts, limits, cancel, trace);
+    metrics::time(Phase::Verify, start.elapsed());
+    metrics::count(Counter::TypeChecks, TYPE_CHECKS.with(Cell::get) - type_checks);
+    match &res {
+        Ok(prog) => metrics::count(Counter::FunctionsVerified, prog.funcs.len() as u64),
+        Err(_) => metrics::count(Counter::VerifyErrors, 1),
+    }
+    res
+}
+
+fn check_program(
+    data_section: Vec<u8>,
+    type_decs: Vec<TypeDec>,
+    types_instrs: Vec<ForwardDec>,
+    unverified_stmts: Vec<Stmt1>,
+    limits: &Limits,
+    cancel: &Cancellation,
+    mut trace: Option<&mut Vec<Explained>>,
+) -> Result<IRProgram, Error> {
+    let _span = log::span(Level::Debug, module_path!(), "verify", String::new);
+    let sigs = Signatures::new(&type_decs, &types_instrs, limits, trace.as_deref_mut())?;
+    let mut verified_stmts: Vec<Stmt2> = vec![];
+    for stmt in &unverified_stmts {
+        verified_stmts.push(sigs.check_body(&data_section, stmt, cancel, trace.as_deref_mut())?);
+    }
+    sigs.program(data_section, verified_stmts)
+}
+
+/// What each function body is checked against: the named types and the types of all the functions, from the declarations.
+/// Bodies can be checked in any order, or as they arrive (see `stream`), once this is worked out.
+pub(crate) struct Signatures {
+    named: Vec<NamedType>,
+    types: HashMap<Label, Type>,
+    /// The signatures and the named types' definitions, interned in `arena` for the bodies to build on.
+    arena: TypeArena,
+    ids: HashMap<Label, TypeId>,
+    definitions: Vec<Option<TypeId>>,
+    imports: HashMap<Label, (u64, u64)>,
+    exports: HashMap<(u64, u64), Label>,
+    fresh_id: u32,
+    limits: Limits,
+}
+
+impl Signatures {
+    pub(crate) fn new(
+        type_decs: &[TypeDec],
+        types_instrs: &[ForwardDec],
+        limits: &Limits,
+        trace: Option<&mut Vec<Explained>>,
+    ) -> Result<Signatures, Error> {
+        let mut types = HashMap::new();
+        let mut ids = HashMap::new();
+        let mut interned = Types::new();
+        let mut imports = HashMap::new();
+        let mut exports = HashMap::new();
+        let named = named_types(type_decs, types_instrs.len(), limits)?;
+        let (sigs, fresh_id) = type_pass_all(&named, types_instrs, limits, trace)?;
+        for (l, vis, t) in sigs {
+            ids.insert(l, interned.intern(&t));
+            types.insert(l, t);
+            match vis {
+                Visibility::Import(a, b) => {
+                    imports.insert(l, (a, b));
+                }
+                Visibility::Export(a, b) => {
+                    exports.insert((a, b), l);
+                }
+                Visibility::Local => {}
+            }
+        }
+        let definitions = named
+            .iter()
+            .map(|named| named.definition.as_ref().map(|t| interned.intern(t)))
+            .collect();
+        Ok(Signatures {
+            named,
+            types,
+            arena: interned.finish(),
+            ids,
+            definitions,
+            imports,
+            exports,
+            fresh_id,
+            limits: *limits,
+        })
+    }
+
+    pub(crate) fn check_body(
+        &self,
+        data_section: &[u8],
+        stmt: &Stmt1,
+        cancel: &Cancellation,
+        trace: Option<&mut Vec<Explained>>,
+    ) -> Result<Stmt2, Error> {
+        let Stmt1::Func(label, _, _) = stmt;
+        let _span = log::span(Level::Trace, module_path!(), "function", || label.to_string());
+        definition_pass(data_section, stmt, self, cancel, trace)
+    }
+
+    /// The type function `label` is checked against, if it's declared.
+    pub(crate) fn signature(&self, label: Label) -> Option<&Type> {
+        self.types.get(&label)
+    }
+
+    pub(crate) fn imports(&self) -> &HashMap<Label, (u64, u64)> {
+        &self.imports
+    }
+
+    pub(crate) fn exports(&self) -> &HashMap<(u64, u64), Label> {
+        &self.exports
+    }
+
+    /// Put the checked bodies together into a program, once they've all been checked.
+    pub(crate) fn program(self, data_section: Vec<u8>, verified_stmts: Vec<Stmt2>) -> Result<IRProgram, Error> {
+        if let Some(Stmt2::Func(_, t, _, _)) = verified_stmts.first() {
+            check_entry(t)?;
+        }
+        event!(
+            Level::Debug,
+            "verified {} functions, with {} imports and {} exports",
+            verified_stmts.len(),
+            self.imports.len(),
+            self.exports.len()
+        );
+        Ok(IRProgram {
+            data_section,
+            imports: self.imports,
+            exports: self.exports,
+            funcs: verified_stmts,
+        })
     }
-    let verified_stmts: Vec<Stmt2> = unverified_stmts
-        .iter()
-        .map(|stmt| definition_pass(data_section.len(), stmt, &types, fresh_id))
-        .collect::<Result<Vec<_>, Error>>()?;
-    match verified_stmts.get(0) {
-        Some(Stmt2::Func(_, Type::Func(param_ts), _)) => {
-            if param_ts.len() != 0 {
-                return Err(Error::TypeErrorMainHasArgs);
+}
+
+/// The entry function can only take `i32`s, which the host passes in with `Instance::run_with_args`.
+pub(crate) fn check_entry(t: &Type) -> Result<(), Error> {
+    if let Type::Func(param_ts) = t {
+        if let Some(t) = param_ts.iter().find(|t| **t != Type::I32) {
+            return Err(Error::TypeErrorEntryParam(t.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// The elaborated type of every forward-declared function, in the order they're declared.
+/// This only needs the declarations, so it works even if a function body doesn't verify.
+pub fn signatures(
+    type_decs: &[TypeDec],
+    types_instrs: &[ForwardDec],
+) -> Result<Vec<(Label, Visibility, Type)>, Error> {
+    let limits = Limits::default();
+    let named = named_types(type_decs, types_instrs.len(), &limits)?;
+    let (sigs, _fresh_id) = type_pass_all(&named, types_instrs, &limits, None)?;
+    Ok(sigs)
+}
+
+/// Check the type declarations of a program with `n_funcs` functions, returning the named types in order.
+/// Each declaration starts with `size s`, so the declarations can mention any named type, themselves included,
+/// before their definitions are checked. A declaration's ops are numbered as if they came after the forward declarations,
+/// so its type variables can't be mistaken for a function's.
+pub fn named_types(type_decs: &[TypeDec], n_funcs: usize, limits: &Limits) -> Result<Vec<NamedType>, Error> {
+    let mut named = vec![];
+    for TypeDec::Type(k, visibility, ops) in type_decs {
+        let Some(Op1::Size(s)) = ops.first() else {
+            return Err(Error::TypeDeclNeedsSize(*k));
+        };
+        named.push(NamedType {
+            visibility: *visibility,
+            size: *s as usize,
+            definition: None,
+        });
+    }
+    for (i, TypeDec::Type(k, visibility, ops)) in type_decs.iter().enumerate() {
+        let label = (n_funcs as u32).saturating_add(*k);
+        let mut tracer = Tracer::new(None, label, true);
+        let (stack, _) = declaration_pass(label, ops, &named, label, limits, &mut tracer)?;
+        let size = named[i].size;
+        match (&stack[..], visibility) {
+            ([CTStackVal::Size(_)], _) => {}
+            ([CTStackVal::Size(_), CTStackVal::Type(t)], Visibility::Local | Visibility::Export(_, _)) => {
+                if t.size() != size {
+                    return Err(Error::TypeDeclSizeMismatch(*k, size, t.size()));
+                }
+                // a named type can be behind a pointer, so it can't own a region or hold a handle
+                move_only(label, Op1::Named(*k), t)?;
+                named[i].definition = Some(t.clone());
             }
+            _ => return Err(Error::TypeDeclBadStack(*k, stack)),
         }
-        _ => (),
     }
-    Ok(IRProgram {
-        data_section,
-        imports,
-        exports,
-        funcs: verified_stmts,
-    })
+    event!(Level::Debug, "checked {} named types", named.len());
+    Ok(named)
+}
+
+pub(crate) fn type_pass_all(
+    named: &[NamedType],
+    types_instrs: &[ForwardDec],
+    limits: &Limits,
+    mut trace: Option<&mut Vec<Explained>>,
+) -> Result<(Vec<(Label, Visibility, Type)>, u32), Error> {
+    let _span = log::span(Level::Debug, module_path!(), "signatures", String::new);
+    let mut sigs = vec![];
+    let mut fresh_id = 0;
+    for stmt in types_instrs {
+        let (l, vis, t, new_fresh_id) = type_pass(stmt, named, fresh_id, limits, trace.as_deref_mut())?;
+        sigs.push((l, vis, t));
+        fresh_id = new_fresh_id;
+    }
+    Ok((sigs, fresh_id))
+}
+
+/// Records the verifier's state around each instruction of one function, if anyone asked for it.
+struct Tracer<'a> {
+    trace: Option<&'a mut Vec<Explained>>,
+    label: Label,
+    forward_dec: bool,
+    before: VerifierState,
+    /// The runtime stack the last time it was resolved, and what it resolved to,
+    /// so only the types that changed since then are resolved again.
+    ids: Stack<TypeId>,
+    resolved: Stack<Type>,
+}
+
+impl<'a> Tracer<'a> {
+    fn new(trace: Option<&'a mut Vec<Explained>>, label: Label, forward_dec: bool) -> Self {
+        Tracer {
+            trace,
+            label,
+            forward_dec,
+            before: VerifierState::default(),
+            ids: Stack::new(),
+            resolved: Stack::new(),
+        }
+    }
+
+    /// The types on the runtime stack as trees, for the trace, if there is one.
+    fn resolve(&mut self, stack_type: &Stack<TypeId>, types: &Types) -> Stack<Type> {
+        if self.trace.is_none() {
+            return Stack::new();
+        }
+        let (kept, _popped, pushed) = self.ids.diverge(stack_type);
+        let pushed = pushed.into_iter().map(|t| types.resolve(*t)).collect::<Vec<_>>();
+        self.resolved.truncate(kept);
+        for t in pushed {
+            self.resolved.push(t);
+        }
+        self.ids = stack_type.clone();
+        self.resolved.clone()
+    }
+
+    /// Set the state the function's first instruction starts from.
+    fn start(&mut self, compile_time_stack: &Stack<CTStackVal>, stack_type: &Stack<Type>, rgn_vars: &[Region]) {
+        if self.trace.is_some() {
+            self.before = VerifierState {
+                compile_time_stack: compile_time_stack.clone(),
+                stack_type: stack_type.clone(),
+                rgn_vars: rgn_vars.to_vec(),
+            };
+        }
+    }
+
+    fn step(
+        &mut self,
+        pos: Pos,
+        op: Op1,
+        compile_time_stack: &Stack<CTStackVal>,
+        stack_type: &Stack<Type>,
+        rgn_vars: &[Region],
+    ) {
+        let Some(trace) = self.trace.as_deref_mut() else {
+            return;
+        };
+        let after = VerifierState {
+            compile_time_stack: compile_time_stack.clone(),
+            stack_type: stack_type.clone(),
+            rgn_vars: rgn_vars.to_vec(),
+        };
+        trace.push(Explained {
+            label: self.label,
+            forward_dec: self.forward_dec,
+            pos,
+            op,
+            before: std::mem::replace(&mut self.before, after.clone()),
+            after,
+        });
+    }
 }
 
 pub fn type_pass(
     stmt: &ForwardDec,
-    mut fresh_id: u32,
+    named: &[NamedType],
+    fresh_id: u32,
+    limits: &Limits,
+    trace: Option<&mut Vec<Explained>>,
 ) -> Result<(Label, Visibility, Type, u32), Error> {
     let ForwardDec::Func(label, visibility, ops) = stmt;
+    let mut tracer = Tracer::new(trace, *label, true);
+    let (compile_time_stack, pos) = declaration_pass(*label, ops, named, fresh_id, limits, &mut tracer)?;
+    match &compile_time_stack[..] {
+        [CTStackVal::Type(t)] => Ok((*label, *visibility, t.clone(), pos)),
+        _ => Err(Error::ForwardDeclBadStack(compile_time_stack)),
+    }
+}
+
+/// Run the compile-time ops of a forward or type declaration, returning what they leave on the compile-time stack
+/// and the position after the last op.
+fn declaration_pass(
+    label: Label,
+    ops: &[Op1],
+    named: &[NamedType],
+    mut fresh_id: u32,
+    limits: &Limits,
+    tracer: &mut Tracer,
+) -> Result<(Vec<CTStackVal>, Pos), Error> {
+    let label = &label;
     let mut next_region_is_unique = false;
-    let mut compile_time_stack: Vec<CTStackVal> = vec![];
+    let mut compile_time_stack: Stack<CTStackVal> = Stack::new();
     let mut quantification_stack: Vec<Quantification> = vec![];
+    // which component of the last `tuple_fields` the next `field` describes
+    let mut next_field = 0;
     let mut pos = *label;
     for op in ops {
+        compile_time_stack.mark();
         match op {
             Op1::Unique => next_region_is_unique = true,
             Op1::Handle => handle_handle(pos, op, &mut compile_time_stack)?,
+            Op1::BigInt => handle_bigint(pos, op, &mut compile_time_stack)?,
+            Op1::Buf => handle_buf(pos, op, &mut compile_time_stack)?,
             Op1::I32 => compile_time_stack.push(CTStackVal::Type(Type::I32)),
             Op1::Tuple(n) => handle_tuple(n, pos, op, &mut compile_time_stack)?,
+            Op1::TupleFields(n) => {
+                handle_tuple(n, pos, op, &mut compile_time_stack)?;
+                next_field = 0;
+            }
+            Op1::Field(descriptor) => handle_field(&mut next_field, *descriptor, pos, op, &mut compile_time_stack)?,
             Op1::Some => handle_some(
                 pos,
                 op,
@@ -94,51 +554,59 @@ pub fn type_pass(
                 &mut compile_time_stack,
                 &mut quantification_stack,
             )?,
+            Op1::SomeRgn => handle_some_rgn(label, &mut fresh_id, &mut compile_time_stack, &mut quantification_stack),
             Op1::End => handle_end(pos, op, &mut compile_time_stack, &mut quantification_stack)?,
             Op1::Func(n) => handle_func(n, pos, op, &mut compile_time_stack)?,
             Op1::CTGet(i) => handle_ctget(pos, i, &mut compile_time_stack)?,
-            Op1::Size(s) => compile_time_stack.push(CTStackVal::Size((*s).try_into().unwrap())),
+            Op1::Size(s) => compile_time_stack.push(CTStackVal::Size(*s as usize)),
             Op1::Ptr => handle_ptr(pos, op, &mut compile_time_stack)?,
             Op1::Arr => handle_arr(pos, op, &mut compile_time_stack)?,
+            Op1::Map => handle_map(pos, op, &mut compile_time_stack)?,
+            Op1::Res(kind) => handle_res(pos, op, *kind, &mut compile_time_stack)?,
             Op1::DataSec => compile_time_stack.push(CTStackVal::Region(Region {
                 unique: false,
                 id: DataSection,
             })),
             Op1::U8 => compile_time_stack.push(CTStackVal::Type(Type::U8)),
+            Op1::Named(k) => handle_named(pos, op, *k, named, &mut compile_time_stack)?,
             op => return Err(Error::ForwardDeclRuntimeOp(*op)),
         }
+        check_depth(limits, &compile_time_stack, &Stack::new(), &Types::new())?;
+        tracer.step(pos, *op, &compile_time_stack, &Stack::new(), &[]);
         pos += 1;
     }
-    match &compile_time_stack[..] {
-        [CTStackVal::Type(t)] => Ok((*label, *visibility, t.clone(), pos)),
-        _ => return Err(Error::ForwardDeclBadStack(compile_time_stack)),
-    }
+    Ok((compile_time_stack.to_vec(), pos))
 }
 
-pub fn definition_pass(
-    data_section_len: usize,
+pub(crate) fn definition_pass(
+    data_section: &[u8],
     stmt: &Stmt1,
-    types: &HashMap<Label, Type>,
-    mut fresh_id: u32,
+    sigs: &Signatures,
+    cancel: &Cancellation,
+    trace: Option<&mut Vec<Explained>>,
 ) -> Result<Stmt2, Error> {
+    let (named, limits) = (&sigs.named, &sigs.limits);
+    let data_section_len = data_section.len();
+    let mut fresh_id = sigs.fresh_id;
     let Stmt1::Func(label, pos, ops) = stmt;
+    let start_pos = *pos;
     let mut pos = *pos;
     let mut ops_iter = ops.iter();
 
-    let Some(my_type) = types.get(label).cloned() else {
-        panic!("Type not found for label {}", label);
+    let (Some(my_type), Some(&my_id)) = (sigs.types.get(label), sigs.ids.get(label)) else {
+        return Err(Error::SyntaxErrorLabelOutOfRange(start_pos, *label, sigs.types.len()));
     };
+    // the types this body makes go on top of the signatures, which were interned once for every body
+    let mut types = Types::on(&sigs.arena);
+    let (i32_t, u8_t) = (types.mk(Node::I32), types.mk(Node::U8));
     // The stacks used for this pass algorithm.
-    let (mut compile_time_stack, mut stack_type) = setup_verifier(&my_type)?;
+    let (mut compile_time_stack, stack_type) = setup_verifier(&types, my_id)?;
     compile_time_stack.reverse();
-    // println!("Stack type:");
-    // for t in &stack_type {
-    //     println!("- {}", t.pretty());
-    // }
     let mut quantification_stack: Vec<Quantification> = vec![];
 
     // The verified bytecode produced by this first pass.
     let mut verified_ops: Vec<Op2> = vec![];
+    let mut host_sites: Vec<HostSite> = vec![];
 
     // The list of region variables the function is quantified (polymorphic) over.
     let mut rgn_vars: Vec<Region> = vec![Region {
@@ -147,22 +615,43 @@ pub fn definition_pass(
     }];
     for ctval in &compile_time_stack {
         if let CTStackVal::Region(r) = ctval {
-            rgn_vars.push(r.clone());
+            rgn_vars.push(*r);
         }
     }
+    let mut compile_time_stack = Stack::from(compile_time_stack);
+    let mut stack_type = Stack::from(stack_type);
+
+    let mut tracer = Tracer::new(trace, *label, false);
+    let resolved = tracer.resolve(&stack_type, &types);
+    tracer.start(&compile_time_stack, &resolved, &rgn_vars);
 
     let mut next_region_is_unique = false;
+    // which component of the last `tuple_fields` the next `field` describes
+    let mut next_field = 0;
 
     loop {
-        // dbg!(&compile_time_stack.iter().map(|v| v.pretty()).collect::<Vec<_>>());
-        // dbg!(&stack_type.iter().map(|v| v.pretty()).collect::<Vec<_>>());
+        // looking at the clock on every op would be slow, and a thousand ops is still quick
+        if (pos - start_pos) % 1024 == 0 {
+            cancel.check()?;
+        }
+        compile_time_stack.mark();
+        stack_type.mark();
         match ops_iter.next() {
             None => break,
             Some(op) => match op {
                 Op1::Unique => next_region_is_unique = true,
                 Op1::Handle => handle_handle(pos, op, &mut compile_time_stack)?,
+                Op1::BigInt => handle_bigint(pos, op, &mut compile_time_stack)?,
+                Op1::Buf => handle_buf(pos, op, &mut compile_time_stack)?,
                 Op1::I32 => compile_time_stack.push(CTStackVal::Type(Type::I32)),
                 Op1::Tuple(n) => handle_tuple(n, pos, op, &mut compile_time_stack)?,
+                Op1::TupleFields(n) => {
+                    handle_tuple(n, pos, op, &mut compile_time_stack)?;
+                    next_field = 0;
+                }
+                Op1::Field(descriptor) => {
+                    handle_field(&mut next_field, *descriptor, pos, op, &mut compile_time_stack)?
+                }
                 Op1::Some => handle_some(
                     pos,
                     op,
@@ -186,36 +675,37 @@ pub fn definition_pass(
                     &mut compile_time_stack,
                     &mut quantification_stack,
                 )?,
+                Op1::SomeRgn => {
+                    handle_some_rgn(label, &mut fresh_id, &mut compile_time_stack, &mut quantification_stack)
+                }
                 Op1::End => {
                     handle_end(pos, op, &mut compile_time_stack, &mut quantification_stack)?
                 }
                 Op1::App => match compile_time_stack.pop() {
                     Some(CTStackVal::Type(t_arg)) => {
-                        let (id, s, t) = match stack_type.pop() {
-                            Some(Type::Forall(id, s, t)) => (id, s, t),
-                            Some(t) => return Err(Error::TypeErrorForallExpected(pos, *op, t)),
+                        let (id, s, t) = match pop(&types, &mut stack_type) {
+                            Some((_, &Node::Forall(id, s, t))) => (id, s, t),
+                            Some((t, _)) => return Err(Error::TypeErrorForallExpected(pos, *op, types.resolve(t))),
                             None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
                         };
                         if s != t_arg.size() {
                             return Err(Error::SizeError(pos, *op, s, t_arg.size()));
                         }
-                        let new_t =
-                            substitute_t(&*t, &HashMap::from([(id, t_arg)]), &HashMap::new());
+                        move_only(pos, *op, &t_arg)?;
+                        let t_arg = types.intern(&t_arg);
+                        let new_t = types.substitute(t, &HashMap::from([(id, t_arg)]), &HashMap::new());
                         stack_type.push(new_t);
                     }
                     Some(CTStackVal::Region(r_arg)) => {
-                        let (r, t, captured_rgns) = match stack_type.pop() {
-                            Some(Type::ForallRegion(r, t, captured_rgns)) => (r, t, captured_rgns),
-                            Some(t) => {
-                                return Err(Error::TypeErrorForallRegionExpected(pos, *op, t))
+                        let (r, t, captured_rgns) = match pop(&types, &mut stack_type) {
+                            Some((_, Node::ForallRegion(r, t, captured_rgns))) => (*r, *t, captured_rgns.clone()),
+                            Some((t, _)) => {
+                                return Err(Error::TypeErrorForallRegionExpected(pos, *op, types.resolve(t)))
                             }
                             None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
                         };
-                        if r.unique && captured_rgns.iter().any(|r2| r_arg.id == r2.id) {
-                            return Err(Error::RegionAccessError(pos, *op, r_arg));
-                        }
-                        let new_t =
-                            substitute_t(&*t, &HashMap::new(), &HashMap::from([(r.id, r_arg)]));
+                        check_region_arg(pos, *op, &r, &r_arg, &captured_rgns, &rgn_vars)?;
+                        let new_t = types.substitute(t, &HashMap::new(), &HashMap::from([(r.id, r_arg)]));
                         stack_type.push(new_t);
                     }
                     Some(ctval) => return Err(Error::KindErrorBadApp(pos, *op, ctval)),
@@ -223,127 +713,107 @@ pub fn definition_pass(
                 },
                 Op1::Func(n) => handle_func(n, pos, op, &mut compile_time_stack)?,
                 Op1::CTGet(i) => handle_ctget(pos, i, &mut compile_time_stack)?,
-                Op1::Lced => panic!("Lced should not appear in this context"),
-                Op1::Import(_, _) => panic!("Import should not appear in this context"),
-                Op1::Export(_, _) => panic!("Export should not appear in this context"),
+                Op1::Lced | Op1::Import(_, _) | Op1::Export(_, _) => return Err(Error::DeclarationOpInBody(pos, *op)),
                 Op1::Unpack => {
-                    let t = match stack_type.pop() {
-                        Some(Type::Exists(_id, _s, t)) => t,
-                        Some(t) => return Err(Error::TypeErrorExistentialExpected(pos, *op, t)),
+                    let t = match pop(&types, &mut stack_type) {
+                        Some((_, &Node::Exists(_id, _s, t))) => t,
+                        // the package's region is live again, under a fresh name, and this function owns it now
+                        Some((_, &Node::ExistsRegion(r, t))) => {
+                            let fresh = Region {
+                                unique: true,
+                                id: RgnId::Var(Id(*label, fresh_id)),
+                            };
+                            fresh_id += 1;
+                            rgn_vars.push(fresh);
+                            compile_time_stack.push(CTStackVal::Region(fresh));
+                            types.substitute(t, &HashMap::new(), &HashMap::from([(r.id, fresh)]))
+                        }
+                        Some((t, _)) => return Err(Error::TypeErrorExistentialExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    stack_type.push(*t);
+                    stack_type.push(t);
                 }
-                Op1::Get(i) => {
+                Op1::Get(i) | Op1::Share(i) => {
                     let stack_len = stack_type.len();
                     if stack_len == 0 {
                         return Err(Error::TypeErrorEmptyStack(pos, *op));
                     }
                     let i2 = usize::from(*i);
-                    if stack_len - 1 < i2 {
+                    let Some(&t) = stack_type.nth_from_top(i2) else {
                         return Err(Error::TypeErrorGetOutOfRange(pos, *i, stack_len));
+                    };
+                    let offset = stack_type.iter().take(i2).map(|t| types.size(*t)).sum();
+                    if types.owns_region(t) {
+                        return Err(Error::TypeErrorOwnsRegion(pos, *op, types.resolve(t)));
                     }
-                    let mut offset = 0;
-                    for j in 0..*i {
-                        offset += stack_type[stack_len - 1 - (j as usize)].size();
+                    // handles are affine, so copying one has to be asked for with `share`
+                    if *op != Op1::Share(*i) && types.holds_any_handle(t) {
+                        return Err(Error::TypeErrorHandleCopy(pos, *op, types.resolve(t)));
                     }
-                    let t = stack_type.get(stack_len - 1 - i2).unwrap().clone();
-                    let size = t.size();
+                    let size = types.size(t);
                     stack_type.push(t);
                     verified_ops.push(Op2::Get(offset, size));
                 }
                 Op1::Init(i) => {
                     let mb_val = stack_type.pop();
                     let mb_tpl = stack_type.pop();
-                    let f = |component_types: Vec<(bool, Type)>,
-                             g: &dyn Fn(
-                        &Type,
-                        Vec<(bool, Type)>,
-                        &mut Vec<Type>,
-                        &mut Vec<Op2>,
-                    ) -> ()| {
-                        let formal = match component_types.get(usize::from(*i)) {
-                            Some((false, formal)) => formal,
-                            Some((true, _t)) => {
-                                return Err(Error::TypeErrorDoubleInit(pos, *op, *i))
-                            }
-                            None => {
-                                return Err(Error::TypeErrorInitOutOfRange(
-                                    pos,
-                                    *i,
-                                    component_types.len(),
-                                ))
-                            }
-                        };
-                        let Some(actual) = mb_val else {
-                            return Err(Error::TypeErrorEmptyStack(pos, *op));
-                        };
-                        if type_eq(formal, &actual) {
-                            g(&actual, component_types, &mut stack_type, &mut verified_ops);
-                        } else {
-                            return Err(Error::TypeErrorInitTypeMismatch(
-                                pos,
-                                formal.clone(),
-                                actual,
-                            ));
-                        }
-                        Ok(())
-                    };
-                    match mb_tpl {
-                        Some(Type::Tuple(component_types)) => f(
-                            component_types,
-                            &|actual: &Type,
-                              mut component_types: Vec<(bool, Type)>,
-                              stack_type: &mut Vec<Type>,
-                              verified_ops: &mut Vec<Op2>| {
-                                let mut offset = 0;
-                                let tpl_size = component_types.iter().map(|(_, t)| t.size()).sum();
-                                for i2 in 0..*i {
-                                    let (_, t) = &component_types[i2 as usize];
-                                    offset += t.size();
-                                }
-                                component_types[*i as usize] = (true, actual.clone());
-                                stack_type.push(Type::Tuple(component_types));
-                                verified_ops.push(Op2::Init(offset, actual.size(), tpl_size));
-                            },
-                        )?,
-                        Some(Type::Ptr(boxed_t, r)) => {
-                            let Type::Tuple(component_types) = *boxed_t else {
-                                return Err(Error::TypeErrorTupleExpected(pos, *op, *boxed_t));
+                    // the tuple's components, and the region it's in if it's behind a pointer
+                    let (mut component_types, in_rgn) = match mb_tpl.map(|t| (t, types.node(t))) {
+                        Some((_, Node::Tuple(component_types))) => (component_types.clone(), None),
+                        Some((_, &Node::Ptr(boxed_t, r))) => {
+                            let Node::Tuple(component_types) = types.node(boxed_t) else {
+                                return Err(Error::TypeErrorTupleExpected(pos, *op, types.resolve(boxed_t)));
                             };
                             if rgn_vars.iter().all(|r2| r.id != r2.id) {
                                 return Err(Error::RegionAccessError(pos, *op, r));
                             }
-                            f(
-                                component_types,
-                                &|actual: &Type,
-                                  mut component_types: Vec<(bool, Type)>,
-                                  stack_type: &mut Vec<Type>,
-                                  verified_ops: &mut Vec<Op2>| {
-                                    let mut offset = 0;
-                                    for i2 in 0..*i {
-                                        let (_, t) = &component_types[i2 as usize];
-                                        offset += t.size();
-                                    }
-                                    component_types[*i as usize] = (true, actual.clone());
-                                    stack_type
-                                        .push(Type::Ptr(Box::new(Type::Tuple(component_types)), r));
-                                    verified_ops.push(Op2::InitIP(offset, actual.size()));
-                                },
-                            )?
+                            (component_types.clone(), Some(r))
                         }
-                        Some(t) => return Err(Error::TypeErrorTupleExpected(pos, *op, t)),
+                        Some((t, _)) => return Err(Error::TypeErrorTupleExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    let formal = match component_types.get(usize::from(*i)) {
+                        Some(Component { init: false, t: formal, .. }) => *formal,
+                        Some(Component { mutable: true, t: formal, .. }) => *formal,
+                        Some(Component { init: true, .. }) => return Err(Error::TypeErrorDoubleInit(pos, *op, *i)),
+                        None => return Err(Error::TypeErrorInitOutOfRange(pos, *i, component_types.len())),
+                    };
+                    let Some(actual) = mb_val else {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    };
+                    if !same(&types, formal, actual) {
+                        return Err(Error::TypeErrorInitTypeMismatch(
+                            pos,
+                            types.resolve(formal),
+                            types.resolve(actual),
+                        ));
+                    }
+                    let offset = component_types[..usize::from(*i)]
+                        .iter()
+                        .map(|field| types.size(field.t))
+                        .sum();
+                    let tpl_size = component_types.iter().map(|field| types.size(field.t)).sum();
+                    component_types[usize::from(*i)].init = true;
+                    let tpl = types.mk(Node::Tuple(component_types));
+                    match in_rgn {
+                        None => {
+                            stack_type.push(tpl);
+                            verified_ops.push(Op2::Init(offset, types.size(actual), tpl_size));
+                        }
+                        Some(r) => {
+                            stack_type.push(types.mk(Node::Ptr(tpl, r)));
+                            verified_ops.push(Op2::InitIP(offset, types.size(actual)));
+                        }
                     }
                 }
                 Op1::Malloc => {
                     let mb_type = compile_time_stack.pop();
                     match mb_type {
                         Some(CTStackVal::Type(Type::Ptr(t, r))) => {
-                            let r2 = match stack_type.pop() {
-                                Some(Type::Handle(r2)) => r2,
-                                Some(t) => {
-                                    return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t));
+                            let r2 = match pop(&types, &mut stack_type) {
+                                Some((_, &Node::Handle(r2))) => r2,
+                                Some((t, _)) => {
+                                    return Err(Error::TypeErrorRegionHandleExpected(pos, *op, types.resolve(t)));
                                 }
                                 None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                             };
@@ -358,10 +828,10 @@ pub fn definition_pass(
                             let size = t.size();
                             if let Type::Tuple(component_types) = t {
                                 let mut ts = vec![];
-                                for (_, t) in component_types {
-                                    ts.push((false, t));
+                                for field in component_types {
+                                    ts.push(Field { init: false, ..field });
                                 }
-                                stack_type.push(Type::Ptr(Box::new(Type::Tuple(ts)), r));
+                                stack_type.push(types.intern(&Type::Ptr(Box::new(Type::Tuple(ts)), r)));
                                 verified_ops.push(Op2::Malloc(size));
                             } else {
                                 return Err(Error::TypeErrorMallocNonTuple(pos, *op, t));
@@ -369,30 +839,30 @@ pub fn definition_pass(
                         }
                         Some(CTStackVal::Type(Type::Tuple(component_types))) => {
                             let mut ts = vec![];
-                            for (_, t) in component_types {
-                                ts.push((false, t))
+                            for field in component_types {
+                                ts.push(Field { init: false, ..field })
                             }
                             let t = Type::Tuple(ts);
                             let size = t.size();
                             if size > 4096 {
                                 return Err(Error::TooBigForStack(pos, *op, t));
                             }
-                            stack_type.push(t);
+                            stack_type.push(types.intern(&t));
                             verified_ops.push(Op2::Alloca(size));
                         }
                         Some(CTStackVal::Type(Type::Array(t, r))) => {
-                            match stack_type.pop() {
-                                Some(Type::I32) => {} // success
-                                Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                            match pop(&types, &mut stack_type) {
+                                Some((_, Node::I32)) => {} // success
+                                Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                                 None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
                             }
-                            match stack_type.pop() {
-                                Some(Type::Handle(r2)) if r2.id != r.id => {
-                                    return Err(Error::RegionError(pos, *op, r, r2))
+                            match pop(&types, &mut stack_type) {
+                                Some((_, Node::Handle(r2))) if r2.id != r.id => {
+                                    return Err(Error::RegionError(pos, *op, r, *r2))
                                 }
-                                Some(Type::Handle(_r)) => {} // success
-                                Some(t) => {
-                                    return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t))
+                                Some((_, Node::Handle(_r))) => {} // success
+                                Some((t, _)) => {
+                                    return Err(Error::TypeErrorRegionHandleExpected(pos, *op, types.resolve(t)))
                                 }
                                 None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                             }
@@ -400,121 +870,117 @@ pub fn definition_pass(
                                 return Err(Error::RegionAccessError(pos, *op, r));
                             }
                             let size = (*t).size();
-                            stack_type.push(Type::Array(t, r));
+                            stack_type.push(types.intern(&Type::Array(t, r)));
                             verified_ops.push(Op2::NewArr(size));
                         }
+                        Some(CTStackVal::Type(Type::Map(t, r))) => {
+                            let r2 = pop_dest_handle(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                            if r.id != r2.id {
+                                return Err(Error::RegionError(pos, *op, r, r2));
+                            }
+                            let size = t.size();
+                            stack_type.push(types.intern(&Type::Map(t, r)));
+                            verified_ops.push(Op2::NewMap(size));
+                        }
                         Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
                         None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
                     };
                 }
                 Op1::Proj(i) => {
-                    let mut f = |component_types: Vec<(bool, Type)>,
-                                 mut stack_type: &mut Vec<Type>,
-                                 g: &dyn Fn(
-                        &Type,
-                        usize,
-                        &mut Vec<Type>,
-                        &mut Vec<Op2>,
-                        Vec<(bool, Type)>,
-                    ) -> ()| {
-                        let s: usize = component_types.iter().map(|(_, t)| t.size()).sum();
-                        let mb_t = component_types.get(usize::from(*i)).cloned();
-                        let t = match mb_t {
-                            Some((true, t)) => t,
-                            Some((false, _)) => {
-                                return Err(Error::TypeErrorUninitializedRead(pos, *op, *i))
-                            }
-                            None => {
-                                return Err(Error::TypeErrorProjOutOfRange(
-                                    pos,
-                                    *i,
-                                    component_types.len(),
-                                ))
-                            }
-                        };
-                        g(&t, s, &mut stack_type, &mut verified_ops, component_types);
-                        Ok(())
-                    };
                     let Some(tpl) = stack_type.pop() else {
                         return Err(Error::TypeErrorEmptyStack(pos, *op));
                     };
-                    match tpl {
-                        Type::Tuple(component_types) => {
-                            f(component_types, &mut stack_type, &|t: &Type, s: usize, stack_type: &mut Vec<Type>, verified_ops: &mut Vec<Op2>, component_types: Vec<(bool, Type)>| {
-                                let mut offset = 0;
-                                for i2 in 0..*i {
-                                    let (_, t) = &component_types[i2 as usize];
-                                    offset += t.size();
-                                }
-                                stack_type.push(t.clone());
-                                verified_ops.push(Op2::Proj(offset, t.size(), s));
-                            })?;
-                        }
-                        Type::Ptr(boxed_t, r) => {
+                    // the tuple's components, and whether it's behind a pointer
+                    let (component_types, in_place) = match types.node(tpl) {
+                        Node::Tuple(component_types) => (component_types.clone(), false),
+                        &Node::Ptr(boxed_t, r) => {
                             if r.id == RgnId::DataSection {
                                 return Err(Error::ReadOnlyRegionError(pos, *op, r.id));
                             } else if rgn_vars.iter().all(|r2| r.id != r2.id) {
                                 return Err(Error::RegionAccessError(pos, *op, r));
                             }
-                            let Type::Tuple(component_types) = *boxed_t else {
-                                return Err(Error::TypeErrorTupleExpected(pos, *op, *boxed_t));
+                            let Node::Tuple(component_types) = types.node(boxed_t) else {
+                                return Err(Error::TypeErrorTupleExpected(pos, *op, types.resolve(boxed_t)));
                             };
-                            f(component_types, &mut stack_type, &|t: &Type, _s: usize, stack_type: &mut Vec<Type>, verified_ops: &mut Vec<Op2>, component_types: Vec<(bool, Type)>| {
-                                let mut offset = 0;
-                                for i2 in 0..*i {
-                                    let (_, t) = &component_types[i2 as usize];
-                                    offset += t.size();
-                                }
-                                stack_type.push(t.clone());
-                                verified_ops.push(Op2::ProjIP(offset, t.size()));
-                            })?;
+                            (component_types.clone(), true)
+                        }
+                        _ => return Err(Error::TypeErrorTupleExpected(pos, *op, types.resolve(tpl))),
+                    };
+                    let s: usize = component_types.iter().map(|field| types.size(field.t)).sum();
+                    let t = match component_types.get(usize::from(*i)) {
+                        Some(Component { init: true, t, .. }) => *t,
+                        Some(Component { init: false, .. }) => {
+                            return Err(Error::TypeErrorUninitializedRead(pos, *op, *i))
                         }
-                        t => return Err(Error::TypeErrorTupleExpected(pos, *op, t)),
+                        None => return Err(Error::TypeErrorProjOutOfRange(pos, *i, component_types.len())),
+                    };
+                    let offset = component_types[..usize::from(*i)]
+                        .iter()
+                        .map(|field| types.size(field.t))
+                        .sum();
+                    stack_type.push(t);
+                    if in_place {
+                        verified_ops.push(Op2::ProjIP(offset, types.size(t)));
+                    } else {
+                        verified_ops.push(Op2::Proj(offset, types.size(t), s));
                     }
                 }
                 Op1::Call => {
                     let Some(t) = stack_type.pop() else {
                         return Err(Error::TypeErrorEmptyStack(pos, *op));
                     };
-                    handle_call(pos, &t, &mut stack_type, &mut compile_time_stack, Op1::Call)?;
+                    handle_call(
+                        pos,
+                        &mut types,
+                        t,
+                        &mut stack_type,
+                        &mut compile_time_stack,
+                        &rgn_vars,
+                        Op1::Call,
+                    )?;
                     verified_ops.push(Op2::Call)
                 }
-                // Op1::Print => {
-                //     panic!("op `print` no longer supported. Use `write` instead, targetting the console.");
-                    // Some(Type::Array(t, r)) => {
-                    //     if *t != Type::U8 {
-                    //         return Err(Error::TypeError(pos, *op, Type::U8, *t));
-                    //     }
-                    //     if rgn_vars.iter().all(|r2| r.id != r2.id) {
-                    //         return Err(Error::RegionAccessError(pos, *op, r));
-                    //     }
-                    //     verified_ops.push(Op2::Print);
-                    // }
-                    // Some(t) => return Err(Error::TypeErrorArrayExpected(pos, *op, t)),
-                    // None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
-                // }
                 Op1::Lit(lit) => {
-                    stack_type.push(Type::I32);
+                    stack_type.push(i32_t);
                     verified_ops.push(Op2::Lit(*lit))
                 }
                 Op1::GlobalFunc(label) => {
-                    let t = types
+                    let t = sigs
+                        .ids
                         .get(label)
                         .ok_or(Error::UnknownGlobalFunc(pos, *op, *label))?;
-                    stack_type.push(t.clone());
+                    stack_type.push(*t);
                     verified_ops.push(Op2::GlobalFunc(*label))
                 }
-                Op1::Halt => match stack_type.pop() {
-                    Some(Type::U8) => verified_ops.push(Op2::Halt),
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
+                Op1::Halt => match pop(&types, &mut stack_type) {
+                    Some((_, Node::U8)) => verified_ops.push(Op2::Halt),
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::U8, types.resolve(t))),
                     None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
+                // packing a region rather than a type
+                Op1::Pack if matches!(compile_time_stack.last(), Some(CTStackVal::Region(_))) => {
+                    let Some(type_of_hidden) = stack_type.pop() else {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    };
+                    let t = pack_region(
+                        pos,
+                        *op,
+                        &mut types,
+                        type_of_hidden,
+                        &mut compile_time_stack,
+                        &mut rgn_vars,
+                    )?;
+                    stack_type.push(t);
+                }
                 Op1::Pack => {
                     let Some(type_of_hidden) = stack_type.pop() else {
                         return Err(Error::TypeErrorEmptyStack(pos, *op));
                     };
                     let hidden_type = match compile_time_stack.pop() {
-                        Some(CTStackVal::Type(t)) => t,
+                        Some(CTStackVal::Type(t)) => {
+                            move_only(pos, *op, &t)?;
+                            t
+                        }
                         Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
                         None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
                     };
@@ -530,26 +996,32 @@ pub fn definition_pass(
                         Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
                         None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
                     };
-                    // dbg!(&type_of_hidden.pretty());
                     if size_of_hidden != hidden_type.size() {
                         return Err(Error::SizeError(
                             pos,
                             *op,
                             size_of_hidden,
-                            type_of_hidden.size(),
+                            types.size(type_of_hidden),
                         ));
                     }
-                    let unpacked_type = substitute_t(
-                        &existential_type,
+                    let existential_type = types.intern(&existential_type);
+                    let hidden_type = types.intern(&hidden_type);
+                    let unpacked_type = types.substitute(
+                        existential_type,
                         &HashMap::from([(id, hidden_type)]),
                         &HashMap::new(),
                     );
-                    if !type_eq(&type_of_hidden, &unpacked_type) {
-                        return Err(Error::TypeError(pos, *op, unpacked_type, type_of_hidden));
+                    if !same(&types, type_of_hidden, unpacked_type) {
+                        return Err(Error::TypeError(
+                            pos,
+                            *op,
+                            types.resolve(unpacked_type),
+                            types.resolve(type_of_hidden),
+                        ));
                     }
-                    stack_type.push(Type::Exists(id, size_of_hidden, existential_type));
+                    stack_type.push(types.mk(Node::Exists(id, size_of_hidden, existential_type)));
                 }
-                Op1::Size(s) => compile_time_stack.push(CTStackVal::Size((*s).try_into().unwrap())),
+                Op1::Size(s) => compile_time_stack.push(CTStackVal::Size(*s as usize)),
                 Op1::NewRgn(size) => {
                     let id = Id(*label, fresh_id);
                     fresh_id += 1;
@@ -557,15 +1029,15 @@ pub fn definition_pass(
                         unique: true,
                         id: RgnId::Var(id),
                     };
-                    rgn_vars.push(r.clone());
-                    stack_type.push(Type::Handle(r.clone()));
+                    rgn_vars.push(r);
+                    stack_type.push(types.mk(Node::Handle(r)));
                     compile_time_stack.push(CTStackVal::Region(r));
-                    verified_ops.push(Op2::NewRgn((*size).try_into().unwrap()));
+                    verified_ops.push(Op2::NewRgn(*size as usize));
                 }
                 Op1::FreeRgn => {
-                    let r = match stack_type.pop() {
-                        Some(Type::Handle(r)) => r,
-                        Some(t) => return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t)),
+                    let r = match pop(&types, &mut stack_type) {
+                        Some((_, &Node::Handle(r))) => r,
+                        Some((t, _)) => return Err(Error::TypeErrorRegionHandleExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
                     match rgn_vars.iter().find(|r2| r.id == r2.id) {
@@ -578,133 +1050,192 @@ pub fn definition_pass(
                 }
                 Op1::Ptr => handle_ptr(pos, op, &mut compile_time_stack)?,
                 Op1::Deref => {
-                    let (t, r) = match stack_type.pop() {
-                        Some(Type::Ptr(t, r)) => (t, r),
-                        Some(t) => return Err(Error::TypeErrorPtrExpected(pos, *op, t)),
+                    let (t, r) = match pop(&types, &mut stack_type) {
+                        Some((_, &Node::Ptr(t, r))) => (t, r),
+                        Some((t, _)) => return Err(Error::TypeErrorPtrExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
                     if rgn_vars.iter().all(|r2| r.id != r2.id) {
                         return Err(Error::RegionAccessError(pos, *op, r));
                     }
-                    let size = t.size();
+                    let size = types.size(t);
                     if size > 4096 {
-                        return Err(Error::TooBigForStack(pos, *op, *t));
+                        return Err(Error::TooBigForStack(pos, *op, types.resolve(t)));
                     }
-                    stack_type.push(*t);
+                    stack_type.push(t);
                     verified_ops.push(Op2::Deref(size));
                 }
                 Op1::Arr => handle_arr(pos, op, &mut compile_time_stack)?,
+                Op1::Map => handle_map(pos, op, &mut compile_time_stack)?,
+                Op1::Res(kind) => handle_res(pos, op, *kind, &mut compile_time_stack)?,
                 Op1::ArrMut => {
-                    match stack_type.pop() {
-                        Some(Type::I32) => {} // success
-                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
                     let Some(t) = stack_type.pop() else {
                         return Err(Error::TypeErrorEmptyStack(pos, *op));
                     };
-                    let r = match stack_type.pop() {
-                        Some(Type::Array(_, r)) if r.id == DataSection => {
+                    let r = match pop(&types, &mut stack_type) {
+                        Some((_, Node::Array(_, r))) if r.id == DataSection => {
                             return Err(Error::CannotMutateDataSection(pos, *op));
                         }
-                        Some(Type::Array(t2, r)) if type_eq(&t, &t2) => r,
-                        Some(Type::Array(t2, _)) => return Err(Error::TypeError(pos, *op, t, *t2)),
-                        Some(t) => return Err(Error::TypeErrorArrayExpected(pos, *op, t)),
+                        Some((_, &Node::Array(t2, r))) if same(&types, t, t2) => r,
+                        Some((_, &Node::Array(t2, _))) => {
+                            return Err(Error::TypeError(pos, *op, types.resolve(t), types.resolve(t2)))
+                        }
+                        Some((t, _)) => return Err(Error::TypeErrorArrayExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
                     if rgn_vars.iter().all(|r2| r2.id != r.id) {
                         return Err(Error::RegionAccessError(pos, *op, r));
                     }
-                    let size = t.size();
-                    stack_type.push(Type::Array(Box::new(t), r));
+                    let size = types.size(t);
+                    stack_type.push(types.mk(Node::Array(t, r)));
                     verified_ops.push(Op2::ArrMut(size))
                 }
                 Op1::ArrProj => {
-                    match stack_type.pop() {
-                        Some(Type::I32) => {} // success
-                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    let (t, r) = match stack_type.pop() {
-                        Some(Type::Array(t, r)) => (t, r),
-                        Some(t) => return Err(Error::TypeErrorArrayExpected(pos, *op, t)),
+                    let (t, r) = match pop(&types, &mut stack_type) {
+                        Some((_, &Node::Array(t, r))) => (t, r),
+                        Some((t, _)) => return Err(Error::TypeErrorArrayExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
                     if rgn_vars.iter().all(|r2| r2.id != r.id) {
                         return Err(Error::RegionAccessError(pos, *op, r));
                     }
-                    let t = *t;
-                    stack_type.push(t.clone());
+                    stack_type.push(t);
                     if r.id == DataSection {
-                        verified_ops.push(Op2::DataIndex(t.size()))
+                        verified_ops.push(Op2::DataIndex(types.size(t)))
                     } else {
-                        verified_ops.push(Op2::ArrProj(t.size()))
+                        verified_ops.push(Op2::ArrProj(types.size(t)))
                     }
                 }
-                Op1::Add => match stack_type.pop() {
-                    Some(Type::I32) => {
-                        match stack_type.pop() {
-                            Some(Type::I32) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                Op1::Add | Op1::AddTrap => match pop(&types, &mut stack_type) {
+                    Some((_, Node::I32)) => {
+                        match pop(&types, &mut stack_type) {
+                            Some((_, Node::I32)) => {} // success
+                            Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                             None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         };
-                        stack_type.push(Type::I32);
-                        verified_ops.push(Op2::AddI32);
+                        stack_type.push(i32_t);
+                        verified_ops.push(Op2::AddI32(overflow(op)));
                     }
-                    Some(Type::U8) => {
-                        match stack_type.pop() {
-                            Some(Type::U8) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
+                    Some((_, Node::U8)) => {
+                        match pop(&types, &mut stack_type) {
+                            Some((_, Node::U8)) => {} // success
+                            Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::U8, types.resolve(t))),
                             None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         };
-                        stack_type.push(Type::U8);
-                        verified_ops.push(Op2::AddU8);
+                        stack_type.push(u8_t);
+                        verified_ops.push(Op2::AddU8(overflow(op)));
                     }
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                     None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
-                Op1::Mul => match stack_type.pop() {
-                    Some(Type::I32) => {
-                        match stack_type.pop() {
-                            Some(Type::I32) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                Op1::Sub | Op1::SubTrap => match pop(&types, &mut stack_type) {
+                    Some((_, Node::I32)) => {
+                        match pop(&types, &mut stack_type) {
+                            Some((_, Node::I32)) => {} // success
+                            Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                            None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                        };
+                        stack_type.push(i32_t);
+                        verified_ops.push(Op2::SubI32(overflow(op)));
+                    }
+                    Some((_, Node::U8)) => {
+                        match pop(&types, &mut stack_type) {
+                            Some((_, Node::U8)) => {} // success
+                            Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::U8, types.resolve(t))),
+                            None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                        };
+                        stack_type.push(u8_t);
+                        verified_ops.push(Op2::SubU8(overflow(op)));
+                    }
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                },
+                Op1::AddSat
+                | Op1::SubSat
+                | Op1::MulSat
+                | Op1::AddChecked
+                | Op1::SubChecked
+                | Op1::MulChecked => {
+                    let arith = match op {
+                        Op1::AddSat | Op1::AddChecked => ArithOp::Add,
+                        Op1::SubSat | Op1::SubChecked => ArithOp::Sub,
+                        _ => ArithOp::Mul,
+                    };
+                    let checked = matches!(op, Op1::AddChecked | Op1::SubChecked | Op1::MulChecked);
+                    let t = match pop(&types, &mut stack_type) {
+                        Some((t, Node::I32 | Node::U8)) => t,
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    };
+                    match stack_type.pop() {
+                        Some(t2) if same(&types, t, t2) => {} // success
+                        Some(t2) => return Err(Error::TypeError(pos, *op, types.resolve(t), types.resolve(t2))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    verified_ops.push(match (types.node(t), checked) {
+                        (Node::I32, false) => Op2::SatI32(arith),
+                        (Node::I32, true) => Op2::CheckedI32(arith),
+                        (_, false) => Op2::SatU8(arith),
+                        (_, true) => Op2::CheckedU8(arith),
+                    });
+                    stack_type.push(t);
+                    if checked {
+                        // whether the result fit
+                        stack_type.push(i32_t);
+                    }
+                }
+                Op1::Mul | Op1::MulTrap => match pop(&types, &mut stack_type) {
+                    Some((_, Node::I32)) => {
+                        match pop(&types, &mut stack_type) {
+                            Some((_, Node::I32)) => {} // success
+                            Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                             None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         }
-                        stack_type.push(Type::I32);
-                        verified_ops.push(Op2::MulI32);
+                        stack_type.push(i32_t);
+                        verified_ops.push(Op2::MulI32(overflow(op)));
                     }
-                    Some(Type::U8) => {
-                        match stack_type.pop() {
-                            Some(Type::U8) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
+                    Some((_, Node::U8)) => {
+                        match pop(&types, &mut stack_type) {
+                            Some((_, Node::U8)) => {} // success
+                            Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::U8, types.resolve(t))),
                             None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         }
-                        stack_type.push(Type::U8);
-                        verified_ops.push(Op2::MulU8);
+                        stack_type.push(u8_t);
+                        verified_ops.push(Op2::MulU8(overflow(op)));
                     }
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                     None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
-                Op1::Div => match stack_type.pop() {
-                    Some(Type::I32) => {
-                        match stack_type.pop() {
-                            Some(Type::I32) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                Op1::Div | Op1::DivTrap => match pop(&types, &mut stack_type) {
+                    Some((_, Node::I32)) => {
+                        match pop(&types, &mut stack_type) {
+                            Some((_, Node::I32)) => {} // success
+                            Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                             None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         }
-                        stack_type.push(Type::I32);
-                        verified_ops.push(Op2::DivI32);
+                        stack_type.push(i32_t);
+                        verified_ops.push(Op2::DivI32(overflow(op)));
                     }
-                    Some(Type::U8) => {
-                        match stack_type.pop() {
-                            Some(Type::U8) => {} // success
-                            Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
+                    Some((_, Node::U8)) => {
+                        match pop(&types, &mut stack_type) {
+                            Some((_, Node::U8)) => {} // success
+                            Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::U8, types.resolve(t))),
                             None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         }
-                        stack_type.push(Type::U8);
+                        stack_type.push(u8_t);
                         verified_ops.push(Op2::DivU8);
                     }
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                     None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
                 Op1::CallNZ => {
@@ -712,28 +1243,38 @@ pub fn definition_pass(
                         return Err(Error::TypeErrorEmptyStack(pos, *op));
                     };
                     match stack_type.pop() {
-                        Some(t2) if type_eq(&t1, &t2) => {} // success
-                        Some(t2) => return Err(Error::TypeError(pos, *op, t1, t2)),
+                        Some(t2) if same(&types, t1, t2) => {} // success
+                        Some(t2) => return Err(Error::TypeError(pos, *op, types.resolve(t1), types.resolve(t2))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    match stack_type.pop() {
-                        Some(Type::I32) => {} // success
-                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
                     handle_call(
                         pos,
-                        &t1,
+                        &mut types,
+                        t1,
                         &mut stack_type,
                         &mut compile_time_stack,
+                        &rgn_vars,
                         Op1::CallNZ,
                     )?;
                     verified_ops.push(Op2::CallNZ);
                 }
                 Op1::Data(loc) => match compile_time_stack.pop() {
                     Some(CTStackVal::Type(Type::Array(t, r))) if r.id == DataSection => {
+                        // the host never saw these bytes, so they can't be its resources
+                        if holds_resource(&t) {
+                            return Err(Error::InvalidDataSectionType(pos, *op, Type::Array(t, r)));
+                        }
                         let loc = *loc as usize;
-                        stack_type.push(Type::Array(t, r.clone()));
+                        // the array runs to the end of the data section, so it can be empty but can't start past it
+                        if loc > data_section_len {
+                            return Err(Error::DataSectionLoadOutOfBounds(pos, *op, loc, data_section_len));
+                        }
+                        stack_type.push(types.intern(&Type::Array(t, r)));
                         verified_ops.push(Op2::Data(loc));
                     }
                     Some(CTStackVal::Type(t)) => {
@@ -748,13 +1289,13 @@ pub fn definition_pass(
                                     data_section_len,
                                 ));
                             }
-                            stack_type.push(Type::Ptr(
+                            stack_type.push(types.intern(&Type::Ptr(
                                 Box::new(t),
                                 Region {
                                     unique: false,
                                     id: DataSection,
                                 },
-                            ));
+                            )));
                             verified_ops.push(Op2::Data(loc));
                         } else {
                             return Err(Error::InvalidDataSectionType(pos, *op, t.clone()));
@@ -763,6 +1304,145 @@ pub fn definition_pass(
                     Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
                     None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
                 },
+                Op1::BigData(loc) => {
+                    let loc = *loc as usize;
+                    let Some(bytes) = data_section.get(loc..) else {
+                        return Err(Error::DataSectionLoadOutOfBounds(pos, *op, loc, data_section_len));
+                    };
+                    if bigint::literal_len(bytes).is_none() {
+                        return Err(Error::BadBigIntLiteral(pos, *op, loc));
+                    }
+                    stack_type.push(types.mk(Node::BigInt(Region {
+                        unique: false,
+                        id: DataSection,
+                    })));
+                    verified_ops.push(Op2::BigData(loc));
+                }
+                Op1::BigFromI32 => {
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    let r = pop_dest_handle(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    stack_type.push(types.mk(Node::BigInt(r)));
+                    verified_ops.push(Op2::BigFromI32);
+                }
+                Op1::BigAdd | Op1::BigSub | Op1::BigMul | Op1::BigDiv | Op1::BigModulo => {
+                    pop_bigint(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    pop_bigint(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    let r = pop_dest_handle(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    stack_type.push(types.mk(Node::BigInt(r)));
+                    verified_ops.push(match op {
+                        Op1::BigAdd => Op2::BigAdd,
+                        Op1::BigSub => Op2::BigSub,
+                        Op1::BigMul => Op2::BigMul,
+                        Op1::BigDiv => Op2::BigDiv,
+                        _ => Op2::BigModulo,
+                    });
+                }
+                Op1::BigCmp => {
+                    pop_bigint(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    pop_bigint(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    stack_type.push(i32_t);
+                    verified_ops.push(Op2::BigCmp);
+                }
+                Op1::BigToI32 => {
+                    pop_bigint(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    stack_type.push(i32_t);
+                    verified_ops.push(Op2::BigToI32);
+                }
+                Op1::BufNew => {
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    let r = pop_dest_handle(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    stack_type.push(types.mk(Node::Buffer(r)));
+                    verified_ops.push(Op2::BufNew);
+                }
+                Op1::BufLen => {
+                    pop_buffer(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    stack_type.push(i32_t);
+                    verified_ops.push(Op2::BufLen);
+                }
+                Op1::BufGetU8 | Op1::BufGetU32 => {
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    pop_buffer(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    if *op == Op1::BufGetU8 {
+                        stack_type.push(u8_t);
+                        verified_ops.push(Op2::BufGetU8);
+                    } else {
+                        stack_type.push(i32_t);
+                        verified_ops.push(Op2::BufGetU32);
+                    }
+                }
+                Op1::BufSetU8 | Op1::BufSetU32 => {
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    let val = if *op == Op1::BufSetU8 { u8_t } else { i32_t };
+                    match stack_type.pop() {
+                        Some(t) if t == val => {} // success
+                        Some(t) => return Err(Error::TypeError(pos, *op, types.resolve(val), types.resolve(t))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    let r = pop_buffer(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    stack_type.push(types.mk(Node::Buffer(r)));
+                    verified_ops.push(if *op == Op1::BufSetU8 { Op2::BufSetU8 } else { Op2::BufSetU32 });
+                }
+                Op1::MapSet => {
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    let Some(val) = stack_type.pop() else {
+                        return Err(Error::TypeErrorEmptyStack(pos, *op));
+                    };
+                    let (t, r) = pop_map(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    if !same(&types, val, t) {
+                        return Err(Error::TypeError(pos, *op, types.resolve(t), types.resolve(val)));
+                    }
+                    let size = types.size(t);
+                    stack_type.push(types.mk(Node::Map(t, r)));
+                    verified_ops.push(Op2::MapSet(size));
+                }
+                Op1::MapGet | Op1::MapHas | Op1::MapRemove => {
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    let (t, r) = pop_map(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    let size = types.size(t);
+                    match op {
+                        Op1::MapGet => {
+                            stack_type.push(t);
+                            verified_ops.push(Op2::MapGet(size));
+                        }
+                        Op1::MapHas => {
+                            stack_type.push(i32_t);
+                            verified_ops.push(Op2::MapHas(size));
+                        }
+                        _ => {
+                            stack_type.push(types.mk(Node::Map(t, r)));
+                            verified_ops.push(Op2::MapRemove(size));
+                        }
+                    }
+                }
+                Op1::MapLen => {
+                    pop_map(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    stack_type.push(i32_t);
+                    verified_ops.push(Op2::MapLen);
+                }
                 Op1::DataSec => {
                     compile_time_stack.push(CTStackVal::Region(Region {
                         unique: false,
@@ -772,23 +1452,42 @@ pub fn definition_pass(
                 Op1::U8 => {
                     compile_time_stack.push(CTStackVal::Type(Type::U8));
                 }
-                Op1::CopyN => {
+                Op1::Named(k) => handle_named(pos, op, *k, named, &mut compile_time_stack)?,
+                Op1::Fold(k) => {
+                    let (size, definition) = definition_of(pos, op, *k, sigs)?;
                     match stack_type.pop() {
-                        Some(Type::I32) => {} // success
-                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                        Some(t) if same(&types, definition, t) => stack_type.push(types.mk(Node::Named(*k, size))),
+                        Some(t) => {
+                            return Err(Error::TypeError(pos, *op, types.resolve(definition), types.resolve(t)))
+                        }
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                }
+                Op1::Unfold => match pop(&types, &mut stack_type) {
+                    Some((_, &Node::Named(k, _))) => {
+                        let (_, definition) = definition_of(pos, op, k, sigs)?;
+                        stack_type.push(definition);
+                    }
+                    Some((t, _)) => return Err(Error::TypeErrorNamedTypeExpected(pos, *op, types.resolve(t))),
+                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                },
+                Op1::CopyN => {
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    let (t, r) = match stack_type.pop() {
-                        Some(Type::Array(t, r)) => (t, r),
-                        Some(t) => return Err(Error::TypeErrorArrayExpected(pos, *op, t)),
+                    let (arr, t, r) = match pop(&types, &mut stack_type) {
+                        Some((arr, &Node::Array(t, r))) => (arr, t, r),
+                        Some((t, _)) => return Err(Error::TypeErrorArrayExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    let r2 = match stack_type.pop() {
-                        Some(Type::Array(t2, r2)) if type_eq(&t, &t2) => r2,
-                        Some(Type::Array(t2, _)) => {
-                            return Err(Error::TypeError(pos, *op, *t, *t2))
+                    let r2 = match pop(&types, &mut stack_type) {
+                        Some((_, &Node::Array(t2, r2))) if same(&types, t, t2) => r2,
+                        Some((_, &Node::Array(t2, _))) => {
+                            return Err(Error::TypeError(pos, *op, types.resolve(t), types.resolve(t2)))
                         }
-                        Some(t) => return Err(Error::TypeErrorArrayExpected(pos, *op, t)),
+                        Some((t, _)) => return Err(Error::TypeErrorArrayExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
                     if r2.id == DataSection {
@@ -800,148 +1499,263 @@ pub fn definition_pass(
                     if rgn_vars.iter().all(|r| r.id != r2.id) {
                         return Err(Error::RegionAccessError(pos, *op, r2));
                     }
-                    verified_ops.push(Op2::CopyN(t.size()));
-                    stack_type.push(Type::Array(t, r));
+                    verified_ops.push(Op2::CopyN(types.size(t)));
+                    stack_type.push(arr);
                 }
                 Op1::U8Lit(n) => {
-                    stack_type.push(Type::U8);
+                    stack_type.push(u8_t);
                     verified_ops.push(Op2::U8Lit(*n));
                 }
-                Op1::U8ToI32 => match stack_type.pop() {
-                    Some(Type::U8) => {
-                        stack_type.push(Type::I32);
+                Op1::U8ToI32 => match pop(&types, &mut stack_type) {
+                    Some((_, Node::U8)) => {
+                        stack_type.push(i32_t);
                         verified_ops.push(Op2::U8ToI32);
                     }
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::U8, types.resolve(t))),
                     None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
-                Op1::Modulo => match stack_type.pop() {
-                    Some(Type::I32) => match stack_type.pop() {
-                        Some(Type::I32) => {
-                            stack_type.push(Type::I32);
-                            verified_ops.push(Op2::ModuloI32);
+                Op1::Modulo | Op1::ModuloTrap => match pop(&types, &mut stack_type) {
+                    Some((_, Node::I32)) => match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {
+                            stack_type.push(i32_t);
+                            verified_ops.push(Op2::ModuloI32(overflow(op)));
                         }
-                        Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     },
-                    Some(Type::U8) => match stack_type.pop() {
-                        Some(Type::U8) => {
-                            stack_type.push(Type::U8);
+                    Some((_, Node::U8)) => match pop(&types, &mut stack_type) {
+                        Some((_, Node::U8)) => {
+                            stack_type.push(u8_t);
                             verified_ops.push(Op2::ModuloU8);
                         }
-                        Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::U8, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     },
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                     None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
-                Op1::I32ToU8 => match stack_type.pop() {
-                    Some(Type::I32) => {
-                        stack_type.push(Type::U8);
+                Op1::I32ToU8 => match pop(&types, &mut stack_type) {
+                    Some((_, Node::I32)) => {
+                        stack_type.push(u8_t);
                         verified_ops.push(Op2::I32ToU8);
                     }
-                    Some(t) => return Err(Error::TypeError(pos, *op, Type::I32, t)),
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
                     None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                 },
                 Op1::Read(c) => {
-                    let t = match c {
-                        0 => match stack_type.pop() {
-                            Some(Type::Handle(r)) => Type::Array(Box::new(Type::U8), r),
-                            Some(t) => {
-                                return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t))
+                    let r = match c {
+                        0 => match pop(&types, &mut stack_type) {
+                            Some((_, &Node::Handle(r))) => r,
+                            Some((t, _)) => {
+                                return Err(Error::TypeErrorRegionHandleExpected(pos, *op, types.resolve(t)))
                             }
                             None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         },
                         _ => return Err(Error::UnknownChannel(pos, *op, *c)),
                     };
-                    let (a, body) = match stack_type.pop() {
-                        Some(Type::Exists(a, 16, body)) => (a, body),
-                        Some(t) => return Err(Error::TypeErrorExistentialExpected(pos, *op, t)),
+                    let (a, body) = match pop(&types, &mut stack_type) {
+                        Some((_, &Node::Exists(a, 16, body))) => (a, body),
+                        Some((t, _)) => return Err(Error::TypeErrorExistentialExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    let body2 = Type::Tuple(vec![
-                        (true, Type::Func(vec![t, Type::Var(a, 16)])),
-                        (true, Type::Var(a, 16)),
-                    ]);
-                    if type_eq(&*body, &body2) {
+                    // the handler gets the bytes as a u8 array or as a buffer, which are laid out the same
+                    let handler = |t: Type| {
+                        Type::Tuple(vec![
+                            Field::new(Type::Func(vec![t, Type::Var(a, 16)])),
+                            Field::new(Type::Var(a, 16)),
+                        ])
+                    };
+                    let body2 = handler(Type::Array(Box::new(Type::U8), r));
+                    let (arr_handler, buf_handler) = (types.intern(&body2), types.intern(&handler(Type::Buffer(r))));
+                    if same(&types, body, arr_handler) || same(&types, body, buf_handler) {
                         verified_ops.push(Op2::Read(*c));
                     } else {
-                        return Err(Error::TypeError(pos, *op, body2, *body));
+                        return Err(Error::TypeError(pos, *op, body2, types.resolve(body)));
                     }
                 }
                 Op1::Write(c) => {
-                    let t = match c {
-                        0 => match stack_type.pop() {
-                            Some(Type::Handle(r)) => Type::Array(Box::new(Type::U8), r),
-                            Some(t) => {
-                                return Err(Error::TypeErrorRegionHandleExpected(pos, *op, t))
+                    let r = match c {
+                        0 => match pop(&types, &mut stack_type) {
+                            Some((_, &Node::Handle(r))) => r,
+                            Some((t, _)) => {
+                                return Err(Error::TypeErrorRegionHandleExpected(pos, *op, types.resolve(t)))
                             }
                             None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         },
                         _ => return Err(Error::UnknownChannel(pos, *op, *c)),
                     };
-                    match stack_type.pop() {
-                        Some(Type::U8) => {} // success
-                        Some(t) => return Err(Error::TypeError(pos, *op, Type::U8, t)),
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::U8)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::U8, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
-                    let (a, body) = match stack_type.pop() {
-                        Some(Type::Exists(a, 16, body)) => (a, body),
-                        Some(t) => return Err(Error::TypeErrorExistentialExpected(pos, *op, t)),
+                    let (a, body) = match pop(&types, &mut stack_type) {
+                        Some((_, &Node::Exists(a, 16, body))) => (a, body),
+                        Some((t, _)) => return Err(Error::TypeErrorExistentialExpected(pos, *op, types.resolve(t))),
                         None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                     };
                     let body2 = Type::Tuple(vec![
-                        (true, Type::Func(vec![Type::Var(a, 16)])),
-                        (true, Type::Var(a, 16)),
+                        Field::new(Type::Func(vec![Type::Var(a, 16)])),
+                        Field::new(Type::Var(a, 16)),
                     ]);
-                    if type_eq(&*body, &body2) {
+                    let t = Type::Array(Box::new(Type::U8), r);
+                    let (handler, arr, buf) = (
+                        types.intern(&body2),
+                        types.intern(&t),
+                        types.mk(Node::Buffer(r)),
+                    );
+                    if same(&types, body, handler) {
                         match stack_type.pop() {
-                            Some(t2) if type_eq(&t, &t2) => {
+                            // a buffer is laid out like a u8 array, so it can be written too
+                            Some(t2) if same(&types, arr, t2) || same(&types, buf, t2) => {
                                 verified_ops.push(Op2::Write(*c));
                             }
-                            Some(t2) => return Err(Error::TypeError(pos, *op, t, t2)),
+                            Some(t2) => return Err(Error::TypeError(pos, *op, t, types.resolve(t2))),
                             None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
                         }
                     } else {
-                        return Err(Error::TypeError(pos, *op, body2, *body));
+                        return Err(Error::TypeError(pos, *op, body2, types.resolve(body)));
                     }
                 }
+                Op1::Nop => verified_ops.push(Op2::Nop),
+                Op1::Marker(n) => verified_ops.push(Op2::Marker(*n)),
+                Op1::Yield => match pop(&types, &mut stack_type) {
+                    // the host hands back an i32 when it resumes the program
+                    Some((_, Node::I32)) => {
+                        stack_type.push(i32_t);
+                        verified_ops.push(Op2::Yield);
+                    }
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                },
+                Op1::HostCall(f) => match pop(&types, &mut stack_type) {
+                    // which host functions exist is up to the embedder, so that's checked at runtime
+                    Some((_, Node::I32)) => {
+                        host_sites.push(HostSite {
+                            op: verified_ops.len(),
+                            host_fn: *f,
+                            stack: stack_type.to_vec().into_iter().map(|t| types.resolve(t)).collect(),
+                            regions: rgn_vars.iter().map(|r| r.id).collect(),
+                        });
+                        stack_type.push(i32_t);
+                        verified_ops.push(Op2::HostCall(*f));
+                    }
+                    Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                    None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                },
+                Op1::HostRes(f) => {
+                    let (kind, r) = match compile_time_stack.pop() {
+                        Some(CTStackVal::Type(Type::Resource(kind, r))) => (kind, r),
+                        Some(CTStackVal::Type(t)) => return Err(Error::TypeErrorResourceExpected(pos, *op, t)),
+                        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                    };
+                    match pop(&types, &mut stack_type) {
+                        Some((_, Node::I32)) => {} // success
+                        Some((t, _)) => return Err(Error::TypeError(pos, *op, Type::I32, types.resolve(t))),
+                        None => return Err(Error::TypeErrorEmptyStack(pos, *op)),
+                    }
+                    // the resource is made in the region before the host is asked for it, so the handle goes first
+                    let r2 = pop_dest_handle(pos, op, &types, &mut stack_type, &rgn_vars)?;
+                    if r.id != r2.id {
+                        return Err(Error::RegionError(pos, *op, r, r2));
+                    }
+                    host_sites.push(HostSite {
+                        op: verified_ops.len(),
+                        host_fn: *f,
+                        stack: stack_type.to_vec().into_iter().map(|t| types.resolve(t)).collect(),
+                        regions: rgn_vars.iter().map(|r| r.id).collect(),
+                    });
+                    stack_type.push(types.mk(Node::Resource(kind, r)));
+                    verified_ops.push(Op2::HostRes(*f, kind));
+                }
             },
         }
+        check_depth(limits, &compile_time_stack, &stack_type, &types)?;
+        let op = ops[(pos - start_pos) as usize];
+        let resolved = tracer.resolve(&stack_type, &types);
+        tracer.step(pos, op, &compile_time_stack, &resolved, &rgn_vars);
         pos += 1;
     }
-    if quantification_stack.len() > 0 {
+    if !quantification_stack.is_empty() {
         return Err(Error::TypeErrorNonEmptyQuantificationStack(*label));
     }
+    // a body the program ended in the middle of would have the VM run off the end of it
+    if !matches!(ops.last(), Some(Op1::Call | Op1::CallNZ | Op1::Halt)) {
+        return Err(Error::UnexpectedEOF);
+    }
     // wrap t in the quantifiers from kind_context
-    Ok(Stmt2::Func(*label, my_type, verified_ops))
+    Ok(Stmt2::Func(*label, my_type.clone(), verified_ops, host_sites))
+}
+
+/// Check that no type an op made is nested deeper than `limits.type_depth`. Types only get deeper when they're made,
+/// so with each op checking what it pushed, everything on the stacks stays shallow enough for the checker to recurse over.
+fn check_depth(
+    limits: &Limits,
+    compile_time_stack: &Stack<CTStackVal>,
+    stack_type: &Stack<TypeId>,
+    types: &Types,
+) -> Result<(), Error> {
+    let made = compile_time_stack.new_since_mark().filter_map(|ctval| match ctval {
+        CTStackVal::Type(t) => Some(t.depth()),
+        _ => None,
+    });
+    for depth in made.chain(stack_type.new_since_mark().map(|t| types.depth(*t))) {
+        if depth > limits.type_depth {
+            return Err(Error::LimitExceeded(Limit::TypeDepth, limits.type_depth, depth));
+        }
+    }
+    Ok(())
 }
 
 fn valid_data_section_type(t: &Type) -> bool {
     match t {
         Type::I32 => true,
         Type::Array(_, r) if r.id == DataSection => true,
-        Type::Tuple(components) if components.iter().all(|(_, t)| valid_data_section_type(t)) => {
+        Type::Tuple(components) if components.iter().all(|field| valid_data_section_type(&field.t)) => {
             true
         }
         _ => false,
     }
 }
 
+/// Whether a value of type `t` could have a resource in it, which only the host can make.
+/// A type variable could be anything, and a named type could be defined as anything, so they could.
+fn holds_resource(t: &Type) -> bool {
+    match t {
+        Type::Resource(_, _) | Type::Var(_, _) | Type::Named(_, _) => true,
+        Type::Tuple(fields) => fields.iter().any(|field| holds_resource(&field.t)),
+        Type::Ptr(t, _)
+        | Type::Array(t, _)
+        | Type::Map(t, _)
+        | Type::Forall(_, _, t)
+        | Type::ForallRegion(_, t, _)
+        | Type::Exists(_, _, t)
+        | Type::ExistsRegion(_, t) => holds_resource(t),
+        // a function value is just where its code is
+        Type::I32 | Type::U8 | Type::Handle(_) | Type::Func(_) | Type::BigInt(_) | Type::Buffer(_) => false,
+    }
+}
+
+/// Check that the caller meets everything the callee's signature requires:
+/// a compile-time argument for each quantifier (of the right kind and size, and only live regions),
+/// then a runtime argument of the right type for each parameter.
 fn handle_call(
     pos: u32,
-    t: &Type,
-    stack_type: &mut Vec<Type>,
-    compile_time_stack: &mut Vec<CTStackVal>,
+    types: &mut Types,
+    t: TypeId,
+    stack_type: &mut Stack<TypeId>,
+    compile_time_stack: &mut Stack<CTStackVal>,
+    rgn_vars: &[Region],
     op1: Op1,
 ) -> Result<(), Error> {
-    match t {
-        Type::Func(args) => {
+    match types.node(t).clone() {
+        Node::Func(args) => {
             let arg_ts_needed = args;
             let mut arg_ts_present = vec![];
             for _ in 0..arg_ts_needed.len() {
                 match stack_type.pop() {
-                    Some(t) => arg_ts_present.push(t.clone()),
+                    Some(t) => arg_ts_present.push(t),
                     None => {
                         return Err(Error::TypeErrorNotEnoughRuntimeArgs(
                             pos,
@@ -951,59 +1765,75 @@ fn handle_call(
                     }
                 }
             }
-            let types_match = arg_ts_present
-                .iter()
-                .zip(arg_ts_needed.iter())
-                .all(|(t1, t2)| type_eq(t1, t2));
-            if !types_match {
-                for t in stack_type {
-                    println!("{}", t.pretty());
+            for (i, (t1, t2)) in arg_ts_needed.into_iter().zip(arg_ts_present).enumerate() {
+                if !same(types, t1, t2) {
+                    let (init1, init2) = (fully_initialized(types, t1), fully_initialized(types, t2));
+                    if same(types, init1, init2) {
+                        return Err(Error::TypeErrorCallArgUninitialized(pos, op1, i, types.resolve(t2)));
+                    }
+                    return Err(Error::TypeErrorCallArgMismatch(pos, op1, i, types.resolve(t1), types.resolve(t2)));
                 }
-                return Err(Error::TypeErrorCallArgTypesMismatch(
-                    pos,
-                    arg_ts_needed.to_vec(),
-                    arg_ts_present,
-                ));
             }
             Ok(())
         }
-        Type::Forall(var, size, body) => {
+        Node::Forall(var, size, body) => {
             let mb_t = compile_time_stack.pop();
             match mb_t {
                 Some(CTStackVal::Type(t)) => {
-                    if t.size() != *size {
-                        return Err(Error::SizeError(pos, op1, *size, t.size()));
+                    if t.size() != size {
+                        return Err(Error::SizeError(pos, op1, size, t.size()));
                     }
-                    let new_t = substitute_t(&*body, &HashMap::from([(*var, t)]), &HashMap::new());
-                    handle_call(pos, &new_t, stack_type, compile_time_stack, op1)
+                    move_only(pos, op1, &t)?;
+                    let t = types.intern(&t);
+                    let new_t = types.substitute(body, &HashMap::from([(var, t)]), &HashMap::new());
+                    handle_call(pos, types, new_t, stack_type, compile_time_stack, rgn_vars, op1)
                 }
-                Some(ctval) => return Err(Error::KindError(pos, op1, Kind::Type, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, op1)),
+                Some(ctval) => Err(Error::KindError(pos, op1, Kind::Type, ctval)),
+                None => Err(Error::TypeErrorNotEnoughCTArgs(pos, op1, Kind::Type)),
             }
         }
-        Type::ForallRegion(var, body, captured_rgns) => {
+        Node::ForallRegion(var, body, captured_rgns) => {
             let mb_r = compile_time_stack.pop();
             match mb_r {
                 Some(CTStackVal::Region(r)) => {
-                    if var.unique && captured_rgns.iter().any(|r2| r2.id == r.id) {
-                        return Err(Error::RegionAccessError(pos, op1, r));
-                    }
-                    let new_t =
-                        substitute_t(&*body, &HashMap::new(), &HashMap::from([(var.id, r)]));
-                    handle_call(pos, &new_t, stack_type, compile_time_stack, op1)
+                    check_region_arg(pos, op1, &var, &r, &captured_rgns, rgn_vars)?;
+                    let new_t = types.substitute(body, &HashMap::new(), &HashMap::from([(var.id, r)]));
+                    handle_call(pos, types, new_t, stack_type, compile_time_stack, rgn_vars, op1)
                 }
-                Some(ctval) => return Err(Error::KindError(pos, op1, Kind::Region, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, op1)),
+                Some(ctval) => Err(Error::KindError(pos, op1, Kind::Region, ctval)),
+                None => Err(Error::TypeErrorNotEnoughCTArgs(pos, op1, Kind::Region)),
             }
         }
-        _ => return Err(Error::TypeErrorFunctionExpected(pos, op1, t.clone())),
+        _ => Err(Error::TypeErrorFunctionExpected(pos, op1, types.resolve(t))),
+    }
+}
+
+/// Check that region `r` can instantiate the region variable `var` of a function that captured `captured_rgns`,
+/// either at a call or at an `app`.
+/// The function gets to use the region, so it has to be live, and if `var` is unique, the function can free it,
+/// so it has to be unique here too, and not one the function captured.
+fn check_region_arg(
+    pos: Pos,
+    op: Op1,
+    var: &Region,
+    r: &Region,
+    captured_rgns: &[Region],
+    rgn_vars: &[Region],
+) -> Result<(), Error> {
+    if var.unique && captured_rgns.iter().any(|r2| r2.id == r.id) {
+        return Err(Error::RegionAccessError(pos, op, *r));
+    }
+    match rgn_vars.iter().find(|r2| r2.id == r.id) {
+        None => Err(Error::TypeErrorCallRegionNotLive(pos, op, *r)),
+        Some(live) if var.unique && !live.unique => Err(Error::TypeErrorCallRegionNotUnique(pos, op, *r)),
+        Some(_) => Ok(()),
     }
 }
 
 fn handle_handle(
     pos: u32,
     op: &Op1,
-    compile_time_stack: &mut Vec<CTStackVal>,
+    compile_time_stack: &mut Stack<CTStackVal>,
 ) -> Result<(), Error> {
     match compile_time_stack.pop() {
         Some(CTStackVal::Region(r)) => {
@@ -1013,8 +1843,109 @@ fn handle_handle(
             compile_time_stack.push(CTStackVal::Type(Type::Handle(r)));
             Ok(())
         }
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Region, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+/// The type of a big integer in the region on top of the compile-time stack.
+/// Unlike a handle, it can be in the data section, as a literal.
+fn handle_bigint(pos: u32, op: &Op1, compile_time_stack: &mut Stack<CTStackVal>) -> Result<(), Error> {
+    match compile_time_stack.pop() {
+        Some(CTStackVal::Region(r)) => {
+            compile_time_stack.push(CTStackVal::Type(Type::BigInt(r)));
+            Ok(())
+        }
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+/// Pop an operand of a `big_` op, a big integer in a region that's still live.
+fn pop_bigint(
+    pos: u32,
+    op: &Op1,
+    types: &Types,
+    stack_type: &mut Stack<TypeId>,
+    rgn_vars: &[Region],
+) -> Result<(), Error> {
+    match pop(types, stack_type) {
+        Some((_, &Node::BigInt(r))) if rgn_vars.iter().all(|r2| r.id != r2.id) => {
+            Err(Error::RegionAccessError(pos, *op, r))
+        }
+        Some((_, Node::BigInt(_))) => Ok(()),
+        Some((t, _)) => Err(Error::TypeErrorBigIntExpected(pos, *op, types.resolve(t))),
+        None => Err(Error::TypeErrorEmptyStack(pos, *op)),
+    }
+}
+
+/// The type of a buffer in the region on top of the compile-time stack.
+/// Like a handle, and unlike an array, it can't be in the data section, since a buffer can be written to.
+fn handle_buf(pos: u32, op: &Op1, compile_time_stack: &mut Stack<CTStackVal>) -> Result<(), Error> {
+    match compile_time_stack.pop() {
+        Some(CTStackVal::Region(r)) => {
+            if r.id == RgnId::DataSection {
+                return Err(Error::ReadOnlyRegionError(pos, *op, r.id));
+            }
+            compile_time_stack.push(CTStackVal::Type(Type::Buffer(r)));
+            Ok(())
+        }
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+/// Pop the buffer a `buf_` op works on, in a region that's still live, and say which region.
+fn pop_buffer(
+    pos: u32,
+    op: &Op1,
+    types: &Types,
+    stack_type: &mut Stack<TypeId>,
+    rgn_vars: &[Region],
+) -> Result<Region, Error> {
+    match pop(types, stack_type) {
+        Some((_, &Node::Buffer(r))) if rgn_vars.iter().all(|r2| r.id != r2.id) => {
+            Err(Error::RegionAccessError(pos, *op, r))
+        }
+        Some((_, &Node::Buffer(r))) => Ok(r),
+        Some((t, _)) => Err(Error::TypeErrorBufferExpected(pos, *op, types.resolve(t))),
+        None => Err(Error::TypeErrorEmptyStack(pos, *op)),
+    }
+}
+
+/// Pop the map a `map_` op works on, in a region that's still live, and say what its values are and which region it's in.
+fn pop_map(
+    pos: u32,
+    op: &Op1,
+    types: &Types,
+    stack_type: &mut Stack<TypeId>,
+    rgn_vars: &[Region],
+) -> Result<(TypeId, Region), Error> {
+    match pop(types, stack_type) {
+        Some((_, &Node::Map(_, r))) if rgn_vars.iter().all(|r2| r.id != r2.id) => {
+            Err(Error::RegionAccessError(pos, *op, r))
+        }
+        Some((_, &Node::Map(t, r))) => Ok((t, r)),
+        Some((t, _)) => Err(Error::TypeErrorMapExpected(pos, *op, types.resolve(t))),
+        None => Err(Error::TypeErrorEmptyStack(pos, *op)),
+    }
+}
+
+/// Pop the handle of the region an op makes its result in, like a `big_` op or `buf_new`, which is consumed like `malloc`'s.
+fn pop_dest_handle(
+    pos: u32,
+    op: &Op1,
+    types: &Types,
+    stack_type: &mut Stack<TypeId>,
+    rgn_vars: &[Region],
+) -> Result<Region, Error> {
+    match pop(types, stack_type) {
+        Some((_, &Node::Handle(r))) if rgn_vars.iter().all(|r2| r.id != r2.id) => {
+            Err(Error::RegionAccessError(pos, *op, r))
+        }
+        Some((_, &Node::Handle(r))) => Ok(r),
+        Some((t, _)) => Err(Error::TypeErrorRegionHandleExpected(pos, *op, types.resolve(t))),
+        None => Err(Error::TypeErrorEmptyStack(pos, *op)),
     }
 }
 
@@ -1022,12 +1953,12 @@ fn handle_tuple(
     n: &u8,
     pos: u32,
     op: &Op1,
-    compile_time_stack: &mut Vec<CTStackVal>,
+    compile_time_stack: &mut Stack<CTStackVal>,
 ) -> Result<(), Error> {
     let mut ts = vec![];
     for _ in 0..*n {
         match compile_time_stack.pop() {
-            Some(CTStackVal::Type(t)) => ts.push((true, t)),
+            Some(CTStackVal::Type(t)) => ts.push(Field::new(t)),
             Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
             None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         }
@@ -1036,10 +1967,60 @@ fn handle_tuple(
     Ok(())
 }
 
+/// Apply a `field` descriptor to the next component of the tuple type that `tuple_fields` just made.
+/// The parser has checked that the `field`s follow their `tuple_fields`, so the tuple is on top of the stack.
+fn handle_field(
+    index: &mut u8,
+    descriptor: u8,
+    pos: u32,
+    op: &Op1,
+    compile_time_stack: &mut Stack<CTStackVal>,
+) -> Result<(), Error> {
+    let i = *index;
+    *index += 1;
+    if descriptor & !FIELD_MUTABLE != 0 {
+        return Err(Error::TypeErrorBadField(pos, *op, i, descriptor));
+    }
+    match compile_time_stack.last_mut() {
+        Some(CTStackVal::Type(Type::Tuple(ts))) if usize::from(i) < ts.len() => {
+            ts[usize::from(i)].mutable = descriptor & FIELD_MUTABLE != 0;
+            Ok(())
+        }
+        Some(CTStackVal::Type(t)) => Err(Error::TypeErrorTupleExpected(pos, *op, t.clone())),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval.clone())),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+fn handle_named(
+    pos: u32,
+    op: &Op1,
+    k: u32,
+    named: &[NamedType],
+    compile_time_stack: &mut Stack<CTStackVal>,
+) -> Result<(), Error> {
+    match named.get(k as usize) {
+        Some(t) => {
+            compile_time_stack.push(CTStackVal::Type(Type::Named(k, t.size)));
+            Ok(())
+        }
+        None => Err(Error::UnknownNamedType(pos, *op, k, named.len())),
+    }
+}
+
+/// The size and definition of named type `k`, which `fold` and `unfold` go between.
+fn definition_of(pos: u32, op: &Op1, k: u32, sigs: &Signatures) -> Result<(usize, TypeId), Error> {
+    match (sigs.named.get(k as usize), sigs.definitions.get(k as usize)) {
+        (Some(NamedType { size, .. }), Some(Some(t))) => Ok((*size, *t)),
+        (Some(_), _) => Err(Error::TypeErrorAbstractType(pos, *op, k)),
+        (None, _) => Err(Error::UnknownNamedType(pos, *op, k, sigs.named.len())),
+    }
+}
+
 fn handle_some(
     pos: u32,
     op: &Op1,
-    compile_time_stack: &mut Vec<CTStackVal>,
+    compile_time_stack: &mut Stack<CTStackVal>,
     fresh_id: &mut u32,
     label: &u32,
     quantification_stack: &mut Vec<Quantification>,
@@ -1048,19 +2029,19 @@ fn handle_some(
         Some(CTStackVal::Size(s)) => {
             let id = Id(*label, *fresh_id);
             *fresh_id += 1;
-            compile_time_stack.push(CTStackVal::Type(Type::Var(id.clone(), s)));
+            compile_time_stack.push(CTStackVal::Type(Type::Var(id, s)));
             quantification_stack.push(Quantification::Exist(id, s));
             Ok(())
         }
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Size, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Size, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
     }
 }
 
 fn handle_all(
     pos: u32,
     op: &Op1,
-    compile_time_stack: &mut Vec<CTStackVal>,
+    compile_time_stack: &mut Stack<CTStackVal>,
     fresh_id: &mut u32,
     label: &u32,
     quantification_stack: &mut Vec<Quantification>,
@@ -1069,12 +2050,12 @@ fn handle_all(
         Some(CTStackVal::Size(s)) => {
             let id = Id(*label, *fresh_id);
             *fresh_id += 1;
-            compile_time_stack.push(CTStackVal::Type(Type::Var(id.clone(), s)));
+            compile_time_stack.push(CTStackVal::Type(Type::Var(id, s)));
             quantification_stack.push(Quantification::Forall(id, s));
             Ok(())
         }
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Size, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Size, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
     }
 }
 
@@ -1082,7 +2063,7 @@ fn handle_rgn(
     next_region_is_unique: &mut bool,
     label: &u32,
     fresh_id: &mut u32,
-    compile_time_stack: &mut Vec<CTStackVal>,
+    compile_time_stack: &mut Stack<CTStackVal>,
     quantification_stack: &mut Vec<Quantification>,
 ) -> Result<(), Error> {
     let id = Id(*label, *fresh_id);
@@ -1091,15 +2072,124 @@ fn handle_rgn(
         id: RgnId::Var(id),
     };
     *fresh_id += 1;
-    compile_time_stack.push(CTStackVal::Region(r.clone()));
+    compile_time_stack.push(CTStackVal::Region(r));
     quantification_stack.push(Quantification::Region(r));
     Ok(())
 }
 
+/// Pack a value whose type mentions the region on top of the compile-time stack into the region package under it.
+/// The package owns the region from then on, so the region stops being live here,
+/// which is why it has to be live and unique to begin with.
+fn pack_region(
+    pos: Pos,
+    op: Op1,
+    types: &mut Types,
+    type_of_hidden: TypeId,
+    compile_time_stack: &mut Stack<CTStackVal>,
+    rgn_vars: &mut Vec<Region>,
+) -> Result<TypeId, Error> {
+    let r = match compile_time_stack.pop() {
+        Some(CTStackVal::Region(r)) => r,
+        Some(ctval) => return Err(Error::KindError(pos, op, Kind::Region, ctval)),
+        None => return Err(Error::TypeErrorEmptyCTStack(pos, op)),
+    };
+    let (var, body) = match compile_time_stack.pop() {
+        Some(CTStackVal::Type(Type::ExistsRegion(var, body))) => (var, body),
+        Some(CTStackVal::Type(t)) => return Err(Error::TypeErrorExistentialExpected(pos, op, t)),
+        Some(ctval) => return Err(Error::KindError(pos, op, Kind::Type, ctval)),
+        None => return Err(Error::TypeErrorEmptyCTStack(pos, op)),
+    };
+    match rgn_vars.iter().find(|r2| r2.id == r.id) {
+        Some(live) if live.unique => {}
+        Some(_) => return Err(Error::UniquenessError(pos, op, r)),
+        None => return Err(Error::RegionAccessError(pos, op, r)),
+    }
+    let body = types.intern(&body);
+    let unpacked_type = types.substitute(body, &HashMap::new(), &HashMap::from([(var.id, r)]));
+    if !same(types, type_of_hidden, unpacked_type) {
+        return Err(Error::TypeError(
+            pos,
+            op,
+            types.resolve(unpacked_type),
+            types.resolve(type_of_hidden),
+        ));
+    }
+    rgn_vars.retain(|r2| r2.id != r.id);
+    Ok(types.mk(Node::ExistsRegion(var, body)))
+}
+
+/// Whether a value of type `t` owns a region, so copying it would give two owners.
+/// Function types don't own anything, even if they take a region package.
+pub fn owns_region(t: &Type) -> bool {
+    match t {
+        Type::ExistsRegion(_, _) => true,
+        Type::Tuple(ts) => ts.iter().any(|field| owns_region(&field.t)),
+        Type::Exists(_, _, t) => owns_region(t),
+        _ => false,
+    }
+}
+
+/// Whether a value of type `t` holds the handle of region `r` itself, rather than behind a pointer.
+fn holds_handle(t: &Type, r: &Region) -> bool {
+    match t {
+        Type::Handle(r2) => r2.id == r.id,
+        Type::Tuple(ts) => ts.iter().any(|field| holds_handle(&field.t, r)),
+        Type::Exists(_, _, t) | Type::ExistsRegion(_, t) => holds_handle(t, r),
+        _ => false,
+    }
+}
+
+/// Check that `t` can be copied, because it's about to be put somewhere that copies it:
+/// in memory, in a named type, or as a type argument.
+fn move_only(pos: Pos, op: Op1, t: &Type) -> Result<(), Error> {
+    if owns_region(t) {
+        return Err(Error::TypeErrorOwnsRegion(pos, op, t.clone()));
+    }
+    if holds_any_handle(t) {
+        return Err(Error::TypeErrorHandleCopy(pos, op, t.clone()));
+    }
+    Ok(())
+}
+
+/// Whether arithmetic op `op` wraps or traps on overflow.
+fn overflow(op: &Op1) -> Overflow {
+    match op {
+        Op1::AddTrap | Op1::SubTrap | Op1::MulTrap | Op1::DivTrap | Op1::ModuloTrap => Overflow::Trap,
+        _ => Overflow::Wrap,
+    }
+}
+
+/// Whether a value of type `t` holds any region handle itself, rather than behind a pointer.
+fn holds_any_handle(t: &Type) -> bool {
+    match t {
+        Type::Handle(_) => true,
+        Type::Tuple(ts) => ts.iter().any(|field| holds_any_handle(&field.t)),
+        Type::Exists(_, _, t) | Type::ExistsRegion(_, t) => holds_any_handle(t),
+        _ => false,
+    }
+}
+
+/// Start a region package, binding a region variable for the body like `rgn` does.
+/// The package owns its region, so the region is always unique.
+fn handle_some_rgn(
+    label: &u32,
+    fresh_id: &mut u32,
+    compile_time_stack: &mut Stack<CTStackVal>,
+    quantification_stack: &mut Vec<Quantification>,
+) {
+    let r = Region {
+        unique: true,
+        id: RgnId::Var(Id(*label, *fresh_id)),
+    };
+    *fresh_id += 1;
+    compile_time_stack.push(CTStackVal::Region(r));
+    quantification_stack.push(Quantification::ExistRegion(r));
+}
+
 fn handle_end(
     pos: u32,
     op: &Op1,
-    compile_time_stack: &mut Vec<CTStackVal>,
+    compile_time_stack: &mut Stack<CTStackVal>,
     quantification_stack: &mut Vec<Quantification>,
 ) -> Result<(), Error> {
     match quantification_stack.pop() {
@@ -1110,16 +2200,16 @@ fn handle_end(
                     Ok(())
                 }
                 Some(CTStackVal::Type(Type::Var(id2, _))) => {
-                    return Err(Error::TypeErrorSpecificTypeVarExpected(pos, *op, id, id2))
+                    Err(Error::TypeErrorSpecificTypeVarExpected(pos, *op, id, id2))
                 }
                 Some(CTStackVal::Type(t)) => {
-                    return Err(Error::TypeErrorTypeVarExpected(pos, *op, id, t))
+                    Err(Error::TypeErrorTypeVarExpected(pos, *op, id, t))
                 }
-                Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
             },
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         },
         Some(Quantification::Forall(id, s)) => match compile_time_stack.pop() {
             Some(CTStackVal::Type(t)) => match compile_time_stack.pop() {
@@ -1128,16 +2218,34 @@ fn handle_end(
                     Ok(())
                 }
                 Some(CTStackVal::Type(Type::Var(id2, _))) => {
-                    return Err(Error::TypeErrorSpecificTypeVarExpected(pos, *op, id, id2))
+                    Err(Error::TypeErrorSpecificTypeVarExpected(pos, *op, id, id2))
                 }
                 Some(CTStackVal::Type(t)) => {
-                    return Err(Error::TypeErrorTypeVarExpected(pos, *op, id, t))
+                    Err(Error::TypeErrorTypeVarExpected(pos, *op, id, t))
                 }
-                Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
             },
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        },
+        Some(Quantification::ExistRegion(r)) => match compile_time_stack.pop() {
+            Some(CTStackVal::Type(t)) => match compile_time_stack.pop() {
+                Some(CTStackVal::Region(r2)) if r.id == r2.id => {
+                    let holds = holds_handle(&t, &r);
+                    let package = Type::ExistsRegion(r, Box::new(t));
+                    if !holds {
+                        return Err(Error::RegionPackageWithoutHandle(pos, *op, package));
+                    }
+                    compile_time_stack.push(CTStackVal::Type(package));
+                    Ok(())
+                }
+                Some(CTStackVal::Region(r2)) => Err(Error::RegionError(pos, *op, r, r2)),
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            },
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         },
         Some(Quantification::Region(r)) => match compile_time_stack.pop() {
             Some(CTStackVal::Type(t)) => match compile_time_stack.pop() {
@@ -1149,14 +2257,14 @@ fn handle_end(
                     )));
                     Ok(())
                 }
-                Some(CTStackVal::Region(r2)) => return Err(Error::RegionError(pos, *op, r, r2)),
-                Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Region, ctval)),
-                None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+                Some(CTStackVal::Region(r2)) => Err(Error::RegionError(pos, *op, r, r2)),
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
             },
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+            Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+            None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
         },
-        None => return Err(Error::TypeErrorEmptyQuantificationStack(pos, *op)),
+        None => Err(Error::TypeErrorEmptyQuantificationStack(pos, *op)),
     }
 }
 
@@ -1164,7 +2272,7 @@ fn handle_func(
     n: &u8,
     pos: u32,
     op: &Op1,
-    compile_time_stack: &mut Vec<CTStackVal>,
+    compile_time_stack: &mut Stack<CTStackVal>,
 ) -> Result<(), Error> {
     let mut ts = vec![];
     for _ in 0..*n {
@@ -1178,175 +2286,158 @@ fn handle_func(
     Ok(())
 }
 
-fn handle_ctget(pos: u32, i: &u8, compile_time_stack: &mut Vec<CTStackVal>) -> Result<(), Error> {
-    if compile_time_stack.len() - 1 < *i as usize {
+fn handle_ctget(pos: u32, i: &u8, compile_time_stack: &mut Stack<CTStackVal>) -> Result<(), Error> {
+    if compile_time_stack.is_empty() {
         return Err(Error::TypeErrorEmptyCTStack(pos, Op1::CTGet(*i)));
     }
-    match compile_time_stack.get(compile_time_stack.len() - 1 - (*i) as usize) {
+    match compile_time_stack.nth_from_top(*i as usize) {
         Some(ctval) => {
             compile_time_stack.push(ctval.clone());
             Ok(())
         }
-        None => {
-            return Err(Error::TypeErrorCTGetOutOfRange(
-                pos,
-                *i,
-                compile_time_stack.len(),
-            ))
-        }
+        None => Err(Error::TypeErrorCTGetOutOfRange(
+            pos,
+            *i,
+            compile_time_stack.to_vec(),
+        )),
     }
 }
 
-fn handle_ptr(pos: u32, op: &Op1, compile_time_stack: &mut Vec<CTStackVal>) -> Result<(), Error> {
+fn handle_ptr(pos: u32, op: &Op1, compile_time_stack: &mut Stack<CTStackVal>) -> Result<(), Error> {
     match compile_time_stack.pop() {
-        Some(CTStackVal::Type(t)) => match compile_time_stack.pop() {
-            Some(CTStackVal::Region(r)) => {
-                compile_time_stack.push(CTStackVal::Type(Type::Ptr(Box::new(t), r)));
-                Ok(())
+        Some(CTStackVal::Type(t)) => {
+            move_only(pos, *op, &t)?;
+            match compile_time_stack.pop() {
+                Some(CTStackVal::Region(r)) => {
+                    compile_time_stack.push(CTStackVal::Type(Type::Ptr(Box::new(t), r)));
+                    Ok(())
+                }
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
             }
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Region, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
-        },
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        }
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
     }
 }
 
 pub fn handle_arr(
     pos: u32,
     op: &Op1,
-    compile_time_stack: &mut Vec<CTStackVal>,
+    compile_time_stack: &mut Stack<CTStackVal>,
 ) -> Result<(), Error> {
     match compile_time_stack.pop() {
-        Some(CTStackVal::Type(t)) => match compile_time_stack.pop() {
-            Some(CTStackVal::Region(r)) => {
-                compile_time_stack.push(CTStackVal::Type(Type::Array(Box::new(t), r)));
-                Ok(())
+        Some(CTStackVal::Type(t)) => {
+            move_only(pos, *op, &t)?;
+            match compile_time_stack.pop() {
+                Some(CTStackVal::Region(r)) => {
+                    compile_time_stack.push(CTStackVal::Type(Type::Array(Box::new(t), r)));
+                    Ok(())
+                }
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
             }
-            Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Region, ctval)),
-            None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
-        },
-        Some(ctval) => return Err(Error::KindError(pos, *op, Kind::Type, ctval)),
-        None => return Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+        }
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
     }
 }
 
-/// Perform some variable substitutions within a type.
-/// This does not modify the original.
-pub fn substitute_t(typ: &Type, tsubs: &HashMap<Id, Type>, rsubs: &HashMap<RgnId, Region>) -> Type {
-    match typ {
-        Type::I32 => Type::I32,
-        Type::U8 => Type::U8,
-        Type::Handle(r) => Type::Handle(substitute_r(r, rsubs)),
-        Type::Tuple(ts) => Type::Tuple(
-            ts.iter()
-                .map(|(init, t)| (*init, substitute_t(t, tsubs, rsubs)))
-                .collect(),
-        ),
-        Type::Ptr(t, r) => Type::Ptr(
-            Box::new(substitute_t(t, tsubs, rsubs)),
-            substitute_r(r, rsubs),
-        ),
-        Type::Var(id, repr) => match tsubs.get(id) {
-            Some(new) => new.clone(),
-            None => Type::Var(*id, repr.clone()),
-        },
-        Type::Func(args) => {
-            Type::Func(args.iter().map(|t| substitute_t(t, tsubs, rsubs)).collect())
+/// The type of a resource of kind `kind` in the region on top of the compile-time stack.
+/// Like a buffer, it can't be in the data section, where it wouldn't have come from the host.
+fn handle_res(pos: u32, op: &Op1, kind: u32, compile_time_stack: &mut Stack<CTStackVal>) -> Result<(), Error> {
+    match compile_time_stack.pop() {
+        Some(CTStackVal::Region(r)) => {
+            if r.id == RgnId::DataSection {
+                return Err(Error::ReadOnlyRegionError(pos, *op, r.id));
+            }
+            compile_time_stack.push(CTStackVal::Type(Type::Resource(kind, r)));
+            Ok(())
         }
-        Type::Exists(id, s, t) => Type::Exists(*id, *s, Box::new(substitute_t(t, tsubs, rsubs))),
-        Type::Forall(id, s, t) => Type::Forall(*id, *s, Box::new(substitute_t(t, tsubs, rsubs))),
-        Type::ForallRegion(id, t, captured_rgns) => {
-            let mut captured_rgns = captured_rgns.clone();
-            for (_, r) in rsubs {
-                if r.unique {
-                    captured_rgns.push(*r);
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
+    }
+}
+
+/// The type of a map in the region under the type of its values on the compile-time stack.
+/// Its values are copied in and out, like an array's elements, and it can't be in the data section, like a buffer.
+fn handle_map(pos: u32, op: &Op1, compile_time_stack: &mut Stack<CTStackVal>) -> Result<(), Error> {
+    match compile_time_stack.pop() {
+        Some(CTStackVal::Type(t)) => {
+            move_only(pos, *op, &t)?;
+            match compile_time_stack.pop() {
+                Some(CTStackVal::Region(r)) if r.id == RgnId::DataSection => Err(Error::ReadOnlyRegionError(pos, *op, r.id)),
+                Some(CTStackVal::Region(r)) => {
+                    compile_time_stack.push(CTStackVal::Type(Type::Map(Box::new(t), r)));
+                    Ok(())
                 }
+                Some(ctval) => Err(Error::KindError(pos, *op, Kind::Region, ctval)),
+                None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
             }
-            Type::ForallRegion(*id, Box::new(substitute_t(t, tsubs, rsubs)), captured_rgns)
         }
-        Type::Array(t, r) => Type::Array(
-            Box::new(substitute_t(t, tsubs, rsubs)),
-            substitute_r(r, rsubs),
-        ),
+        Some(ctval) => Err(Error::KindError(pos, *op, Kind::Type, ctval)),
+        None => Err(Error::TypeErrorEmptyCTStack(pos, *op)),
     }
 }
 
-/// Perform some variable substitutions in a compile-time region value.
-/// This does not modify the original
-pub fn substitute_r(r: &Region, rsubs: &HashMap<RgnId, Region>) -> Region {
-    match rsubs.get(&r.id) {
-        Some(r2) => *r2,
-        None => *r,
-    }
+/// Take the type on top of the runtime stack off, along with what it is, for matching on.
+fn pop<'t>(types: &'t Types, stack_type: &mut Stack<TypeId>) -> Option<(TypeId, &'t Node)> {
+    stack_type.pop().map(|t| (t, types.node(t)))
 }
 
-/// Check if two types are equal, for typechecking purposes.
-pub fn type_eq(type1: &Type, type2: &Type) -> bool {
-    match (type1, type2) {
-        (Type::I32, Type::I32) => true,
-        (Type::U8, Type::U8) => true,
-        (Type::Handle(r1), Type::Handle(r2)) => r1 == r2,
-        (Type::Tuple(ts1), Type::Tuple(ts2)) => {
-            ts1.len() == ts2.len() && {
-                let mut ts2 = ts2.iter();
-                for (init1, t1) in ts1 {
-                    let (init2, t2) = ts2.next().unwrap();
-                    if init1 != init2 || !type_eq(t1, t2) {
-                        return false;
-                    }
-                }
-                return true;
-            }
-        }
-        (Type::Ptr(t1, r1), Type::Ptr(t2, r2)) => r1 == r2 && type_eq(t1, t2),
-        (Type::Var(id1, repr1), Type::Var(id2, repr2)) => id1 == id2 && repr1 == repr2,
-        (Type::Func(ts1), Type::Func(ts2)) => {
-            ts1.iter().zip(ts2.iter()).all(|(t1, t2)| type_eq(&t1, &t2))
-        }
-        (Type::Exists(id1, repr1, t1), Type::Exists(id2, repr2, t2)) => {
-            let mut sub = HashMap::new();
-            sub.insert(*id2, Type::Var(*id1, repr1.clone()));
-            let t2_subbed = substitute_t(t2, &sub, &HashMap::new());
-            repr1 == repr2 && type_eq(t1, &t2_subbed)
-        }
-        (Type::Forall(id1, size1, body1), Type::Forall(id2, size2, body2)) => {
-            let mut sub = HashMap::new();
-            sub.insert(*id2, Type::Var(*id1, size1.clone()));
-            let body2_subbed = substitute_t(&body2, &sub, &HashMap::new());
-            size1 == size2 && type_eq(body1, &body2_subbed)
+/// Whether two types on the runtime stack are the same, for typechecking purposes.
+fn same(types: &Types, t1: TypeId, t2: TypeId) -> bool {
+    TYPE_CHECKS.with(|n| n.set(n.get() + 1));
+    types.eq(t1, t2)
+}
+
+/// The type with every tuple component marked as initialized, for telling
+/// "this isn't initialized yet" apart from "this is the wrong type".
+fn fully_initialized(types: &mut Types, t: TypeId) -> TypeId {
+    match types.node(t).clone() {
+        Node::Tuple(ts) => {
+            let ts = ts
+                .into_iter()
+                .map(|field| Component {
+                    init: true,
+                    t: fully_initialized(types, field.t),
+                    ..field
+                })
+                .collect();
+            types.mk(Node::Tuple(ts))
         }
-        (
-            Type::ForallRegion(r1, body1, _captured_rgns1),
-            Type::ForallRegion(r2, body2, _captured_rgns2),
-        ) => {
-            let mut sub = HashMap::new();
-            sub.insert(r2.id, *r1);
-            let body2_subbed = substitute_t(&body2, &HashMap::new(), &sub);
-            type_eq(body1, &body2_subbed)
+        Node::Ptr(t, r) => {
+            let t = fully_initialized(types, t);
+            types.mk(Node::Ptr(t, r))
         }
-        (Type::Array(t1, r1), Type::Array(t2, r2)) => r1 == r2 && type_eq(t1, t2),
-        (_, _) => false,
+        _ => t,
     }
 }
 
-fn setup_verifier(t: &Type) -> Result<(Vec<CTStackVal>, Vec<Type>), Error> {
-    match t {
-        Type::Forall(id, s, t) => {
-            let (mut ct_stack, param_types) = setup_verifier(t)?;
+/// Check if two types are equal, for typechecking purposes: the same up to the names of their bound variables.
+pub fn type_eq(type1: &Type, type2: &Type) -> bool {
+    let mut types = Types::new();
+    let (t1, t2) = (types.intern(type1), types.intern(type2));
+    same(&types, t1, t2)
+}
+
+fn setup_verifier(types: &Types, t: TypeId) -> Result<(Vec<CTStackVal>, Vec<TypeId>), Error> {
+    match types.node(t) {
+        Node::Forall(id, s, t) => {
+            let (mut ct_stack, param_types) = setup_verifier(types, *t)?;
             ct_stack.push(CTStackVal::Type(Type::Var(*id, *s)));
             Ok((ct_stack, param_types))
         }
-        Type::ForallRegion(r, t, _captured_rgns) => {
-            let (mut ct_stack, param_types) = setup_verifier(t)?;
+        Node::ForallRegion(r, t, _captured_rgns) => {
+            let (mut ct_stack, param_types) = setup_verifier(types, *t)?;
             ct_stack.push(CTStackVal::Region(*r));
             Ok((ct_stack, param_types))
         }
-        Type::Func(param_ts) => {
+        Node::Func(param_ts) => {
             let mut param_ts = param_ts.to_vec();
             param_ts.reverse();
-            Ok((vec![], param_ts.into()))
+            Ok((vec![], param_ts))
         }
-        t => return Err(Error::ForwardDeclNotType(t.clone())),
+        _ => Err(Error::ForwardDeclNotType(types.resolve(t))),
     }
 }
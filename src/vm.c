@@ -17,17 +17,166 @@
 
 #define METADATA_OFFSET (sizeof(u64) + sizeof(u64))
 
-Region *new_region(size_t size) {
-    dbg("region size with metadata: %lu\n", sizeof(size_t) + sizeof(size_t) + sizeof(size_t) + size);
-    Region *r = malloc(sizeof(size_t) + sizeof(size_t) + sizeof(size_t) + size); // an extra sizeof(size_t) to be safe re: padding
+#ifdef SVM_PROFILE
+// the cheapest clock there is on each platform: the TSC on x86, the virtual counter on ARM, and otherwise nanoseconds
+#if defined(__x86_64__) || defined(__i386__)
+#include <x86intrin.h>
+u64 vm_profile_ticks(void) {
+    return __rdtsc();
+}
+#elif defined(__aarch64__)
+u64 vm_profile_ticks(void) {
+    u64 t;
+    __asm__ volatile("mrs %0, cntvct_el0" : "=r"(t));
+    return t;
+}
+#else
+#include <time.h>
+u64 vm_profile_ticks(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return (u64)ts.tv_sec * 1000000000 + ts.tv_nsec;
+}
+#endif
+
+// pick a gap between 1 and twice the period, less one, so the gaps average out to the period
+static inline void next_sample(struct Profile *p) {
+    p->jitter ^= p->jitter << 13;
+    p->jitter ^= p->jitter >> 17;
+    p->jitter ^= p->jitter << 5;
+    p->countdown = 1 + p->jitter % (2 * PROFILE_SAMPLE_PERIOD - 1);
+}
+
+// count the instruction about to run, finish timing the one before it if it was sampled,
+// and start timing this one if it's the next sample.
+static inline void profile_op(struct Profile *p, u8 op) {
+    p->counts[op]++;
+    if (p->timing) {
+        p->ticks[p->timed_op] += vm_profile_ticks() - p->started;
+        p->samples[p->timed_op]++;
+        p->timing = 0;
+    }
+    if (--p->countdown == 0) {
+        next_sample(p);
+        p->timed_op = op;
+        p->timing = 1;
+        p->started = vm_profile_ticks();
+    }
+}
+
+void vm_instance_profile(Instance *inst, u64 *counts, u64 *ticks, u64 *samples) {
+    memcpy(counts, inst->profile.counts, sizeof(inst->profile.counts));
+    memcpy(ticks, inst->profile.ticks, sizeof(inst->profile.ticks));
+    memcpy(samples, inst->profile.samples, sizeof(inst->profile.samples));
+}
+
+void vm_instance_reset_profile(Instance *inst) {
+    memset(&inst->profile, 0, sizeof(inst->profile));
+    inst->profile.jitter = 1;
+    next_sample(&inst->profile);
+}
+
+#define PROFILE_OP(op) profile_op(&inst->profile, op);
+// an instruction that leaves `eval` (by halting, yielding, or trapping) isn't timed,
+// since whatever the host does before the next one would be counted as part of it
+#define PROFILE_ENTER() inst->profile.timing = 0;
+#else
+#define PROFILE_OP(op)
+#define PROFILE_ENTER()
+#endif
+
+// how much memory a small region takes, header and all
+static size_t region_block(size_t size) {
+    return (sizeof(Region) + size + 15) & ~(size_t)15;
+}
+
+// a freed pooled region of the given block size, if there is one
+static Region *reuse_region(struct Regions *rs, size_t block) {
+    Region **prev = &rs->free[block / 16 % POOL_BUCKETS];
+    for (Region *r = *prev; r != NULL; prev = &r->next_freed, r = r->next_freed) {
+        if (sizeof(Region) + r->capacity == block) {
+            *prev = r->next_freed;
+            return r;
+        }
+    }
+    return NULL;
+}
+
+// carve a block out of the newest chunk, making a bigger one if it doesn't fit
+static Region *carve_region(struct Regions *rs, size_t block) {
+    struct Chunk *c = rs->chunks;
+    if (c == NULL || c->used + block > c->size) {
+        // an adaptive instance's first chunk holds as many bytes of small regions as it's had live at once
+        if (rs->next_chunk == 0) rs->next_chunk = rs->stats.small_peak;
+        size_t size = rs->next_chunk < block ? block : rs->next_chunk;
+        c = malloc(sizeof(struct Chunk) + size);
+        c->next = rs->chunks;
+        c->size = size;
+        c->used = 0;
+        rs->chunks = c;
+        rs->stats.chunk_bytes += size;
+        rs->next_chunk = size * rs->growth;
+    }
+    Region *r = (Region*)(c->data + c->used);
+    c->used += block;
+    return r;
+}
+
+Region *new_region(Instance *inst, size_t size) {
+    dbg("region size with metadata: %lu\n", sizeof(Region) + size);
+    struct Regions *rs = &inst->regions;
+    rs->stats.made++;
+    Region *r = NULL;
+    if (size <= rs->large_region) {
+        size_t block = region_block(size);
+        rs->stats.small++;
+        rs->stats.small_live += block;
+        if (rs->stats.small_live > rs->stats.small_peak) rs->stats.small_peak = rs->stats.small_live;
+        if (rs->strategy == REGION_POOLED || (rs->strategy == REGION_ADAPTIVE && rs->stats.small > ADAPTIVE_AFTER)) {
+            rs->stats.pooled++;
+            r = reuse_region(rs, block);
+            if (r == NULL) r = carve_region(rs, block);
+            // the rest of the block is the region's to use too
+            r->capacity = block - sizeof(Region);
+            r->pooled = 1;
+        }
+    }
+    if (r == NULL) {
+        r = malloc(sizeof(Region) + size);
+        r->capacity = size;
+        r->pooled = 0;
+    }
     r->offset = 0;
-    r->capacity = size;
+    r->generation = 1;
+    r->next_freed = NULL;
+    r->resources = 0;
     return r;
 }
 
+// take a freed region out of the instance's live bytes, whether or not its memory is given back
+static void count_freed_region(struct Regions *rs, Region *r) {
+    if (r->pooled) {
+        rs->stats.small_live -= sizeof(Region) + r->capacity;
+    } else if (r->capacity <= rs->large_region) {
+        rs->stats.small_live -= region_block(r->capacity);
+    }
+}
+
+void free_region(Instance *inst, Region *r) {
+    struct Regions *rs = &inst->regions;
+    count_freed_region(rs, r);
+    if (r->pooled) {
+        Region **bucket = &rs->free[(sizeof(Region) + r->capacity) / 16 % POOL_BUCKETS];
+        r->next_freed = *bucket;
+        *bucket = r;
+    } else {
+        free(r);
+    }
+}
+
 Pointer alloc_object(Region *r, u64 size) {
     // I'd love to figure out how to have less conditionals in this function, but it's just a prototype.
-    if (r->offset + METADATA_OFFSET + size > r->capacity) {
+    if (size > r->capacity || r->offset + METADATA_OFFSET + size > r->capacity) {
         for (size_t offset = 0; offset < r->offset; offset += METADATA_OFFSET + r->data[offset]) {
             // negative generation means free
             // the absolute value of the generation is what the last generation was, then we add one to get the current generation
@@ -44,8 +193,7 @@ Pointer alloc_object(Region *r, u64 size) {
             }
         }
         dbg("r->offet: %lu, size: %lu, r->capacity: %lu\n", r->offset, size, r->capacity);
-        printf("Runtime Error! Allocation too big for region!\n");
-        exit(1); // this will jump to an exception handler eventually
+        return (Pointer){0, NULL};
     } else {
         i64 first_generation = 1;
         memcpy(r->data + r->offset, &first_generation, sizeof(first_generation));
@@ -61,6 +209,76 @@ Pointer alloc_object(Region *r, u64 size) {
     }
 }
 
+#define POISON 0xAB
+
+// Paranoid mode's version of alloc_object.
+// The object is followed by one shadow byte per byte of the object, which is nonzero until that byte is initialized.
+// Freed objects are never reused, so stale pointers keep pointing at their own shadow bytes.
+Pointer alloc_poisoned(Region *r, u64 size) {
+    if (size > r->capacity || r->offset + METADATA_OFFSET + 2 * size > r->capacity) {
+        return (Pointer){0, NULL};
+    }
+    i64 first_generation = 1;
+    memcpy(r->data + r->offset, &first_generation, sizeof(first_generation));
+    memcpy(r->data + r->offset + sizeof(first_generation), &size, sizeof(size));
+    Pointer ptr = {first_generation, r->data + r->offset + METADATA_OFFSET};
+    r->offset += METADATA_OFFSET + 2 * size;
+    memset(ptr.reference, POISON, size);
+    memset(ptr.reference + size, 1, size);
+    return ptr;
+}
+
+u8 *shadow_of(Pointer ptr) {
+    u64 size;
+    memcpy(&size, ptr.reference - sizeof(size), sizeof(size));
+    return ptr.reference + size;
+}
+
+// whether any of the given bytes of a paranoid-mode object haven't been initialized
+int is_poisoned(Instance *inst, Pointer ptr, size_t offset, size_t size) {
+    // data section pointers have no metadata, and the data section is always initialized
+    if (!inst->paranoid || ptr.generation < 0) return 0;
+    u8 *shadow = shadow_of(ptr);
+    for (size_t i = offset; i < offset + size; i++) {
+        if (shadow[i]) return 1;
+    }
+    return 0;
+}
+
+void unpoison(Instance *inst, Pointer ptr, size_t offset, size_t size) {
+    if (!inst->paranoid || ptr.generation < 0) return;
+    memset(shadow_of(ptr) + offset, 0, size);
+}
+
+// Checked mode's version of alloc_object (or alloc_poisoned, in paranoid mode too).
+// The object's metadata is preceded by the region it's in, so a pointer can be checked against its region.
+Pointer alloc_checked(Instance *inst, Region *r, u64 size) {
+    if (r->offset + sizeof(r) > r->capacity) {
+        return (Pointer){0, NULL};
+    }
+    memcpy(r->data + r->offset, &r, sizeof(r));
+    r->offset += sizeof(r);
+    Pointer ptr = inst->paranoid ? alloc_poisoned(r, size) : alloc_object(r, size);
+    if (ptr.reference == NULL) r->offset -= sizeof(r);
+    return ptr;
+}
+
+Pointer alloc_in(Instance *inst, Region *r, u64 size) {
+    if (inst->checked) return alloc_checked(inst, r, size);
+    return inst->paranoid ? alloc_poisoned(r, size) : alloc_object(r, size);
+}
+
+// whether a checked-mode pointer is into a freed region, or at an object that isn't there anymore
+int is_stale(Pointer ptr) {
+    // data section pointers are never freed
+    if (ptr.generation < 0) return 0;
+    Region *r;
+    memcpy(&r, ptr.reference - METADATA_OFFSET - sizeof(r), sizeof(r));
+    i64 g;
+    memcpy(&g, ptr.reference - METADATA_OFFSET, sizeof(g));
+    return r->generation < 0 || ptr.generation != g;
+}
+
 void check_ptr(Pointer ptr) {
     dbg("check ptr:\n");
     for (int i = 0; i < 20; i++) {
@@ -95,12 +313,29 @@ void free_object(Pointer ptr) {
     memcpy(&name, instrs + pc, sizeof(name)); \
     pc += sizeof(name); \
 
+// go back to the previous stack chunk once everything in this one has been popped.
+#define PREV_CHUNK_IF_EMPTY() \
+    if (sp == 0 && stack->last != NULL) { \
+        struct Stack *done = stack; \
+        sp = done->saved_sp; \
+        stack = done->last; \
+        done->last = inst->spare_chunks; \
+        inst->spare_chunks = done; \
+    }
+
 #define POP(t, name) \
     t name; \
-    if (sp == 0 && stack->last != NULL) { stack = stack->last; sp = stack->saved_sp; } \
+    PREV_CHUNK_IF_EMPTY(); \
     sp -= sizeof(name); \
     memcpy(&name, stack->data + sp, sizeof(name));
 
+// pop `size` bytes into the buffer `buf`.
+// values never straddle two chunks, so this is the same as POP for a size only known at runtime.
+#define POP_BYTES(buf, size) \
+    PREV_CHUNK_IF_EMPTY(); \
+    sp -= size; \
+    memcpy(buf, stack->data + sp, size);
+
 // push a value onto the stack.
 // no `ensure_size` here because the caller will often know that it's not necessary.
 #define PUSH(t, e) \
@@ -110,9 +345,16 @@ void free_object(Pointer ptr) {
 
 // start a new contiguous stack chunk if the given size wouldn't fit.
 // The caller must guarantee that the given size is less than STACK_CHUNK_SIZE
-void ensure_size(struct Stack **stack, u32 *sp, size_t size) {
+void ensure_size(Instance *inst, struct Stack **stack, u32 *sp, size_t size) {
     if (*sp + size > STACK_CHUNK_SIZE) {
-        struct Stack *new_stack = malloc(sizeof(struct Stack));
+        // reuse a chunk the stack has already moved back out of, if there is one,
+        // so a program going back and forth over a chunk boundary doesn't allocate every time.
+        struct Stack *new_stack = inst->spare_chunks;
+        if (new_stack != NULL) {
+            inst->spare_chunks = new_stack->last;
+        } else {
+            new_stack = malloc(sizeof(struct Stack));
+        }
         new_stack->last = *stack;
         dbg("NEW STACK %u %lu\n", *sp, size);
         new_stack->saved_sp = *sp;
@@ -122,101 +364,674 @@ void ensure_size(struct Stack **stack, u32 *sp, size_t size) {
     }
 }
 
-Handler scheduler[255];
-u8 scheduler_len = 0;
+// Safepoints are where the VM checks whether the embedder asked it to stop, or to pause.
+// They're only at calls and between tasks, since every loop in a CPS program goes through a call.
+// A pause stops at the call before it jumps, so continuing runs the call again.
+#define SAFEPOINT() \
+    if (__atomic_load_n(inst->interrupt, __ATOMIC_RELAXED)) TRAP(VM_TRAP_INTERRUPTED); \
+    if (__atomic_load_n(inst->pause, __ATOMIC_RELAXED)) TRAP(VM_SAFEPOINT);
+
+// remember that the current instruction is a call, for backtraces.
+#define RECORD_CALL() \
+    inst->calls[inst->call_count++ % CALL_HISTORY] = op_pc;
+
+// the scheduler's safepoint, between tasks, where there's no current instruction to report.
+// a pc of 0 is never an instruction (it's the data section's size), so it stands for stopping here.
+#define SCHEDULER_SAFEPOINT() \
+    if (__atomic_load_n(inst->interrupt, __ATOMIC_RELAXED)) { \
+        inst->suspended_pc = 0; \
+        inst->suspended_sp = inst->sp; \
+        inst->suspended_stack = stack; \
+        return VM_TRAP_INTERRUPTED; \
+    } \
+    if (__atomic_load_n(inst->pause, __ATOMIC_RELAXED)) { \
+        inst->suspended_pc = 0; \
+        inst->suspended_sp = inst->sp; \
+        inst->suspended_stack = stack; \
+        return VM_SAFEPOINT; \
+    }
+
+// stop the run at the current instruction, leaving where it stopped in the instance for core dumps.
+#define TRAP(code) \
+    { \
+        inst->suspended_pc = op_pc; \
+        inst->suspended_sp = sp; \
+        inst->suspended_stack = stack; \
+        return code; \
+    }
+
+// in checked mode, trap on a handle to a region that's been freed.
+#define CHECK_HANDLE(r) \
+    if (inst->checked && (r)->generation < 0) TRAP(VM_TRAP_USE_AFTER_FREE);
+
+// in checked mode, trap on a pointer into a region that's been freed.
+#define CHECK_LIVE(ptr) \
+    if (inst->checked && is_stale(ptr)) TRAP(VM_TRAP_USE_AFTER_FREE);
+
+// Big integers, laid out as bigint.rs says: a u32 that's 1 if it's negative, a u32 count of limbs,
+// and then the limbs of the magnitude, least significant first, with no zero limbs on top.
+// Literals in the data section can be anywhere, so limbs are always copied in and out rather than cast.
+#define BIG_HEADER 8
+
+u32 big_negative(const u8 *big) {
+    u32 negative;
+    memcpy(&negative, big, sizeof(negative));
+    return negative;
+}
+
+u32 big_len(const u8 *big) {
+    u32 len;
+    memcpy(&len, big + 4, sizeof(len));
+    return len;
+}
+
+// the `i`th limb, which is 0 past the top one
+u32 big_limb(const u8 *big, u32 i) {
+    if (i >= big_len(big)) return 0;
+    u32 limb;
+    memcpy(&limb, big + BIG_HEADER + 4 * (size_t)i, sizeof(limb));
+    return limb;
+}
+
+void set_limb(u8 *big, u32 i, u32 limb) {
+    memcpy(big + BIG_HEADER + 4 * (size_t)i, &limb, sizeof(limb));
+}
+
+// how many bytes a big integer with room for `len` limbs takes up
+size_t big_size(size_t len) {
+    return BIG_HEADER + 4 * len;
+}
+
+// write the sign and the count of limbs of a result whose limbs are written, dropping any zero limbs on top
+void big_finish(u8 *big, u32 negative, u32 len) {
+    while (len > 0) {
+        u32 top;
+        memcpy(&top, big + BIG_HEADER + 4 * (size_t)(len - 1), sizeof(top));
+        if (top != 0) break;
+        len--;
+    }
+    negative = negative && len > 0;
+    memcpy(big, &negative, sizeof(negative));
+    memcpy(big + 4, &len, sizeof(len));
+}
+
+// -1, 0, or 1 as the magnitude of `a` is less than, the same as, or more than the magnitude of `b`
+int mag_cmp(const u8 *a, const u8 *b) {
+    u32 la = big_len(a), lb = big_len(b);
+    if (la != lb) return la < lb ? -1 : 1;
+    for (u32 i = la; i > 0; i--) {
+        u32 x = big_limb(a, i - 1), y = big_limb(b, i - 1);
+        if (x != y) return x < y ? -1 : 1;
+    }
+    return 0;
+}
+
+// the magnitudes of `a` and `b` added into `out`, which has room for one more limb than the longer of them
+u32 mag_add(u8 *out, const u8 *a, const u8 *b) {
+    u32 n = big_len(a) > big_len(b) ? big_len(a) : big_len(b);
+    u64 carry = 0;
+    for (u32 i = 0; i < n; i++) {
+        u64 x = (u64)big_limb(a, i) + big_limb(b, i) + carry;
+        set_limb(out, i, (u32)x);
+        carry = x >> 32;
+    }
+    set_limb(out, n, (u32)carry);
+    return n + 1;
+}
+
+// the magnitude of `b` taken from the magnitude of `a` into `out`, when `a`'s is at least `b`'s
+u32 mag_sub(u8 *out, const u8 *a, const u8 *b) {
+    u32 n = big_len(a);
+    u64 borrow = 0;
+    for (u32 i = 0; i < n; i++) {
+        u64 x = (u64)big_limb(a, i) - big_limb(b, i) - borrow;
+        set_limb(out, i, (u32)x);
+        borrow = x >> 63;
+    }
+    return n;
+}
+
+// `a` plus `b`, or minus `b` if `flip` is set, into `out`, which has room for one more limb than the longer of them
+void big_add(u8 *out, const u8 *a, const u8 *b, u32 flip) {
+    u32 na = big_negative(a), nb = big_negative(b) ^ flip;
+    if (na == nb) {
+        big_finish(out, na, mag_add(out, a, b));
+    } else if (mag_cmp(a, b) >= 0) {
+        big_finish(out, na, mag_sub(out, a, b));
+    } else {
+        big_finish(out, nb, mag_sub(out, b, a));
+    }
+}
+
+void big_mul(u8 *out, const u8 *a, const u8 *b) {
+    u32 la = big_len(a), lb = big_len(b);
+    memset(out + BIG_HEADER, 0, 4 * ((size_t)la + lb));
+    for (u32 i = 0; i < la; i++) {
+        u64 carry = 0;
+        for (u32 j = 0; j < lb; j++) {
+            u32 limb;
+            memcpy(&limb, out + BIG_HEADER + 4 * ((size_t)i + j), sizeof(limb));
+            u64 x = (u64)big_limb(a, i) * big_limb(b, j) + limb + carry;
+            set_limb(out, i + j, (u32)x);
+            carry = x >> 32;
+        }
+        set_limb(out, i + lb, (u32)carry);
+    }
+    big_finish(out, big_negative(a) ^ big_negative(b), la + lb);
+}
+
+// `a` divided by `b`, which isn't 0, truncating like C does.
+// The quotient goes in `quot`, with room for as many limbs as `a`, or the remainder goes in `rem`,
+// with room for as many limbs as `b`, whichever isn't NULL. This is long division a bit at a time,
+// with the running remainder in memory of its own.
+void big_div(u8 *quot, u8 *rem, const u8 *a, const u8 *b) {
+    u32 la = big_len(a), lb = big_len(b);
+    u32 *r = calloc((size_t)lb + 1, sizeof(u32));
+    if (quot != NULL) memset(quot + BIG_HEADER, 0, 4 * (size_t)la);
+    for (size_t bit = 32 * (size_t)la; bit > 0; bit--) {
+        // r = r * 2 + the next bit of a
+        u32 carry = (big_limb(a, (bit - 1) / 32) >> ((bit - 1) % 32)) & 1;
+        for (u32 i = 0; i <= lb; i++) {
+            u32 top = r[i] >> 31;
+            r[i] = (r[i] << 1) | carry;
+            carry = top;
+        }
+        // if r >= b, take b away and set the bit of the quotient
+        int ge = r[lb] != 0;
+        if (!ge) {
+            ge = 1;
+            for (u32 i = lb; i > 0; i--) {
+                if (r[i - 1] != big_limb(b, i - 1)) {
+                    ge = r[i - 1] > big_limb(b, i - 1);
+                    break;
+                }
+            }
+        }
+        if (ge) {
+            u64 borrow = 0;
+            for (u32 i = 0; i <= lb; i++) {
+                u64 x = (u64)r[i] - big_limb(b, i) - borrow;
+                r[i] = (u32)x;
+                borrow = x >> 63;
+            }
+            if (quot != NULL) {
+                u32 limb;
+                memcpy(&limb, quot + BIG_HEADER + 4 * ((bit - 1) / 32), sizeof(limb));
+                set_limb(quot, (bit - 1) / 32, limb | (1u << ((bit - 1) % 32)));
+            }
+        }
+    }
+    if (quot != NULL) big_finish(quot, big_negative(a) ^ big_negative(b), la);
+    if (rem != NULL) {
+        memcpy(rem + BIG_HEADER, r, 4 * (size_t)lb);
+        big_finish(rem, big_negative(a), lb);
+    }
+    free(r);
+}
+
+// A host resource: a u32 that says what kind it is, the host's i32 id for it, and the region it's in,
+// which is counted as holding one more resource once the host gives the id.
+// The program can't look inside, so only the host ever sees the id.
+#define RESOURCE_SIZE 16
+#define RESOURCE_REGION 8
+
+// Maps, from i32 keys to values of the size in each map op's immediate.
+// A map is a header object and a table, another object in the same region. Each of the table's slots is a u32 state,
+// the i32 key, and the value, and a key is found by probing from its hash to the next empty slot.
+// Once the table is 3/4 taken (by keys or by keys that were removed) it's replaced with a new one,
+// and the old one stays in the region until the region is freed, like everything else in it.
+typedef struct {
+    u64 len;  // how many keys there are
+    u64 used; // how many slots aren't empty
+    u64 cap;  // how many slots there are, a power of 2
+    Region *rgn;
+    Pointer table;
+} Map;
+
+#define MAP_EMPTY 0
+#define MAP_FULL 1
+#define MAP_REMOVED 2
+#define MAP_START_CAP 8
+
+u8 *map_slot(const Map *m, u64 i, u64 val_size) {
+    return m->table.reference + i * (8 + val_size);
+}
+
+u32 slot_state(const u8 *slot) {
+    u32 state;
+    memcpy(&state, slot, sizeof(state));
+    return state;
+}
+
+// the slot with `key`, or if there isn't one, the slot it should go in, which is the first removed one on the way if there is one
+u8 *map_find(const Map *m, i32 key, u64 val_size) {
+    u32 h = (u32)key * 0x9E3779B1u;
+    u8 *removed = NULL;
+    // the table always has an empty slot, so this ends
+    for (u64 i = (h ^ (h >> 16)) & (m->cap - 1);; i = (i + 1) & (m->cap - 1)) {
+        u8 *slot = map_slot(m, i, val_size);
+        u32 state = slot_state(slot);
+        if (state == MAP_EMPTY) return removed != NULL ? removed : slot;
+        if (state == MAP_REMOVED) {
+            if (removed == NULL) removed = slot;
+            continue;
+        }
+        i32 k;
+        memcpy(&k, slot + 4, sizeof(k));
+        if (k == key) return slot;
+    }
+}
+
+// whether `slot` is where `key` is
+int slot_has(const u8 *slot, i32 key) {
+    i32 k;
+    memcpy(&k, slot + 4, sizeof(k));
+    return slot_state(slot) == MAP_FULL && k == key;
+}
+
+// a new table for `m` of `cap` slots, or 0 if its region doesn't have room
+int map_table(Instance *inst, Map *m, u64 cap, u64 val_size) {
+    u64 size = cap * (8 + val_size);
+    Pointer table = alloc_in(inst, m->rgn, size);
+    if (table.reference == NULL) return 0;
+    memset(table.reference, 0, size);
+    unpoison(inst, table, 0, size);
+    m->table = table;
+    m->cap = cap;
+    m->used = 0;
+    return 1;
+}
+
+// make sure `m` has room for one more key, or give 0 if its region doesn't
+int map_reserve(Instance *inst, Map *m, u64 val_size) {
+    if ((m->used + 1) * 4 <= m->cap * 3) return 1;
+    Map old = *m;
+    // removed keys aren't copied over, so if they're what's taking up the table it doesn't have to grow
+    u64 cap = (m->len + 1) * 2 > m->cap ? m->cap * 2 : m->cap;
+    if (!map_table(inst, m, cap, val_size)) return 0;
+    for (u64 i = 0; i < old.cap; i++) {
+        u8 *slot = map_slot(&old, i, val_size);
+        if (slot_state(slot) != MAP_FULL) continue;
+        i32 key;
+        memcpy(&key, slot + 4, sizeof(key));
+        memcpy(map_find(m, key, val_size), slot, 8 + val_size);
+        m->used++;
+    }
+    return 1;
+}
 
-int post_task(Handler h) {
-    if (scheduler_len == 255) return 0;
-    scheduler[scheduler_len++] = h;
+int post_task(Instance *inst, Handler h) {
+    if (inst->scheduler_len == 255) return 0;
+    inst->scheduler[inst->scheduler_len++] = h;
     return 1;
 }
 
-u8 waiting = 0;
-Handler stdin_handler = {0};
-Region *stdin_rgn = NULL;
-Pointer stdin_str_ptr = {0, NULL};
-Handler stdout_handler = {0};
-Handler stderr_handler = {0};
+// SIGIO is process-wide, so only one instance at a time can be reading stdin.
+// Instances run on any thread, so the first `read` takes it with a compare-and-swap, and any other instance's traps,
+// until the owner is freed. The handler counts itself in `stdin_handlers`, so freeing the owner waits for it to be done.
+static Instance *stdin_owner = NULL;
+static u32 stdin_handlers = 0;
 
 void handle_stdin() {
+    __atomic_add_fetch(&stdin_handlers, 1, __ATOMIC_ACQ_REL);
+    Instance *inst = __atomic_load_n(&stdin_owner, __ATOMIC_ACQUIRE);
+    if (inst == NULL) {
+        __atomic_sub_fetch(&stdin_handlers, 1, __ATOMIC_ACQ_REL);
+        return;
+    }
     ssize_t bytes;
     char buffer[1024];
     // Read all available input
     while ((bytes = read(STDIN_FILENO, buffer, sizeof(buffer))) > 0) {
-        Pointer ptr = alloc_object(stdin_rgn, bytes + sizeof(bytes));
+        Pointer ptr = alloc_in(inst, inst->stdin_rgn, bytes + sizeof(bytes));
+        // input that doesn't fit in the region is dropped, since there's no op to trap
+        if (ptr.reference == NULL) continue;
         memcpy(ptr.reference, &bytes, sizeof(bytes));
         memcpy(ptr.reference + sizeof(bytes), buffer, bytes);
         Handler h;
-        memcpy(&h, &stdin_handler, sizeof(h));
+        memcpy(&h, &inst->stdin_handler, sizeof(h));
         memcpy(h.param, &ptr, sizeof(ptr));
         h.param_size = sizeof(ptr);
-        if (post_task(h)) {
+        if (!post_task(inst, h)) {
             printf("failed to post stdin handler to scheduler\n");
             exit(1);
         }
-        waiting &= 0b11111110;
+        inst->waiting &= 0b11111110;
+    }
+    __atomic_sub_fetch(&stdin_handlers, 1, __ATOMIC_ACQ_REL);
+}
+
+// the pause flag of instances the embedder never pauses
+static const u8 never_paused = 0;
+
+Instance *vm_instance_new(const u8 *interrupt) {
+    Instance *inst = calloc(1, sizeof(Instance));
+    inst->interrupt = interrupt;
+    inst->pause = &never_paused;
+    vm_instance_set_regions(inst, REGION_MALLOC, 64 * 1024, 2, 4096);
+#ifdef SVM_PROFILE
+    vm_instance_reset_profile(inst);
+#endif
+    inst->stack = malloc(sizeof(struct Stack));
+    inst->stack->last = NULL;
+    return inst;
+}
+
+void vm_instance_set_pause(Instance *inst, const u8 *pause) {
+    inst->pause = pause;
+}
+
+void vm_instance_set_paranoid(Instance *inst, u8 paranoid) {
+    inst->paranoid = paranoid;
+}
+
+void vm_instance_set_checked(Instance *inst, u8 checked) {
+    inst->checked = checked;
+}
+
+void vm_instance_set_regions(Instance *inst, u8 strategy, u64 chunk_size, u32 growth, u64 large_region) {
+    struct Regions *rs = &inst->regions;
+    rs->strategy = strategy;
+    rs->chunk_size = chunk_size;
+    rs->growth = growth < 1 ? 1 : growth;
+    rs->large_region = large_region;
+    rs->next_chunk = strategy == REGION_ADAPTIVE ? 0 : chunk_size;
+}
+
+void vm_instance_region_stats(Instance *inst, RegionStats *stats) {
+    *stats = inst->regions.stats;
+}
+
+void vm_instance_free(Instance *inst) {
+    Instance *owner = inst;
+    if (__atomic_compare_exchange_n(&stdin_owner, &owner, NULL, 0, __ATOMIC_ACQ_REL, __ATOMIC_ACQUIRE)) {
+        while (__atomic_load_n(&stdin_handlers, __ATOMIC_ACQUIRE) > 0) usleep(100);
+    }
+    struct Stack *stack = inst->stack;
+    while (stack != NULL) {
+        struct Stack *last = stack->last;
+        free(stack);
+        stack = last;
+    }
+    stack = inst->spare_chunks;
+    while (stack != NULL) {
+        struct Stack *last = stack->last;
+        free(stack);
+        stack = last;
+    }
+    Region *r = inst->freed_regions;
+    while (r != NULL) {
+        Region *next = r->next_freed;
+        if (!r->pooled) free(r);
+        r = next;
+    }
+    struct Chunk *c = inst->regions.chunks;
+    while (c != NULL) {
+        struct Chunk *next = c->next;
+        free(c);
+        c = next;
+    }
+    free(inst);
+}
+
+// Run scheduled tasks until none are left and nothing is waiting on IO.
+int run_scheduler(Instance *inst, u8 instrs[]) {
+    struct Stack *stack = inst->stack;
+    while (1) {
+        while (inst->scheduler_len > 0) {
+            SCHEDULER_SAFEPOINT();
+            Handler h = inst->scheduler[--inst->scheduler_len];
+            memcpy(stack->data + inst->sp, &h.param, h.param_size);
+            inst->sp += h.param_size;
+            memcpy(stack->data + inst->sp, &h.env, sizeof(h.env));
+            inst->sp += sizeof(h.env);
+            int err = eval(inst, instrs, h.f, inst->sp + h.param_size + sizeof(h.env), inst->data_section_size, stack);
+            if (err) return err;
+        }
+        dbg("waiting: %d\nscheduler_len: %d\n", inst->waiting, inst->scheduler_len);
+        while (inst->scheduler_len == 0 && inst->waiting) {
+            SCHEDULER_SAFEPOINT();
+            usleep(10000);
+        }
+        if (!inst->waiting && inst->scheduler_len == 0) {
+            return 0;
+        }
     }
-    
 }
 
-u8 vm_function(u8 instrs[]) {
+int vm_instance_run(Instance *inst, u8 instrs[], const u8 *args, u32 args_size) {
     // for (u32 i = 0; i < instrs_len; i++) {
     //     dbg(" %d", instrs[i]);
     // }
     // dbg("\n");
     u32 data_section_size;
     memcpy(&data_section_size, instrs, sizeof(data_section_size));
-    dbg("data section size: %lu\n", data_section_size);
-    u32 pc = sizeof(data_section_size) + data_section_size;
+    return vm_instance_start(inst, instrs, sizeof(data_section_size) + data_section_size, args, args_size);
+}
+
+int vm_instance_start(Instance *inst, u8 instrs[], u32 pc, const u8 *args, u32 args_size) {
+    memcpy(&inst->data_section_size, instrs, sizeof(inst->data_section_size));
+    dbg("data section size: %lu\n", inst->data_section_size);
     dbg("pc: %lu\n", pc);
-    u32 sp = 0;
-    struct Stack *stack = malloc(sizeof(struct Stack));
+    inst->sp = 0;
+    inst->call_count = 0;
+    inst->scheduler_len = 0;
+    inst->waiting = 0;
+    inst->minting.reference = NULL;
 
-    int flags = fcntl(STDIN_FILENO, F_GETFL, 0);
-    fcntl(STDIN_FILENO, F_SETFL, flags | O_NONBLOCK | O_ASYNC);
-    fcntl(STDIN_FILENO, __F_SETOWN, getpid());
-    signal(SIGIO, handle_stdin);
+    // the first function runs first, with its arguments and nothing else on the stack,
+    // since a task from the scheduler would also get a handler's environment.
+    // the verifier only allows i32 arguments, and few enough that they fit in the first chunk.
+    memcpy(inst->stack->data, args, args_size);
+    int err = eval(inst, instrs, pc, args_size, inst->data_section_size, inst->stack);
+    if (err) return err;
+    return run_scheduler(inst, instrs);
+}
 
-    Handler on_start = (Handler){.f=pc};
-    post_task(on_start); // guaranteed to succeed; no failure check here
-    while (1) {
-        while (scheduler_len > 0) {
-            Handler h = scheduler[--scheduler_len];
-            memcpy(stack->data + sp, &h.param, h.param_size);
-            sp += h.param_size;
-            memcpy(stack->data + sp, &h.env, sizeof(h.env));
-            sp += sizeof(h.env);
-            u8 err = eval(instrs, h.f, sp + h.param_size + sizeof(h.env), data_section_size, stack);
-            if (err) return err;
-        }
-        dbg("waiting: %d\nscheduler_len: %d\n", waiting);
-        while (scheduler_len == 0 && waiting) usleep(10000);
-        if (!waiting && scheduler_len == 0) {
-            return 0;
-        }
+int vm_instance_resume(Instance *inst, u8 instrs[], i32 val) {
+    u32 sp = inst->suspended_sp;
+    struct Stack *stack = inst->suspended_stack;
+    Pointer res = inst->minting;
+    if (res.reference != NULL) {
+        memcpy(res.reference + sizeof(u32), &val, sizeof(val));
+        Region *r;
+        memcpy(&r, res.reference + RESOURCE_REGION, sizeof(r));
+        r->resources++;
+        unpoison(inst, res, 0, RESOURCE_SIZE);
+        inst->minting.reference = NULL;
+        ensure_size(inst, &stack, &sp, sizeof(res));
+        PUSH(Pointer, res);
+    } else {
+        ensure_size(inst, &stack, &sp, sizeof(val));
+        PUSH(i32, val);
+    }
+    int err = eval(inst, instrs, inst->suspended_pc, sp, inst->data_section_size, stack);
+    if (err || inst->callback != NULL) return err;
+    return run_scheduler(inst, instrs);
+}
+
+int vm_instance_continue(Instance *inst, u8 instrs[]) {
+    // paused between tasks, so there's no instruction to go back to
+    if (inst->suspended_pc == 0) return run_scheduler(inst, instrs);
+    int err = eval(inst, instrs, inst->suspended_pc, inst->suspended_sp, inst->data_section_size, inst->suspended_stack);
+    if (err || inst->callback != NULL) return err;
+    return run_scheduler(inst, instrs);
+}
+
+int vm_instance_call(Instance *inst, u8 instrs[], u32 f, const u8 *args, u32 args_size) {
+    struct Callback *cb = malloc(sizeof(struct Callback));
+    cb->suspended_pc = inst->suspended_pc;
+    cb->suspended_sp = inst->suspended_sp;
+    cb->suspended_stack = inst->suspended_stack;
+    cb->yielded = inst->yielded;
+    cb->host_func = inst->host_func;
+    cb->minting = inst->minting;
+    inst->minting.reference = NULL;
+    cb->outer = inst->callback;
+    inst->callback = cb;
+    struct Stack *stack = inst->spare_chunks;
+    if (stack != NULL) {
+        inst->spare_chunks = stack->last;
+    } else {
+        stack = malloc(sizeof(struct Stack));
     }
+    stack->last = NULL;
+    cb->stack = stack;
+    // the callback only takes i32s, so like the entry function's, its arguments fit in one chunk
+    memcpy(stack->data, args, args_size);
+    return eval(inst, instrs, f, args_size, inst->data_section_size, stack);
 }
 
-u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack) {
+void vm_instance_return(Instance *inst) {
+    struct Callback *cb = inst->callback;
+    // everything from where the callback stopped down to its first chunk goes back on the spare list.
+    // if it stopped without saying where (an internal error), only the first chunk is known to be its own.
+    struct Stack *top = inst->suspended_stack;
+    while (top != NULL && top != cb->stack) top = top->last;
+    struct Stack *stack = top == NULL ? cb->stack : inst->suspended_stack;
+    while (stack != NULL) {
+        struct Stack *last = stack->last;
+        stack->last = inst->spare_chunks;
+        inst->spare_chunks = stack;
+        stack = last;
+    }
+    inst->suspended_pc = cb->suspended_pc;
+    inst->suspended_sp = cb->suspended_sp;
+    inst->suspended_stack = cb->suspended_stack;
+    inst->yielded = cb->yielded;
+    inst->host_func = cb->host_func;
+    inst->minting = cb->minting;
+    inst->callback = cb->outer;
+    free(cb);
+}
+
+i32 vm_instance_yielded(Instance *inst) {
+    return inst->yielded;
+}
+
+u32 vm_instance_host_func(Instance *inst) {
+    return inst->host_func;
+}
+
+i64 vm_instance_minting(Instance *inst) {
+    if (inst->minting.reference == NULL) return -1;
+    u32 kind;
+    memcpy(&kind, inst->minting.reference, sizeof(kind));
+    return kind;
+}
+
+u64 vm_instance_minting_region(Instance *inst) {
+    if (inst->minting.reference == NULL) return 0;
+    Region *r;
+    memcpy(&r, inst->minting.reference + RESOURCE_REGION, sizeof(r));
+    return (u64)(uintptr_t)r;
+}
+
+u64 vm_instance_finalizing(Instance *inst) {
+    return (u64)(uintptr_t)inst->finalizing;
+}
+
+u32 vm_instance_stopped_pc(Instance *inst) {
+    return inst->suspended_pc;
+}
+
+size_t vm_instance_stack_size(Instance *inst) {
+    size_t size = 0;
+    u32 sp = inst->suspended_sp;
+    for (struct Stack *stack = inst->suspended_stack; stack != NULL; stack = stack->last) {
+        size += sp;
+        sp = stack->saved_sp;
+    }
+    return size;
+}
+
+void vm_instance_copy_stack(Instance *inst, u8 *out) {
+    // walk from the top chunk down, filling `out` from the end so the bottom of the stack comes first
+    size_t end = vm_instance_stack_size(inst);
+    u32 sp = inst->suspended_sp;
+    for (struct Stack *stack = inst->suspended_stack; stack != NULL; stack = stack->last) {
+        end -= sp;
+        memcpy(out + end, stack->data, sp);
+        sp = stack->saved_sp;
+    }
+}
+
+u32 vm_instance_calls(Instance *inst, u32 *out) {
+    u32 n = inst->call_count < CALL_HISTORY ? inst->call_count : CALL_HISTORY;
+    for (u32 i = 0; i < n; i++) {
+        out[i] = inst->calls[(inst->call_count - 1 - i) % CALL_HISTORY];
+    }
+    return n;
+}
+
+u8 vm_instance_tasks(Instance *inst, u32 *out) {
+    for (u8 i = 0; i < inst->scheduler_len; i++) {
+        out[i] = inst->scheduler[i].f;
+    }
+    return inst->scheduler_len;
+}
+
+// How one instruction gets to the next, picked at build time.
+// By default each one breaks out of the switch, and the loop switches on the next one.
+// With SVM_THREADED_DISPATCH (the `threaded-dispatch` feature), each one jumps straight to the next one's code
+// through a table, using the labels-as-values extension of GCC and Clang,
+// so every instruction has its own indirect jump for the branch predictor to learn.
+#ifdef SVM_THREADED_DISPATCH
+#define OP(n) case n: op_##n:
+#define OP_DEFAULT default: op_unknown:
+#define DISPATCH() \
+    { \
+        op_pc = pc; \
+        PROFILE_OP(instrs[pc]) \
+        goto *(instrs[pc] < OP_COUNT ? dispatch_table[instrs[pc]] : &&op_unknown); \
+    }
+#else
+#define OP(n) case n:
+#define OP_DEFAULT default:
+#define DISPATCH() break
+#endif
+
+int eval(Instance *inst, u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack) {
+#ifdef SVM_THREADED_DISPATCH
+    enum { OP_COUNT = 84 };
+    static void *const dispatch_table[OP_COUNT] = {
+        &&op_0, &&op_1, &&op_2, &&op_3, &&op_4, &&op_5, &&op_6, &&op_7,
+        &&op_8, &&op_9, &&op_10, &&op_11, &&op_12, &&op_13, &&op_14, &&op_15,
+        &&op_16, &&op_17, &&op_18, &&op_19, &&op_20, &&op_21, &&op_22, &&op_23,
+        &&op_24, &&op_25, &&op_26, &&op_27, &&op_28, &&op_29, &&op_30, &&op_31,
+        &&op_32, &&op_33, &&op_34, &&op_35, &&op_36, &&op_37, &&op_38, &&op_39,
+        &&op_40, &&op_41, &&op_42, &&op_43, &&op_44, &&op_45, &&op_46, &&op_47,
+        &&op_48, &&op_49, &&op_50, &&op_51, &&op_52, &&op_53, &&op_54, &&op_55,
+        &&op_56, &&op_57, &&op_58, &&op_59, &&op_60, &&op_61, &&op_62, &&op_63,
+        &&op_64, &&op_65, &&op_66, &&op_67, &&op_68, &&op_69, &&op_70, &&op_71,
+        &&op_72, &&op_73, &&op_74, &&op_75, &&op_76, &&op_77, &&op_78, &&op_79,
+        &&op_80, &&op_81, &&op_82, &&op_83
+    };
+#endif
+    PROFILE_ENTER()
     while (1) {
+        // where the current instruction starts, for traps
+        u32 op_pc = pc;
+        PROFILE_OP(instrs[pc])
         // dbg("pc: %d, sp: %d\n", pc, sp);
         // for (u32 i = 0; i < sp; i++) {
         //     dbg(" %d", stack->data[i]);
         // }
         // dbg("\n");
         switch (instrs[pc]) {
-        case 0: {
+        OP(0) {
             dbg("get!\n");
             pc++;
             INSTR_PARAM(size_t, offset);
             INSTR_PARAM(size_t, size);
-            ensure_size(&stack, &sp, size);
+            ensure_size(inst, &stack, &sp, size);
             struct Stack *stack2 = stack;
             u32 sp2 = sp;
             int i = 10;
             while (sp2 < offset + size && i > 0) {
                 dbg(" sp2: %u\n offset: %lu\n size: %lu\n saved sp: %u\n\n", sp2, offset, size, stack2->saved_sp);
-                offset -= sp2 + (STACK_CHUNK_SIZE - stack2->saved_sp);
+                // the value is in an earlier chunk, so skip past everything in this one
+                offset -= sp2;
                 sp2 = stack2->saved_sp;
                 stack2 = stack2->last;
                 i--;
@@ -226,78 +1041,90 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             }
             memcpy(stack->data + sp, stack2->data + sp2 - offset - size, size);
             sp += size;
-            break;
+            DISPATCH();
         }
-        case 1: {
+        OP(1) {
             dbg("init!\n");
             pc++;
             INSTR_PARAM(size_t, offset);
             INSTR_PARAM(size_t, size);
             INSTR_PARAM(size_t, tpl_size);
-            sp -= size;
-            memcpy(stack->data + sp - tpl_size + offset, stack->data + sp, size);
-            break;
+            u8 val[STACK_CHUNK_SIZE];
+            POP_BYTES(val, size);
+            PREV_CHUNK_IF_EMPTY();
+            memcpy(stack->data + sp - tpl_size + offset, val, size);
+            DISPATCH();
         }
-        case 2: {
+        OP(2) {
             dbg("init in-place!\n");
             pc++;
             INSTR_PARAM(size_t, offset);
             INSTR_PARAM(size_t, size);
-            Pointer ptr; 
-            sp -= size + sizeof(ptr);
-            memcpy(&ptr, stack->data + sp, sizeof(ptr));
+            u8 val[STACK_CHUNK_SIZE];
+            POP_BYTES(val, size);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
             check_ptr(ptr);
-            memcpy(ptr.reference + offset, stack->data + sp + sizeof(ptr), size);
+            memcpy(ptr.reference + offset, val, size);
+            unpoison(inst, ptr, offset, size);
             PUSH(Pointer, ptr);
-            break;
+            DISPATCH();
         }
-        case 3: {
+        OP(3) {
             dbg("malloc!\n");
             pc++;
             INSTR_PARAM(size_t, size);
             POP(Region*, handle);
-            ensure_size(&stack, &sp, sizeof(handle));
-            PUSH(Pointer, alloc_object(handle, size));
-            break;
+            CHECK_HANDLE(handle);
+            Pointer ptr = alloc_in(inst, handle, size);
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            ensure_size(inst, &stack, &sp, sizeof(handle));
+            PUSH(Pointer, ptr);
+            DISPATCH();
         }
-        case 4: {
+        OP(4) {
             dbg("alloca!\n");
             pc++;
             INSTR_PARAM(size_t, size);
-            ensure_size(&stack, &sp, size);
+            ensure_size(inst, &stack, &sp, size);
             sp += size;
-            break;
+            DISPATCH();
         }
-        case 5: {
+        OP(5) {
             dbg("projection!\n");
             pc++;
             INSTR_PARAM(size_t, offset);
             INSTR_PARAM(size_t, size);
             INSTR_PARAM(size_t, tpl_size);
+            PREV_CHUNK_IF_EMPTY();
             sp -= tpl_size;
-            memcpy(stack->data + sp, stack->data + sp + offset, size);
+            memmove(stack->data + sp, stack->data + sp + offset, size);
             sp += size;
-            break;
+            DISPATCH();
         }
-        case 6: {
+        OP(6) {
             dbg("projection in-place!\n");
             pc++;
             INSTR_PARAM(size_t, offset);
             INSTR_PARAM(size_t, size);
             POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
             check_ptr(ptr);
-            ensure_size(&stack, &sp, size);
+            if (is_poisoned(inst, ptr, offset, size)) TRAP(VM_TRAP_UNINITIALIZED);
+            ensure_size(inst, &stack, &sp, size);
             memcpy(stack->data + sp, ptr.reference + offset, size);
             sp += size;
-            break;
+            DISPATCH();
         }
-        case 7: {
+        OP(7) {
             dbg("call!\n");
+            SAFEPOINT();
+            RECORD_CALL();
             POP(u32, new_pc);
             pc = new_pc;
-            break;
+            DISPATCH();
         }
-        case 8: {
+        OP(8) {
             dbg("print!\n");
             pc++;
             POP(Pointer, ptr);
@@ -306,141 +1133,171 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
                 size_t size = (size_t)instrs + 4 + (size_t)data_section_size - (size_t)ptr.reference;
                 printf("%.*s", (int)size, ptr.reference);
             } else {
+                CHECK_LIVE(ptr);
                 check_ptr(ptr);
                 size_t array_len;
                 memcpy(&array_len, ptr.reference, sizeof(array_len));
                 printf("%.*s", (int)array_len, ptr.reference + sizeof(array_len));
             }
-            break;
+            DISPATCH();
         }
-        case 9: {
+        OP(9) {
             dbg("literal!\n");
             pc++;
             INSTR_PARAM(i32, lit);
-            ensure_size(&stack, &sp, sizeof(lit));
+            ensure_size(inst, &stack, &sp, sizeof(lit));
             PUSH(i32, lit);
-            break;
+            DISPATCH();
         }
-        case 10: {
+        OP(10) {
             dbg("global function!\n");
             pc++;
             INSTR_PARAM(u32, lit);
-            ensure_size(&stack, &sp, sizeof(lit));
+            ensure_size(inst, &stack, &sp, sizeof(lit));
             PUSH(u32, lit);
-            break;
+            DISPATCH();
         }
-        case 11: {
+        OP(11) {
             dbg("halt!\n");
             POP(u8, status_code);
+            // a callback that halts still has its stack freed from here
+            inst->suspended_sp = sp;
+            inst->suspended_stack = stack;
             return status_code;
-            break;
+            DISPATCH();
         }
-        case 12: {
+        OP(12) {
             dbg("new region!\n");
             pc++;
             INSTR_PARAM(size_t, size);
-            Region *r = new_region(size);
-            ensure_size(&stack, &sp, sizeof(r));
+            Region *r = new_region(inst, size);
+            ensure_size(inst, &stack, &sp, sizeof(r));
             PUSH(Region*, r);
-            break;
+            DISPATCH();
         }
-        case 13: {
+        OP(13) {
             dbg("free region!\n");
             pc++;
             POP(Region*, r);
-            free(r);
-            break;
+            u32 resources = r->resources;
+            if (inst->checked) {
+                if (r->generation < 0) TRAP(VM_TRAP_DOUBLE_FREE);
+                r->generation = -r->generation;
+                count_freed_region(&inst->regions, r);
+                r->next_freed = inst->freed_regions;
+                inst->freed_regions = r;
+            } else {
+                free_region(inst, r);
+            }
+            if (resources > 0) {
+                // the host closes them before the next instruction, so a region can't outlive what it holds
+                inst->finalizing = r;
+                inst->suspended_pc = pc;
+                inst->suspended_sp = sp;
+                inst->suspended_stack = stack;
+                return VM_FINALIZE;
+            }
+            DISPATCH();
         }
-        case 14: {
+        OP(14) {
             dbg("dereference pointer!\n");
             pc++;
             INSTR_PARAM(size_t, size);
             POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
             check_ptr(ptr);
-            ensure_size(&stack, &sp, size);
+            if (is_poisoned(inst, ptr, 0, size)) TRAP(VM_TRAP_UNINITIALIZED);
+            ensure_size(inst, &stack, &sp, size);
             memcpy(stack->data + sp, ptr.reference, size);
             sp += size;
-            break;
+            DISPATCH();
         }
-        case 15: {
+        OP(15) {
             dbg("new array!\n");
             pc++;
             INSTR_PARAM(size_t, elem_size);
             POP(i32, len);
             POP(Region*, r);
+            CHECK_HANDLE(r);
+            if (len < 0) TRAP(VM_TRAP_OUT_OF_BOUNDS);
             size_t size = elem_size * len;
             dbg("size: %ld\n", sizeof(size) + size);
-            Pointer ptr = alloc_object(r, sizeof(size) + size);
+            Pointer ptr = alloc_in(inst, r, sizeof(size) + size);
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
             memcpy(ptr.reference, &size, sizeof(size));
             memset(ptr.reference + sizeof(size), 0, size);
-            ensure_size(&stack, &sp, sizeof(ptr));
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
             PUSH(Pointer, ptr);
-            break;
+            DISPATCH();
         }
-        case 16: {
+        OP(16) {
             dbg("mutate array component!\n");
             pc++;
             INSTR_PARAM(size_t, elem_size);
             POP(i32, i);
-            Pointer ptr;
-            memcpy(&ptr, stack->data + sp - elem_size - sizeof(ptr), sizeof(ptr));
+            u8 val[STACK_CHUNK_SIZE];
+            POP_BYTES(val, elem_size);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
             size_t n = elem_size * i;
             size_t array_len;
             memcpy(&array_len, ptr.reference, sizeof(array_len));
-            if (n + elem_size > array_len) {
-                printf("Runtime Error! Array index out of bounds during an initialization.\n");
-                return 1;
+            if (i < 0 || n + elem_size > array_len) {
+                TRAP(VM_TRAP_OUT_OF_BOUNDS);
             }
-            memcpy(ptr.reference + sizeof(array_len) + n, stack->data + sp - elem_size, elem_size);
-            sp -= elem_size + sizeof(ptr);
+            memcpy(ptr.reference + sizeof(array_len) + n, val, elem_size);
             PUSH(Pointer, ptr);
-            break;
+            DISPATCH();
         }
-        case 17: {
+        OP(17) {
             dbg("project from array!\n");
             pc++;
             INSTR_PARAM(size_t, elem_size);
             POP(i32, i);
             size_t n = elem_size * i;
             POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
             check_ptr(ptr);
             size_t array_len;
             memcpy(&array_len, ptr.reference, sizeof(array_len));
-            if (n + elem_size > array_len) {
-                printf("Runtime Error! Array index out of bounds during a projection.\n");
-                return 1;
+            if (i < 0 || n + elem_size > array_len) {
+                TRAP(VM_TRAP_OUT_OF_BOUNDS);
             }
-            ensure_size(&stack, &sp, elem_size);
+            ensure_size(inst, &stack, &sp, elem_size);
             memcpy(stack->data + sp, ptr.reference + sizeof(array_len) + n, elem_size);
             sp += elem_size;
-            break;
+            DISPATCH();
         }
-        case 18: {
+        OP(18) {
             dbg("add two i32s!\n");
             pc++;
             POP(i32, a);
             POP(i32, b);
-            PUSH(i32, a + b);
-            break;
+            // signed overflow is undefined in C, so wrapping arithmetic is done unsigned
+            PUSH(i32, (i32)((u32)a + (u32)b));
+            DISPATCH();
         }
-        case 19: {
+        OP(19) {
             dbg("multiply two i32s!\n");
             pc++;
             POP(i32, a);
             POP(i32, b);
-            PUSH(i32, a * b);
-            break;
+            PUSH(i32, (i32)((u32)a * (u32)b));
+            DISPATCH();
         }
-        case 20: {
+        OP(20) {
             dbg("divide two i32s!\n");
             pc++;
             POP(i32, a);
             POP(i32, b);
-            PUSH(i32, b / a);
-            break;
+            if (a == 0) TRAP(VM_TRAP_DIVIDE_BY_ZERO);
+            PUSH(i32, b == INT32_MIN && a == -1 ? INT32_MIN : b / a);
+            DISPATCH();
         }
-        case 21: {
+        OP(21) {
             dbg("call if not zero!\n");
+            SAFEPOINT();
+            RECORD_CALL();
             POP(u32, f);
             POP(u32, g);
             POP(i32, cond);
@@ -450,9 +1307,9 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
             } else {
                 pc = f;
             }
-            break;
+            DISPATCH();
         }
-        case 22: {
+        OP(22) {
             dbg("load from data section!\n");
             pc++;
             INSTR_PARAM(size_t, offset);
@@ -462,25 +1319,26 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
                 .generation = -1 
             };
             PUSH(Pointer, ptr);
-            break;
+            DISPATCH();
         }
-        case 23: {
+        OP(23) {
             dbg("project from data-section array!\n");
             pc++;
             INSTR_PARAM(size_t, elem_size);
             POP(i32, i);
             size_t n = elem_size * i;
             POP(Pointer, ptr); // frontend ensures this is a data-section pointer, so we don't need to check it.
-            if (n + elem_size > data_section_size) {
-                printf("Runtime Error! Array index out of bounds during a projection from the data section.\n");
-                return 1;
+            // the array goes from the pointer to the end of the data section
+            size_t array_len = instrs + 4 + data_section_size - ptr.reference;
+            if (i < 0 || n + elem_size > array_len) {
+                TRAP(VM_TRAP_OUT_OF_BOUNDS);
             }
-            ensure_size(&stack, &sp, elem_size);
+            ensure_size(inst, &stack, &sp, elem_size);
             memcpy(stack->data + sp, ptr.reference + n, elem_size);
             sp += elem_size;
-            break;
+            DISPATCH();
         }
-        case 24: {
+        OP(24) {
             dbg("copy n elements!\n");
             pc++;
             POP(i32, n);
@@ -498,145 +1356,779 @@ u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack)
                 }
                 src_ref = src_array.reference;
             } else {
+                CHECK_LIVE(src_array);
                 check_ptr(src_array);
                 size_t array_len;
                 memcpy(&array_len, src_array.reference, sizeof(array_len));
                 size = (size_t)n * elem_size;
-                if ((size_t)n > array_len) {
-                    size = array_len * elem_size;
+                if (size > array_len) {
+                    size = array_len;
                 }
                 src_ref = src_array.reference + sizeof(array_len);
             }
+            CHECK_LIVE(dest_array);
             size_t dest_array_len;
             memcpy(&dest_array_len, dest_array.reference, sizeof(dest_array_len));
-            if (n < 0) {
-                printf("Runtime Error! Negative size (%d) during a copy.\n", n);
-                return 1;
-            } else if (dest_array_len < (u32)n) {
-                printf("Runtime Error! Copy (%d) out of bounds for array of size %lu.\n", n, dest_array_len);
-                return 1;
+            if (n < 0 || dest_array_len < (size_t)n * elem_size) {
+                TRAP(VM_TRAP_OUT_OF_BOUNDS);
             }
             memcpy(dest_array.reference + sizeof(size), src_ref, size);
             PUSH(Pointer, dest_array);
             dbg("%.*s\n", (int)size, dest_array.reference + sizeof(size));
-            break;
+            DISPATCH();
         }
-        case 25: {
+        OP(25) {
             dbg("u8 literal!\n");
             pc++;
             INSTR_PARAM(u8, val);
-            ensure_size(&stack, &sp, sizeof(val));
+            ensure_size(inst, &stack, &sp, sizeof(val));
             PUSH(u8, val);
-            break;
+            DISPATCH();
         }
-        case 26: {
+        OP(26) {
             dbg("add u8!\n");
             pc++;
             POP(u8, a);
             POP(u8, b);
             PUSH(u8, a + b);
-            break;
+            DISPATCH();
         }
-        case 27: {
+        OP(27) {
             dbg("multiply u8!\n");
             pc++;
             POP(u8, a);
             POP(u8, b);
             PUSH(u8, a * b);
-            break;
+            DISPATCH();
         }
-        case 28: {
+        OP(28) {
             dbg("divide u8!\n");
             pc++;
             POP(u8, a);
             POP(u8, b);
+            if (a == 0) TRAP(VM_TRAP_DIVIDE_BY_ZERO);
             PUSH(u8, b / a);
-            break;
+            DISPATCH();
         }
-        case 29: {
+        OP(29) {
             dbg("u8 to i32!\n");
             pc++;
             POP(u8, a);
             PUSH(i32, a);
-            break;
+            DISPATCH();
         }
-        case 30: {
+        OP(30) {
             dbg("modulo i32!\n");
             pc++;
             POP(i32, a);
             POP(i32, b);
-            PUSH(i32, b % a);
-            break;
+            if (a == 0) TRAP(VM_TRAP_DIVIDE_BY_ZERO);
+            PUSH(i32, b == INT32_MIN && a == -1 ? 0 : b % a);
+            DISPATCH();
         }
-        case 31: {
+        OP(31) {
             dbg("modulo u8!\n");
             pc++;
             POP(u8, a);
             POP(u8, b);
+            if (a == 0) TRAP(VM_TRAP_DIVIDE_BY_ZERO);
             PUSH(u8, b % a);
-            break;
+            DISPATCH();
         }
-        case 32: {
+        OP(32) {
             dbg("i32 to u8!\n");
             pc++;
             POP(i32, a);
             PUSH(u8, a);
-            break;
+            DISPATCH();
         }
-        case 33: {
+        OP(33) {
             dbg("read!\n");
             pc++;
             INSTR_PARAM(u8, c);
             switch (c) {
                 case 0: {
                     POP(Region*, r);
+                    CHECK_HANDLE(r);
                     POP(Pointer, env);
                     POP(u32, handler);
-                    stdin_handler.f = handler;
-                    stdin_handler.env = env;
-                    stdin_rgn = r;
-                    waiting |= 0b1;
+                    inst->stdin_handler.f = handler;
+                    inst->stdin_handler.env = env;
+                    inst->stdin_rgn = r;
+                    inst->waiting |= 0b1;
+                    Instance *owner = NULL;
+                    if (__atomic_compare_exchange_n(&stdin_owner, &owner, inst, 0, __ATOMIC_ACQ_REL, __ATOMIC_ACQUIRE)) {
+                        int flags = fcntl(STDIN_FILENO, F_GETFL, 0);
+                        fcntl(STDIN_FILENO, F_SETFL, flags | O_NONBLOCK | O_ASYNC);
+                        fcntl(STDIN_FILENO, __F_SETOWN, getpid());
+                        signal(SIGIO, handle_stdin);
+                    } else if (owner != inst) {
+                        inst->waiting &= 0b11111110;
+                        TRAP(VM_TRAP_STDIN_BUSY);
+                    }
                     break;
                 }
             }
-            break;
+            DISPATCH();
         }
-        case 34: {
+        OP(34) {
             dbg("write!\n");
             pc++;
             INSTR_PARAM(u8, c);
             switch (c) {
                 case 0: {
                     POP(Region*, r);
+                    CHECK_HANDLE(r);
                     POP(u8, write_mode);
                     POP(Pointer, env);
                     POP(u32, handler);
                     POP(Pointer, str_ptr);
+                    CHECK_LIVE(str_ptr);
                     if (write_mode == 0) {
-                        stdout_handler.f = handler;
-                        stdout_handler.env = env;
+                        inst->stdout_handler.f = handler;
+                        inst->stdout_handler.env = env;
                         size_t len;
                         memcpy(&len, str_ptr.reference, sizeof(len));
                         printf("%.*s", (int)len, str_ptr.reference + sizeof(len));
-                        post_task(stdout_handler);
+                        post_task(inst, inst->stdout_handler);
                     } else if (write_mode == 1) {
-                        stderr_handler.f = handler;
-                        stderr_handler.env = env;
+                        inst->stderr_handler.f = handler;
+                        inst->stderr_handler.env = env;
                         size_t len;
                         memcpy(&len, str_ptr.reference, sizeof(len));
                         fprintf(stderr, "%.*s", (int)len, str_ptr.reference + sizeof(len));
-                        post_task(stderr_handler);
+                        post_task(inst, inst->stderr_handler);
                     } else {
                         printf("Internal SaberVM Error! Unknown write mode %d.\n", write_mode);
                         exit(1);
                     }
-                    // waiting |= 0b10;
+                    // inst->waiting |= 0b10;
                     break;
                 }
             }
-            break;
+            DISPATCH();
+        }
+        OP(35) {
+            dbg("yield!\n");
+            pc++;
+            POP(i32, val);
+            inst->yielded = val;
+            inst->suspended_pc = pc;
+            inst->suspended_sp = sp;
+            inst->suspended_stack = stack;
+            return VM_YIELDED;
+        }
+        OP(36) {
+            dbg("host call!\n");
+            pc++;
+            INSTR_PARAM(u32, f);
+            POP(i32, arg);
+            inst->host_func = f;
+            inst->yielded = arg;
+            inst->suspended_pc = pc;
+            inst->suspended_sp = sp;
+            inst->suspended_stack = stack;
+            return VM_HOST_CALL;
+        }
+        // the `_trap` versions of the arithmetic ops, which do the math wide enough that it can't overflow
+        OP(37) {
+            dbg("add two i32s, trapping on overflow!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            i64 r = (i64)b + a;
+            if (r < INT32_MIN || r > INT32_MAX) TRAP(VM_TRAP_OVERFLOW);
+            PUSH(i32, r);
+            DISPATCH();
+        }
+        OP(38) {
+            dbg("multiply two i32s, trapping on overflow!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            i64 r = (i64)b * a;
+            if (r < INT32_MIN || r > INT32_MAX) TRAP(VM_TRAP_OVERFLOW);
+            PUSH(i32, r);
+            DISPATCH();
+        }
+        OP(39) {
+            dbg("divide two i32s, trapping on overflow!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            if (a == 0) TRAP(VM_TRAP_DIVIDE_BY_ZERO);
+            if (b == INT32_MIN && a == -1) TRAP(VM_TRAP_OVERFLOW);
+            PUSH(i32, b / a);
+            DISPATCH();
+        }
+        OP(40) {
+            dbg("modulo i32, trapping on overflow!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            if (a == 0) TRAP(VM_TRAP_DIVIDE_BY_ZERO);
+            if (b == INT32_MIN && a == -1) TRAP(VM_TRAP_OVERFLOW);
+            PUSH(i32, b % a);
+            DISPATCH();
+        }
+        OP(41) {
+            dbg("add u8, trapping on overflow!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            if (b + a > UINT8_MAX) TRAP(VM_TRAP_OVERFLOW);
+            PUSH(u8, b + a);
+            DISPATCH();
+        }
+        OP(42) {
+            dbg("multiply u8, trapping on overflow!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            if (b * a > UINT8_MAX) TRAP(VM_TRAP_OVERFLOW);
+            PUSH(u8, b * a);
+            DISPATCH();
+        }
+        OP(43) {
+            dbg("subtract two i32s!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            PUSH(i32, (i32)((u32)b - (u32)a));
+            DISPATCH();
+        }
+        OP(44) {
+            dbg("subtract two i32s, trapping on overflow!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            i64 r = (i64)b - a;
+            if (r < INT32_MIN || r > INT32_MAX) TRAP(VM_TRAP_OVERFLOW);
+            PUSH(i32, r);
+            DISPATCH();
+        }
+        OP(45) {
+            dbg("subtract u8!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            PUSH(u8, b - a);
+            DISPATCH();
+        }
+        OP(46) {
+            dbg("subtract u8, trapping on overflow!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            if (a > b) TRAP(VM_TRAP_OVERFLOW);
+            PUSH(u8, b - a);
+            DISPATCH();
+        }
+        // the saturating ops, which clamp the wide result to the type
+        OP(47) {
+            dbg("add two i32s, saturating!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            i64 r = (i64)b + a;
+            PUSH(i32, r < INT32_MIN ? INT32_MIN : r > INT32_MAX ? INT32_MAX : r);
+            DISPATCH();
+        }
+        OP(48) {
+            dbg("subtract two i32s, saturating!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            i64 r = (i64)b - a;
+            PUSH(i32, r < INT32_MIN ? INT32_MIN : r > INT32_MAX ? INT32_MAX : r);
+            DISPATCH();
+        }
+        OP(49) {
+            dbg("multiply two i32s, saturating!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            i64 r = (i64)b * a;
+            PUSH(i32, r < INT32_MIN ? INT32_MIN : r > INT32_MAX ? INT32_MAX : r);
+            DISPATCH();
+        }
+        OP(50) {
+            dbg("add u8, saturating!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            int r = b + a;
+            PUSH(u8, r < 0 ? 0 : r > UINT8_MAX ? UINT8_MAX : r);
+            DISPATCH();
+        }
+        OP(51) {
+            dbg("subtract u8, saturating!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            int r = b - a;
+            PUSH(u8, r < 0 ? 0 : r > UINT8_MAX ? UINT8_MAX : r);
+            DISPATCH();
+        }
+        OP(52) {
+            dbg("multiply u8, saturating!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            int r = b * a;
+            PUSH(u8, r < 0 ? 0 : r > UINT8_MAX ? UINT8_MAX : r);
+            DISPATCH();
+        }
+        // the checked ops, which push the wrapped result and then whether it fit
+        OP(53) {
+            dbg("add two i32s, checked!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            i64 r = (i64)b + a;
+            PUSH(i32, (i32)((u32)b + (u32)a));
+            PUSH(i32, r >= INT32_MIN && r <= INT32_MAX);
+            DISPATCH();
+        }
+        OP(54) {
+            dbg("subtract two i32s, checked!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            i64 r = (i64)b - a;
+            PUSH(i32, (i32)((u32)b - (u32)a));
+            PUSH(i32, r >= INT32_MIN && r <= INT32_MAX);
+            DISPATCH();
+        }
+        OP(55) {
+            dbg("multiply two i32s, checked!\n");
+            pc++;
+            POP(i32, a);
+            POP(i32, b);
+            i64 r = (i64)b * a;
+            PUSH(i32, (i32)((u32)b * (u32)a));
+            PUSH(i32, r >= INT32_MIN && r <= INT32_MAX);
+            DISPATCH();
+        }
+        OP(56) {
+            dbg("add u8, checked!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            int r = b + a;
+            // the flag is wider than the operands it replaces
+            ensure_size(inst, &stack, &sp, sizeof(u8) + sizeof(i32));
+            PUSH(u8, r);
+            PUSH(i32, r >= 0 && r <= UINT8_MAX);
+            DISPATCH();
+        }
+        OP(57) {
+            dbg("subtract u8, checked!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            int r = b - a;
+            // the flag is wider than the operands it replaces
+            ensure_size(inst, &stack, &sp, sizeof(u8) + sizeof(i32));
+            PUSH(u8, r);
+            PUSH(i32, r >= 0 && r <= UINT8_MAX);
+            DISPATCH();
+        }
+        OP(58) {
+            dbg("multiply u8, checked!\n");
+            pc++;
+            POP(u8, a);
+            POP(u8, b);
+            int r = b * a;
+            // the flag is wider than the operands it replaces
+            ensure_size(inst, &stack, &sp, sizeof(u8) + sizeof(i32));
+            PUSH(u8, r);
+            PUSH(i32, r >= 0 && r <= UINT8_MAX);
+            DISPATCH();
+        }
+        OP(59) {
+            dbg("nop!\n");
+            pc++;
+            DISPATCH();
+        }
+        OP(60) {
+            // markers are only there for tools reading the code, so skip over the number
+            dbg("marker!\n");
+            pc += 1 + sizeof(u32);
+            DISPATCH();
+        }
+        OP(61) {
+            // the start of a function that's verified the first time it's called.
+            // the next byte is set once it has been, and the host writes its code after that before setting it
+            dbg("lazy!\n");
+            if (__atomic_load_n(&instrs[pc + 1], __ATOMIC_ACQUIRE)) {
+                pc += 2;
+                DISPATCH();
+            }
+            inst->suspended_pc = pc;
+            inst->suspended_sp = sp;
+            inst->suspended_stack = stack;
+            return VM_VERIFY;
+        }
+        OP(62) {
+            dbg("big integer literal!\n");
+            pc++;
+            INSTR_PARAM(size_t, offset);
+            // like `data`, the literal is used where it is in the data section
+            Pointer ptr = (Pointer){.reference = instrs + 4 + offset, .generation = -1};
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(63) {
+            dbg("big integer from i32!\n");
+            pc++;
+            POP(i32, n);
+            POP(Region*, r);
+            CHECK_HANDLE(r);
+            Pointer ptr = alloc_in(inst, r, big_size(1));
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            // the magnitude is done unsigned, since INT32_MIN has no positive i32
+            set_limb(ptr.reference, 0, n < 0 ? 0u - (u32)n : (u32)n);
+            big_finish(ptr.reference, n < 0, 1);
+            unpoison(inst, ptr, 0, big_size(1));
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(64) {
+            dbg("add two big integers!\n");
+            pc++;
+            POP(Pointer, b);
+            POP(Pointer, a);
+            POP(Region*, r);
+            CHECK_LIVE(a);
+            CHECK_LIVE(b);
+            CHECK_HANDLE(r);
+            size_t size = big_size((size_t)(big_len(a.reference) > big_len(b.reference) ? big_len(a.reference) : big_len(b.reference)) + 1);
+            Pointer ptr = alloc_in(inst, r, size);
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            big_add(ptr.reference, a.reference, b.reference, 0);
+            unpoison(inst, ptr, 0, size);
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(65) {
+            dbg("subtract two big integers!\n");
+            pc++;
+            POP(Pointer, b);
+            POP(Pointer, a);
+            POP(Region*, r);
+            CHECK_LIVE(a);
+            CHECK_LIVE(b);
+            CHECK_HANDLE(r);
+            size_t size = big_size((size_t)(big_len(a.reference) > big_len(b.reference) ? big_len(a.reference) : big_len(b.reference)) + 1);
+            Pointer ptr = alloc_in(inst, r, size);
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            big_add(ptr.reference, a.reference, b.reference, 1);
+            unpoison(inst, ptr, 0, size);
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(66) {
+            dbg("multiply two big integers!\n");
+            pc++;
+            POP(Pointer, b);
+            POP(Pointer, a);
+            POP(Region*, r);
+            CHECK_LIVE(a);
+            CHECK_LIVE(b);
+            CHECK_HANDLE(r);
+            size_t size = big_size((size_t)big_len(a.reference) + big_len(b.reference));
+            Pointer ptr = alloc_in(inst, r, size);
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            big_mul(ptr.reference, a.reference, b.reference);
+            unpoison(inst, ptr, 0, size);
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(67) {
+            dbg("divide two big integers!\n");
+            pc++;
+            POP(Pointer, b);
+            POP(Pointer, a);
+            POP(Region*, r);
+            CHECK_LIVE(a);
+            CHECK_LIVE(b);
+            CHECK_HANDLE(r);
+            if (big_len(b.reference) == 0) TRAP(VM_TRAP_DIVIDE_BY_ZERO);
+            size_t size = big_size(big_len(a.reference));
+            Pointer ptr = alloc_in(inst, r, size);
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            big_div(ptr.reference, NULL, a.reference, b.reference);
+            unpoison(inst, ptr, 0, size);
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(68) {
+            dbg("modulo big integers!\n");
+            pc++;
+            POP(Pointer, b);
+            POP(Pointer, a);
+            POP(Region*, r);
+            CHECK_LIVE(a);
+            CHECK_LIVE(b);
+            CHECK_HANDLE(r);
+            if (big_len(b.reference) == 0) TRAP(VM_TRAP_DIVIDE_BY_ZERO);
+            size_t size = big_size(big_len(b.reference));
+            Pointer ptr = alloc_in(inst, r, size);
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            big_div(NULL, ptr.reference, a.reference, b.reference);
+            unpoison(inst, ptr, 0, size);
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(69) {
+            dbg("compare two big integers!\n");
+            pc++;
+            POP(Pointer, b);
+            POP(Pointer, a);
+            CHECK_LIVE(a);
+            CHECK_LIVE(b);
+            u32 na = big_negative(a.reference), nb = big_negative(b.reference);
+            i32 cmp = na != nb ? (na ? -1 : 1) : (na ? -mag_cmp(a.reference, b.reference) : mag_cmp(a.reference, b.reference));
+            PUSH(i32, cmp);
+            DISPATCH();
+        }
+        OP(70) {
+            dbg("big integer to i32!\n");
+            pc++;
+            POP(Pointer, a);
+            CHECK_LIVE(a);
+            u32 len = big_len(a.reference), mag = big_limb(a.reference, 0);
+            u32 negative = big_negative(a.reference);
+            if (len > 1 || mag > (negative ? (u32)INT32_MAX + 1 : (u32)INT32_MAX)) TRAP(VM_TRAP_OVERFLOW);
+            PUSH(i32, negative ? (i32)(0u - mag) : (i32)mag);
+            DISPATCH();
+        }
+        OP(71) {
+            dbg("new buffer!\n");
+            pc++;
+            POP(i32, len);
+            POP(Region*, r);
+            CHECK_HANDLE(r);
+            if (len < 0) TRAP(VM_TRAP_OUT_OF_BOUNDS);
+            // laid out like a u8 array, so `read` and `write` can take one
+            size_t size = len;
+            Pointer ptr = alloc_in(inst, r, sizeof(size) + size);
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            memcpy(ptr.reference, &size, sizeof(size));
+            memset(ptr.reference + sizeof(size), 0, size);
+            unpoison(inst, ptr, 0, sizeof(size) + size);
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(72) {
+            dbg("buffer length!\n");
+            pc++;
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            size_t len;
+            memcpy(&len, ptr.reference, sizeof(len));
+            PUSH(i32, len);
+            DISPATCH();
+        }
+        OP(73) {
+            dbg("get a u8 from a buffer!\n");
+            pc++;
+            POP(i32, i);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            size_t len;
+            memcpy(&len, ptr.reference, sizeof(len));
+            if (i < 0 || (size_t)i + 1 > len) TRAP(VM_TRAP_OUT_OF_BOUNDS);
+            PUSH(u8, ptr.reference[sizeof(len) + i]);
+            DISPATCH();
+        }
+        OP(74) {
+            dbg("set a u8 in a buffer!\n");
+            pc++;
+            POP(i32, i);
+            POP(u8, val);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            size_t len;
+            memcpy(&len, ptr.reference, sizeof(len));
+            if (i < 0 || (size_t)i + 1 > len) TRAP(VM_TRAP_OUT_OF_BOUNDS);
+            ptr.reference[sizeof(len) + i] = val;
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(75) {
+            dbg("get a u32 from a buffer!\n");
+            pc++;
+            POP(i32, i);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            size_t len;
+            memcpy(&len, ptr.reference, sizeof(len));
+            if (i < 0 || (size_t)i + 4 > len) TRAP(VM_TRAP_OUT_OF_BOUNDS);
+            // little-endian whatever the host is, since buffers are for binary formats
+            u8 *b = ptr.reference + sizeof(len) + i;
+            u32 val = (u32)b[0] | (u32)b[1] << 8 | (u32)b[2] << 16 | (u32)b[3] << 24;
+            PUSH(i32, (i32)val);
+            DISPATCH();
+        }
+        OP(76) {
+            dbg("set a u32 in a buffer!\n");
+            pc++;
+            POP(i32, i);
+            POP(i32, val);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            size_t len;
+            memcpy(&len, ptr.reference, sizeof(len));
+            if (i < 0 || (size_t)i + 4 > len) TRAP(VM_TRAP_OUT_OF_BOUNDS);
+            u8 *b = ptr.reference + sizeof(len) + i;
+            for (int k = 0; k < 4; k++) {
+                b[k] = (u32)val >> (8 * k);
+            }
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(77) {
+            dbg("new map!\n");
+            pc++;
+            INSTR_PARAM(size_t, val_size);
+            POP(Region*, r);
+            CHECK_HANDLE(r);
+            Pointer ptr = alloc_in(inst, r, sizeof(Map));
+            if (ptr.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            Map m = {0, 0, 0, r, {0, NULL}};
+            if (!map_table(inst, &m, MAP_START_CAP, val_size)) TRAP(VM_TRAP_REGION_FULL);
+            memcpy(ptr.reference, &m, sizeof(m));
+            unpoison(inst, ptr, 0, sizeof(m));
+            ensure_size(inst, &stack, &sp, sizeof(ptr));
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(78) {
+            dbg("get from a map!\n");
+            pc++;
+            INSTR_PARAM(size_t, val_size);
+            POP(i32, key);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            Map m;
+            memcpy(&m, ptr.reference, sizeof(m));
+            u8 *slot = map_find(&m, key, val_size);
+            // a key that isn't there is like an index past the end of an array
+            if (!slot_has(slot, key)) TRAP(VM_TRAP_OUT_OF_BOUNDS);
+            ensure_size(inst, &stack, &sp, val_size);
+            memcpy(stack->data + sp, slot + 8, val_size);
+            sp += val_size;
+            DISPATCH();
+        }
+        OP(79) {
+            dbg("set in a map!\n");
+            pc++;
+            INSTR_PARAM(size_t, val_size);
+            POP(i32, key);
+            u8 val[STACK_CHUNK_SIZE];
+            POP_BYTES(val, val_size);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            Map m;
+            memcpy(&m, ptr.reference, sizeof(m));
+            u8 *slot = map_find(&m, key, val_size);
+            if (!slot_has(slot, key)) {
+                if (!map_reserve(inst, &m, val_size)) TRAP(VM_TRAP_REGION_FULL);
+                slot = map_find(&m, key, val_size);
+                if (slot_state(slot) == MAP_EMPTY) m.used++;
+                u32 state = MAP_FULL;
+                memcpy(slot, &state, sizeof(state));
+                memcpy(slot + 4, &key, sizeof(key));
+                m.len++;
+                memcpy(ptr.reference, &m, sizeof(m));
+            }
+            memcpy(slot + 8, val, val_size);
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(80) {
+            dbg("is it in a map?\n");
+            pc++;
+            INSTR_PARAM(size_t, val_size);
+            POP(i32, key);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            Map m;
+            memcpy(&m, ptr.reference, sizeof(m));
+            PUSH(i32, slot_has(map_find(&m, key, val_size), key));
+            DISPATCH();
+        }
+        OP(81) {
+            dbg("remove from a map!\n");
+            pc++;
+            INSTR_PARAM(size_t, val_size);
+            POP(i32, key);
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            Map m;
+            memcpy(&m, ptr.reference, sizeof(m));
+            u8 *slot = map_find(&m, key, val_size);
+            if (slot_has(slot, key)) {
+                u32 state = MAP_REMOVED;
+                memcpy(slot, &state, sizeof(state));
+                m.len--;
+                memcpy(ptr.reference, &m, sizeof(m));
+            }
+            PUSH(Pointer, ptr);
+            DISPATCH();
+        }
+        OP(82) {
+            dbg("map length!\n");
+            pc++;
+            POP(Pointer, ptr);
+            CHECK_LIVE(ptr);
+            check_ptr(ptr);
+            Map m;
+            memcpy(&m, ptr.reference, sizeof(m));
+            PUSH(i32, m.len);
+            DISPATCH();
+        }
+        OP(83) {
+            dbg("host call making a resource!\n");
+            pc++;
+            INSTR_PARAM(u32, f);
+            INSTR_PARAM(u32, kind);
+            POP(i32, arg);
+            POP(Region*, r);
+            CHECK_HANDLE(r);
+            // made before the host is asked for it, so a full region doesn't leave the host with one the program never got
+            Pointer res = alloc_in(inst, r, RESOURCE_SIZE);
+            if (res.reference == NULL) TRAP(VM_TRAP_REGION_FULL);
+            memcpy(res.reference, &kind, sizeof(kind));
+            memcpy(res.reference + RESOURCE_REGION, &r, sizeof(r));
+            inst->minting = res;
+            inst->host_func = f;
+            inst->yielded = arg;
+            inst->suspended_pc = pc;
+            inst->suspended_sp = sp;
+            inst->suspended_stack = stack;
+            return VM_HOST_CALL;
         }
-        default: {
+        OP_DEFAULT {
             printf("internal error!! Unknown IR op %d, please let the SaberVM team know!!", instrs[pc]);
             return 1;
         }
@@ -27,6 +27,36 @@ typedef int32_t i32;
  */
 #define STACK_CHUNK_SIZE 4096
 
+/*
+ * How many of the most recent calls an instance remembers, for backtraces.
+ */
+#define CALL_HISTORY 16
+
+#ifdef SVM_PROFILE
+/*
+ * With SVM_PROFILE (the `profile` feature), one in this many instructions is timed, on average.
+ * The gap between samples is random, so a loop whose length divides the period doesn't keep timing the same instruction.
+ */
+#define PROFILE_SAMPLE_PERIOD 32
+
+/*
+ * Per-opcode counts and timings, indexed by the byte each instruction starts with.
+ * Every instruction is counted, but only the sampled ones are timed, in ticks of the CPU's cycle counter.
+ */
+struct Profile {
+    u64 counts[256];
+    u64 ticks[256];
+    u64 samples[256];
+    // the instruction being timed, and when it started, if `timing` is set
+    u8 timed_op;
+    u8 timing;
+    u64 started;
+    // how many more instructions until the next sample, and the state of the xorshift that picks the gap after that
+    u32 countdown;
+    u32 jitter;
+};
+#endif
+
 /*
  * A pointer to an object within a region.
  * The `generation` field is used to detect when a pointer becomes invalid.
@@ -37,17 +67,87 @@ typedef struct {
     u8 *reference;
 } Pointer;
 
+/*
+ * The verifier lays out values using the sizes from `Type::size` in header.rs,
+ * so the C representations have to agree with it.
+ */
+_Static_assert(sizeof(Pointer) == 16, "pointers and arrays are 16 bytes to the verifier");
+_Static_assert(sizeof(void*) == 8, "region handles are 8 bytes to the verifier");
+
 /*
  * A region (growable, nonmoving arena) of memory.
  * The type system ensures pointers into the region aren't dereferenced after the region is freed.
  * In the future I'll likely switch to a non-growing region where the size is given.
  */
-typedef struct {
+typedef struct Region {
     size_t offset;
     size_t capacity;
+    // only used in checked mode: like an object's generation, this is negated when the region is freed
+    i64 generation;
+    // in checked mode freed regions aren't given back, so stale handles and pointers still have something to check;
+    // they're kept on a list instead, until the instance is freed
+    struct Region *next_freed;
+    // whether it was carved out of one of the instance's chunks, rather than allocated on its own
+    u8 pooled;
+    // how many host resources were made in it, which the host finalizes when it's freed
+    u32 resources;
     u8 data[];
 } Region;
 
+/*
+ * How an instance gets the memory for its regions (see `RegionStrategy` in vm.rs).
+ * Pooled regions are carved out of chunks, which are only given back when the instance is freed,
+ * and a freed one goes on a list to be reused by the next region of the same size.
+ */
+#define REGION_MALLOC 0
+#define REGION_POOLED 1
+#define REGION_ADAPTIVE 2
+// how many small regions an adaptive instance makes on their own before it starts pooling them
+#define ADAPTIVE_AFTER 64
+#define POOL_BUCKETS 32
+
+struct Chunk {
+    struct Chunk *next;
+    size_t size;
+    size_t used;
+    u8 data[];
+};
+
+/*
+ * What an instance's regions have cost so far, kept whatever the strategy.
+ * Sizes are of whole blocks: a region's header and its bytes, rounded up to 16.
+ */
+typedef struct {
+    u64 made;
+    // how many were small enough to pool, and how many of those were carved out of a chunk or reused
+    u64 small;
+    u64 pooled;
+    // how many bytes of small regions are live now, and the most there have been at once
+    u64 small_live;
+    u64 small_peak;
+    u64 chunk_bytes;
+} RegionStats;
+
+struct Regions {
+    u8 strategy;
+    size_t chunk_size;
+    u32 growth;
+    // regions bigger than this are always allocated on their own
+    size_t large_region;
+    // how big the next chunk will be, or 0 for an adaptive instance that hasn't made one yet
+    size_t next_chunk;
+    // the newest first, which is the one regions are carved out of
+    struct Chunk *chunks;
+    // freed pooled regions, by block size
+    Region *free[POOL_BUCKETS];
+    RegionStats stats;
+};
+
+/*
+ * One chunk of the operand stack, which is just bytes.
+ * Values carry no tags: the verifier knows the type of everything on the stack,
+ * so each instruction's immediates already say how many bytes to move and from where.
+ */
 struct Stack {
     struct Stack *last;
     u32 saved_sp;
@@ -62,16 +162,82 @@ typedef struct {
 } Handler;
 
 /*
- * Allocate a new region.
+ * Where the run was stopped when a host function called back into the program,
+ * to go back to once the callback returns.
+ */
+struct Callback {
+    u32 suspended_pc;
+    u32 suspended_sp;
+    struct Stack *suspended_stack;
+    i32 yielded;
+    u32 host_func;
+    Pointer minting;
+    // the callback gets a stack of its own, so it can't touch the values under the host call
+    struct Stack *stack;
+    struct Callback *outer;
+};
+
+/*
+ * The per-run state of the VM.
+ * One verified module can be run by many instances, so nothing in here is shared between runs.
+ */
+typedef struct {
+    // set by the embedder (possibly from another thread) to stop the run at the next safepoint
+    const u8 *interrupt;
+    // set by the embedder to park the run at the next safepoint, shared by every instance it pauses together
+    const u8 *pause;
+    u32 data_section_size;
+    struct Stack *stack;
+    u32 sp;
+    // chunks the stack has moved back out of, to be reused before allocating new ones
+    struct Stack *spare_chunks;
+    // where to pick back up after a `yield`
+    u32 suspended_pc;
+    u32 suspended_sp;
+    struct Stack *suspended_stack;
+    // the argument to the last `yield` or `host_call`
+    i32 yielded;
+    u32 host_func;
+    // the resource a `host_res` made for the host to fill in, or a NULL reference if the host call was a `host_call`
+    Pointer minting;
+    // the region the last `free_rgn` that stopped with VM_FINALIZE freed, only to tell the host which resources it held
+    Region *finalizing;
+    Handler scheduler[255];
+    u8 scheduler_len;
+    u8 waiting;
+    Handler stdin_handler;
+    Region *stdin_rgn;
+    Handler stdout_handler;
+    Handler stderr_handler;
+    // poison fresh allocations and trap on uninitialized reads, as a check on the verifier
+    u8 paranoid;
+    // keep freed regions around and trap on any use of one, as another check on the verifier
+    u8 checked;
+    Region *freed_regions;
+    struct Regions regions;
+    // CPS calls never return, so there are no frames to walk;
+    // instead this rings through where the last CALL_HISTORY calls were made from
+    u32 calls[CALL_HISTORY];
+    u32 call_count;
+    // the innermost callback that's running, or NULL
+    struct Callback *callback;
+#ifdef SVM_PROFILE
+    struct Profile profile;
+#endif
+} Instance;
+
+/*
+ * Allocate a new region, the way the instance's strategy says to.
  * The type system ensures memory is written to before it is read,
  * so there's no need to initialize the memory.
  */
-Region *new_region();
+Region *new_region(Instance *inst, size_t size);
 
 /*
  * Allocate an object in a region 
  * The type system ensures it gets initialized before it is read,
  * so there's no need to initialize the memory.
+ * If there isn't room for it, the pointer's reference is NULL.
  */
 Pointer alloc_object(Region *r, u64 size);
 
@@ -89,17 +255,194 @@ void check_ptr(Pointer ptr);
 void free_object(Pointer ptr);
 
 /*
- * Free a region of memory.
+ * Free a region of memory, or keep it to be reused if it's pooled.
  * Static analysis is used to keep this safe, instead of generations.
  */
-void free_region(Region *r);
+void free_region(Instance *inst, Region *r);
+
+/*
+ * Traps and yields are returned from `eval` and `vm_instance_run` as negative numbers,
+ * so they can't be confused with the 0-255 status codes given to `halt`.
+ */
+#define VM_TRAP_INTERRUPTED (-1)
+#define VM_YIELDED (-2)
+#define VM_HOST_CALL (-3)
+#define VM_TRAP_UNINITIALIZED (-4)
+#define VM_TRAP_OUT_OF_BOUNDS (-5)
+#define VM_TRAP_USE_AFTER_FREE (-6)
+#define VM_TRAP_DOUBLE_FREE (-7)
+#define VM_TRAP_OVERFLOW (-8)
+#define VM_TRAP_DIVIDE_BY_ZERO (-9)
+// not a trap: the run reached a function that hasn't been verified yet, and can go on once it has
+#define VM_VERIFY (-10)
+// not a trap: the run stopped at a safepoint because the embedder paused it, and can go on once it's resumed
+#define VM_SAFEPOINT (-11)
+#define VM_TRAP_REGION_FULL (-12)
+// not a trap: a `free_rgn` freed a region with host resources in it, and the run can go on once the host has finalized them
+#define VM_FINALIZE (-13)
+#define VM_TRAP_STDIN_BUSY (-14)
+
+/*
+ * Allocate the state for a new run of a module.
+ * `interrupt` is an atomic flag owned by the embedder, checked at every safepoint.
+ */
+extern Instance *vm_instance_new(const u8 *interrupt);
+
+/*
+ * Use the given atomic flag, owned by the embedder, to pause the instance at its safepoints.
+ * Until this is called, the instance uses a flag that's never set.
+ */
+extern void vm_instance_set_pause(Instance *inst, const u8 *pause);
+
+/*
+ * Say how the instance gets the memory for its regions from now on, with one of the REGION_ strategies.
+ * Until this is called, every region is allocated on its own.
+ */
+extern void vm_instance_set_regions(Instance *inst, u8 strategy, u64 chunk_size, u32 growth, u64 large_region);
+
+/*
+ * Copy out what the instance's regions have cost so far.
+ */
+extern void vm_instance_region_stats(Instance *inst, RegionStats *stats);
+
+#ifdef SVM_PROFILE
+/*
+ * The cycle counter the profile is timed with, so the embedder can work out how long a tick is.
+ */
+extern u64 vm_profile_ticks(void);
+
+/*
+ * Copy out the instance's profile: 256 counts, 256 tick totals, and 256 sample counts, one for each opcode.
+ */
+extern void vm_instance_profile(Instance *inst, u64 *counts, u64 *ticks, u64 *samples);
+
+/*
+ * Start the instance's profile over.
+ */
+extern void vm_instance_reset_profile(Instance *inst);
+#endif
+
+/*
+ * The entry point: run the given linked bytecode on the given instance.
+ * The entry function's arguments are the `args_size` bytes at `args`, as they'd be laid out on the stack.
+ * Returns the status code given to `halt`, or a negative trap code.
+ */
+extern int vm_instance_run(Instance *inst, u8 instrs[], const u8 *args, u32 args_size);
+
+/*
+ * Like `vm_instance_run`, but starting at the function at code position `f` instead of the entry function.
+ */
+extern int vm_instance_start(Instance *inst, u8 instrs[], u32 f, const u8 *args, u32 args_size);
+
+/*
+ * Continue a run that stopped at a `yield`, pushing `val` as the result of the `yield`.
+ * At a `host_res`, `val` is the host's id for the resource instead, and the resource is pushed.
+ * Returns the same things as `vm_instance_run`.
+ * Inside a callback this only continues the callback, and the scheduler isn't run.
+ */
+extern int vm_instance_resume(Instance *inst, u8 instrs[], i32 val);
+
+/*
+ * Continue a run that stopped with VM_VERIFY, once the function it stopped at has been verified,
+ * or with VM_FINALIZE, once the host has finalized the freed region's resources.
+ * Like `vm_instance_resume`, but without pushing anything.
+ */
+extern int vm_instance_continue(Instance *inst, u8 instrs[]);
+
+/*
+ * From a host function, call the function at code position `f` with the `args_size` bytes at `args` on a fresh stack.
+ * The callback gives its result back with `yield`, so this returns VM_YIELDED, with the result in `vm_instance_yielded`.
+ * It can also stop at a host call, trap, or halt, like `vm_instance_run`.
+ * Either way, `vm_instance_return` has to be called once the callback is over.
+ */
+extern int vm_instance_call(Instance *inst, u8 instrs[], u32 f, const u8 *args, u32 args_size);
+
+/*
+ * End the innermost callback, freeing its stack and going back to the host call it was made from.
+ */
+extern void vm_instance_return(Instance *inst);
+
+/*
+ * The value given to the last `yield` or `host_call`.
+ */
+extern i32 vm_instance_yielded(Instance *inst);
+
+/*
+ * The host function named by the last `host_call`.
+ * A host call suspends the run just like a `yield`, and the host continues it with `vm_instance_resume`.
+ */
+extern u32 vm_instance_host_func(Instance *inst);
+
+/*
+ * The kind of resource the last host call makes, if it was a `host_res`, or -1 if it was a `host_call`.
+ */
+extern i64 vm_instance_minting(Instance *inst);
+
+/*
+ * The region the resource the last host call makes is in, if it was a `host_res`.
+ * Only the address matters, to match the resource with the `free_rgn` that frees it (see `vm_instance_finalizing`).
+ */
+extern u64 vm_instance_minting_region(Instance *inst);
+
+/*
+ * The region freed by the `free_rgn` the run stopped at with VM_FINALIZE.
+ * The host finalizes every resource it was given by a `host_res` into that region before continuing the run,
+ * since once the run goes on another region could get the same address.
+ */
+extern u64 vm_instance_finalizing(Instance *inst);
+
+/*
+ * Where the last run stopped, after a trap, `yield`, or host call.
+ * This is 0 if it stopped between tasks.
+ */
+extern u32 vm_instance_stopped_pc(Instance *inst);
+
+/*
+ * How many bytes were on the stack where the last run stopped.
+ */
+extern size_t vm_instance_stack_size(Instance *inst);
+
+/*
+ * Copy the stack from where the last run stopped into `out`, bottom first.
+ * `out` must have room for `vm_instance_stack_size` bytes.
+ */
+extern void vm_instance_copy_stack(Instance *inst, u8 *out);
+
+/*
+ * Write the code position of every task still waiting in the scheduler to `out`, and return how many there are.
+ * `out` must have room for 255.
+ */
+extern u8 vm_instance_tasks(Instance *inst, u32 *out);
+
+/*
+ * Write where the most recent calls (at most CALL_HISTORY) were made from to `out`, newest first,
+ * and return how many there are.
+ */
+extern u32 vm_instance_calls(Instance *inst, u32 *out);
+
+/*
+ * Turn paranoid mode on or off for the instance's next run.
+ * In paranoid mode, every `malloc` also records which bytes of the object have been initialized,
+ * and reading a byte that hasn't been is a trap.
+ * The verifier should make that impossible, so this is for checking the verifier.
+ */
+extern void vm_instance_set_paranoid(Instance *inst, u8 paranoid);
+
+/*
+ * Turn checked mode on or off for the instance's next run.
+ * In checked mode, `free_rgn` doesn't give the region's memory back, and just marks it freed,
+ * so every use of a handle or pointer can check that its region is still live,
+ * and freeing a region twice or touching its memory after it's freed is a trap.
+ * The verifier should make that impossible too, so this is for checking the verifier.
+ */
+extern void vm_instance_set_checked(Instance *inst, u8 checked);
 
 /*
- * The entry point.
+ * Free an instance and its stack.
  */
-extern uint8_t vm_function(u8 instrs[]);
+extern void vm_instance_free(Instance *inst);
 
 /*
  * The actual VM implementation.
  */
-u8 eval(u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack);
\ No newline at end of file
+int eval(Instance *inst, u8 instrs[], u32 pc, u32 sp, u32 data_section_size, struct Stack *stack);
\ No newline at end of file
@@ -4,203 +4,1552 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+use std::cell::UnsafeCell;
 use std::collections::HashMap;
-use std::vec;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+use std::{slice, vec};
 
+use crate::coredump::CoreDump;
 use crate::header::*;
-use crate::pretty::Pretty;
-use std::fs;
+#[cfg(feature = "async")]
+use crate::host::AsyncHostFn;
+use crate::guest::{FromStack, GuestFn, GuestView, IntoArgs, IntoSvm};
+use crate::host::{CallingHostFn, Clock, EnvAccess, Host, HostFn, HostFns, NativeHostFn, Rng, StdProfile, Strings, Timer};
+use crate::host::{Finalizer, HostBinding, ViewingHostFn};
+use crate::host::{
+    self, ARG_BYTE, ARG_COUNT, ARG_LEN, CLOCK_MONOTONIC, CLOCK_WALL, ENV_BYTE, ENV_LEN, FMT_HEX, FMT_I32, PARSE_I32, RANDOM, SCAN_I32,
+    UTF8_VALID,
+};
+use crate::instr::Instr;
+use crate::log::{self, event, Level};
+use crate::metrics::{self, Counter, Phase};
+use crate::parse;
+#[cfg(feature = "profile")]
+use crate::profile::Profile;
+use crate::safepoint::Safepoints;
+use crate::verify::{self, Signatures, VerifyPass};
+
+/// The C side of an `Instance`. Only ever handled through a pointer.
+#[repr(C)]
+struct RawInstance {
+    _private: [u8; 0],
+}
 
 extern "C" {
-    fn vm_function(bytes: *mut u8) -> u8;
-}
-
-pub fn go(ir_programs: Vec<IRProgram>) -> u8 {
-    let mut str = String::new();
-    let code_size = 4 + ir_programs.iter().map(program_size).sum::<usize>();
-    let mut code = Vec::with_capacity(code_size);
-    let mut import_map = HashMap::new();
-    let mut prog_id = 0;
-    for prog in &ir_programs {
-        for (k,v) in &prog.exports {
-            import_map.insert(*k, (prog_id, *v));
-        }
-        prog_id += 1;
-    }
-    let mut data_sec_positions = HashMap::new();
-    code.extend(vec![0, 0, 0, 0]);
-    let mut pos: u32 = 4;
-    prog_id = 0;
-    for prog in &ir_programs {
-        data_sec_positions.insert(prog_id, pos - 4);
-        let data_section_len = prog.data_section.len();
-        code.extend(prog.data_section.iter());
-        pos += data_section_len as u32;
-        prog_id += 1;
-    }
-    code[0..4].copy_from_slice(&(pos - 4).to_le_bytes());
-    let mut func_positions = HashMap::new();
-    let mut pos2 = pos;
-    prog_id = 0;
-    for prog in &ir_programs {
-        for Stmt2::Func(l, _, ops) in &prog.funcs {
-            func_positions.insert((prog_id, *l), pos2);
-            pos2 += ops.iter().map(op_len).sum::<usize>() as u32;
-        }
-        prog_id += 1;
-    }
-    assert!(pos2 == code_size as u32);
-    assert!(pos < pos2);
-    prog_id = 0;
-    for prog in &ir_programs {
-        let mut label_map = HashMap::new();
+    fn vm_instance_new(interrupt: *const AtomicBool) -> *mut RawInstance;
+    fn vm_instance_set_pause(inst: *mut RawInstance, pause: *const AtomicBool);
+    fn vm_instance_set_regions(inst: *mut RawInstance, strategy: u8, chunk_size: u64, growth: u32, large_region: u64);
+    fn vm_instance_region_stats(inst: *mut RawInstance, stats: *mut RegionStats);
+    fn vm_instance_run(inst: *mut RawInstance, bytes: *mut u8, args: *const u8, args_size: u32) -> i32;
+    fn vm_instance_start(inst: *mut RawInstance, bytes: *mut u8, f: u32, args: *const u8, args_size: u32) -> i32;
+    fn vm_instance_resume(inst: *mut RawInstance, bytes: *mut u8, val: i32) -> i32;
+    fn vm_instance_continue(inst: *mut RawInstance, bytes: *mut u8) -> i32;
+    fn vm_instance_call(inst: *mut RawInstance, bytes: *mut u8, f: u32, args: *const u8, args_size: u32) -> i32;
+    fn vm_instance_return(inst: *mut RawInstance);
+    fn vm_instance_yielded(inst: *mut RawInstance) -> i32;
+    fn vm_instance_host_func(inst: *mut RawInstance) -> u32;
+    fn vm_instance_minting(inst: *mut RawInstance) -> i64;
+    fn vm_instance_minting_region(inst: *mut RawInstance) -> u64;
+    fn vm_instance_finalizing(inst: *mut RawInstance) -> u64;
+    fn vm_instance_stopped_pc(inst: *mut RawInstance) -> u32;
+    fn vm_instance_stack_size(inst: *mut RawInstance) -> usize;
+    fn vm_instance_copy_stack(inst: *mut RawInstance, out: *mut u8);
+    fn vm_instance_tasks(inst: *mut RawInstance, out: *mut u32) -> u8;
+    fn vm_instance_calls(inst: *mut RawInstance, out: *mut u32) -> u32;
+    fn vm_instance_set_paranoid(inst: *mut RawInstance, paranoid: u8);
+    fn vm_instance_set_checked(inst: *mut RawInstance, checked: u8);
+    fn vm_instance_free(inst: *mut RawInstance);
+    #[cfg(feature = "profile")]
+    fn vm_profile_ticks() -> u64;
+    #[cfg(feature = "profile")]
+    fn vm_instance_profile(inst: *mut RawInstance, counts: *mut u64, ticks: *mut u64, samples: *mut u64);
+    #[cfg(feature = "profile")]
+    fn vm_instance_reset_profile(inst: *mut RawInstance);
+}
+
+/// How the C VM goes from one instruction to the next, as picked by the `threaded-dispatch` feature when it was built.
+pub const DISPATCH: &str = if cfg!(feature = "threaded-dispatch") { "threaded" } else { "switch" };
+
+/// `CALL_HISTORY` from vm.h.
+const CALL_HISTORY: usize = 16;
+
+/// Trap and yield codes from vm.h.
+const VM_TRAP_INTERRUPTED: i32 = -1;
+const VM_YIELDED: i32 = -2;
+const VM_HOST_CALL: i32 = -3;
+const VM_TRAP_UNINITIALIZED: i32 = -4;
+const VM_TRAP_OUT_OF_BOUNDS: i32 = -5;
+const VM_TRAP_USE_AFTER_FREE: i32 = -6;
+const VM_TRAP_DOUBLE_FREE: i32 = -7;
+const VM_TRAP_OVERFLOW: i32 = -8;
+const VM_TRAP_DIVIDE_BY_ZERO: i32 = -9;
+const VM_VERIFY: i32 = -10;
+const VM_SAFEPOINT: i32 = -11;
+const VM_TRAP_REGION_FULL: i32 = -12;
+const VM_FINALIZE: i32 = -13;
+const VM_TRAP_STDIN_BUSY: i32 = -14;
+
+/// The op at the start of a function that's verified the first time it's called, and its flag (see `Code::publish`).
+const STUB: [u8; 2] = [61, 0];
+
+/// Where a call into the C VM left off.
+enum Step {
+    Done(Outcome),
+    HostCall(u32, i32),
+}
+
+/// A parsed, verified, and linked set of SaberVM programs.
+/// A module never changes after it's built, so one module can back any number of instances.
+/// Everything the VM would otherwise look up at runtime (function positions, imports, data section offsets)
+/// is resolved during linking, so a module is just bytes and can be shared freely between threads.
+/// The one exception is a function verified lazily (see `Verification::Lazy`), whose code is filled in when it's first called.
+pub struct Module {
+    /// Different for every module built in this process, so a `GuestFn` can tell whether it's from this one.
+    id: u64,
+    code: Code,
+    /// Where each function starts in `code`, in order, with the program it came from and its label.
+    functions: Vec<(u32, usize, Label)>,
+    /// How many `i32`s the entry function takes.
+    entry_params: usize,
+    /// What the verifier knew at each `host_call`, by the position just after it, which is where a host call stops.
+    host_sites: HashMap<u32, HostSite>,
+    /// Where each `marker` is in `code`, in order, with its number.
+    markers: Vec<(u32, u32)>,
+    /// The functions that are verified when they're first called, in the order they're in `code`.
+    lazy: Vec<LazyFn>,
+    /// What's needed to verify them, by the index of the program they're in.
+    lazy_programs: HashMap<usize, LazyProgram>,
+    /// The functions the host can call by name (see `Feature::ExportNames`).
+    exports: HashMap<String, Export>,
+    /// How its instances get the memory for their regions.
+    regions: RegionArena,
+}
+
+/// A function the host can call by name, where it starts in the code (at its stub if it's lazy) and its type.
+struct Export {
+    pc: u32,
+    t: Type,
+}
+
+/// A module's linked code.
+/// Nothing writes to it once it's linked, except to fill in a lazily verified function before the VM can run it.
+struct Code(Box<[UnsafeCell<u8>]>);
+
+// A lazily verified function's code is only read once its flag is set, which is only done after it's been written,
+// and it's only written once, so the code can be shared like plain bytes.
+unsafe impl Sync for Code {}
+
+impl Code {
+    fn new(code: Vec<u8>) -> Code {
+        // `UnsafeCell<u8>` has the same layout as `u8`
+        let code = Box::into_raw(code.into_boxed_slice()) as *mut [UnsafeCell<u8>];
+        Code(unsafe { Box::from_raw(code) })
+    }
+
+    /// The code, for the C VM, which never writes to it.
+    fn ptr(&self) -> *mut u8 {
+        UnsafeCell::raw_get(self.0.as_ptr())
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Bytes that are never written after linking, like the data section.
+    fn fixed(&self, range: Range<usize>) -> &[u8] {
+        assert!(range.end <= self.len());
+        unsafe { slice::from_raw_parts(self.ptr().add(range.start), range.len()) }
+    }
+
+    /// Fill in the code of the lazily verified function with its stub at `stub`, then set the stub's flag,
+    /// so that the VM runs the code instead of stopping there.
+    /// Safety: this must be done at most once per function, and `code` has to fit in the space kept for it.
+    unsafe fn publish(&self, stub: u32, code: &[u8]) {
+        let stub = self.ptr().add(stub as usize);
+        std::ptr::copy_nonoverlapping(code.as_ptr(), stub.add(STUB.len()), code.len());
+        AtomicU8::from_ptr(stub.add(1)).store(1, Ordering::Release);
+    }
+}
+
+/// When the programs in a module are verified.
+/// Either way, nothing in a function that doesn't verify ever runs, but lazily that's only found out when it's called.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Verification {
+    /// Check everything while the module is built, so building it is what fails.
+    #[default]
+    Eager,
+    /// Check the declarations while the module is built, but each function body only when it's first called,
+    /// which traps with `Trap::Unverified` if the body doesn't verify.
+    /// A big program that only runs a few of its functions starts much faster this way.
+    /// This only applies to programs with `Feature::SizedBodies`, since the others would have to be parsed all the way
+    /// through to find their bodies anyway, and not when there are passes, since they look at whole programs.
+    Lazy,
+}
+
+/// How an instance gets the memory for its regions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RegionStrategy {
+    /// Every region is allocated on its own, and given back as soon as it's freed.
+    #[default]
+    Malloc,
+    /// Regions up to `RegionArena::large_region` bytes are carved out of bigger chunks,
+    /// and a freed one is kept to be reused by the next region of the same size.
+    /// A module that makes many tiny regions spends much less on allocator overhead this way,
+    /// but the chunks are only given back when the instance is dropped.
+    Pooled,
+    /// Like `Malloc` for the first 64 small regions, then like `Pooled`,
+    /// with the first chunk sized to hold as many bytes of small regions as were live at once up to then
+    /// (see `RegionStats::small_peak`), instead of `RegionArena::chunk_size`.
+    /// So a module that only makes a few regions never has a chunk, and one that makes many has chunks the size of what it uses.
+    Adaptive,
+}
+
+/// How the regions of a module's instances are laid out in memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegionArena {
+    pub strategy: RegionStrategy,
+    /// How many bytes the first chunk has, with `RegionStrategy::Pooled`.
+    pub chunk_size: usize,
+    /// How many times bigger each chunk is than the one before.
+    pub growth: u32,
+    /// Regions bigger than this many bytes are always allocated on their own, whatever the strategy.
+    pub large_region: usize,
+}
+
+impl Default for RegionArena {
+    fn default() -> RegionArena {
+        RegionArena {
+            strategy: RegionStrategy::Malloc,
+            chunk_size: 64 * 1024,
+            growth: 2,
+            large_region: 4096,
+        }
+    }
+}
+
+/// What an instance's regions have cost since it was made, from `Instance::region_stats`, whatever the strategy.
+/// Sizes are of whole blocks: a region's header and its bytes, rounded up to 16.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegionStats {
+    /// How many regions were made.
+    pub made: u64,
+    /// How many of those were no bigger than `RegionArena::large_region`.
+    pub small: u64,
+    /// How many of the small ones were carved out of a chunk or reused, rather than allocated on their own.
+    pub pooled: u64,
+    /// How many bytes of small regions are live now.
+    pub small_live: u64,
+    /// The most bytes of small regions that were live at once.
+    pub small_peak: u64,
+    /// How many bytes of chunks there are.
+    pub chunk_bytes: u64,
+}
+
+/// A program whose bodies are verified when they're first called, and what's needed to do that.
+struct LazyProgram {
+    sigs: Signatures,
+    data_section: Vec<u8>,
+    /// The bytes of the bodies, which are the end of the program, and each body's label and range in them.
+    bodies: Vec<u8>,
+    ranges: Vec<(Label, Range<usize>)>,
+    /// How many functions the program declares.
+    n: u32,
+    config: Config,
+    /// Where each function the program can name starts in the module's code, and where its data section does.
+    positions: HashMap<Label, u32>,
+    data_start: u64,
+}
+
+/// A lazily verified function, which starts with a stub that stops the VM until it has been.
+struct LazyFn {
+    /// Where the stub is in the module's code. The function's code comes right after it.
+    stub: u32,
+    /// How many bytes are kept after the stub for the code, which is as many as the body could possibly link to.
+    reserved: usize,
+    program: usize,
+    label: Label,
+    /// Where its body is in the program's `bodies`.
+    body: Range<usize>,
+    verified: OnceLock<Result<Verified, Error>>,
+}
+
+/// What linking a lazily verified function gave, like `Module::host_sites` and `Module::markers` give for the rest.
+struct Verified {
+    host_sites: Vec<(u32, HostSite)>,
+    markers: Vec<(u32, u32)>,
+}
+
+/// How many bytes to keep for the code of a body that's `size` bytes, before it's verified.
+/// No op links to more than 12.5 times its size: `init` and `proj` take 2 bytes and link to 25.
+fn reserved_len(size: usize) -> usize {
+    (size * 25).div_ceil(2)
+}
+
+/// A program on its way into a module, either verified or with only its declarations checked.
+enum Linkable {
+    Verified(IRProgram),
+    Lazy(Box<LazyProgram>),
+}
+
+impl Linkable {
+    fn data_section(&self) -> &[u8] {
+        match self {
+            Linkable::Verified(prog) => &prog.data_section,
+            Linkable::Lazy(prog) => &prog.data_section,
+        }
+    }
+
+    fn exports(&self) -> &HashMap<(u64, u64), Label> {
+        match self {
+            Linkable::Verified(prog) => &prog.exports,
+            Linkable::Lazy(prog) => prog.sigs.exports(),
+        }
+    }
+
+    fn imports(&self) -> &HashMap<Label, (u64, u64)> {
+        match self {
+            Linkable::Verified(prog) => &prog.imports,
+            Linkable::Lazy(prog) => prog.sigs.imports(),
+        }
+    }
+
+    /// Each function with a body, in order, with how many bytes of code it takes up.
+    fn lens(&self) -> Vec<(Label, usize)> {
+        match self {
+            Linkable::Verified(prog) => prog
+                .funcs
+                .iter()
+                .map(|Stmt2::Func(l, _, ops, _)| (*l, ops.iter().map(op_len).sum()))
+                .collect(),
+            Linkable::Lazy(prog) => {
+                prog.ranges.iter().map(|(l, range)| (*l, STUB.len() + reserved_len(range.len()))).collect()
+            }
+        }
+    }
+
+    /// The type of the function with label `label`, if it has a body.
+    fn signature(&self, label: Label) -> Option<&Type> {
+        match self {
+            Linkable::Verified(prog) => prog.funcs.iter().find(|Stmt2::Func(l, _, _, _)| *l == label).map(|Stmt2::Func(_, t, _, _)| t),
+            Linkable::Lazy(prog) => prog.sigs.signature(label),
+        }
+    }
+
+    /// How many `i32`s the program's first function takes.
+    fn entry_params(&self) -> usize {
+        let t = match self {
+            Linkable::Verified(prog) => prog.funcs.first().map(|Stmt2::Func(_, t, _, _)| t),
+            Linkable::Lazy(prog) => prog.ranges.first().and_then(|(l, _)| prog.sigs.signature(*l)),
+        };
+        match t {
+            Some(Type::Func(param_ts)) => param_ts.len(),
+            _ => 0,
+        }
+    }
+}
+
+impl LazyProgram {
+    /// Parse and check the declarations of a program with sized bodies, or `None` if it doesn't have them,
+    /// along with the names of its exports.
+    fn new(bytes: &[u8], config: &Config) -> Result<Option<(LazyProgram, Vec<(String, Label)>)>, Error> {
+        let Some((data_section, type_decs, forward_decs, ranges)) = parse::declarations(bytes, &config.limits)? else {
+            return Ok(None);
+        };
+        verify::check_opcodes(&forward_decs, &[], &config.allowed_opcodes)?;
+        let names = parse::export_names(bytes, &forward_decs)?;
+        let sigs = Signatures::new(&type_decs, &forward_decs, &config.limits, None)?;
+        if let Some(t) = ranges.first().and_then(|(l, _)| sigs.signature(*l)) {
+            verify::check_entry(t)?;
+        }
+        let start = ranges.first().map_or(bytes.len(), |(_, range)| range.start);
+        event!(Level::Debug, "checked the declarations of {} functions, leaving {} bodies for later", forward_decs.len(), ranges.len());
+        let prog = LazyProgram {
+            sigs,
+            data_section,
+            bodies: bytes[start..].to_vec(),
+            ranges: ranges.into_iter().map(|(l, range)| (l, range.start - start..range.end - start)).collect(),
+            n: forward_decs.len() as u32,
+            config: *config,
+            positions: HashMap::new(),
+            data_start: 0,
+        };
+        Ok(Some((prog, names)))
+    }
+
+    /// Parse, verify, and link one body, giving back its code.
+    fn check(&self, f: &LazyFn) -> Result<(Vec<u8>, Verified), Error> {
+        let limits = &self.config.limits;
+        let stmt = parse::body(&self.bodies, f.body.clone(), f.label, self.n, limits)?;
+        verify::check_opcodes(&[], slice::from_ref(&stmt), &self.config.allowed_opcodes)?;
+        let Stmt2::Func(_, _, ops, sites) =
+            self.sigs.check_body(&self.data_section, &stmt, &Cancellation::default(), None)?;
+        let mut code = vec![];
+        let mut verified = Verified {
+            host_sites: vec![],
+            markers: vec![],
+        };
+        let mut sites = sites.into_iter().peekable();
+        let mut pos = f.stub + STUB.len() as u32;
+        for (i, op) in ops.iter().enumerate() {
+            if let Op2::Marker(n) = op {
+                verified.markers.push((pos, *n));
+            }
+            let Some(instr) = Instr::link(op, &mut |label| self.positions.get(&label).copied(), self.data_start) else {
+                return Err(unlinked(i, op));
+            };
+            instr.encode(&mut code);
+            pos += instr.size() as u32;
+            if let Some(site) = sites.next_if(|site| site.op == i) {
+                verified.host_sites.push((pos, site));
+            }
+        }
+        assert!(code.len() <= f.reserved);
+        Ok((code, verified))
+    }
+}
+
+/// What an embedder lets the programs in a module do, checked when the module is built.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    pub limits: Limits,
+    /// The ops programs may use, like `OpcodeSet::all().deny(0x2D).deny(0x2E)` for no `read` or `write`.
+    /// A program that uses any other op is rejected before it's verified.
+    pub allowed_opcodes: OpcodeSet,
+    /// Whether function bodies can wait to be verified until they're called, which changes when a bad one is found.
+    /// A `stream::Stream` verifies bodies as they arrive either way.
+    pub verification: Verification,
+    /// How the module's instances get the memory for their regions.
+    pub regions: RegionArena,
+}
+
+/// A position in a module's code, in terms of the programs it was linked from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// The index of the program in the list the module was built from.
+    pub program: usize,
+    pub function: Label,
+    /// How many bytes of linked code into the function the position is.
+    pub offset: u32,
+}
+
+// Servers verify a module once and run it from many threads, so this must never regress.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Module>();
+    assert_send_sync::<Arc<Module>>();
+};
+
+impl Module {
+    /// Parse and verify each program, then link them together into one module.
+    pub fn new(bytes: Vec<ByteStream>) -> Result<Module, Error> {
+        Module::with_limits(bytes, &Limits::default())
+    }
+
+    /// Like `new`, but with custom bounds on what the parser accepts, for untrusted input.
+    pub fn with_limits(bytes: Vec<ByteStream>, limits: &Limits) -> Result<Module, Error> {
+        Module::cancellable(bytes, limits, &Cancellation::default())
+    }
+
+    /// Like `with_limits`, but verification gives up with an error if `cancel` says so.
+    pub fn cancellable(
+        bytes: Vec<ByteStream>,
+        limits: &Limits,
+        cancel: &Cancellation,
+    ) -> Result<Module, Error> {
+        Module::with_passes(bytes, limits, cancel, &[])
+    }
+
+    /// Like `cancellable`, but also running each of `passes` on every program once it type checks.
+    pub fn with_passes(
+        bytes: Vec<ByteStream>,
+        limits: &Limits,
+        cancel: &Cancellation,
+        passes: &[&dyn VerifyPass],
+    ) -> Result<Module, Error> {
+        let config = Config {
+            limits: *limits,
+            ..Config::default()
+        };
+        Module::with_config(bytes, &config, cancel, passes)
+    }
+
+    /// Like `with_passes`, but with everything a `Config` can turn off.
+    /// The programs can be any bytes, like `mmap::Mapped` files, not just `Vec`s.
+    pub fn with_config<B: AsRef<[u8]>>(
+        bytes: Vec<B>,
+        config: &Config,
+        cancel: &Cancellation,
+        passes: &[&dyn VerifyPass],
+    ) -> Result<Module, Error> {
+        let lazy = config.verification == Verification::Lazy && passes.is_empty();
+        let mut programs = vec![];
+        let mut names = HashMap::new();
+        for (prog_id, prog) in bytes.iter().enumerate() {
+            let mut name = |(name, label): (String, Label)| match names.insert(name.clone(), (prog_id, label)) {
+                Some(_) => Err(Error::DuplicateExportName(name)),
+                None => Ok(()),
+            };
+            if lazy {
+                if let Some((prog, prog_names)) = LazyProgram::new(prog.as_ref(), config)? {
+                    prog_names.into_iter().try_for_each(&mut name)?;
+                    programs.push(Linkable::Lazy(Box::new(prog)));
+                    continue;
+                }
+            }
+            let (data_section, type_decs, types_instrs, unverified_stmts) =
+                parse::go_with_limits(prog.as_ref(), &config.limits)?;
+            verify::check_opcodes(&types_instrs, &unverified_stmts, &config.allowed_opcodes)?;
+            parse::export_names(prog.as_ref(), &types_instrs)?.into_iter().try_for_each(name)?;
+            let ir_program =
+                verify::go_with_limits(data_section, type_decs, types_instrs, unverified_stmts, &config.limits, cancel)?;
+            verify::run_passes(&ir_program, passes)?;
+            programs.push(Linkable::Verified(ir_program));
+        }
+        let mut module = Module::link_all(programs, names)?;
+        module.regions = config.regions;
+        Ok(module)
+    }
+
+    /// Collapse already-verified programs into the byte array the C VM runs.
+    /// IR has no export names, so the module's functions can't be called by name.
+    /// This fails if the first program has no functions, or if a program imports something none of them export.
+    pub fn link(ir_programs: Vec<IRProgram>) -> Result<Module, Error> {
+        Module::link_all(ir_programs.into_iter().map(Linkable::Verified).collect(), HashMap::new())
+    }
+
+    /// Like `link`, but leaving space after a stub for each body of a lazy program, to be filled in when it's called,
+    /// and with `names` for the functions the host can call, each with its program and label.
+    fn link_all(mut programs: Vec<Linkable>, names: HashMap<String, (usize, Label)>) -> Result<Module, Error> {
+        let _span = log::span(Level::Debug, module_path!(), "link", || format!("{} programs", programs.len()));
+        let start = Instant::now();
+        let lens = programs.iter().map(Linkable::lens).collect::<Vec<_>>();
+        // the entry function is the first program's first function
+        if lens.first().is_none_or(Vec::is_empty) {
+            return Err(Error::NoEntryFunction);
+        }
+        let code_size = 4 + programs
+            .iter()
+            .zip(&lens)
+            .map(|(prog, lens)| prog.data_section().len() + lens.iter().map(|(_, len)| len).sum::<usize>())
+            .sum::<usize>();
+        let mut code = Vec::with_capacity(code_size);
+        let mut import_map = HashMap::new();
+        for (prog_id, prog) in programs.iter().enumerate() {
+            for (k,v) in prog.exports() {
+                import_map.insert(*k, (prog_id, *v));
+            }
+        }
+        let mut data_sec_positions = vec![];
+        code.extend(vec![0, 0, 0, 0]);
+        let mut pos: u32 = 4;
+        for prog in &programs {
+            data_sec_positions.push(pos - 4);
+            let data_section_len = prog.data_section().len();
+            code.extend(prog.data_section().iter());
+            pos += data_section_len as u32;
+        }
+        code[0..4].copy_from_slice(&(pos - 4).to_ne_bytes());
+        let mut func_positions = HashMap::new();
+        let mut functions = vec![];
+        let mut host_sites = HashMap::new();
+        let mut markers = vec![];
+        let mut lazy = vec![];
         let mut pos2 = pos;
-        for Stmt2::Func(label, _, ops) in &prog.funcs {
-            label_map.insert(*label, pos2);
-            pos2 += ops.iter().map(op_len).sum::<usize>() as u32;
-        }
-        for Stmt2::Func(l, t, ops) in &prog.funcs {
-            str += &("function ".to_string() + &l.to_string() + ": " + &t.pretty() + "\n");
-            for op in ops {
-                str += &(pos.to_string() + " " + &op.pretty() + "\n");
-                match op {
-                    Op2::GlobalFunc(label) => {
-                        let func_pos = match label_map.get(label) {
-                            Some(pos) => *pos,
-                            None => {
-                                let func_id = import_map.get(prog.imports.get(label).unwrap()).unwrap();
-                                *func_positions.get(func_id).unwrap()
-                            },
-                        };
-                        code.extend(op_to_bytes(&Op2::GlobalFunc(func_pos as u32)));
+        // where each function a program can name starts: its own, and those it imports, which come after
+        let mut positions = vec![];
+        for (prog_id, (prog, lens)) in programs.iter().zip(&lens).enumerate() {
+            let mut label_map = HashMap::new();
+            for (l, len) in lens {
+                func_positions.insert((prog_id, *l), pos2);
+                label_map.insert(*l, pos2);
+                // a lazily verified function's code starts after its stub, so its offsets are the same as if it weren't
+                let stub = if let Linkable::Lazy(_) = prog { STUB.len() as u32 } else { 0 };
+                functions.push((pos2 + stub, prog_id, *l));
+                pos2 += *len as u32;
+            }
+            positions.push(label_map);
+        }
+        assert!(pos2 == code_size as u32);
+        for (prog, label_map) in programs.iter().zip(&mut positions) {
+            for (label, name) in prog.imports() {
+                let Some(func_pos) = import_map.get(name).and_then(|func_id| func_positions.get(func_id)) else {
+                    return Err(Error::UnknownImport(*label, import_name(*name)));
+                };
+                label_map.insert(*label, *func_pos);
+            }
+        }
+        let mut exports = HashMap::new();
+        for (name, (prog_id, label)) in names {
+            let pc = func_positions.get(&(prog_id, label));
+            let t = programs.get(prog_id).and_then(|prog| prog.signature(label));
+            let (Some(pc), Some(t)) = (pc, t) else {
+                return Err(Error::ExportNameNotFunction(name, label));
+            };
+            exports.insert(name, Export { pc: *pc, t: t.clone() });
+        }
+        for (prog_id, (prog, (label_map, data_start))) in
+            programs.iter_mut().zip(positions.into_iter().zip(data_sec_positions)).enumerate()
+        {
+            let data_start = data_start as u64;
+            let prog = match prog {
+                Linkable::Verified(prog) => prog,
+                Linkable::Lazy(prog) => {
+                    // bodies are linked when they're verified
+                    prog.positions = label_map;
+                    prog.data_start = data_start;
+                    for (label, range) in &prog.ranges {
+                        let reserved = reserved_len(range.len());
+                        lazy.push(LazyFn {
+                            stub: pos,
+                            reserved,
+                            program: prog_id,
+                            label: *label,
+                            body: range.clone(),
+                            verified: OnceLock::new(),
+                        });
+                        code.extend(STUB);
+                        code.resize(code.len() + reserved, 0);
+                        pos += (STUB.len() + reserved) as u32;
+                    }
+                    continue;
+                }
+            };
+            let mut func_pos = |label| label_map.get(&label).copied();
+            for Stmt2::Func(_, _, ops, sites) in &prog.funcs {
+                let mut sites = sites.iter().peekable();
+                for (i, op) in ops.iter().enumerate() {
+                    if let Op2::Marker(n) = op {
+                        markers.push((pos, *n));
+                    }
+                    let Some(instr) = Instr::link(op, &mut func_pos, data_start) else {
+                        return Err(unlinked(i, op));
+                    };
+                    instr.encode(&mut code);
+                    pos += instr.size() as u32;
+                    if let Some(site) = sites.next_if(|site| site.op == i) {
+                        host_sites.insert(pos, site.clone());
+                    }
+                }
+            }
+        }
+        let entry_params = programs.first().map_or(0, Linkable::entry_params);
+        let lazy_programs = programs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(prog_id, prog)| match prog {
+                Linkable::Lazy(prog) => Some((prog_id, *prog)),
+                Linkable::Verified(_) => None,
+            })
+            .collect();
+        metrics::time(Phase::Link, start.elapsed());
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Ok(Module {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            code: Code::new(code),
+            functions,
+            entry_params,
+            host_sites,
+            markers,
+            lazy,
+            lazy_programs,
+            regions: RegionArena::default(),
+            exports,
+        })
+    }
+
+    /// How many arguments `Instance::run_with_args` has to be given. They're all `i32`s.
+    pub fn entry_params(&self) -> usize {
+        self.entry_params
+    }
+
+    /// The number of the last `marker` at or before `loc` in its function, if there is one,
+    /// so a trap or a backtrace can be matched up with what the compiler tagged.
+    pub fn marker(&self, loc: Location) -> Option<u32> {
+        let (start, _, _) = self.functions.iter().find(|(_, p, f)| *p == loc.program && *f == loc.function)?;
+        let pc = start + loc.offset;
+        let markers = match self.lazy_at(start - STUB.len() as u32) {
+            Some(f) => &f.verified.get()?.as_ref().ok()?.markers,
+            None => &self.markers,
+        };
+        let i = markers.partition_point(|(at, _)| *at <= pc).checked_sub(1)?;
+        let (at, n) = markers[i];
+        (at >= *start).then_some(n)
+    }
+
+    /// The type of the function exported as `name` (see `Feature::ExportNames`), which `Instance::call` checks its arguments against.
+    pub fn export_signature(&self, name: &str) -> Option<&Type> {
+        self.exports.get(name).map(|export| &export.t)
+    }
+
+    /// The name and type of every function the host can call, in no particular order.
+    pub fn exports(&self) -> impl Iterator<Item = (&str, &Type)> {
+        self.exports.iter().map(|(name, export)| (name.as_str(), &export.t))
+    }
+
+    /// Whether this module can replace `old` in an instance (see `Instance::reload`): it has to export everything `old` does,
+    /// with the same types, and its entry function has to take as many arguments.
+    pub fn check_reload(&self, old: &Module) -> Result<(), ReloadError> {
+        if self.entry_params != old.entry_params {
+            return Err(ReloadError::EntryChanged(old.entry_params, self.entry_params));
+        }
+        let mut exports = old.exports().collect::<Vec<_>>();
+        exports.sort_by_key(|(name, _)| *name);
+        for (name, t) in exports {
+            match self.export_signature(name) {
+                None => return Err(ReloadError::MissingExport(name.to_string())),
+                Some(new) if new != t => return Err(ReloadError::ExportChanged(name.to_string(), t.clone(), new.clone())),
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Why the function at `loc` didn't verify, if it was verified lazily when it was called (see `Trap::Unverified`).
+    pub fn verify_error(&self, loc: Location) -> Option<&Error> {
+        let f = self.lazy.iter().find(|f| f.program == loc.program && f.label == loc.function)?;
+        f.verified.get()?.as_ref().err()
+    }
+
+    /// Which function a position in the linked code is in, or `None` if it's not in any function.
+    /// A run stopped at a lazily verified function's stub is at the start of the function.
+    pub fn locate(&self, mut pc: u32) -> Option<Location> {
+        if let Some(f) = self.lazy_at(pc) {
+            pc = f.stub + STUB.len() as u32;
+        }
+        let i = self.functions.partition_point(|(start, _, _)| *start <= pc).checked_sub(1)?;
+        let (start, program, function) = self.functions[i];
+        if pc as usize >= self.code.len() {
+            return None;
+        }
+        Some(Location {
+            program,
+            function,
+            offset: pc - start,
+        })
+    }
+
+    /// The lazily verified function whose stub is at `pc`, if there is one.
+    fn lazy_at(&self, pc: u32) -> Option<&LazyFn> {
+        let i = self.lazy.binary_search_by_key(&pc, |f| f.stub).ok()?;
+        Some(&self.lazy[i])
+    }
+
+    /// Verify the function whose stub a run stopped at, if that hasn't been done yet, so the run can go on.
+    /// Only one thread does it, and any others that get there first wait for it to finish.
+    fn verify_lazily(&self, pc: u32) -> Result<(), Trap> {
+        // the VM only stops to verify at a stub, but if it somehow didn't, there's nothing here to verify and go on with
+        let Some(f) = self.lazy_at(pc) else {
+            return Err(Trap::Unverified(self.locate(pc).map_or(0, |loc| loc.function)));
+        };
+        match self.verify_fn(f) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Trap::Unverified(f.label)),
+        }
+    }
+
+    /// Verify every lazily verified function that hasn't been yet, in the order they're in the code, giving back the first error.
+    /// Runs of the module can go on while this does, on other threads, so a program can start before it's all been checked
+    /// (see `sabervm run`); a run that gets to a function first verifies it itself, or waits for this to.
+    pub fn verify_all(&self) -> Result<(), Error> {
+        for f in &self.lazy {
+            if let Err(e) = self.verify_fn(f) {
+                return Err(e.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify a lazily verified function, if that hasn't been done yet, and fill in its code if it verifies.
+    fn verify_fn<'a>(&self, f: &'a LazyFn) -> &'a Result<Verified, Error> {
+        f.verified.get_or_init(|| {
+            let _span = log::span(Level::Debug, module_path!(), "verify lazily", || f.label.to_string());
+            let start = Instant::now();
+            let prog = &self.lazy_programs[&f.program];
+            let res = prog.check(f).map(|(code, verified)| {
+                // this is the only time the function's code is written, and `check` checked it fits
+                unsafe { self.code.publish(f.stub, &code) };
+                verified
+            });
+            metrics::time(Phase::Verify, start.elapsed());
+            match &res {
+                Ok(_) => metrics::count(Counter::FunctionsVerified, 1),
+                Err(e) => {
+                    event!(Level::Info, "function {} doesn't verify: {:?}", f.label, e);
+                    metrics::count(Counter::VerifyErrors, 1);
+                }
+            }
+            res
+        })
+    }
+
+    /// What the verifier knew at the `host_call` that stopped at `pc`.
+    fn host_site(&self, pc: u32) -> Option<&HostSite> {
+        if let Some(site) = self.host_sites.get(&pc) {
+            return Some(site);
+        }
+        let i = self.lazy.partition_point(|f| f.stub < pc).checked_sub(1)?;
+        let verified = self.lazy[i].verified.get()?.as_ref().ok()?;
+        verified.host_sites.iter().find(|(at, _)| *at == pc).map(|(_, site)| site)
+    }
+
+    /// Every `host_call` in the module's code that has been verified, by the position just after it.
+    fn host_sites(&self) -> impl Iterator<Item = (u32, &HostSite)> {
+        let lazy = self.lazy.iter().filter_map(|f| f.verified.get()?.as_ref().ok());
+        let lazy = lazy.flat_map(|verified| verified.host_sites.iter().map(|(pc, site)| (*pc, site)));
+        self.host_sites.iter().map(|(pc, site)| (*pc, site)).chain(lazy)
+    }
+}
+
+/// One run of a `Module`.
+/// This owns everything that changes while a program runs: the stack, the scheduler, and the IO handlers.
+/// Instances keep their module alive through an `Arc`, so they can be moved to other threads.
+pub struct Instance {
+    module: Arc<Module>,
+    interrupt: Arc<AtomicBool>,
+    safepoints: Option<Safepoints>,
+    /// The module from `reload`, waiting for the run in progress to finish on the old one.
+    reloaded: Option<Arc<Module>>,
+    host_fns: HostFns,
+    suspended: bool,
+    trapped: Option<Trap>,
+    /// When the profile started, by the clock and by the VM's cycle counter, to work out how long a tick is.
+    #[cfg(feature = "profile")]
+    profile_start: (Instant, u64),
+    raw: *mut RawInstance,
+}
+
+// The C instance is only ever touched through `&mut self`, and it doesn't point into any other instance.
+// The interrupt and pause flags it reads are atomic and kept alive by `self.interrupt` and `self.safepoints`.
+// Stdin is shared by the whole process, so only one instance at a time can `read` it, and the others trap with `Trap::StdinBusy`.
+unsafe impl Send for Instance {}
+
+impl Instance {
+    pub fn new(module: Arc<Module>) -> Instance {
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let raw = unsafe { vm_instance_new(Arc::as_ptr(&interrupt)) };
+        let regions = module.regions;
+        let strategy = match regions.strategy {
+            RegionStrategy::Malloc => 0,
+            RegionStrategy::Pooled => 1,
+            RegionStrategy::Adaptive => 2,
+        };
+        unsafe {
+            vm_instance_set_regions(raw, strategy, regions.chunk_size as u64, regions.growth, regions.large_region as u64)
+        };
+        Instance {
+            module,
+            interrupt,
+            safepoints: None,
+            reloaded: None,
+            host_fns: HostFns::default(),
+            suspended: false,
+            trapped: None,
+            #[cfg(feature = "profile")]
+            profile_start: (Instant::now(), unsafe { vm_profile_ticks() }),
+            raw,
+        }
+    }
+
+    /// The module the last run used, which is the one the next run uses too unless `reload` gave it a new one.
+    pub fn module(&self) -> &Arc<Module> {
+        &self.module
+    }
+
+    /// Swap in `module`, a new version of this instance's module, for every run and call from now on,
+    /// so a plugin host can update a plugin without starting it over.
+    /// The new module has to export everything the old one did, with the same types, take the same entry arguments,
+    /// and call each host function bound with `register_binding` the way the binding takes (see `Module::check_reload`).
+    /// If the last run stopped at a `yield`, `resume` finishes it on the old code; the new module starts with the next `run` or `call`.
+    /// The host functions, and the settings like `set_checked`, stay as they are.
+    /// A `GuestFn` the host kept from a run on the old module refers to the old code, so calling it back after the swap
+    /// gives `Trap::ForeignGuestFn`.
+    pub fn reload(&mut self, module: Arc<Module>) -> Result<(), ReloadError> {
+        let old = self.reloaded.as_ref().unwrap_or(&self.module);
+        module.check_reload(old)?;
+        for (index, binding) in self.host_fns.bindings() {
+            check_binding(&module, index, binding).map_err(ReloadError::Binding)?;
+        }
+        event!(Level::Info, "reloaded the module");
+        if self.suspended {
+            self.reloaded = Some(module);
+        } else {
+            self.module = module;
+        }
+        Ok(())
+    }
+
+    /// Start using the module from `reload`, at the start of a run.
+    fn swap_reloaded(&mut self) {
+        if let Some(module) = self.reloaded.take() {
+            self.module = module;
+        }
+    }
+
+    /// Get a handle that can stop this instance from another thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            flag: self.interrupt.clone(),
+        }
+    }
+
+    /// Join a group of instances that can be paused together, leaving any group this one was in (see `safepoint.rs`).
+    pub fn set_safepoints(&mut self, safepoints: &Safepoints) {
+        unsafe { vm_instance_set_pause(self.raw, safepoints.flag()) }
+        self.safepoints = Some(safepoints.clone());
+    }
+
+    /// Make `malloc` poison the memory it hands out, trapping with `Trap::UninitializedRead` on any read of a byte that hasn't been initialized.
+    /// The verifier should already rule that out, so this is a (slower) way to check the verifier.
+    pub fn set_paranoid(&mut self, paranoid: bool) {
+        unsafe { vm_instance_set_paranoid(self.raw, paranoid.into()) }
+    }
+
+    /// Make `free_rgn` keep the region's memory and mark it freed, so that freeing it again traps with `Trap::DoubleFree`
+    /// and any other use of it traps with `Trap::UseAfterFree`, instead of touching memory that's been given back.
+    /// Like paranoid mode, this checks the verifier, which should already rule both out, and freed regions stay allocated until the instance is dropped.
+    pub fn set_checked(&mut self, checked: bool) {
+        unsafe { vm_instance_set_checked(self.raw, checked.into()) }
+    }
+
+    /// Provide the function that `host_call index` runs.
+    pub fn register_host_fn(&mut self, index: u32, f: impl FnMut(i32) -> i32 + Send + 'static) {
+        let f: HostFn = Box::new(f);
+        self.host_fns.insert(index, Host::Sync(f));
+    }
+
+    /// Like `register_host_fn`, but `f` can fail, stopping the run with `Trap::HostSignature`.
+    /// The Python bindings use this to end a run at an exception, which is raised in place of the trap.
+    #[cfg(feature = "python")]
+    pub(crate) fn register_fallible_host_fn(&mut self, index: u32, mut f: impl FnMut(i32) -> Option<i32> + Send + 'static) {
+        let f: NativeHostFn = Box::new(move |view| f(<(i32,)>::from_stack(view)?.0));
+        self.host_fns.insert(index, Host::Native(f));
+    }
+
+    /// Let programs read the environment variables in `access`, through the host functions `host::ENV_LEN` and `host::ENV_BYTE`.
+    /// The values are read now, so every run of the instance sees the same ones.
+    pub fn allow_env(&mut self, access: &EnvAccess) {
+        let values = Arc::new(access.snapshot());
+        let values2 = values.clone();
+        self.register_host_fn(ENV_LEN, move |index| values.len_of(index));
+        self.register_host_fn(ENV_BYTE, move |arg| values2.byte(arg));
+    }
+
+    /// Give programs random numbers through the host function `host::RANDOM`, starting from `seed`.
+    /// Running again with the same seed gives the same numbers, so a run that went wrong can be replayed.
+    pub fn allow_random(&mut self, seed: u64) {
+        event!(Level::Info, "random seed {}", seed);
+        let mut rng = Rng::seeded(seed);
+        self.register_host_fn(RANDOM, move |bound| rng.below(bound));
+    }
+
+    /// Let programs read the time through the host functions `host::CLOCK_MONOTONIC` and `host::CLOCK_WALL`.
+    /// A `Clock::Fixed` clock makes every run read the same times.
+    pub fn allow_clock(&mut self, clock: Clock) {
+        let mut monotonic = Timer::new(clock);
+        let mut wall = monotonic.clone();
+        self.register_host_fn(CLOCK_MONOTONIC, move |part| monotonic.read(false, part));
+        self.register_host_fn(CLOCK_WALL, move |part| wall.read(true, part));
+    }
+
+    /// Let programs read `args` through the host functions `host::ARG_COUNT`, `host::ARG_LEN`, and `host::ARG_BYTE`.
+    /// These are separate from the `i32`s `run_with_args` passes to the entry function.
+    pub fn allow_args(&mut self, args: &[String]) {
+        let count = args.len().try_into().unwrap_or(i32::MAX);
+        let strings = Arc::new(Strings(args.iter().map(|arg| Some(arg.clone().into_bytes())).collect()));
+        let strings2 = strings.clone();
+        self.register_host_fn(ARG_COUNT, move |_| count);
+        self.register_host_fn(ARG_LEN, move |index| strings.len_of(index));
+        self.register_host_fn(ARG_BYTE, move |arg| strings2.byte(arg));
+    }
+
+    /// Provide every host function of the standard profile, `svm_std` (see `host::STD_PROFILE`).
+    pub fn allow_std(&mut self, profile: &StdProfile) {
+        self.allow_env(&profile.env);
+        self.allow_random(profile.seed);
+        self.allow_clock(profile.clock);
+        self.allow_args(&profile.args);
+        self.allow_text();
+    }
+
+    /// Let programs format and parse text in buffers through the host functions `host::FMT_I32`, `host::FMT_HEX`,
+    /// `host::SCAN_I32`, `host::PARSE_I32`, and `host::UTF8_VALID`, instead of each compiler writing its own in bytecode.
+    /// They only touch the buffers they're given, so there's nothing to configure.
+    pub fn allow_text(&mut self) {
+        let fns: [(u32, fn(&GuestView) -> Option<i32>); 5] = [
+            (FMT_I32, host::fmt_i32),
+            (FMT_HEX, host::fmt_hex),
+            (SCAN_I32, host::scan),
+            (PARSE_I32, host::parse_i32),
+            (UTF8_VALID, host::utf8_valid),
+        ];
+        for (index, f) in fns {
+            self.host_fns.insert(index, Host::Native(Box::new(f)));
+        }
+    }
+
+    /// Like `register_host_fn`, but the function also gets a view of the program's stack, to read the values under the argument.
+    /// The view can't outlive the call, since the program could free what it points into once it runs again.
+    pub fn register_host_fn_with_view(
+        &mut self,
+        index: u32,
+        f: impl FnMut(&GuestView, i32) -> i32 + Send + 'static,
+    ) {
+        let f: ViewingHostFn = Box::new(f);
+        self.host_fns.insert(index, Host::Viewing(f));
+    }
+
+    /// Like `register_host_fn`, but the function can also call back into the program through a `Guest`,
+    /// like a visitor that calls a function the program gave it on each of a host's values.
+    pub fn register_host_fn_with_callbacks(
+        &mut self,
+        index: u32,
+        f: impl FnMut(&mut Guest, i32) -> i32 + Send + 'static,
+    ) {
+        let f: CallingHostFn = Box::new(f);
+        self.host_fns.insert(index, Host::Calling(f));
+    }
+
+    /// Like `register_host_fn`, but `f` takes Rust values instead of an `i32` (see `guest::FromStack`) and gives back anything that's `IntoSvm`.
+    /// For example, `|(name, n): (String, i32)| name.len() as i32 * n` reads a string from under the argument.
+    /// If the values on the stack can't be read as `f`'s arguments, the run stops with `Trap::HostSignature`.
+    pub fn register_native_host_fn<A: FromStack, R: IntoSvm>(
+        &mut self,
+        index: u32,
+        mut f: impl FnMut(A) -> R + Send + 'static,
+    ) {
+        let f: NativeHostFn = Box::new(move |view| Some(f(A::from_stack(view)?).into_svm()));
+        self.host_fns.insert(index, Host::Native(f));
+    }
+
+    /// Provide the function that `host_res index` runs to make a resource of kind `kind`, like a file or a socket.
+    /// Like `register_native_host_fn`, `f` takes Rust values, but it gives the host's own id for the resource,
+    /// which the program can't see or change, and which host functions taking a `guest::Resource<KIND>` get back.
+    /// A `host_res` of a function that doesn't make that kind, or a `host_call` of this one, stops the run with `Trap::HostSignature`,
+    /// so programs can't forge a resource of one kind from another, or from a number.
+    pub fn register_resource_fn<A: FromStack>(
+        &mut self,
+        index: u32,
+        kind: u32,
+        mut f: impl FnMut(A) -> i32 + Send + 'static,
+    ) {
+        let f: NativeHostFn = Box::new(move |view| Some(f(A::from_stack(view)?)));
+        self.host_fns.insert(index, Host::Minting(kind, f));
+    }
+
+    /// Provide what the host does with a resource of kind `kind` once the program is done with it, like closing a file,
+    /// given the id its `register_resource_fn` gave it. Every resource is finalized exactly once:
+    /// a `free_rgn` finalizes the ones in its region, newest first, before the next instruction runs,
+    /// and when a run ends by halting or trapping (even in a callback, or from an interrupt),
+    /// the ones in regions the program never freed are finalized, newest first, before `run` returns.
+    /// A run stopped at a `yield` keeps its resources until it ends, or until the instance runs from the start again or is dropped.
+    /// Resources made before the finalizer was provided are finalized with it too.
+    pub fn register_finalizer(&mut self, kind: u32, f: impl FnMut(i32) + Send + 'static) {
+        let f: Finalizer = Box::new(f);
+        self.host_fns.set_finalizer(kind, f);
+    }
+
+    /// Provide a host function bound with `#[svm_host_fn]` for `host_call index`,
+    /// after checking that every `host_call index` in the module has the function's arguments on the stack.
+    /// The ones in functions that haven't been verified lazily yet can't be checked, so they trap with `Trap::HostSignature` instead.
+    pub fn register_binding(&mut self, index: u32, binding: HostBinding) -> Result<(), Error> {
+        check_binding(&self.module, index, &binding)?;
+        event!(Level::Debug, "bound host function {} to {}", binding.name, index);
+        self.host_fns.bind(index, binding);
+        Ok(())
+    }
+
+    /// Provide an async function for `host_call index`.
+    /// Programs that use it have to be run with `run_async`.
+    #[cfg(feature = "async")]
+    pub fn register_async_host_fn<F, Fut>(&mut self, index: u32, mut f: F)
+    where
+        F: FnMut(i32) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = i32> + Send + 'static,
+    {
+        let f: AsyncHostFn = Box::new(move |arg| Box::pin(f(arg)));
+        self.host_fns.insert(index, Host::Async(f));
+    }
+
+    /// Run the module from its entry point.
+    /// This starts over even if the last run stopped at a `yield`.
+    pub fn run(&mut self) -> Result<Outcome, Trap> {
+        self.run_with_args(&[])
+    }
+
+    /// Like `run`, but passing `args` to the entry function, the last one on top of the stack.
+    /// There have to be as many as `Module::entry_params` says.
+    pub fn run_with_args(&mut self, args: &[i32]) -> Result<Outcome, Trap> {
+        let _span = log::span(Level::Debug, module_path!(), "run", || format!("{:?}", args));
+        let res = self.start(args)?;
+        let res = self.drive(res);
+        self.finish(res)
+    }
+
+    /// Like `run_with_args`, but starting at the function the module exports as `name` (see `Feature::ExportNames`),
+    /// so a module can be used like a library. The function can only take `i32`s and `u8`s, and `args` are checked
+    /// against its type (see `Module::export_signature`) before anything runs, like `call("fib", (10,))`.
+    pub fn call(&mut self, name: &str, args: impl IntoArgs) -> Result<Outcome, CallError> {
+        // nothing changes until the call is known to be valid, so a run stopped at a `yield` can still be resumed after one that isn't,
+        // on the module it started on, with its resources still open
+        let module = self.reloaded.as_ref().unwrap_or(&self.module);
+        let Some(export) = module.exports.get(name) else {
+            return Err(CallError::UnknownExport(name.to_string()));
+        };
+        // a function type lists its parameters from the top of the stack down, and the arguments go the other way
+        let params = match &export.t {
+            Type::Func(param_ts) if param_ts.iter().all(|t| matches!(t, Type::I32 | Type::U8)) => {
+                param_ts.iter().rev().cloned().collect::<Vec<_>>()
+            }
+            t => return Err(CallError::NotCallable(name.to_string(), t.clone())),
+        };
+        if !args.fits(&params) {
+            return Err(CallError::ArgMismatch(name.to_string(), export.t.clone(), args.signature()));
+        }
+        let pc = export.pc;
+        let _span = log::span(Level::Debug, module_path!(), "call", || format!("{} {}", name, args.signature()));
+        let args = args.into_args(&params);
+        self.swap_reloaded();
+        self.host_fns.finalize_all();
+        let code = self.module.code.ptr();
+        let res = in_vm(self.raw, code, self.safepoints.as_ref(), || unsafe {
+            vm_instance_start(self.raw, code, pc, args.as_ptr(), args.len() as u32)
+        });
+        let res = self.drive(res);
+        self.finish(res).map_err(CallError::Trap)
+    }
+
+    /// Whether the last run stopped at a `yield`, so it can be resumed.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Continue a run that stopped at a `yield`, with `val` as the result of the `yield`.
+    /// If it didn't, nothing runs, and this gives `Trap::NotSuspended`.
+    pub fn resume(&mut self, val: i32) -> Result<Outcome, Trap> {
+        if !self.suspended {
+            return Err(Trap::NotSuspended);
+        }
+        let _span = log::span(Level::Debug, module_path!(), "resume", || val.to_string());
+        let res = self.continue_with(val);
+        let res = self.drive(res);
+        self.finish(res)
+    }
+
+    /// Like `run`, but async host functions are awaited instead of trapping.
+    /// The instance is suspended while a host future is pending, so this never blocks an executor thread on IO.
+    #[cfg(feature = "async")]
+    pub async fn run_async(&mut self) -> Result<Outcome, Trap> {
+        event!(Level::Debug, "running asynchronously");
+        let res = self.start(&[])?;
+        let res = self.drive_async(res).await;
+        self.finish(res)
+    }
+
+    /// Like `resume`, but async host functions are awaited instead of trapping.
+    #[cfg(feature = "async")]
+    pub async fn resume_async(&mut self, val: i32) -> Result<Outcome, Trap> {
+        if !self.suspended {
+            return Err(Trap::NotSuspended);
+        }
+        let res = self.continue_with(val);
+        let res = self.drive_async(res).await;
+        self.finish(res)
+    }
+
+    pub fn region_stats(&self) -> RegionStats {
+        let mut stats = RegionStats::default();
+        unsafe { vm_instance_region_stats(self.raw, &mut stats) };
+        stats
+    }
+
+    /// What the VM's loop has run since the instance was made or `reset_profile` was last called, by opcode (see `profile.rs`).
+    #[cfg(feature = "profile")]
+    pub fn profile(&self) -> Profile {
+        let (mut counts, mut ticks, mut samples) = ([0; 256], [0; 256], [0; 256]);
+        unsafe { vm_instance_profile(self.raw, counts.as_mut_ptr(), ticks.as_mut_ptr(), samples.as_mut_ptr()) };
+        let (start, start_ticks) = self.profile_start;
+        let elapsed_ticks = unsafe { vm_profile_ticks() } - start_ticks;
+        // without enough ticks to go by, assume they're nanoseconds, which the fallback clock's are
+        let tick = match elapsed_ticks {
+            0 => 1e-9,
+            n => start.elapsed().as_secs_f64() / n as f64,
+        };
+        Profile::new(&counts, &ticks, &samples, tick)
+    }
+
+    #[cfg(feature = "profile")]
+    pub fn reset_profile(&mut self) {
+        unsafe { vm_instance_reset_profile(self.raw) }
+        self.profile_start = (Instant::now(), unsafe { vm_profile_ticks() });
+    }
+
+    /// The state of the VM where the last run trapped, or `None` if it didn't trap.
+    /// This has to be taken before the instance runs again.
+    pub fn core_dump(&self) -> Option<CoreDump> {
+        let trap = self.trapped?;
+        let pc = unsafe { vm_instance_stopped_pc(self.raw) };
+        let mut stack = vec![0; unsafe { vm_instance_stack_size(self.raw) }];
+        unsafe { vm_instance_copy_stack(self.raw, stack.as_mut_ptr()) };
+        let mut tasks = vec![0; 255];
+        let n = unsafe { vm_instance_tasks(self.raw, tasks.as_mut_ptr()) };
+        tasks.truncate(n.into());
+        let mut calls = vec![0; CALL_HISTORY];
+        let n = unsafe { vm_instance_calls(self.raw, calls.as_mut_ptr()) };
+        calls.truncate(n as usize);
+        Some(CoreDump {
+            trap,
+            pc,
+            calls,
+            tasks,
+            stack,
+        })
+    }
+
+    /// Start a run of the entry function, unless it takes a different number of arguments, in which case nothing runs.
+    fn start(&mut self, args: &[i32]) -> Result<i32, Trap> {
+        if args.len() != self.module.entry_params() {
+            return Err(Trap::ArgCount(self.module.entry_params() as u32));
+        }
+        self.swap_reloaded();
+        // a run left at a `yield` is over once another starts
+        self.host_fns.finalize_all();
+        let args = args.iter().flat_map(|arg| arg.to_ne_bytes()).collect::<Vec<_>>();
+        // the VM never writes to the code or data section, so sharing the module's bytes is fine.
+        let code = self.module.code.ptr();
+        Ok(in_vm(self.raw, code, self.safepoints.as_ref(), || unsafe {
+            vm_instance_run(self.raw, code, args.as_ptr(), args.len() as u32)
+        }))
+    }
+
+    fn continue_with(&mut self, val: i32) -> i32 {
+        let code = self.module.code.ptr();
+        in_vm(self.raw, code, self.safepoints.as_ref(), || unsafe { vm_instance_resume(self.raw, code, val) })
+    }
+
+    /// Keep answering host calls until the program halts, yields, or traps.
+    fn drive(&mut self, mut res: i32) -> Result<Outcome, Trap> {
+        loop {
+            match self.step(res)? {
+                Step::Done(outcome) => return Ok(outcome),
+                Step::HostCall(f, arg) => {
+                    let val = match call_host(self.raw, &self.module, &mut self.host_fns, self.safepoints.as_ref(), f, arg) {
+                        Ok(val) => val,
+                        Err(trap) => return Err(self.host_trap(trap)),
+                    };
+                    res = self.continue_with(val);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    async fn drive_async(&mut self, mut res: i32) -> Result<Outcome, Trap> {
+        loop {
+            match self.step(res)? {
+                Step::Done(outcome) => return Ok(outcome),
+                Step::HostCall(f, arg) => {
+                    let minting = unsafe { vm_instance_minting(self.raw) } >= 0;
+                    let val = match self.host_fns.get_mut(f) {
+                        Some(Host::Async(host_fn)) if !minting => {
+                            event!(Level::Trace, "host call {} with {}", f, arg);
+                            host_fn(arg).await
+                        }
+                        _ => match call_host(self.raw, &self.module, &mut self.host_fns, self.safepoints.as_ref(), f, arg) {
+                            Ok(val) => val,
+                            Err(trap) => return Err(self.host_trap(trap)),
+                        },
+                    };
+                    res = self.continue_with(val);
+                }
+            }
+        }
+    }
+
+    /// End a run at a host call that couldn't be answered.
+    fn host_trap(&mut self, trap: Trap) -> Trap {
+        self.suspended = false;
+        // a callback can be interrupted too, and that has to be delivered just like in the program
+        if trap == Trap::Interrupted {
+            self.interrupt.store(false, Ordering::Relaxed);
+        }
+        trap
+    }
+
+    /// Remember a trap for `core_dump`, log how the run stopped, and finalize its resources if it's over.
+    fn finish(&mut self, res: Result<Outcome, Trap>) -> Result<Outcome, Trap> {
+        match res {
+            Ok(outcome) => event!(Level::Debug, "stopped: {:?}", outcome),
+            Err(trap) => event!(Level::Info, "trapped: {:?}", trap),
+        }
+        if !matches!(res, Ok(Outcome::Yielded(_))) {
+            self.host_fns.finalize_all();
+        }
+        self.trapped = res.err();
+        res
+    }
+
+    fn step(&mut self, mut res: i32) -> Result<Step, Trap> {
+        while res == VM_VERIFY || res == VM_FINALIZE {
+            if res == VM_FINALIZE {
+                self.host_fns.finalize_region(unsafe { vm_instance_finalizing(self.raw) });
+            } else {
+                let pc = unsafe { vm_instance_stopped_pc(self.raw) };
+                if let Err(trap) = self.module.verify_lazily(pc) {
+                    // the run stopped at a stub, so there's nothing to resume, like at any other trap
+                    self.suspended = false;
+                    return Err(trap);
+                }
+            }
+            let code = self.module.code.ptr();
+            res = in_vm(self.raw, code, self.safepoints.as_ref(), || unsafe { vm_instance_continue(self.raw, code) });
+        }
+        self.suspended = res == VM_YIELDED || res == VM_HOST_CALL;
+        match res {
+            VM_TRAP_INTERRUPTED => {
+                // the interrupt has been delivered, so the next run starts fresh
+                self.interrupt.store(false, Ordering::Relaxed);
+                Err(Trap::Interrupted)
+            }
+            VM_YIELDED => Ok(Step::Done(Outcome::Yielded(unsafe {
+                vm_instance_yielded(self.raw)
+            }))),
+            VM_TRAP_UNINITIALIZED => Err(Trap::UninitializedRead),
+            VM_TRAP_OUT_OF_BOUNDS => Err(Trap::OutOfBounds),
+            VM_TRAP_USE_AFTER_FREE => Err(Trap::UseAfterFree),
+            VM_TRAP_DOUBLE_FREE => Err(Trap::DoubleFree),
+            VM_TRAP_OVERFLOW => Err(Trap::Overflow),
+            VM_TRAP_DIVIDE_BY_ZERO => Err(Trap::DivideByZero),
+            VM_TRAP_REGION_FULL => Err(Trap::RegionFull),
+            VM_TRAP_STDIN_BUSY => Err(Trap::StdinBusy),
+            VM_HOST_CALL => {
+                let f = unsafe { vm_instance_host_func(self.raw) };
+                let arg = unsafe { vm_instance_yielded(self.raw) };
+                Ok(Step::HostCall(f, arg))
+            }
+            status => Ok(Step::Done(Outcome::Halted(status as u8))),
+        }
+    }
+}
+
+/// A way to stop a running `Instance`, for example to enforce a wall-clock timeout.
+/// The instance stops with `Trap::Interrupted` the next time it reaches a safepoint (a call, or between tasks),
+/// so this costs nothing per instruction.
+#[derive(Clone)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        self.host_fns.finalize_all();
+        unsafe { vm_instance_free(self.raw) }
+    }
+}
+
+/// What a host function registered with `Instance::register_host_fn_with_callbacks` gets, to look at and call into the program.
+/// A view of the stack can't be kept across a call, since the callback could change what it points into.
+pub struct Guest<'a> {
+    raw: *mut RawInstance,
+    module: &'a Module,
+    host_fns: &'a mut HostFns,
+    safepoints: Option<&'a Safepoints>,
+    arg: i32,
+    /// The first trap from a callback, which stops the run once the host function returns.
+    trapped: Option<Trap>,
+}
+
+impl Guest<'_> {
+    /// The program's stack under the host function's argument.
+    pub fn view(&self) -> GuestView<'_> {
+        guest_view(self.raw, self.module, self.arg)
+    }
+
+    /// Call `f` with `args` on a stack of its own, and give back what it yields.
+    /// Its host calls are answered like the program's, except that it can't call the host function that's calling it.
+    /// If it traps, or halts instead of yielding, the run stops with that trap once the host function returns.
+    /// If `args` aren't as many as `f` takes, nothing runs, and this gives `Trap::ArgCount` without stopping the run.
+    /// The same goes for an `f` from another module, or from before a `reload`, with `Trap::ForeignGuestFn`.
+    pub fn call(&mut self, f: GuestFn, args: &[i32]) -> Result<i32, Trap> {
+        if f.module != self.module.id {
+            return Err(Trap::ForeignGuestFn);
+        }
+        if args.len() != f.params {
+            return Err(Trap::ArgCount(f.params as u32));
+        }
+        let _span = log::span(Level::Debug, module_path!(), "callback", || format!("{} {:?}", f.pc, args));
+        let code = self.module.code.ptr();
+        let args = args.iter().flat_map(|arg| arg.to_ne_bytes()).collect::<Vec<_>>();
+        let mut res = in_vm(self.raw, code, self.safepoints, || unsafe {
+            vm_instance_call(self.raw, code, f.pc, args.as_ptr(), args.len() as u32)
+        });
+        // go back to the host call even if a host function in the callback panics
+        struct Return(*mut RawInstance);
+        impl Drop for Return {
+            fn drop(&mut self) {
+                unsafe { vm_instance_return(self.0) }
+            }
+        }
+        let _return = Return(self.raw);
+        let out = loop {
+            match res {
+                VM_YIELDED => break Ok(unsafe { vm_instance_yielded(self.raw) }),
+                VM_HOST_CALL => {
+                    let f = unsafe { vm_instance_host_func(self.raw) };
+                    let arg = unsafe { vm_instance_yielded(self.raw) };
+                    match call_host(self.raw, self.module, self.host_fns, self.safepoints, f, arg) {
+                        Ok(val) => res = in_vm(self.raw, code, self.safepoints, || unsafe { vm_instance_resume(self.raw, code, val) }),
+                        Err(trap) => break Err(trap),
                     }
-                    Op2::Data(data_pos) => {
-                        let data_sec_pos = data_sec_positions.get(&prog_id).unwrap();
-                        code.extend(op_to_bytes(&Op2::Data(*data_sec_pos as usize + *data_pos)));
+                }
+                VM_VERIFY => {
+                    let pc = unsafe { vm_instance_stopped_pc(self.raw) };
+                    match self.module.verify_lazily(pc) {
+                        Ok(()) => res = in_vm(self.raw, code, self.safepoints, || unsafe { vm_instance_continue(self.raw, code) }),
+                        Err(trap) => break Err(trap),
                     }
-                    _ => code.extend(op_to_bytes(op)),
                 }
-                pos += op_len(op) as u32;
-            }
-        }
-        prog_id += 1;
-    }
-    let _ = fs::write("t.txt", str);
-    unsafe { vm_function(code.as_mut_ptr()) }
-}
-
-fn op_to_bytes(op: &Op2) -> Vec<u8> {
-    match op {
-        Op2::Get(offset, size) => [
-            vec![0],
-            offset.to_le_bytes().to_vec(),
-            size.to_le_bytes().to_vec(),
-        ]
-        .concat(),
-        Op2::Init(offset, size, tpl_size) => [
-            vec![1],
-            offset.to_le_bytes().to_vec(),
-            size.to_le_bytes().to_vec(),
-            tpl_size.to_le_bytes().to_vec(),
-        ]
-        .concat(),
-        Op2::InitIP(offset, size) => [
-            vec![2],
-            offset.to_le_bytes().to_vec(),
-            size.to_le_bytes().to_vec(),
-        ]
-        .concat(),
-        Op2::Malloc(size) => [vec![3], size.to_le_bytes().to_vec()].concat(),
-        Op2::Alloca(size) => [vec![4], size.to_le_bytes().to_vec()].concat(),
-        Op2::Proj(offset, size, tpl_size) => [
-            vec![5],
-            offset.to_le_bytes().to_vec(),
-            size.to_le_bytes().to_vec(),
-            tpl_size.to_le_bytes().to_vec(),
-        ]
-        .concat(),
-        Op2::ProjIP(offset, size) => [
-            vec![6],
-            offset.to_le_bytes().to_vec(),
-            size.to_le_bytes().to_vec(),
-        ]
-        .concat(),
-        Op2::Call => vec![7],
-        // Op2::Print => vec![8],
-        Op2::Lit(lit) => [vec![9], lit.to_le_bytes().to_vec()].concat(),
-        Op2::GlobalFunc(label) => [vec![10], label.to_le_bytes().to_vec()].concat(),
-        Op2::Halt => vec![11],
-        Op2::NewRgn(size) => [vec![12], size.to_le_bytes().to_vec()].concat(),
-        Op2::FreeRgn => vec![13],
-        Op2::Deref(size) => [vec![14], size.to_le_bytes().to_vec()].concat(),
-        Op2::NewArr(size) => [vec![15], size.to_le_bytes().to_vec()].concat(),
-        Op2::ArrMut(size) => [vec![16], size.to_le_bytes().to_vec()].concat(),
-        Op2::ArrProj(size) => [vec![17], size.to_le_bytes().to_vec()].concat(),
-        Op2::AddI32 => vec![18],
-        Op2::MulI32 => vec![19],
-        Op2::DivI32 => vec![20],
-        Op2::CallNZ => vec![21],
-        Op2::Data(size) => [vec![22], size.to_le_bytes().to_vec()].concat(),
-        Op2::DataIndex(size) => [vec![23], size.to_le_bytes().to_vec()].concat(),
-        Op2::CopyN(size) => [vec![24], size.to_le_bytes().to_vec()].concat(),
-        Op2::U8Lit(n) => vec![25, *n],
-        Op2::AddU8 => vec![26],
-        Op2::MulU8 => vec![27],
-        Op2::DivU8 => vec![28],
-        Op2::U8ToI32 => vec![29],
-        Op2::ModuloI32 => vec![30],
-        Op2::ModuloU8 => vec![31],
-        Op2::I32ToU8 => vec![32],
-        Op2::Read(c) => vec![33, *c],
-        Op2::Write(c) => vec![34, *c],
-    }
-}
-
-
-fn op_len(op: &Op2) -> usize {
-    match op {
-        Op2::Get(_, _) => 1 + 8 + 8,
-        Op2::Init(_, _, _) => 1 + 8 + 8 + 8,
-        Op2::InitIP(_, _) => 1 + 8 + 8,
-        Op2::Malloc(_) => 1 + 8,
-        Op2::Alloca(_) => 1 + 8,
-        Op2::Proj(_, _, _) => 1 + 8 + 8 + 8,
-        Op2::ProjIP(_, _) => 1 + 8 + 8,
-        Op2::Call => 1,
-        // Op2::Print => 1,
-        Op2::Lit(_) => 1 + 4,
-        Op2::GlobalFunc(_) => 1 + 4,
-        Op2::Halt => 1,
-        Op2::NewRgn(_) => 1 + 8,
-        Op2::FreeRgn => 1,
-        Op2::Deref(_) => 1 + 8,
-        Op2::NewArr(_) => 1 + 8,
-        Op2::ArrMut(_) => 1 + 8,
-        Op2::ArrProj(_) => 1 + 8,
-        Op2::AddI32 => 1,
-        Op2::MulI32 => 1,
-        Op2::DivI32 => 1,
-        Op2::CallNZ => 1,
-        Op2::Data(_) => 1 + 8,
-        Op2::DataIndex(_) => 1 + 8,
-        Op2::CopyN(_) => 1 + 8,
-        Op2::U8Lit(_) => 1 + 1,
-        Op2::AddU8 => 1,
-        Op2::MulU8 => 1,
-        Op2::DivU8 => 1,
-        Op2::U8ToI32 => 1,
-        Op2::ModuloI32 => 1,
-        Op2::ModuloU8 => 1,
-        Op2::I32ToU8 => 1,
-        Op2::Read(_) => 1 + 1,
-        Op2::Write(_) => 1 + 1,
-    }
-}
-
-fn program_size(prog: &IRProgram) -> usize {
-    let mut out = prog.data_section.len();
-    for Stmt2::Func(_, _, ops) in &prog.funcs {
-        out += ops.iter().map(op_len).sum::<usize>();
-    }
-    out
-}
\ No newline at end of file
+                VM_FINALIZE => {
+                    self.host_fns.finalize_region(unsafe { vm_instance_finalizing(self.raw) });
+                    res = in_vm(self.raw, code, self.safepoints, || unsafe { vm_instance_continue(self.raw, code) });
+                }
+                VM_TRAP_INTERRUPTED => break Err(Trap::Interrupted),
+                VM_TRAP_UNINITIALIZED => break Err(Trap::UninitializedRead),
+                VM_TRAP_OUT_OF_BOUNDS => break Err(Trap::OutOfBounds),
+                VM_TRAP_USE_AFTER_FREE => break Err(Trap::UseAfterFree),
+                VM_TRAP_DOUBLE_FREE => break Err(Trap::DoubleFree),
+                VM_TRAP_OVERFLOW => break Err(Trap::Overflow),
+                VM_TRAP_DIVIDE_BY_ZERO => break Err(Trap::DivideByZero),
+                VM_TRAP_REGION_FULL => break Err(Trap::RegionFull),
+                VM_TRAP_STDIN_BUSY => break Err(Trap::StdinBusy),
+                status => break Err(Trap::CallbackHalted(status as u8)),
+            }
+        };
+        if let Err(trap) = out {
+            self.trapped.get_or_insert(trap);
+        }
+        out
+    }
+}
+
+/// Answer host call `f` for an instance stopped at one.
+fn call_host(
+    raw: *mut RawInstance,
+    module: &Module,
+    host_fns: &mut HostFns,
+    safepoints: Option<&Safepoints>,
+    f: u32,
+    arg: i32,
+) -> Result<i32, Trap> {
+    event!(Level::Trace, "host call {} with {}", f, arg);
+    let busy = host_fns.is_busy(f);
+    // a `host_res` has to be answered by a function that makes its kind of resource, and only a `host_res` can be
+    let minting = u32::try_from(unsafe { vm_instance_minting(raw) }).ok();
+    match host_fns.get_mut(f) {
+        Some(Host::Minting(kind, host_fn)) if minting == Some(*kind) => {
+            let kind = *kind;
+            let id = host_fn(&guest_view(raw, module, arg)).ok_or(Trap::HostSignature(f))?;
+            host_fns.opened(kind, id, unsafe { vm_instance_minting_region(raw) });
+            return Ok(id);
+        }
+        Some(Host::Minting(_, _)) => return Err(Trap::HostSignature(f)),
+        Some(_) if minting.is_some() => return Err(Trap::HostSignature(f)),
+        Some(Host::Sync(host_fn)) => return Ok(host_fn(arg)),
+        Some(Host::Viewing(host_fn)) => return Ok(host_fn(&guest_view(raw, module, arg), arg)),
+        Some(Host::Native(host_fn)) => return host_fn(&guest_view(raw, module, arg)).ok_or(Trap::HostSignature(f)),
+        Some(Host::Calling(_)) => {}
+        #[cfg(feature = "async")]
+        Some(Host::Async(_)) => return Err(Trap::AsyncHostFunction(f)),
+        None if busy => return Err(Trap::ReentrantHostCall(f)),
+        None => return Err(Trap::UnknownHostFunction(f)),
+    }
+    let Some(Host::Calling(mut host_fn)) = host_fns.take(f) else {
+        unreachable!()
+    };
+    let mut guest = Guest {
+        raw,
+        module,
+        host_fns,
+        safepoints,
+        arg,
+        trapped: None,
+    };
+    let val = host_fn(&mut guest, arg);
+    let trapped = guest.trapped;
+    host_fns.put_back(f, Host::Calling(host_fn));
+    trapped.map_or(Ok(val), Err)
+}
+
+/// Check that every `host_call index` in `module` has the arguments `binding` takes on the stack.
+fn check_binding(module: &Module, index: u32, binding: &HostBinding) -> Result<(), Error> {
+    let mut sites = module.host_sites().filter(|(_, site)| site.host_fn == index).collect::<Vec<_>>();
+    sites.sort_by_key(|(pc, _)| *pc);
+    for (pc, site) in sites {
+        if !(binding.accepts)(&site.stack) {
+            let function = module.locate(pc).map_or(0, |loc| loc.function);
+            return Err(Error::HostSignatureMismatch(index, function, (binding.signature)(), site.stack.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Run bytecode with `f`, one of the calls into the C VM, counting the instance as running in its group of safepoints if it has one.
+/// When a pause stops it at a safepoint, it waits there for the pause to end and then goes on.
+fn in_vm(raw: *mut RawInstance, code: *mut u8, safepoints: Option<&Safepoints>, f: impl FnOnce() -> i32) -> i32 {
+    let Some(safepoints) = safepoints else {
+        return f();
+    };
+    safepoints.enter();
+    let mut res = f();
+    while res == VM_SAFEPOINT {
+        safepoints.leave();
+        safepoints.enter();
+        res = unsafe { vm_instance_continue(raw, code) };
+    }
+    safepoints.leave();
+    res
+}
+
+/// The view a host function gets of an instance stopped at a host call.
+fn guest_view(raw: *mut RawInstance, module: &Module, arg: i32) -> GuestView<'_> {
+    let pc = unsafe { vm_instance_stopped_pc(raw) };
+    let mut stack = vec![0; unsafe { vm_instance_stack_size(raw) }];
+    unsafe { vm_instance_copy_stack(raw, stack.as_mut_ptr()) };
+    let mut len = [0; 4];
+    len.copy_from_slice(module.code.fixed(0..4));
+    let data_section_len = u32::from_ne_bytes(len) as usize;
+    GuestView::new(stack, arg, module.host_site(pc), module.code.fixed(4..4 + data_section_len), module.id)
+}
+
+/// How many bytes `op` takes up in linked code, which doesn't depend on where anything was linked.
+pub(crate) fn op_len(op: &Op2) -> usize {
+    Instr::link(op, &mut |_| Some(0), 0).map_or(0, |instr| instr.size())
+}
+
+/// The error for op `i` of a function not linking, because it names a function its program has no body for and doesn't import,
+/// which the verifier rules out, so only an `IRProgram` made some other way can have one.
+fn unlinked(i: usize, op: &Op2) -> Error {
+    let label = match op {
+        Op2::GlobalFunc(label) => *label,
+        _ => 0,
+    };
+    Error::UnknownGlobalFunc(i as Pos, Op1::GlobalFunc(label), label)
+}
+
+/// An import or export name as text, without the zeros that pad it to 16 bytes.
+fn import_name((a, b): (u64, u64)) -> String {
+    let bytes = [a.to_le_bytes(), b.to_le_bytes()].concat();
+    let len = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}